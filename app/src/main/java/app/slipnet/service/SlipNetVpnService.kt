@@ -31,6 +31,7 @@ import app.slipnet.tunnel.HevSocks5Tunnel
 import app.slipnet.tunnel.HttpProxyServer
 import app.slipnet.tunnel.NaiveBridge
 import app.slipnet.tunnel.SlipstreamBridge
+import app.slipnet.tunnel.SlipstreamException
 import app.slipnet.tunnel.SlipstreamSocksBridge
 import app.slipnet.tunnel.SnowflakeBridge
 import app.slipnet.tunnel.SshTunnelBridge
@@ -91,6 +92,8 @@ class SlipNetVpnService : VpnService() {
     private val serviceScope = CoroutineScope(SupervisorJob() + Dispatchers.Main)
     private var vpnInterface: ParcelFileDescriptor? = null
     private var healthCheckJob: Job? = null
+    /** Error the Slipstream client last exited with, taken by [waitForQuicReady]. */
+    private var slipstreamExitError: SlipstreamException? = null
     private var currentProfileId: Long = -1
     private var currentTunnelType: TunnelType = TunnelType.SLIPSTREAM
     private var connectivityManager: ConnectivityManager? = null
@@ -581,7 +584,11 @@ class SlipNetVpnService : VpnService() {
         val quicReady = waitForQuicReady(maxAttempts = 150, delayMs = 200)
         if (!quicReady) {
             Log.e(TAG, "QUIC connection not ready — cannot establish SSH through Slipstream")
-            connectionManager.onVpnError("Slipstream tunnel failed to connect (QUIC timeout)")
+            val exitError = slipstreamExitError
+            connectionManager.onVpnError(
+                if (exitError != null) "Slipstream client failed: ${exitError.message}"
+                else "Slipstream tunnel failed to connect (QUIC timeout)"
+            )
             SlipstreamBridge.stopClient()
             SlipstreamBridge.setVpnService(null)
             stopForeground(STOP_FOREGROUND_REMOVE)
@@ -1743,6 +1750,7 @@ class SlipNetVpnService : VpnService() {
      */
     private suspend fun waitForQuicReady(maxAttempts: Int, delayMs: Long): Boolean {
         Log.d(TAG, "Waiting for QUIC connection to be ready (max ${maxAttempts * delayMs}ms)")
+        slipstreamExitError = null
 
         return kotlinx.coroutines.withContext(Dispatchers.IO) {
             repeat(maxAttempts) { attempt ->
@@ -1756,6 +1764,7 @@ class SlipNetVpnService : VpnService() {
 
                 if (!nativeRunning) {
                     Log.e(TAG, "Native client stopped while waiting for QUIC (attempt ${attempt + 1})")
+                    slipstreamExitError = takeSlipstreamError()
                     return@withContext false
                 }
 
@@ -1786,6 +1795,19 @@ class SlipNetVpnService : VpnService() {
         }
     }
 
+    /**
+     * Take the error the Slipstream client exited with, logging it, or null if it exited cleanly.
+     */
+    private fun takeSlipstreamError(): SlipstreamException? {
+        return try {
+            SlipstreamBridge.checkClientError()
+            null
+        } catch (e: SlipstreamException) {
+            Log.e(TAG, "Slipstream client failed (${e.javaClass.simpleName}): ${e.message}")
+            e
+        }
+    }
+
     /**
      * Wait for the SOCKS5 proxy to be ready by checking if the port is listening.
      */
@@ -1918,6 +1940,9 @@ class SlipNetVpnService : VpnService() {
 
                 if (!proxyHealthy || !tunnelRunning) {
                     Log.e(TAG, "Health check failed: proxy=$proxyHealthy (type=$currentTunnelType), tunnel=$tunnelRunning")
+                    if (currentTunnelType == TunnelType.SLIPSTREAM || currentTunnelType == TunnelType.SLIPSTREAM_SSH) {
+                        takeSlipstreamError()
+                    }
                     launch(Dispatchers.Main) {
                         handleTunnelFailure("health check failed")
                     }
@@ -2012,7 +2037,7 @@ class SlipNetVpnService : VpnService() {
                     debouncedReconnect("network restored")
                 } else if (currentNetwork != network) {
                     Log.i(TAG, "Network changed from $currentNetwork to $network, triggering reconnection")
-                    debouncedReconnect("network change")
+                    reconnectForNetworkChange("network change")
                 }
                 currentNetwork = network
                 // Update tracked addresses for new network
@@ -2046,7 +2071,7 @@ class SlipNetVpnService : VpnService() {
                     val added = newAddresses - lastNetworkAddresses
                     val removed = lastNetworkAddresses - newAddresses
                     Log.i(TAG, "IP addresses changed: added=$added, removed=$removed")
-                    debouncedReconnect("IP address change")
+                    reconnectForNetworkChange("IP address change")
                 }
                 lastNetworkAddresses = newAddresses
             }
@@ -2084,6 +2109,20 @@ class SlipNetVpnService : VpnService() {
         networkCallback = null
     }
 
+    /**
+     * A Slipstream client migrates its QUIC connection to the new network itself (and reconnects
+     * on its own if that fails), so it is only told about the change. Other tunnels restart.
+     */
+    private fun reconnectForNetworkChange(reason: String) {
+        val slipstream = currentTunnelType == TunnelType.SLIPSTREAM ||
+            currentTunnelType == TunnelType.SLIPSTREAM_SSH
+        if (slipstream && SlipstreamBridge.notifyNetworkChanged()) {
+            Log.i(TAG, "Slipstream client migrating after $reason")
+            return
+        }
+        debouncedReconnect(reason)
+    }
+
     /**
      * Debounced reconnection to avoid thrashing on rapid network changes.
      * Waits 500ms before triggering reconnection in case more changes come in.
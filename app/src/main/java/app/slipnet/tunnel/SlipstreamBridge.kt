@@ -1,5 +1,8 @@
 package app.slipnet.tunnel
 
+import android.content.Context
+import android.net.ConnectivityManager
+import android.net.NetworkCapabilities
 import android.net.VpnService
 import app.slipnet.util.AppLog as Log
 import java.lang.ref.WeakReference
@@ -66,6 +69,27 @@ object SlipstreamBridge {
         return result
     }
 
+    /**
+     * Called from JNI when no resolvers were configured.
+     * Returns the DNS servers of the active underlying (non-VPN) network.
+     */
+    @JvmStatic
+    fun systemDnsServers(): Array<String> {
+        val service = vpnServiceRef?.get() ?: return emptyArray()
+        val cm = service.getSystemService(Context.CONNECTIVITY_SERVICE) as? ConnectivityManager
+            ?: return emptyArray()
+        // With the tunnel up the active network is our own VPN; use the network beneath it.
+        val network = cm.allNetworks.firstOrNull { network ->
+            val caps = cm.getNetworkCapabilities(network) ?: return@firstOrNull false
+            caps.hasCapability(NetworkCapabilities.NET_CAPABILITY_INTERNET) &&
+                !caps.hasTransport(NetworkCapabilities.TRANSPORT_VPN)
+        } ?: return emptyArray()
+        val servers = cm.getLinkProperties(network)?.dnsServers.orEmpty()
+            .mapNotNull { it.hostAddress }
+        Log.d(TAG, "System DNS servers: $servers")
+        return servers.toTypedArray()
+    }
+
     /**
      * Start the slipstream client (DNS tunnel).
      * The client will listen on the specified host:port for SOCKS5 connections.
@@ -219,6 +243,15 @@ object SlipstreamBridge {
         }
     }
 
+    /**
+     * Throw the error the native client last exited with, if any, and clear it.
+     */
+    @Throws(SlipstreamException::class)
+    fun checkClientError() {
+        if (!isLibraryLoaded) return
+        nativeThrowLastError()
+    }
+
     /**
      * Check if the client is running AND the port is actually listening.
      * Use this for health checks after connection is established.
@@ -286,6 +319,10 @@ object SlipstreamBridge {
     private external fun nativeStopSlipstreamClient()
     private external fun nativeIsClientRunning(): Boolean
     private external fun nativeIsQuicReady(): Boolean
+    private external fun nativeGetMetricsJson(): String?
+    private external fun nativeGetConnectionStatsJson(): String?
+    private external fun nativeThrowLastError()
+    private external fun nativeNotifyNetworkChanged()
 
     /**
      * Check if the native client reports it's running (alias for isClientRunning).
@@ -306,4 +343,48 @@ object SlipstreamBridge {
             false
         }
     }
+
+    /**
+     * Latest connection metrics as a JSON object (RTT, pacing rate, cwin, bytes in transit,
+     * active streams, bytes tunneled, uptime), or null before the client has sampled any.
+     */
+    fun getMetricsJson(): String? {
+        if (!isLibraryLoaded) return null
+        return try {
+            nativeGetMetricsJson()
+        } catch (e: Exception) {
+            Log.e(TAG, "Error reading native metrics", e)
+            null
+        }
+    }
+
+    /**
+     * Live QUIC path quality of the active connection as a JSON object (RTT, cwin, pacing and
+     * receive rates, packets sent and lost, loss rate), or null while disconnected.
+     */
+    fun getConnectionStatsJson(): String? {
+        if (!isLibraryLoaded) return null
+        return try {
+            nativeGetConnectionStatsJson()
+        } catch (e: Exception) {
+            Log.e(TAG, "Error reading native connection stats", e)
+            null
+        }
+    }
+
+    /**
+     * Tell the native client the default network changed (for example Wi-Fi to cellular) so it
+     * migrates the QUIC connection to the new address instead of waiting for its next check.
+     * Returns false when there is no ready connection to migrate; the caller should reconnect.
+     */
+    fun notifyNetworkChanged(): Boolean {
+        if (!isQuicReady()) return false
+        return try {
+            nativeNotifyNetworkChanged()
+            true
+        } catch (e: Exception) {
+            Log.e(TAG, "Error notifying network change", e)
+            false
+        }
+    }
 }
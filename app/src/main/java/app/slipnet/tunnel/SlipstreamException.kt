@@ -0,0 +1,31 @@
+package app.slipnet.tunnel
+
+/**
+ * Error the native slipstream client exited with, one subclass per Rust `ClientError` variant.
+ * Thrown from JNI by [SlipstreamBridge.checkClientError].
+ */
+sealed class SlipstreamException(message: String) : Exception(message) {
+    /** Invalid configuration: addresses, domain, certificates, or other settings. */
+    class Config(message: String) : SlipstreamException(message)
+
+    /** A DNS message could not be built or parsed. */
+    class Dns(message: String) : SlipstreamException(message)
+
+    /** picoquic failed to create or drive the QUIC connection. */
+    class Quic(message: String) : SlipstreamException(message)
+
+    /** A socket or file operation failed. */
+    class Io(message: String) : SlipstreamException(message)
+
+    /** picoquic handed over a socket address of an unsupported family. */
+    class Address(message: String) : SlipstreamException(message)
+
+    /** A TLS or crypto library error. */
+    class Tls(message: String) : SlipstreamException(message)
+
+    /** The TLS handshake failed, for example because the server certificate is not pinned. */
+    class CryptoErrors(message: String) : SlipstreamException(message)
+
+    /** The client stopped because a shutdown was requested. */
+    class Shutdown(message: String) : SlipstreamException(message)
+}
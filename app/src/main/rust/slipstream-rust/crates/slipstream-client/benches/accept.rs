@@ -0,0 +1,75 @@
+//! Criterion benchmark for accepting bursts of local connections with `--listener-workers`.
+//!
+//! Run with `cargo bench -p slipstream-client --bench accept` and compare against
+//! `benches/baseline.txt`. Each iteration opens 100 connections at once and waits until the
+//! workers have accepted all of them, on a current-thread runtime like the client's.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use slipstream::runtime::bind_tcp_listeners;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+const CONNECTIONS: usize = 100;
+const WORKERS: [usize; 3] = [1, 2, 4];
+
+/// Binds `workers` listeners on a loopback port and accepts on each until the runtime goes.
+/// Every accept adds a permit to the returned semaphore.
+fn spawn_workers(runtime: &Runtime, workers: usize) -> (SocketAddr, Arc<Semaphore>) {
+    let listeners = runtime
+        .block_on(bind_tcp_listeners("127.0.0.1", 0, workers))
+        .expect("bind listeners");
+    let addr = listeners[0].local_addr().expect("listener address");
+    let accepted = Arc::new(Semaphore::new(0));
+    for listener in listeners {
+        let accepted = Arc::clone(&accepted);
+        runtime.spawn(async move {
+            while listener.accept().await.is_ok() {
+                accepted.add_permits(1);
+            }
+        });
+    }
+    (addr, accepted)
+}
+
+async fn connect_burst(addr: SocketAddr, accepted: &Semaphore) {
+    let mut connects = JoinSet::new();
+    for _ in 0..CONNECTIONS {
+        connects.spawn(TcpStream::connect(addr));
+    }
+    let mut streams = Vec::with_capacity(CONNECTIONS);
+    while let Some(stream) = connects.join_next().await {
+        let stream = stream.expect("connect task").expect("connect");
+        // Reset rather than close, so the bursts leave no TIME_WAIT entries behind.
+        let _ = socket2::SockRef::from(&stream).set_linger(Some(Duration::ZERO));
+        streams.push(stream);
+    }
+    accepted
+        .acquire_many(CONNECTIONS as u32)
+        .await
+        .expect("workers running")
+        .forget();
+}
+
+fn bench_accept_burst(c: &mut Criterion) {
+    let runtime = Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("runtime");
+    let mut group = c.benchmark_group("accept_burst");
+    group.throughput(Throughput::Elements(CONNECTIONS as u64));
+    for workers in WORKERS {
+        let (addr, accepted) = spawn_workers(&runtime, workers);
+        group.bench_with_input(BenchmarkId::from_parameter(workers), &workers, |b, _| {
+            b.iter(|| runtime.block_on(connect_burst(addr, &accepted)));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_accept_burst);
+criterion_main!(benches);
@@ -6,13 +6,15 @@
 //! - Socket protection via VpnService.protect()
 
 use crate::error::ClientError;
+use crate::metrics::MetricsSnapshot;
 use crate::runtime::run_client;
 use jni::objects::{JBooleanArray, JClass, JIntArray, JObject, JObjectArray, JString, JValue};
-use jni::sys::{jboolean, jbooleanArray, jint, jintArray, JNI_FALSE, JNI_TRUE};
+use jni::sys::{jboolean, jbooleanArray, jint, jintArray, jstring, JNI_FALSE, JNI_TRUE};
 use jni::JNIEnv;
 use once_cell::sync::OnceCell;
-use slipstream_core::HostPort;
-use slipstream_ffi::{ClientConfig, ResolverMode, ResolverSpec};
+use slipstream_core::{AddressPolicy, HostPort};
+use slipstream_ffi::picoquic::PathQuality;
+use slipstream_ffi::{ClientConfig, ListenerMode, ResolverMode, ResolverSpec, ResolverStrategy};
 use std::os::unix::io::RawFd;
 use std::panic;
 use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
@@ -46,6 +48,17 @@ static CONSECUTIVE_FAILURES: AtomicI32 = AtomicI32::new(0);
 /// Maximum consecutive failures before giving up.
 const MAX_CONSECUTIVE_FAILURES: i32 = 5;
 
+/// Latest metrics snapshot, shared between the client thread and JNI callers.
+/// Stands in for the `--metrics-socket` Unix socket used on other platforms.
+static METRICS: Mutex<Option<MetricsSnapshot>> = Mutex::new(None);
+
+/// Latest path quality of the active connection; `None` while disconnected.
+static CONNECTION_STATS: Mutex<Option<PathQuality>> = Mutex::new(None);
+
+/// Java exception class and message of the error the client thread last exited with,
+/// thrown by `nativeThrowLastError`.
+static LAST_ERROR: Mutex<Option<(&'static str, String)>> = Mutex::new(None);
+
 /// Handle to the client thread.
 static CLIENT_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
 
@@ -95,6 +108,20 @@ pub fn exceeded_max_failures() -> bool {
     CONSECUTIVE_FAILURES.load(Ordering::SeqCst) >= MAX_CONSECUTIVE_FAILURES
 }
 
+/// Store the latest metrics snapshot for `nativeGetMetricsJson`.
+pub(crate) fn store_metrics(snapshot: MetricsSnapshot) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        *metrics = Some(snapshot);
+    }
+}
+
+/// Store the latest path quality for `nativeGetConnectionStatsJson`.
+pub(crate) fn store_connection_stats(stats: Option<PathQuality>) {
+    if let Ok(mut current) = CONNECTION_STATS.lock() {
+        *current = stats;
+    }
+}
+
 /// Protect a socket file descriptor via VpnService.protect().
 /// This MUST be called for the UDP socket used for DNS queries BEFORE sending any data.
 /// Returns true if protection succeeded, false otherwise.
@@ -152,6 +179,50 @@ pub fn protect_socket(fd: RawFd) -> bool {
     }
 }
 
+/// Fetch the DNS servers of the active underlying network via ConnectivityManager.
+/// Returns an empty list if the JVM, bridge class or VpnService is unavailable.
+pub(crate) fn system_dns_servers() -> Vec<String> {
+    let (Some(jvm), Some(class_ref)) = (JAVA_VM.get(), BRIDGE_CLASS.get()) else {
+        warn!("JNI not initialized, cannot query system DNS servers");
+        return Vec::new();
+    };
+
+    let mut env = match jvm.attach_current_thread() {
+        Ok(env) => env,
+        Err(e) => {
+            error!("Failed to attach to JVM: {:?}", e);
+            return Vec::new();
+        }
+    };
+
+    // Call SlipstreamBridge.systemDnsServers() using cached class reference
+    // Safety: GlobalRef holds a valid JNI reference, converting to JClass is safe
+    let class = unsafe { JClass::from_raw(class_ref.as_raw()) };
+    let array = match env
+        .call_static_method(class, "systemDnsServers", "()[Ljava/lang/String;", &[])
+        .and_then(|val| val.l())
+    {
+        Ok(obj) => JObjectArray::from(obj),
+        Err(e) => {
+            error!("Failed to call systemDnsServers: {:?}", e);
+            let _ = env.exception_clear();
+            return Vec::new();
+        }
+    };
+
+    let len = env.get_array_length(&array).unwrap_or(0);
+    let mut servers = Vec::with_capacity(len as usize);
+    for i in 0..len {
+        let Ok(obj) = env.get_object_array_element(&array, i) else {
+            continue;
+        };
+        if let Ok(server) = env.get_string(&JString::from(obj)) {
+            servers.push(String::from(server));
+        }
+    }
+    servers
+}
+
 // ============================================================================
 // JNI Functions
 // ============================================================================
@@ -331,6 +402,9 @@ fn start_client_impl<'local>(
     IS_QUIC_READY.store(false, Ordering::SeqCst);
     IS_THREAD_DONE.store(false, Ordering::SeqCst);
     CONSECUTIVE_FAILURES.store(0, Ordering::SeqCst);
+    if let Ok(mut last_error) = LAST_ERROR.lock() {
+        *last_error = None;
+    }
 
     // Extract domain
     let domain_str: String = match env.get_string(&domain) {
@@ -375,8 +449,7 @@ fn start_client_impl<'local>(
     };
 
     if resolver_count == 0 {
-        error!("No resolvers provided");
-        return -2;
+        info!("No resolvers provided; using the system DNS servers");
     }
 
     // Wrap raw arrays in safe JNI types
@@ -432,8 +505,10 @@ fn start_client_impl<'local>(
                 host,
                 port,
                 family: slipstream_core::AddressFamily::V4,
+                zone: None,
             },
             mode,
+            doh_path: None,
         });
     }
 
@@ -523,12 +598,49 @@ fn run_client_thread(
         let config = ClientConfig {
             tcp_listen_host: &listen_host,
             tcp_listen_port: listen_port,
+            listener_mode: ListenerMode::Passthrough,
+            listener_workers: 1,
+            datagram_mode: false,
+            stream_priority_mode: None,
             resolvers: &resolvers,
+            address_policy: AddressPolicy::Any,
+            resolver_strategy: ResolverStrategy::Ordered,
+            resolver_health_window_ms: 2000,
             domain: &domain,
+            qtype: slipstream_dns::RR_TXT,
+            encoding: slipstream_dns::Encoding::Base32,
+            probe_qname_capacity: false,
+            max_qname_len: slipstream_dns::MAX_DNS_NAME_LEN as u8,
+            ignore_cached_responses: false,
+            edns: slipstream_dns::EdnsOptions::default(),
+            auth_secret: None,
+            auth_mac_len: slipstream_dns::hmac::DEFAULT_MAC_LEN,
             cert: None, // TODO: Support certificate pinning from Android
+            cert_mode: slipstream_ffi::CertMode::LeafPin,
+            resolver_cert: None,
+            sni: None,
+            tls_min_version: slipstream_ffi::TlsVersion::Tls13,
+            tls_ciphers: &[],
             congestion_control: congestion_control.as_deref(),
             gso,
             keep_alive_interval,
+            adaptive_keepalive: false,
+            min_keep_alive_ms: keep_alive_interval as u64,
+            max_keep_alive_ms: 30_000,
+            metrics_socket: None,
+            health_listen: None,
+            migration_timeout_ms: 3000,
+            enable_migration: false,
+            max_query_rate: None,
+            min_query_rate: 1,
+            max_pacing_rate: None,
+            pipeline_window: 4,
+            reconnect_backoff_ms: 250,
+            reconnect_max_ms: 5000,
+            max_reconnect_attempts: 0,
+            idle_timeout_seconds: 0,
+            session_ticket_path: None,
+            on_state_change: None,
             debug_poll,
             debug_streams,
             idle_poll_interval_ms,
@@ -552,8 +664,14 @@ fn run_client_thread(
             Ok(code) => {
                 info!("Client exited with code: {}", code);
             }
+            Err(ClientError::Shutdown) => {
+                info!("Client stopped on request");
+            }
             Err(e) => {
                 error!("Client error: {:?}", e);
+                if let Ok(mut last_error) = LAST_ERROR.lock() {
+                    *last_error = Some((exception_class(&e), e.to_string()));
+                }
             }
         }
     }));
@@ -649,6 +767,111 @@ pub extern "system" fn Java_app_slipnet_tunnel_SlipstreamBridge_nativeIsQuicRead
     }
 }
 
+/// Tell the client the default network changed so it migrates the QUIC path right away.
+#[no_mangle]
+pub extern "system" fn Java_app_slipnet_tunnel_SlipstreamBridge_nativeNotifyNetworkChanged(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    crate::network_monitor::notify_network_changed();
+}
+
+/// Java exception class thrown for each `ClientError` variant.
+fn exception_class(err: &ClientError) -> &'static str {
+    match err {
+        ClientError::Config(_) => "app/slipnet/tunnel/SlipstreamException$Config",
+        ClientError::Dns(_) => "app/slipnet/tunnel/SlipstreamException$Dns",
+        ClientError::Quic { .. } => "app/slipnet/tunnel/SlipstreamException$Quic",
+        ClientError::Io(_) => "app/slipnet/tunnel/SlipstreamException$Io",
+        ClientError::Address(_) => "app/slipnet/tunnel/SlipstreamException$Address",
+        ClientError::Tls { .. } => "app/slipnet/tunnel/SlipstreamException$Tls",
+        ClientError::CryptoErrors(_) => "app/slipnet/tunnel/SlipstreamException$CryptoErrors",
+        ClientError::Shutdown => "app/slipnet/tunnel/SlipstreamException$Shutdown",
+    }
+}
+
+/// Throw the error the client thread last exited with, if any, and clear it.
+#[no_mangle]
+pub extern "system" fn Java_app_slipnet_tunnel_SlipstreamBridge_nativeThrowLastError(
+    mut env: JNIEnv,
+    _class: JClass,
+) {
+    let last_error = LAST_ERROR.lock().ok().and_then(|mut last_error| last_error.take());
+    let Some((class, message)) = last_error else {
+        return;
+    };
+    if let Err(err) = env.throw_new(class, &message) {
+        warn!("Failed to throw {}: {}", class, err);
+    }
+}
+
+/// Get the latest metrics snapshot as JSON, or null before the first sample.
+#[no_mangle]
+pub extern "system" fn Java_app_slipnet_tunnel_SlipstreamBridge_nativeGetMetricsJson(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let snapshot = METRICS.lock().ok().and_then(|metrics| *metrics);
+    let Some(snapshot) = snapshot else {
+        return std::ptr::null_mut();
+    };
+    match env.new_string(snapshot.to_json()) {
+        Ok(json) => json.into_raw(),
+        Err(err) => {
+            warn!("Failed to create metrics string: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Get the active connection's path quality as JSON, or null while disconnected.
+#[no_mangle]
+pub extern "system" fn Java_app_slipnet_tunnel_SlipstreamBridge_nativeGetConnectionStatsJson(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let stats = CONNECTION_STATS.lock().ok().and_then(|stats| *stats);
+    let Some(stats) = stats else {
+        return std::ptr::null_mut();
+    };
+    match env.new_string(path_quality_json(&stats)) {
+        Ok(json) => json.into_raw(),
+        Err(err) => {
+            warn!("Failed to create connection stats string: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Flat JSON for `nativeGetConnectionStatsJson`, with times in microseconds.
+fn path_quality_json(quality: &PathQuality) -> String {
+    format!(
+        "{{\"receive_rate_estimate\":{},\"pacing_rate\":{},\"cwin\":{},\
+         \"bytes_in_transit\":{},\"rtt_us\":{},\"rtt_sample_us\":{},\
+         \"rtt_variant_us\":{},\"rtt_min_us\":{},\"rtt_max_us\":{},\"sent\":{},\
+         \"lost\":{},\"timer_losses\":{},\"spurious_losses\":{},\
+         \"max_spurious_rtt_us\":{},\"max_reorder_delay_us\":{},\"max_reorder_gap\":{},\
+         \"loss_rate\":{:.4}}}",
+        quality.receive_rate_bytes_per_sec,
+        quality.pacing_rate_bytes_per_sec,
+        quality.cwin_bytes,
+        quality.bytes_in_transit,
+        quality.rtt.as_micros(),
+        quality.rtt_sample.as_micros(),
+        quality.rtt_variance.as_micros(),
+        quality.rtt_min.as_micros(),
+        quality.rtt_max.as_micros(),
+        quality.packets_sent,
+        quality.packets_lost,
+        quality.timer_losses,
+        quality.spurious_losses,
+        quality.max_spurious_rtt.as_micros(),
+        quality.max_reorder_delay.as_micros(),
+        quality.max_reorder_gap,
+        quality.loss_rate()
+    )
+}
+
 // ============================================================================
 // Tests
 // ============================================================================
@@ -682,6 +905,28 @@ mod tests {
         IS_LISTENER_READY.store(false, Ordering::SeqCst);
     }
 
+    #[test]
+    fn test_exception_classes_are_distinct() {
+        let errors = [
+            ClientError::config("bad"),
+            ClientError::Dns(slipstream_dns::DnsError::new("bad")),
+            ClientError::quic(-1, "bad"),
+            ClientError::Io(std::io::Error::other("bad")),
+            ClientError::Address("bad".to_string()),
+            ClientError::Tls {
+                file: "ssl.c".to_string(),
+                line: 1,
+                description: "bad".to_string(),
+            },
+            ClientError::CryptoErrors(vec!["bad".to_string()]),
+            ClientError::Shutdown,
+        ];
+        let mut classes: Vec<&str> = errors.iter().map(exception_class).collect();
+        classes.sort();
+        classes.dedup();
+        assert_eq!(classes.len(), errors.len());
+    }
+
     #[test]
     fn test_failure_tracking() {
         CONSECUTIVE_FAILURES.store(0, Ordering::SeqCst);
@@ -697,4 +942,34 @@ mod tests {
         // Reset
         CONSECUTIVE_FAILURES.store(0, Ordering::SeqCst);
     }
+
+    #[test]
+    fn test_connection_stats_json_carries_every_path_quality_field() {
+        let quality = PathQuality::from(&slipstream_ffi::picoquic::picoquic_path_quality_t {
+            receive_rate_estimate: 1,
+            pacing_rate: 2,
+            cwin: 3,
+            rtt: 4,
+            rtt_sample: 5,
+            rtt_variant: 6,
+            rtt_min: 7,
+            rtt_max: 8,
+            sent: 200,
+            lost: 5,
+            timer_losses: 11,
+            spurious_losses: 12,
+            max_spurious_rtt: 13,
+            max_reorder_delay: 14,
+            max_reorder_gap: 15,
+            bytes_in_transit: 16,
+        });
+        assert_eq!(
+            path_quality_json(&quality),
+            "{\"receive_rate_estimate\":1,\"pacing_rate\":2,\"cwin\":3,\"bytes_in_transit\":16,\
+             \"rtt_us\":4,\"rtt_sample_us\":5,\"rtt_variant_us\":6,\"rtt_min_us\":7,\
+             \"rtt_max_us\":8,\"sent\":200,\"lost\":5,\"timer_losses\":11,\
+             \"spurious_losses\":12,\"max_spurious_rtt_us\":13,\"max_reorder_delay_us\":14,\
+             \"max_reorder_gap\":15,\"loss_rate\":0.0250}"
+        );
+    }
 }
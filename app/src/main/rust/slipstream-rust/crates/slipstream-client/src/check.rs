@@ -0,0 +1,363 @@
+use crate::local_socket::{unix_listen_path, UNIX_LISTEN_PREFIX};
+use crate::pinning::{
+    load_cert_check, load_pinned_cert, tls_cipher_name, tls_version_label, CertCheck,
+};
+use crate::resolver_discovery::system_resolver_specs;
+use crate::runtime::SLIPSTREAM_SNI;
+use slipstream_core::resolve_host_port;
+use slipstream_core::stream_priority::StreamPriorityMode;
+use slipstream_ffi::{ClientConfig, ListenerMode, ResolverMode};
+use std::fmt::Write;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+
+/// Validates a resolved config for `--check-config` and renders it as `key: value` lines.
+///
+/// Resolves every resolver and loads the pinned certificates like `run_client` would, but
+/// never binds a socket or starts picoquic.
+pub(crate) fn check_config(config: &ClientConfig<'_>) -> Result<String, String> {
+    let tcp_listen = match unix_listen_path(config.tcp_listen_host) {
+        Some(path) => format!("{}{}", UNIX_LISTEN_PREFIX, path),
+        None => resolve_listen(config.tcp_listen_host, config.tcp_listen_port)?.to_string(),
+    };
+    let system_resolvers = if config.resolvers.is_empty() {
+        let specs = system_resolver_specs(config.address_policy);
+        if specs.is_empty() {
+            return Err(
+                "No resolvers configured and none found in the system DNS configuration"
+                    .to_string(),
+            );
+        }
+        specs
+    } else {
+        Vec::new()
+    };
+    let source = if system_resolvers.is_empty() {
+        ""
+    } else {
+        " (system)"
+    };
+    let mut resolvers = Vec::with_capacity(config.resolvers.len());
+    for spec in config.resolvers.iter().chain(&system_resolvers) {
+        let addr = resolve_host_port(&spec.resolver).map_err(|err| err.to_string())?;
+        resolvers.push((spec, addr));
+    }
+    let cert_check = config
+        .cert
+        .map(|cert| load_cert_check(cert, config.cert_mode))
+        .transpose()
+        .map_err(|err| err.to_string())?;
+    if let Some(path) = config.resolver_cert {
+        if load_pinned_cert(path).map_err(|err| err.to_string())?.len() != 1 {
+            return Err("Resolver cert must contain exactly one certificate".to_string());
+        }
+    }
+
+    let mut summary = String::new();
+    let _ = writeln!(summary, "tcp_listen: {}", tcp_listen);
+    let _ = writeln!(
+        summary,
+        "listener_mode: {}",
+        listener_mode_label(config.listener_mode)
+    );
+    let _ = writeln!(summary, "listener_workers: {}", config.listener_workers);
+    let _ = writeln!(summary, "datagram_mode: {}", config.datagram_mode);
+    let _ = writeln!(
+        summary,
+        "stream_priority_mode: {}",
+        config
+            .stream_priority_mode
+            .map_or("default", StreamPriorityMode::label)
+    );
+    let _ = writeln!(summary, "domain: {}", config.domain);
+    let _ = writeln!(summary, "address_family: {}", config.address_policy.label());
+    for (spec, addr) in resolvers {
+        let _ = write!(
+            summary,
+            "resolver: {} {}:{} ({})",
+            mode_label(spec.mode),
+            spec.resolver.host,
+            spec.resolver.port,
+            addr
+        );
+        if let Some(path) = &spec.doh_path {
+            let _ = write!(summary, " path={}", path);
+        }
+        summary.push_str(source);
+        summary.push('\n');
+    }
+    let _ = writeln!(summary, "resolver_strategy: {:?}", config.resolver_strategy);
+    let _ = writeln!(summary, "qtype: {}", config.qtype);
+    let _ = writeln!(summary, "max_qname_len: {}", config.max_qname_len);
+    let _ = writeln!(summary, "encoding: {:?}", config.encoding);
+    let _ = writeln!(
+        summary,
+        "ignore_cached_responses: {}",
+        config.ignore_cached_responses
+    );
+    let _ = writeln!(
+        summary,
+        "edns: udp_size={} dnssec_ok={} ecs_optout={} padding_block={}",
+        config.edns.udp_payload,
+        config.edns.dnssec_ok,
+        config.edns.ecs_optout,
+        config.edns.padding_block
+    );
+    match config.auth_secret {
+        Some(_) => {
+            let _ = writeln!(
+                summary,
+                "auth: hmac-sha256 ({} byte mac)",
+                config.auth_mac_len
+            );
+        }
+        None => {
+            let _ = writeln!(summary, "auth: disabled");
+        }
+    }
+    match (config.cert, cert_check) {
+        (Some(cert), Some(CertCheck::Pins(pins))) => {
+            let _ = writeln!(summary, "cert: {} ({} pin(s))", cert, pins.len());
+        }
+        (Some(cert), Some(CertCheck::Chain { anchors })) => {
+            let _ = writeln!(
+                summary,
+                "cert: {} (chain-verify, {} CA certificate(s))",
+                cert,
+                anchors.len()
+            );
+        }
+        _ => {
+            let _ = writeln!(summary, "cert: disabled");
+        }
+    }
+    let _ = writeln!(
+        summary,
+        "resolver_cert: {}",
+        config.resolver_cert.unwrap_or("disabled")
+    );
+    let _ = writeln!(summary, "sni: {}", config.sni.unwrap_or(SLIPSTREAM_SNI));
+    let _ = writeln!(
+        summary,
+        "tls_min_version: {}",
+        tls_version_label(config.tls_min_version)
+    );
+    if config.tls_ciphers.is_empty() {
+        let _ = writeln!(summary, "tls_ciphers: default");
+    } else {
+        let names: Vec<_> = config
+            .tls_ciphers
+            .iter()
+            .map(|c| tls_cipher_name(*c))
+            .collect();
+        let _ = writeln!(summary, "tls_ciphers: {}", names.join(","));
+    }
+    let _ = writeln!(
+        summary,
+        "congestion_control: {}",
+        config.congestion_control.unwrap_or("default")
+    );
+    let _ = writeln!(
+        summary,
+        "keep_alive_interval_ms: {}",
+        config.keep_alive_interval
+    );
+    if config.adaptive_keepalive {
+        let _ = writeln!(
+            summary,
+            "adaptive_keepalive: {}-{}ms",
+            config.min_keep_alive_ms, config.max_keep_alive_ms
+        );
+    } else {
+        let _ = writeln!(summary, "adaptive_keepalive: disabled");
+    }
+    match config.max_query_rate {
+        Some(max_rate) => {
+            let _ = writeln!(
+                summary,
+                "query_rate: {}-{} queries/s",
+                config.min_query_rate, max_rate
+            );
+        }
+        None => {
+            let _ = writeln!(summary, "query_rate: unlimited");
+        }
+    }
+    match config.max_pacing_rate {
+        Some(rate) => {
+            let _ = writeln!(summary, "max_pacing_rate: {} bytes/s", rate);
+        }
+        None => {
+            let _ = writeln!(summary, "max_pacing_rate: unlimited");
+        }
+    }
+    let _ = writeln!(summary, "pipeline_window: {}", config.pipeline_window);
+    let _ = writeln!(
+        summary,
+        "reconnect_backoff_ms: {}-{}",
+        config.reconnect_backoff_ms, config.reconnect_max_ms
+    );
+    if config.idle_timeout_seconds > 0 {
+        let _ = writeln!(
+            summary,
+            "idle_timeout_seconds: {}",
+            config.idle_timeout_seconds
+        );
+    } else {
+        let _ = writeln!(summary, "idle_timeout_seconds: disabled");
+    }
+    let _ = writeln!(
+        summary,
+        "session_ticket_path: {}",
+        config.session_ticket_path.unwrap_or("disabled")
+    );
+    let _ = writeln!(
+        summary,
+        "metrics_socket: {}",
+        config.metrics_socket.unwrap_or("disabled")
+    );
+    match config.health_listen {
+        Some(addr) => {
+            let _ = writeln!(summary, "health_listen: {}", addr);
+        }
+        None => {
+            let _ = writeln!(summary, "health_listen: disabled");
+        }
+    }
+    Ok(summary)
+}
+
+/// Binds the TCP listen address and closes it again, for `--dry-run`.
+///
+/// A `unix:` listen socket is left alone, since binding it would replace a running
+/// client's socket.
+pub(crate) fn probe_listen(config: &ClientConfig<'_>) -> Result<(), String> {
+    if unix_listen_path(config.tcp_listen_host).is_some() {
+        return Ok(());
+    }
+    let addr = resolve_listen(config.tcp_listen_host, config.tcp_listen_port)?;
+    TcpListener::bind(addr)
+        .map(drop)
+        .map_err(|err| format!("Cannot bind TCP listen address {}: {}", addr, err))
+}
+
+fn resolve_listen(host: &str, port: u16) -> Result<SocketAddr, String> {
+    (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("No addresses resolved for {}:{}", host, port))
+}
+
+fn listener_mode_label(mode: ListenerMode) -> &'static str {
+    match mode {
+        ListenerMode::Passthrough => "passthrough",
+        ListenerMode::ConnectProxy => "connect-proxy",
+        ListenerMode::Socks5 => "socks5",
+    }
+}
+
+fn mode_label(mode: ResolverMode) -> &'static str {
+    match mode {
+        ResolverMode::Recursive => "recursive",
+        ResolverMode::Authoritative => "authoritative",
+        ResolverMode::Doh => "doh",
+        ResolverMode::Dot => "dot",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slipstream_core::{parse_host_port, AddressKind, AddressPolicy};
+    use slipstream_dns::Encoding;
+    use slipstream_ffi::{CertMode, ResolverSpec, ResolverStrategy};
+
+    fn config<'a>(resolvers: &'a [ResolverSpec], cert: Option<&'a str>) -> ClientConfig<'a> {
+        ClientConfig {
+            tcp_listen_host: "127.0.0.1",
+            tcp_listen_port: 5201,
+            listener_mode: ListenerMode::Passthrough,
+            listener_workers: 1,
+            datagram_mode: false,
+            stream_priority_mode: None,
+            resolvers,
+            address_policy: AddressPolicy::Any,
+            resolver_strategy: ResolverStrategy::Ordered,
+            resolver_health_window_ms: 2000,
+            domain: "tunnel.example.net",
+            qtype: slipstream_dns::RR_TXT,
+            encoding: Encoding::Base32,
+            probe_qname_capacity: false,
+            max_qname_len: slipstream_dns::MAX_DNS_NAME_LEN as u8,
+            ignore_cached_responses: false,
+            edns: slipstream_dns::EdnsOptions::default(),
+            auth_secret: None,
+            auth_mac_len: slipstream_dns::hmac::DEFAULT_MAC_LEN,
+            cert,
+            cert_mode: CertMode::LeafPin,
+            resolver_cert: None,
+            sni: None,
+            tls_min_version: slipstream_ffi::TlsVersion::Tls13,
+            tls_ciphers: &[],
+            congestion_control: None,
+            gso: false,
+            keep_alive_interval: 400,
+            adaptive_keepalive: false,
+            min_keep_alive_ms: 400,
+            max_keep_alive_ms: 30_000,
+            metrics_socket: None,
+            health_listen: None,
+            migration_timeout_ms: 3000,
+            enable_migration: false,
+            max_query_rate: None,
+            min_query_rate: 1,
+            max_pacing_rate: None,
+            pipeline_window: 4,
+            reconnect_backoff_ms: 250,
+            reconnect_max_ms: 5000,
+            max_reconnect_attempts: 0,
+            idle_timeout_seconds: 0,
+            session_ticket_path: None,
+            on_state_change: None,
+            debug_poll: false,
+            debug_streams: false,
+            idle_poll_interval_ms: 2000,
+        }
+    }
+
+    #[test]
+    fn summarizes_resolvers_and_rejects_missing_cert() {
+        let resolvers = [ResolverSpec {
+            resolver: parse_host_port("192.0.2.1", 53, AddressKind::Resolver).unwrap(),
+            mode: ResolverMode::Authoritative,
+            doh_path: None,
+        }];
+        let summary = check_config(&config(&resolvers, None)).expect("valid config");
+        assert!(summary.contains("tcp_listen: 127.0.0.1:5201\n"));
+        assert!(summary.contains("resolver: authoritative 192.0.2.1:53 (192.0.2.1:53)\n"));
+        assert!(summary.contains("cert: disabled\n"));
+        // Without --sni the built-in name is sent, not the tunnel domain.
+        assert!(summary.contains(&format!("sni: {}\n", SLIPSTREAM_SNI)));
+        assert!(!summary.contains("sni: tunnel.example.net"));
+
+        let mut fronted = config(&resolvers, None);
+        fronted.sni = Some("front.example.net");
+        let summary = check_config(&fronted).expect("valid config");
+        assert!(summary.contains("sni: front.example.net\n"));
+
+        let missing = "/nonexistent/slipstream-check-cert.pem";
+        assert!(check_config(&config(&resolvers, Some(missing))).is_err());
+    }
+
+    #[test]
+    fn probe_reports_a_taken_listen_port() {
+        let taken = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = taken.local_addr().expect("local addr").port();
+        let mut config = config(&[], None);
+        config.tcp_listen_port = port;
+        let err = probe_listen(&config).unwrap_err();
+        assert!(err.contains("Cannot bind TCP listen address"), "{}", err);
+        drop(taken);
+        assert!(probe_listen(&config).is_ok());
+    }
+}
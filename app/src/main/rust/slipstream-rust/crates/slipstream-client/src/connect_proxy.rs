@@ -0,0 +1,204 @@
+//! HTTP CONNECT handshake for `--listener-mode connect-proxy`.
+//!
+//! The listener answers the CONNECT request itself and forwards the requested authority to the
+//! server in a `proxy_frame` header at the start of the QUIC stream.
+
+use slipstream_core::proxy_frame::{encode_proxy_frame, ProxyTarget};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout, Duration};
+use tracing::debug;
+
+const MAX_REQUEST_HEAD_BYTES: usize = 8 * 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const RESPONSE_ESTABLISHED: &[u8] = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+const RESPONSE_BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n";
+const RESPONSE_NOT_ALLOWED: &[u8] =
+    b"HTTP/1.1 405 Method Not Allowed\r\nAllow: CONNECT\r\nConnection: close\r\n\r\n";
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ConnectError {
+    NotConnect,
+    Malformed(&'static str),
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectError::NotConnect => write!(f, "method is not CONNECT"),
+            ConnectError::Malformed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Reads and answers the CONNECT request on `stream`.
+///
+/// Returns the bytes that must open the QUIC stream: the proxy frame, followed by anything the
+/// client sent after its request head. `None` means the connection was refused or timed out.
+pub(crate) async fn accept_connect(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+) -> Option<Vec<u8>> {
+    match timeout(HANDSHAKE_TIMEOUT, handshake(stream)).await {
+        Ok(Ok(preamble)) => Some(preamble),
+        Ok(Err(err)) => {
+            debug!("connect-proxy: rejected request: {}", err);
+            None
+        }
+        Err(_) => {
+            debug!("connect-proxy: request timed out");
+            None
+        }
+    }
+}
+
+async fn handshake(stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    let head_len = loop {
+        if let Some(end) = find_head_end(&buf) {
+            break end;
+        }
+        if buf.len() >= MAX_REQUEST_HEAD_BYTES {
+            let _ = stream.write_all(RESPONSE_BAD_REQUEST).await;
+            return Err("request head too large".to_string());
+        }
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|err| err.to_string())?;
+        if read == 0 {
+            return Err("connection closed before the request head".to_string());
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    };
+    let target = match parse_connect_request(&buf[..head_len]) {
+        Ok(target) => target,
+        Err(err) => {
+            let response = match err {
+                ConnectError::NotConnect => RESPONSE_NOT_ALLOWED,
+                ConnectError::Malformed(_) => RESPONSE_BAD_REQUEST,
+            };
+            let _ = stream.write_all(response).await;
+            return Err(err.to_string());
+        }
+    };
+    let mut preamble = match encode_proxy_frame(&target) {
+        Ok(frame) => frame,
+        Err(err) => {
+            let _ = stream.write_all(RESPONSE_BAD_REQUEST).await;
+            return Err(err.to_string());
+        }
+    };
+    stream
+        .write_all(RESPONSE_ESTABLISHED)
+        .await
+        .map_err(|err| err.to_string())?;
+    debug!(
+        "connect-proxy: tunneling to {}:{}",
+        target.host, target.port
+    );
+    preamble.extend_from_slice(&buf[head_len..]);
+    Ok(preamble)
+}
+
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+/// Parses `CONNECT host:port HTTP/1.x` from a request head; header fields are ignored.
+pub(crate) fn parse_connect_request(head: &[u8]) -> Result<ProxyTarget, ConnectError> {
+    let line_end = head
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .ok_or(ConnectError::Malformed("missing request line"))?;
+    let line = std::str::from_utf8(&head[..line_end])
+        .map_err(|_| ConnectError::Malformed("request line is not UTF-8"))?;
+    let mut parts = line.split(' ');
+    let (Some(method), Some(authority), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ConnectError::Malformed("request line needs three parts"));
+    };
+    if method != "CONNECT" {
+        return Err(ConnectError::NotConnect);
+    }
+    if !version.starts_with("HTTP/1.") {
+        return Err(ConnectError::Malformed("unsupported HTTP version"));
+    }
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        let (host, port) = rest
+            .split_once("]:")
+            .ok_or(ConnectError::Malformed("bad IPv6 authority"))?;
+        (host, port)
+    } else {
+        authority
+            .rsplit_once(':')
+            .ok_or(ConnectError::Malformed("authority needs a port"))?
+    };
+    let port = port
+        .parse::<u16>()
+        .ok()
+        .filter(|port| *port != 0)
+        .ok_or(ConnectError::Malformed("invalid port"))?;
+    if host.is_empty() {
+        return Err(ConnectError::Malformed("empty host"));
+    }
+    Ok(ProxyTarget {
+        host: host.to_string(),
+        port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slipstream_core::proxy_frame::decode_proxy_frame;
+    use tokio::net::{TcpListener, TcpStream as TokioTcpStream};
+
+    #[test]
+    fn parses_connect_authorities() {
+        let target = parse_connect_request(
+            b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("example.com", 443));
+        let target = parse_connect_request(b"CONNECT [2001:db8::1]:22 HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("2001:db8::1", 22));
+        assert_eq!(
+            parse_connect_request(b"GET / HTTP/1.1\r\n\r\n"),
+            Err(ConnectError::NotConnect)
+        );
+        assert!(parse_connect_request(b"CONNECT example.com HTTP/1.1\r\n\r\n").is_err());
+        assert!(parse_connect_request(b"CONNECT example.com:0 HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn answers_connect_and_keeps_early_data() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .expect("build tokio runtime");
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+            let addr = listener.local_addr().expect("local addr");
+            let client = tokio::spawn(async move {
+                let mut client = TokioTcpStream::connect(addr).await.expect("connect");
+                client
+                    .write_all(b"CONNECT example.com:443 HTTP/1.1\r\n\r\nhello")
+                    .await
+                    .expect("write");
+                let mut response = vec![0u8; RESPONSE_ESTABLISHED.len()];
+                client.read_exact(&mut response).await.expect("read");
+                response
+            });
+            let (mut stream, _) = listener.accept().await.expect("accept");
+            let preamble = accept_connect(&mut stream).await.expect("preamble");
+            let (target, frame_len) = decode_proxy_frame(&preamble).unwrap().unwrap();
+            assert_eq!((target.host.as_str(), target.port), ("example.com", 443));
+            assert_eq!(&preamble[frame_len..], b"hello");
+            assert_eq!(client.await.expect("client"), RESPONSE_ESTABLISHED);
+        });
+    }
+}
@@ -0,0 +1,184 @@
+//! `--datagram-mode` UDP listener.
+//!
+//! The client listens for UDP on the TCP listen address and gives each source address its own
+//! flow id; the payloads travel to the server in QUIC DATAGRAM frames and the server's replies
+//! come back to the source they belong to. Nothing is retransmitted: a datagram that arrives
+//! before the connection is ready, or that does not fit one QUIC packet, is dropped.
+
+use crate::streams::Command;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Sources quiet for this long lose their flow id, matching the server's idle flow timeout.
+const DATAGRAM_FLOW_IDLE: Duration = Duration::from_secs(60);
+/// Sources tracked at once; a new source past this takes the flow of the quietest one.
+const MAX_DATAGRAM_FLOWS: usize = 1024;
+const MAX_UDP_PAYLOAD: usize = 65_535;
+
+struct Flow {
+    id: u64,
+    last_active: Instant,
+}
+
+pub(crate) struct DatagramListener {
+    socket: Arc<UdpSocket>,
+    flows: HashMap<SocketAddr, Flow>,
+    peers: HashMap<u64, SocketAddr>,
+    next_flow_id: u64,
+}
+
+impl DatagramListener {
+    pub(crate) async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self {
+            socket: Arc::new(UdpSocket::bind(addr).await?),
+            flows: HashMap::new(),
+            peers: HashMap::new(),
+            next_flow_id: 0,
+        })
+    }
+
+    /// Feeds every datagram the listener receives to the event loop as `Command::Datagram`.
+    pub(crate) fn spawn_reader(&self, command_tx: mpsc::UnboundedSender<Command>) {
+        let socket = Arc::clone(&self.socket);
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+            loop {
+                match socket.recv_from(&mut buf).await {
+                    Ok((read, peer)) => {
+                        let command = Command::Datagram {
+                            peer,
+                            data: buf[..read].to_vec(),
+                        };
+                        if command_tx.send(command).is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => debug!("UDP listener recv failed: {}", err),
+                }
+            }
+        });
+    }
+
+    /// Flow id for `peer`; a new source gets the next id and idle sources are forgotten, as is
+    /// the quietest source once [`MAX_DATAGRAM_FLOWS`] are active.
+    pub(crate) fn flow_for(&mut self, peer: SocketAddr, now: Instant) -> u64 {
+        let active =
+            |flow: &Flow| now.saturating_duration_since(flow.last_active) < DATAGRAM_FLOW_IDLE;
+        if let Some(flow) = self.flows.get_mut(&peer).filter(|flow| active(flow)) {
+            flow.last_active = now;
+            return flow.id;
+        }
+        let peers = &mut self.peers;
+        self.flows.retain(|_, flow| {
+            let active = active(flow);
+            if !active {
+                peers.remove(&flow.id);
+            }
+            active
+        });
+        if self.flows.len() >= MAX_DATAGRAM_FLOWS {
+            let quietest = self
+                .flows
+                .iter()
+                .min_by_key(|(_, flow)| flow.last_active)
+                .map(|(peer, flow)| (*peer, flow.id));
+            if let Some((quietest, flow_id)) = quietest {
+                self.flows.remove(&quietest);
+                self.peers.remove(&flow_id);
+            }
+        }
+        let id = self.next_flow_id;
+        self.next_flow_id += 1;
+        self.flows.insert(
+            peer,
+            Flow {
+                id,
+                last_active: now,
+            },
+        );
+        self.peers.insert(id, peer);
+        id
+    }
+
+    /// Sends a server reply to the source of `flow_id`; replies for forgotten flows, or that
+    /// would block, are dropped.
+    pub(crate) fn reply(&mut self, flow_id: u64, payload: &[u8], now: Instant) {
+        let Some(peer) = self.peers.get(&flow_id).copied() else {
+            return;
+        };
+        if let Some(flow) = self.flows.get_mut(&peer) {
+            flow.last_active = now;
+        }
+        if let Err(err) = self.socket.try_send_to(payload, peer) {
+            debug!("flow {}: UDP reply to {} dropped: {}", flow_id, peer, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sources_keep_their_flow_until_idle() {
+        let mut listener = DatagramListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .expect("bind listener");
+        let client = UdpSocket::bind("127.0.0.1:0").await.expect("bind client");
+        let client_addr = client.local_addr().expect("client addr");
+        let other: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(listener.flow_for(client_addr, start), 0);
+        assert_eq!(listener.flow_for(other, start), 1);
+        assert_eq!(listener.flow_for(client_addr, start), 0);
+
+        // A fresh socket is not known to be writable yet, and `reply` never waits.
+        listener.socket.writable().await.expect("listener writable");
+        listener.reply(0, b"pong", start + DATAGRAM_FLOW_IDLE / 2);
+        let mut buf = [0u8; 8];
+        let (read, from) = client.recv_from(&mut buf).await.expect("client recv");
+        assert_eq!(&buf[..read], b"pong");
+        assert_eq!(from, listener.socket.local_addr().unwrap());
+
+        let later = start + DATAGRAM_FLOW_IDLE + Duration::from_secs(1);
+        assert_eq!(listener.flow_for(client_addr, later), 0);
+        assert_eq!(listener.flow_for(other, later), 2);
+        assert!(!listener.peers.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn the_quietest_source_gives_way_when_flows_are_full() {
+        let mut listener = DatagramListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .expect("bind listener");
+        let start = Instant::now();
+        let source = |port: usize| SocketAddr::from(([127, 0, 0, 1], 10_000 + port as u16));
+
+        for port in 0..MAX_DATAGRAM_FLOWS {
+            let now = start + Duration::from_millis(port as u64);
+            assert_eq!(listener.flow_for(source(port), now), port as u64);
+        }
+        let now = start + Duration::from_secs(1);
+        listener.flow_for(source(0), now);
+        let newcomer = listener.flow_for(source(MAX_DATAGRAM_FLOWS), now);
+
+        assert_eq!(listener.flows.len(), MAX_DATAGRAM_FLOWS);
+        assert_eq!(listener.peers.len(), MAX_DATAGRAM_FLOWS);
+        assert!(
+            !listener.flows.contains_key(&source(1)),
+            "source 1 was the quietest"
+        );
+        assert_eq!(listener.flow_for(source(0), now), 0);
+        assert_eq!(
+            listener.peers.get(&newcomer),
+            Some(&source(MAX_DATAGRAM_FLOWS))
+        );
+    }
+}
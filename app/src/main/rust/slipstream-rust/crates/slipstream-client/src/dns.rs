@@ -1,13 +1,31 @@
+mod auth;
+mod capacity;
 mod debug;
+mod doh;
+mod dot;
+mod health;
 mod path;
 mod poll;
+mod query_id;
+mod reorder;
+mod replay;
 mod resolver;
 mod response;
+mod transport;
 
+pub(crate) use auth::auth_domain;
+pub(crate) use capacity::probe_resolver_capacities;
 pub(crate) use debug::maybe_report_debug;
+pub(crate) use health::ResolverHealth;
+#[cfg(test)]
+pub(crate) use health::MAX_CONSECUTIVE_FAILURES;
 pub(crate) use path::{add_paths, refresh_resolver_path, resolver_mode_to_c};
 pub(crate) use poll::{expire_inflight_polls, send_poll_queries};
+pub(crate) use query_id::{randomize_qname_case, QueryIdPool};
+pub(crate) use reorder::ReorderBuffer;
+pub(crate) use replay::{query_domain, ReplayGuard};
 pub(crate) use resolver::{
     reset_resolver_path, resolve_resolvers, sockaddr_storage_to_socket_addr, ResolverState,
 };
-pub(crate) use response::{handle_dns_response, DnsResponseContext};
+pub(crate) use response::{handle_dns_response, release_held_responses, DnsResponseContext};
+pub(crate) use transport::EncryptedTransport;
@@ -0,0 +1,53 @@
+use crate::error::ClientError;
+use slipstream_dns::hmac;
+use std::borrow::Cow;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns `domain` prefixed with the auth label for `payload` when a secret is configured.
+///
+/// Every call draws a fresh nonce, so the label is never reused across queries. The label goes
+/// on before the sequence label so the server finds it right ahead of the domain.
+pub(crate) fn auth_domain<'a>(
+    secret: Option<&[u8]>,
+    mac_len: usize,
+    payload: &[u8],
+    domain: &'a str,
+) -> Result<Cow<'a, str>, ClientError> {
+    let Some(secret) = secret else {
+        return Ok(Cow::Borrowed(domain));
+    };
+    let nonce = hmac::new_nonce(unix_now_secs())?;
+    let tag = hmac::AuthTag {
+        nonce,
+        mac: hmac::compute(secret, &nonce, payload, mac_len)?,
+    };
+    Ok(Cow::Owned(format!("{}.{}", hmac::auth_label(&tag), domain)))
+}
+
+fn unix_now_secs() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_the_domain_only_with_a_secret() {
+        assert_eq!(
+            auth_domain(None, 8, b"payload", "test.com").expect("domain"),
+            "test.com"
+        );
+        let domain = auth_domain(Some(b"secret"), 8, b"payload", "test.com").expect("domain");
+        let (label, rest) = domain.split_once('.').expect("auth label");
+        assert_eq!(rest, "test.com");
+        let tag = hmac::parse_auth_label(label).expect("parse label");
+        assert!(hmac::verify(b"secret", &tag, b"payload"));
+        assert!(tag.issued_at().abs_diff(unix_now_secs()) <= 1);
+
+        let again = auth_domain(Some(b"secret"), 8, b"payload", "test.com").expect("domain");
+        assert_ne!(domain, again, "each query draws its own nonce");
+    }
+}
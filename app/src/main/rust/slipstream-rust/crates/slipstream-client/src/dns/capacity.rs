@@ -0,0 +1,287 @@
+use slipstream_core::{normalize_dual_stack_addr, resolve_host_port};
+use slipstream_dns::{
+    build_probe_qname, encode_query, response_id_and_rcode, EdnsOptions, QnameConfig, QueryParams,
+    Rcode, CLASS_IN, MAX_DNS_NAME_LEN,
+};
+use slipstream_ffi::{ClientConfig, ResolverMode};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket as TokioUdpSocket;
+use tokio::task::JoinSet;
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, info, warn};
+
+use super::auth::auth_domain;
+use super::query_id::random_query_id;
+use crate::error::ClientError;
+
+const PROBE_TIMEOUT: Duration = Duration::from_millis(1500);
+const PROBE_ATTEMPTS: usize = 2;
+/// MX, which the server answers with NXDOMAIN as it carries no tunnel data; a resolver that
+/// cannot reach the server answers SERVFAIL instead, so the two stay apart.
+const PROBE_QTYPE: u16 = 15;
+// Ascending capacity; the last step is the unrestricted default.
+const PROBE_STEPS: [QnameConfig; 4] = [
+    QnameConfig {
+        max_label_len: 32,
+        max_labels: 4,
+        max_name_len: MAX_DNS_NAME_LEN as u8,
+    },
+    QnameConfig {
+        max_label_len: 57,
+        max_labels: 3,
+        max_name_len: MAX_DNS_NAME_LEN as u8,
+    },
+    QnameConfig {
+        max_label_len: 57,
+        max_labels: 4,
+        max_name_len: MAX_DNS_NAME_LEN as u8,
+    },
+    QnameConfig {
+        max_label_len: 57,
+        max_labels: u8::MAX,
+        max_name_len: MAX_DNS_NAME_LEN as u8,
+    },
+];
+
+/// Probes every plain UDP resolver in `config` at once, each from its own socket made by
+/// `bind`, and returns the largest query name shape each one answered. Resolvers that answer
+/// no probe are left out and use the default shape.
+pub(crate) async fn probe_resolver_capacities<F, Fut>(
+    config: &ClientConfig<'_>,
+    bind: F,
+) -> Result<HashMap<SocketAddr, QnameConfig>, ClientError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<TokioUdpSocket, ClientError>>,
+{
+    let mut probes = JoinSet::new();
+    for spec in config.resolvers {
+        if matches!(spec.mode, ResolverMode::Doh | ResolverMode::Dot) {
+            continue;
+        }
+        let Ok(addr) = resolve_host_port(&spec.resolver) else {
+            continue;
+        };
+        let addr = normalize_dual_stack_addr(addr);
+        let udp = bind().await?;
+        let query = ProbeQuery {
+            domain: config.domain.to_string(),
+            edns: config.edns,
+            auth_secret: config.auth_secret.map(<[u8]>::to_vec),
+            auth_mac_len: config.auth_mac_len,
+        };
+        probes.spawn(async move {
+            let capacity = probe_qname_capacity(&udp, addr, &query, PROBE_TIMEOUT).await;
+            (addr, capacity)
+        });
+    }
+    let mut hints = HashMap::new();
+    while let Some(probe) = probes.join_next().await {
+        let Ok((addr, capacity)) = probe else {
+            continue;
+        };
+        match capacity {
+            Some(capacity) => {
+                info!(
+                    "Resolver {} carries query names up to {} labels of {} chars",
+                    addr, capacity.max_labels, capacity.max_label_len
+                );
+                hints.insert(addr, capacity);
+            }
+            None => warn!(
+                "Resolver {} answered no capacity probes; using the default query name shape",
+                addr
+            ),
+        }
+    }
+    Ok(hints)
+}
+
+/// What probe queries ask for: the tunnel's domain and EDNS options, and the auth label real
+/// queries carry so probes measure the room left next to it.
+pub(crate) struct ProbeQuery {
+    pub(crate) domain: String,
+    pub(crate) edns: EdnsOptions,
+    pub(crate) auth_secret: Option<Vec<u8>>,
+    pub(crate) auth_mac_len: usize,
+}
+
+/// Sends progressively larger probe queries carrying no payload to `resolver` and returns the
+/// largest shape that drew a response, stopping at the first one that does not.
+pub(crate) async fn probe_qname_capacity(
+    udp: &TokioUdpSocket,
+    resolver: SocketAddr,
+    query: &ProbeQuery,
+    timeout: Duration,
+) -> Option<QnameConfig> {
+    let mut capacity = None;
+    for step in PROBE_STEPS {
+        let mut answered = false;
+        for _ in 0..PROBE_ATTEMPTS {
+            let Ok(id) = random_query_id() else {
+                return capacity;
+            };
+            let Ok(domain) = auth_domain(
+                query.auth_secret.as_deref(),
+                query.auth_mac_len,
+                &[],
+                &query.domain,
+            ) else {
+                return capacity;
+            };
+            let Ok(qname) = build_probe_qname(&domain, step, id) else {
+                return capacity;
+            };
+            let params = QueryParams {
+                id,
+                qname: &qname,
+                qtype: PROBE_QTYPE,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: query.edns,
+            };
+            let Ok(packet) = encode_query(&params) else {
+                return capacity;
+            };
+            if let Err(err) = udp.send_to(&packet, resolver).await {
+                debug!("capacity probe to {} failed: {}", resolver, err);
+                continue;
+            }
+            if wait_for_probe_response(udp, resolver, id, timeout).await {
+                answered = true;
+                break;
+            }
+        }
+        if !answered {
+            break;
+        }
+        capacity = Some(step);
+    }
+    capacity
+}
+
+// Only the NXDOMAIN the tunnel server answers a probe with counts. SERVFAIL is what a resolver
+// sends when the query never got through, and FORMERR, REFUSED and the like mean it rejected
+// the query itself.
+async fn wait_for_probe_response(
+    udp: &TokioUdpSocket,
+    resolver: SocketAddr,
+    id: u16,
+    timeout: Duration,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1500];
+    loop {
+        let Ok(Ok((size, peer))) = timeout_at(deadline, udp.recv_from(&mut buf)).await else {
+            return false;
+        };
+        if normalize_dual_stack_addr(peer) != normalize_dual_stack_addr(resolver) {
+            continue;
+        }
+        match response_id_and_rcode(&buf[..size]) {
+            Some((response_id, rcode)) if response_id == id => {
+                return rcode == Some(Rcode::NameError);
+            }
+            _ => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slipstream_dns::ResponseParams;
+    use slipstream_dns::{decode_query, encode_response, DecodeQueryError, Encoding};
+
+    // Answers like a tunnel server behind a resolver that drops names longer than `max_len`,
+    // or with `servfail` like a resolver that cannot reach the server.
+    async fn fake_resolver(socket: TokioUdpSocket, max_len: usize, servfail: bool) {
+        let mut buf = [0u8; 1500];
+        loop {
+            let Ok((size, peer)) = socket.recv_from(&mut buf).await else {
+                return;
+            };
+            let Err(DecodeQueryError::Reply {
+                id,
+                rd,
+                cd,
+                ad,
+                question: Some(question),
+                rcode,
+            }) = decode_query(&buf[..size], "test.com", Encoding::Base32)
+            else {
+                continue;
+            };
+            if question.name.len() > max_len {
+                continue;
+            }
+            let response = encode_response(&ResponseParams {
+                id,
+                rd,
+                cd,
+                ad,
+                question: &question,
+                payload: None,
+                rcode: Some(if servfail {
+                    Rcode::ServerFailure
+                } else {
+                    rcode
+                }),
+                encoding: Encoding::Base32,
+                ttl: 0,
+                compress_names: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode response");
+            let _ = socket.send_to(&response, peer).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_stops_at_first_unanswered_size() {
+        let resolver = TokioUdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let resolver_addr = resolver.local_addr().expect("resolver addr");
+        // Fits 57x3 (183 chars with the domain) but not 57x4.
+        tokio::spawn(fake_resolver(resolver, 200, false));
+
+        let udp = TokioUdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let mut query = ProbeQuery {
+            domain: "test.com".to_string(),
+            edns: EdnsOptions::default(),
+            auth_secret: None,
+            auth_mac_len: slipstream_dns::hmac::DEFAULT_MAC_LEN,
+        };
+        let timeout = Duration::from_millis(100);
+        let capacity = probe_qname_capacity(&udp, resolver_addr, &query, timeout).await;
+        assert_eq!(capacity, Some(PROBE_STEPS[1]));
+
+        // The auth label takes room from the payload, so 57x3 no longer fits.
+        query.auth_secret = Some(b"secret".to_vec());
+        let capacity = probe_qname_capacity(&udp, resolver_addr, &query, timeout).await;
+        assert_eq!(capacity, Some(PROBE_STEPS[0]));
+    }
+
+    #[tokio::test]
+    async fn servfail_answers_do_not_count() {
+        let resolver = TokioUdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let resolver_addr = resolver.local_addr().expect("resolver addr");
+        tokio::spawn(fake_resolver(resolver, usize::MAX, true));
+
+        let udp = TokioUdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let query = ProbeQuery {
+            domain: "test.com".to_string(),
+            edns: EdnsOptions::default(),
+            auth_secret: None,
+            auth_mac_len: slipstream_dns::hmac::DEFAULT_MAC_LEN,
+        };
+        let timeout = Duration::from_millis(100);
+        let capacity = probe_qname_capacity(&udp, resolver_addr, &query, timeout).await;
+        assert_eq!(capacity, None);
+    }
+}
@@ -0,0 +1,521 @@
+use bytes::Bytes;
+use h2::client::SendRequest;
+use http::header::{ACCEPT, CONTENT_TYPE, RETRY_AFTER};
+use http::{Method, Request, StatusCode};
+use openssl::ssl::{SslConnector, SslMethod};
+use slipstream_core::AddressFamily;
+use slipstream_ffi::ResolverSpec;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_openssl::SslStream;
+use tracing::{debug, info, warn};
+
+use super::transport::{connect_tcp, EncryptedResponse};
+
+const DOH_CONTENT_TYPE: &str = "application/dns-message";
+const DOH_DEFAULT_PORT: u16 = 443;
+const DOH_MAX_RESPONSE_BYTES: usize = 65_535;
+const DOH_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const DOH_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Starts the HTTP/2 worker for one DoH resolver and returns its query sender.
+pub(super) fn spawn_doh_worker(
+    resolver: &ResolverSpec,
+    connect_addr: SocketAddr,
+    addr: SocketAddr,
+    response_tx: mpsc::UnboundedSender<EncryptedResponse>,
+) -> mpsc::UnboundedSender<Vec<u8>> {
+    let endpoint = DohEndpoint::new(resolver, connect_addr, addr);
+    info!("DoH resolver {} via {}", endpoint.uri, connect_addr);
+    let (query_tx, query_rx) = mpsc::unbounded_channel();
+    tokio::spawn(run_doh_worker(endpoint, query_rx, response_tx));
+    query_tx
+}
+
+/// Where a DoH worker sends its queries and how it opens the HTTP/2 connection for them.
+trait DohConnect {
+    /// Request URI, also used in logs.
+    fn uri(&self) -> &str;
+    /// Resolver address responses are tagged with.
+    fn addr(&self) -> SocketAddr;
+    async fn connect(&self) -> Result<SendRequest<Bytes>, String>;
+}
+
+struct DohEndpoint {
+    connect_addr: SocketAddr,
+    addr: SocketAddr,
+    host: String,
+    uri: String,
+}
+
+impl DohEndpoint {
+    fn new(resolver: &ResolverSpec, connect_addr: SocketAddr, addr: SocketAddr) -> Self {
+        let host = resolver.resolver.host.clone();
+        let mut authority = match resolver.resolver.family {
+            AddressFamily::V6 => format!("[{}]", host),
+            AddressFamily::V4 => host.clone(),
+        };
+        if resolver.resolver.port != DOH_DEFAULT_PORT {
+            authority = format!("{}:{}", authority, resolver.resolver.port);
+        }
+        let path = resolver.doh_path.as_deref().unwrap_or("/dns-query");
+        Self {
+            connect_addr,
+            addr,
+            host,
+            uri: format!("https://{}{}", authority, path),
+        }
+    }
+}
+
+enum DohEvent {
+    RateLimited(Option<Duration>),
+    Succeeded,
+    ConnectionLost,
+}
+
+async fn run_doh_worker(
+    endpoint: impl DohConnect,
+    mut query_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    response_tx: mpsc::UnboundedSender<EncryptedResponse>,
+) {
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let mut connection: Option<SendRequest<Bytes>> = None;
+    let mut backoff = DOH_BACKOFF_MIN;
+    let mut paused_until: Option<Instant> = None;
+    let mut dropped_while_paused = 0u64;
+    loop {
+        tokio::select! {
+            query = query_rx.recv() => {
+                let Some(query) = query else {
+                    break;
+                };
+                if let Some(until) = paused_until {
+                    if Instant::now() < until {
+                        // QUIC retransmits lost packets, so dropping here only delays data.
+                        dropped_while_paused = dropped_while_paused.saturating_add(1);
+                        continue;
+                    }
+                    paused_until = None;
+                    if dropped_while_paused > 0 {
+                        debug!(
+                            "DoH {}: dropped {} queries during back-off",
+                            endpoint.uri(), dropped_while_paused
+                        );
+                        dropped_while_paused = 0;
+                    }
+                }
+                if connection.is_none() {
+                    match endpoint.connect().await {
+                        Ok(sender) => {
+                            debug!("DoH {}: connected", endpoint.uri());
+                            connection = Some(sender);
+                        }
+                        Err(err) => {
+                            warn!(
+                                "DoH {}: connect failed ({}); retrying in {}ms",
+                                endpoint.uri(),
+                                err,
+                                backoff.as_millis()
+                            );
+                            paused_until = Some(Instant::now() + backoff);
+                            backoff = (backoff * 2).min(DOH_BACKOFF_MAX);
+                            continue;
+                        }
+                    }
+                }
+                let Some(sender) = connection.clone() else {
+                    continue;
+                };
+                tokio::spawn(send_doh_query(
+                    sender,
+                    endpoint.uri().to_string(),
+                    endpoint.addr(),
+                    query,
+                    response_tx.clone(),
+                    event_tx.clone(),
+                ));
+            }
+            Some(event) = event_rx.recv() => {
+                match event {
+                    DohEvent::Succeeded => {
+                        backoff = DOH_BACKOFF_MIN;
+                    }
+                    DohEvent::RateLimited(retry_after) => {
+                        if paused_until.is_some() {
+                            continue;
+                        }
+                        let delay = retry_after.unwrap_or(backoff).min(DOH_BACKOFF_MAX);
+                        warn!(
+                            "DoH {}: rate limited (HTTP 429); pausing queries for {}ms",
+                            endpoint.uri(),
+                            delay.as_millis()
+                        );
+                        paused_until = Some(Instant::now() + delay);
+                        backoff = (backoff * 2).min(DOH_BACKOFF_MAX);
+                    }
+                    DohEvent::ConnectionLost => {
+                        if connection.take().is_some() {
+                            debug!("DoH {}: connection lost; reconnecting on next query", endpoint.uri());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn send_doh_query(
+    sender: SendRequest<Bytes>,
+    uri: String,
+    addr: SocketAddr,
+    query: Vec<u8>,
+    response_tx: mpsc::UnboundedSender<EncryptedResponse>,
+    event_tx: mpsc::UnboundedSender<DohEvent>,
+) {
+    match exchange(sender, &uri, query).await {
+        Ok(DohOutcome::Response(body)) => {
+            let _ = event_tx.send(DohEvent::Succeeded);
+            let _ = response_tx.send((addr, body));
+        }
+        Ok(DohOutcome::RateLimited(retry_after)) => {
+            let _ = event_tx.send(DohEvent::RateLimited(retry_after));
+        }
+        Ok(DohOutcome::Status(status)) => {
+            debug!("DoH {}: unexpected HTTP status {}", uri, status);
+        }
+        Err(ExchangeError::Request(err)) => {
+            debug!("DoH {}: request failed: {}", uri, err);
+        }
+        Err(ExchangeError::Http2(err)) => {
+            debug!("DoH {}: request failed: {}", uri, err);
+            if err.is_go_away() || err.is_io() || err.reason().is_some() {
+                let _ = event_tx.send(DohEvent::ConnectionLost);
+            }
+        }
+    }
+}
+
+enum DohOutcome {
+    Response(Vec<u8>),
+    RateLimited(Option<Duration>),
+    Status(StatusCode),
+}
+
+enum ExchangeError {
+    /// `http` rejected the request, e.g. for a URI it cannot parse; the connection is fine.
+    Request(http::Error),
+    Http2(h2::Error),
+}
+
+impl From<h2::Error> for ExchangeError {
+    fn from(err: h2::Error) -> Self {
+        Self::Http2(err)
+    }
+}
+
+async fn exchange(
+    sender: SendRequest<Bytes>,
+    uri: &str,
+    query: Vec<u8>,
+) -> Result<DohOutcome, ExchangeError> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(CONTENT_TYPE, DOH_CONTENT_TYPE)
+        .header(ACCEPT, DOH_CONTENT_TYPE)
+        .body(())
+        .map_err(ExchangeError::Request)?;
+    let mut sender = sender.ready().await?;
+    let (response, mut body_tx) = sender.send_request(request, false)?;
+    body_tx.send_data(Bytes::from(query), true)?;
+    let response = response.await?;
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        return Ok(DohOutcome::RateLimited(retry_after));
+    }
+    if status != StatusCode::OK {
+        return Ok(DohOutcome::Status(status));
+    }
+    let mut body = response.into_body();
+    let mut out = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        out.extend_from_slice(&chunk);
+        if out.len() > DOH_MAX_RESPONSE_BYTES {
+            return Ok(DohOutcome::Status(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+    }
+    Ok(DohOutcome::Response(out))
+}
+
+impl DohConnect for DohEndpoint {
+    fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    async fn connect(&self) -> Result<SendRequest<Bytes>, String> {
+        connect_tls(self).await
+    }
+}
+
+async fn connect_tls(endpoint: &DohEndpoint) -> Result<SendRequest<Bytes>, String> {
+    let tcp = connect_tcp(endpoint.connect_addr)
+        .await
+        .map_err(|err| err.to_string())?;
+    let _ = tcp.set_nodelay(true);
+    let mut builder =
+        SslConnector::builder(SslMethod::tls_client()).map_err(|err| err.to_string())?;
+    builder
+        .set_alpn_protos(b"\x02h2")
+        .map_err(|err| err.to_string())?;
+    let ssl = builder
+        .build()
+        .configure()
+        .and_then(|config| config.into_ssl(&endpoint.host))
+        .map_err(|err| err.to_string())?;
+    let mut tls = SslStream::new(ssl, tcp).map_err(|err| err.to_string())?;
+    Pin::new(&mut tls)
+        .connect()
+        .await
+        .map_err(|err| format!("TLS handshake failed: {}", err))?;
+    if tls.ssl().selected_alpn_protocol() != Some(b"h2") {
+        return Err("server did not negotiate HTTP/2".to_string());
+    }
+    handshake(tls, &endpoint.uri).await
+}
+
+/// Runs the HTTP/2 handshake over `io` and drives the connection in the background.
+async fn handshake<T>(io: T, uri: &str) -> Result<SendRequest<Bytes>, String>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, connection) = h2::client::handshake(io)
+        .await
+        .map_err(|err| err.to_string())?;
+    let uri = uri.to_string();
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            debug!("DoH {}: connection closed: {}", uri, err);
+        }
+    });
+    Ok(sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::{sleep, timeout};
+
+    /// The mock resolver in plaintext HTTP/2; the worker does not care how the bytes travel.
+    struct PlainEndpoint {
+        addr: SocketAddr,
+        uri: String,
+    }
+
+    impl DohConnect for PlainEndpoint {
+        fn uri(&self) -> &str {
+            &self.uri
+        }
+
+        fn addr(&self) -> SocketAddr {
+            self.addr
+        }
+
+        async fn connect(&self) -> Result<SendRequest<Bytes>, String> {
+            let tcp = TcpStream::connect(self.addr)
+                .await
+                .map_err(|err| err.to_string())?;
+            handshake(tcp, &self.uri).await
+        }
+    }
+
+    struct MockDoh {
+        endpoint: PlainEndpoint,
+        connections: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    /// Serves h2 connections that each take `per_connection` requests before a GOAWAY. The
+    /// n-th request overall gets the n-th `(status, Retry-After)` reply while they last, and
+    /// 200 with the query reversed after that.
+    async fn mock_doh_server(
+        replies: Vec<(StatusCode, Option<&'static str>)>,
+        per_connection: usize,
+    ) -> MockDoh {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let connections = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(AtomicUsize::new(0));
+        let replies = Arc::new(Mutex::new(replies.into_iter()));
+        let (accepted, served) = (connections.clone(), requests.clone());
+        tokio::spawn(async move {
+            loop {
+                let (tcp, _) = listener.accept().await.expect("accept");
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let mut connection = h2::server::handshake(tcp).await.expect("h2 handshake");
+                let (served, replies) = (served.clone(), replies.clone());
+                tokio::spawn(async move {
+                    let mut taken = 0;
+                    while let Some(request) = connection.accept().await {
+                        let Ok((request, mut respond)) = request else {
+                            break;
+                        };
+                        served.fetch_add(1, Ordering::SeqCst);
+                        let reply = replies.lock().unwrap().next();
+                        tokio::spawn(async move {
+                            let mut body = request.into_body();
+                            let mut query = Vec::new();
+                            while let Some(Ok(chunk)) = body.data().await {
+                                let _ = body.flow_control().release_capacity(chunk.len());
+                                query.extend_from_slice(&chunk);
+                            }
+                            let mut response = http::Response::builder();
+                            if let Some((status, retry_after)) = reply {
+                                response = response.status(status);
+                                if let Some(retry_after) = retry_after {
+                                    response = response.header(RETRY_AFTER, retry_after);
+                                }
+                                let response = response.body(()).expect("response");
+                                let _ = respond.send_response(response, true);
+                                return;
+                            }
+                            query.reverse();
+                            let response = response.body(()).expect("response");
+                            if let Ok(mut send) = respond.send_response(response, false) {
+                                let _ = send.send_data(Bytes::from(query), true);
+                            }
+                        });
+                        taken += 1;
+                        if taken == per_connection {
+                            connection.graceful_shutdown();
+                        }
+                    }
+                });
+            }
+        });
+        MockDoh {
+            endpoint: PlainEndpoint {
+                addr,
+                uri: format!("http://{}/dns-query", addr),
+            },
+            connections,
+            requests,
+        }
+    }
+
+    /// Sends `query` until it is answered, as QUIC would retransmit it.
+    async fn answered(
+        query_tx: &mpsc::UnboundedSender<Vec<u8>>,
+        response_rx: &mut mpsc::UnboundedReceiver<EncryptedResponse>,
+        query: Vec<u8>,
+    ) -> Vec<u8> {
+        let retry = async {
+            loop {
+                query_tx.send(query.clone()).expect("send query");
+                if let Ok(Some((_, body))) =
+                    timeout(Duration::from_millis(100), response_rx.recv()).await
+                {
+                    return body;
+                }
+            }
+        };
+        timeout(Duration::from_secs(5), retry)
+            .await
+            .expect("response")
+    }
+
+    #[tokio::test]
+    async fn reuses_one_connection_and_redials_after_goaway() {
+        let mock = mock_doh_server(Vec::new(), 2).await;
+        let (response_tx, mut response_rx) = mpsc::unbounded_channel();
+        let (query_tx, query_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_doh_worker(mock.endpoint, query_rx, response_tx));
+
+        for query in [vec![1, 2], vec![3, 4]] {
+            query_tx.send(query).expect("send query");
+            let (_, body) = timeout(Duration::from_secs(5), response_rx.recv())
+                .await
+                .expect("response")
+                .expect("channel open");
+            assert_eq!(body.len(), 2);
+        }
+        assert_eq!(mock.connections.load(Ordering::SeqCst), 1);
+
+        // The server sent GOAWAY after two requests; the worker must dial a fresh connection.
+        let body = answered(&query_tx, &mut response_rx, vec![5, 6]).await;
+        assert_eq!(body, vec![6, 5]);
+        assert_eq!(mock.connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn pauses_queries_after_http_429() {
+        let mock = mock_doh_server(
+            vec![
+                (StatusCode::TOO_MANY_REQUESTS, Some("1")),
+                (StatusCode::OK, None),
+                (StatusCode::TOO_MANY_REQUESTS, None),
+            ],
+            usize::MAX,
+        )
+        .await;
+        let (response_tx, mut response_rx) = mpsc::unbounded_channel();
+        let (query_tx, query_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_doh_worker(mock.endpoint, query_rx, response_tx));
+        let requests = |count| {
+            let requests = mock.requests.clone();
+            async move {
+                while requests.load(Ordering::SeqCst) < count {
+                    sleep(Duration::from_millis(5)).await;
+                }
+            }
+        };
+
+        // Retry-After: 1 holds queries back for a second.
+        let limited = Instant::now();
+        query_tx.send(vec![1]).expect("send query");
+        timeout(Duration::from_secs(5), requests(1))
+            .await
+            .expect("first request");
+        sleep(Duration::from_millis(100)).await;
+        query_tx.send(vec![2]).expect("send query");
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(mock.requests.load(Ordering::SeqCst), 1);
+        answered(&query_tx, &mut response_rx, vec![3]).await;
+        assert!(limited.elapsed() >= Duration::from_secs(1));
+
+        // The success reset the back-off, so a bare 429 pauses for DOH_BACKOFF_MIN.
+        let count = mock.requests.load(Ordering::SeqCst);
+        let limited = Instant::now();
+        query_tx.send(vec![4]).expect("send query");
+        timeout(Duration::from_secs(5), requests(count + 1))
+            .await
+            .expect("limited request");
+        sleep(Duration::from_millis(100)).await;
+        query_tx.send(vec![5]).expect("send query");
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(mock.requests.load(Ordering::SeqCst), count + 1);
+        answered(&query_tx, &mut response_rx, vec![6]).await;
+        let paused = limited.elapsed();
+        assert!(paused >= DOH_BACKOFF_MIN, "{:?}", paused);
+        assert!(paused < Duration::from_secs(1), "{:?}", paused);
+    }
+}
@@ -0,0 +1,338 @@
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use slipstream_ffi::ResolverSpec;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_openssl::SslStream;
+use tracing::{debug, info, warn};
+
+use super::transport::{connect_tcp, EncryptedResponse};
+
+const DOT_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const DOT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+// Resolvers close idle DoT connections (RFC 7858 section 3.4); reconnect before writing
+// into a connection the resolver has most likely dropped already.
+const DOT_IDLE_TIMEOUT: Duration = Duration::from_secs(20);
+
+type TlsStream = SslStream<TcpStream>;
+
+/// Starts the persistent TLS worker for one DoT resolver and returns its query sender.
+pub(super) fn spawn_dot_worker(
+    resolver: &ResolverSpec,
+    connect_addr: SocketAddr,
+    addr: SocketAddr,
+    pinned_der: Option<Arc<Vec<u8>>>,
+    response_tx: mpsc::UnboundedSender<EncryptedResponse>,
+) -> mpsc::UnboundedSender<Vec<u8>> {
+    let endpoint = DotEndpoint {
+        connect_addr,
+        addr,
+        host: resolver.resolver.host.clone(),
+        pinned_der,
+    };
+    info!(
+        "DoT resolver {}:{} via {}",
+        endpoint.host, resolver.resolver.port, connect_addr
+    );
+    let (query_tx, query_rx) = mpsc::unbounded_channel();
+    tokio::spawn(run_dot_worker(endpoint, query_rx, response_tx));
+    query_tx
+}
+
+struct DotEndpoint {
+    connect_addr: SocketAddr,
+    addr: SocketAddr,
+    host: String,
+    /// DER of the pinned resolver leaf certificate; `None` uses normal chain validation.
+    pinned_der: Option<Arc<Vec<u8>>>,
+}
+
+struct DotConnection {
+    writer: WriteHalf<TlsStream>,
+    generation: u64,
+    last_used: Instant,
+}
+
+async fn run_dot_worker(
+    endpoint: DotEndpoint,
+    mut query_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    response_tx: mpsc::UnboundedSender<EncryptedResponse>,
+) {
+    let (lost_tx, mut lost_rx) = mpsc::unbounded_channel::<u64>();
+    let mut connection: Option<DotConnection> = None;
+    let mut generation = 0u64;
+    let mut backoff = DOT_BACKOFF_MIN;
+    let mut paused_until: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            query = query_rx.recv() => {
+                let Some(query) = query else {
+                    break;
+                };
+                if let Some(until) = paused_until {
+                    if Instant::now() < until {
+                        // QUIC retransmits lost packets, so dropping here only delays data.
+                        continue;
+                    }
+                    paused_until = None;
+                }
+                if connection
+                    .as_ref()
+                    .is_some_and(|conn| conn.last_used.elapsed() >= DOT_IDLE_TIMEOUT)
+                {
+                    debug!("DoT {}: connection idle; reconnecting", endpoint.host);
+                    connection = None;
+                }
+                if connection.is_none() {
+                    match connect(&endpoint).await {
+                        Ok(tls) => {
+                            debug!("DoT {}: connected", endpoint.host);
+                            generation = generation.wrapping_add(1);
+                            let (reader, writer) = tokio::io::split(tls);
+                            tokio::spawn(read_responses(
+                                reader,
+                                endpoint.addr,
+                                generation,
+                                response_tx.clone(),
+                                lost_tx.clone(),
+                            ));
+                            connection = Some(DotConnection {
+                                writer,
+                                generation,
+                                last_used: Instant::now(),
+                            });
+                            backoff = DOT_BACKOFF_MIN;
+                        }
+                        Err(err) => {
+                            warn!(
+                                "DoT {}: connect failed ({}); retrying in {}ms",
+                                endpoint.host,
+                                err,
+                                backoff.as_millis()
+                            );
+                            paused_until = Some(Instant::now() + backoff);
+                            backoff = (backoff * 2).min(DOT_BACKOFF_MAX);
+                            continue;
+                        }
+                    }
+                }
+                let Some(conn) = connection.as_mut() else {
+                    continue;
+                };
+                if let Err(err) = write_framed(&mut conn.writer, &query).await {
+                    debug!("DoT {}: write failed: {}", endpoint.host, err);
+                    connection = None;
+                    continue;
+                }
+                conn.last_used = Instant::now();
+            }
+            Some(lost_generation) = lost_rx.recv() => {
+                if connection
+                    .as_ref()
+                    .is_some_and(|conn| conn.generation == lost_generation)
+                {
+                    debug!("DoT {}: connection closed; reconnecting on next query", endpoint.host);
+                    connection = None;
+                }
+            }
+        }
+    }
+}
+
+/// Writes one DNS message with the RFC 7858 two-byte length prefix.
+async fn write_framed(writer: &mut WriteHalf<TlsStream>, message: &[u8]) -> std::io::Result<()> {
+    let len = u16::try_from(message.len())
+        .map_err(|_| std::io::Error::other("DNS message exceeds 65535 bytes"))?;
+    let mut frame = Vec::with_capacity(message.len() + 2);
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(message);
+    writer.write_all(&frame).await?;
+    writer.flush().await
+}
+
+async fn read_responses(
+    mut reader: ReadHalf<TlsStream>,
+    addr: SocketAddr,
+    generation: u64,
+    response_tx: mpsc::UnboundedSender<EncryptedResponse>,
+    lost_tx: mpsc::UnboundedSender<u64>,
+) {
+    loop {
+        let mut len_buf = [0u8; 2];
+        if reader.read_exact(&mut len_buf).await.is_err() {
+            break;
+        }
+        let len = u16::from_be_bytes(len_buf) as usize;
+        let mut message = vec![0u8; len];
+        if reader.read_exact(&mut message).await.is_err() {
+            break;
+        }
+        if response_tx.send((addr, message)).is_err() {
+            return;
+        }
+    }
+    let _ = lost_tx.send(generation);
+}
+
+async fn connect(endpoint: &DotEndpoint) -> Result<TlsStream, String> {
+    let tcp = connect_tcp(endpoint.connect_addr)
+        .await
+        .map_err(|err| err.to_string())?;
+    let _ = tcp.set_nodelay(true);
+    let mut builder =
+        SslConnector::builder(SslMethod::tls_client()).map_err(|err| err.to_string())?;
+    if let Some(pinned_der) = endpoint.pinned_der.clone() {
+        // Strict leaf pinning, matching --cert for the server: the chain is not consulted.
+        builder.set_verify_callback(SslVerifyMode::PEER, move |_, ctx| {
+            if ctx.error_depth() != 0 {
+                return true;
+            }
+            ctx.current_cert()
+                .and_then(|cert| cert.to_der().ok())
+                .is_some_and(|der| der == *pinned_der)
+        });
+    }
+    let ssl = builder
+        .build()
+        .configure()
+        .map(|config| config.verify_hostname(endpoint.pinned_der.is_none()))
+        .and_then(|config| config.into_ssl(&endpoint.host))
+        .map_err(|err| err.to_string())?;
+    let mut tls = SslStream::new(ssl, tcp).map_err(|err| err.to_string())?;
+    Pin::new(&mut tls)
+        .connect()
+        .await
+        .map_err(|err| format!("TLS handshake failed: {}", err))?;
+    Ok(tls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::ssl::{SslAcceptor, SslMethod};
+    use openssl::x509::{X509NameBuilder, X509};
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    fn self_signed() -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).expect("group");
+        let pkey = PKey::from_ec_key(EcKey::generate(&group).expect("key")).expect("pkey");
+        let mut name = X509NameBuilder::new().expect("name");
+        name.append_entry_by_text("CN", "dot.test").expect("cn");
+        let name = name.build();
+        let mut builder = X509::builder().expect("builder");
+        builder.set_version(2).expect("version");
+        builder.set_subject_name(&name).expect("subject");
+        builder.set_issuer_name(&name).expect("issuer");
+        builder.set_pubkey(&pkey).expect("pubkey");
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).expect("not before"))
+            .expect("set not before");
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).expect("not after"))
+            .expect("set not after");
+        builder
+            .sign(&pkey, openssl::hash::MessageDigest::sha256())
+            .expect("sign");
+        (builder.build(), pkey)
+    }
+
+    /// Accepts `connections` TLS clients in turn, answering each framed query with its
+    /// reverse and closing the connection after `per_connection` answers.
+    async fn mock_dot_server(
+        cert: X509,
+        key: PKey<Private>,
+        connections: usize,
+        per_connection: usize,
+    ) -> SocketAddr {
+        let mut acceptor =
+            SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).expect("acceptor");
+        acceptor.set_certificate(&cert).expect("cert");
+        acceptor.set_private_key(&key).expect("key");
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        tokio::spawn(async move {
+            for _ in 0..connections {
+                let (tcp, _) = listener.accept().await.expect("accept");
+                let ssl = openssl::ssl::Ssl::new(acceptor.context()).expect("ssl");
+                let mut tls = SslStream::new(ssl, tcp).expect("stream");
+                if Pin::new(&mut tls).accept().await.is_err() {
+                    continue;
+                }
+                for _ in 0..per_connection {
+                    let mut len_buf = [0u8; 2];
+                    tls.read_exact(&mut len_buf).await.expect("read len");
+                    let mut query = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+                    tls.read_exact(&mut query).await.expect("read query");
+                    query.reverse();
+                    let mut frame = (query.len() as u16).to_be_bytes().to_vec();
+                    frame.extend_from_slice(&query);
+                    tls.write_all(&frame).await.expect("write response");
+                }
+                let _ = tls.shutdown().await;
+            }
+        });
+        addr
+    }
+
+    fn endpoint_for(addr: SocketAddr, pinned: &X509) -> DotEndpoint {
+        DotEndpoint {
+            connect_addr: addr,
+            addr,
+            host: "127.0.0.1".to_string(),
+            pinned_der: Some(Arc::new(pinned.to_der().expect("der"))),
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_queries_and_reconnects_after_close() {
+        let (cert, key) = self_signed();
+        let addr = mock_dot_server(cert.clone(), key, 2, 1).await;
+        let (response_tx, mut response_rx) = mpsc::unbounded_channel();
+        let endpoint = endpoint_for(addr, &cert);
+        let (query_tx, query_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_dot_worker(endpoint, query_rx, response_tx));
+
+        query_tx.send(vec![1, 2, 3]).expect("send first");
+        let (peer, body) = timeout(Duration::from_secs(5), response_rx.recv())
+            .await
+            .expect("first response")
+            .expect("channel open");
+        assert_eq!(peer, addr);
+        assert_eq!(body, vec![3, 2, 1]);
+
+        // The server closed the first connection; the worker must dial a fresh one.
+        let second = async {
+            loop {
+                query_tx.send(vec![4, 5]).expect("send second");
+                if let Ok(Some(response)) =
+                    timeout(Duration::from_millis(200), response_rx.recv()).await
+                {
+                    return response;
+                }
+            }
+        };
+        let (_, body) = timeout(Duration::from_secs(5), second)
+            .await
+            .expect("second response");
+        assert_eq!(body, vec![5, 4]);
+    }
+
+    #[tokio::test]
+    async fn rejects_unpinned_resolver_certificate() {
+        let (cert, key) = self_signed();
+        let (other, _) = self_signed();
+        let addr = mock_dot_server(cert.clone(), key, 1, 0).await;
+        assert!(connect(&endpoint_for(addr, &other)).await.is_err());
+    }
+}
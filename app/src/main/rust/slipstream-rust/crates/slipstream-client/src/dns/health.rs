@@ -0,0 +1,112 @@
+use std::fmt;
+use tracing::info;
+
+use super::resolver::ResolverState;
+
+/// Consecutive failures after which a resolver is treated as degraded.
+pub(crate) const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+// Smoothing factor of 1/8, as used for TCP SRTT.
+const RTT_EMA_SHIFT: u32 = 3;
+
+/// Per-resolver health: a smoothed RTT and a run of consecutive failures.
+///
+/// A failure is a send error or a full health window without any response to an
+/// outstanding query; any response resets the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ResolverHealth {
+    pub(crate) smoothed_rtt_us: Option<u64>,
+    pub(crate) consecutive_failures: u32,
+    last_rtt_sample_us: u64,
+}
+
+impl ResolverHealth {
+    /// Folds a picoquic RTT sample into the moving average; repeated samples are ignored.
+    pub(crate) fn record_rtt_sample(&mut self, sample_us: u64) {
+        if sample_us == 0 || sample_us == self.last_rtt_sample_us {
+            return;
+        }
+        self.last_rtt_sample_us = sample_us;
+        self.smoothed_rtt_us = Some(match self.smoothed_rtt_us {
+            Some(smoothed) => smoothed - (smoothed >> RTT_EMA_SHIFT) + (sample_us >> RTT_EMA_SHIFT),
+            None => sample_us,
+        });
+    }
+
+    /// Counts a failure and reports whether it just made the resolver degraded.
+    pub(crate) fn record_failure(&mut self) -> bool {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.consecutive_failures == MAX_CONSECUTIVE_FAILURES
+    }
+
+    /// Clears the failure run and reports whether the resolver was degraded.
+    pub(crate) fn record_success(&mut self) -> bool {
+        let was_degraded = self.is_degraded();
+        self.consecutive_failures = 0;
+        was_degraded
+    }
+
+    pub(crate) fn is_degraded(&self) -> bool {
+        self.consecutive_failures >= MAX_CONSECUTIVE_FAILURES
+    }
+}
+
+/// Marks `resolver` as answering: closes its health window and clears its failures.
+pub(crate) fn record_resolver_response(resolver: &mut ResolverState) {
+    resolver.unanswered_since = None;
+    if resolver.health.record_success() {
+        info!("Resolver {} recovered", resolver.addr);
+    }
+}
+
+impl fmt::Display for ResolverHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.smoothed_rtt_us {
+            Some(rtt) => write!(f, "srtt={}ms", rtt / 1000)?,
+            None => write!(f, "srtt=unknown")?,
+        }
+        write!(
+            f,
+            " failures={}{}",
+            self.consecutive_failures,
+            if self.is_degraded() {
+                " (degraded)"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooths_rtt_samples() {
+        let mut health = ResolverHealth::default();
+        health.record_rtt_sample(80_000);
+        assert_eq!(health.smoothed_rtt_us, Some(80_000));
+        health.record_rtt_sample(160_000);
+        assert_eq!(health.smoothed_rtt_us, Some(90_000));
+        health.record_rtt_sample(160_000);
+        assert_eq!(health.smoothed_rtt_us, Some(90_000));
+        health.record_rtt_sample(0);
+        assert_eq!(health.smoothed_rtt_us, Some(90_000));
+    }
+
+    #[test]
+    fn degrades_after_consecutive_failures() {
+        let mut health = ResolverHealth::default();
+        for _ in 1..MAX_CONSECUTIVE_FAILURES {
+            assert!(!health.record_failure());
+        }
+        assert!(!health.is_degraded());
+        assert!(health.record_failure());
+        assert!(health.is_degraded());
+        assert!(!health.record_failure());
+        assert_eq!(health.to_string(), "srtt=unknown failures=4 (degraded)");
+        assert!(health.record_success());
+        assert!(!health.is_degraded());
+        assert!(!health.record_success());
+    }
+}
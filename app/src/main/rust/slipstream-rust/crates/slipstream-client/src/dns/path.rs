@@ -109,7 +109,8 @@ pub(crate) fn add_paths(
 
 pub(crate) fn resolver_mode_to_c(mode: ResolverMode) -> libc::c_int {
     match mode {
-        ResolverMode::Recursive => 1,
+        // DoH paths are polled like recursive resolvers; only the transport differs.
+        ResolverMode::Recursive | ResolverMode::Doh | ResolverMode::Dot => 1,
         ResolverMode::Authoritative => 2,
     }
 }
@@ -1,15 +1,20 @@
 use crate::error::ClientError;
+use crate::network_monitor::TunnelSockets;
+use crate::pacing::TokenBucket;
 use slipstream_core::net::is_transient_udp_error;
-use slipstream_dns::{build_qname, encode_query, QueryParams, CLASS_IN, RR_TXT};
+use slipstream_dns::{build_qname, encode_query, QueryParams, CLASS_IN};
 use slipstream_ffi::picoquic::{
     picoquic_cnx_t, picoquic_current_time, picoquic_prepare_packet_ex, slipstream_request_poll,
 };
 use slipstream_ffi::{ClientConfig, ResolverMode};
 use std::collections::HashMap;
-use tokio::net::UdpSocket as TokioUdpSocket;
 
+use super::auth::auth_domain;
 use super::path::refresh_resolver_path;
+use super::query_id::{randomize_qname_case, QueryIdPool};
+use super::replay::{query_domain, ReplayGuard};
 use super::resolver::{sockaddr_storage_to_socket_addr, ResolverState};
+use super::transport::EncryptedTransport;
 use slipstream_core::normalize_dual_stack_addr;
 
 const AUTHORITATIVE_POLL_TIMEOUT_US: u64 = 5_000_000;
@@ -33,10 +38,13 @@ pub(crate) fn expire_inflight_polls(inflight_poll_ids: &mut HashMap<u16, u64>, n
 #[allow(clippy::too_many_arguments)]
 pub(crate) async fn send_poll_queries(
     cnx: *mut picoquic_cnx_t,
-    udp: &TokioUdpSocket,
+    sockets: &TunnelSockets,
+    encrypted: &EncryptedTransport,
     config: &ClientConfig<'_>,
     local_addr_storage: &mut libc::sockaddr_storage,
-    dns_id: &mut u16,
+    query_ids: &mut QueryIdPool,
+    replay: &mut Option<ReplayGuard>,
+    query_rate: &mut Option<TokenBucket>,
     resolver: &mut ResolverState,
     remaining: &mut usize,
     send_buf: &mut [u8],
@@ -49,6 +57,13 @@ pub(crate) async fn send_poll_queries(
 
     while remaining_count > 0 {
         let current_time = unsafe { picoquic_current_time() };
+        if query_rate
+            .as_mut()
+            .is_some_and(|bucket| !bucket.ready(current_time))
+        {
+            *remaining = remaining_count;
+            break;
+        }
         unsafe {
             slipstream_request_poll(cnx);
         }
@@ -72,7 +87,7 @@ pub(crate) async fn send_poll_queries(
             )
         };
         if ret < 0 {
-            return Err(ClientError::new("Failed preparing poll packet"));
+            return Err(ClientError::quic(ret, "Failed preparing poll packet"));
         }
         if send_length == 0 || addr_to.ss_family == 0 {
             *remaining = remaining_count;
@@ -86,31 +101,52 @@ pub(crate) async fn send_poll_queries(
         resolver.debug.send_bytes = resolver.debug.send_bytes.saturating_add(send_length as u64);
         resolver.debug.polls_sent = resolver.debug.polls_sent.saturating_add(1);
 
-        let poll_id = *dns_id;
-        let qname = build_qname(&send_buf[..send_length], config.domain)
-            .map_err(|err| ClientError::new(err.to_string()))?;
+        let dest = sockaddr_storage_to_socket_addr(&addr_to)?;
+        let dest = normalize_dual_stack_addr(dest);
+        let domain = auth_domain(
+            config.auth_secret,
+            config.auth_mac_len,
+            &send_buf[..send_length],
+            config.domain,
+        )?;
+        let (domain, sequence) = query_domain(replay, &domain);
+        let qname = build_qname(
+            &send_buf[..send_length],
+            &domain,
+            config.encoding,
+            resolver.qname_config(config.max_qname_len),
+        )?;
+        let qname = randomize_qname_case(&qname, config.domain)?;
+        let poll_id = query_ids.allocate(dest, &qname, sequence, true, current_time)?;
         let params = QueryParams {
             id: poll_id,
             qname: &qname,
-            qtype: RR_TXT,
+            qtype: config.qtype,
             qclass: CLASS_IN,
             rd: true,
             cd: false,
             qdcount: 1,
             is_query: true,
+            edns: config.edns,
         };
-        *dns_id = dns_id.wrapping_add(1);
-        let packet = encode_query(&params).map_err(|err| ClientError::new(err.to_string()))?;
+        if let Some(bucket) = query_rate.as_mut() {
+            bucket.record_sent(poll_id, current_time);
+        }
+        let packet = encode_query(&params)?;
 
-        let dest = sockaddr_storage_to_socket_addr(&addr_to)?;
-        let dest = normalize_dual_stack_addr(dest);
-        if let Err(err) = udp.send_to(&packet, dest).await {
+        if encrypted.try_send(dest, &packet) {
+            continue;
+        }
+        resolver.unanswered_since.get_or_insert(current_time);
+        if let Err(err) = sockets.for_source(&addr_from).send_to(&packet, dest).await {
+            query_ids.release(poll_id);
             if is_transient_udp_error(&err) {
+                resolver.send_failed = true;
                 remaining_count = remaining_count.saturating_add(1);
                 *remaining = remaining_count;
                 break;
             }
-            return Err(ClientError::new(err.to_string()));
+            return Err(err.into());
         }
         if resolver.mode == ResolverMode::Authoritative {
             resolver.inflight_poll_ids.insert(poll_id, current_time);
@@ -0,0 +1,424 @@
+use crate::error::ClientError;
+use slipstream_dns::{randomize_domain_case, DnsError};
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use tracing::warn;
+
+/// Lifetime of an unanswered ID before its resolver has answered anything, and the most any
+/// round-trip estimate can stretch it to.
+const DEFAULT_QUERY_TIMEOUT_US: u64 = 5_000_000;
+/// Shortest lifetime of an unanswered ID, however fast its resolver has been.
+const MIN_QUERY_TIMEOUT_US: u64 = 500_000;
+/// Answers from one resolver that echo the query name in another case before its answers are
+/// matched ignoring case.
+const CASE_MISMATCH_LIMIT: u32 = 3;
+/// Random IDs drawn for a slot before giving up on finding one not in flight.
+const ID_ATTEMPTS: usize = 1024;
+
+/// Draws a 16-bit query ID from the OpenSSL CSPRNG.
+pub(crate) fn random_query_id() -> Result<u16, ClientError> {
+    let mut bytes = [0u8; 2];
+    openssl::rand::rand_bytes(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+/// Applies 0x20 case randomization to the `domain` suffix of `qname`.
+pub(crate) fn randomize_qname_case(qname: &str, domain: &str) -> Result<String, ClientError> {
+    // One bit per letter of a maximum-length domain.
+    let mut entropy = [0u8; 32];
+    openssl::rand::rand_bytes(&mut entropy)?;
+    Ok(randomize_domain_case(qname, domain, &entropy))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct InflightQuery {
+    pub(crate) resolver: SocketAddr,
+    /// Query name as sent, including its randomized case.
+    pub(crate) qname: String,
+    pub(crate) sent_at: u64,
+    pub(crate) is_poll: bool,
+    /// Counter of the query's sequence label, with `--ignore-cached-responses`.
+    pub(crate) sequence: Option<u32>,
+    /// Position of the query among those sent to its resolver, which orders its answer.
+    pub(crate) index: u64,
+}
+
+/// Send order of the queries to one resolver.
+#[derive(Default)]
+struct ResolverQueries {
+    next_index: u64,
+    inflight: BTreeSet<u64>,
+}
+
+/// Random query IDs for every query in flight, keyed by ID.
+///
+/// Responses are only dispatched when their ID is in flight to the resolver that answered and
+/// their question echoes the query name byte for byte, 0x20 case included, so off-path
+/// guesses and stale duplicates never reach picoquic. A resolver that rewrites the case of
+/// [`CASE_MISMATCH_LIMIT`] otherwise matching answers is then matched ignoring case, so
+/// resolvers that do not preserve case still work. IDs are expired once left unanswered for
+/// the retransmission timeout of their resolver's measured round trip; callers re-queue
+/// expired polls.
+///
+/// With a `--pipeline-window` above 1, the top bits of each ID carry the query's slot in the
+/// window (its send index modulo the window rounded up to a power of two) and the rest stay
+/// random, so 0x20 case randomization carries more of the anti-spoofing weight.
+#[derive(Default)]
+pub(crate) struct QueryIdPool {
+    inflight: HashMap<u16, InflightQuery>,
+    per_resolver: HashMap<SocketAddr, ResolverQueries>,
+    /// Top bits of each ID given to the window slot.
+    slot_bits: u32,
+    /// Answers per resolver whose question only differed from the query name in case.
+    case_mismatches: HashMap<SocketAddr, u32>,
+    /// Round trips measured from each resolver's answers.
+    rtt: HashMap<SocketAddr, ResolverRtt>,
+}
+
+/// Smoothed round trip to one resolver, estimated as in RFC 6298.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ResolverRtt {
+    srtt_us: u64,
+    rttvar_us: u64,
+}
+
+impl ResolverRtt {
+    fn new(sample_us: u64) -> Self {
+        Self {
+            srtt_us: sample_us,
+            rttvar_us: sample_us / 2,
+        }
+    }
+
+    fn update(&mut self, sample_us: u64) {
+        let deviation = self.srtt_us.abs_diff(sample_us);
+        self.rttvar_us = (3 * self.rttvar_us + deviation) / 4;
+        self.srtt_us = (7 * self.srtt_us + sample_us) / 8;
+    }
+
+    fn timeout_us(&self) -> u64 {
+        self.srtt_us
+            .saturating_add(self.rttvar_us.saturating_mul(4))
+            .clamp(MIN_QUERY_TIMEOUT_US, DEFAULT_QUERY_TIMEOUT_US)
+    }
+}
+
+impl QueryIdPool {
+    /// A pool whose IDs carry a slot in a window of `window` queries per resolver.
+    pub(crate) fn with_window(window: usize) -> Self {
+        Self {
+            slot_bits: if window > 1 {
+                window.next_power_of_two().trailing_zeros()
+            } else {
+                0
+            },
+            ..Self::default()
+        }
+    }
+
+    /// The window slot `id` carries in its top bits.
+    #[cfg(test)]
+    fn slot_of(&self, id: u16) -> u16 {
+        id.checked_shr(16 - self.slot_bits).unwrap_or(0)
+    }
+
+    /// A random ID carrying the slot of send index `index`.
+    fn slotted_id(&self, index: u64) -> Result<u16, ClientError> {
+        let random = random_query_id()?;
+        if self.slot_bits == 0 {
+            return Ok(random);
+        }
+        let slot = (index & ((1 << self.slot_bits) - 1)) as u16;
+        Ok(slot << (16 - self.slot_bits) | random >> self.slot_bits)
+    }
+
+    /// How long a query to `resolver` may go unanswered before its ID expires.
+    fn timeout_us(&self, resolver: SocketAddr) -> u64 {
+        self.rtt
+            .get(&resolver)
+            .map_or(DEFAULT_QUERY_TIMEOUT_US, ResolverRtt::timeout_us)
+    }
+
+    /// Reserves a fresh ID for the next query to `resolver`, skipping IDs already in flight.
+    pub(crate) fn allocate(
+        &mut self,
+        resolver: SocketAddr,
+        qname: &str,
+        sequence: Option<u32>,
+        is_poll: bool,
+        now: u64,
+    ) -> Result<u16, ClientError> {
+        if self.inflight.len() > u16::MAX as usize {
+            return Err(ClientError::Dns(DnsError::new("No free DNS query IDs")));
+        }
+        let index = self
+            .per_resolver
+            .get(&resolver)
+            .map_or(0, |queries| queries.next_index);
+        let mut id = None;
+        for _ in 0..ID_ATTEMPTS {
+            let candidate = self.slotted_id(index)?;
+            if !self.inflight.contains_key(&candidate) {
+                id = Some(candidate);
+                break;
+            }
+        }
+        let Some(id) = id else {
+            return Err(ClientError::Dns(DnsError::new("No free DNS query IDs")));
+        };
+        let queries = self.per_resolver.entry(resolver).or_default();
+        queries.next_index += 1;
+        queries.inflight.insert(index);
+        self.inflight.insert(
+            id,
+            InflightQuery {
+                resolver,
+                qname: qname.to_string(),
+                sent_at: now,
+                is_poll,
+                sequence,
+                index,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Returns an ID whose query was never sent.
+    pub(crate) fn release(&mut self, id: u16) {
+        if let Some(query) = self.inflight.remove(&id) {
+            self.forget(&query);
+        }
+    }
+
+    /// Queries to `resolver` still awaiting an answer.
+    pub(crate) fn inflight_to(&self, resolver: SocketAddr) -> usize {
+        self.per_resolver
+            .get(&resolver)
+            .map_or(0, |queries| queries.inflight.len())
+    }
+
+    /// Send index of the earliest query to `resolver` still awaiting an answer.
+    pub(crate) fn oldest_inflight(&self, resolver: SocketAddr) -> Option<u64> {
+        self.per_resolver
+            .get(&resolver)
+            .and_then(|queries| queries.inflight.first().copied())
+    }
+
+    fn forget(&mut self, query: &InflightQuery) {
+        if let Some(queries) = self.per_resolver.get_mut(&query.resolver) {
+            queries.inflight.remove(&query.index);
+        }
+    }
+
+    /// Removes and returns the query `id` if it was sent to `peer` as `question`, counting the
+    /// answer's round trip at `now` toward `peer`'s timeout.
+    ///
+    /// A mismatched resolver or question leaves the entry in place for the genuine answer.
+    pub(crate) fn take(
+        &mut self,
+        id: u16,
+        peer: SocketAddr,
+        question: Option<&str>,
+        now: u64,
+    ) -> Option<InflightQuery> {
+        let query = self.inflight.get(&id)?;
+        let question = question?;
+        if query.resolver != peer || !question.eq_ignore_ascii_case(&query.qname) {
+            return None;
+        }
+        if question != query.qname && !self.note_case_mismatch(peer) {
+            return None;
+        }
+        let query = self.inflight.remove(&id)?;
+        self.forget(&query);
+        let sample_us = now.saturating_sub(query.sent_at);
+        self.rtt
+            .entry(peer)
+            .and_modify(|rtt| rtt.update(sample_us))
+            .or_insert_with(|| ResolverRtt::new(sample_us));
+        Some(query)
+    }
+
+    /// Counts an answer from `peer` that only rewrote the case of the query name; true once
+    /// `peer` has done so often enough that its answers are matched ignoring case.
+    fn note_case_mismatch(&mut self, peer: SocketAddr) -> bool {
+        let mismatches = self.case_mismatches.entry(peer).or_default();
+        if *mismatches >= CASE_MISMATCH_LIMIT {
+            return true;
+        }
+        *mismatches += 1;
+        if *mismatches < CASE_MISMATCH_LIMIT {
+            return false;
+        }
+        warn!(
+            "Resolver {} does not preserve the query name case; matching its answers ignoring case",
+            peer
+        );
+        true
+    }
+
+    /// Drops IDs older than their resolver's timeout and returns the expired queries.
+    pub(crate) fn expire(&mut self, now: u64) -> Vec<InflightQuery> {
+        if self.inflight.is_empty() {
+            return Vec::new();
+        }
+        let timeouts: HashMap<SocketAddr, u64> = self
+            .per_resolver
+            .keys()
+            .map(|&resolver| (resolver, self.timeout_us(resolver)))
+            .collect();
+        let mut expired = Vec::new();
+        self.inflight.retain(|_, query| {
+            let timeout_us = timeouts
+                .get(&query.resolver)
+                .copied()
+                .unwrap_or(DEFAULT_QUERY_TIMEOUT_US);
+            if query.sent_at.saturating_add(timeout_us) <= now {
+                expired.push(query.clone());
+                false
+            } else {
+                true
+            }
+        });
+        for query in &expired {
+            self.forget(query);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QNAME: &str = "nbswy3dp.TeSt.CoM.";
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([192, 0, 2, 1], port))
+    }
+
+    #[test]
+    fn matches_ids_only_from_the_queried_resolver() {
+        let mut pool = QueryIdPool::default();
+        let id = pool.allocate(addr(53), QNAME, None, true, 10).unwrap();
+        let other = pool.allocate(addr(53), QNAME, None, false, 10).unwrap();
+        assert_ne!(id, other);
+        assert_eq!(pool.take(id, addr(5353), Some(QNAME), 0), None);
+        let query = pool.take(id, addr(53), Some(QNAME), 0).expect("in flight");
+        assert!(query.is_poll);
+        assert_eq!(pool.take(id, addr(53), Some(QNAME), 0), None);
+        assert_eq!(pool.inflight.len(), 1);
+        assert_eq!(pool.inflight_to(addr(53)), 1);
+        pool.release(other);
+        assert_eq!(pool.inflight.len(), 0);
+        assert_eq!(pool.inflight_to(addr(53)), 0);
+    }
+
+    #[test]
+    fn rejects_responses_with_altered_case() {
+        let mut pool = QueryIdPool::default();
+        let id = pool.allocate(addr(53), QNAME, None, false, 10).unwrap();
+        assert_eq!(pool.take(id, addr(53), Some("nbswy3dp.test.com."), 0), None);
+        assert_eq!(pool.take(id, addr(53), Some("NBSWY3DP.TeSt.CoM."), 0), None);
+        assert_eq!(
+            pool.take(id, addr(53), Some("nbswy3dp.other.com."), 0),
+            None
+        );
+        assert_eq!(pool.take(id, addr(53), None, 0), None);
+        assert!(pool.take(id, addr(53), Some(QNAME), 0).is_some());
+
+        let cased = randomize_qname_case("nbswy3dp.test.com.", "test.com").unwrap();
+        assert!(cased.starts_with("nbswy3dp."));
+        assert!(cased.eq_ignore_ascii_case("nbswy3dp.test.com."));
+    }
+
+    #[test]
+    fn resolvers_that_keep_rewriting_case_are_matched_ignoring_case() {
+        let mut pool = QueryIdPool::default();
+        let lowered = QNAME.to_ascii_lowercase();
+        for _ in 1..CASE_MISMATCH_LIMIT {
+            let id = pool.allocate(addr(53), QNAME, None, false, 10).unwrap();
+            assert_eq!(pool.take(id, addr(53), Some(&lowered), 0), None);
+            pool.release(id);
+        }
+        let id = pool.allocate(addr(53), QNAME, None, false, 10).unwrap();
+        assert!(pool.take(id, addr(53), Some(&lowered), 0).is_some());
+        let id = pool.allocate(addr(53), QNAME, None, false, 10).unwrap();
+        assert!(pool.take(id, addr(53), Some(&lowered), 0).is_some());
+
+        let id = pool.allocate(addr(54), QNAME, None, false, 10).unwrap();
+        assert_eq!(
+            pool.take(id, addr(54), Some(&lowered), 0),
+            None,
+            "other resolvers keep the exact match"
+        );
+    }
+
+    #[test]
+    fn expires_after_the_round_trip_timeout_of_each_resolver() {
+        let mut pool = QueryIdPool::default();
+        let answered = pool.allocate(addr(53), QNAME, None, false, 0).unwrap();
+        assert!(pool
+            .take(answered, addr(53), Some(QNAME), 100_000)
+            .is_some());
+        // 100 ms with 50 ms of variance gives 300 ms, raised to the floor.
+        assert_eq!(pool.timeout_us(addr(53)), MIN_QUERY_TIMEOUT_US);
+        assert_eq!(pool.timeout_us(addr(54)), DEFAULT_QUERY_TIMEOUT_US);
+
+        let first = pool
+            .allocate(addr(53), QNAME, None, true, 1_000_000)
+            .unwrap();
+        pool.allocate(addr(54), QNAME, None, false, 1_000_000)
+            .unwrap();
+        assert!(pool.expire(1_499_999).is_empty());
+        let expired = pool.expire(1_500_000);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].resolver, addr(53));
+        assert_eq!(pool.take(first, addr(53), Some(QNAME), 1_500_000), None);
+        assert_eq!(pool.inflight_to(addr(53)), 0);
+        assert_eq!(pool.inflight_to(addr(54)), 1);
+        assert!(pool.expire(5_999_999).is_empty());
+        assert_eq!(pool.expire(6_000_000).len(), 1);
+        assert_eq!(pool.inflight.len(), 0);
+    }
+
+    #[test]
+    fn ids_carry_their_window_slot_and_queries_keep_send_order() {
+        let mut pool = QueryIdPool::with_window(4);
+        assert_eq!(pool.slot_bits, 2);
+        let ids: Vec<u16> = (0..6)
+            .map(|_| pool.allocate(addr(53), QNAME, None, false, 0).unwrap())
+            .collect();
+        let slots: Vec<u16> = ids.iter().map(|&id| pool.slot_of(id)).collect();
+        assert_eq!(slots, vec![0, 1, 2, 3, 0, 1]);
+        assert_eq!(pool.oldest_inflight(addr(53)), Some(0));
+
+        let second = pool.take(ids[1], addr(53), Some(QNAME), 0).unwrap();
+        assert_eq!(second.index, 1);
+        assert_eq!(pool.oldest_inflight(addr(53)), Some(0));
+        pool.release(ids[0]);
+        assert_eq!(pool.oldest_inflight(addr(53)), Some(2));
+        assert_eq!(pool.oldest_inflight(addr(54)), None);
+
+        assert_eq!(QueryIdPool::with_window(64).slot_bits, 6);
+        assert_eq!(QueryIdPool::with_window(5).slot_bits, 3);
+        let unslotted = QueryIdPool::with_window(1);
+        assert_eq!(unslotted.slot_of(0xffff), 0);
+    }
+
+    #[test]
+    fn round_trip_timeouts_follow_the_samples_within_bounds() {
+        let mut rtt = ResolverRtt::new(400_000);
+        assert_eq!(rtt.timeout_us(), 1_200_000);
+        for _ in 0..50 {
+            rtt.update(400_000);
+        }
+        assert_eq!(rtt.timeout_us(), MIN_QUERY_TIMEOUT_US);
+        rtt.update(4_000_000);
+        assert!(rtt.timeout_us() > 1_200_000);
+        assert_eq!(
+            ResolverRtt::new(3_000_000).timeout_us(),
+            DEFAULT_QUERY_TIMEOUT_US
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+
+/// A decoded response held back until the queries sent before it are settled.
+pub(crate) struct HeldResponse {
+    pub(crate) payload: Vec<u8>,
+    /// Port of the socket the response came in on; see `DnsResponseContext::local_port`.
+    pub(crate) local_port: Option<u16>,
+}
+
+/// Hands answers to picoquic in the order their queries went out, per resolver.
+///
+/// Answers are keyed by their query's send index. One is held while an earlier query to the
+/// same resolver is still in flight, and released once that query is answered, expired, or
+/// never sent. At most `window` answers are held per resolver; past that the oldest go out
+/// anyway, so a lost query delays the ones after it by no more than a window's worth of
+/// answers. A window of 0 disables reordering.
+pub(crate) struct ReorderBuffer<T> {
+    window: usize,
+    held: HashMap<SocketAddr, BTreeMap<u64, T>>,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub(crate) fn new(window: usize) -> Self {
+        Self {
+            window,
+            held: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// Resolvers with answers held back.
+    pub(crate) fn holding(&self) -> Vec<SocketAddr> {
+        self.held.keys().copied().collect()
+    }
+
+    /// Takes the answer to query `index` to `resolver` and returns the answers now due, in
+    /// send order; `oldest_inflight` is the earliest query to `resolver` still unanswered.
+    pub(crate) fn push(
+        &mut self,
+        resolver: SocketAddr,
+        index: u64,
+        answer: T,
+        oldest_inflight: Option<u64>,
+    ) -> Vec<T> {
+        if self.window == 0 {
+            return vec![answer];
+        }
+        self.held.entry(resolver).or_default().insert(index, answer);
+        self.release(resolver, oldest_inflight)
+    }
+
+    /// Returns the answers to `resolver` no earlier query is still in flight for, plus the
+    /// oldest of any beyond the window, in send order.
+    pub(crate) fn release(&mut self, resolver: SocketAddr, oldest_inflight: Option<u64>) -> Vec<T> {
+        let Some(held) = self.held.get_mut(&resolver) else {
+            return Vec::new();
+        };
+        let mut due = Vec::new();
+        while let Some(&index) = held.keys().next() {
+            let settled = oldest_inflight.is_none_or(|oldest| index < oldest);
+            if !settled && held.len() <= self.window {
+                break;
+            }
+            due.extend(held.remove(&index));
+        }
+        if held.is_empty() {
+            self.held.remove(&resolver);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver() -> SocketAddr {
+        SocketAddr::from(([192, 0, 2, 1], 53))
+    }
+
+    #[test]
+    fn holds_answers_until_earlier_queries_settle() {
+        let mut buffer = ReorderBuffer::new(4);
+        // Queries 0..3 are in flight; 2 and 1 are answered first.
+        assert!(buffer.push(resolver(), 2, "c", Some(0)).is_empty());
+        assert!(buffer.push(resolver(), 1, "b", Some(0)).is_empty());
+        assert_eq!(
+            buffer.push(resolver(), 0, "a", Some(3)),
+            vec!["a", "b", "c"]
+        );
+        assert!(buffer.is_empty());
+
+        // Query 3 expires unanswered, which releases the answer to 4.
+        assert!(buffer.push(resolver(), 4, "e", Some(3)).is_empty());
+        assert_eq!(buffer.holding(), vec![resolver()]);
+        assert_eq!(buffer.release(resolver(), None), vec!["e"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn releases_the_oldest_past_the_window() {
+        let mut buffer = ReorderBuffer::new(2);
+        assert!(buffer.push(resolver(), 1, 1, Some(0)).is_empty());
+        assert!(buffer.push(resolver(), 2, 2, Some(0)).is_empty());
+        assert_eq!(buffer.push(resolver(), 3, 3, Some(0)), vec![1]);
+        // A late answer behind the released ones goes straight out.
+        assert_eq!(buffer.push(resolver(), 0, 0, Some(4)), vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn a_zero_window_passes_answers_through() {
+        let mut buffer = ReorderBuffer::new(0);
+        assert_eq!(buffer.push(resolver(), 5, 5, Some(0)), vec![5]);
+        assert!(buffer.is_empty());
+    }
+}
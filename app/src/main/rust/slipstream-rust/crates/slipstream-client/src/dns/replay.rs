@@ -0,0 +1,210 @@
+use slipstream_dns::sequence_label;
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tracing::warn;
+
+const REPLAY_WARN_INTERVAL_US: u64 = 1_000_000;
+/// Counters each resolver's window remembers behind its newest accepted one.
+const REPLAY_WINDOW: u32 = 1024;
+const REPLAY_WINDOW_WORDS: usize = REPLAY_WINDOW as usize / 64;
+
+/// Sequence counters for `--ignore-cached-responses`.
+///
+/// Every query name carries the next counter in a label ahead of the domain, and a response is
+/// accepted once per counter, per resolver: each resolver has a sliding window over the last
+/// [`REPLAY_WINDOW`] counters behind the newest one it answered, as in IPsec anti-replay. A
+/// resolver replaying a cached answer, or answering a counter too old for its window, is
+/// discarded, while answers merely arriving out of order still get through.
+pub(crate) struct ReplayGuard {
+    next_sequence: u32,
+    windows: HashMap<SocketAddr, ReplayWindow>,
+    replays: u64,
+    last_warn_at: u64,
+}
+
+/// The counters one resolver has answered: the newest, and a bitmap of those behind it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ReplayWindow {
+    newest: u32,
+    /// Bit `n` is set once counter `newest - n` was accepted.
+    seen: [u64; REPLAY_WINDOW_WORDS],
+}
+
+impl ReplayWindow {
+    fn new(sequence: u32) -> Self {
+        let mut seen = [0u64; REPLAY_WINDOW_WORDS];
+        seen[0] = 1;
+        Self {
+            newest: sequence,
+            seen,
+        }
+    }
+
+    /// Marks `sequence` as seen; false if it already was or has left the window.
+    ///
+    /// Counters compare in serial number arithmetic, so wrapping past `u32::MAX` is fine.
+    fn accept(&mut self, sequence: u32) -> bool {
+        let ahead = sequence.wrapping_sub(self.newest) as i32;
+        if ahead > 0 {
+            self.shift(ahead as u32);
+            self.newest = sequence;
+            self.seen[0] |= 1;
+            return true;
+        }
+        let behind = self.newest.wrapping_sub(sequence);
+        if behind >= REPLAY_WINDOW {
+            return false;
+        }
+        let (word, bit) = ((behind / 64) as usize, behind % 64);
+        if self.seen[word] & (1 << bit) != 0 {
+            return false;
+        }
+        self.seen[word] |= 1 << bit;
+        true
+    }
+
+    // Moves every bit `by` places further behind, dropping those that leave the window.
+    fn shift(&mut self, by: u32) {
+        if by >= REPLAY_WINDOW {
+            self.seen = [0; REPLAY_WINDOW_WORDS];
+            return;
+        }
+        let (words, bits) = ((by / 64) as usize, by % 64);
+        for index in (0..REPLAY_WINDOW_WORDS).rev() {
+            let mut word = match index.checked_sub(words) {
+                Some(source) => self.seen[source] << bits,
+                None => 0,
+            };
+            if bits > 0 {
+                if let Some(carry) = index.checked_sub(words + 1) {
+                    word |= self.seen[carry] >> (64 - bits);
+                }
+            }
+            self.seen[index] = word;
+        }
+    }
+}
+
+impl ReplayGuard {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            windows: HashMap::new(),
+            replays: 0,
+            last_warn_at: 0,
+        }
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        sequence
+    }
+
+    /// Records `sequence` as answered by `resolver`, unless that resolver already answered it
+    /// or it is too old for the resolver's window.
+    pub(crate) fn accept(&mut self, resolver: SocketAddr, sequence: u32, now: u64) -> bool {
+        let accepted = match self.windows.entry(resolver) {
+            Entry::Occupied(mut window) => window.get_mut().accept(sequence),
+            Entry::Vacant(slot) => {
+                slot.insert(ReplayWindow::new(sequence));
+                true
+            }
+        };
+        if !accepted {
+            self.replays = self.replays.saturating_add(1);
+            if now.saturating_sub(self.last_warn_at) >= REPLAY_WARN_INTERVAL_US {
+                warn!(
+                    "Discarded replayed DNS response from {}: sequence {} was already answered or is too old ({} replays so far)",
+                    resolver, sequence, self.replays
+                );
+                self.last_warn_at = now;
+            }
+        }
+        accepted
+    }
+}
+
+/// Returns the domain to build the next query name under, prefixed with a fresh sequence label
+/// when `replay` is enabled, and the counter it carries.
+pub(crate) fn query_domain<'a>(
+    replay: &mut Option<ReplayGuard>,
+    domain: &'a str,
+) -> (Cow<'a, str>, Option<u32>) {
+    match replay.as_mut() {
+        Some(guard) => {
+            let sequence = guard.next_sequence();
+            (
+                Cow::Owned(format!("{}.{}", sequence_label(sequence), domain)),
+                Some(sequence),
+            )
+        }
+        None => (Cow::Borrowed(domain), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([192, 0, 2, 1], port))
+    }
+
+    #[test]
+    fn accepts_each_sequence_once_per_resolver() {
+        let mut replay = Some(ReplayGuard::new());
+        let (domain, first) = query_domain(&mut replay, "test.com");
+        assert_eq!(domain, "900000000.test.com");
+        let (_, second) = query_domain(&mut replay, "test.com");
+        let (first, second) = (first.unwrap(), second.unwrap());
+        let guard = replay.as_mut().unwrap();
+        assert!(guard.accept(addr(53), second, 0));
+        // Out of order, but not yet seen.
+        assert!(guard.accept(addr(53), first, 1));
+        assert!(!guard.accept(addr(53), first, 2));
+        assert!(!guard.accept(addr(53), second, 3));
+        assert_eq!(guard.replays, 2);
+        // Another resolver keeps its own window.
+        assert!(guard.accept(addr(54), first, 4));
+
+        let mut guard = ReplayGuard::new();
+        assert!(guard.accept(addr(53), u32::MAX, 0));
+        assert!(guard.accept(addr(53), 0, 0));
+        assert!(!guard.accept(addr(53), u32::MAX, 0));
+
+        let mut disabled = None;
+        assert_eq!(
+            query_domain(&mut disabled, "test.com"),
+            (Cow::Borrowed("test.com"), None)
+        );
+    }
+
+    #[test]
+    fn the_window_slides_past_old_sequences() {
+        let mut window = ReplayWindow::new(0);
+        assert!(window.accept(70));
+        assert!(window.accept(3));
+        assert!(!window.accept(3));
+        assert!(!window.accept(0));
+        assert!(window.accept(69));
+
+        // Bits keep their place across word boundaries as the window moves.
+        assert!(window.accept(200));
+        assert!(!window.accept(70));
+        assert!(!window.accept(3));
+        assert!(window.accept(4));
+
+        assert!(window.accept(REPLAY_WINDOW + 4));
+        assert!(!window.accept(4), "left the window");
+        assert!(!window.accept(3));
+        assert!(window.accept(5));
+        assert!(!window.accept(200));
+
+        assert!(window.accept(u32::MAX / 2));
+        assert_eq!(window.seen[1..], [0; REPLAY_WINDOW_WORDS - 1]);
+        assert_eq!(window.seen[0], 1);
+    }
+}
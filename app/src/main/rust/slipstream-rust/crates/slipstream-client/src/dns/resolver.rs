@@ -1,12 +1,14 @@
 use crate::error::ClientError;
 use crate::pacing::{PacingBudgetSnapshot, PacingPollBudget};
 use slipstream_core::{normalize_dual_stack_addr, resolve_host_port};
+use slipstream_dns::QnameConfig;
 use slipstream_ffi::{socket_addr_to_storage, ResolverMode, ResolverSpec};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use tracing::warn;
 
 use super::debug::DebugMetrics;
+use super::health::ResolverHealth;
 
 pub(crate) struct ResolverState {
     pub(crate) addr: SocketAddr,
@@ -22,10 +24,26 @@ pub(crate) struct ResolverState {
     pub(crate) inflight_poll_ids: HashMap<u16, u64>,
     pub(crate) pacing_budget: Option<PacingPollBudget>,
     pub(crate) last_pacing_snapshot: Option<PacingBudgetSnapshot>,
+    /// Start of the current health window with queries still waiting for any response.
+    pub(crate) unanswered_since: Option<u64>,
+    pub(crate) send_failed: bool,
+    pub(crate) health: ResolverHealth,
+    /// Largest query name shape the resolver answered when probed; `None` uses the default.
+    pub(crate) capacity_hint: Option<QnameConfig>,
+    /// Responses that carried a QUIC payload; error rcodes and empty answers are not counted.
+    pub(crate) answered_polls: u64,
     pub(crate) debug: DebugMetrics,
 }
 
 impl ResolverState {
+    /// Query name shape for this resolver: its probed capacity, capped at `max_name_len`.
+    pub(crate) fn qname_config(&self, max_name_len: u8) -> QnameConfig {
+        QnameConfig {
+            max_name_len,
+            ..self.capacity_hint.unwrap_or_default()
+        }
+    }
+
     pub(crate) fn label(&self) -> String {
         format!(
             "path_id={} unique_id={:?} resolver={} mode={:?}",
@@ -42,11 +60,10 @@ pub(crate) fn resolve_resolvers(
     let mut resolved = Vec::with_capacity(resolvers.len());
     let mut seen = HashMap::new();
     for (idx, resolver) in resolvers.iter().enumerate() {
-        let addr = resolve_host_port(&resolver.resolver)
-            .map_err(|err| ClientError::new(err.to_string()))?;
+        let addr = resolve_host_port(&resolver.resolver)?;
         let addr = normalize_dual_stack_addr(addr);
         if let Some(existing_mode) = seen.get(&addr) {
-            return Err(ClientError::new(format!(
+            return Err(ClientError::config(format!(
                 "Duplicate resolver address {} (modes: {:?} and {:?})",
                 addr, existing_mode, resolver.mode
             )));
@@ -67,9 +84,14 @@ pub(crate) fn resolve_resolvers(
             inflight_poll_ids: HashMap::new(),
             pacing_budget: match resolver.mode {
                 ResolverMode::Authoritative => Some(PacingPollBudget::new(mtu)),
-                ResolverMode::Recursive => None,
+                ResolverMode::Recursive | ResolverMode::Doh | ResolverMode::Dot => None,
             },
             last_pacing_snapshot: None,
+            unanswered_since: None,
+            send_failed: false,
+            health: ResolverHealth::default(),
+            capacity_hint: None,
+            answered_polls: 0,
             debug: DebugMetrics::new(debug_poll),
         });
     }
@@ -88,6 +110,7 @@ pub(crate) fn reset_resolver_path(resolver: &mut ResolverState) {
     resolver.pending_polls = 0;
     resolver.inflight_poll_ids.clear();
     resolver.last_pacing_snapshot = None;
+    resolver.unanswered_since = None;
     resolver.probe_attempts = 0;
     resolver.next_probe_at = 0;
 }
@@ -95,7 +118,7 @@ pub(crate) fn reset_resolver_path(resolver: &mut ResolverState) {
 pub(crate) fn sockaddr_storage_to_socket_addr(
     storage: &libc::sockaddr_storage,
 ) -> Result<SocketAddr, ClientError> {
-    slipstream_ffi::sockaddr_storage_to_socket_addr(storage).map_err(ClientError::new)
+    slipstream_ffi::sockaddr_storage_to_socket_addr(storage).map_err(ClientError::Address)
 }
 
 #[cfg(test)]
@@ -112,16 +135,20 @@ mod tests {
                     host: "127.0.0.1".to_string(),
                     port: 8853,
                     family: AddressFamily::V4,
+                    zone: None,
                 },
                 mode: ResolverMode::Recursive,
+                doh_path: None,
             },
             ResolverSpec {
                 resolver: HostPort {
                     host: "127.0.0.1".to_string(),
                     port: 8853,
                     family: AddressFamily::V4,
+                    zone: None,
                 },
                 mode: ResolverMode::Authoritative,
+                doh_path: None,
             },
         ];
 
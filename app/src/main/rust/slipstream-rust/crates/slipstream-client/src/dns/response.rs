@@ -1,13 +1,19 @@
 use crate::error::ClientError;
-use slipstream_dns::decode_response;
+use crate::pacing::TokenBucket;
+use slipstream_dns::{decode_response, response_question_name};
 use slipstream_ffi::picoquic::{
     picoquic_cnx_t, picoquic_current_time, picoquic_incoming_packet_ex, picoquic_quic_t,
     PICOQUIC_PACKET_LOOP_RECV_MAX,
 };
 use slipstream_ffi::{socket_addr_to_storage, ResolverMode};
 use std::net::SocketAddr;
+use tracing::debug;
 
-use super::resolver::ResolverState;
+use super::health::record_resolver_response;
+use super::query_id::QueryIdPool;
+use super::reorder::{HeldResponse, ReorderBuffer};
+use super::replay::ReplayGuard;
+use super::resolver::{sockaddr_storage_to_socket_addr, ResolverState};
 use slipstream_core::normalize_dual_stack_addr;
 
 const MAX_POLL_BURST: usize = PICOQUIC_PACKET_LOOP_RECV_MAX;
@@ -15,7 +21,14 @@ const MAX_POLL_BURST: usize = PICOQUIC_PACKET_LOOP_RECV_MAX;
 pub(crate) struct DnsResponseContext<'a> {
     pub(crate) quic: *mut picoquic_quic_t,
     pub(crate) local_addr_storage: &'a libc::sockaddr_storage,
+    /// Port of the UDP socket the response came in on; while a rebind is probed the
+    /// resolver's last source port may belong to the other socket.
+    pub(crate) local_port: Option<u16>,
     pub(crate) resolvers: &'a mut [ResolverState],
+    pub(crate) query_rate: Option<&'a mut TokenBucket>,
+    pub(crate) query_ids: &'a mut QueryIdPool,
+    pub(crate) replay: Option<&'a mut ReplayGuard>,
+    pub(crate) reorder: &'a mut ReorderBuffer<HeldResponse>,
 }
 
 pub(crate) fn handle_dns_response(
@@ -24,71 +37,131 @@ pub(crate) fn handle_dns_response(
     ctx: &mut DnsResponseContext<'_>,
 ) -> Result<(), ClientError> {
     let peer = normalize_dual_stack_addr(peer);
-    let response_id = dns_response_id(buf);
-    if let Some(payload) = decode_response(buf) {
-        let resolver_index = ctx
-            .resolvers
-            .iter()
-            .position(|resolver| resolver.addr == peer);
-        let mut peer_storage = socket_addr_to_storage(peer);
-        let mut local_storage = if let Some(index) = resolver_index {
-            ctx.resolvers[index]
-                .local_addr_storage
-                .as_ref()
-                .map(|storage| unsafe { std::ptr::read(storage) })
-                .unwrap_or_else(|| unsafe { std::ptr::read(ctx.local_addr_storage) })
-        } else {
-            unsafe { std::ptr::read(ctx.local_addr_storage) }
-        };
-        let mut first_cnx: *mut picoquic_cnx_t = std::ptr::null_mut();
-        let mut first_path: libc::c_int = -1;
-        let current_time = unsafe { picoquic_current_time() };
-        let ret = unsafe {
-            picoquic_incoming_packet_ex(
-                ctx.quic,
-                payload.as_ptr() as *mut u8,
-                payload.len(),
-                &mut peer_storage as *mut _ as *mut libc::sockaddr,
-                &mut local_storage as *mut _ as *mut libc::sockaddr,
-                0,
-                0,
-                &mut first_cnx,
-                &mut first_path,
-                current_time,
-            )
-        };
-        if ret < 0 {
-            return Err(ClientError::new("Failed processing inbound QUIC packet"));
+    let Some(response_id) = dns_response_id(buf) else {
+        return Ok(());
+    };
+    let question = response_question_name(buf);
+    let now = unsafe { picoquic_current_time() };
+    let Some(query) = ctx
+        .query_ids
+        .take(response_id, peer, question.as_deref(), now)
+    else {
+        debug!(
+            "Dropping DNS response id={} from {}: no matching query in flight",
+            response_id, peer
+        );
+        return Ok(());
+    };
+    if let (Some(replay), Some(sequence)) = (ctx.replay.as_deref_mut(), query.sequence) {
+        if !replay.accept(peer, sequence, now) {
+            return Ok(());
         }
-        let resolver = if let Some(resolver) = find_resolver_by_path_id(ctx.resolvers, first_path) {
-            Some(resolver)
-        } else {
-            find_resolver_by_addr(ctx.resolvers, peer)
-        };
-        if let Some(resolver) = resolver {
-            if first_path >= 0 && resolver.path_id != first_path {
-                resolver.path_id = first_path;
-                resolver.added = true;
-            }
-            resolver.debug.dns_responses = resolver.debug.dns_responses.saturating_add(1);
-            if let Some(response_id) = response_id {
-                if resolver.mode == ResolverMode::Authoritative {
-                    resolver.inflight_poll_ids.remove(&response_id);
-                }
-            }
+    }
+    if let Some(bucket) = ctx.query_rate.as_deref_mut() {
+        bucket.record_answered(response_id);
+    }
+    let payload = decode_response(buf);
+    if let Some(resolver) = find_resolver_by_addr(ctx.resolvers, peer) {
+        resolver.debug.dns_responses = resolver.debug.dns_responses.saturating_add(1);
+        record_resolver_response(resolver);
+        if resolver.mode == ResolverMode::Authoritative {
+            resolver.inflight_poll_ids.remove(&response_id);
+        }
+        if payload.is_some() {
+            resolver.answered_polls = resolver.answered_polls.saturating_add(1);
             // Both modes: each response triggers a demand-driven poll.
             // For authoritative mode this provides a floor so that the poll
             // rate never drops below the actual response rate, even when BBR's
             // pacing estimate is conservative.
-            resolver.pending_polls =
-                resolver.pending_polls.saturating_add(1).min(MAX_POLL_BURST);
+            resolver.pending_polls = resolver.pending_polls.saturating_add(1).min(MAX_POLL_BURST);
+        }
+    }
+    let oldest_inflight = ctx.query_ids.oldest_inflight(peer);
+    let due = match payload {
+        Some(payload) => {
+            let held = HeldResponse {
+                payload,
+                local_port: ctx.local_port,
+            };
+            ctx.reorder.push(peer, query.index, held, oldest_inflight)
+        }
+        // An answer without a payload still settles its place in the order.
+        None => ctx.reorder.release(peer, oldest_inflight),
+    };
+    for held in due {
+        deliver_response(&held, peer, ctx)?;
+    }
+    Ok(())
+}
+
+/// Delivers the answers held back for queries that have since expired or were never sent.
+pub(crate) fn release_held_responses(ctx: &mut DnsResponseContext<'_>) -> Result<(), ClientError> {
+    for peer in ctx.reorder.holding() {
+        let oldest_inflight = ctx.query_ids.oldest_inflight(peer);
+        for held in ctx.reorder.release(peer, oldest_inflight) {
+            deliver_response(&held, peer, ctx)?;
         }
-    } else if let Some(response_id) = response_id {
-        if let Some(resolver) = find_resolver_by_addr(ctx.resolvers, peer) {
-            resolver.debug.dns_responses = resolver.debug.dns_responses.saturating_add(1);
-            if resolver.mode == ResolverMode::Authoritative {
-                resolver.inflight_poll_ids.remove(&response_id);
-            }
+    }
+    Ok(())
+}
+
+fn deliver_response(
+    held: &HeldResponse,
+    peer: SocketAddr,
+    ctx: &mut DnsResponseContext<'_>,
+) -> Result<(), ClientError> {
+    let resolver_index = ctx
+        .resolvers
+        .iter()
+        .position(|resolver| resolver.addr == peer);
+    let mut peer_storage = socket_addr_to_storage(peer);
+    let mut local_storage = if let Some(index) = resolver_index {
+        ctx.resolvers[index]
+            .local_addr_storage
+            .as_ref()
+            .map(|storage| unsafe { std::ptr::read(storage) })
+            .unwrap_or_else(|| unsafe { std::ptr::read(ctx.local_addr_storage) })
+    } else {
+        unsafe { std::ptr::read(ctx.local_addr_storage) }
+    };
+    if let Some(port) = held.local_port {
+        if let Ok(mut local) = sockaddr_storage_to_socket_addr(&local_storage) {
+            local.set_port(port);
+            local_storage = socket_addr_to_storage(local);
+        }
+    }
+    let mut first_cnx: *mut picoquic_cnx_t = std::ptr::null_mut();
+    let mut first_path: libc::c_int = -1;
+    let current_time = unsafe { picoquic_current_time() };
+    let ret = unsafe {
+        picoquic_incoming_packet_ex(
+            ctx.quic,
+            held.payload.as_ptr() as *mut u8,
+            held.payload.len(),
+            &mut peer_storage as *mut _ as *mut libc::sockaddr,
+            &mut local_storage as *mut _ as *mut libc::sockaddr,
+            0,
+            0,
+            &mut first_cnx,
+            &mut first_path,
+            current_time,
+        )
+    };
+    if ret < 0 {
+        return Err(ClientError::quic(
+            ret,
+            "Failed processing inbound QUIC packet",
+        ));
+    }
+    let resolver = if let Some(resolver) = find_resolver_by_path_id(ctx.resolvers, first_path) {
+        Some(resolver)
+    } else {
+        find_resolver_by_addr(ctx.resolvers, peer)
+    };
+    if let Some(resolver) = resolver {
+        if first_path >= 0 && resolver.path_id != first_path {
+            resolver.path_id = first_path;
+            resolver.added = true;
         }
     }
     Ok(())
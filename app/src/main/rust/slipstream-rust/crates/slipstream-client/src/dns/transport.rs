@@ -0,0 +1,100 @@
+use crate::error::ClientError;
+use crate::pinning::load_pinned_cert;
+use slipstream_core::{normalize_dual_stack_addr, resolve_host_port};
+use slipstream_ffi::{ResolverMode, ResolverSpec};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use super::doh::spawn_doh_worker;
+use super::dot::spawn_dot_worker;
+
+/// DNS responses received over DoH/DoT, tagged with the resolver address they belong to.
+pub(crate) type EncryptedResponse = (SocketAddr, Vec<u8>);
+
+/// Routes outbound DNS queries for DoH and DoT resolvers to per-endpoint workers.
+///
+/// Workers outlive individual QUIC connections so the TLS connection is reused across
+/// polls and reconnects. Resolvers in UDP modes are not tracked here and keep using the
+/// shared UDP socket.
+pub(crate) struct EncryptedTransport {
+    senders: HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>,
+    _response_tx: mpsc::UnboundedSender<EncryptedResponse>,
+}
+
+impl EncryptedTransport {
+    pub(crate) fn spawn(
+        resolvers: &[ResolverSpec],
+        resolver_cert: Option<&str>,
+        response_tx: mpsc::UnboundedSender<EncryptedResponse>,
+    ) -> Result<Self, ClientError> {
+        let pinned_der = match resolver_cert {
+            Some(path) => {
+                let mut certs = load_pinned_cert(path)?;
+                if certs.len() != 1 {
+                    return Err(ClientError::config(
+                        "Resolver cert must contain exactly one certificate",
+                    ));
+                }
+                let (der, _) = certs.remove(0);
+                Some(Arc::new(der))
+            }
+            None => None,
+        };
+        let mut senders = HashMap::new();
+        for resolver in resolvers {
+            if !matches!(resolver.mode, ResolverMode::Doh | ResolverMode::Dot) {
+                continue;
+            }
+            let connect_addr = resolve_host_port(&resolver.resolver)?;
+            // Keyed like ResolverState::addr so picoquic path addresses match.
+            let addr = normalize_dual_stack_addr(connect_addr);
+            let query_tx = if resolver.mode == ResolverMode::Doh {
+                spawn_doh_worker(resolver, connect_addr, addr, response_tx.clone())
+            } else {
+                spawn_dot_worker(
+                    resolver,
+                    connect_addr,
+                    addr,
+                    pinned_der.clone(),
+                    response_tx.clone(),
+                )
+            };
+            senders.insert(addr, query_tx);
+        }
+        Ok(Self {
+            senders,
+            _response_tx: response_tx,
+        })
+    }
+
+    /// Queues `packet` for `dest` if it is a DoH/DoT resolver; returns false for UDP resolvers.
+    pub(crate) fn try_send(&self, dest: SocketAddr, packet: &[u8]) -> bool {
+        let Some(sender) = self.senders.get(&dest) else {
+            return false;
+        };
+        if sender.send(packet.to_vec()).is_err() {
+            warn!("Encrypted DNS worker for {} is gone; dropping query", dest);
+        }
+        true
+    }
+}
+
+pub(super) async fn connect_tcp(addr: SocketAddr) -> std::io::Result<TcpStream> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    // Keep DoH/DoT traffic outside the VPN on Android, same as the UDP resolver socket.
+    #[cfg(target_os = "android")]
+    {
+        use std::os::unix::io::AsRawFd;
+        if !crate::android::protect_socket(socket.as_raw_fd()) {
+            return Err(std::io::Error::other("failed to protect resolver socket"));
+        }
+    }
+    socket.connect(addr).await
+}
@@ -1,22 +1,115 @@
-use std::fmt;
+use slipstream_core::ConfigError;
+use slipstream_dns::DnsError;
+use std::{fmt, io};
 
 #[derive(Debug)]
-pub struct ClientError {
-    message: String,
+pub enum ClientError {
+    /// Invalid configuration: addresses, domain, certificates, or other settings.
+    Config(ConfigError),
+    /// A DNS message could not be built or parsed.
+    Dns(DnsError),
+    /// picoquic failed; `code` is its return value, or 0 when it reported none.
+    Quic {
+        code: i32,
+        message: String,
+    },
+    Io(io::Error),
+    /// picoquic handed over a socket address of a family the client cannot use.
+    Address(String),
+    /// A TLS or crypto library error and the source location that raised it.
+    Tls {
+        file: String,
+        line: i32,
+        description: String,
+    },
+    /// The TLS handshake failed: certificate rejections and picoquic crypto errors, in order.
+    CryptoErrors(Vec<String>),
+    /// The client stopped because a shutdown was requested.
+    Shutdown,
 }
 
 impl ClientError {
-    pub(crate) fn new(message: impl Into<String>) -> Self {
-        Self {
+    pub(crate) fn config(message: impl Into<String>) -> Self {
+        Self::Config(ConfigError::new(message))
+    }
+
+    pub(crate) fn quic(code: i32, message: impl Into<String>) -> Self {
+        Self::Quic {
+            code,
             message: message.into(),
         }
     }
+
+    /// Wraps `err` with `context`, keeping its kind.
+    pub(crate) fn io(err: io::Error, context: impl fmt::Display) -> Self {
+        Self::Io(io::Error::new(err.kind(), format!("{}: {}", context, err)))
+    }
 }
 
 impl fmt::Display for ClientError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message)
+        match self {
+            Self::Config(err) => write!(f, "{}", err),
+            Self::Dns(err) => write!(f, "{}", err),
+            Self::Quic { code: 0, message } => write!(f, "{}", message),
+            Self::Quic { code, message } => write!(f, "{} (error {})", message, code),
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Address(message) => write!(f, "{}", message),
+            Self::Tls {
+                file,
+                line,
+                description,
+            } => write!(f, "TLS error at {}:{}: {}", file, line, description),
+            Self::CryptoErrors(errors) => {
+                write!(f, "TLS handshake failed: {}", errors.join("; "))
+            }
+            Self::Shutdown => write!(f, "Shutdown requested"),
+        }
     }
 }
 
-impl std::error::Error for ClientError {}
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Config(err) => Some(err),
+            Self::Dns(err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for ClientError {
+    fn from(err: ConfigError) -> Self {
+        Self::Config(err)
+    }
+}
+
+impl From<DnsError> for ClientError {
+    fn from(err: DnsError) -> Self {
+        Self::Dns(err)
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<openssl::error::ErrorStack> for ClientError {
+    fn from(err: openssl::error::ErrorStack) -> Self {
+        match err.errors().first() {
+            Some(first) => Self::Tls {
+                file: first.file().to_string(),
+                line: first.line() as i32,
+                description: err.to_string(),
+            },
+            None => Self::Tls {
+                file: "?".to_string(),
+                line: 0,
+                description: err.to_string(),
+            },
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::error::ClientError;
+use crate::streams::{Command, HealthCheckResult};
+use slipstream_core::http::{plain_response, read_request_head, request_target, REQUEST_TIMEOUT};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Binds `addr` and serves `GET /health` from a separate task.
+///
+/// Each request sends one probe through the tunnel: 200 with the round trip when the server
+/// echoes it in time, 503 otherwise.
+pub(crate) async fn spawn_health_server(
+    addr: SocketAddr,
+    command_tx: mpsc::UnboundedSender<Command>,
+) -> Result<SocketAddr, ClientError> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|err| ClientError::io(err, format!("Failed to bind health address {}", addr)))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|err| ClientError::io(err, "Failed to read health address"))?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let command_tx = command_tx.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = serve_connection(stream, &command_tx).await {
+                            tracing::debug!("health request failed: {}", err);
+                        }
+                    });
+                }
+                Err(err) => tracing::warn!("health accept failed: {}", err),
+            }
+        }
+    });
+    Ok(local_addr)
+}
+
+async fn serve_connection(
+    mut stream: TcpStream,
+    command_tx: &mpsc::UnboundedSender<Command>,
+) -> std::io::Result<()> {
+    let Ok(head) = timeout(REQUEST_TIMEOUT, read_request_head(&mut stream)).await else {
+        return Ok(());
+    };
+    let response = match head? {
+        Some(head) => respond(&head, command_tx).await,
+        None => plain_response("400 Bad Request", "bad request\n"),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn respond(head: &str, command_tx: &mpsc::UnboundedSender<Command>) -> String {
+    let Some((method, path)) = request_target(head) else {
+        return plain_response("400 Bad Request", "bad request\n");
+    };
+    match (method, path) {
+        ("GET", "/health") => match probe(command_tx).await {
+            Ok(rtt) => plain_response("200 OK", &format!("ok rtt_ms={}\n", rtt.as_millis())),
+            Err(reason) => plain_response(
+                "503 Service Unavailable",
+                &format!("unavailable: {}\n", reason),
+            ),
+        },
+        (_, "/health") => plain_response("405 Method Not Allowed", "method not allowed\n"),
+        _ => plain_response("404 Not Found", "not found\n"),
+    }
+}
+
+async fn probe(command_tx: &mpsc::UnboundedSender<Command>) -> HealthCheckResult {
+    let (reply, result) = oneshot::channel();
+    if command_tx.send(Command::HealthCheck { reply }).is_err() {
+        return Err("client stopped".to_string());
+    }
+    match timeout(PROBE_TIMEOUT, result).await {
+        Ok(Ok(result)) => result,
+        // The probe was dropped, e.g. while the tunnel is reconnecting.
+        Ok(Err(_)) => Err("tunnel not connected".to_string()),
+        Err(_) => Err("no echo from server".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.expect("write");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("read response");
+        response
+    }
+
+    #[tokio::test]
+    async fn reports_probe_round_trips_over_http() {
+        let (command_tx, mut command_rx) = mpsc::unbounded_channel();
+        let addr = spawn_health_server("127.0.0.1:0".parse().unwrap(), command_tx)
+            .await
+            .expect("spawn health server");
+        tokio::spawn(async move {
+            let mut answered = false;
+            while let Some(command) = command_rx.recv().await {
+                if let Command::HealthCheck { reply } = command {
+                    // Answer the first probe and drop the second, as a reconnect would.
+                    if !answered {
+                        let _ = reply.send(Ok(Duration::from_millis(42)));
+                        answered = true;
+                    }
+                }
+            }
+        });
+
+        let response = get(addr, "/health").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("ok rtt_ms=42\n"), "{}", response);
+
+        let response = get(addr, "/health").await;
+        assert!(
+            response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{}",
+            response
+        );
+
+        let response = get(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -3,11 +3,19 @@
 //! This module provides the core functionality for the slipstream DNS tunnel client,
 //! including Android JNI bindings for mobile deployment.
 
+pub mod connect_proxy;
+pub mod datagram;
 pub mod dns;
 pub mod error;
+pub mod health_check;
+pub mod local_socket;
+pub mod metrics;
+pub mod network_monitor;
 pub mod pacing;
 pub mod pinning;
+pub mod resolver_discovery;
 pub mod runtime;
+pub mod socks5;
 pub mod streams;
 
 #[cfg(target_os = "android")]
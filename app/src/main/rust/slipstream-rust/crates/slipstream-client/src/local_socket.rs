@@ -0,0 +1,360 @@
+//! Local listeners the client accepts application connections on: TCP by default, or a
+//! Unix-domain socket when `--tcp-listen-host` is `unix:/path/to.sock`.
+
+use std::io;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::tcp::{OwnedReadHalf as TcpReadHalf, OwnedWriteHalf as TcpWriteHalf};
+#[cfg(unix)]
+use tokio::net::unix::{OwnedReadHalf as UnixReadHalf, OwnedWriteHalf as UnixWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::error::ClientError;
+
+/// Listen host prefix that selects a Unix-domain socket instead of TCP.
+pub const UNIX_LISTEN_PREFIX: &str = "unix:";
+
+/// Socket path named by a `unix:/path/to.sock` listen host, if it is one.
+pub fn unix_listen_path(host: &str) -> Option<&str> {
+    host.strip_prefix(UNIX_LISTEN_PREFIX)
+}
+
+pub(crate) enum LocalListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl LocalListener {
+    pub(crate) async fn accept(&self) -> io::Result<LocalStream> {
+        match self {
+            Self::Tcp(listener) => listener
+                .accept()
+                .await
+                .map(|(stream, _)| LocalStream::Tcp(stream)),
+            #[cfg(unix)]
+            Self::Unix(listener) => listener
+                .accept()
+                .await
+                .map(|(stream, _)| LocalStream::Unix(stream)),
+        }
+    }
+}
+
+/// Removes the listening socket file once the client stops using it.
+#[cfg(unix)]
+pub(crate) struct UnixSocketGuard(PathBuf);
+
+#[cfg(unix)]
+impl Drop for UnixSocketGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Checks a `unix:` listen host against the platform and the other options; TCP hosts pass.
+pub fn validate_listen_host(host: &str, datagram_mode: bool) -> Result<(), String> {
+    let Some(path) = unix_listen_path(host) else {
+        return Ok(());
+    };
+    if !cfg!(unix) {
+        return Err(unsupported_platform(path));
+    }
+    if path.is_empty() {
+        return Err(format!(
+            "{} listen host needs a socket path",
+            UNIX_LISTEN_PREFIX
+        ));
+    }
+    if datagram_mode {
+        return Err(format!(
+            "Datagram mode needs a TCP listen host, not {}{}",
+            UNIX_LISTEN_PREFIX, path
+        ));
+    }
+    Ok(())
+}
+
+fn unsupported_platform(path: &str) -> String {
+    format!(
+        "Cannot listen on {}{}: Unix-domain sockets are not supported on this platform",
+        UNIX_LISTEN_PREFIX, path
+    )
+}
+
+/// Binds `path`, first removing a socket file that no process is listening on any more.
+///
+/// A socket with a live listener behind it, or any other kind of file, is left alone and the
+/// bind fails.
+#[cfg(unix)]
+pub(crate) fn bind_unix_listener(
+    path: &str,
+) -> Result<(LocalListener, UnixSocketGuard), ClientError> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => {
+                    return Err(ClientError::config(format!(
+                        "Unix socket {} is in use by another process",
+                        path
+                    )))
+                }
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                    let _ = std::fs::remove_file(path);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|err| ClientError::io(err, format_args!("Failed to bind Unix socket {}", path)))?;
+    Ok((
+        LocalListener::Unix(listener),
+        UnixSocketGuard(PathBuf::from(path)),
+    ))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn bind_unix_listener(path: &str) -> Result<(LocalListener, ()), ClientError> {
+    Err(ClientError::config(unsupported_platform(path)))
+}
+
+/// An accepted application connection.
+pub(crate) enum LocalStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl LocalStream {
+    pub(crate) fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_nodelay(nodelay),
+            // Unix sockets do not batch small writes.
+            #[cfg(unix)]
+            Self::Unix(_) => Ok(()),
+        }
+    }
+
+    pub(crate) fn into_split(self) -> (LocalReadHalf, LocalWriteHalf) {
+        match self {
+            Self::Tcp(stream) => {
+                let (read, write) = stream.into_split();
+                (LocalReadHalf::Tcp(read), LocalWriteHalf::Tcp(write))
+            }
+            #[cfg(unix)]
+            Self::Unix(stream) => {
+                let (read, write) = stream.into_split();
+                (LocalReadHalf::Unix(read), LocalWriteHalf::Unix(write))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for LocalStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Tcp(stream) => stream.as_raw_fd(),
+            Self::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl AsyncRead for LocalStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for LocalStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+pub(crate) enum LocalReadHalf {
+    Tcp(TcpReadHalf),
+    #[cfg(unix)]
+    Unix(UnixReadHalf),
+}
+
+impl AsyncRead for LocalReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(half) => Pin::new(half).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(half) => Pin::new(half).poll_read(cx, buf),
+        }
+    }
+}
+
+pub(crate) enum LocalWriteHalf {
+    Tcp(TcpWriteHalf),
+    #[cfg(unix)]
+    Unix(UnixWriteHalf),
+}
+
+impl LocalWriteHalf {
+    /// Aborts the connection because its QUIC stream is gone.
+    ///
+    /// Dropping a TCP write half would send a FIN; it is forgotten instead so that, with a
+    /// zero linger, the close once the read half is dropped sends a RST. Unix sockets have no
+    /// reset, so the peer just sees the connection end.
+    pub(crate) fn reset(self) {
+        match self {
+            Self::Tcp(half) => {
+                let _ = half.as_ref().set_zero_linger();
+                half.forget();
+            }
+            #[cfg(unix)]
+            Self::Unix(half) => drop(half),
+        }
+    }
+}
+
+impl AsyncWrite for LocalWriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(half) => Pin::new(half).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(half) => Pin::new(half).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(half) => Pin::new(half).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(half) => Pin::new(half).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(half) => Pin::new(half).poll_shutdown(cx),
+            #[cfg(unix)]
+            Self::Unix(half) => Pin::new(half).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn recognizes_unix_listen_hosts() {
+        assert_eq!(
+            unix_listen_path("unix:/run/slip.sock"),
+            Some("/run/slip.sock")
+        );
+        assert_eq!(unix_listen_path("127.0.0.1"), None);
+        assert_eq!(unix_listen_path("::"), None);
+    }
+
+    #[test]
+    fn validates_unix_listen_hosts_against_their_options() {
+        assert!(validate_listen_host("127.0.0.1", true).is_ok());
+        assert!(validate_listen_host("unix:/run/slip.sock", false).is_ok());
+        assert!(validate_listen_host("unix:", false).is_err());
+        let err = validate_listen_host("unix:/run/slip.sock", true).unwrap_err();
+        assert!(err.contains("Datagram mode"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn unix_listener_leaves_a_live_socket_alone() {
+        let path = std::env::temp_dir().join(format!(
+            "slipstream-listen-live-{}.sock",
+            std::process::id()
+        ));
+        let path_str = path.to_str().expect("utf-8 temp path");
+        let _ = std::fs::remove_file(&path);
+        let (_listener, guard) = bind_unix_listener(path_str).expect("first bind");
+
+        let err = bind_unix_listener(path_str)
+            .err()
+            .expect("second bind fails");
+        assert!(err.to_string().contains("in use"), "{}", err);
+        assert!(path.exists(), "the live socket is kept");
+        UnixStream::connect(&path)
+            .await
+            .expect("first listener still reachable");
+        drop(guard);
+    }
+
+    #[tokio::test]
+    async fn unix_listener_accepts_and_removes_its_socket() {
+        let path =
+            std::env::temp_dir().join(format!("slipstream-listen-{}.sock", std::process::id()));
+        let path_str = path.to_str().expect("utf-8 temp path");
+        // A stale socket from an earlier run is replaced.
+        drop(std::os::unix::net::UnixListener::bind(&path).expect("stale socket"));
+        let (listener, guard) = bind_unix_listener(path_str).expect("bind");
+
+        let mut app = UnixStream::connect(&path).await.expect("connect");
+        let accepted = listener.accept().await.expect("accept");
+        assert!(accepted.set_nodelay(true).is_ok());
+        let (mut read_half, mut write_half) = accepted.into_split();
+        app.write_all(b"ping").await.expect("app write");
+        let mut buf = [0u8; 4];
+        read_half.read_exact(&mut buf).await.expect("read");
+        assert_eq!(&buf, b"ping");
+        write_half.write_all(b"pong").await.expect("write");
+        app.read_exact(&mut buf).await.expect("app read");
+        assert_eq!(&buf, b"pong");
+
+        drop(guard);
+        assert!(!path.exists(), "socket file should be removed");
+    }
+}
@@ -1,15 +1,35 @@
+mod check;
+mod connect_proxy;
+mod datagram;
 mod dns;
 mod error;
+mod health_check;
+mod local_socket;
+mod metrics;
+mod network_monitor;
 mod pacing;
 mod pinning;
+mod resolver_discovery;
 mod runtime;
+mod socks5;
 mod streams;
 
-use clap::{parser::ValueSource, ArgGroup, CommandFactory, FromArgMatches, Parser};
+use clap::{parser::ValueSource, ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use slipstream_core::config_file::{ConfigFile, ConfigValue, FileArgs, Section};
+use slipstream_core::logging::{parse_log_format, resolve_log_format, LogFormat};
+use slipstream_core::stream_priority::{parse_stream_priority_mode, StreamPriorityMode};
+use slipstream_core::watchdog;
 use slipstream_core::{
-    normalize_domain, parse_host_port, parse_host_port_parts, sip003, AddressKind, HostPort,
+    apply_address_policy, normalize_domain, parse_address_policy, parse_host_port,
+    parse_host_port_parts, sip003, wildcard_base, AddressKind, AddressPolicy, ConfigError,
+    HostPort,
 };
-use slipstream_ffi::{ClientConfig, ResolverMode, ResolverSpec};
+use slipstream_dns::{EdnsOptions, Encoding};
+use slipstream_ffi::{
+    CertMode, ClientConfig, ListenerMode, ResolverMode, ResolverSpec, ResolverStrategy, TlsVersion,
+};
+use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::runtime::Builder;
 use tracing_subscriber::EnvFilter;
 
@@ -22,7 +42,7 @@ use runtime::run_client;
     group(
         ArgGroup::new("resolvers")
             .multiple(true)
-            .args(["resolver", "authoritative"])
+            .args(["resolver", "authoritative", "doh", "dot"])
     )
 )]
 struct Args {
@@ -30,6 +50,30 @@ struct Args {
     tcp_listen_host: String,
     #[arg(long = "tcp-listen-port", short = 'l', default_value_t = 5201)]
     tcp_listen_port: u16,
+    #[arg(
+        long = "listener-mode",
+        value_name = "MODE",
+        default_value = "passthrough",
+        value_parser = parse_listener_mode
+    )]
+    listener_mode: ListenerMode,
+    #[arg(long = "socks5", conflicts_with = "listener_mode")]
+    socks5: bool,
+    #[arg(
+        long = "listener-workers",
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..=64)
+    )]
+    listener_workers: u16,
+    #[arg(long = "datagram-mode")]
+    datagram_mode: bool,
+    #[arg(
+        long = "stream-priority-mode",
+        value_name = "MODE",
+        value_parser = parse_stream_priority_mode
+    )]
+    stream_priority_mode: Option<StreamPriorityMode>,
     #[arg(long = "resolver", short = 'r', value_parser = parse_resolver)]
     resolver: Vec<HostPort>,
     #[arg(
@@ -40,6 +84,33 @@ struct Args {
     congestion_control: Option<String>,
     #[arg(long = "authoritative", value_parser = parse_resolver)]
     authoritative: Vec<HostPort>,
+    #[arg(long = "doh", value_name = "URL", value_parser = parse_doh_url)]
+    doh: Vec<DohUrl>,
+    #[arg(long = "dot", value_name = "HOST:PORT", value_parser = parse_dot_resolver)]
+    dot: Vec<HostPort>,
+    #[arg(long = "resolvers-file", value_name = "PATH")]
+    resolvers_file: Option<String>,
+    #[arg(
+        long = "resolver-strategy",
+        value_name = "STRATEGY",
+        default_value = "ordered",
+        value_parser = parse_resolver_strategy
+    )]
+    resolver_strategy: ResolverStrategy,
+    #[arg(
+        long = "address-family",
+        value_name = "4|6|any",
+        default_value = "any",
+        value_parser = parse_address_policy
+    )]
+    address_family: AddressPolicy,
+    #[arg(
+        long = "resolver-health-window",
+        value_name = "MS",
+        default_value_t = 2000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    resolver_health_window: u64,
     #[arg(
         short = 'g',
         long = "gso",
@@ -50,10 +121,145 @@ struct Args {
     gso: bool,
     #[arg(long = "domain", short = 'd', value_parser = parse_domain)]
     domain: Option<String>,
-    #[arg(long = "cert", value_name = "PATH")]
+    #[arg(long = "qtype", value_name = "TYPE", default_value = "txt", value_parser = parse_qtype_arg)]
+    qtype: u16,
+    #[arg(
+        long = "encoding",
+        value_name = "ENCODING",
+        default_value = "base32",
+        value_parser = parse_encoding_arg
+    )]
+    encoding: Encoding,
+    #[arg(long = "probe-qname-capacity")]
+    probe_qname_capacity: bool,
+    #[arg(
+        long = "max-qname-len",
+        value_name = "LEN",
+        default_value_t = slipstream_dns::MAX_DNS_NAME_LEN as u8,
+        value_parser = clap::value_parser!(u8).range(1..=slipstream_dns::MAX_DNS_NAME_LEN as i64)
+    )]
+    max_qname_len: u8,
+    #[arg(long = "ignore-cached-responses")]
+    ignore_cached_responses: bool,
+    #[arg(
+        long = "edns-udp-size",
+        value_name = "BYTES",
+        default_value_t = slipstream_dns::EDNS_UDP_PAYLOAD,
+        value_parser = parse_edns_udp_size
+    )]
+    edns_udp_size: u16,
+    #[arg(long = "edns-dnssec-ok")]
+    edns_dnssec_ok: bool,
+    #[arg(long = "ecs-optout")]
+    ecs_optout: bool,
+    #[arg(long = "query-padding", value_name = "BYTES", default_value_t = 0)]
+    query_padding: u16,
+    #[arg(long = "auth-secret", value_name = "FILE")]
+    auth_secret: Option<String>,
+    #[arg(
+        long = "auth-mac-len",
+        value_name = "BYTES",
+        default_value_t = slipstream_dns::hmac::DEFAULT_MAC_LEN as u8,
+        value_parser = clap::value_parser!(u8).range(
+            slipstream_dns::hmac::MIN_MAC_LEN as i64..=slipstream_dns::hmac::MAX_MAC_LEN as i64
+        )
+    )]
+    auth_mac_len: u8,
+    #[arg(long = "cert", value_name = "PATH|PIN")]
     cert: Option<String>,
+    #[arg(
+        long = "cert-mode",
+        value_name = "MODE",
+        default_value = "leaf-pin",
+        value_parser = parse_cert_mode
+    )]
+    cert_mode: CertMode,
+    #[arg(long = "resolver-cert", value_name = "PATH")]
+    resolver_cert: Option<String>,
+    #[arg(long = "sni", value_name = "NAME", value_parser = parse_domain)]
+    sni: Option<String>,
+    #[arg(
+        long = "tls-min-version",
+        value_name = "VERSION",
+        default_value = "1.3",
+        value_parser = parse_tls_min_version
+    )]
+    tls_min_version: TlsVersion,
+    #[arg(long = "tls-ciphers", value_name = "LIST")]
+    tls_ciphers: Option<String>,
     #[arg(long = "keep-alive-interval", short = 't', default_value_t = 400)]
     keep_alive_interval: u16,
+    #[arg(long = "adaptive-keepalive", alias = "adaptive-keep-alive")]
+    adaptive_keepalive: bool,
+    #[arg(long = "min-keep-alive", value_name = "MS")]
+    min_keep_alive: Option<u64>,
+    #[arg(long = "max-keep-alive", value_name = "MS", default_value_t = 30_000)]
+    max_keep_alive: u64,
+    #[arg(long = "metrics-socket", value_name = "PATH")]
+    metrics_socket: Option<String>,
+    #[arg(long = "health-listen", value_name = "HOST:PORT")]
+    health_listen: Option<SocketAddr>,
+    #[arg(long = "migration-timeout-ms", default_value_t = 3000)]
+    migration_timeout_ms: u64,
+    #[arg(long = "enable-migration")]
+    enable_migration: bool,
+    #[arg(
+        long = "max-query-rate",
+        value_name = "QPS",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    max_query_rate: Option<u32>,
+    #[arg(
+        long = "min-query-rate",
+        value_name = "QPS",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    min_query_rate: u32,
+    #[arg(
+        long = "max-pacing-rate",
+        value_name = "BYTES_PER_SEC",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    max_pacing_rate: Option<u64>,
+    #[arg(
+        long = "pipeline-window",
+        value_name = "N",
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u16).range(..=64)
+    )]
+    pipeline_window: u16,
+    #[arg(
+        long = "reconnect-backoff-ms",
+        value_name = "MS",
+        default_value_t = 250,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    reconnect_backoff_ms: u64,
+    #[arg(long = "reconnect-max-ms", value_name = "MS", default_value_t = 5000)]
+    reconnect_max_ms: u64,
+    #[arg(long = "max-reconnect-attempts", default_value_t = 0)]
+    max_reconnect_attempts: u32,
+    #[arg(
+        long = "idle-timeout-seconds",
+        value_name = "SECONDS",
+        default_value_t = 0
+    )]
+    idle_timeout_seconds: u64,
+    #[arg(long = "session-ticket-path", value_name = "PATH")]
+    session_ticket_path: Option<String>,
+    #[arg(long = "config", value_name = "FILE")]
+    config: Option<String>,
+    #[arg(long = "check-config")]
+    check_config: bool,
+    #[arg(long = "dry-run", conflicts_with = "check_config")]
+    dry_run: bool,
+    #[arg(long = "watchdog-interval-ms", value_name = "MS", default_value_t = 0)]
+    watchdog_interval_ms: u64,
+    #[arg(long = "watchdog-abort")]
+    watchdog_abort: bool,
+    #[arg(long = "log-format", value_name = "text|json", value_parser = parse_log_format)]
+    log_format: Option<LogFormat>,
     #[arg(long = "debug-poll")]
     debug_poll: bool,
     #[arg(long = "debug-streams")]
@@ -63,9 +269,17 @@ struct Args {
 }
 
 fn main() {
-    init_logging();
-    let matches = Args::command().get_matches();
+    let (matches, config_file_err) = apply_config_file(Args::command().get_matches());
     let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let (log_format, log_format_err) = resolve_log_format(args.log_format, DEFAULT_LOG_FORMAT);
+    init_logging(log_format);
+    if let Some(err) = log_format_err {
+        tracing::warn!("Ignoring {}", err);
+    }
+    if let Some(err) = config_file_err {
+        tracing::error!("Config file error: {}", err);
+        std::process::exit(2);
+    }
     let sip003_env = sip003::read_sip003_env().unwrap_or_else(|err| {
         tracing::error!("SIP003 env error: {}", err);
         std::process::exit(2);
@@ -73,6 +287,12 @@ fn main() {
     if sip003_env.is_present() {
         tracing::info!("SIP003 env detected; applying SS_* overrides with CLI precedence");
     }
+    if let Err(errors) = sip003::validate(&sip003_env.plugin_options, &plugin_option_schema()) {
+        for err in errors {
+            tracing::error!("SIP003 env error: {}", err);
+        }
+        std::process::exit(2);
+    }
 
     let tcp_listen_host_provided = cli_provided(&matches, "tcp_listen_host");
     let tcp_listen_port_provided = cli_provided(&matches, "tcp_listen_port");
@@ -89,6 +309,10 @@ fn main() {
         tracing::error!("SIP003 env error: {}", err);
         std::process::exit(2);
     });
+    if let Err(err) = local_socket::validate_listen_host(&tcp_listen_host, args.datagram_mode) {
+        tracing::error!("--tcp-listen-host: {}", err);
+        std::process::exit(2);
+    }
 
     let domain = if let Some(domain) = args.domain.clone() {
         domain
@@ -105,9 +329,22 @@ fn main() {
         }
     };
 
+    let address_policy = if cli_provided(&matches, "address_family") {
+        args.address_family
+    } else if let Some(value) =
+        sip003::last_option_value(&sip003_env.plugin_options, "address-family")
+    {
+        parse_address_policy(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
+    } else {
+        args.address_family
+    };
+
     let cli_has_resolvers = has_cli_resolvers(&matches);
-    let resolvers = if cli_has_resolvers {
-        build_resolvers(&matches, true).unwrap_or_else(|err| {
+    let resolvers = if cli_has_resolvers || args.resolvers_file.is_some() {
+        build_resolvers_with_file(&matches, args.resolvers_file.as_deref()).unwrap_or_else(|err| {
             tracing::error!("Resolver error: {}", err);
             std::process::exit(2);
         })
@@ -141,14 +378,31 @@ fn main() {
                             tracing::error!("SIP003 env error: {}", err);
                             std::process::exit(2);
                         });
-                vec![ResolverSpec { resolver, mode }]
+                vec![ResolverSpec {
+                    resolver,
+                    mode,
+                    doh_path: None,
+                }]
             } else {
-                tracing::error!("At least one resolver is required");
-                std::process::exit(2);
+                // run_client falls back to the system's DNS servers.
+                Vec::new()
             }
         }
     };
 
+    let resolvers: Vec<ResolverSpec> = resolvers
+        .into_iter()
+        .map(|spec| {
+            let resolver =
+                apply_address_policy(spec.resolver, AddressKind::Resolver, address_policy)?;
+            Ok(ResolverSpec { resolver, ..spec })
+        })
+        .collect::<Result<_, ConfigError>>()
+        .unwrap_or_else(|err| {
+            tracing::error!("Resolver error: {}", err);
+            std::process::exit(2);
+        });
+
     let congestion_control = if args.congestion_control.is_some() {
         args.congestion_control.clone()
     } else {
@@ -158,11 +412,114 @@ fn main() {
         })
     };
 
+    let qtype = if cli_provided(&matches, "qtype") {
+        args.qtype
+    } else {
+        let qtype_override = parse_qtype_option(&sip003_env.plugin_options).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        });
+        qtype_override.unwrap_or(args.qtype)
+    };
+
+    let listener_mode = if args.socks5 {
+        ListenerMode::Socks5
+    } else if cli_provided(&matches, "listener_mode") {
+        args.listener_mode
+    } else {
+        let mode_override =
+            parse_listener_mode_option(&sip003_env.plugin_options).unwrap_or_else(|err| {
+                tracing::error!("SIP003 env error: {}", err);
+                std::process::exit(2);
+            });
+        mode_override.unwrap_or(args.listener_mode)
+    };
+
+    let resolver_strategy = if cli_provided(&matches, "resolver_strategy") {
+        args.resolver_strategy
+    } else {
+        let strategy_override = parse_resolver_strategy_option(&sip003_env.plugin_options)
+            .unwrap_or_else(|err| {
+                tracing::error!("SIP003 env error: {}", err);
+                std::process::exit(2);
+            });
+        strategy_override.unwrap_or(args.resolver_strategy)
+    };
+
+    let resolver_health_window = if cli_provided(&matches, "resolver_health_window") {
+        args.resolver_health_window
+    } else {
+        let window_override = parse_resolver_health_window(&sip003_env.plugin_options)
+            .unwrap_or_else(|err| {
+                tracing::error!("SIP003 env error: {}", err);
+                std::process::exit(2);
+            });
+        window_override.unwrap_or(args.resolver_health_window)
+    };
+
+    let encoding = if cli_provided(&matches, "encoding") {
+        args.encoding
+    } else {
+        let encoding_override =
+            parse_encoding_option(&sip003_env.plugin_options).unwrap_or_else(|err| {
+                tracing::error!("SIP003 env error: {}", err);
+                std::process::exit(2);
+            });
+        encoding_override.unwrap_or(args.encoding)
+    };
+
     let cert = if args.cert.is_some() {
         args.cert.clone()
     } else {
         sip003::last_option_value(&sip003_env.plugin_options, "cert")
     };
+    let cert_mode = if cli_provided(&matches, "cert_mode") {
+        args.cert_mode
+    } else {
+        let mode_override =
+            parse_cert_mode_option(&sip003_env.plugin_options).unwrap_or_else(|err| {
+                tracing::error!("SIP003 env error: {}", err);
+                std::process::exit(2);
+            });
+        mode_override.unwrap_or(args.cert_mode)
+    };
+    let resolver_cert = if args.resolver_cert.is_some() {
+        args.resolver_cert.clone()
+    } else {
+        sip003::last_option_value(&sip003_env.plugin_options, "resolver-cert")
+    };
+    let sni = if args.sni.is_some() {
+        args.sni.clone()
+    } else {
+        parse_sni_option(&sip003_env.plugin_options).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
+    };
+
+    let tls_ciphers = args
+        .tls_ciphers
+        .as_deref()
+        .map(pinning::parse_tls_ciphers)
+        .transpose()
+        .unwrap_or_else(|err| {
+            tracing::error!("Invalid --tls-ciphers: {}", err);
+            std::process::exit(2);
+        })
+        .unwrap_or_default();
+
+    let auth_secret_path = if args.auth_secret.is_some() {
+        args.auth_secret.clone()
+    } else {
+        sip003::last_option_value(&sip003_env.plugin_options, "auth-secret")
+    };
+    let auth_secret = auth_secret_path.map(|path| {
+        slipstream_dns::hmac::load_secret(&path).unwrap_or_else(|err| {
+            tracing::error!("Cannot read auth secret {}: {}", path, err);
+            std::process::exit(2);
+        })
+    });
+
     if cert.is_none() {
         tracing::warn!(
             "Server certificate pinning is disabled; this allows MITM. Provide --cert to pin the server leaf, or dismiss this if your underlying tunnel provides authentication."
@@ -180,6 +537,25 @@ fn main() {
         keep_alive_override.unwrap_or(args.keep_alive_interval)
     };
 
+    let edns_udp_size = if cli_provided(&matches, "edns_udp_size") {
+        args.edns_udp_size
+    } else {
+        let edns_override =
+            parse_edns_udp_size_option(&sip003_env.plugin_options).unwrap_or_else(|err| {
+                tracing::error!("SIP003 env error: {}", err);
+                std::process::exit(2);
+            });
+        edns_override.unwrap_or(args.edns_udp_size)
+    };
+
+    if args
+        .max_query_rate
+        .is_some_and(|max_rate| args.min_query_rate > max_rate)
+    {
+        tracing::error!("--min-query-rate must not exceed --max-query-rate");
+        std::process::exit(2);
+    }
+
     let idle_poll_interval = if cli_provided(&matches, "idle_poll_interval") {
         args.idle_poll_interval
     } else {
@@ -194,22 +570,106 @@ fn main() {
     let config = ClientConfig {
         tcp_listen_host: &tcp_listen_host,
         tcp_listen_port,
+        listener_mode,
+        listener_workers: args.listener_workers as usize,
+        datagram_mode: args.datagram_mode,
+        stream_priority_mode: args.stream_priority_mode,
         resolvers: &resolvers,
+        address_policy,
+        resolver_strategy,
+        resolver_health_window_ms: resolver_health_window,
         congestion_control: congestion_control.as_deref(),
         gso: args.gso,
         domain: &domain,
+        qtype,
+        encoding,
+        probe_qname_capacity: args.probe_qname_capacity,
+        max_qname_len: args.max_qname_len,
+        ignore_cached_responses: args.ignore_cached_responses,
+        edns: EdnsOptions {
+            udp_payload: edns_udp_size,
+            dnssec_ok: args.edns_dnssec_ok,
+            ecs_optout: args.ecs_optout,
+            padding_block: args.query_padding,
+        },
+        auth_secret: auth_secret.as_deref(),
+        auth_mac_len: args.auth_mac_len as usize,
         cert: cert.as_deref(),
+        cert_mode,
+        resolver_cert: resolver_cert.as_deref(),
+        sni: sni.as_deref(),
+        tls_min_version: args.tls_min_version,
+        tls_ciphers: &tls_ciphers,
         keep_alive_interval: keep_alive_interval as usize,
+        adaptive_keepalive: args.adaptive_keepalive,
+        min_keep_alive_ms: args.min_keep_alive.unwrap_or(keep_alive_interval as u64),
+        max_keep_alive_ms: args.max_keep_alive,
+        metrics_socket: args.metrics_socket.as_deref(),
+        health_listen: args.health_listen,
+        migration_timeout_ms: args.migration_timeout_ms,
+        enable_migration: args.enable_migration,
+        max_query_rate: args.max_query_rate,
+        min_query_rate: args.min_query_rate,
+        max_pacing_rate: args.max_pacing_rate,
+        pipeline_window: args.pipeline_window as usize,
+        reconnect_backoff_ms: args.reconnect_backoff_ms,
+        reconnect_max_ms: args.reconnect_max_ms,
+        max_reconnect_attempts: args.max_reconnect_attempts,
+        idle_timeout_seconds: args.idle_timeout_seconds,
+        session_ticket_path: args.session_ticket_path.as_deref(),
+        on_state_change: None,
         debug_poll: args.debug_poll,
         debug_streams: args.debug_streams,
         idle_poll_interval_ms: idle_poll_interval,
     };
 
+    if args.check_config {
+        match check::check_config(&config) {
+            Ok(summary) => {
+                print!("{}", summary);
+                std::process::exit(0);
+            }
+            Err(err) => {
+                tracing::error!("Config error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+    if args.dry_run {
+        match check::check_config(&config).and_then(|_| check::probe_listen(&config)) {
+            Ok(()) => {
+                tracing::info!("Configuration OK");
+                std::process::exit(0);
+            }
+            Err(err) => {
+                tracing::error!("Config error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+
     let runtime = Builder::new_current_thread()
         .enable_io()
         .enable_time()
         .build()
         .expect("Failed to build Tokio runtime");
+    if args.watchdog_interval_ms > 0 {
+        let interval = Duration::from_millis(args.watchdog_interval_ms);
+        match watchdog::start(interval, args.watchdog_abort) {
+            Ok(responder) => {
+                runtime.spawn(responder);
+            }
+            Err(err) => {
+                tracing::error!("Failed to start the watchdog: {}", err);
+                std::process::exit(1);
+            }
+        }
+    } else if args.watchdog_abort {
+        tracing::warn!("Ignoring --watchdog-abort without --watchdog-interval-ms");
+    }
+    if args.enable_migration && args.migration_timeout_ms == 0 {
+        tracing::warn!("Ignoring --enable-migration with --migration-timeout-ms 0");
+    }
     match runtime.block_on(run_client(&config)) {
         Ok(code) => std::process::exit(code),
         Err(err) => {
@@ -219,23 +679,141 @@ fn main() {
     }
 }
 
-fn init_logging() {
+/// Log format used when neither `--log-format` nor `SLIPSTREAM_LOG_FORMAT` is set.
+const DEFAULT_LOG_FORMAT: LogFormat = if cfg!(feature = "json-logs") {
+    LogFormat::Json
+} else {
+    LogFormat::Text
+};
+
+fn init_logging(format: LogFormat) {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .without_time()
-        .try_init();
+    let _ = match format {
+        LogFormat::Text => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_target(false)
+            .without_time()
+            .try_init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_env_filter(filter)
+            .try_init(),
+    };
 }
 
 fn parse_domain(input: &str) -> Result<String, String> {
-    normalize_domain(input).map_err(|err| err.to_string())
+    let domain = normalize_domain(input).map_err(|err| err.to_string())?;
+    if wildcard_base(&domain).is_some() {
+        return Err(format!(
+            "Client domain {} must be a concrete name; wildcards are only accepted by the server",
+            domain
+        ));
+    }
+    Ok(domain)
+}
+
+fn parse_qtype_arg(input: &str) -> Result<u16, String> {
+    slipstream_dns::parse_qtype(input).ok_or_else(|| {
+        format!(
+            "Unsupported query type (expected txt, a, aaaa, cname, or null): {}",
+            input
+        )
+    })
+}
+
+fn parse_encoding_arg(input: &str) -> Result<Encoding, String> {
+    slipstream_dns::parse_encoding(input).ok_or_else(|| {
+        format!(
+            "Unsupported encoding (expected base32 or base64url): {}",
+            input
+        )
+    })
+}
+
+fn parse_listener_mode(input: &str) -> Result<ListenerMode, String> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "passthrough" => Ok(ListenerMode::Passthrough),
+        "connect-proxy" => Ok(ListenerMode::ConnectProxy),
+        "socks5" => Ok(ListenerMode::Socks5),
+        _ => Err(format!(
+            "Unsupported listener mode (expected passthrough, connect-proxy, or socks5): {}",
+            input
+        )),
+    }
+}
+
+fn parse_cert_mode(input: &str) -> Result<CertMode, String> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "leaf-pin" => Ok(CertMode::LeafPin),
+        "chain-verify" => Ok(CertMode::ChainVerify),
+        _ => Err(format!(
+            "Unsupported cert mode (expected leaf-pin or chain-verify): {}",
+            input
+        )),
+    }
+}
+
+fn parse_tls_min_version(input: &str) -> Result<TlsVersion, String> {
+    match input.trim() {
+        "1.2" => Ok(TlsVersion::Tls12),
+        "1.3" => Ok(TlsVersion::Tls13),
+        _ => Err(format!(
+            "Unsupported TLS version (expected 1.2 or 1.3): {}",
+            input
+        )),
+    }
+}
+
+fn parse_resolver_strategy(input: &str) -> Result<ResolverStrategy, String> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "ordered" => Ok(ResolverStrategy::Ordered),
+        "round-robin" => Ok(ResolverStrategy::RoundRobin),
+        "latency-weighted" => Ok(ResolverStrategy::LatencyWeighted),
+        _ => Err(format!(
+            "Unsupported resolver strategy (expected ordered, round-robin, or latency-weighted): {}",
+            input
+        )),
+    }
 }
 
 fn parse_resolver(input: &str) -> Result<HostPort, String> {
     parse_host_port(input, 53, AddressKind::Resolver).map_err(|err| err.to_string())
 }
 
+fn parse_dot_resolver(input: &str) -> Result<HostPort, String> {
+    parse_host_port(input, 853, AddressKind::Resolver).map_err(|err| err.to_string())
+}
+
+#[derive(Debug, Clone)]
+struct DohUrl {
+    resolver: HostPort,
+    path: String,
+}
+
+fn parse_doh_url(input: &str) -> Result<DohUrl, String> {
+    let trimmed = input.trim();
+    let rest = trimmed
+        .strip_prefix("https://")
+        .ok_or_else(|| format!("DoH URL must start with https://: {}", trimmed))?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/dns-query"),
+    };
+    if authority.is_empty() {
+        return Err(format!("DoH URL is missing a host: {}", trimmed));
+    }
+    let path = path
+        .parse::<http::uri::PathAndQuery>()
+        .map_err(|err| format!("Invalid DoH URL path {}: {}", path, err))?;
+    let resolver =
+        parse_host_port(authority, 443, AddressKind::Resolver).map_err(|err| err.to_string())?;
+    Ok(DohUrl {
+        resolver,
+        path: path.as_str().to_string(),
+    })
+}
+
 fn build_resolvers(matches: &clap::ArgMatches, require: bool) -> Result<Vec<ResolverSpec>, String> {
     let mut ordered = Vec::new();
     collect_resolvers(matches, "resolver", ResolverMode::Recursive, &mut ordered)?;
@@ -245,6 +823,27 @@ fn build_resolvers(matches: &clap::ArgMatches, require: bool) -> Result<Vec<Reso
         ResolverMode::Authoritative,
         &mut ordered,
     )?;
+    collect_resolvers(matches, "dot", ResolverMode::Dot, &mut ordered)?;
+    let indices: Vec<usize> = matches.indices_of("doh").into_iter().flatten().collect();
+    let values: Vec<DohUrl> = matches
+        .get_many::<DohUrl>("doh")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    if indices.len() != values.len() {
+        return Err("Mismatched doh arguments".to_string());
+    }
+    for (idx, url) in indices.into_iter().zip(values) {
+        ordered.push((
+            idx,
+            ResolverSpec {
+                resolver: url.resolver,
+                mode: ResolverMode::Doh,
+                doh_path: Some(url.path),
+            },
+        ));
+    }
     if ordered.is_empty() && require {
         return Err("At least one resolver is required".to_string());
     }
@@ -252,6 +851,57 @@ fn build_resolvers(matches: &clap::ArgMatches, require: bool) -> Result<Vec<Reso
     Ok(ordered.into_iter().map(|(_, spec)| spec).collect())
 }
 
+/// Resolver flags first, then `--resolvers-file` entries for addresses the flags don't name.
+fn build_resolvers_with_file(
+    matches: &clap::ArgMatches,
+    path: Option<&str>,
+) -> Result<Vec<ResolverSpec>, String> {
+    let Some(path) = path else {
+        return build_resolvers(matches, true);
+    };
+    let mut resolvers = build_resolvers(matches, false)?;
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read resolvers file {}: {}", path, err))?;
+    let from_file = parse_resolvers_file(&contents).map_err(|err| format!("{}: {}", path, err))?;
+    for spec in from_file {
+        let listed = resolvers.iter().any(|existing| {
+            existing.resolver.host == spec.resolver.host
+                && existing.resolver.port == spec.resolver.port
+        });
+        if !listed {
+            resolvers.push(spec);
+        }
+    }
+    if resolvers.is_empty() {
+        return Err("At least one resolver is required".to_string());
+    }
+    Ok(resolvers)
+}
+
+/// Parses `--resolvers-file`: one `host[:port]` per line, `authoritative:` marking
+/// authoritative resolvers, with blank lines and `#` comments skipped.
+fn parse_resolvers_file(contents: &str) -> Result<Vec<ResolverSpec>, String> {
+    let mut resolvers = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (mode, address) = match line.strip_prefix("authoritative:") {
+            Some(address) => (ResolverMode::Authoritative, address.trim()),
+            None => (ResolverMode::Recursive, line),
+        };
+        let resolver =
+            parse_resolver(address).map_err(|err| format!("line {}: {}", idx + 1, err))?;
+        resolvers.push(ResolverSpec {
+            resolver,
+            mode,
+            doh_path: None,
+        });
+    }
+    Ok(resolvers)
+}
+
 fn collect_resolvers(
     matches: &clap::ArgMatches,
     name: &str,
@@ -269,7 +919,14 @@ fn collect_resolvers(
         return Err(format!("Mismatched {} arguments", name));
     }
     for (idx, resolver) in indices.into_iter().zip(values) {
-        ordered.push((idx, ResolverSpec { resolver, mode }));
+        ordered.push((
+            idx,
+            ResolverSpec {
+                resolver,
+                mode,
+                doh_path: None,
+            },
+        ));
     }
     Ok(())
 }
@@ -278,6 +935,112 @@ fn cli_provided(matches: &clap::ArgMatches, id: &str) -> bool {
     matches.value_source(id) == Some(ValueSource::CommandLine)
 }
 
+/// Parses the command line again with the `--config` file's options under it.
+///
+/// On error the command line alone is returned so logging can start before reporting it.
+fn apply_config_file(matches: ArgMatches) -> (ArgMatches, Option<ConfigError>) {
+    let Some(path) = matches.get_one::<String>("config") else {
+        return (matches, None);
+    };
+    match load_config_file(path, &matches) {
+        Ok(merged) => (merged, None),
+        Err(err) => (matches, Some(err)),
+    }
+}
+
+fn load_config_file(path: &str, matches: &ArgMatches) -> Result<ArgMatches, ConfigError> {
+    let file = ConfigFile::load(std::path::Path::new(path))?;
+    file.check_sections(&["client", "resolver"])?;
+    let command = Args::command();
+    let mut args = FileArgs::new(&file, &command, matches);
+    // Resolvers on the command line replace the file's list rather than adding to it.
+    let cli_resolvers = ["resolver", "authoritative", "doh", "dot"]
+        .iter()
+        .any(|id| args.command_line_provided(id));
+    for section in file.sections() {
+        if section.name == "client" {
+            args.push_section(section)?;
+        } else if !cli_resolvers {
+            push_resolver_section(&file, &mut args, section)?;
+        }
+    }
+    args.into_matches(std::env::args_os())
+}
+
+/// Renders a `[[resolver]]` table (`host`, `port`, `mode`, or `url` for DoH) as a flag.
+fn push_resolver_section(
+    file: &ConfigFile,
+    args: &mut FileArgs<'_>,
+    section: &Section,
+) -> Result<(), ConfigError> {
+    let mut fields = [
+        ("host", None),
+        ("port", None),
+        ("mode", None),
+        ("url", None),
+    ];
+    for option in &section.options {
+        let Some((_, field)) = fields.iter_mut().find(|(key, _)| *key == option.key) else {
+            return Err(file.error(
+                option.line,
+                format!(
+                    "Unknown resolver option {} (expected host, port, mode, or url)",
+                    option.key
+                ),
+            ));
+        };
+        match &option.value {
+            ConfigValue::Values(values) if values.len() == 1 => {
+                *field = Some((values[0].as_str(), option.line));
+            }
+            _ => {
+                return Err(file.error(
+                    option.line,
+                    format!("Resolver {} must be a single value", option.key),
+                ))
+            }
+        }
+    }
+    let [host, port, mode, url] = fields.map(|(_, field)| field);
+    let flag = match mode.map(|(mode, _)| mode).unwrap_or("recursive") {
+        "recursive" => "resolver",
+        "authoritative" => "authoritative",
+        "doh" => "doh",
+        "dot" => "dot",
+        other => {
+            return Err(file.error(
+                mode.map_or(section.line, |(_, line)| line),
+                format!(
+                    "Unknown resolver mode {} (expected recursive, authoritative, doh, or dot)",
+                    other
+                ),
+            ))
+        }
+    };
+    if flag == "doh" {
+        let Some((url, line)) = url else {
+            return Err(file.error(section.line, "DoH resolvers need a url"));
+        };
+        if host.is_some() || port.is_some() {
+            return Err(file.error(line, "DoH resolvers take a url instead of host and port"));
+        }
+        return args.push_value(line, flag, url);
+    }
+    let Some((host, line)) = host else {
+        return Err(file.error(section.line, "Resolvers need a host"));
+    };
+    if let Some((_, url_line)) = url {
+        return Err(file.error(url_line, "Only DoH resolvers take a url"));
+    }
+    let address = match port {
+        Some((port, _)) if host.contains(':') => format!("[{}]:{}", host, port),
+        Some((port, _)) => format!("{}:{}", host, port),
+        None if host.contains(':') => format!("[{}]", host),
+        None => host.to_string(),
+    };
+    args.push_value(line, flag, &address)
+}
+
 fn has_cli_resolvers(matches: &clap::ArgMatches) -> bool {
     matches
         .get_many::<HostPort>("resolver")
@@ -287,6 +1050,65 @@ fn has_cli_resolvers(matches: &clap::ArgMatches) -> bool {
             .get_many::<HostPort>("authoritative")
             .map(|values| values.len() > 0)
             .unwrap_or(false)
+        || matches
+            .get_many::<DohUrl>("doh")
+            .map(|values| values.len() > 0)
+            .unwrap_or(false)
+        || matches
+            .get_many::<HostPort>("dot")
+            .map(|values| values.len() > 0)
+            .unwrap_or(false)
+}
+
+/// `SS_PLUGIN_OPTIONS` keys the client consumes; anything else is rejected up front.
+fn plugin_option_schema() -> sip003::Sip003Schema {
+    use sip003::SchemaDef;
+
+    sip003::Sip003Schema::new(vec![
+        SchemaDef::new("domain").list_parsed_by(parse_domain),
+        SchemaDef::new("resolver").list_parsed_by(parse_resolver),
+        // Empty switches the SS_REMOTE_* fallback into authoritative mode.
+        SchemaDef::new("authoritative").validator(|value| {
+            if value.trim().is_empty() {
+                return Ok(());
+            }
+            for entry in sip003::split_list(value).map_err(|err| err.to_string())? {
+                parse_resolver(&entry)?;
+            }
+            Ok(())
+        }),
+        SchemaDef::new("doh").list_parsed_by(parse_doh_url),
+        SchemaDef::new("dot").list_parsed_by(parse_dot_resolver),
+        SchemaDef::new("qtype").parsed_by(parse_qtype_arg),
+        SchemaDef::new("encoding").parsed_by(parse_encoding_arg),
+        SchemaDef::new("cert"),
+        SchemaDef::new("cert-mode").parsed_by(parse_cert_mode),
+        SchemaDef::new("resolver-cert"),
+        SchemaDef::new("sni").parsed_by(parse_domain),
+        SchemaDef::new("resolver-strategy").parsed_by(parse_resolver_strategy),
+        SchemaDef::new("resolver-health-window").parsed_by(|value| match value.parse::<u64>() {
+            Ok(window) if window > 0 => Ok(window),
+            _ => Err(format!("Invalid resolver-health-window value: {}", value)),
+        }),
+        SchemaDef::new("listener-mode").parsed_by(parse_listener_mode),
+        SchemaDef::new("congestion-control").parsed_by(|value| match value {
+            "bbr" | "dcubic" => Ok(()),
+            _ => Err(format!("Invalid congestion-control value: {}", value)),
+        }),
+        SchemaDef::new("keep-alive-interval").parsed_by(|value| {
+            value
+                .parse::<u16>()
+                .map_err(|_| format!("Invalid keep-alive-interval value: {}", value))
+        }),
+        SchemaDef::new("edns-udp-size").parsed_by(parse_edns_udp_size),
+        SchemaDef::new("idle-poll-interval").parsed_by(|value| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid idle-poll-interval value: {}", value))
+        }),
+        SchemaDef::new("address-family").parsed_by(parse_address_policy),
+        SchemaDef::new("auth-secret"),
+    ])
 }
 
 fn parse_domain_option(options: &[sip003::Sip003Option]) -> Result<Option<String>, String> {
@@ -305,8 +1127,7 @@ fn parse_domain_option(options: &[sip003::Sip003Option]) -> Result<Option<String
             let entry = entries
                 .pop()
                 .ok_or_else(|| "SIP003 domain option must contain a single value".to_string())?;
-            let normalized = normalize_domain(&entry).map_err(|err| err.to_string())?;
-            domain = Some(normalized);
+            domain = Some(parse_domain(&entry)?);
         }
     }
     Ok(domain)
@@ -326,6 +1147,8 @@ fn parse_resolvers_from_options(
         let mode = match option.key.as_str() {
             "resolver" => ResolverMode::Recursive,
             "authoritative" => ResolverMode::Authoritative,
+            "doh" => ResolverMode::Doh,
+            "dot" => ResolverMode::Dot,
             _ => continue,
         };
         let trimmed = option.value.trim();
@@ -338,9 +1161,23 @@ fn parse_resolvers_from_options(
         }
         let entries = sip003::split_list(&option.value).map_err(|err| err.to_string())?;
         for entry in entries {
-            let resolver = parse_host_port(&entry, 53, AddressKind::Resolver)
+            if mode == ResolverMode::Doh {
+                let url = parse_doh_url(&entry)?;
+                ordered.push(ResolverSpec {
+                    resolver: url.resolver,
+                    mode,
+                    doh_path: Some(url.path),
+                });
+                continue;
+            }
+            let default_port = if mode == ResolverMode::Dot { 853 } else { 53 };
+            let resolver = parse_host_port(&entry, default_port, AddressKind::Resolver)
                 .map_err(|err| err.to_string())?;
-            ordered.push(ResolverSpec { resolver, mode });
+            ordered.push(ResolverSpec {
+                resolver,
+                mode,
+                doh_path: None,
+            });
         }
     }
     Ok(ResolverOptions {
@@ -363,6 +1200,88 @@ fn parse_congestion_control(options: &[sip003::Sip003Option]) -> Result<Option<S
     Ok(last)
 }
 
+fn parse_qtype_option(options: &[sip003::Sip003Option]) -> Result<Option<u16>, String> {
+    let mut last = None;
+    for option in options {
+        if option.key == "qtype" {
+            let value = option.value.trim();
+            let parsed = slipstream_dns::parse_qtype(value)
+                .ok_or_else(|| format!("Invalid qtype value: {}", value))?;
+            last = Some(parsed);
+        }
+    }
+    Ok(last)
+}
+
+fn parse_sni_option(options: &[sip003::Sip003Option]) -> Result<Option<String>, String> {
+    sip003::last_option_value(options, "sni")
+        .map(|value| parse_domain(&value))
+        .transpose()
+}
+
+fn parse_encoding_option(options: &[sip003::Sip003Option]) -> Result<Option<Encoding>, String> {
+    let mut last = None;
+    for option in options {
+        if option.key == "encoding" {
+            let value = option.value.trim();
+            let parsed = slipstream_dns::parse_encoding(value)
+                .ok_or_else(|| format!("Invalid encoding value: {}", value))?;
+            last = Some(parsed);
+        }
+    }
+    Ok(last)
+}
+
+fn parse_listener_mode_option(
+    options: &[sip003::Sip003Option],
+) -> Result<Option<ListenerMode>, String> {
+    let mut last = None;
+    for option in options {
+        if option.key == "listener-mode" {
+            last = Some(parse_listener_mode(&option.value)?);
+        }
+    }
+    Ok(last)
+}
+
+fn parse_cert_mode_option(options: &[sip003::Sip003Option]) -> Result<Option<CertMode>, String> {
+    let mut last = None;
+    for option in options {
+        if option.key == "cert-mode" {
+            last = Some(parse_cert_mode(&option.value)?);
+        }
+    }
+    Ok(last)
+}
+
+fn parse_resolver_strategy_option(
+    options: &[sip003::Sip003Option],
+) -> Result<Option<ResolverStrategy>, String> {
+    let mut last = None;
+    for option in options {
+        if option.key == "resolver-strategy" {
+            last = Some(parse_resolver_strategy(&option.value)?);
+        }
+    }
+    Ok(last)
+}
+
+fn parse_resolver_health_window(options: &[sip003::Sip003Option]) -> Result<Option<u64>, String> {
+    let mut last = None;
+    for option in options {
+        if option.key == "resolver-health-window" {
+            let value = option.value.trim();
+            let parsed = value
+                .parse::<u64>()
+                .ok()
+                .filter(|window| *window > 0)
+                .ok_or_else(|| format!("Invalid resolver-health-window value: {}", value))?;
+            last = Some(parsed);
+        }
+    }
+    Ok(last)
+}
+
 fn parse_keep_alive_interval(options: &[sip003::Sip003Option]) -> Result<Option<u16>, String> {
     let mut last = None;
     for option in options {
@@ -377,6 +1296,31 @@ fn parse_keep_alive_interval(options: &[sip003::Sip003Option]) -> Result<Option<
     Ok(last)
 }
 
+fn parse_edns_udp_size(input: &str) -> Result<u16, String> {
+    let trimmed = input.trim();
+    let value = trimmed
+        .parse::<u16>()
+        .map_err(|_| format!("Invalid edns-udp-size value: {}", trimmed))?;
+    if value < slipstream_dns::MIN_EDNS_UDP_PAYLOAD {
+        return Err(format!(
+            "edns-udp-size must be between {} and 65535: {}",
+            slipstream_dns::MIN_EDNS_UDP_PAYLOAD,
+            trimmed
+        ));
+    }
+    Ok(value)
+}
+
+fn parse_edns_udp_size_option(options: &[sip003::Sip003Option]) -> Result<Option<u16>, String> {
+    let mut last = None;
+    for option in options {
+        if option.key == "edns-udp-size" {
+            last = Some(parse_edns_udp_size(&option.value)?);
+        }
+    }
+    Ok(last)
+}
+
 fn parse_idle_poll_interval(options: &[sip003::Sip003Option]) -> Result<Option<u64>, String> {
     let mut last = None;
     for option in options {
@@ -421,6 +1365,62 @@ mod tests {
         assert_eq!(resolvers[2].resolver.port, 5353);
     }
 
+    #[test]
+    fn resolvers_file_adds_to_cli_resolvers() {
+        let contents =
+            "# resolvers\n\n8.8.8.8\nauthoritative: 1.1.1.1 # primary\n[2001:db8::1]:5353\n";
+        let from_file = parse_resolvers_file(contents).expect("file should parse");
+        assert_eq!(from_file.len(), 3);
+        assert_eq!(from_file[0].mode, ResolverMode::Recursive);
+        assert_eq!(from_file[1].resolver.host, "1.1.1.1");
+        assert_eq!(from_file[1].mode, ResolverMode::Authoritative);
+        assert_eq!(from_file[2].resolver.port, 5353);
+
+        let err = parse_resolvers_file("1.1.1.1\n\nbad host:x\n").unwrap_err();
+        assert!(err.starts_with("line 3: "), "{}", err);
+
+        let path =
+            std::env::temp_dir().join(format!("slipstream-resolvers-{}.txt", std::process::id()));
+        std::fs::write(&path, contents).expect("write resolvers file");
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--resolver",
+                "1.1.1.1",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers_with_file(&matches, path.to_str());
+        std::fs::remove_file(&path).expect("remove resolvers file");
+        let resolvers = resolvers.expect("resolvers should merge");
+        let hosts: Vec<_> = resolvers
+            .iter()
+            .map(|spec| (spec.resolver.host.as_str(), spec.mode))
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                ("1.1.1.1", ResolverMode::Recursive),
+                ("8.8.8.8", ResolverMode::Recursive),
+                ("2001:db8::1", ResolverMode::Recursive),
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_edns_udp_size() {
+        assert_eq!(parse_edns_udp_size("512"), Ok(512));
+        assert_eq!(parse_edns_udp_size(" 65535 "), Ok(65535));
+        assert!(parse_edns_udp_size("511").is_err());
+        assert!(parse_edns_udp_size("65536").is_err());
+        let options = vec![sip003::Sip003Option {
+            key: "edns-udp-size".to_string(),
+            value: "4096".to_string(),
+        }];
+        assert_eq!(parse_edns_udp_size_option(&options), Ok(Some(4096)));
+    }
+
     #[test]
     fn maps_authoritative_first() {
         let matches = Args::command()
@@ -470,6 +1470,210 @@ mod tests {
         assert!(!parsed.authoritative_remote);
     }
 
+    #[test]
+    fn mixes_doh_with_udp_resolvers() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--domain",
+                "example.com",
+                "--doh",
+                "https://1.1.1.1/dns-query",
+                "--resolver",
+                "9.9.9.9",
+                "--doh",
+                "https://[2001:db8::1]:8443/resolve",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers(&matches, true).expect("resolvers should parse");
+        assert_eq!(resolvers.len(), 3);
+        assert_eq!(resolvers[0].resolver.host, "1.1.1.1");
+        assert_eq!(resolvers[0].resolver.port, 443);
+        assert_eq!(resolvers[0].mode, ResolverMode::Doh);
+        assert_eq!(resolvers[0].doh_path.as_deref(), Some("/dns-query"));
+        assert_eq!(resolvers[1].mode, ResolverMode::Recursive);
+        assert_eq!(resolvers[2].resolver.host, "2001:db8::1");
+        assert_eq!(resolvers[2].resolver.port, 8443);
+        assert_eq!(resolvers[2].doh_path.as_deref(), Some("/resolve"));
+
+        assert!(parse_doh_url("https://1.1.1.1/dns query").is_err());
+        assert!(parse_doh_url("https://1.1.1.1/dns\u{7f}").is_err());
+    }
+
+    #[test]
+    fn dot_resolvers_default_to_port_853() {
+        let matches = Args::command()
+            .try_get_matches_from([
+                "slipstream-client",
+                "--dot",
+                "9.9.9.9",
+                "--resolver",
+                "1.1.1.1",
+                "--dot",
+                "[2001:db8::1]:8853",
+                "--domain",
+                "example.com",
+            ])
+            .expect("matches should parse");
+        let resolvers = build_resolvers(&matches, true).expect("resolvers should parse");
+        assert_eq!(resolvers.len(), 3);
+        assert_eq!(resolvers[0].mode, ResolverMode::Dot);
+        assert_eq!(resolvers[0].resolver.port, 853);
+        assert_eq!(resolvers[1].mode, ResolverMode::Recursive);
+        assert_eq!(resolvers[2].mode, ResolverMode::Dot);
+        assert_eq!(resolvers[2].resolver.port, 8853);
+
+        let options = vec![sip003::Sip003Option {
+            key: "dot".to_string(),
+            value: "9.9.9.9,149.112.112.112:853".to_string(),
+        }];
+        let parsed = parse_resolvers_from_options(&options).expect("options should parse");
+        assert_eq!(parsed.resolvers.len(), 2);
+        assert!(parsed
+            .resolvers
+            .iter()
+            .all(|spec| spec.mode == ResolverMode::Dot && spec.resolver.port == 853));
+    }
+
+    #[test]
+    fn doh_url_requires_https() {
+        assert!(parse_doh_url("http://1.1.1.1/dns-query").is_err());
+        assert!(parse_doh_url("https:///dns-query").is_err());
+        let parsed = parse_doh_url("https://dns.example").expect("url should parse");
+        assert_eq!(parsed.path, "/dns-query");
+    }
+
+    #[test]
+    fn parses_plugin_doh_resolvers() {
+        let options = vec![
+            sip003::Sip003Option {
+                key: "doh".to_string(),
+                value: "https://1.1.1.1/dns-query".to_string(),
+            },
+            sip003::Sip003Option {
+                key: "resolver".to_string(),
+                value: "8.8.8.8".to_string(),
+            },
+        ];
+        let parsed = parse_resolvers_from_options(&options).expect("options should parse");
+        assert_eq!(parsed.resolvers.len(), 2);
+        assert_eq!(parsed.resolvers[0].mode, ResolverMode::Doh);
+        assert_eq!(parsed.resolvers[0].resolver.port, 443);
+        assert_eq!(parsed.resolvers[1].mode, ResolverMode::Recursive);
+    }
+
+    #[test]
+    fn plugin_qtype_overrides_default() {
+        let options = vec![
+            sip003::Sip003Option {
+                key: "qtype".to_string(),
+                value: "aaaa".to_string(),
+            },
+            sip003::Sip003Option {
+                key: "qtype".to_string(),
+                value: "CNAME".to_string(),
+            },
+        ];
+        let qtype = parse_qtype_option(&options)
+            .expect("options should parse")
+            .expect("qtype should exist");
+        assert_eq!(qtype, slipstream_dns::RR_CNAME);
+
+        let invalid = vec![sip003::Sip003Option {
+            key: "qtype".to_string(),
+            value: "mx".to_string(),
+        }];
+        assert!(parse_qtype_option(&invalid).is_err());
+    }
+
+    #[test]
+    fn sni_is_normalized_and_kept_apart_from_the_domain() {
+        let args = Args::try_parse_from([
+            "slipstream-client",
+            "--domain",
+            "tunnel.example.com",
+            "--sni",
+            "front.example.net.",
+        ])
+        .expect("args should parse");
+        assert_eq!(args.sni.as_deref(), Some("front.example.net"));
+        assert_eq!(args.domain.as_deref(), Some("tunnel.example.com"));
+        assert!(Args::try_parse_from([
+            "slipstream-client",
+            "--domain",
+            "tunnel.example.com",
+            "--sni",
+            "*.example.net",
+        ])
+        .is_err());
+
+        let options = vec![sip003::Sip003Option {
+            key: "sni".to_string(),
+            value: "front.example.net".to_string(),
+        }];
+        assert_eq!(
+            parse_sni_option(&options),
+            Ok(Some("front.example.net".to_string()))
+        );
+        assert_eq!(parse_sni_option(&[]), Ok(None));
+        let invalid = vec![sip003::Sip003Option {
+            key: "sni".to_string(),
+            value: ".".to_string(),
+        }];
+        assert!(parse_sni_option(&invalid).is_err());
+    }
+
+    #[test]
+    fn plugin_encoding_overrides_default() {
+        let options = vec![sip003::Sip003Option {
+            key: "encoding".to_string(),
+            value: "Base64URL".to_string(),
+        }];
+        let encoding = parse_encoding_option(&options)
+            .expect("options should parse")
+            .expect("encoding should exist");
+        assert_eq!(encoding, Encoding::Base64Url);
+
+        let invalid = vec![sip003::Sip003Option {
+            key: "encoding".to_string(),
+            value: "base64".to_string(),
+        }];
+        assert!(parse_encoding_option(&invalid).is_err());
+    }
+
+    #[test]
+    fn plugin_resolver_strategy_overrides_default() {
+        let options = vec![sip003::Sip003Option {
+            key: "resolver-strategy".to_string(),
+            value: "latency-weighted".to_string(),
+        }];
+        let strategy = parse_resolver_strategy_option(&options)
+            .expect("options should parse")
+            .expect("strategy should exist");
+        assert_eq!(strategy, ResolverStrategy::LatencyWeighted);
+
+        let invalid = vec![sip003::Sip003Option {
+            key: "resolver-strategy".to_string(),
+            value: "random".to_string(),
+        }];
+        assert!(parse_resolver_strategy_option(&invalid).is_err());
+    }
+
+    #[test]
+    fn plugin_resolver_health_window_rejects_zero() {
+        let options = vec![sip003::Sip003Option {
+            key: "resolver-health-window".to_string(),
+            value: "5000".to_string(),
+        }];
+        assert_eq!(parse_resolver_health_window(&options), Ok(Some(5000)));
+
+        let zero = vec![sip003::Sip003Option {
+            key: "resolver-health-window".to_string(),
+            value: "0".to_string(),
+        }];
+        assert!(parse_resolver_health_window(&zero).is_err());
+    }
+
     #[test]
     fn plugin_domain_single_entry() {
         let options = vec![sip003::Sip003Option {
@@ -516,4 +1720,22 @@ mod tests {
         assert!(parsed.resolvers.is_empty());
         assert!(parsed.authoritative_remote);
     }
+
+    #[test]
+    fn plugin_option_schema_accepts_client_keys_only() {
+        let schema = plugin_option_schema();
+        let options = sip003::parse_plugin_options(
+            "domain=example.com;resolver=1.1.1.1:53;authoritative;doh=https://1.1.1.1/dns-query;\
+             qtype=txt;listener-mode=socks5;keep-alive-interval=200;address-family=4",
+        )
+        .expect("options should parse");
+        assert_eq!(sip003::validate(&options, &schema), Ok(()));
+
+        let options =
+            sip003::parse_plugin_options("qtype=mx;max-connections=5;edns-udp-size=1;dot=")
+                .expect("options should parse");
+        let errors = sip003::validate(&options, &schema).unwrap_err();
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[1].contains("Unknown SS_PLUGIN_OPTIONS key: max-connections"));
+    }
 }
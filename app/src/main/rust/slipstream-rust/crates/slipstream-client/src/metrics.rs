@@ -0,0 +1,198 @@
+use crate::error::ClientError;
+use crate::streams::ClientState;
+use slipstream_ffi::picoquic::{
+    get_bytes_in_transit, get_cwin, get_pacing_rate, get_rtt, picoquic_cnx_t,
+};
+use std::time::Instant;
+use tokio::sync::watch;
+use tracing::warn;
+
+#[cfg(not(target_os = "android"))]
+use tokio::io::AsyncWriteExt;
+#[cfg(not(target_os = "android"))]
+use tokio::net::UnixListener;
+#[cfg(not(target_os = "android"))]
+use tracing::info;
+
+const METRICS_PUBLISH_INTERVAL_US: u64 = 250_000;
+
+/// Connection telemetry served to local tools.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct MetricsSnapshot {
+    pub(crate) connected: bool,
+    pub(crate) rtt_us: u64,
+    pub(crate) pacing_rate_bytes_per_sec: u64,
+    pub(crate) cwin_bytes: u64,
+    pub(crate) bytes_in_transit: u64,
+    pub(crate) active_streams: usize,
+    pub(crate) bytes_sent: u64,
+    pub(crate) bytes_received: u64,
+    pub(crate) uptime_secs: u64,
+}
+
+impl MetricsSnapshot {
+    pub(crate) fn to_json(self) -> String {
+        format!(
+            "{{\"connected\":{},\"rtt_us\":{},\"pacing_rate_bytes_per_sec\":{},\"cwin_bytes\":{},\
+             \"bytes_in_transit\":{},\"active_streams\":{},\"bytes_sent\":{},\
+             \"bytes_received\":{},\"uptime_secs\":{}}}",
+            self.connected,
+            self.rtt_us,
+            self.pacing_rate_bytes_per_sec,
+            self.cwin_bytes,
+            self.bytes_in_transit,
+            self.active_streams,
+            self.bytes_sent,
+            self.bytes_received,
+            self.uptime_secs
+        )
+    }
+}
+
+/// Samples the connection for `--metrics-socket` (or the JNI surface on Android).
+pub(crate) struct MetricsPublisher {
+    tx: Option<watch::Sender<MetricsSnapshot>>,
+    started_at: Instant,
+    last_publish_at: u64,
+}
+
+impl MetricsPublisher {
+    /// Binds `socket_path` and spawns the task answering each connection with one JSON line.
+    #[cfg(not(target_os = "android"))]
+    pub(crate) fn new(socket_path: Option<&str>) -> Result<Self, ClientError> {
+        let tx = match socket_path {
+            Some(path) => {
+                let listener = bind_metrics_socket(path)?;
+                let (tx, rx) = watch::channel(MetricsSnapshot::default());
+                info!("Serving metrics on {}", path);
+                tokio::spawn(serve_metrics(listener, rx));
+                Some(tx)
+            }
+            None => None,
+        };
+        Ok(Self {
+            tx,
+            started_at: Instant::now(),
+            last_publish_at: 0,
+        })
+    }
+
+    /// On Android snapshots always go to the JNI surface; there is no socket.
+    #[cfg(target_os = "android")]
+    pub(crate) fn new(socket_path: Option<&str>) -> Result<Self, ClientError> {
+        if socket_path.is_some() {
+            warn!("--metrics-socket is ignored on Android; use the JNI metrics surface");
+        }
+        Ok(Self {
+            tx: None,
+            started_at: Instant::now(),
+            last_publish_at: 0,
+        })
+    }
+
+    fn enabled(&self) -> bool {
+        self.tx.is_some() || cfg!(target_os = "android")
+    }
+
+    /// Publishes a fresh snapshot at most every 250ms; a null `cnx` reports a disconnect.
+    pub(crate) fn publish(&mut self, cnx: *mut picoquic_cnx_t, state: &ClientState, now: u64) {
+        if !self.enabled() {
+            return;
+        }
+        if !cnx.is_null() && now.saturating_sub(self.last_publish_at) < METRICS_PUBLISH_INTERVAL_US
+        {
+            return;
+        }
+        self.last_publish_at = now;
+        let (bytes_sent, bytes_received) = state.tunneled_bytes();
+        // SAFETY: the getters accept null, and a non-null cnx is the live connection.
+        let snapshot = unsafe {
+            MetricsSnapshot {
+                connected: !cnx.is_null() && state.is_ready(),
+                rtt_us: get_rtt(cnx),
+                pacing_rate_bytes_per_sec: get_pacing_rate(cnx),
+                cwin_bytes: get_cwin(cnx),
+                bytes_in_transit: get_bytes_in_transit(cnx),
+                active_streams: if cnx.is_null() {
+                    0
+                } else {
+                    state.streams_len()
+                },
+                bytes_sent,
+                bytes_received,
+                uptime_secs: self.started_at.elapsed().as_secs(),
+            }
+        };
+        if let Some(tx) = self.tx.as_ref() {
+            tx.send_replace(snapshot);
+        }
+        #[cfg(target_os = "android")]
+        {
+            crate::android::store_metrics(snapshot);
+            // SAFETY: get_path_quality accepts null, and a non-null cnx is the live connection.
+            crate::android::store_connection_stats(unsafe {
+                slipstream_ffi::picoquic::get_path_quality(cnx)
+            });
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+fn bind_metrics_socket(path: &str) -> Result<UnixListener, ClientError> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left behind by an earlier run would make bind fail; other files are kept.
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    UnixListener::bind(path)
+        .map_err(|err| ClientError::io(err, format_args!("Failed to bind metrics socket {}", path)))
+}
+
+#[cfg(not(target_os = "android"))]
+async fn serve_metrics(listener: UnixListener, rx: watch::Receiver<MetricsSnapshot>) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("metrics socket accept failed: {}", err);
+                continue;
+            }
+        };
+        let mut body = rx.borrow().to_json();
+        body.push('\n');
+        tokio::spawn(async move {
+            if let Err(err) = stream.write_all(body.as_bytes()).await {
+                warn!("metrics socket write failed: {}", err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_serializes_to_flat_json() {
+        let snapshot = MetricsSnapshot {
+            connected: true,
+            rtt_us: 85_000,
+            pacing_rate_bytes_per_sec: 40_000,
+            cwin_bytes: 15_000,
+            bytes_in_transit: 1_200,
+            active_streams: 3,
+            bytes_sent: 4_096,
+            bytes_received: 65_536,
+            uptime_secs: 42,
+        };
+        assert_eq!(
+            snapshot.to_json(),
+            "{\"connected\":true,\"rtt_us\":85000,\"pacing_rate_bytes_per_sec\":40000,\
+             \"cwin_bytes\":15000,\"bytes_in_transit\":1200,\"active_streams\":3,\
+             \"bytes_sent\":4096,\"bytes_received\":65536,\"uptime_secs\":42}"
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! Local address change detection and QUIC path migration.
+//!
+//! The primary address is the source address the OS routes toward the primary resolver,
+//! read back from a connected UDP socket (no packets are sent). When it changes, the client
+//! probes a new path from that address instead of tearing the connection down. With
+//! `--enable-migration` it also probes from a fresh local port when the primary resolver
+//! stops answering while the other paths do not (a resolver that alone goes quiet is left to
+//! the other paths), which is how a NAT rebinding on a mobile network change usually looks.
+//! The fresh socket only carries the probe until picoquic validates the new path; the
+//! tunnel keeps sending from its current socket until then.
+
+use crate::dns::{sockaddr_storage_to_socket_addr, ResolverState};
+use slipstream_ffi::picoquic::{
+    picoquic_cnx_t, picoquic_get_path_addr, picoquic_probe_new_path_ex,
+};
+use slipstream_ffi::socket_addr_to_storage;
+use slipstream_ffi::ResolverMode;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::net::UdpSocket as TokioUdpSocket;
+
+const NETWORK_CHECK_INTERVAL_US: u64 = 1_000_000;
+
+// Set by the platform (the Android JNI surface) to force an immediate check.
+static NETWORK_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Asks the monitor to re-read the primary address on its next poll.
+#[cfg(target_os = "android")]
+pub(crate) fn notify_network_changed() {
+    NETWORK_CHANGED.store(true, Ordering::SeqCst);
+}
+
+/// A UDP socket kept open to ask the OS which local address routes toward one target.
+struct RouteProbe {
+    socket: Socket,
+    target: SocketAddr,
+}
+
+impl RouteProbe {
+    fn new(target: SocketAddr) -> Option<Self> {
+        let (domain, unspecified) = match target {
+            SocketAddr::V4(_) => (Domain::IPV4, IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            SocketAddr::V6(_) => (Domain::IPV6, IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        };
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP)).ok()?;
+        socket
+            .bind(&SockAddr::from(SocketAddr::new(unspecified, 0)))
+            .ok()?;
+        // Route like the tunnel socket, which bypasses the VPN.
+        #[cfg(target_os = "android")]
+        {
+            if !crate::android::protect_socket(socket.as_raw_fd()) {
+                return None;
+            }
+        }
+        Some(Self { socket, target })
+    }
+
+    /// Returns the local address the OS would use to reach the target now.
+    fn local_ip(&self) -> Option<IpAddr> {
+        // A connected UDP socket keeps the source address it was given, so drop the old
+        // association first to have the route looked up again. The result is ignored: some
+        // platforms report an error for AF_UNSPEC but disconnect anyway.
+        let unspec = libc::sockaddr {
+            sa_family: libc::AF_UNSPEC as libc::sa_family_t,
+            ..unsafe { std::mem::zeroed() }
+        };
+        unsafe {
+            libc::connect(
+                self.socket.as_raw_fd(),
+                &unspec,
+                std::mem::size_of::<libc::sockaddr>() as libc::socklen_t,
+            );
+        }
+        self.socket.connect(&SockAddr::from(self.target)).ok()?;
+        let local = self.socket.local_addr().ok()?.as_socket()?;
+        let ip = local.ip();
+        (!ip.is_unspecified()).then_some(ip)
+    }
+}
+
+/// Watches the primary address for one connection.
+pub(crate) struct NetworkMonitor {
+    target: SocketAddr,
+    probe: Option<RouteProbe>,
+    current: Option<IpAddr>,
+    next_check_at: u64,
+}
+
+impl NetworkMonitor {
+    pub(crate) fn new(target: SocketAddr, now: u64) -> Self {
+        NETWORK_CHANGED.store(false, Ordering::SeqCst);
+        let probe = RouteProbe::new(target);
+        let current = probe.as_ref().and_then(RouteProbe::local_ip);
+        Self {
+            target,
+            probe,
+            current,
+            next_check_at: now.saturating_add(NETWORK_CHECK_INTERVAL_US),
+        }
+    }
+
+    /// The primary address as of the last poll.
+    pub(crate) fn current(&self) -> Option<IpAddr> {
+        self.current
+    }
+
+    /// Re-reads the primary address once per second (or when notified) through the same probe
+    /// socket and returns it if it changed since the last poll.
+    pub(crate) fn poll(&mut self, now: u64) -> Option<IpAddr> {
+        let notified = NETWORK_CHANGED.swap(false, Ordering::SeqCst);
+        if !notified && now < self.next_check_at {
+            return None;
+        }
+        self.next_check_at = now.saturating_add(NETWORK_CHECK_INTERVAL_US);
+        if self.probe.is_none() {
+            self.probe = RouteProbe::new(self.target);
+        }
+        let ip = self.probe.as_ref().and_then(RouteProbe::local_ip);
+        self.observe(ip)
+    }
+
+    fn observe(&mut self, ip: Option<IpAddr>) -> Option<IpAddr> {
+        // Losing the route is not a change to migrate to; wait for the next address.
+        let ip = ip?;
+        if self.current == Some(ip) {
+            return None;
+        }
+        self.current = Some(ip);
+        Some(ip)
+    }
+}
+
+/// True when the primary resolver is degraded and so is every other UDP path, which points
+/// at our own mapping rather than at the resolver. With a healthy path left, the selector
+/// moves polls over to it and picoquic keeps the connection on it instead.
+pub(crate) fn loss_looks_local(resolvers: &[ResolverState]) -> bool {
+    let Some((primary, others)) = resolvers.split_first() else {
+        return false;
+    };
+    primary.health.is_degraded()
+        && others
+            .iter()
+            .filter(|resolver| {
+                resolver.added
+                    && matches!(
+                        resolver.mode,
+                        ResolverMode::Recursive | ResolverMode::Authoritative
+                    )
+            })
+            .all(|resolver| resolver.health.is_degraded())
+}
+
+/// The tunnel socket, plus the fresh one a rebind probes from until its path validates.
+pub(crate) struct TunnelSockets {
+    current: TokioUdpSocket,
+    probing: Option<(TokioUdpSocket, u16)>,
+}
+
+impl TunnelSockets {
+    pub(crate) fn new(current: TokioUdpSocket) -> Self {
+        Self {
+            current,
+            probing: None,
+        }
+    }
+
+    pub(crate) fn current(&self) -> &TokioUdpSocket {
+        &self.current
+    }
+
+    /// Keeps `socket` open next to the current one while its path is probed.
+    pub(crate) fn start_probing(&mut self, socket: TokioUdpSocket, port: u16) {
+        self.probing = Some((socket, port));
+    }
+
+    /// Makes the probed socket the tunnel socket once its path validated.
+    pub(crate) fn promote(&mut self) {
+        if let Some((socket, _)) = self.probing.take() {
+            self.current = socket;
+        }
+    }
+
+    /// Closes the probed socket of a migration that never validated.
+    pub(crate) fn stop_probing(&mut self) {
+        self.probing = None;
+    }
+
+    /// The socket bound to the port picoquic chose as the packet's source.
+    pub(crate) fn for_source(&self, addr_from: &libc::sockaddr_storage) -> &TokioUdpSocket {
+        match &self.probing {
+            Some((socket, port))
+                if sockaddr_storage_to_socket_addr(addr_from)
+                    .is_ok_and(|from| from.port() == *port) =>
+            {
+                socket
+            }
+            _ => &self.current,
+        }
+    }
+
+    /// Receives on the probing socket; never resolves while there is none.
+    pub(crate) async fn recv_probe(
+        &self,
+        buf: &mut [u8],
+    ) -> std::io::Result<(usize, SocketAddr, u16)> {
+        match &self.probing {
+            Some((socket, port)) => {
+                let (size, peer) = socket.recv_from(buf).await?;
+                Ok((size, peer, *port))
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// A path probe from a new local address that must validate before its deadline.
+pub(crate) struct Migration {
+    pub(crate) local: SocketAddr,
+    pub(crate) deadline: u64,
+    /// The probe comes from a fresh socket on the same address, so only the port tells the
+    /// new path apart.
+    pub(crate) rebound: bool,
+}
+
+/// Probes a path to `resolver` from `local`; picoquic validates it with PATH_CHALLENGE.
+pub(crate) fn start_migration(
+    cnx: *mut picoquic_cnx_t,
+    resolver: &ResolverState,
+    local: SocketAddr,
+    rebound: bool,
+    now: u64,
+    timeout_us: u64,
+) -> Result<Migration, libc::c_int> {
+    let local_storage = socket_addr_to_storage(local);
+    let mut path_id: libc::c_int = -1;
+    let ret = unsafe {
+        picoquic_probe_new_path_ex(
+            cnx,
+            &resolver.storage as *const _ as *const libc::sockaddr,
+            &local_storage as *const _ as *const libc::sockaddr,
+            0,
+            now,
+            0,
+            &mut path_id,
+        )
+    };
+    if ret != 0 || path_id < 0 {
+        return Err(ret);
+    }
+    Ok(Migration {
+        local,
+        deadline: now.saturating_add(timeout_us),
+        rebound,
+    })
+}
+
+/// True once the resolver's validated path uses the migration's local address (and port,
+/// for a rebound socket).
+pub(crate) fn migration_complete(
+    cnx: *mut picoquic_cnx_t,
+    resolver: &ResolverState,
+    migration: &Migration,
+) -> bool {
+    let Some(unique_path_id) = resolver.unique_path_id else {
+        return false;
+    };
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let ret = unsafe { picoquic_get_path_addr(cnx, unique_path_id, 1, &mut storage) };
+    if ret != 0 {
+        return false;
+    }
+    sockaddr_storage_to_socket_addr(&storage).is_ok_and(|local| path_matches(migration, local))
+}
+
+fn path_matches(migration: &Migration, local: SocketAddr) -> bool {
+    local.ip() == migration.local.ip()
+        && (!migration.rebound || local.port() == migration.local.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{resolve_resolvers, MAX_CONSECUTIVE_FAILURES};
+    use slipstream_core::{AddressFamily, HostPort};
+    use slipstream_ffi::ResolverSpec;
+
+    #[test]
+    fn loopback_target_routes_from_loopback_on_every_read() {
+        let target = SocketAddr::from(([127, 0, 0, 1], 53));
+        let probe = RouteProbe::new(target).expect("probe socket");
+        assert_eq!(probe.local_ip(), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        // The same socket is reconnected for each read.
+        assert_eq!(probe.local_ip(), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    }
+
+    #[test]
+    fn monitor_reports_each_new_address_once() {
+        let target = SocketAddr::from(([127, 0, 0, 1], 53));
+        let mut monitor = NetworkMonitor::new(target, 0);
+        let wifi = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+        let cellular = IpAddr::V4(Ipv4Addr::new(10, 64, 3, 7));
+
+        assert_eq!(monitor.observe(Some(wifi)), Some(wifi));
+        assert_eq!(monitor.observe(Some(wifi)), None);
+        assert_eq!(monitor.observe(None), None);
+        assert_eq!(monitor.observe(Some(cellular)), Some(cellular));
+        // Steady state: the loopback route never changes.
+        monitor.current = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(monitor.poll(NETWORK_CHECK_INTERVAL_US), None);
+    }
+
+    #[test]
+    fn rebound_migrations_complete_only_on_the_new_port() {
+        let old = SocketAddr::from(([10, 64, 3, 7], 40_000));
+        let new = SocketAddr::from(([10, 64, 3, 7], 40_001));
+        let rebound = Migration {
+            local: new,
+            deadline: 0,
+            rebound: true,
+        };
+        assert!(path_matches(&rebound, new));
+        assert!(!path_matches(&rebound, old));
+
+        // A new address keeps its port, so only the address has to match.
+        let moved = Migration {
+            local: SocketAddr::from(([192, 168, 1, 20], 40_000)),
+            deadline: 0,
+            rebound: false,
+        };
+        assert!(path_matches(
+            &moved,
+            SocketAddr::from(([192, 168, 1, 20], 40_001))
+        ));
+        assert!(!path_matches(&moved, old));
+    }
+
+    #[test]
+    fn rebinds_only_when_every_udp_path_shows_loss() {
+        let specs: Vec<ResolverSpec> = [5341, 5342]
+            .iter()
+            .map(|&port| ResolverSpec {
+                resolver: HostPort {
+                    host: "127.0.0.1".to_string(),
+                    port,
+                    family: AddressFamily::V4,
+                    zone: None,
+                },
+                mode: ResolverMode::Recursive,
+                doh_path: None,
+            })
+            .collect();
+        let mut resolvers = resolve_resolvers(&specs, 900, false).expect("resolvers");
+        for resolver in resolvers.iter_mut() {
+            resolver.added = true;
+        }
+        assert!(!loss_looks_local(&resolvers));
+
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            resolvers[0].health.record_failure();
+        }
+        assert!(
+            !loss_looks_local(&resolvers),
+            "the other resolver still answers"
+        );
+        assert!(loss_looks_local(&resolvers[..1]));
+
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            resolvers[1].health.record_failure();
+        }
+        assert!(loss_looks_local(&resolvers));
+    }
+}
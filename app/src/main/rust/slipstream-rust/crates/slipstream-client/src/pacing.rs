@@ -1,10 +1,30 @@
 use slipstream_ffi::picoquic::picoquic_path_quality_t;
+use std::collections::HashMap;
 
 // Pacing gain tuning for the poll-based pacing loop.
 const PACING_GAIN_BASE: f64 = 1.0;
 const PACING_GAIN_PROBE: f64 = 1.25;
 const PACING_GAIN_EPSILON: f64 = 0.05;
 
+// Query rate self-tuning for the token bucket.
+const QUERY_TIMEOUT_US: u64 = 5_000_000;
+const RATE_WINDOW_US: u64 = 5_000_000;
+const RATE_WINDOW_MIN_QUERIES: u64 = 10;
+const RATE_INCREASE_QUIET_US: u64 = 30_000_000;
+const RATE_INCREASE_FACTOR: f64 = 1.1;
+
+// `--max-pacing-rate` holds this many microseconds of bytes, and logs at most this often.
+const PACING_CAP_BURST_US: f64 = 100_000.0;
+const PACING_CAP_LOG_INTERVAL_US: u64 = 10_000_000;
+
+// Adaptive keep-alive tuning for `--adaptive-keepalive`.
+const KEEP_ALIVE_RECOMPUTE_INTERVAL_US: u64 = 5_000_000;
+const KEEP_ALIVE_RTT_MULTIPLIER: u64 = 2;
+// Polls answered with a payload before the RTT estimate is trusted.
+const KEEP_ALIVE_WARMUP_POLLS: u64 = 10;
+// Smaller changes keep the current interval rather than churning picoquic's timer.
+const KEEP_ALIVE_MIN_CHANGE_PERCENT: u64 = 10;
+
 #[derive(Clone, Copy, Debug, Default)]
 pub(crate) struct PacingBudgetSnapshot {
     pub(crate) pacing_rate: u64,
@@ -101,3 +121,392 @@ pub(crate) fn inflight_packet_estimate(bytes_in_transit: u64, mtu: u32) -> usize
         packets as usize
     }
 }
+
+/// Caps the DNS query rate at `--max-query-rate`, holding up to one second of tokens.
+///
+/// The rate halves when more than 10% of the queries sent in a window go unanswered for
+/// five seconds, and grows by 10% (up to the maximum) after every 30 seconds without one.
+pub(crate) struct TokenBucket {
+    max_rate: f64,
+    min_rate: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill_at: u64,
+    outstanding: HashMap<u16, u64>,
+    window_started_at: u64,
+    window_sent: u64,
+    window_timeouts: u64,
+    last_timeout_at: u64,
+    last_increase_at: u64,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(max_rate: u32, min_rate: u32, now: u64) -> Self {
+        debug_assert!(
+            min_rate > 0 && min_rate <= max_rate,
+            "TokenBucket::new expects 0 < min_rate <= max_rate"
+        );
+        let max_rate = max_rate.max(1) as f64;
+        Self {
+            max_rate,
+            min_rate: (min_rate.max(1) as f64).min(max_rate),
+            rate: max_rate,
+            tokens: max_rate,
+            last_refill_at: now,
+            outstanding: HashMap::new(),
+            window_started_at: now,
+            window_sent: 0,
+            window_timeouts: 0,
+            last_timeout_at: now,
+            last_increase_at: now,
+        }
+    }
+
+    fn burst(&self) -> f64 {
+        self.rate.max(1.0)
+    }
+
+    fn refill(&mut self, now: u64) {
+        let elapsed_us = now.saturating_sub(self.last_refill_at);
+        self.last_refill_at = now.max(self.last_refill_at);
+        self.tokens = (self.tokens + self.rate * elapsed_us as f64 / 1_000_000.0).min(self.burst());
+    }
+
+    /// True when a query may be sent now.
+    pub(crate) fn ready(&mut self, now: u64) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+
+    /// Microseconds until the next token is available.
+    pub(crate) fn wait_us(&mut self, now: u64) -> u64 {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            return 0;
+        }
+        ((1.0 - self.tokens) * 1_000_000.0 / self.rate).ceil() as u64
+    }
+
+    /// Spends a token on query `id` and starts waiting for its response.
+    pub(crate) fn record_sent(&mut self, id: u16, now: u64) {
+        self.refill(now);
+        self.tokens = (self.tokens - 1.0).max(0.0);
+        self.outstanding.insert(id, now);
+        self.window_sent = self.window_sent.saturating_add(1);
+    }
+
+    pub(crate) fn record_answered(&mut self, id: u16) {
+        self.outstanding.remove(&id);
+    }
+
+    /// Expires unanswered queries and adjusts the rate; returns the new rate if it changed.
+    pub(crate) fn tune(&mut self, now: u64) -> Option<f64> {
+        let expire_before = now.saturating_sub(QUERY_TIMEOUT_US);
+        let before = self.outstanding.len();
+        self.outstanding
+            .retain(|_, sent_at| *sent_at > expire_before);
+        let timed_out = (before - self.outstanding.len()) as u64;
+        if timed_out > 0 {
+            self.window_timeouts = self.window_timeouts.saturating_add(timed_out);
+            self.last_timeout_at = now;
+        }
+
+        if now.saturating_sub(self.window_started_at) >= RATE_WINDOW_US {
+            let lossy = self.window_sent >= RATE_WINDOW_MIN_QUERIES
+                && self.window_timeouts.saturating_mul(10) > self.window_sent;
+            self.window_started_at = now;
+            self.window_sent = 0;
+            self.window_timeouts = 0;
+            if lossy && self.rate > self.min_rate {
+                self.rate = (self.rate / 2.0).max(self.min_rate);
+                self.tokens = self.tokens.min(self.burst());
+                self.last_increase_at = now;
+                return Some(self.rate);
+            }
+        }
+
+        let quiet_since = self.last_timeout_at.max(self.last_increase_at);
+        if self.rate < self.max_rate && now.saturating_sub(quiet_since) >= RATE_INCREASE_QUIET_US {
+            self.rate = (self.rate * RATE_INCREASE_FACTOR).min(self.max_rate);
+            self.last_increase_at = now;
+            return Some(self.rate);
+        }
+        None
+    }
+}
+
+/// Caps the QUIC bytes the send loop puts on the wire at `--max-pacing-rate` bytes per second.
+///
+/// Holds 100 ms worth of bytes. A packet may overdraw the bucket, since its size is only known
+/// once picoquic has built it; the next packet then waits for the debt to refill.
+pub(crate) struct PacingCap {
+    rate: f64,
+    tokens: f64,
+    last_refill_at: u64,
+    last_log_at: Option<u64>,
+}
+
+impl PacingCap {
+    pub(crate) fn new(bytes_per_sec: u64, now: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        Self {
+            rate,
+            tokens: Self::burst_for(rate),
+            last_refill_at: now,
+            last_log_at: None,
+        }
+    }
+
+    fn burst_for(rate: f64) -> f64 {
+        (rate * PACING_CAP_BURST_US / 1_000_000.0).max(1.0)
+    }
+
+    fn refill(&mut self, now: u64) {
+        let elapsed_us = now.saturating_sub(self.last_refill_at);
+        self.last_refill_at = now.max(self.last_refill_at);
+        self.tokens = (self.tokens + self.rate * elapsed_us as f64 / 1_000_000.0)
+            .min(Self::burst_for(self.rate));
+    }
+
+    /// True when the next packet may be sent now.
+    pub(crate) fn ready(&mut self, now: u64) -> bool {
+        self.refill(now);
+        self.tokens > 0.0
+    }
+
+    /// Microseconds until the bucket is out of debt.
+    pub(crate) fn wait_us(&mut self, now: u64) -> u64 {
+        self.refill(now);
+        if self.tokens > 0.0 {
+            return 0;
+        }
+        ((1.0 - self.tokens) * 1_000_000.0 / self.rate).ceil() as u64
+    }
+
+    pub(crate) fn record_sent(&mut self, bytes: usize, now: u64) {
+        self.refill(now);
+        self.tokens -= bytes as f64;
+    }
+
+    /// Notes that the cap held a packet back; true when that is worth logging, at most once
+    /// every 10 seconds.
+    pub(crate) fn note_limited(&mut self, now: u64) -> bool {
+        if self
+            .last_log_at
+            .is_some_and(|at| now.saturating_sub(at) < PACING_CAP_LOG_INTERVAL_US)
+        {
+            return false;
+        }
+        self.last_log_at = Some(now);
+        true
+    }
+}
+
+/// A keep-alive setting for picoquic from [`AdaptiveKeepAlive::update`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum KeepAliveChange {
+    Enable(u64),
+    Disable,
+}
+
+/// Rescales the picoquic keep-alive interval to the measured RTT.
+///
+/// Once the first few polls have been answered, the interval becomes
+/// `KEEP_ALIVE_RTT_MULTIPLIER × smoothed rtt` every few seconds, clamped to `--min-keep-alive`
+/// and `--max-keep-alive`, so high-latency paths stop sending keep-alives faster than they can
+/// be answered and fast paths send them sooner. While the tunnel polls continuously for open
+/// streams, every answer already keeps the connection alive, so keep-alives are turned off
+/// until it goes quiet again.
+pub(crate) struct AdaptiveKeepAlive {
+    min_interval_us: u64,
+    max_interval_us: u64,
+    current_us: u64,
+    enabled: bool,
+    next_at: u64,
+}
+
+impl AdaptiveKeepAlive {
+    /// Starts from `initial_us`, the static `--keep-alive-interval` picoquic was given.
+    pub(crate) fn new(
+        min_interval_us: u64,
+        max_interval_us: u64,
+        initial_us: u64,
+        now: u64,
+    ) -> Self {
+        Self {
+            min_interval_us,
+            max_interval_us: max_interval_us.max(min_interval_us),
+            current_us: initial_us,
+            enabled: true,
+            next_at: now.saturating_add(KEEP_ALIVE_RECOMPUTE_INTERVAL_US),
+        }
+    }
+
+    fn interval_for(&self, rtt_us: u64) -> u64 {
+        rtt_us
+            .saturating_mul(KEEP_ALIVE_RTT_MULTIPLIER)
+            .clamp(self.min_interval_us, self.max_interval_us)
+    }
+
+    /// Returns the change picoquic needs, if any. `answered_polls` counts the polls answered
+    /// with a payload on this connection, and `polling` says whether open streams keep the
+    /// tunnel polling.
+    pub(crate) fn update(
+        &mut self,
+        rtt_us: u64,
+        answered_polls: u64,
+        polling: bool,
+        now: u64,
+    ) -> Option<KeepAliveChange> {
+        if polling {
+            return std::mem::replace(&mut self.enabled, false).then_some(KeepAliveChange::Disable);
+        }
+        let resumed = !std::mem::replace(&mut self.enabled, true);
+        if answered_polls >= KEEP_ALIVE_WARMUP_POLLS && now >= self.next_at {
+            self.next_at = now.saturating_add(KEEP_ALIVE_RECOMPUTE_INTERVAL_US);
+            let interval_us = self.interval_for(rtt_us);
+            let change_us = interval_us.abs_diff(self.current_us);
+            if change_us.saturating_mul(100)
+                > self
+                    .current_us
+                    .saturating_mul(KEEP_ALIVE_MIN_CHANGE_PERCENT)
+            {
+                self.current_us = interval_us;
+                return Some(KeepAliveChange::Enable(interval_us));
+            }
+        }
+        resumed.then_some(KeepAliveChange::Enable(self.current_us))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND_US: u64 = 1_000_000;
+
+    #[test]
+    fn pacing_cap_lets_one_packet_overdraw_and_then_waits() {
+        let mut cap = PacingCap::new(10_000, 0);
+        assert!(cap.ready(0));
+        cap.record_sent(900, 0);
+        assert!(cap.ready(0));
+        cap.record_sent(900, 0);
+        // 1000 bytes of burst minus 1800 sent leaves 800 bytes of debt: 80 ms at 10 kB/s.
+        assert!(!cap.ready(0));
+        assert_eq!(cap.wait_us(0), 80_100);
+        assert!(cap.ready(80_100));
+        // Idle time never banks more than 100 ms of bytes.
+        cap.ready(10 * SECOND_US);
+        assert_eq!(cap.tokens, 1_000.0);
+
+        assert!(cap.note_limited(SECOND_US));
+        assert!(!cap.note_limited(SECOND_US + PACING_CAP_LOG_INTERVAL_US - 1));
+        assert!(cap.note_limited(SECOND_US + PACING_CAP_LOG_INTERVAL_US));
+    }
+
+    #[test]
+    fn bucket_holds_one_second_of_tokens() {
+        let mut bucket = TokenBucket::new(4, 1, 0);
+        for id in 0..4 {
+            assert!(bucket.ready(0));
+            bucket.record_sent(id, 0);
+        }
+        assert!(!bucket.ready(0));
+        assert_eq!(bucket.wait_us(0), SECOND_US / 4);
+        assert!(bucket.ready(SECOND_US / 4));
+        // Idle time never banks more than one second of queries.
+        bucket.ready(10 * SECOND_US);
+        assert_eq!(bucket.tokens, 4.0);
+    }
+
+    #[test]
+    fn lossy_window_halves_rate_down_to_minimum() {
+        let mut bucket = TokenBucket::new(40, 15, 0);
+        for id in 0..20 {
+            bucket.record_sent(id, 0);
+        }
+        for id in 0..17 {
+            bucket.record_answered(id);
+        }
+        assert_eq!(bucket.tune(RATE_WINDOW_US - 1), None);
+        assert_eq!(bucket.tune(RATE_WINDOW_US), Some(20.0));
+
+        for id in 100..120 {
+            bucket.record_sent(id, RATE_WINDOW_US);
+        }
+        assert_eq!(bucket.tune(2 * RATE_WINDOW_US), Some(15.0));
+
+        // Exactly 10% lost is tolerated.
+        for id in 200..220 {
+            bucket.record_sent(id, 2 * RATE_WINDOW_US);
+        }
+        for id in 202..220 {
+            bucket.record_answered(id);
+        }
+        assert_eq!(bucket.tune(3 * RATE_WINDOW_US), None);
+        assert_eq!(bucket.rate, 15.0);
+    }
+
+    #[test]
+    fn quiet_period_grows_rate_back_to_maximum() {
+        let mut bucket = TokenBucket::new(40, 1, 0);
+        bucket.rate = 20.0;
+        assert_eq!(bucket.tune(RATE_INCREASE_QUIET_US - 1), None);
+        assert_eq!(
+            bucket.tune(RATE_INCREASE_QUIET_US).map(f64::round),
+            Some(22.0)
+        );
+        assert_eq!(bucket.tune(RATE_INCREASE_QUIET_US + 1), None);
+
+        bucket.rate = 39.0;
+        assert_eq!(bucket.tune(2 * RATE_INCREASE_QUIET_US), Some(40.0));
+        assert_eq!(bucket.tune(3 * RATE_INCREASE_QUIET_US), None);
+    }
+
+    #[test]
+    fn keep_alive_scales_with_rtt_within_the_bounds() {
+        let keep_alive = AdaptiveKeepAlive::new(400_000, 30_000_000, 400_000, 0);
+        assert_eq!(keep_alive.interval_for(50_000), 400_000);
+        assert_eq!(keep_alive.interval_for(500_000), 1_000_000);
+        assert_eq!(keep_alive.interval_for(60_000_000), 30_000_000);
+    }
+
+    #[test]
+    fn keep_alive_waits_for_answered_polls_and_ignores_small_changes() {
+        let mut keep_alive = AdaptiveKeepAlive::new(200_000, 30_000_000, 400_000, 0);
+        let due = KEEP_ALIVE_RECOMPUTE_INTERVAL_US;
+        let warm = KEEP_ALIVE_WARMUP_POLLS;
+        assert_eq!(keep_alive.update(500_000, warm - 1, false, due), None);
+        assert_eq!(keep_alive.update(500_000, warm, false, due - 1), None);
+        assert_eq!(
+            keep_alive.update(500_000, warm, false, due),
+            Some(KeepAliveChange::Enable(1_000_000))
+        );
+        // Not due again yet.
+        assert_eq!(keep_alive.update(250_000, warm, false, due + 1), None);
+        // 1.1s is exactly 10% above the current 1s, which is not enough.
+        assert_eq!(keep_alive.update(550_000, warm, false, 2 * due), None);
+        assert_eq!(
+            keep_alive.update(50_000, warm, false, 3 * due),
+            Some(KeepAliveChange::Enable(200_000))
+        );
+    }
+
+    #[test]
+    fn keep_alive_is_off_while_streams_keep_the_tunnel_polling() {
+        let mut keep_alive = AdaptiveKeepAlive::new(200_000, 30_000_000, 400_000, 0);
+        let warm = KEEP_ALIVE_WARMUP_POLLS;
+        assert_eq!(
+            keep_alive.update(500_000, 0, true, 1),
+            Some(KeepAliveChange::Disable)
+        );
+        assert_eq!(keep_alive.update(500_000, warm, true, 2), None);
+        // Back to the interval it had once the streams close.
+        assert_eq!(
+            keep_alive.update(500_000, warm, false, 3),
+            Some(KeepAliveChange::Enable(400_000))
+        );
+        assert_eq!(keep_alive.update(500_000, warm, false, 4), None);
+    }
+}
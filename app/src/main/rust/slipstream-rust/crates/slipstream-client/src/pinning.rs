@@ -1,14 +1,27 @@
+use crate::error::ClientError;
 use libc::{c_char, c_int, c_void, size_t};
+use openssl::base64;
+use openssl::error::ErrorStack;
 use openssl::hash::MessageDigest;
 use openssl::pkey::{Id, PKey, Public};
 use openssl::rsa::Padding;
+use openssl::sha::sha256;
 use openssl::sign::{RsaPssSaltlen, Verifier};
-use openssl::x509::X509;
+use openssl::stack::Stack;
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::verify::{X509CheckFlags, X509VerifyFlags, X509VerifyParam};
+use openssl::x509::{X509StoreContext, X509};
 use slipstream_ffi::picoquic::{
-    picoquic_quic_t, picoquic_set_verify_certificate_callback, ptls_iovec_t, ptls_t,
-    ptls_verify_certificate_t, ptls_verify_sign_cb_fn,
+    picoquic_quic_t, picoquic_set_cipher_suite, picoquic_set_verify_certificate_callback,
+    ptls_iovec_t, ptls_t, ptls_verify_certificate_t, ptls_verify_sign_cb_fn,
+    PICOQUIC_AES_128_GCM_SHA256, PICOQUIC_AES_256_GCM_SHA384, PICOQUIC_CHACHA20_POLY1305_SHA256,
 };
+use slipstream_ffi::{take_crypto_errors, CertMode, TlsCipher, TlsVersion};
+use std::ffi::CStr;
 use std::fs;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
 
 const SIG_RSA_PKCS1_SHA256: u16 = 0x0401;
 const SIG_RSA_PKCS1_SHA384: u16 = 0x0501;
@@ -26,6 +39,43 @@ const SIG_RSA_PSS_PSS_SHA384: u16 = 0x080A;
 const SIG_RSA_PSS_PSS_SHA512: u16 = 0x080B;
 const SIG_ALGO_SENTINEL: u16 = 0xFFFF;
 
+/// `X509_V_ERR_HOSTNAME_MISMATCH` and `X509_V_ERR_IP_ADDRESS_MISMATCH` from `x509_vfy.h`.
+const X509_V_ERR_HOSTNAME_MISMATCH: c_int = 62;
+const X509_V_ERR_IP_ADDRESS_MISMATCH: c_int = 64;
+
+const SPKI_PIN_PREFIX: &str = "sha256//";
+
+/// The cipher suites `--tls-ciphers` accepts: the TLS 1.3 suites QUIC defines packet
+/// protection for.
+const TLS_CIPHERS: [(&str, TlsCipher); 3] = [
+    ("TLS_AES_128_GCM_SHA256", TlsCipher::Aes128GcmSha256),
+    ("TLS_AES_256_GCM_SHA384", TlsCipher::Aes256GcmSha384),
+    (
+        "TLS_CHACHA20_POLY1305_SHA256",
+        TlsCipher::Chacha20Poly1305Sha256,
+    ),
+];
+
+/// Why one QUIC context's `--cert` verifier turned down server certificates since the last
+/// `take_handshake_error`; picoquic only sees a failed callback.
+#[derive(Debug, Clone, Default)]
+pub struct CertRejections(Arc<Mutex<Vec<String>>>);
+
+impl CertRejections {
+    fn push(&self, reason: String) {
+        if let Ok(mut rejections) = self.0.lock() {
+            rejections.push(reason);
+        }
+    }
+
+    fn take(&self) -> Vec<String> {
+        self.0
+            .lock()
+            .map(|mut rejections| std::mem::take(&mut *rejections))
+            .unwrap_or_default()
+    }
+}
+
 static PINNING_ALGOS: [u16; 15] = [
     SIG_ED25519,
     SIG_ED448,
@@ -47,26 +97,221 @@ static PINNING_ALGOS: [u16; 15] = [
 #[repr(C)]
 struct PinnedCertVerifier {
     super_ctx: ptls_verify_certificate_t,
-    pinned_der: Vec<u8>,
-    pkey: PKey<Public>,
+    pins: Vec<CertPin>,
+    rejections: CertRejections,
+}
+
+#[repr(C)]
+struct ChainVerifier {
+    super_ctx: ptls_verify_certificate_t,
+    anchors: Arc<Vec<X509>>,
+    rejections: CertRejections,
+}
+
+/// How `--cert` is enforced, loaded once and reapplied to every QUIC context.
+pub enum CertCheck {
+    /// `--cert-mode leaf-pin`: the server leaf must match one of these pins.
+    Pins(Vec<CertPin>),
+    /// `--cert-mode chain-verify`: the server chain must lead to one of `anchors`.
+    Chain { anchors: Arc<Vec<X509>> },
+}
+
+/// Loads `--cert` for `mode`.
+pub fn load_cert_check(value: &str, mode: CertMode) -> Result<CertCheck, ClientError> {
+    match mode {
+        CertMode::LeafPin => load_cert_pins(value).map(CertCheck::Pins),
+        CertMode::ChainVerify => {
+            let anchors = load_trust_anchors(value)?;
+            Ok(CertCheck::Chain {
+                anchors: Arc::new(anchors),
+            })
+        }
+    }
+}
+
+/// Installs `check` on `quic`; the returned handle collects that context's rejections.
+pub fn configure_cert_check(
+    quic: *mut picoquic_quic_t,
+    check: &CertCheck,
+) -> Result<CertRejections, ClientError> {
+    match check {
+        CertCheck::Pins(pins) => configure_pinned_certificate(quic, pins),
+        CertCheck::Chain { anchors } => configure_chain_verifier(quic, Arc::clone(anchors)),
+    }
+}
+
+pub fn tls_version_label(version: TlsVersion) -> &'static str {
+    match version {
+        TlsVersion::Tls12 => "1.2",
+        TlsVersion::Tls13 => "1.3",
+    }
+}
+
+pub fn tls_cipher_name(cipher: TlsCipher) -> &'static str {
+    TLS_CIPHERS
+        .iter()
+        .find(|(_, known)| *known == cipher)
+        .map(|(name, _)| *name)
+        .unwrap_or("unknown")
+}
+
+/// Parses the comma-separated `--tls-ciphers` list, dropping repeats.
+///
+/// picoquic offers either one cipher suite or all of them, so a list naming exactly two is
+/// rejected rather than silently widened.
+pub fn parse_tls_ciphers(list: &str) -> Result<Vec<TlsCipher>, ClientError> {
+    let mut ciphers = Vec::new();
+    for entry in list.split(',').map(str::trim) {
+        let Some((_, cipher)) = TLS_CIPHERS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(entry))
+        else {
+            return Err(ClientError::config(format!(
+                "Unsupported TLS cipher suite {:?} (expected {})",
+                entry,
+                TLS_CIPHERS.map(|(name, _)| name).join(", ")
+            )));
+        };
+        if !ciphers.contains(cipher) {
+            ciphers.push(*cipher);
+        }
+    }
+    cipher_suite_id(&ciphers)?;
+    Ok(ciphers)
+}
+
+/// The picoquic cipher suite id for `ciphers`: 0 for the full set, else the one IANA id.
+fn cipher_suite_id(ciphers: &[TlsCipher]) -> Result<c_int, ClientError> {
+    match ciphers {
+        [] => Ok(0),
+        [cipher] => Ok(match cipher {
+            TlsCipher::Aes128GcmSha256 => PICOQUIC_AES_128_GCM_SHA256,
+            TlsCipher::Aes256GcmSha384 => PICOQUIC_AES_256_GCM_SHA384,
+            TlsCipher::Chacha20Poly1305Sha256 => PICOQUIC_CHACHA20_POLY1305_SHA256,
+        }),
+        _ if ciphers.len() == TLS_CIPHERS.len() => Ok(0),
+        _ => Err(ClientError::config(
+            "TLS cipher list must name one cipher suite or all of them",
+        )),
+    }
+}
+
+/// Applies `--tls-min-version` and `--tls-ciphers` to a QUIC context.
+///
+/// QUIC only runs over TLS 1.3, so a 1.2 minimum changes nothing on the wire; it is logged
+/// so a diagnostic run shows what was asked for.
+///
+/// # Safety
+/// `quic` must be null or point to a valid picoquic context for the duration of the call.
+pub unsafe fn configure_tls_params(
+    quic: *mut picoquic_quic_t,
+    min_version: TlsVersion,
+    ciphers: &[TlsCipher],
+) -> Result<(), ClientError> {
+    if quic.is_null() {
+        return Err(ClientError::quic(0, "QUIC context is null"));
+    }
+    if min_version != TlsVersion::Tls13 {
+        debug!(
+            "TLS minimum {} requested; QUIC negotiates TLS 1.3",
+            tls_version_label(min_version)
+        );
+    }
+    let suite = cipher_suite_id(ciphers)?;
+    if suite == 0 {
+        return Ok(());
+    }
+    // SAFETY: caller guarantees quic is a valid picoquic context.
+    if unsafe { picoquic_set_cipher_suite(quic, suite) } != 0 {
+        return Err(ClientError::config(format!(
+            "TLS cipher suite {} is not available in this build",
+            tls_cipher_name(ciphers[0])
+        )));
+    }
+    Ok(())
+}
+
+/// One accepted server leaf; `--cert` may configure several to allow rotation.
+#[derive(Clone)]
+pub enum CertPin {
+    /// The exact leaf DER loaded from a PEM file.
+    Der { der: Vec<u8>, pkey: PKey<Public> },
+    /// SHA-256 of the leaf's SubjectPublicKeyInfo, so the leaf can be reissued with the same key.
+    SpkiSha256([u8; 32]),
+}
+
+impl CertPin {
+    /// Returns the key the leaf must prove possession of, or None if the leaf does not match.
+    fn matching_key(&self, leaf_der: &[u8]) -> Option<PKey<Public>> {
+        match self {
+            CertPin::Der { der, pkey } => (leaf_der == der.as_slice()).then(|| pkey.clone()),
+            CertPin::SpkiSha256(expected) => {
+                let pkey = X509::from_der(leaf_der).ok()?.public_key().ok()?;
+                let spki = pkey.public_key_to_der().ok()?;
+                (sha256(&spki) == *expected).then_some(pkey)
+            }
+        }
+    }
+}
+
+/// Parses a `--cert` value: a comma-separated list of PEM bundle paths and `sha256//<base64>`
+/// SPKI pins. Every certificate in every bundle becomes a pin.
+pub fn load_cert_pins(value: &str) -> Result<Vec<CertPin>, ClientError> {
+    let mut pins = Vec::new();
+    for entry in value.split(',').map(str::trim) {
+        if entry.is_empty() {
+            return Err(ClientError::config(format!(
+                "Empty entry in cert list {}",
+                value
+            )));
+        }
+        if entry.starts_with(SPKI_PIN_PREFIX) {
+            pins.push(CertPin::SpkiSha256(parse_spki_pin(entry)?));
+        } else {
+            pins.extend(
+                load_pinned_cert(entry)?
+                    .into_iter()
+                    .map(|(der, pkey)| CertPin::Der { der, pkey }),
+            );
+        }
+    }
+    Ok(pins)
+}
+
+fn parse_spki_pin(value: &str) -> Result<[u8; 32], ClientError> {
+    let encoded = value.strip_prefix(SPKI_PIN_PREFIX).unwrap_or(value);
+    let digest = base64::decode_block(encoded)
+        .map_err(|err| ClientError::config(format!("Invalid SPKI pin {}: {}", value, err)))?;
+    digest.try_into().map_err(|digest: Vec<u8>| {
+        ClientError::config(format!(
+            "Invalid SPKI pin {}: expected 32 bytes, got {}",
+            value,
+            digest.len()
+        ))
+    })
 }
 
 pub fn configure_pinned_certificate(
     quic: *mut picoquic_quic_t,
-    cert_path: &str,
-) -> Result<(), String> {
+    pins: &[CertPin],
+) -> Result<CertRejections, ClientError> {
     if quic.is_null() {
-        return Err("QUIC context is null".to_string());
+        return Err(ClientError::quic(0, "QUIC context is null"));
+    }
+    if pins.is_empty() {
+        return Err(ClientError::config(
+            "At least one pinned certificate is required",
+        ));
     }
-    let (pinned_der, pkey) = load_pinned_cert(cert_path)?;
     let verifier = Box::new(PinnedCertVerifier {
         super_ctx: ptls_verify_certificate_t {
             cb: Some(pinned_verify_certificate),
             algos: PINNING_ALGOS.as_ptr(),
         },
-        pinned_der,
-        pkey,
+        pins: pins.to_vec(),
+        rejections: CertRejections::default(),
     });
+    let rejections = verifier.rejections.clone();
     let raw = Box::into_raw(verifier);
     // SAFETY: `quic` is a valid context, and the verifier pointer remains alive until picoquic
     // calls the provided free callback.
@@ -77,25 +322,118 @@ pub fn configure_pinned_certificate(
             Some(pinned_verify_free),
         );
     }
-    Ok(())
+    Ok(rejections)
+}
+
+/// Loads the chain-verify anchors from a comma-separated list of PEM bundles. Every
+/// certificate in them is an anchor, so a bundle holding only an intermediate CA trusts the
+/// chains below it.
+pub fn load_trust_anchors(value: &str) -> Result<Vec<X509>, ClientError> {
+    let mut anchors = Vec::new();
+    for entry in value.split(',').map(str::trim) {
+        if entry.is_empty() {
+            return Err(ClientError::config(format!(
+                "Empty entry in cert list {}",
+                value
+            )));
+        }
+        if entry.starts_with(SPKI_PIN_PREFIX) {
+            return Err(ClientError::config(format!(
+                "SPKI pin {} requires --cert-mode leaf-pin",
+                entry
+            )));
+        }
+        anchors.extend(load_cert_bundle(entry)?);
+    }
+    Ok(anchors)
+}
+
+/// A store trusting `anchors` that also requires the leaf to name `server_name`, following
+/// RFC 6125: the subject CN only counts when the leaf has no DNS subjectAltName.
+fn trust_store(anchors: &[X509], server_name: &str) -> Result<X509Store, ErrorStack> {
+    let mut param = X509VerifyParam::new()?;
+    param.set_hostflags(X509CheckFlags::NO_PARTIAL_WILDCARDS);
+    let server_name = server_name.trim_end_matches('.');
+    match server_name.parse::<IpAddr>() {
+        Ok(ip) => param.set_ip(ip)?,
+        Err(_) => param.set_host(server_name)?,
+    }
+    let mut builder = X509StoreBuilder::new()?;
+    builder.set_flags(X509VerifyFlags::PARTIAL_CHAIN)?;
+    builder.set_param(&param)?;
+    for anchor in anchors {
+        builder.add_cert(anchor.clone())?;
+    }
+    Ok(builder.build())
+}
+
+fn configure_chain_verifier(
+    quic: *mut picoquic_quic_t,
+    anchors: Arc<Vec<X509>>,
+) -> Result<CertRejections, ClientError> {
+    if quic.is_null() {
+        return Err(ClientError::quic(0, "QUIC context is null"));
+    }
+    let verifier = Box::new(ChainVerifier {
+        super_ctx: ptls_verify_certificate_t {
+            cb: Some(chain_verify_certificate),
+            algos: PINNING_ALGOS.as_ptr(),
+        },
+        anchors,
+        rejections: CertRejections::default(),
+    });
+    let rejections = verifier.rejections.clone();
+    let raw = Box::into_raw(verifier);
+    // SAFETY: `quic` is a valid context, and the verifier pointer remains alive until picoquic
+    // calls the provided free callback.
+    unsafe {
+        picoquic_set_verify_certificate_callback(
+            quic,
+            &mut (*raw).super_ctx,
+            Some(chain_verify_free),
+        );
+    }
+    Ok(rejections)
+}
+
+/// A pinned leaf's DER and the public key it carries.
+pub(crate) type PinnedCert = (Vec<u8>, PKey<Public>);
+
+/// Loads every certificate in a PEM bundle; an empty bundle is an error.
+pub(crate) fn load_pinned_cert(cert_path: &str) -> Result<Vec<PinnedCert>, ClientError> {
+    load_cert_bundle(cert_path)?
+        .iter()
+        .map(|cert| Ok((cert.to_der()?, cert.public_key()?)))
+        .collect()
 }
 
-fn load_pinned_cert(cert_path: &str) -> Result<(Vec<u8>, PKey<Public>), String> {
-    let pem =
-        fs::read(cert_path).map_err(|err| format!("Failed to read cert {}: {}", cert_path, err))?;
-    let mut certs = X509::stack_from_pem(&pem)
-        .map_err(|err| format!("Failed to parse cert {}: {}", cert_path, err))?;
-    if certs.len() != 1 {
-        return Err("Pinned cert must contain exactly one certificate".to_string());
+fn load_cert_bundle(cert_path: &str) -> Result<Vec<X509>, ClientError> {
+    let pem = fs::read(cert_path)
+        .map_err(|err| ClientError::io(err, format_args!("Failed to read cert {}", cert_path)))?;
+    let certs = X509::stack_from_pem(&pem).map_err(|err| {
+        ClientError::config(format!("Failed to parse cert {}: {}", cert_path, err))
+    })?;
+    if certs.is_empty() {
+        return Err(ClientError::config(format!(
+            "Pinned cert {} contains no certificates",
+            cert_path
+        )));
     }
-    let cert = certs.remove(0);
-    let der = cert
-        .to_der()
-        .map_err(|err| format!("Failed to convert cert to DER: {}", err))?;
-    let pkey = cert
-        .public_key()
-        .map_err(|err| format!("Failed to extract public key: {}", err))?;
-    Ok((der, pkey))
+    Ok(certs)
+}
+
+fn reject(rejections: &CertRejections, reason: String) -> c_int {
+    warn!("{}", reason);
+    rejections.push(reason);
+    -1
+}
+
+/// Drains what a failed handshake left behind, the context's certificate rejections first,
+/// then picoquic's crypto errors; `None` when there is nothing to report.
+pub(crate) fn take_handshake_error(rejections: Option<&CertRejections>) -> Option<ClientError> {
+    let mut errors = rejections.map(CertRejections::take).unwrap_or_default();
+    errors.extend(take_crypto_errors());
+    (!errors.is_empty()).then_some(ClientError::CryptoErrors(errors))
 }
 
 unsafe extern "C" fn pinned_verify_free(ctx: *mut ptls_verify_certificate_t) {
@@ -125,18 +463,114 @@ unsafe extern "C" fn pinned_verify_certificate(
         return -1;
     }
     let leaf_bytes = std::slice::from_raw_parts(leaf.base as *const u8, leaf.len);
-    if leaf_bytes != verifier.pinned_der.as_slice() {
+    let Some(pkey) = verifier
+        .pins
+        .iter()
+        .find_map(|pin| pin.matching_key(leaf_bytes))
+    else {
+        return reject(
+            &verifier.rejections,
+            format!(
+                "server certificate matches none of the {} --cert pins",
+                verifier.pins.len()
+            ),
+        );
+    };
+    if verify_sign.is_null() || verify_sign_ctx.is_null() {
         return -1;
     }
-    if !verify_sign.is_null() {
-        *verify_sign = Some(pinned_verify_sign);
+    // picotls calls verify_sign exactly once per handshake (with empty buffers when the
+    // handshake ends before CertificateVerify), which releases this key.
+    *verify_sign = Some(pinned_verify_sign);
+    *verify_sign_ctx = Box::into_raw(Box::new(pkey)) as *mut c_void;
+    0
+}
+
+unsafe extern "C" fn chain_verify_free(ctx: *mut ptls_verify_certificate_t) {
+    if ctx.is_null() {
+        return;
+    }
+    let _ = Box::from_raw(ctx as *mut ChainVerifier);
+}
+
+unsafe extern "C" fn chain_verify_certificate(
+    self_ptr: *mut ptls_verify_certificate_t,
+    _tls: *mut ptls_t,
+    server_name: *const c_char,
+    verify_sign: *mut ptls_verify_sign_cb_fn,
+    verify_sign_ctx: *mut *mut c_void,
+    certs: *mut ptls_iovec_t,
+    num_certs: size_t,
+) -> c_int {
+    if self_ptr.is_null() || server_name.is_null() || certs.is_null() || num_certs == 0 {
+        return -1;
+    }
+    let verifier = &*(self_ptr as *const ChainVerifier);
+    let Ok(server_name) = CStr::from_ptr(server_name).to_str() else {
+        return -1;
+    };
+    // SAFETY: picotls supplies a valid certificate chain for the duration of the callback.
+    let certs = std::slice::from_raw_parts(certs, num_certs);
+    if certs
+        .iter()
+        .any(|cert| cert.base.is_null() || cert.len == 0)
+    {
+        return -1;
     }
-    if !verify_sign_ctx.is_null() {
-        *verify_sign_ctx = self_ptr as *mut c_void;
+    let chain: Vec<&[u8]> = certs
+        .iter()
+        .map(|cert| std::slice::from_raw_parts(cert.base as *const u8, cert.len))
+        .collect();
+    let pkey = match verify_chain(&verifier.anchors, &chain, server_name) {
+        Ok(pkey) => pkey,
+        Err(err) => {
+            return reject(
+                &verifier.rejections,
+                format!("server certificate rejected: {}", err),
+            )
+        }
+    };
+    if verify_sign.is_null() || verify_sign_ctx.is_null() {
+        return -1;
     }
+    // Same contract as the pinned verifier: picotls calls verify_sign exactly once.
+    *verify_sign = Some(pinned_verify_sign);
+    *verify_sign_ctx = Box::into_raw(Box::new(pkey)) as *mut c_void;
     0
 }
 
+/// Verifies `chain` (leaf first, as the server sent it) up to one of `anchors` and checks
+/// that the leaf names `server_name`; returns the leaf's public key.
+fn verify_chain(
+    anchors: &[X509],
+    chain: &[&[u8]],
+    server_name: &str,
+) -> Result<PKey<Public>, String> {
+    let store = trust_store(anchors, server_name).map_err(|err| err.to_string())?;
+    let (leaf, rest) = chain.split_first().ok_or("empty certificate chain")?;
+    let leaf = X509::from_der(leaf).map_err(|err| err.to_string())?;
+    let mut intermediates = Stack::new().map_err(|err| err.to_string())?;
+    for der in rest {
+        let cert = X509::from_der(der).map_err(|err| err.to_string())?;
+        intermediates.push(cert).map_err(|err| err.to_string())?;
+    }
+    let mut context = X509StoreContext::new().map_err(|err| err.to_string())?;
+    let (verified, error) = context
+        .init(&store, &leaf, &intermediates, |ctx| {
+            Ok((ctx.verify_cert()?, ctx.error()))
+        })
+        .map_err(|err| err.to_string())?;
+    if !verified {
+        return Err(match error.as_raw() {
+            X509_V_ERR_HOSTNAME_MISMATCH | X509_V_ERR_IP_ADDRESS_MISMATCH => {
+                format!("certificate does not name {}", server_name)
+            }
+            _ => format!("chain verification failed: {}", error),
+        });
+    }
+    leaf.public_key().map_err(|err| err.to_string())
+}
+
 unsafe extern "C" fn pinned_verify_sign(
     verify_ctx: *mut c_void,
     algo: u16,
@@ -146,17 +580,17 @@ unsafe extern "C" fn pinned_verify_sign(
     if verify_ctx.is_null() {
         return -1;
     }
+    let pkey = Box::from_raw(verify_ctx as *mut PKey<Public>);
     if data.base.is_null() && data.len == 0 && sign.base.is_null() && sign.len == 0 {
         return 0;
     }
     if data.base.is_null() || sign.base.is_null() {
         return -1;
     }
-    let verifier = &*(verify_ctx as *const PinnedCertVerifier);
     // SAFETY: picotls supplies valid message and signature buffers while verifying.
     let data = std::slice::from_raw_parts(data.base as *const u8, data.len);
     let signature = std::slice::from_raw_parts(sign.base as *const u8, sign.len);
-    match verify_signature(&verifier.pkey, algo, data, signature) {
+    match verify_signature(&pkey, algo, data, signature) {
         Ok(true) => 0,
         Ok(false) => -1,
         Err(_) => -1,
@@ -265,3 +699,392 @@ fn verify_eddsa(
     verifier.update(data).map_err(|err| err.to_string())?;
     verifier.verify(sig).map_err(|err| err.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::{Asn1Integer, Asn1Time};
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::X509NameBuilder;
+
+    fn self_signed(key: &PKey<Private>, serial: u32, days: u32) -> X509 {
+        let mut name = X509NameBuilder::new().expect("name builder");
+        name.append_entry_by_text("CN", "slipstream")
+            .expect("set CN");
+        let name = name.build();
+        let mut builder = X509::builder().expect("x509 builder");
+        builder.set_version(2).expect("set version");
+        let serial = BigNum::from_u32(serial).expect("serial bignum");
+        let serial = Asn1Integer::from_bn(&serial).expect("serial integer");
+        builder.set_serial_number(&serial).expect("set serial");
+        builder.set_subject_name(&name).expect("set subject");
+        builder.set_issuer_name(&name).expect("set issuer");
+        builder.set_pubkey(key).expect("set public key");
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).expect("not before"))
+            .expect("set not before");
+        builder
+            .set_not_after(&Asn1Time::days_from_now(days).expect("not after"))
+            .expect("set not after");
+        builder
+            .sign(key, MessageDigest::sha256())
+            .expect("sign certificate");
+        builder.build()
+    }
+
+    fn ec_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).expect("P-256 group");
+        PKey::from_ec_key(EcKey::generate(&group).expect("generate key")).expect("wrap key")
+    }
+
+    fn spki_pin(cert: &X509) -> String {
+        let spki = cert
+            .public_key()
+            .expect("certificate key")
+            .public_key_to_der()
+            .expect("SPKI DER");
+        format!(
+            "{}{}",
+            SPKI_PIN_PREFIX,
+            base64::encode_block(&sha256(&spki))
+        )
+    }
+
+    fn matching_key(pins: &[CertPin], leaf_der: &[u8]) -> Option<PKey<Public>> {
+        pins.iter().find_map(|pin| pin.matching_key(leaf_der))
+    }
+
+    fn write_bundle(name: &str, certs: &[&X509]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "slipstream-pinning-{}-{}.pem",
+            std::process::id(),
+            name
+        ));
+        let pem: Vec<u8> = certs
+            .iter()
+            .flat_map(|cert| cert.to_pem().expect("certificate PEM"))
+            .collect();
+        fs::write(&path, pem).expect("write bundle");
+        path
+    }
+
+    #[test]
+    fn spki_pin_survives_reissued_leaf() {
+        let key = ec_key();
+        let original = self_signed(&key, 1, 30);
+        let reissued = self_signed(&key, 2, 365);
+        let original_der = original.to_der().expect("original DER");
+        let reissued_der = reissued.to_der().expect("reissued DER");
+        assert_ne!(original_der, reissued_der);
+
+        let pins = load_cert_pins(&spki_pin(&original)).expect("parse pin");
+        let pkey = matching_key(&pins, &reissued_der).expect("reissued leaf matches");
+        assert!(pkey.public_eq(&key));
+        assert!(matching_key(&pins, &original_der).is_some());
+
+        let der_pin = CertPin::Der {
+            der: original_der,
+            pkey: original.public_key().expect("original key"),
+        };
+        assert!(der_pin.matching_key(&reissued_der).is_none());
+    }
+
+    #[test]
+    fn spki_pin_rejects_other_key() {
+        let pinned = self_signed(&ec_key(), 1, 30);
+        let other = self_signed(&ec_key(), 1, 30);
+        let pins = load_cert_pins(&spki_pin(&pinned)).expect("parse pin");
+        let other_der = other.to_der().expect("other DER");
+        assert!(matching_key(&pins, &other_der).is_none());
+    }
+
+    #[test]
+    fn spki_pin_requires_sha256_length() {
+        let err = load_cert_pins("sha256//AAAA").err().expect("short pin");
+        assert!(err.to_string().contains("expected 32 bytes"), "{}", err);
+        assert!(load_cert_pins("sha256//not base64!").is_err());
+    }
+
+    #[test]
+    fn bundle_accepts_any_pinned_leaf_with_its_own_key() {
+        let (old_key, new_key) = (ec_key(), ec_key());
+        let old = self_signed(&old_key, 1, 30);
+        let new = self_signed(&new_key, 2, 30);
+        let bundle = write_bundle("rotation", &[&old, &new]);
+
+        let pins = load_cert_pins(bundle.to_str().expect("utf-8 path")).expect("load bundle");
+        assert_eq!(pins.len(), 2);
+        let new_der = new.to_der().expect("new DER");
+        let pkey = matching_key(&pins, &new_der).expect("new leaf matches");
+        assert!(pkey.public_eq(&new_key));
+        let old_der = old.to_der().expect("old DER");
+        let pkey = matching_key(&pins, &old_der).expect("old leaf matches");
+        assert!(pkey.public_eq(&old_key));
+        let stranger = self_signed(&ec_key(), 3, 30);
+        let stranger_der = stranger.to_der().expect("stranger DER");
+        assert!(matching_key(&pins, &stranger_der).is_none());
+        fs::remove_file(bundle).expect("remove bundle");
+    }
+
+    #[test]
+    fn pin_mismatch_surfaces_as_crypto_errors() {
+        let pinned = self_signed(&ec_key(), 1, 30);
+        let stranger = self_signed(&ec_key(), 2, 30);
+        let mut verifier = PinnedCertVerifier {
+            super_ctx: ptls_verify_certificate_t {
+                cb: Some(pinned_verify_certificate),
+                algos: PINNING_ALGOS.as_ptr(),
+            },
+            pins: load_cert_pins(&spki_pin(&pinned)).expect("parse pin"),
+            rejections: CertRejections::default(),
+        };
+        let mut der = stranger.to_der().unwrap();
+        let mut certs = [ptls_iovec_t {
+            base: der.as_mut_ptr(),
+            len: der.len(),
+        }];
+        let mut verify_sign: ptls_verify_sign_cb_fn = None;
+        let mut verify_sign_ctx = std::ptr::null_mut();
+        let ret = unsafe {
+            pinned_verify_certificate(
+                &mut verifier.super_ctx,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &mut verify_sign,
+                &mut verify_sign_ctx,
+                certs.as_mut_ptr(),
+                certs.len(),
+            )
+        };
+        assert_eq!(ret, -1);
+        assert!(verify_sign.is_none());
+
+        let err = take_handshake_error(Some(&verifier.rejections)).expect("rejection recorded");
+        let ClientError::CryptoErrors(errors) = &err else {
+            panic!("unexpected error: {}", err);
+        };
+        assert!(
+            errors
+                .iter()
+                .any(|error| error.contains("matches none of the 1 --cert pins")),
+            "{:?}",
+            errors
+        );
+        assert!(err.to_string().starts_with("TLS handshake failed: "));
+    }
+
+    #[test]
+    fn comma_list_mixes_paths_and_spki_pins() {
+        let file_cert = self_signed(&ec_key(), 1, 30);
+        let spki_cert = self_signed(&ec_key(), 2, 30);
+        let bundle = write_bundle("list", &[&file_cert]);
+
+        let value = format!("{}, {}", bundle.display(), spki_pin(&spki_cert));
+        let pins = load_cert_pins(&value).expect("load list");
+        assert_eq!(pins.len(), 2);
+        let file_der = file_cert.to_der().expect("file cert DER");
+        assert!(matching_key(&pins, &file_der).is_some());
+        let spki_der = spki_cert.to_der().expect("pinned cert DER");
+        assert!(matching_key(&pins, &spki_der).is_some());
+        assert!(load_cert_pins(&format!("{},", bundle.display())).is_err());
+        fs::remove_file(bundle).expect("remove bundle");
+    }
+
+    fn issued(
+        key: &PKey<Private>,
+        cn: &str,
+        issuer: Option<(&X509, &PKey<Private>)>,
+        sans: &[&str],
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", cn).unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = Asn1Integer::from_bn(&BigNum::from_u32(1).unwrap()).unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(30).unwrap())
+            .unwrap();
+        if sans.is_empty() {
+            let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+            builder.append_extension(constraints).unwrap();
+        } else {
+            let mut alt_names = SubjectAlternativeName::new();
+            for san in sans {
+                match san.parse::<IpAddr>() {
+                    Ok(_) => alt_names.ip(san),
+                    Err(_) => alt_names.dns(san),
+                };
+            }
+            let alt_names = alt_names
+                .build(&builder.x509v3_context(issuer.map(|(cert, _)| &**cert), None))
+                .unwrap();
+            builder.append_extension(alt_names).unwrap();
+        }
+        let (issuer_name, signing_key) = match issuer {
+            Some((cert, key)) => (cert.subject_name(), key),
+            None => (&*name, key),
+        };
+        builder.set_issuer_name(issuer_name).unwrap();
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn chain_verify_follows_intermediates_to_a_configured_ca() {
+        let (root_key, intermediate_key, leaf_key) = (ec_key(), ec_key(), ec_key());
+        let root = issued(&root_key, "root", None, &[]);
+        let intermediate = issued(
+            &intermediate_key,
+            "intermediate",
+            Some((&root, &root_key)),
+            &[],
+        );
+        let leaf = issued(
+            &leaf_key,
+            "leaf",
+            Some((&intermediate, &intermediate_key)),
+            &["tunnel.example.com"],
+        );
+        let bundle = write_bundle("chain-root", &[&root]);
+        let check = load_cert_check(bundle.to_str().unwrap(), CertMode::ChainVerify)
+            .expect("load trust store");
+        let CertCheck::Chain { anchors } = check else {
+            panic!("expected a chain check");
+        };
+        assert_eq!(anchors.len(), 1);
+        let (leaf_der, intermediate_der) = (leaf.to_der().unwrap(), intermediate.to_der().unwrap());
+
+        let pkey = verify_chain(
+            &anchors,
+            &[&leaf_der, &intermediate_der],
+            "tunnel.example.com",
+        )
+        .expect("chain verifies");
+        assert!(pkey.public_eq(&leaf_key));
+        let err = verify_chain(
+            &anchors,
+            &[&leaf_der, &intermediate_der],
+            "other.example.com",
+        )
+        .unwrap_err();
+        assert!(err.contains("does not name"), "{}", err);
+        assert!(verify_chain(&anchors, &[&leaf_der], "tunnel.example.com").is_err());
+
+        let stranger_key = ec_key();
+        let stranger_ca = issued(&stranger_key, "root", None, &[]);
+        let stranger = issued(
+            &ec_key(),
+            "leaf",
+            Some((&stranger_ca, &stranger_key)),
+            &["tunnel.example.com"],
+        );
+        let stranger_der = stranger.to_der().unwrap();
+        assert!(verify_chain(&anchors, &[&stranger_der], "tunnel.example.com").is_err());
+        fs::remove_file(bundle).unwrap();
+    }
+
+    #[test]
+    fn chain_verify_trusts_an_intermediate_alone_and_rejects_spki_pins() {
+        let (root_key, intermediate_key, leaf_key) = (ec_key(), ec_key(), ec_key());
+        let root = issued(&root_key, "root", None, &[]);
+        let intermediate = issued(
+            &intermediate_key,
+            "intermediate",
+            Some((&root, &root_key)),
+            &[],
+        );
+        let leaf = issued(
+            &leaf_key,
+            "leaf",
+            Some((&intermediate, &intermediate_key)),
+            &["192.0.2.7"],
+        );
+        let bundle = write_bundle("chain-intermediate", &[&intermediate]);
+        let anchors = load_trust_anchors(bundle.to_str().unwrap()).expect("load trust anchors");
+        let leaf_der = leaf.to_der().unwrap();
+        assert!(verify_chain(&anchors, &[&leaf_der], "192.0.2.7").is_ok());
+        assert!(verify_chain(&anchors, &[&leaf_der], "192.0.2.8").is_err());
+
+        let err = load_trust_anchors(&spki_pin(&leaf)).expect_err("pins are leaf-pin only");
+        assert!(err.to_string().contains("--cert-mode leaf-pin"), "{}", err);
+        fs::remove_file(bundle).unwrap();
+    }
+
+    #[test]
+    fn server_names_use_the_cn_only_without_dns_sans() {
+        let (ca_key, key) = (ec_key(), ec_key());
+        let ca = issued(&ca_key, "root", None, &[]);
+        let wildcard = issued(
+            &key,
+            "fallback.example",
+            Some((&ca, &ca_key)),
+            &["*.example.com"],
+        );
+        let anchors = [ca];
+        let wildcard_der = wildcard.to_der().expect("wildcard DER");
+        let names = |name: &str| verify_chain(&anchors, &[&wildcard_der], name).is_ok();
+        assert!(names("a.example.com"));
+        assert!(names("A.Example.COM."));
+        assert!(!names("a.b.example.com"));
+        assert!(!names("example.com"));
+        assert!(!names("fallback.example"));
+
+        let self_signed = self_signed(&key, 1, 30);
+        let anchors = [self_signed.clone()];
+        let cn_only_der = self_signed.to_der().expect("self-signed DER");
+        assert!(verify_chain(&anchors, &[&cn_only_der], "slipstream").is_ok());
+        let err = verify_chain(&anchors, &[&cn_only_der], "slipstream.example")
+            .expect_err("CN does not match");
+        assert!(err.contains("does not name"), "{}", err);
+    }
+
+    #[test]
+    fn empty_bundle_is_rejected() {
+        let bundle = write_bundle("empty", &[]);
+        let err = load_pinned_cert(bundle.to_str().expect("utf-8 path")).expect_err("empty bundle");
+        assert!(matches!(err, ClientError::Config(_)), "{}", err);
+        assert!(
+            err.to_string().contains("contains no certificates"),
+            "{}",
+            err
+        );
+        fs::remove_file(bundle).expect("remove bundle");
+    }
+
+    #[test]
+    fn tls_cipher_list_accepts_one_or_all_quic_suites() {
+        assert_eq!(
+            parse_tls_ciphers("tls_chacha20_poly1305_sha256").unwrap(),
+            [TlsCipher::Chacha20Poly1305Sha256]
+        );
+        let all = parse_tls_ciphers(
+            "TLS_AES_256_GCM_SHA384, TLS_AES_128_GCM_SHA256,TLS_CHACHA20_POLY1305_SHA256",
+        )
+        .unwrap();
+        assert_eq!(cipher_suite_id(&all).unwrap(), 0);
+        assert_eq!(
+            parse_tls_ciphers("TLS_AES_128_GCM_SHA256,TLS_AES_128_GCM_SHA256").unwrap(),
+            [TlsCipher::Aes128GcmSha256]
+        );
+
+        let err = parse_tls_ciphers("TLS_AES_128_CCM_SHA256").expect_err("not a QUIC suite");
+        assert!(
+            err.to_string().contains("TLS_AES_128_CCM_SHA256"),
+            "{}",
+            err
+        );
+        assert!(parse_tls_ciphers("TLS_AES_128_GCM_SHA256,TLS_AES_256_GCM_SHA384").is_err());
+        assert!(parse_tls_ciphers("").is_err());
+    }
+}
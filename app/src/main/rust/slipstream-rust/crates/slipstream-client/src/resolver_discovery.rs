@@ -0,0 +1,121 @@
+use slipstream_core::{
+    apply_address_policy, parse_host_port_parts, AddressKind, AddressPolicy, HostPort,
+};
+use slipstream_ffi::{ResolverMode, ResolverSpec};
+use tracing::warn;
+
+const DNS_PORT: u16 = 53;
+#[cfg(not(target_os = "android"))]
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// Returns the system's configured DNS servers, used when no resolver was given.
+///
+/// Reads `nameserver` lines from `/etc/resolv.conf`, or on Android asks ConnectivityManager
+/// for the DNS servers of the active underlying network.
+pub fn discover_system_resolvers() -> Vec<HostPort> {
+    #[cfg(target_os = "android")]
+    {
+        dedup_resolvers(
+            crate::android::system_dns_servers()
+                .iter()
+                .filter_map(|host| {
+                    parse_host_port_parts(host, DNS_PORT, AddressKind::Resolver).ok()
+                }),
+        )
+    }
+    #[cfg(not(target_os = "android"))]
+    {
+        match std::fs::read_to_string(RESOLV_CONF_PATH) {
+            Ok(contents) => parse_resolv_conf(&contents),
+            Err(err) => {
+                warn!("Failed to read {}: {}", RESOLV_CONF_PATH, err);
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// Discovered system resolvers as recursive UDP resolver specs, skipping those `policy`
+/// rules out.
+pub(crate) fn system_resolver_specs(policy: AddressPolicy) -> Vec<ResolverSpec> {
+    discover_system_resolvers()
+        .into_iter()
+        .filter_map(|resolver| apply_address_policy(resolver, AddressKind::Resolver, policy).ok())
+        .map(|resolver| ResolverSpec {
+            resolver,
+            mode: ResolverMode::Recursive,
+            doh_path: None,
+        })
+        .collect()
+}
+
+/// Parses the `nameserver` lines of a resolv.conf, skipping comments and invalid entries.
+#[cfg_attr(target_os = "android", allow(dead_code))]
+fn parse_resolv_conf(contents: &str) -> Vec<HostPort> {
+    dedup_resolvers(contents.lines().filter_map(|line| {
+        let line = line.split(['#', ';']).next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some("nameserver") {
+            return None;
+        }
+        let host = fields.next()?;
+        match parse_host_port_parts(host, DNS_PORT, AddressKind::Resolver) {
+            Ok(resolver) => Some(resolver),
+            Err(err) => {
+                warn!("Ignoring nameserver {}: {}", host, err);
+                None
+            }
+        }
+    }))
+}
+
+fn dedup_resolvers(resolvers: impl Iterator<Item = HostPort>) -> Vec<HostPort> {
+    let mut unique: Vec<HostPort> = Vec::new();
+    for resolver in resolvers {
+        let duplicate = unique.iter().any(|seen| {
+            seen.host == resolver.host && seen.port == resolver.port && seen.zone == resolver.zone
+        });
+        if !duplicate {
+            unique.push(resolver);
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nameserver_lines() {
+        let resolvers = parse_resolv_conf(
+            "# Generated by NetworkManager\n\
+             search example.com\n\
+             nameserver 192.0.2.53\n\
+             nameserver\t2001:db8::1 # secondary\n\
+             nameserver fe80::1%eth0\n\
+             ; nameserver 192.0.2.54\n\
+             nameserver 192.0.2.53\n\
+             nameserver\n\
+             options edns0\n",
+        );
+        let hosts: Vec<(&str, u16, Option<&str>)> = resolvers
+            .iter()
+            .map(|resolver| {
+                (
+                    resolver.host.as_str(),
+                    resolver.port,
+                    resolver.zone.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            hosts,
+            vec![
+                ("192.0.2.53", 53, None),
+                ("2001:db8::1", 53, None),
+                ("fe80::1", 53, Some("eth0")),
+            ]
+        );
+    }
+}
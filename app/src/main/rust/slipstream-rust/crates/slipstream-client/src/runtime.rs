@@ -1,11 +1,18 @@
+mod idle;
 mod path;
 mod setup;
+mod state;
+mod strategy;
 
+use self::idle::IdleTimeout;
 use self::path::{
     apply_path_mode, drain_path_events, fetch_path_quality, find_resolver_by_addr_mut,
     loop_burst_total, path_poll_burst_max,
 };
-use self::setup::{bind_tcp_listener, bind_udp_socket, compute_mtu, map_io};
+pub use self::setup::bind_tcp_listeners;
+use self::setup::{bind_udp_socket, compute_mtu};
+use self::state::StateReporter;
+use self::strategy::ResolverSelector;
 
 // Android-specific imports for state signaling
 #[cfg(target_os = "android")]
@@ -31,36 +38,60 @@ fn record_connection_failure() {}
 fn exceeded_max_failures() -> bool {
     false
 }
+use crate::datagram::DatagramListener;
 use crate::dns::{
-    add_paths, expire_inflight_polls, handle_dns_response, maybe_report_debug,
-    refresh_resolver_path, resolve_resolvers, resolver_mode_to_c, send_poll_queries,
-    sockaddr_storage_to_socket_addr, DnsResponseContext,
+    add_paths, auth_domain, expire_inflight_polls, handle_dns_response, maybe_report_debug,
+    probe_resolver_capacities, query_domain, randomize_qname_case, refresh_resolver_path,
+    release_held_responses, resolve_resolvers, resolver_mode_to_c, send_poll_queries,
+    sockaddr_storage_to_socket_addr, DnsResponseContext, EncryptedTransport, QueryIdPool,
+    ReorderBuffer, ReplayGuard, ResolverHealth,
 };
 use crate::error::ClientError;
-use crate::pacing::{cwnd_target_polls, inflight_packet_estimate};
-use crate::pinning::configure_pinned_certificate;
+use crate::health_check::spawn_health_server;
+use crate::local_socket::{
+    bind_unix_listener, unix_listen_path, validate_listen_host, LocalListener,
+};
+use crate::metrics::MetricsPublisher;
+use crate::network_monitor::{
+    loss_looks_local, migration_complete, start_migration, Migration, NetworkMonitor, TunnelSockets,
+};
+use crate::pacing::{
+    cwnd_target_polls, inflight_packet_estimate, AdaptiveKeepAlive, KeepAliveChange, PacingCap,
+    TokenBucket,
+};
+use crate::pinning::{
+    configure_cert_check, configure_tls_params, load_cert_check, take_handshake_error, CertCheck,
+};
+use crate::resolver_discovery::system_resolver_specs;
 use crate::streams::{
     acceptor::ClientAcceptor, client_callback, drain_commands, drain_stream_data, handle_command,
     ClientState, Command,
 };
 use slipstream_core::{net::is_transient_udp_error, normalize_dual_stack_addr};
-use slipstream_dns::{build_qname, encode_query, QueryParams, CLASS_IN, RR_TXT};
+use slipstream_dns::{
+    build_qname, encode_query, hmac, max_payload_len_for_domain, sequence_label, QnameConfig,
+    QueryParams, CLASS_IN, MAX_DNS_NAME_LEN,
+};
 use slipstream_ffi::{
     configure_quic_with_custom,
     picoquic::{
-        picoquic_close, picoquic_cnx_t, picoquic_connection_id_t, picoquic_create,
-        picoquic_create_client_cnx, picoquic_current_time, picoquic_disable_keep_alive,
-        picoquic_enable_keep_alive, picoquic_enable_path_callbacks,
-        picoquic_enable_path_callbacks_default, picoquic_get_next_wake_delay,
-        picoquic_prepare_next_packet_ex, picoquic_set_callback, slipstream_has_ready_stream,
-        slipstream_is_flow_blocked, slipstream_mixed_cc_algorithm, slipstream_set_cc_override,
-        slipstream_set_default_path_mode, PICOQUIC_CONNECTION_ID_MAX_SIZE,
+        get_path_quality, get_rtt, picoquic_close, picoquic_cnx_t, picoquic_connection_id_t,
+        picoquic_create, picoquic_create_client_cnx, picoquic_current_time,
+        picoquic_disable_keep_alive, picoquic_enable_keep_alive, picoquic_enable_path_callbacks,
+        picoquic_enable_path_callbacks_default, picoquic_get_cnx_state,
+        picoquic_get_next_wake_delay, picoquic_get_pacing_rate, picoquic_prepare_next_packet_ex,
+        picoquic_quic_t, picoquic_set_callback, picoquic_state_enum, picoquic_tls_is_psk_handshake,
+        slipstream_enable_datagrams, slipstream_has_ready_stream, slipstream_is_flow_blocked,
+        slipstream_mixed_cc_algorithm, slipstream_set_cc_override,
+        slipstream_set_default_path_mode, PathQuality, PICOQUIC_CONNECTION_ID_MAX_SIZE,
         PICOQUIC_MAX_PACKET_SIZE, PICOQUIC_PACKET_LOOP_RECV_MAX, PICOQUIC_PACKET_LOOP_SEND_MAX,
     },
-    socket_addr_to_storage, take_crypto_errors, ClientConfig, QuicGuard, ResolverMode,
+    prepare_ticket_file, save_session_tickets, socket_addr_to_storage, take_crypto_error_details,
+    ClientConfig, ClientState as TunnelState, QuicGuard, ResolverMode,
 };
-use std::ffi::CString;
-use std::net::Ipv6Addr;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::net::{Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{mpsc, Notify};
@@ -69,12 +100,12 @@ use tracing::{debug, error, info, warn};
 
 // Protocol defaults; see docs/config.md for details.
 const SLIPSTREAM_ALPN: &str = "picoquic_sample";
-const SLIPSTREAM_SNI: &str = "test.example.com";
+pub(crate) const SLIPSTREAM_SNI: &str = "test.example.com";
 const DNS_WAKE_DELAY_MAX_US: i64 = 10_000_000;
 const DNS_POLL_SLICE_US: u64 = 50_000;
-const RECONNECT_SLEEP_MIN_MS: u64 = 250;
-const RECONNECT_SLEEP_MAX_MS: u64 = 5_000;
 const FLOW_BLOCKED_LOG_INTERVAL_US: u64 = 1_000_000;
+const RESOLVER_HEALTH_LOG_INTERVAL_US: u64 = 30_000_000;
+const CONNECTION_STATS_LOG_INTERVAL_US: u64 = 5_000_000;
 const IDLE_THRESHOLD_US: u64 = 2_000_000; // 2s without streams → idle
 
 fn is_ipv6_unspecified(host: &str) -> bool {
@@ -94,56 +125,245 @@ fn drain_disconnected_commands(command_rx: &mut mpsc::UnboundedReceiver<Command>
     dropped
 }
 
+fn log_resolver_health(addr: SocketAddr, health: &ResolverHealth) {
+    if health.is_degraded() {
+        warn!("Resolver {} health: {}", addr, health);
+    } else {
+        info!("Resolver {} health: {}", addr, health);
+    }
+}
+
+fn log_path_quality(quality: &PathQuality) {
+    info!(
+        "Connection stats: rtt={}ms (min {}ms, var {}ms) cwin={} in_transit={} pacing_rate={}B/s recv_rate={}B/s sent={} lost={} ({:.2}%) spurious={}",
+        quality.rtt.as_millis(),
+        quality.rtt_min.as_millis(),
+        quality.rtt_variance.as_millis(),
+        quality.cwin_bytes,
+        quality.bytes_in_transit,
+        quality.pacing_rate_bytes_per_sec,
+        quality.receive_rate_bytes_per_sec,
+        quality.packets_sent,
+        quality.packets_lost,
+        quality.loss_rate() * 100.0,
+        quality.spurious_losses
+    );
+}
+
+fn log_handshake_time(handshake_us: u64, resumed: bool, last_full_us: Option<u64>) {
+    match (resumed, last_full_us) {
+        (true, Some(full_us)) => info!(
+            "QUIC handshake completed in {}ms (resumed, full handshake took {}ms)",
+            handshake_us / 1000,
+            full_us / 1000
+        ),
+        (true, None) => info!(
+            "QUIC handshake completed in {}ms (resumed)",
+            handshake_us / 1000
+        ),
+        (false, _) => info!(
+            "QUIC handshake completed in {}ms (full)",
+            handshake_us / 1000
+        ),
+    }
+}
+
+fn persist_session_tickets(quic: *mut picoquic_quic_t, path: Option<&CStr>) {
+    let Some(path) = path else {
+        return;
+    };
+    // SAFETY: the caller still holds the QUIC context guard.
+    if let Err(ret) = unsafe { save_session_tickets(quic, path) } {
+        warn!(
+            "Failed to save session tickets to {}: error {}",
+            path.to_string_lossy(),
+            ret
+        );
+    }
+}
+
+/// Runs the tunnel until it gives up or shuts down, reporting each `ClientState` change to
+/// `config.on_state_change`.
 pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
-    let domain_len = config.domain.len();
-    let mtu = compute_mtu(domain_len)?;
-    let udp = bind_udp_socket().await?;
+    let states = StateReporter::new(config.on_state_change);
+    states.report(TunnelState::Connecting);
+    let result = run_tunnel(config, &states).await;
+    states.report(TunnelState::Closed);
+    result
+}
+
+/// Runs with the configured resolvers, or with the system's when none are configured.
+async fn run_tunnel(
+    config: &ClientConfig<'_>,
+    states: &StateReporter<'_>,
+) -> Result<i32, ClientError> {
+    if !config.resolvers.is_empty() {
+        return run_with_resolvers(config, states).await;
+    }
+    let resolvers = system_resolver_specs(config.address_policy);
+    if resolvers.is_empty() {
+        return Err(ClientError::config(
+            "No resolvers configured and none found in the system DNS configuration",
+        ));
+    }
+    for spec in &resolvers {
+        info!(
+            "Using system resolver {}:{}",
+            spec.resolver.host, spec.resolver.port
+        );
+    }
+    run_with_resolvers(
+        &ClientConfig {
+            resolvers: &resolvers,
+            ..*config
+        },
+        states,
+    )
+    .await
+}
+
+async fn run_with_resolvers(
+    config: &ClientConfig<'_>,
+    states: &StateReporter<'_>,
+) -> Result<i32, ClientError> {
+    // Size queries for the sequence label `--ignore-cached-responses` and the auth label
+    // `--auth-secret` add ahead of the domain.
+    let mut sized_domain = config.domain.to_string();
+    if config.auth_secret.is_some() {
+        let label_len = hmac::auth_label_len(config.auth_mac_len);
+        sized_domain = format!("{}.{}", "0".repeat(label_len), sized_domain);
+    }
+    if config.ignore_cached_responses {
+        sized_domain = format!("{}.{}", sequence_label(0), sized_domain);
+    }
+    let mut mtu = compute_mtu(sized_domain.len(), config.encoding)?;
+    if (config.max_qname_len as usize) < MAX_DNS_NAME_LEN {
+        let capped = QnameConfig {
+            max_name_len: config.max_qname_len,
+            ..QnameConfig::default()
+        };
+        let max_payload = max_payload_len_for_domain(&sized_domain, config.encoding, capped)?;
+        if max_payload == 0 {
+            return Err(ClientError::config(format!(
+                "--max-qname-len {} leaves no room for payload after the domain",
+                config.max_qname_len
+            )));
+        }
+        mtu = mtu.min(max_payload as u32);
+        info!(
+            "QUIC MTU capped to {} by --max-qname-len {}",
+            mtu, config.max_qname_len
+        );
+    }
+    let mut sockets = TunnelSockets::new(bind_udp_socket().await?);
+    let capacity_hints = if config.probe_qname_capacity {
+        probe_resolver_capacities(config, bind_udp_socket).await?
+    } else {
+        HashMap::new()
+    };
+    for capacity in capacity_hints.values() {
+        let capacity = QnameConfig {
+            max_name_len: config.max_qname_len,
+            ..*capacity
+        };
+        let max_payload = max_payload_len_for_domain(&sized_domain, config.encoding, capacity)?;
+        mtu = mtu.min(max_payload as u32);
+    }
+    if !capacity_hints.is_empty() {
+        info!("QUIC MTU after resolver capacity probes: {}", mtu);
+    }
 
     let (command_tx, mut command_rx) = mpsc::unbounded_channel();
+    if let Some(addr) = config.health_listen {
+        let addr = spawn_health_server(addr, command_tx.clone()).await?;
+        info!("Serving health checks on http://{}/health", addr);
+    }
     let data_notify = Arc::new(Notify::new());
     let acceptor = ClientAcceptor::new();
     let debug_streams = config.debug_streams;
     let tcp_host = config.tcp_listen_host;
     let tcp_port = config.tcp_listen_port;
-    let mut bound_host = tcp_host.to_string();
-    let listener = match bind_tcp_listener(tcp_host, tcp_port).await {
-        Ok(listener) => listener,
-        Err(err) => {
-            if is_ipv6_unspecified(tcp_host) {
-                warn!(
-                    "Failed to bind TCP listener on {}:{} ({}); falling back to 0.0.0.0",
-                    tcp_host, tcp_port, err
-                );
-                match bind_tcp_listener("0.0.0.0", tcp_port).await {
-                    Ok(listener) => {
-                        bound_host = "0.0.0.0".to_string();
-                        listener
-                    }
-                    Err(fallback_err) => {
-                        return Err(ClientError::new(format!(
-                            "Failed to bind TCP listener on {}:{} ({}) or 0.0.0.0:{} ({})",
-                            tcp_host, tcp_port, err, tcp_port, fallback_err
-                        )));
+    let workers = config.listener_workers;
+    let mut datagram_listener = None;
+    // Held until the client exits; dropping it removes the socket file.
+    validate_listen_host(tcp_host, config.datagram_mode).map_err(ClientError::config)?;
+    let (listeners, _unix_socket): (Vec<LocalListener>, _) = match unix_listen_path(tcp_host) {
+        Some(path) => {
+            let (listener, guard) = bind_unix_listener(path)?;
+            info!("Listening on Unix socket {}", path);
+            (vec![listener], Some(guard))
+        }
+        None => {
+            let mut bound_host = tcp_host.to_string();
+            let listeners = match bind_tcp_listeners(tcp_host, tcp_port, workers).await {
+                Ok(listeners) => listeners,
+                Err(err) => {
+                    if is_ipv6_unspecified(tcp_host) {
+                        warn!(
+                            "Failed to bind TCP listener on {}:{} ({}); falling back to 0.0.0.0",
+                            tcp_host, tcp_port, err
+                        );
+                        match bind_tcp_listeners("0.0.0.0", tcp_port, workers).await {
+                            Ok(listeners) => {
+                                bound_host = "0.0.0.0".to_string();
+                                listeners
+                            }
+                            Err(fallback_err) => {
+                                return Err(match fallback_err {
+                                    ClientError::Io(fallback_err) => ClientError::io(
+                                        fallback_err,
+                                        format_args!(
+                                            "Failed to bind TCP listener on {}:{} ({}) or 0.0.0.0:{}",
+                                            tcp_host, tcp_port, err, tcp_port
+                                        ),
+                                    ),
+                                    other => other,
+                                });
+                            }
+                        }
+                    } else {
+                        return Err(err);
                     }
                 }
+            };
+            let worker_count = listeners.len();
+            if config.datagram_mode {
+                let addr = listeners[0].local_addr()?;
+                let listener = DatagramListener::bind(addr).await.map_err(|err| {
+                    ClientError::io(err, format_args!("Failed to bind UDP listener on {}", addr))
+                })?;
+                listener.spawn_reader(command_tx.clone());
+                info!("Listening on UDP port {} for datagram mode", addr.port());
+                datagram_listener = Some(listener);
+            }
+            if worker_count > 1 {
+                info!(
+                    "Listening on TCP port {} (host {}) with {} SO_REUSEPORT workers",
+                    tcp_port, bound_host, worker_count
+                );
             } else {
-                return Err(err);
+                info!("Listening on TCP port {} (host {})", tcp_port, bound_host);
             }
+            (
+                listeners.into_iter().map(LocalListener::Tcp).collect(),
+                None,
+            )
         }
     };
-    acceptor.spawn(listener, command_tx.clone());
-    info!("Listening on TCP port {} (host {})", tcp_port, bound_host);
+    for listener in listeners {
+        acceptor.spawn(listener, command_tx.clone(), config.listener_mode);
+    }
 
     // Signal to Android that the TCP listener is ready
     signal_listener_ready();
 
     let alpn = CString::new(SLIPSTREAM_ALPN)
-        .map_err(|_| ClientError::new("ALPN contains an unexpected null byte"))?;
-    let sni = CString::new(SLIPSTREAM_SNI)
-        .map_err(|_| ClientError::new("SNI contains an unexpected null byte"))?;
+        .map_err(|_| ClientError::config("ALPN contains an unexpected null byte"))?;
+    let sni = CString::new(config.sni.unwrap_or(SLIPSTREAM_SNI))
+        .map_err(|_| ClientError::config("SNI contains an unexpected null byte"))?;
     let cc_override = match config.congestion_control {
         Some(value) => Some(CString::new(value).map_err(|_| {
-            ClientError::new("Congestion control contains an unexpected null byte")
+            ClientError::config("Congestion control contains an unexpected null byte")
         })?),
         None => None,
     };
@@ -154,24 +374,78 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
         debug_streams,
         acceptor,
     ));
+    if let Some(listener) = datagram_listener {
+        state.set_datagram_listener(listener);
+    }
+    state.set_stream_priority_mode(config.stream_priority_mode);
     let state_ptr: *mut ClientState = &mut *state;
     let _state = state;
 
-    let mut reconnect_delay = Duration::from_millis(RECONNECT_SLEEP_MIN_MS);
+    let (encrypted_response_tx, mut encrypted_response_rx) = mpsc::unbounded_channel();
+    let encrypted = EncryptedTransport::spawn(
+        config.resolvers,
+        config.resolver_cert,
+        encrypted_response_tx,
+    )?;
+
+    let cert_check = match config.cert {
+        Some(cert) => {
+            let check = load_cert_check(cert, config.cert_mode)?;
+            match &check {
+                CertCheck::Pins(pins) => {
+                    info!("Pinning the server leaf to {} certificate(s)", pins.len());
+                }
+                CertCheck::Chain { anchors } => {
+                    info!(
+                        "Verifying the server chain against {} CA certificate(s)",
+                        anchors.len()
+                    );
+                }
+            }
+            Some(check)
+        }
+        None => None,
+    };
+
+    let mut metrics = MetricsPublisher::new(config.metrics_socket)?;
+    let reconnect_base = Duration::from_millis(config.reconnect_backoff_ms.max(1));
+    let reconnect_max = Duration::from_millis(config.reconnect_max_ms).max(reconnect_base);
+    let mut reconnect_delay = reconnect_base;
+    let mut reconnect_attempts = 0u32;
+    let mut selector =
+        ResolverSelector::new(config.resolver_strategy, config.resolver_health_window_ms);
+    // picoquic keeps this pointer for the lifetime of each QUIC context.
+    let ticket_path = config
+        .session_ticket_path
+        .map(prepare_ticket_file)
+        .transpose()
+        .map_err(ClientError::config)?;
+    if let Some(path) = config.session_ticket_path {
+        warn!(
+            "Session tickets are kept in {}, so reconnects may send 0-RTT data; anyone who \
+             captured it can replay it, so only tunnel idempotent traffic",
+            path
+        );
+    }
+    let mut last_full_handshake_us: Option<u64> = None;
 
     loop {
         // Check for shutdown before QUIC setup (picoquic_create etc. can be slow)
         if should_shutdown() {
             info!("Shutdown signal received before QUIC setup, exiting");
-            return Ok(0);
+            return Err(ClientError::Shutdown);
         }
 
-        let mut resolvers = resolve_resolvers(config.resolvers, mtu, config.debug_poll)?;
+        let ordered_resolvers = selector.order_for_connect(config.resolvers).await;
+        let mut resolvers = resolve_resolvers(&ordered_resolvers, mtu, config.debug_poll)?;
         if resolvers.is_empty() {
-            return Err(ClientError::new("At least one resolver is required"));
+            return Err(ClientError::config("At least one resolver is required"));
+        }
+        for resolver in resolvers.iter_mut() {
+            resolver.capacity_hint = capacity_hints.get(&resolver.addr).copied();
         }
 
-        let mut local_addr_storage = socket_addr_to_storage(udp.local_addr().map_err(map_io)?);
+        let mut local_addr_storage = socket_addr_to_storage(sockets.current().local_addr()?);
 
         let current_time = unsafe { picoquic_current_time() };
         let quic = unsafe {
@@ -188,29 +462,47 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                 std::ptr::null(),
                 current_time,
                 std::ptr::null_mut(),
-                std::ptr::null(),
+                ticket_path
+                    .as_ref()
+                    .map_or(std::ptr::null(), |path| path.as_ptr()),
                 std::ptr::null(),
                 0,
             )
         };
         if quic.is_null() {
-            let crypto_errors = take_crypto_errors();
-            if crypto_errors.is_empty() {
-                return Err(ClientError::new("Could not create QUIC context"));
+            let mut crypto_errors = take_crypto_error_details().into_iter();
+            let Some(first) = crypto_errors.next() else {
+                return Err(ClientError::quic(0, "Could not create QUIC context"));
+            };
+            let mut description = format!(
+                "Could not create QUIC context: {}",
+                first
+                    .description
+                    .unwrap_or_else(|| format!("crypto error {}", first.code))
+            );
+            for other in crypto_errors {
+                description.push_str(&format!("; {}", other));
             }
-            return Err(ClientError::new(format!(
-                "Could not create QUIC context (TLS errors: {})",
-                crypto_errors.join("; ")
-            )));
+            return Err(ClientError::Tls {
+                file: first.file,
+                line: first.line,
+                description,
+            });
         }
         let _quic_guard = QuicGuard::new(quic);
         let mixed_cc = unsafe { slipstream_mixed_cc_algorithm };
         if mixed_cc.is_null() {
-            return Err(ClientError::new("Could not load mixed congestion control"));
+            return Err(ClientError::quic(
+                0,
+                "Could not load mixed congestion control",
+            ));
         }
         unsafe {
             configure_quic_with_custom(quic, mixed_cc, mtu);
             picoquic_enable_path_callbacks_default(quic, 1);
+            if config.datagram_mode {
+                slipstream_enable_datagrams(quic, PICOQUIC_MAX_PACKET_SIZE as u32);
+            }
             let override_ptr = cc_override
                 .as_ref()
                 .map(|value| value.as_ptr())
@@ -220,9 +512,12 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
         unsafe {
             slipstream_set_default_path_mode(resolver_mode_to_c(resolvers[0].mode));
         }
-        if let Some(cert) = config.cert {
-            configure_pinned_certificate(quic, cert).map_err(ClientError::new)?;
-        }
+        let cert_rejections = match cert_check.as_ref() {
+            Some(check) => Some(configure_cert_check(quic, check)?),
+            None => None,
+        };
+        // SAFETY: quic was created above and is non-null.
+        unsafe { configure_tls_params(quic, config.tls_min_version, config.tls_ciphers)? };
         let mut server_storage = resolvers[0].storage;
         // picoquic_create_client_cnx calls picoquic_start_client_cnx internally (see picoquic/quicctx.c).
         let cnx = unsafe {
@@ -238,7 +533,7 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             )
         };
         if cnx.is_null() {
-            return Err(ClientError::new("Could not create QUIC connection"));
+            return Err(ClientError::quic(0, "Could not create QUIC connection"));
         }
 
         apply_path_mode(cnx, &mut resolvers[0])?;
@@ -257,24 +552,73 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             warn!("GSO is not implemented in the Rust client loop yet.");
         }
 
-        let mut dns_id = 1u16;
-        let mut recv_buf = vec![0u8; 4096];
+        let mut query_ids = QueryIdPool::with_window(config.pipeline_window);
+        let mut reorder = ReorderBuffer::new(config.pipeline_window);
+        let mut replay = config.ignore_cached_responses.then(ReplayGuard::new);
+        // Resolvers may answer with up to the advertised EDNS payload; a shorter buffer would
+        // truncate those answers on receive.
+        let recv_buf_len = usize::from(config.edns.udp_payload).max(4096);
+        let mut recv_buf = vec![0u8; recv_buf_len];
+        // The rebind probe socket is read in the same select as the tunnel socket.
+        let mut probe_recv_buf = vec![0u8; recv_buf_len];
         let mut send_buf = vec![0u8; PICOQUIC_MAX_PACKET_SIZE];
         let packet_loop_send_max = loop_burst_total(&resolvers, PICOQUIC_PACKET_LOOP_SEND_MAX);
         let packet_loop_recv_max = loop_burst_total(&resolvers, PICOQUIC_PACKET_LOOP_RECV_MAX);
         let mut zero_send_loops = 0u64;
         let mut zero_send_with_streams = 0u64;
         let mut last_flow_block_log_at = 0u64;
+        let mut last_health_log_at = 0u64;
+        let mut last_stats_log_at = 0u64;
         let mut quic_ready_signaled = false;
+        let handshake_started_at = current_time;
         let idle_poll_interval_us = config.idle_poll_interval_ms.saturating_mul(1000);
         let mut last_active_at: u64 = 0;
         let mut last_idle_poll_at: u64 = 0;
+        let migration_timeout_us = config.migration_timeout_ms.saturating_mul(1000);
+        // DoH/DoT primaries ride TCP connections; only UDP paths can migrate.
+        let mut network_monitor = (migration_timeout_us > 0
+            && matches!(
+                resolvers[0].mode,
+                ResolverMode::Recursive | ResolverMode::Authoritative
+            ))
+        .then(|| NetworkMonitor::new(resolvers[0].addr, current_time));
+        let rebind_migration = config.enable_migration && network_monitor.is_some();
+        let mut migration: Option<Migration> = None;
+        let mut udp_port = sockets.current().local_addr()?.port();
+        let mut query_rate = config
+            .max_query_rate
+            .map(|max_rate| TokenBucket::new(max_rate, config.min_query_rate, current_time));
+        let mut pacing_cap = config
+            .max_pacing_rate
+            .map(|rate| PacingCap::new(rate, current_time));
+        // A disabled keep-alive stays disabled; there is no interval to adapt.
+        let mut adaptive_keep_alive = (config.adaptive_keepalive && config.keep_alive_interval > 0)
+            .then(|| {
+                let min_us = config.min_keep_alive_ms.max(1).saturating_mul(1000);
+                AdaptiveKeepAlive::new(
+                    min_us,
+                    config.max_keep_alive_ms.saturating_mul(1000),
+                    config.keep_alive_interval as u64 * 1000,
+                    current_time,
+                )
+            });
+        let mut idle_timeout = (config.idle_timeout_seconds > 0).then(|| {
+            IdleTimeout::new(
+                config.idle_timeout_seconds.saturating_mul(1_000_000),
+                unsafe { (*state_ptr).tunneled_bytes() },
+                current_time,
+            )
+        });
 
         loop {
             // Check for shutdown signal from Android
             if should_shutdown() {
                 info!("Shutdown signal received, exiting");
-                return Ok(0);
+                unsafe {
+                    picoquic_close(cnx, 0);
+                }
+                persist_session_tickets(quic, ticket_path.as_deref());
+                return Err(ClientError::Shutdown);
             }
 
             let current_time = unsafe { picoquic_current_time() };
@@ -290,29 +634,147 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                 // Signal QUIC ready to Android (only once per connection)
                 if !quic_ready_signaled {
                     signal_quic_ready();
+                    states.report(TunnelState::Ready);
                     quic_ready_signaled = true;
+                    let handshake_us = current_time.saturating_sub(handshake_started_at);
+                    let resumed = unsafe { picoquic_tls_is_psk_handshake(cnx) } != 0;
+                    log_handshake_time(handshake_us, resumed, last_full_handshake_us);
+                    if !resumed {
+                        last_full_handshake_us = Some(handshake_us);
+                    }
                 }
 
                 unsafe {
                     (*state_ptr).update_acceptor_limit(cnx);
                 }
-                if reconnect_delay != Duration::from_millis(RECONNECT_SLEEP_MIN_MS) {
-                    reconnect_delay = Duration::from_millis(RECONNECT_SLEEP_MIN_MS);
-                }
+                reconnect_delay = reconnect_base;
+                reconnect_attempts = 0;
                 add_paths(cnx, &mut resolvers)?;
                 for resolver in resolvers.iter_mut() {
                     if resolver.added {
                         apply_path_mode(cnx, resolver)?;
                     }
                 }
+                if let Some(ip) = network_monitor
+                    .as_mut()
+                    .and_then(|monitor| monitor.poll(current_time))
+                {
+                    let local = SocketAddr::new(ip, udp_port);
+                    match start_migration(
+                        cnx,
+                        &resolvers[0],
+                        local,
+                        false,
+                        current_time,
+                        migration_timeout_us,
+                    ) {
+                        Ok(pending) => {
+                            info!("Local address changed to {}; migrating the connection", ip);
+                            migration = Some(pending);
+                        }
+                        Err(ret) => {
+                            warn!(
+                                "Local address changed to {} but probing a new path failed ({}); reconnecting",
+                                ip, ret
+                            );
+                            break;
+                        }
+                    }
+                }
             }
             drain_path_events(cnx, &mut resolvers, state_ptr);
+            if let Some(pending) = migration.as_ref() {
+                if migration_complete(cnx, &resolvers[0], pending) {
+                    info!("Migrated the connection to {}", pending.local);
+                    if pending.rebound {
+                        sockets.promote();
+                        udp_port = pending.local.port();
+                        local_addr_storage = socket_addr_to_storage(pending.local);
+                    }
+                    migration = None;
+                } else if current_time >= pending.deadline {
+                    warn!(
+                        "Migration to {} not validated within {}ms; reconnecting",
+                        pending.local, config.migration_timeout_ms
+                    );
+                    sockets.stop_probing();
+                    break;
+                }
+            }
 
             for resolver in resolvers.iter_mut() {
                 if resolver.mode == ResolverMode::Authoritative {
                     expire_inflight_polls(&mut resolver.inflight_poll_ids, current_time);
                 }
             }
+            for expired in query_ids.expire(current_time) {
+                // QUIC recovers lost data queries itself; only lost polls need resending.
+                if !expired.is_poll {
+                    continue;
+                }
+                if let Some(resolver) = find_resolver_by_addr_mut(&mut resolvers, expired.resolver)
+                {
+                    resolver.pending_polls = resolver
+                        .pending_polls
+                        .saturating_add(1)
+                        .min(PICOQUIC_PACKET_LOOP_RECV_MAX);
+                }
+            }
+            if !reorder.is_empty() {
+                release_held_responses(&mut DnsResponseContext {
+                    quic,
+                    local_addr_storage: &local_addr_storage,
+                    local_port: Some(udp_port),
+                    resolvers: &mut resolvers,
+                    query_rate: query_rate.as_mut(),
+                    query_ids: &mut query_ids,
+                    replay: replay.as_mut(),
+                    reorder: &mut reorder,
+                })?;
+            }
+            if let Some(keep_alive) = adaptive_keep_alive.as_mut() {
+                if ready {
+                    let rtt_us = unsafe { get_rtt(cnx) };
+                    let answered_polls = resolvers
+                        .iter()
+                        .map(|resolver| resolver.answered_polls)
+                        .sum();
+                    let polling =
+                        config.pipeline_window > 0 && unsafe { (*state_ptr).streams_len() } > 0;
+                    match keep_alive.update(rtt_us, answered_polls, polling, current_time) {
+                        Some(KeepAliveChange::Enable(interval_us)) => {
+                            debug!(
+                                "Keep-alive interval now {}ms (rtt {}ms)",
+                                interval_us / 1000,
+                                rtt_us / 1000
+                            );
+                            unsafe { picoquic_enable_keep_alive(cnx, interval_us) };
+                        }
+                        Some(KeepAliveChange::Disable) => {
+                            debug!("Keep-alive off while open streams keep the tunnel polling");
+                            unsafe { picoquic_disable_keep_alive(cnx) };
+                        }
+                        None => {}
+                    }
+                }
+            }
+            if let Some(idle) = idle_timeout.as_mut() {
+                let bytes = unsafe { (*state_ptr).tunneled_bytes() };
+                let open_streams = unsafe { (*state_ptr).streams_len() } > 0;
+                if let Some(idle_us) = idle.check(bytes, open_streams, current_time) {
+                    info!(
+                        "Connection idle for {}s; closing and reconnecting",
+                        idle_us / 1_000_000
+                    );
+                    break;
+                }
+            }
+            if let Some(rate) = query_rate
+                .as_mut()
+                .and_then(|bucket| bucket.tune(current_time))
+            {
+                info!("Query rate limit now {:.1} queries/s", rate);
+            }
 
             let delay_us =
                 unsafe { picoquic_get_next_wake_delay(quic, current_time, DNS_WAKE_DELAY_MAX_US) };
@@ -324,6 +786,16 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             }
             let is_idle = idle_poll_interval_us > 0
                 && current_time_for_idle.saturating_sub(last_active_at) >= IDLE_THRESHOLD_US;
+            if streams_len_for_sleep > 0 && config.pipeline_window > 0 {
+                for resolver in resolvers.iter_mut() {
+                    if resolver.mode != ResolverMode::Authoritative {
+                        let window_free = config
+                            .pipeline_window
+                            .saturating_sub(query_ids.inflight_to(resolver.addr));
+                        resolver.pending_polls = resolver.pending_polls.max(window_free);
+                    }
+                }
+            }
 
             let mut has_work = streams_len_for_sleep > 0;
             for resolver in resolvers.iter_mut() {
@@ -348,7 +820,9 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                         // send response-triggered polls even when pacing is zero.
                         pacing.max(resolver.pending_polls)
                     }
-                    ResolverMode::Recursive => resolver.pending_polls,
+                    ResolverMode::Recursive | ResolverMode::Doh | ResolverMode::Dot => {
+                        resolver.pending_polls
+                    }
                 };
                 if pending_for_sleep > 0 {
                     if is_idle && resolver.mode == ResolverMode::Authoritative {
@@ -381,6 +855,15 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             } else {
                 delay_us.max(1).min(MAX_SLEEP_US)
             };
+            // An empty bucket holds queries back; sleep until it refills instead of spinning.
+            let timeout_us = match query_rate.as_mut() {
+                Some(bucket) if has_work => timeout_us.max(bucket.wait_us(current_time_for_idle)),
+                _ => timeout_us,
+            };
+            let timeout_us = match pacing_cap.as_mut() {
+                Some(cap) if has_work => timeout_us.max(cap.wait_us(current_time_for_idle)),
+                _ => timeout_us,
+            };
             let timeout = Duration::from_micros(timeout_us);
 
             tokio::select! {
@@ -390,17 +873,22 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                     }
                 }
                 _ = data_notify.notified() => {}
-                recv = udp.recv_from(&mut recv_buf) => {
+                recv = sockets.current().recv_from(&mut recv_buf) => {
                     match recv {
                         Ok((size, peer)) => {
                             let mut response_ctx = DnsResponseContext {
                                 quic,
                                 local_addr_storage: &local_addr_storage,
+                                local_port: Some(udp_port),
                                 resolvers: &mut resolvers,
+                                query_rate: query_rate.as_mut(),
+                                query_ids: &mut query_ids,
+                                replay: replay.as_mut(),
+                                reorder: &mut reorder,
                             };
                             handle_dns_response(&recv_buf[..size], peer, &mut response_ctx)?;
                             for _ in 1..packet_loop_recv_max {
-                                match udp.try_recv_from(&mut recv_buf) {
+                                match sockets.current().try_recv_from(&mut recv_buf) {
                                     Ok((size, peer)) => {
                                         handle_dns_response(&recv_buf[..size], peer, &mut response_ctx)?;
                                     }
@@ -410,18 +898,61 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                                         if is_transient_udp_error(&err) {
                                             break;
                                         }
-                                        return Err(map_io(err));
+                                        return Err(err.into());
                                     }
                                 }
                             }
                         }
                         Err(err) => {
                             if !is_transient_udp_error(&err) {
-                                return Err(map_io(err));
+                                return Err(err.into());
+                            }
+                        }
+                    }
+                }
+                recv = sockets.recv_probe(&mut probe_recv_buf) => {
+                    match recv {
+                        Ok((size, peer, port)) => {
+                            let mut response_ctx = DnsResponseContext {
+                                quic,
+                                local_addr_storage: &local_addr_storage,
+                                local_port: Some(port),
+                                resolvers: &mut resolvers,
+                                query_rate: query_rate.as_mut(),
+                                query_ids: &mut query_ids,
+                                replay: replay.as_mut(),
+                                reorder: &mut reorder,
+                            };
+                            handle_dns_response(&probe_recv_buf[..size], peer, &mut response_ctx)?;
+                        }
+                        Err(err) => {
+                            if !is_transient_udp_error(&err) {
+                                return Err(err.into());
                             }
                         }
                     }
                 }
+                response = encrypted_response_rx.recv() => {
+                    if let Some((peer, buf)) = response {
+                        let mut response_ctx = DnsResponseContext {
+                            quic,
+                            local_addr_storage: &local_addr_storage,
+                            local_port: None,
+                            resolvers: &mut resolvers,
+                            query_rate: query_rate.as_mut(),
+                            query_ids: &mut query_ids,
+                            replay: replay.as_mut(),
+                            reorder: &mut reorder,
+                        };
+                        handle_dns_response(&buf, peer, &mut response_ctx)?;
+                        for _ in 1..packet_loop_recv_max {
+                            let Ok((peer, buf)) = encrypted_response_rx.try_recv() else {
+                                break;
+                            };
+                            handle_dns_response(&buf, peer, &mut response_ctx)?;
+                        }
+                    }
+                }
                 _ = sleep(timeout) => {}
             }
 
@@ -431,6 +962,24 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
 
             for _ in 0..packet_loop_send_max {
                 let current_time = unsafe { picoquic_current_time() };
+                if query_rate
+                    .as_mut()
+                    .is_some_and(|bucket| !bucket.ready(current_time))
+                {
+                    break;
+                }
+                if let Some(cap) = pacing_cap.as_mut() {
+                    if !cap.ready(current_time) {
+                        if cap.note_limited(current_time) {
+                            info!(
+                                "Pacing limited: sending at most {} bytes/s (QUIC pacing rate {} bytes/s)",
+                                config.max_pacing_rate.unwrap_or_default(),
+                                unsafe { picoquic_get_pacing_rate(cnx) }
+                            );
+                        }
+                        break;
+                    }
+                }
                 let mut send_length: libc::size_t = 0;
                 let mut addr_to: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
                 let mut addr_from: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
@@ -457,7 +1006,10 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                     )
                 };
                 if ret < 0 {
-                    return Err(ClientError::new("Failed preparing outbound QUIC packet"));
+                    return Err(ClientError::quic(
+                        ret,
+                        "Failed preparing outbound QUIC packet",
+                    ));
                 }
                 if send_length == 0 {
                     zero_send_loops = zero_send_loops.saturating_add(1);
@@ -467,7 +1019,7 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                         let flow_blocked = unsafe { slipstream_is_flow_blocked(cnx) } != 0;
                         if flow_blocked {
                             for resolver in resolvers.iter_mut() {
-                                if resolver.mode == ResolverMode::Recursive && resolver.added {
+                                if resolver.mode != ResolverMode::Authoritative && resolver.added {
                                     resolver.pending_polls = resolver.pending_polls.max(1);
                                 }
                             }
@@ -479,38 +1031,68 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                 if addr_to.ss_family == 0 {
                     break;
                 }
+                let mut qname_config = QnameConfig {
+                    max_name_len: config.max_qname_len,
+                    ..QnameConfig::default()
+                };
                 if let Ok(dest) = sockaddr_storage_to_socket_addr(&addr_to) {
                     let dest = normalize_dual_stack_addr(dest);
                     if let Some(resolver) = find_resolver_by_addr_mut(&mut resolvers, dest) {
+                        qname_config = resolver.qname_config(config.max_qname_len);
                         resolver.local_addr_storage = Some(unsafe { std::ptr::read(&addr_from) });
                         resolver.debug.send_packets = resolver.debug.send_packets.saturating_add(1);
                         resolver.debug.send_bytes =
                             resolver.debug.send_bytes.saturating_add(send_length as u64);
+                        resolver.unanswered_since.get_or_insert(current_time);
                     }
                 }
 
-                let qname = build_qname(&send_buf[..send_length], config.domain)
-                    .map_err(|err| ClientError::new(err.to_string()))?;
+                let domain = auth_domain(
+                    config.auth_secret,
+                    config.auth_mac_len,
+                    &send_buf[..send_length],
+                    config.domain,
+                )?;
+                let (domain, sequence) = query_domain(&mut replay, &domain);
+                let qname = build_qname(
+                    &send_buf[..send_length],
+                    &domain,
+                    config.encoding,
+                    qname_config,
+                )?;
+                let qname = randomize_qname_case(&qname, config.domain)?;
+                let dest = sockaddr_storage_to_socket_addr(&addr_to)?;
+                let dest = normalize_dual_stack_addr(dest);
                 let params = QueryParams {
-                    id: dns_id,
+                    id: query_ids.allocate(dest, &qname, sequence, false, current_time)?,
                     qname: &qname,
-                    qtype: RR_TXT,
+                    qtype: config.qtype,
                     qclass: CLASS_IN,
                     rd: true,
                     cd: false,
                     qdcount: 1,
                     is_query: true,
+                    edns: config.edns,
                 };
-                dns_id = dns_id.wrapping_add(1);
-                let packet =
-                    encode_query(&params).map_err(|err| ClientError::new(err.to_string()))?;
+                if let Some(bucket) = query_rate.as_mut() {
+                    bucket.record_sent(params.id, current_time);
+                }
+                if let Some(cap) = pacing_cap.as_mut() {
+                    cap.record_sent(send_length, current_time);
+                }
+                let packet = encode_query(&params)?;
 
-                let dest = sockaddr_storage_to_socket_addr(&addr_to)?;
-                let dest = normalize_dual_stack_addr(dest);
                 local_addr_storage = addr_from;
-                if let Err(err) = udp.send_to(&packet, dest).await {
+                if encrypted.try_send(dest, &packet) {
+                    continue;
+                }
+                if let Err(err) = sockets.for_source(&addr_from).send_to(&packet, dest).await {
+                    query_ids.release(params.id);
                     if !is_transient_udp_error(&err) {
-                        return Err(map_io(err));
+                        return Err(err.into());
+                    }
+                    if let Some(resolver) = find_resolver_by_addr_mut(&mut resolvers, dest) {
+                        resolver.send_failed = true;
                     }
                 }
             }
@@ -550,6 +1132,79 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                     last_flow_block_log_at = now;
                 }
             }
+            let selection_time = unsafe { picoquic_current_time() };
+            selector.record_rtts(cnx, &mut resolvers);
+            selector.update_failures(&mut resolvers, selection_time);
+            // Failed or unanswered sends on every path of an unchanged route usually mean the
+            // NAT dropped or rebound our mapping; a fresh source port gets a new one without a
+            // reconnect. The current socket keeps the tunnel going until the probe validates.
+            if rebind_migration && ready && migration.is_none() && loss_looks_local(&resolvers) {
+                let fresh = match network_monitor.as_ref().and_then(NetworkMonitor::current) {
+                    Some(ip) => match bind_udp_socket().await.and_then(|fresh| {
+                        let local = SocketAddr::new(ip, fresh.local_addr()?.port());
+                        Ok((local, fresh))
+                    }) {
+                        Ok(fresh) => Some(fresh),
+                        Err(err) => {
+                            warn!(
+                                "Failed to bind a socket to rebind from ({}); staying on the current one",
+                                err
+                            );
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                if let Some((local, fresh)) = fresh {
+                    match start_migration(
+                        cnx,
+                        &resolvers[0],
+                        local,
+                        true,
+                        selection_time,
+                        migration_timeout_us,
+                    ) {
+                        Ok(pending) => {
+                            info!(
+                                "Resolver {} stopped answering; migrating the connection to local port {}",
+                                resolvers[0].addr,
+                                local.port()
+                            );
+                            sockets.start_probing(fresh, local.port());
+                            migration = Some(pending);
+                        }
+                        Err(ret) => {
+                            warn!(
+                                "Resolver {} stopped answering but probing a new path from local port {} failed ({}); reconnecting",
+                                resolvers[0].addr,
+                                local.port(),
+                                ret
+                            );
+                            break;
+                        }
+                    }
+                }
+            }
+            selector.distribute_polls(&mut resolvers);
+            metrics.publish(cnx, unsafe { &*state_ptr }, selection_time);
+            if resolvers.len() > 1
+                && selection_time.saturating_sub(last_health_log_at)
+                    >= RESOLVER_HEALTH_LOG_INTERVAL_US
+            {
+                for resolver in resolvers.iter() {
+                    log_resolver_health(resolver.addr, &resolver.health);
+                }
+                last_health_log_at = selection_time;
+            }
+            if config.debug_poll
+                && selection_time.saturating_sub(last_stats_log_at)
+                    >= CONNECTION_STATS_LOG_INTERVAL_US
+            {
+                if let Some(quality) = unsafe { get_path_quality(cnx) } {
+                    log_path_quality(&quality);
+                }
+                last_stats_log_at = selection_time;
+            }
             for resolver in resolvers.iter_mut() {
                 if !refresh_resolver_path(cnx, resolver) {
                     continue;
@@ -601,10 +1256,13 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                             let mut to_send = poll_deficit.min(burst_max);
                             send_poll_queries(
                                 cnx,
-                                &udp,
+                                &sockets,
+                                &encrypted,
                                 config,
                                 &mut local_addr_storage,
-                                &mut dns_id,
+                                &mut query_ids,
+                                &mut replay,
+                                &mut query_rate,
                                 resolver,
                                 &mut to_send,
                                 &mut send_buf,
@@ -615,7 +1273,7 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                             }
                         }
                     }
-                    ResolverMode::Recursive => {
+                    ResolverMode::Recursive | ResolverMode::Doh | ResolverMode::Dot => {
                         resolver.last_pacing_snapshot = None;
                         if resolver.pending_polls > 0 {
                             let burst_max = path_poll_burst_max(resolver);
@@ -623,10 +1281,13 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                                 let mut to_send = burst_max;
                                 send_poll_queries(
                                     cnx,
-                                    &udp,
+                                    &sockets,
+                                    &encrypted,
                                     config,
                                     &mut local_addr_storage,
-                                    &mut dns_id,
+                                    &mut query_ids,
+                                    &mut replay,
+                                    &mut query_rate,
                                     resolver,
                                     &mut to_send,
                                     &mut send_buf,
@@ -640,10 +1301,13 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                                 let mut pending = resolver.pending_polls;
                                 send_poll_queries(
                                     cnx,
-                                    &udp,
+                                    &sockets,
+                                    &encrypted,
                                     config,
                                     &mut local_addr_storage,
-                                    &mut dns_id,
+                                    &mut query_ids,
+                                    &mut replay,
+                                    &mut query_rate,
                                     resolver,
                                     &mut pending,
                                     &mut send_buf,
@@ -680,7 +1344,9 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
                             })
                             .unwrap_or(0)
                     }
-                    ResolverMode::Recursive => resolver.pending_polls,
+                    ResolverMode::Recursive | ResolverMode::Doh | ResolverMode::Dot => {
+                        resolver.pending_polls
+                    }
                 };
                 maybe_report_debug(
                     resolver,
@@ -694,16 +1360,36 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
             }
         }
 
+        let handshake_failed = matches!(
+            unsafe { picoquic_get_cnx_state(cnx) },
+            picoquic_state_enum::picoquic_state_handshake_failure
+                | picoquic_state_enum::picoquic_state_handshake_failure_resend
+        );
         unsafe {
             picoquic_close(cnx, 0);
         }
+        persist_session_tickets(quic, ticket_path.as_deref());
+
+        // Returned instead of a generic failure if the client gives up below.
+        let handshake_error = if handshake_failed || !quic_ready_signaled {
+            take_handshake_error(cert_rejections.as_ref())
+        } else {
+            None
+        };
+        if let Some(err) = handshake_error.as_ref() {
+            warn!("{}", err);
+        }
 
         // Track connection failures - if we never became ready, count as failure
         if !quic_ready_signaled {
             record_connection_failure();
             if exceeded_max_failures() {
                 error!("Exceeded max consecutive connection failures, giving up");
-                return Err(ClientError::new(
+                if let Some(err) = handshake_error {
+                    return Err(err);
+                }
+                return Err(ClientError::quic(
+                    0,
                     "Connection failed repeatedly - check network and server availability",
                 ));
             }
@@ -715,6 +1401,9 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
         unsafe {
             (*state_ptr).reset_for_reconnect();
         }
+        metrics.publish(std::ptr::null_mut(), unsafe { &*state_ptr }, unsafe {
+            picoquic_current_time()
+        });
         let dropped = drain_disconnected_commands(&mut command_rx);
         if dropped > 0 {
             warn!("Dropped {} queued commands while reconnecting", dropped);
@@ -723,26 +1412,49 @@ pub async fn run_client(config: &ClientConfig<'_>) -> Result<i32, ClientError> {
         // Check for shutdown before reconnecting
         if should_shutdown() {
             info!("Shutdown signal received during reconnect, exiting");
-            return Ok(0);
+            return Err(ClientError::Shutdown);
         }
 
-        warn!(
-            "Connection closed; reconnecting in {}ms",
-            reconnect_delay.as_millis()
-        );
+        reconnect_attempts = reconnect_attempts.saturating_add(1);
+        if config.max_reconnect_attempts > 0 {
+            if reconnect_attempts > config.max_reconnect_attempts {
+                error!(
+                    "Giving up after {} reconnect attempts",
+                    config.max_reconnect_attempts
+                );
+                return Err(ClientError::quic(
+                    0,
+                    "Exceeded the maximum number of reconnect attempts",
+                ));
+            }
+            warn!(
+                "Connection closed; reconnect attempt {}/{} in {}ms",
+                reconnect_attempts,
+                config.max_reconnect_attempts,
+                reconnect_delay.as_millis()
+            );
+        } else {
+            warn!(
+                "Connection closed; reconnect attempt {} in {}ms",
+                reconnect_attempts,
+                reconnect_delay.as_millis()
+            );
+        }
+        states.report(TunnelState::Reconnecting);
         // Sleep in small chunks and drop commands that arrive while disconnected.
         let mut remaining_sleep = reconnect_delay;
         while remaining_sleep > Duration::ZERO {
             // Check shutdown during sleep
             if should_shutdown() {
                 info!("Shutdown signal received during reconnect sleep, exiting");
-                return Ok(0);
+                return Err(ClientError::Shutdown);
             }
             let chunk = remaining_sleep.min(Duration::from_millis(100));
             sleep(chunk).await;
             remaining_sleep -= chunk;
             let _ = drain_disconnected_commands(&mut command_rx);
         }
-        reconnect_delay = (reconnect_delay * 2).min(Duration::from_millis(RECONNECT_SLEEP_MAX_MS));
+        reconnect_delay = (reconnect_delay * 2).min(reconnect_max);
+        states.report(TunnelState::Connecting);
     }
 }
@@ -0,0 +1,48 @@
+/// Tracks how long the connection has gone without tunneling stream payload, for
+/// `--idle-timeout-seconds`.
+///
+/// Open streams count as activity too, so a quiet but live session is never cut; keep-alives
+/// and polls do not, since they flow whether or not anything is tunneled.
+pub(crate) struct IdleTimeout {
+    timeout_us: u64,
+    last_bytes: (u64, u64),
+    last_data_at: u64,
+}
+
+impl IdleTimeout {
+    /// `bytes` is the `(sent, received)` stream payload total at connection start.
+    pub(crate) fn new(timeout_us: u64, bytes: (u64, u64), now: u64) -> Self {
+        Self {
+            timeout_us,
+            last_bytes: bytes,
+            last_data_at: now,
+        }
+    }
+
+    /// Returns how long the connection has been idle once that reaches the timeout.
+    pub(crate) fn check(&mut self, bytes: (u64, u64), open_streams: bool, now: u64) -> Option<u64> {
+        if bytes != self.last_bytes || open_streams {
+            self.last_bytes = bytes;
+            self.last_data_at = now;
+            return None;
+        }
+        let idle_us = now.saturating_sub(self.last_data_at);
+        (idle_us >= self.timeout_us).then_some(idle_us)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_and_open_streams_reset_the_timer() {
+        let mut idle = IdleTimeout::new(10_000_000, (0, 0), 0);
+        assert_eq!(idle.check((0, 0), false, 9_000_000), None);
+        assert_eq!(idle.check((0, 512), false, 9_500_000), None);
+        assert_eq!(idle.check((0, 512), false, 19_000_000), None);
+        assert_eq!(idle.check((0, 512), true, 30_000_000), None);
+        assert_eq!(idle.check((0, 512), false, 39_000_000), None);
+        assert_eq!(idle.check((0, 512), false, 40_000_000), Some(10_000_000));
+    }
+}
@@ -107,7 +107,7 @@ pub(crate) fn path_poll_burst_max(resolver: &ResolverState) -> usize {
 fn path_loop_multiplier(mode: ResolverMode) -> usize {
     match mode {
         ResolverMode::Authoritative => AUTHORITATIVE_LOOP_MULTIPLIER,
-        ResolverMode::Recursive => 1,
+        ResolverMode::Recursive | ResolverMode::Doh | ResolverMode::Dot => 1,
     }
 }
 
@@ -0,0 +1,66 @@
+use slipstream_ffi::{ClientState, StateCallback};
+use std::cell::Cell;
+
+/// Passes each change of the tunnel's `ClientState` to `ClientConfig::on_state_change`.
+///
+/// A state reported twice in a row goes out once, so the event loop can report `Ready` on
+/// every pass without tracking whether it already has.
+pub(crate) struct StateReporter<'a> {
+    callback: Option<StateCallback<'a>>,
+    last: Cell<Option<ClientState>>,
+}
+
+impl<'a> StateReporter<'a> {
+    pub(crate) fn new(callback: Option<StateCallback<'a>>) -> Self {
+        Self {
+            callback,
+            last: Cell::new(None),
+        }
+    }
+
+    pub(crate) fn report(&self, state: ClientState) {
+        if self.last.replace(Some(state)) == Some(state) {
+            return;
+        }
+        if let Some(callback) = self.callback {
+            callback.notify(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn reports_each_connection_attempt_through_close() {
+        let seen = Mutex::new(Vec::new());
+        let record = |state| seen.lock().expect("state log").push(state);
+        let states = StateReporter::new(Some(StateCallback(&record)));
+        // A first connection, a drop, one failed attempt and a second connection, in the
+        // order the runtime reports them.
+        states.report(ClientState::Connecting);
+        states.report(ClientState::Ready);
+        states.report(ClientState::Ready);
+        states.report(ClientState::Reconnecting);
+        states.report(ClientState::Connecting);
+        states.report(ClientState::Reconnecting);
+        states.report(ClientState::Connecting);
+        states.report(ClientState::Ready);
+        states.report(ClientState::Closed);
+        assert_eq!(
+            *seen.lock().expect("state log"),
+            vec![
+                ClientState::Connecting,
+                ClientState::Ready,
+                ClientState::Reconnecting,
+                ClientState::Connecting,
+                ClientState::Reconnecting,
+                ClientState::Connecting,
+                ClientState::Ready,
+                ClientState::Closed,
+            ]
+        );
+    }
+}
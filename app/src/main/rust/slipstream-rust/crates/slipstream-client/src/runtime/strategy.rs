@@ -0,0 +1,288 @@
+use crate::dns::{refresh_resolver_path, ResolverState};
+use slipstream_ffi::picoquic::picoquic_cnx_t;
+use slipstream_ffi::{ResolverMode, ResolverSpec, ResolverStrategy};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tracing::warn;
+
+use super::path::fetch_path_quality;
+
+// RTT assumed for paths without a smoothed RTT yet.
+const UNKNOWN_RTT_US: u64 = 250_000;
+
+/// Applies the configured [`ResolverStrategy`] to connection setup and poll routing.
+///
+/// Resolver health is tracked for every strategy. `Ordered` keeps the historical behavior:
+/// the first resolver is the primary path and every path answers its own polls. The other
+/// strategies pool response-driven polls of recursive-style paths and hand them out again
+/// among healthy resolvers, falling back to round-robin over all of them when every
+/// resolver is degraded; picoquic keeps the QUIC connection alive on the remaining paths.
+pub(crate) struct ResolverSelector {
+    strategy: ResolverStrategy,
+    health_window_us: u64,
+    connect_rotation: usize,
+    poll_rotation: usize,
+    /// Last smoothed RTT per resolver, kept across reconnects to pick the next primary.
+    rtt_by_addr: HashMap<SocketAddr, u64>,
+}
+
+impl ResolverSelector {
+    pub(crate) fn new(strategy: ResolverStrategy, health_window_ms: u64) -> Self {
+        Self {
+            strategy,
+            health_window_us: health_window_ms.saturating_mul(1000),
+            connect_rotation: 0,
+            poll_rotation: 0,
+            rtt_by_addr: HashMap::new(),
+        }
+    }
+
+    /// Orders resolvers for the next connection attempt; the first entry becomes path 0.
+    pub(crate) async fn order_for_connect(&mut self, specs: &[ResolverSpec]) -> Vec<ResolverSpec> {
+        let mut ordered = specs.to_vec();
+        match self.strategy {
+            ResolverStrategy::Ordered => {}
+            ResolverStrategy::RoundRobin => {
+                if !ordered.is_empty() {
+                    let shift = self.connect_rotation % ordered.len();
+                    ordered.rotate_left(shift);
+                }
+                self.connect_rotation = self.connect_rotation.wrapping_add(1);
+            }
+            ResolverStrategy::LatencyWeighted => {
+                let addrs = resolve_addrs(ordered.clone()).await;
+                let mut keyed: Vec<(u64, ResolverSpec)> = addrs
+                    .into_iter()
+                    .map(|addr| {
+                        addr.and_then(|addr| self.rtt_by_addr.get(&addr).copied())
+                            .unwrap_or(u64::MAX)
+                    })
+                    .zip(ordered)
+                    .collect();
+                // Unmeasured resolvers keep their configured order behind measured ones.
+                keyed.sort_by_key(|(rtt, _)| *rtt);
+                ordered = keyed.into_iter().map(|(_, spec)| spec).collect();
+            }
+        }
+        ordered
+    }
+
+    /// Feeds per-path RTT samples from picoquic into each resolver's health.
+    pub(crate) fn record_rtts(
+        &mut self,
+        cnx: *mut picoquic_cnx_t,
+        resolvers: &mut [ResolverState],
+    ) {
+        for resolver in resolvers.iter_mut() {
+            if !refresh_resolver_path(cnx, resolver) {
+                continue;
+            }
+            let quality = fetch_path_quality(cnx, resolver);
+            resolver.health.record_rtt_sample(quality.rtt_sample);
+            if let Some(rtt) = resolver.health.smoothed_rtt_us {
+                self.rtt_by_addr.insert(resolver.addr, rtt);
+            }
+        }
+    }
+
+    /// Counts failed sends and health windows that passed without any response.
+    pub(crate) fn update_failures(&self, resolvers: &mut [ResolverState], now: u64) {
+        for resolver in resolvers.iter_mut() {
+            let send_failed = std::mem::take(&mut resolver.send_failed);
+            let timed_out = resolver
+                .unanswered_since
+                .is_some_and(|since| now.saturating_sub(since) >= self.health_window_us);
+            if timed_out {
+                // Restart the window so a silent resolver accrues one failure per window.
+                resolver.unanswered_since = Some(now);
+            }
+            if (send_failed || timed_out) && resolver.health.record_failure() {
+                warn!(
+                    "Resolver {} degraded after {} consecutive failures",
+                    resolver.addr, resolver.health.consecutive_failures
+                );
+            }
+        }
+    }
+
+    /// Re-routes pending polls of recursive-style paths according to the strategy.
+    pub(crate) fn distribute_polls(&mut self, resolvers: &mut [ResolverState]) {
+        if self.strategy == ResolverStrategy::Ordered {
+            return;
+        }
+        let pooled: Vec<usize> = (0..resolvers.len())
+            .filter(|&idx| is_pooled(&resolvers[idx]))
+            .collect();
+        if pooled.len() < 2 {
+            return;
+        }
+        let total: usize = pooled
+            .iter()
+            .map(|&idx| std::mem::take(&mut resolvers[idx].pending_polls))
+            .sum();
+        if total == 0 {
+            return;
+        }
+        let healthy: Vec<usize> = pooled
+            .iter()
+            .copied()
+            .filter(|&idx| !resolvers[idx].health.is_degraded())
+            .collect();
+
+        if self.strategy == ResolverStrategy::LatencyWeighted && !healthy.is_empty() {
+            let idx = healthy
+                .iter()
+                .copied()
+                .min_by_key(|&idx| {
+                    resolvers[idx]
+                        .health
+                        .smoothed_rtt_us
+                        .unwrap_or(UNKNOWN_RTT_US)
+                })
+                .expect("healthy resolvers are not empty");
+            resolvers[idx].pending_polls += total;
+            return;
+        }
+        // Round-robin, over every pooled resolver once all of them are degraded.
+        let targets = if healthy.is_empty() { pooled } else { healthy };
+        for _ in 0..total {
+            let idx = targets[self.poll_rotation % targets.len()];
+            resolvers[idx].pending_polls += 1;
+            self.poll_rotation = self.poll_rotation.wrapping_add(1);
+        }
+    }
+}
+
+/// Looks up each resolver's address once, off the runtime thread; `None` where it fails.
+async fn resolve_addrs(specs: Vec<ResolverSpec>) -> Vec<Option<SocketAddr>> {
+    let count = specs.len();
+    tokio::task::spawn_blocking(move || {
+        specs
+            .iter()
+            .map(|spec| {
+                slipstream_core::resolve_host_port(&spec.resolver)
+                    .ok()
+                    .map(slipstream_core::normalize_dual_stack_addr)
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or_else(|_| vec![None; count])
+}
+
+fn is_pooled(resolver: &ResolverState) -> bool {
+    resolver.added && resolver.mode != ResolverMode::Authoritative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{resolve_resolvers, MAX_CONSECUTIVE_FAILURES};
+    use slipstream_core::{AddressFamily, HostPort};
+
+    const WINDOW_MS: u64 = 2_000;
+
+    fn spec(port: u16) -> ResolverSpec {
+        ResolverSpec {
+            resolver: HostPort {
+                host: "127.0.0.1".to_string(),
+                port,
+                family: AddressFamily::V4,
+                zone: None,
+            },
+            mode: ResolverMode::Recursive,
+            doh_path: None,
+        }
+    }
+
+    fn active_resolvers(ports: &[u16]) -> Vec<ResolverState> {
+        let specs: Vec<ResolverSpec> = ports.iter().map(|&port| spec(port)).collect();
+        let mut resolvers = resolve_resolvers(&specs, 900, false).expect("resolvers");
+        for (idx, resolver) in resolvers.iter_mut().enumerate() {
+            resolver.added = true;
+            resolver.path_id = idx as libc::c_int;
+        }
+        resolvers
+    }
+
+    #[tokio::test]
+    async fn round_robin_rotates_primary_and_polls() {
+        let mut selector = ResolverSelector::new(ResolverStrategy::RoundRobin, WINDOW_MS);
+        let specs = vec![spec(5301), spec(5302), spec(5303)];
+        let first = selector.order_for_connect(&specs).await;
+        let second = selector.order_for_connect(&specs).await;
+        assert_eq!(first[0].resolver.port, 5301);
+        assert_eq!(second[0].resolver.port, 5302);
+
+        let mut resolvers = active_resolvers(&[5301, 5302, 5303]);
+        resolvers[0].pending_polls = 6;
+        selector.distribute_polls(&mut resolvers);
+        let polls: Vec<usize> = resolvers.iter().map(|r| r.pending_polls).collect();
+        assert_eq!(polls, vec![2, 2, 2]);
+    }
+
+    #[tokio::test]
+    async fn latency_weighted_routes_to_lowest_smoothed_rtt() {
+        let mut selector = ResolverSelector::new(ResolverStrategy::LatencyWeighted, WINDOW_MS);
+        let mut resolvers = active_resolvers(&[5311, 5312, 5313]);
+        resolvers[0].health.record_rtt_sample(300_000);
+        resolvers[1].health.record_rtt_sample(100_000);
+        resolvers[0].pending_polls = 8;
+        selector.distribute_polls(&mut resolvers);
+        let polls: Vec<usize> = resolvers.iter().map(|r| r.pending_polls).collect();
+        assert_eq!(polls, vec![0, 8, 0]);
+
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            resolvers[1].health.record_failure();
+        }
+        selector.distribute_polls(&mut resolvers);
+        let polls: Vec<usize> = resolvers.iter().map(|r| r.pending_polls).collect();
+        assert_eq!(polls, vec![0, 0, 8]);
+
+        selector.rtt_by_addr.insert(resolvers[1].addr, 100_000);
+        let ordered = selector.order_for_connect(&[spec(5311), spec(5312)]).await;
+        assert_eq!(ordered[0].resolver.port, 5312);
+    }
+
+    #[test]
+    fn counts_failures_per_window_and_falls_back_to_round_robin() {
+        let window_us = WINDOW_MS * 1000;
+        let mut selector = ResolverSelector::new(ResolverStrategy::LatencyWeighted, WINDOW_MS);
+        let mut resolvers = active_resolvers(&[5321, 5322]);
+        resolvers[0].unanswered_since = Some(0);
+        resolvers[1].send_failed = true;
+        selector.update_failures(&mut resolvers, window_us - 1);
+        assert_eq!(resolvers[0].health.consecutive_failures, 0);
+        assert_eq!(resolvers[1].health.consecutive_failures, 1);
+        assert!(!resolvers[1].send_failed);
+
+        for window in 1..=u64::from(MAX_CONSECUTIVE_FAILURES) {
+            resolvers[1].send_failed = true;
+            selector.update_failures(&mut resolvers, window * window_us);
+        }
+        assert!(resolvers[0].health.is_degraded());
+        assert!(resolvers[1].health.is_degraded());
+        assert_eq!(
+            resolvers[0].unanswered_since,
+            Some(u64::from(MAX_CONSECUTIVE_FAILURES) * window_us)
+        );
+
+        resolvers[0].pending_polls = 4;
+        selector.distribute_polls(&mut resolvers);
+        assert_eq!(resolvers[0].pending_polls, 2);
+        assert_eq!(resolvers[1].pending_polls, 2);
+    }
+
+    #[test]
+    fn ordered_strategy_tracks_health_but_leaves_polls_in_place() {
+        let mut selector = ResolverSelector::new(ResolverStrategy::Ordered, WINDOW_MS);
+        let mut resolvers = active_resolvers(&[5331, 5332]);
+        resolvers[0].pending_polls = 3;
+        resolvers[1].send_failed = true;
+        selector.update_failures(&mut resolvers, 0);
+        selector.distribute_polls(&mut resolvers);
+        assert_eq!(resolvers[0].pending_polls, 3);
+        assert_eq!(resolvers[1].pending_polls, 0);
+        assert_eq!(resolvers[1].health.consecutive_failures, 1);
+    }
+}
@@ -0,0 +1,241 @@
+//! SOCKS5 handshake for `--listener-mode socks5`.
+//!
+//! Only the no-authentication method and the CONNECT command of RFC 1928 are supported. The
+//! requested address is forwarded to the server in the same `proxy_frame` header that
+//! `--listener-mode connect-proxy` uses, so the server runs with `--target-mode connect-proxy`.
+
+use slipstream_core::proxy_frame::{encode_proxy_frame, ProxyTarget};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout, Duration};
+use tracing::debug;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const SOCKS_VERSION: u8 = 0x05;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+/// Negotiates SOCKS5 on `stream` and answers its CONNECT request.
+///
+/// Returns the proxy frame that must open the QUIC stream. `None` means the request was refused
+/// or timed out. Success is reported before the server dials the target, as with CONNECT; a
+/// failed dial closes the connection instead.
+pub(crate) async fn accept_socks5(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+) -> Option<Vec<u8>> {
+    match timeout(HANDSHAKE_TIMEOUT, handshake(stream)).await {
+        Ok(Ok(preamble)) => Some(preamble),
+        Ok(Err(err)) => {
+            debug!("socks5: rejected request: {}", err);
+            None
+        }
+        Err(_) => {
+            debug!("socks5: handshake timed out");
+            None
+        }
+    }
+}
+
+async fn handshake(stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) -> Result<Vec<u8>, String> {
+    let mut greeting = [0u8; 2];
+    read_exact(stream, &mut greeting).await?;
+    if greeting[0] != SOCKS_VERSION {
+        return Err(format!("unsupported SOCKS version {}", greeting[0]));
+    }
+    let mut methods = vec![0u8; greeting[1] as usize];
+    read_exact(stream, &mut methods).await?;
+    if !methods.contains(&METHOD_NO_AUTH) {
+        let _ = stream
+            .write_all(&[SOCKS_VERSION, METHOD_NONE_ACCEPTABLE])
+            .await;
+        return Err("no-authentication method not offered".to_string());
+    }
+    stream
+        .write_all(&[SOCKS_VERSION, METHOD_NO_AUTH])
+        .await
+        .map_err(|err| err.to_string())?;
+
+    // VER, CMD, RSV, ATYP
+    let mut request = [0u8; 4];
+    read_exact(stream, &mut request).await?;
+    if request[0] != SOCKS_VERSION {
+        return Err(format!("unsupported SOCKS version {}", request[0]));
+    }
+    if request[1] != CMD_CONNECT {
+        send_reply(stream, REPLY_COMMAND_NOT_SUPPORTED).await;
+        return Err(format!("unsupported command {}", request[1]));
+    }
+    let addr_len = match request[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            read_exact(stream, &mut len).await?;
+            len[0] as usize
+        }
+        other => {
+            send_reply(stream, REPLY_ADDRESS_NOT_SUPPORTED).await;
+            return Err(format!("unsupported address type {}", other));
+        }
+    };
+    let mut addr_and_port = vec![0u8; addr_len + 2];
+    read_exact(stream, &mut addr_and_port).await?;
+    let port = u16::from_be_bytes([addr_and_port[addr_len], addr_and_port[addr_len + 1]]);
+    let frame = parse_target(request[3], &addr_and_port[..addr_len], port)
+        .map_err(str::to_string)
+        .and_then(|target| {
+            debug!("socks5: tunneling to {}:{}", target.host, target.port);
+            encode_proxy_frame(&target).map_err(|err| err.to_string())
+        });
+    match frame {
+        Ok(frame) => {
+            stream
+                .write_all(&reply(REPLY_SUCCEEDED))
+                .await
+                .map_err(|err| err.to_string())?;
+            Ok(frame)
+        }
+        Err(err) => {
+            send_reply(stream, REPLY_GENERAL_FAILURE).await;
+            Err(err)
+        }
+    }
+}
+
+async fn read_exact(stream: &mut (impl AsyncRead + Unpin), buf: &mut [u8]) -> Result<(), String> {
+    stream
+        .read_exact(buf)
+        .await
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+/// A reply with an unspecified IPv4 bound address; clients ignore it for CONNECT.
+fn reply(code: u8) -> [u8; 10] {
+    [SOCKS_VERSION, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]
+}
+
+async fn send_reply(stream: &mut (impl AsyncWrite + Unpin), code: u8) {
+    let _ = stream.write_all(&reply(code)).await;
+}
+
+/// Builds the dial target from a request's address type, address bytes, and port.
+pub(crate) fn parse_target(atyp: u8, addr: &[u8], port: u16) -> Result<ProxyTarget, &'static str> {
+    if port == 0 {
+        return Err("invalid port");
+    }
+    let host = match atyp {
+        ATYP_IPV4 => <[u8; 4]>::try_from(addr)
+            .map(|octets| Ipv4Addr::from(octets).to_string())
+            .map_err(|_| "bad IPv4 address")?,
+        ATYP_IPV6 => <[u8; 16]>::try_from(addr)
+            .map(|octets| Ipv6Addr::from(octets).to_string())
+            .map_err(|_| "bad IPv6 address")?,
+        ATYP_DOMAIN => std::str::from_utf8(addr)
+            .map_err(|_| "domain is not UTF-8")?
+            .to_string(),
+        _ => return Err("unsupported address type"),
+    };
+    if host.is_empty() {
+        return Err("empty host");
+    }
+    Ok(ProxyTarget { host, port })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slipstream_core::proxy_frame::decode_proxy_frame;
+    use tokio::net::{TcpListener, TcpStream as TokioTcpStream};
+
+    #[test]
+    fn parses_request_addresses() {
+        let target = parse_target(ATYP_IPV4, &[192, 0, 2, 1], 80).unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("192.0.2.1", 80));
+        let mut v6 = [0u8; 16];
+        v6[..2].copy_from_slice(&[0x20, 0x01]);
+        v6[15] = 1;
+        let target = parse_target(ATYP_IPV6, &v6, 22).unwrap();
+        assert_eq!(target.host, "2001::1");
+        let target = parse_target(ATYP_DOMAIN, b"example.com", 443).unwrap();
+        assert_eq!(target.host, "example.com");
+        assert!(parse_target(ATYP_DOMAIN, b"", 443).is_err());
+        assert!(parse_target(ATYP_DOMAIN, b"example.com", 0).is_err());
+        assert!(parse_target(ATYP_IPV4, &[192, 0, 2], 80).is_err());
+    }
+
+    #[test]
+    fn negotiates_no_auth_connect() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .expect("build tokio runtime");
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+            let addr = listener.local_addr().expect("local addr");
+            let client = tokio::spawn(async move {
+                let mut client = TokioTcpStream::connect(addr).await.expect("connect");
+                client.write_all(&[5, 2, 2, 0]).await.expect("greeting");
+                let mut method = [0u8; 2];
+                client.read_exact(&mut method).await.expect("method");
+                let mut request = vec![5, CMD_CONNECT, 0, ATYP_DOMAIN, 11];
+                request.extend_from_slice(b"example.com");
+                request.extend_from_slice(&443u16.to_be_bytes());
+                client.write_all(&request).await.expect("request");
+                let mut response = [0u8; 10];
+                client.read_exact(&mut response).await.expect("reply");
+                (method, response)
+            });
+            let (mut stream, _) = listener.accept().await.expect("accept");
+            let preamble = accept_socks5(&mut stream).await.expect("preamble");
+            let (target, frame_len) = decode_proxy_frame(&preamble).unwrap().unwrap();
+            assert_eq!((target.host.as_str(), target.port), ("example.com", 443));
+            assert_eq!(frame_len, preamble.len());
+            let (method, response) = client.await.expect("client");
+            assert_eq!(method, [SOCKS_VERSION, METHOD_NO_AUTH]);
+            assert_eq!(response, reply(REPLY_SUCCEEDED));
+        });
+    }
+
+    #[test]
+    fn refuses_bind_requests() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .expect("build tokio runtime");
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+            let addr = listener.local_addr().expect("local addr");
+            let client = tokio::spawn(async move {
+                let mut client = TokioTcpStream::connect(addr).await.expect("connect");
+                client.write_all(&[5, 1, 0]).await.expect("greeting");
+                let mut method = [0u8; 2];
+                client.read_exact(&mut method).await.expect("method");
+                // BIND to 192.0.2.1:80
+                client
+                    .write_all(&[5, 2, 0, ATYP_IPV4, 192, 0, 2, 1, 0, 80])
+                    .await
+                    .expect("request");
+                let mut response = [0u8; 10];
+                client.read_exact(&mut response).await.expect("reply");
+                response
+            });
+            let (mut stream, _) = listener.accept().await.expect("accept");
+            assert!(accept_socks5(&mut stream).await.is_none());
+            assert_eq!(
+                client.await.expect("client"),
+                reply(REPLY_COMMAND_NOT_SUPPORTED)
+            );
+        });
+    }
+}
@@ -1,9 +1,16 @@
+use crate::datagram::DatagramListener;
+use crate::local_socket::{LocalReadHalf, LocalStream, LocalWriteHalf};
+use slipstream_core::datagram::decode_datagram;
 use slipstream_core::flow_control::{
     conn_reserve_bytes, consume_error_log_message, consume_stream_data, handle_stream_receive,
     overflow_log_message, promote_error_log_message, promote_streams, reserve_target_offset,
     FlowControlState, HasFlowControlState, PromoteEntry, StreamReceiveConfig, StreamReceiveOps,
 };
+use slipstream_core::health::{
+    decode_health_frame, encode_health_frame, extend_health_frame, is_health_stream,
+};
 use slipstream_core::invariants::InvariantReporter;
+use slipstream_core::stream_priority::StreamPriorityMode;
 use slipstream_core::tcp::{stream_read_limit_chunks, tcp_send_buffer_bytes};
 use slipstream_ffi::picoquic::{
     picoquic_add_to_stream, picoquic_call_back_event_t, picoquic_cnx_t, picoquic_current_time,
@@ -11,23 +18,29 @@ use slipstream_ffi::picoquic::{
     picoquic_mark_active_stream, picoquic_provide_stream_data_buffer, picoquic_reset_stream,
     picoquic_stop_sending, picoquic_stream_data_consumed,
 };
-use slipstream_ffi::{abort_stream_bidi, SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR};
-use std::collections::HashMap;
+use slipstream_ffi::{
+    abort_stream_bidi, queue_datagram, PicoquicStreams, StreamControl,
+    SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream as TokioTcpStream;
 use tokio::sync::{mpsc, oneshot, Notify};
 use tracing::{debug, error, info, warn};
 
 const STREAM_READ_CHUNK_BYTES: usize = 4096;
 const DEFAULT_TCP_RCVBUF_BYTES: usize = 256 * 1024;
 const CLIENT_WRITE_COALESCE_DEFAULT_BYTES: usize = 256 * 1024;
+const MAX_HEALTH_CHECKS_IN_FLIGHT: usize = 8;
 static INVARIANT_REPORTER: InvariantReporter = InvariantReporter::new(1_000_000);
 
 pub(crate) struct ClientState {
     ready: bool,
     closing: bool,
-    streams: HashMap<u64, ClientStream>,
+    /// Open streams, oldest first.
+    streams: BTreeMap<u64, ClientStream>,
     multi_stream_mode: bool,
     command_tx: mpsc::UnboundedSender<Command>,
     data_notify: Arc<Notify>,
@@ -37,6 +50,46 @@ pub(crate) struct ClientState {
     debug_enqueued_bytes: u64,
     debug_last_enqueue_at: u64,
     acceptor_limit_logged: bool,
+    /// `--datagram-mode` UDP listener; kept across reconnects like the TCP listeners.
+    datagrams: Option<DatagramListener>,
+    /// How streams are ordered against each other; `None` leaves picoquic's shared default.
+    stream_priority_mode: Option<StreamPriorityMode>,
+    /// Issues stream priorities; tests swap in a recorder.
+    stream_control: Box<dyn StreamControl>,
+    // Stream and datagram payload bytes over the client's lifetime, across reconnects.
+    bytes_sent: u64,
+    bytes_received: u64,
+    /// `--health-listen` probes waiting for the server's echo, by token.
+    health_checks: HashMap<u64, PendingHealthCheck>,
+    /// Echo frames still arriving, by the server's unidirectional stream.
+    health_replies: HashMap<u64, Vec<u8>>,
+    next_health_token: u64,
+}
+
+/// What a QUIC stream carries. Health probes ride unidirectional streams, which the tunnel
+/// never uses, so they are told apart by stream id alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StreamPurpose {
+    Tunnel,
+    Health,
+}
+
+impl StreamPurpose {
+    pub(crate) fn of(stream_id: u64) -> Self {
+        if is_health_stream(stream_id) {
+            StreamPurpose::Health
+        } else {
+            StreamPurpose::Tunnel
+        }
+    }
+}
+
+/// Round trip of a health probe, or why there is none.
+pub(crate) type HealthCheckResult = Result<Duration, String>;
+
+struct PendingHealthCheck {
+    sent_at: Instant,
+    reply: oneshot::Sender<HealthCheckResult>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -96,10 +149,13 @@ pub(crate) struct ClientBacklogSummary {
 
 pub(crate) mod acceptor {
     use super::Command;
+    use crate::connect_proxy::accept_connect;
+    use crate::local_socket::LocalListener;
+    use crate::socks5::accept_socks5;
     use slipstream_ffi::picoquic::{picoquic_cnx_t, slipstream_get_max_streams_bidir_remote};
+    use slipstream_ffi::ListenerMode;
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
-    use tokio::net::TcpListener as TokioTcpListener;
     use tokio::sync::{mpsc, Notify};
     use tokio::time::{sleep, Duration};
     use tracing::warn;
@@ -124,10 +180,11 @@ pub(crate) mod acceptor {
 
         pub(crate) fn spawn(
             &self,
-            listener: TokioTcpListener,
+            listener: LocalListener,
             command_tx: mpsc::UnboundedSender<Command>,
+            mode: ListenerMode,
         ) {
-            TcpAcceptor::new(listener, command_tx, Arc::clone(&self.limiter)).spawn();
+            LocalAcceptor::new(listener, command_tx, Arc::clone(&self.limiter), mode).spawn();
         }
 
         pub(crate) fn update_limit(&self, cnx: *mut picoquic_cnx_t) -> usize {
@@ -303,29 +360,52 @@ pub(crate) mod acceptor {
 
     struct AcceptorGate {
         limiter: Arc<AcceptorLimiter>,
+        mode: ListenerMode,
     }
 
     impl AcceptorGate {
-        fn new(limiter: Arc<AcceptorLimiter>) -> Self {
-            Self { limiter }
+        fn new(limiter: Arc<AcceptorLimiter>, mode: ListenerMode) -> Self {
+            Self { limiter, mode }
         }
 
         async fn accept_and_dispatch(
             &self,
-            listener: &TokioTcpListener,
+            listener: &LocalListener,
             command_tx: &mpsc::UnboundedSender<Command>,
         ) -> bool {
             let reservation = self.limiter.reserve().await;
             match listener.accept().await {
-                Ok((stream, _)) => {
+                Ok(stream) => {
                     if !reservation.is_fresh() {
                         drop(stream);
                         return true;
                     };
+                    if self.mode != ListenerMode::Passthrough {
+                        // The handshake waits on the local client; keep accepting meanwhile.
+                        let stream_tx = command_tx.clone();
+                        let mode = self.mode;
+                        tokio::spawn(async move {
+                            let mut stream = stream;
+                            let preamble = if mode == ListenerMode::Socks5 {
+                                accept_socks5(&mut stream).await
+                            } else {
+                                accept_connect(&mut stream).await
+                            };
+                            if let Some(preamble) = preamble {
+                                let _ = stream_tx.send(Command::NewStream {
+                                    stream,
+                                    reservation,
+                                    preamble,
+                                });
+                            }
+                        });
+                        return !command_tx.is_closed();
+                    }
                     if command_tx
                         .send(Command::NewStream {
                             stream,
                             reservation,
+                            preamble: Vec::new(),
                         })
                         .is_err()
                     {
@@ -351,22 +431,23 @@ pub(crate) mod acceptor {
         }
     }
 
-    struct TcpAcceptor {
-        listener: TokioTcpListener,
+    struct LocalAcceptor {
+        listener: LocalListener,
         command_tx: mpsc::UnboundedSender<Command>,
         gate: AcceptorGate,
     }
 
-    impl TcpAcceptor {
+    impl LocalAcceptor {
         fn new(
-            listener: TokioTcpListener,
+            listener: LocalListener,
             command_tx: mpsc::UnboundedSender<Command>,
             acceptor_backpressure: Arc<AcceptorLimiter>,
+            mode: ListenerMode,
         ) -> Self {
             Self {
                 listener,
                 command_tx,
-                gate: AcceptorGate::new(acceptor_backpressure),
+                gate: AcceptorGate::new(acceptor_backpressure, mode),
             }
         }
 
@@ -438,7 +519,7 @@ impl ClientState {
         Self {
             ready: false,
             closing: false,
-            streams: HashMap::new(),
+            streams: BTreeMap::new(),
             multi_stream_mode: false,
             command_tx,
             data_notify,
@@ -448,9 +529,27 @@ impl ClientState {
             debug_enqueued_bytes: 0,
             debug_last_enqueue_at: 0,
             acceptor_limit_logged: false,
+            datagrams: None,
+            stream_priority_mode: None,
+            stream_control: Box::new(PicoquicStreams),
+            bytes_sent: 0,
+            bytes_received: 0,
+            health_checks: HashMap::new(),
+            health_replies: HashMap::new(),
+            next_health_token: 0,
         }
     }
 
+    /// Forwards the UDP datagrams `listener` receives in QUIC DATAGRAM frames.
+    pub(crate) fn set_datagram_listener(&mut self, listener: DatagramListener) {
+        self.datagrams = Some(listener);
+    }
+
+    /// Gives each new stream a picoquic priority according to `mode`.
+    pub(crate) fn set_stream_priority_mode(&mut self, mode: Option<StreamPriorityMode>) {
+        self.stream_priority_mode = mode;
+    }
+
     pub(crate) fn is_ready(&self) -> bool {
         self.ready
     }
@@ -463,6 +562,11 @@ impl ClientState {
         self.streams.len()
     }
 
+    /// Stream and datagram payload bytes sent and received since startup.
+    pub(crate) fn tunneled_bytes(&self) -> (u64, u64) {
+        (self.bytes_sent, self.bytes_received)
+    }
+
     pub(crate) fn update_acceptor_limit(&mut self, cnx: *mut picoquic_cnx_t) {
         let max_streams = self.acceptor.update_limit(cnx);
         if !self.acceptor_limit_logged && max_streams > 0 {
@@ -546,13 +650,14 @@ impl ClientState {
 
     pub(crate) fn reset_for_reconnect(&mut self) {
         let debug_streams = self.debug_streams;
-        for (stream_id, mut stream) in self.streams.drain() {
+        for (stream_id, mut stream) in std::mem::take(&mut self.streams) {
             if let Some(read_abort_tx) = stream.read_abort_tx.take() {
                 let _ = read_abort_tx.send(());
             }
-            let _ = stream.write_tx.send(StreamWrite::Fin);
+            // A FIN would tell the local app the response was complete.
+            let _ = stream.write_tx.send(StreamWrite::Reset);
             if debug_streams {
-                debug!("stream {}: closing due to reconnect", stream_id);
+                debug!("stream {}: resetting due to reconnect", stream_id);
             }
         }
         self.ready = false;
@@ -563,6 +668,10 @@ impl ClientState {
         self.debug_enqueued_bytes = 0;
         self.debug_last_enqueue_at = 0;
         self.acceptor_limit_logged = false;
+        for (_, check) in self.health_checks.drain() {
+            let _ = check.reply.send(Err("connection lost".to_string()));
+        }
+        self.health_replies.clear();
     }
 }
 
@@ -628,6 +737,23 @@ struct ClientStream {
     recv_state: StreamRecvState,
     send_state: StreamSendState,
     flow: FlowControlState,
+    /// picoquic priority set for `--stream-priority-mode`; `None` is the shared default.
+    priority: Option<u8>,
+}
+
+impl ClientStream {
+    fn new(write_tx: mpsc::UnboundedSender<StreamWrite>) -> Self {
+        Self {
+            write_tx,
+            read_abort_tx: None,
+            data_rx: None,
+            tx_bytes: 0,
+            recv_state: StreamRecvState::Open,
+            send_state: StreamSendState::Open,
+            flow: FlowControlState::default(),
+            priority: None,
+        }
+    }
 }
 
 impl HasFlowControlState for ClientStream {
@@ -643,12 +769,16 @@ impl HasFlowControlState for ClientStream {
 enum StreamWrite {
     Data(Vec<u8>),
     Fin,
+    /// Abort the local TCP connection with a RST; its QUIC stream is gone.
+    Reset,
 }
 
 pub(crate) enum Command {
     NewStream {
-        stream: TokioTcpStream,
+        stream: LocalStream,
         reservation: acceptor::AcceptorReservation,
+        /// Bytes to send ahead of the socket's own data, such as a connect-proxy frame.
+        preamble: Vec<u8>,
     },
     StreamData {
         stream_id: u64,
@@ -667,6 +797,15 @@ pub(crate) enum Command {
         stream_id: u64,
         bytes: usize,
     },
+    /// A UDP datagram from `peer` on the `--datagram-mode` listener.
+    Datagram {
+        peer: SocketAddr,
+        data: Vec<u8>,
+    },
+    /// Send a health probe through the tunnel and report its round trip on `reply`.
+    HealthCheck {
+        reply: oneshot::Sender<HealthCheckResult>,
+    },
 }
 
 pub(crate) enum PathEvent {
@@ -714,10 +853,18 @@ pub(crate) unsafe extern "C" fn client_callback(
             } else {
                 &[]
             };
-            handle_stream_data(cnx, state, stream_id, fin, data);
+            match StreamPurpose::of(stream_id) {
+                StreamPurpose::Tunnel => handle_stream_data(cnx, state, stream_id, fin, data),
+                StreamPurpose::Health => handle_health_reply(cnx, state, stream_id, fin, data),
+            }
         }
         picoquic_call_back_event_t::picoquic_callback_stream_reset
         | picoquic_call_back_event_t::picoquic_callback_stop_sending => {
+            if StreamPurpose::of(stream_id) == StreamPurpose::Health {
+                // The probe's token is lost with it; the waiting check times out.
+                state.health_replies.remove(&stream_id);
+                return 0;
+            }
             let reason = match fin_or_event {
                 picoquic_call_back_event_t::picoquic_callback_stream_reset => "stream_reset",
                 picoquic_call_back_event_t::picoquic_callback_stop_sending => "stop_sending",
@@ -778,6 +925,12 @@ pub(crate) unsafe extern "C" fn client_callback(
                 let _ = picoquic_provide_stream_data_buffer(bytes as *mut _, 0, 0, 0);
             }
         }
+        picoquic_call_back_event_t::picoquic_callback_datagram
+            if length > 0 && !bytes.is_null() =>
+        {
+            let frame = unsafe { std::slice::from_raw_parts(bytes as *const u8, length) };
+            handle_datagram(state, frame);
+        }
         picoquic_call_back_event_t::picoquic_callback_path_available => {
             state.path_events.push(PathEvent::Available(stream_id));
         }
@@ -790,6 +943,85 @@ pub(crate) unsafe extern "C" fn client_callback(
     0
 }
 
+fn handle_datagram(state: &mut ClientState, frame: &[u8]) {
+    let Some(datagrams) = state.datagrams.as_mut() else {
+        return;
+    };
+    let Some((flow_id, payload)) = decode_datagram(frame) else {
+        return;
+    };
+    state.bytes_received = state.bytes_received.saturating_add(payload.len() as u64);
+    datagrams.reply(flow_id, payload, Instant::now());
+}
+
+/// Opens a unidirectional stream carrying one health probe frame.
+fn send_health_probe(
+    cnx: *mut picoquic_cnx_t,
+    state: &mut ClientState,
+    reply: oneshot::Sender<HealthCheckResult>,
+) {
+    if !state.ready {
+        let _ = reply.send(Err("tunnel not ready".to_string()));
+        return;
+    }
+    state
+        .health_checks
+        .retain(|_, check| !check.reply.is_closed());
+    if state.health_checks.len() >= MAX_HEALTH_CHECKS_IN_FLIGHT {
+        let _ = reply.send(Err("too many health checks in flight".to_string()));
+        return;
+    }
+    let token = state.next_health_token;
+    state.next_health_token = state.next_health_token.wrapping_add(1);
+    let frame = encode_health_frame(token);
+    let stream_id = unsafe { picoquic_get_next_local_stream_id(cnx, 1) };
+    let ret = unsafe { picoquic_add_to_stream(cnx, stream_id, frame.as_ptr(), frame.len(), 1) };
+    if ret != 0 {
+        let _ = reply.send(Err(format!("opening health stream failed ret={}", ret)));
+        return;
+    }
+    if state.debug_streams {
+        debug!("stream {}: health probe token={}", stream_id, token);
+    }
+    state.health_checks.insert(
+        token,
+        PendingHealthCheck {
+            sent_at: Instant::now(),
+            reply,
+        },
+    );
+}
+
+/// Collects the server's echo of a health probe and completes the matching check.
+fn handle_health_reply(
+    cnx: *mut picoquic_cnx_t,
+    state: &mut ClientState,
+    stream_id: u64,
+    fin: bool,
+    data: &[u8],
+) {
+    let frame = state.health_replies.entry(stream_id).or_default();
+    if !extend_health_frame(frame, data) {
+        state.health_replies.remove(&stream_id);
+        warn!("stream {}: malformed health reply", stream_id);
+        let _ = unsafe { picoquic_stop_sending(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
+        return;
+    }
+    let _ = unsafe { picoquic_stream_data_consumed(cnx, stream_id, frame.len() as u64) };
+    if !fin {
+        return;
+    }
+    let token = state
+        .health_replies
+        .remove(&stream_id)
+        .and_then(|frame| decode_health_frame(&frame));
+    let Some(check) = token.and_then(|token| state.health_checks.remove(&token)) else {
+        warn!("stream {}: health reply matches no probe", stream_id);
+        return;
+    };
+    let _ = check.reply.send(Ok(check.sent_at.elapsed()));
+}
+
 fn handle_stream_data(
     cnx: *mut picoquic_cnx_t,
     state: &mut ClientState,
@@ -818,6 +1050,7 @@ fn handle_stream_data(
             unsafe { abort_stream_bidi(cnx, stream_id, SLIPSTREAM_FILE_CANCEL_ERROR) };
             return;
         };
+        state.bytes_received = state.bytes_received.saturating_add(data.len() as u64);
 
         if handle_stream_receive(
             stream,
@@ -946,12 +1179,46 @@ mod test_hooks {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::local_socket::LocalListener;
     use slipstream_core::test_support::ResetOnDrop;
+    use slipstream_ffi::ListenerMode;
     use std::sync::Arc;
-    use tokio::net::TcpListener as TokioTcpListener;
+    use tokio::net::{TcpListener as TokioTcpListener, TcpStream as TokioTcpStream};
     use tokio::sync::{mpsc, oneshot, Notify};
     use tokio::time::{sleep, timeout, Duration};
 
+    /// Stands in for picoquic, recording the priorities a handler sets.
+    #[derive(Clone, Default)]
+    struct RecordedPriorities(Arc<std::sync::Mutex<Vec<(u64, u8)>>>);
+
+    impl RecordedPriorities {
+        fn install(state: &mut ClientState) -> Self {
+            let recorded = Self::default();
+            state.stream_control = Box::new(recorded.clone());
+            recorded
+        }
+
+        fn calls(&self) -> Vec<(u64, u8)> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl StreamControl for RecordedPriorities {
+        unsafe fn abort_stream(&mut self, _cnx: *mut picoquic_cnx_t, _stream_id: u64, _code: u64) {
+            unreachable!("no stream is aborted here");
+        }
+
+        unsafe fn set_stream_priority(
+            &mut self,
+            _cnx: *mut picoquic_cnx_t,
+            stream_id: u64,
+            priority: u8,
+        ) -> libc::c_int {
+            self.0.lock().unwrap().push((stream_id, priority));
+            0
+        }
+    }
+
     #[test]
     fn add_to_stream_fin_failure_removes_stream() {
         let _guard = ResetOnDrop::new(|| test_hooks::set_add_to_stream_failures(0));
@@ -966,13 +1233,8 @@ mod tests {
         state.streams.insert(
             stream_id,
             ClientStream {
-                write_tx,
                 read_abort_tx: Some(read_abort_tx),
-                data_rx: None,
-                tx_bytes: 0,
-                recv_state: StreamRecvState::Open,
-                send_state: StreamSendState::Open,
-                flow: FlowControlState::default(),
+                ..ClientStream::new(write_tx)
             },
         );
 
@@ -1004,13 +1266,9 @@ mod tests {
         state.streams.insert(
             stream_id,
             ClientStream {
-                write_tx,
                 read_abort_tx: Some(read_abort_tx),
                 data_rx: Some(data_rx),
-                tx_bytes: 0,
-                recv_state: StreamRecvState::Open,
-                send_state: StreamSendState::Open,
-                flow: FlowControlState::default(),
+                ..ClientStream::new(write_tx)
             },
         );
 
@@ -1032,6 +1290,42 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn reconnect_resets_local_tcp_streams() {
+        let listener = TokioTcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let mut app = TokioTcpStream::connect(addr).await.expect("connect");
+        let (accepted, _) = listener.accept().await.expect("accept");
+        let (read_half, write_half) = LocalStream::Tcp(accepted).into_split();
+
+        let (command_tx, _command_rx) = mpsc::unbounded_channel();
+        let data_notify = Arc::new(Notify::new());
+        let acceptor = acceptor::ClientAcceptor::new();
+        let mut state = ClientState::new(command_tx.clone(), data_notify, false, acceptor);
+        let stream_id = 4;
+        let (write_tx, write_rx) = mpsc::unbounded_channel();
+        let (read_abort_tx, _read_abort_rx) = oneshot::channel();
+        spawn_client_writer(stream_id, write_half, write_rx, command_tx, 1024);
+        state.streams.insert(
+            stream_id,
+            ClientStream {
+                read_abort_tx: Some(read_abort_tx),
+                ..ClientStream::new(write_tx)
+            },
+        );
+
+        state.reset_for_reconnect();
+        // Stands in for the reader task, which exits on read_abort_tx.
+        drop(read_half);
+
+        let mut buf = [0u8; 16];
+        let err = timeout(Duration::from_secs(1), app.read(&mut buf))
+            .await
+            .expect("read timeout")
+            .expect_err("expected a reset, not a clean close");
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+    }
+
     #[test]
     fn stream_removal_requires_both_halves_closed() {
         let (command_tx, _command_rx) = mpsc::unbounded_channel();
@@ -1046,13 +1340,9 @@ mod tests {
         state.streams.insert(
             stream_id,
             ClientStream {
-                write_tx,
                 read_abort_tx: Some(read_abort_tx),
                 data_rx: Some(data_rx),
-                tx_bytes: 0,
-                recv_state: StreamRecvState::Open,
-                send_state: StreamSendState::Open,
-                flow: FlowControlState::default(),
+                ..ClientStream::new(write_tx)
             },
         );
 
@@ -1092,13 +1382,9 @@ mod tests {
         state.streams.insert(
             stream_id,
             ClientStream {
-                write_tx,
                 read_abort_tx: Some(read_abort_tx),
-                data_rx: None,
-                tx_bytes: 0,
-                recv_state: StreamRecvState::Open,
                 send_state: StreamSendState::FinQueued,
-                flow: FlowControlState::default(),
+                ..ClientStream::new(write_tx)
             },
         );
 
@@ -1117,6 +1403,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn latency_mode_promotes_the_oldest_remaining_stream() {
+        let (command_tx, _command_rx) = mpsc::unbounded_channel();
+        let data_notify = Arc::new(Notify::new());
+        let acceptor = acceptor::ClientAcceptor::new();
+        let mut state = ClientState::new(command_tx, data_notify, false, acceptor);
+        state.set_stream_priority_mode(Some(StreamPriorityMode::Latency));
+        let recorded = RecordedPriorities::install(&mut state);
+        let mode = StreamPriorityMode::Latency;
+        for (stream_id, oldest) in [(0, true), (4, false), (8, false)] {
+            let (write_tx, _write_rx) = mpsc::unbounded_channel();
+            state.streams.insert(
+                stream_id,
+                ClientStream {
+                    priority: Some(mode.initial_priority(stream_id, oldest)),
+                    ..ClientStream::new(write_tx)
+                },
+            );
+        }
+        let priority = |state: &ClientState, stream_id| state.streams[&stream_id].priority;
+
+        promote_oldest_stream(std::ptr::null_mut(), &mut state);
+        assert_eq!(priority(&state, 0), Some(0));
+        assert_eq!(priority(&state, 4), Some(2));
+        assert!(
+            recorded.calls().is_empty(),
+            "the oldest stream already leads"
+        );
+
+        state.streams.remove(&0);
+        promote_oldest_stream(std::ptr::null_mut(), &mut state);
+        assert_eq!(priority(&state, 4), Some(0));
+        assert_eq!(priority(&state, 8), Some(2));
+        assert_eq!(recorded.calls(), vec![(4, 0)]);
+
+        state.set_stream_priority_mode(Some(StreamPriorityMode::Throughput));
+        state.streams.remove(&4);
+        promote_oldest_stream(std::ptr::null_mut(), &mut state);
+        assert_eq!(priority(&state, 8), Some(2), "throughput never reorders");
+        assert_eq!(recorded.calls().len(), 1);
+    }
+
     #[test]
     fn mark_active_stream_failure_removes_stream() {
         let _guard = ResetOnDrop::new(|| test_hooks::set_mark_active_stream_failures(0));
@@ -1133,7 +1461,7 @@ mod tests {
             let addr = listener.local_addr().expect("listener addr");
             let accept = tokio::spawn(async move {
                 let (stream, _) = listener.accept().await.expect("accept");
-                stream
+                LocalStream::Tcp(stream)
             });
             let _client = TokioTcpStream::connect(addr).await.expect("connect");
             let stream = accept.await.expect("accept join");
@@ -1152,6 +1480,7 @@ mod tests {
                 Command::NewStream {
                     stream,
                     reservation,
+                    preamble: Vec::new(),
                 },
             );
 
@@ -1178,7 +1507,11 @@ mod tests {
             let addr = listener.local_addr().expect("listener addr");
             let (command_tx, mut command_rx) = mpsc::unbounded_channel();
             let acceptor = acceptor::ClientAcceptor::new();
-            acceptor.spawn(listener, command_tx);
+            acceptor.spawn(
+                LocalListener::Tcp(listener),
+                command_tx,
+                ListenerMode::Passthrough,
+            );
 
             let mut clients = Vec::new();
             for _ in 0..3 {
@@ -1233,6 +1566,48 @@ pub(crate) fn drain_stream_data(cnx: *mut picoquic_cnx_t, state_ptr: *mut Client
     for stream_id in closed_streams {
         handle_command(cnx, state_ptr, Command::StreamClosed { stream_id });
     }
+    promote_oldest_stream(cnx, unsafe { &mut *state_ptr });
+}
+
+fn set_stream_priority(
+    control: &mut dyn StreamControl,
+    cnx: *mut picoquic_cnx_t,
+    stream_id: u64,
+    priority: u8,
+    debug: bool,
+) {
+    let ret = unsafe { control.set_stream_priority(cnx, stream_id, priority) };
+    if ret != 0 && debug {
+        debug!(
+            "stream {}: set_stream_priority({}) failed ret={}",
+            stream_id, priority, ret
+        );
+    }
+}
+
+/// Moves the oldest open stream up once every stream older than it closed, for modes that
+/// reorder streams.
+fn promote_oldest_stream(cnx: *mut picoquic_cnx_t, state: &mut ClientState) {
+    let Some(priority) = state
+        .stream_priority_mode
+        .and_then(StreamPriorityMode::promoted_priority)
+    else {
+        return;
+    };
+    let debug_streams = state.debug_streams;
+    let Some((stream_id, stream)) = state.streams.iter_mut().next() else {
+        return;
+    };
+    if stream.priority != Some(priority) {
+        stream.priority = Some(priority);
+        set_stream_priority(
+            state.stream_control.as_mut(),
+            cnx,
+            *stream_id,
+            priority,
+            debug_streams,
+        );
+    }
 }
 
 pub(crate) fn handle_command(
@@ -1245,6 +1620,7 @@ pub(crate) fn handle_command(
         Command::NewStream {
             stream,
             reservation,
+            preamble,
         } => {
             if !reservation.is_fresh() {
                 drop(stream);
@@ -1302,6 +1678,10 @@ pub(crate) fn handle_command(
                 STREAM_READ_CHUNK_BYTES,
             );
             let (data_tx, data_rx) = mpsc::channel(read_limit);
+            if !preamble.is_empty() {
+                // The channel is empty and has capacity, so this cannot fail.
+                let _ = data_tx.try_send(preamble);
+            }
             let data_notify = state.data_notify.clone();
             let send_buffer_bytes = tcp_send_buffer_bytes(&stream)
                 .filter(|bytes| *bytes > 0)
@@ -1310,16 +1690,28 @@ pub(crate) fn handle_command(
             let (write_tx, write_rx) = mpsc::unbounded_channel();
             let command_tx = state.command_tx.clone();
             let (read_abort_tx, read_abort_rx) = oneshot::channel();
+            let priority = state.stream_priority_mode.map(|mode| {
+                let oldest = state
+                    .streams
+                    .first_key_value()
+                    .is_none_or(|(other, _)| *other > stream_id);
+                let priority = mode.initial_priority(stream_id, oldest);
+                set_stream_priority(
+                    state.stream_control.as_mut(),
+                    cnx,
+                    stream_id,
+                    priority,
+                    state.debug_streams,
+                );
+                priority
+            });
             state.streams.insert(
                 stream_id,
                 ClientStream {
-                    write_tx,
                     read_abort_tx: Some(read_abort_tx),
                     data_rx: Some(data_rx),
-                    tx_bytes: 0,
-                    recv_state: StreamRecvState::Open,
-                    send_state: StreamSendState::Open,
-                    flow: FlowControlState::default(),
+                    priority,
+                    ..ClientStream::new(write_tx)
                 },
             );
             spawn_client_reader(
@@ -1381,6 +1773,7 @@ pub(crate) fn handle_command(
                 state.streams.remove(&stream_id);
             } else if let Some(stream) = state.streams.get_mut(&stream_id) {
                 stream.tx_bytes = stream.tx_bytes.saturating_add(data.len() as u64);
+                state.bytes_sent = state.bytes_sent.saturating_add(data.len() as u64);
                 let now = unsafe { picoquic_current_time() };
                 state.debug_enqueued_bytes =
                     state.debug_enqueued_bytes.saturating_add(data.len() as u64);
@@ -1505,12 +1898,32 @@ pub(crate) fn handle_command(
             }
             check_stream_invariants(state, stream_id, "StreamWriteDrained");
         }
+        Command::Datagram { peer, data } => {
+            if !state.ready {
+                return;
+            }
+            let Some(datagrams) = state.datagrams.as_mut() else {
+                return;
+            };
+            let flow_id = datagrams.flow_for(peer, Instant::now());
+            if unsafe { queue_datagram(cnx, flow_id, &data) } {
+                state.bytes_sent = state.bytes_sent.saturating_add(data.len() as u64);
+            } else if state.debug_streams {
+                debug!(
+                    "flow {}: dropped a {} byte datagram from {} that does not fit a packet",
+                    flow_id,
+                    data.len(),
+                    peer
+                );
+            }
+        }
+        Command::HealthCheck { reply } => send_health_probe(cnx, state, reply),
     }
 }
 
 fn spawn_client_reader(
     stream_id: u64,
-    mut read_half: tokio::net::tcp::OwnedReadHalf,
+    mut read_half: LocalReadHalf,
     mut read_abort_rx: oneshot::Receiver<()>,
     command_tx: mpsc::UnboundedSender<Command>,
     data_tx: mpsc::Sender<Vec<u8>>,
@@ -1553,7 +1966,7 @@ fn spawn_client_reader(
 
 fn spawn_client_writer(
     stream_id: u64,
-    mut write_half: tokio::net::tcp::OwnedWriteHalf,
+    mut write_half: LocalWriteHalf,
     mut write_rx: mpsc::UnboundedReceiver<StreamWrite>,
     command_tx: mpsc::UnboundedSender<Command>,
     coalesce_max_bytes: usize,
@@ -1565,6 +1978,7 @@ fn spawn_client_writer(
                 StreamWrite::Data(data) => {
                     let mut buffer = data;
                     let mut saw_fin = false;
+                    let mut saw_reset = false;
                     while buffer.len() < coalesce_max_bytes {
                         match write_rx.try_recv() {
                             Ok(StreamWrite::Data(more)) => {
@@ -1577,6 +1991,10 @@ fn spawn_client_writer(
                                 saw_fin = true;
                                 break;
                             }
+                            Ok(StreamWrite::Reset) => {
+                                saw_reset = true;
+                                break;
+                            }
                             Err(mpsc::error::TryRecvError::Empty) => break,
                             Err(mpsc::error::TryRecvError::Disconnected) => {
                                 saw_fin = true;
@@ -1584,6 +2002,10 @@ fn spawn_client_writer(
                             }
                         }
                     }
+                    if saw_reset {
+                        write_half.reset();
+                        return;
+                    }
                     let len = buffer.len();
                     if write_half.write_all(&buffer).await.is_err() {
                         let _ = command_tx.send(Command::StreamWriteError { stream_id });
@@ -1602,6 +2024,10 @@ fn spawn_client_writer(
                     let _ = write_half.shutdown().await;
                     return;
                 }
+                StreamWrite::Reset => {
+                    write_half.reset();
+                    return;
+                }
             }
         }
         let _ = write_half.shutdown().await;
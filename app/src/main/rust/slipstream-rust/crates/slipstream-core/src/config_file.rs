@@ -0,0 +1,433 @@
+//! TOML configuration files for `--config`.
+//!
+//! A file holds `[section]` tables whose keys are CLI flag names with `_` for `-`, and arrays
+//! of tables (`[[section]]`) for repeated groups. Values are turned back into command-line
+//! arguments, so they go through the same parsers as flags and count as given on the command
+//! line when weighed against SIP003 options. Flags that really are on the command line win
+//! over the file.
+
+use crate::ConfigError;
+use clap::error::{ContextKind, ContextValue};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use toml::de::{DeTable, DeValue};
+use toml::Spanned;
+
+#[derive(Debug)]
+pub struct ConfigFile {
+    path: String,
+    sections: Vec<Section>,
+}
+
+/// One `[name]` table, or one entry of a `[[name]]` array.
+#[derive(Debug)]
+pub struct Section {
+    pub name: String,
+    pub line: usize,
+    pub options: Vec<ConfigOption>,
+}
+
+#[derive(Debug)]
+pub struct ConfigOption {
+    pub key: String,
+    pub line: usize,
+    pub value: ConfigValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigValue {
+    Flag(bool),
+    /// A scalar, or every element of an array, rendered as flag values.
+    Values(Vec<String>),
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&ConfigOption> {
+        self.options.iter().find(|option| option.key == key)
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let display = path.display().to_string();
+        let source = fs::read_to_string(path)
+            .map_err(|err| ConfigError::new(format!("{}: {}", display, err)))?;
+        Self::parse(&display, &source)
+    }
+
+    pub fn parse(path: &str, source: &str) -> Result<Self, ConfigError> {
+        let root = DeTable::parse(source).map_err(|err| {
+            let line = err.span().map(|span| line_of(source, span.start));
+            match line {
+                Some(line) => ConfigError::new(format!("{}:{}: {}", path, line, err.message())),
+                None => ConfigError::new(format!("{}: {}", path, err.message())),
+            }
+        })?;
+        let mut file = Self {
+            path: path.to_string(),
+            sections: Vec::new(),
+        };
+        for (name, value) in root.get_ref() {
+            let line = line_of(source, name.span().start);
+            match value.get_ref() {
+                DeValue::Table(table) => {
+                    let section = file.section(source, name.get_ref(), line, table)?;
+                    file.sections.push(section);
+                }
+                DeValue::Array(entries)
+                    if entries.iter().all(|entry| entry.get_ref().is_table()) =>
+                {
+                    for entry in entries {
+                        let line = line_of(source, entry.span().start);
+                        if let Some(table) = entry.get_ref().as_table() {
+                            let section = file.section(source, name.get_ref(), line, table)?;
+                            file.sections.push(section);
+                        }
+                    }
+                }
+                _ => {
+                    return Err(file.error(
+                        line,
+                        format!("{} must be a [section] or [[section]]", name.get_ref()),
+                    ))
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    /// Rejects sections other than `known`, which are most likely typos.
+    pub fn check_sections(&self, known: &[&str]) -> Result<(), ConfigError> {
+        match self
+            .sections
+            .iter()
+            .find(|section| !known.contains(&section.name.as_str()))
+        {
+            Some(section) => Err(self.error(
+                section.line,
+                format!(
+                    "Unknown section [{}] (expected {})",
+                    section.name,
+                    known.join(", ")
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn error(&self, line: usize, message: impl std::fmt::Display) -> ConfigError {
+        ConfigError::new(format!("{}:{}: {}", self.path, line, message))
+    }
+
+    fn section(
+        &self,
+        source: &str,
+        name: &str,
+        line: usize,
+        table: &DeTable<'_>,
+    ) -> Result<Section, ConfigError> {
+        let mut options = Vec::with_capacity(table.len());
+        for (key, value) in table {
+            let line = line_of(source, key.span().start);
+            let value = match value.get_ref() {
+                DeValue::Boolean(flag) => ConfigValue::Flag(*flag),
+                DeValue::Array(items) => {
+                    let mut values = Vec::with_capacity(items.len());
+                    for item in items {
+                        values.push(self.scalar(source, key.get_ref(), item)?);
+                    }
+                    ConfigValue::Values(values)
+                }
+                _ => ConfigValue::Values(vec![self.scalar(source, key.get_ref(), value)?]),
+            };
+            options.push(ConfigOption {
+                key: key.get_ref().to_string(),
+                line,
+                value,
+            });
+        }
+        Ok(Section {
+            name: name.to_string(),
+            line,
+            options,
+        })
+    }
+
+    fn scalar(
+        &self,
+        source: &str,
+        key: &str,
+        value: &Spanned<DeValue<'_>>,
+    ) -> Result<String, ConfigError> {
+        match value.get_ref() {
+            DeValue::String(text) => Ok(text.to_string()),
+            DeValue::Integer(integer) => i128::from_str_radix(integer.as_str(), integer.radix())
+                .map(|number| number.to_string())
+                .map_err(|err| self.error(line_of(source, value.span().start), err)),
+            other => Err(self.error(
+                line_of(source, value.span().start),
+                format!(
+                    "{} must be a string, integer, or boolean (got {})",
+                    key,
+                    other.type_str()
+                ),
+            )),
+        }
+    }
+}
+
+/// Command-line arguments rendered from a config file, remembering the line of each.
+pub struct FileArgs<'a> {
+    file: &'a ConfigFile,
+    command: &'a Command,
+    matches: &'a ArgMatches,
+    args: Vec<OsString>,
+    origins: Vec<(String, Option<String>, usize)>,
+}
+
+impl<'a> FileArgs<'a> {
+    /// `matches` is the command line alone; flags it sets are left out of the file's share.
+    pub fn new(file: &'a ConfigFile, command: &'a Command, matches: &'a ArgMatches) -> Self {
+        Self {
+            file,
+            command,
+            matches,
+            args: Vec::new(),
+            origins: Vec::new(),
+        }
+    }
+
+    /// Renders every option of `section` as the flag of the same name.
+    pub fn push_section(&mut self, section: &Section) -> Result<(), ConfigError> {
+        for option in &section.options {
+            let Some(arg) = self.arg(&option.key) else {
+                return Err(self.file.error(
+                    option.line,
+                    format!("Unknown option {} in [{}]", option.key, section.name),
+                ));
+            };
+            if self.command_line_provided(arg.get_id().as_str()) {
+                continue;
+            }
+            match &option.value {
+                ConfigValue::Flag(flag) => self.push_flag(option.line, &option.key, *flag)?,
+                ConfigValue::Values(values) => {
+                    for value in values {
+                        self.push_value(option.line, &option.key, value)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends `--flag value` for argument `id`.
+    pub fn push_value(&mut self, line: usize, id: &str, value: &str) -> Result<(), ConfigError> {
+        let long = self.long(line, id)?;
+        if !self.takes_values(id) {
+            return Err(self
+                .file
+                .error(line, format!("{} must be true or false", id)));
+        }
+        self.args.push(format!("--{}", long).into());
+        self.args.push(value.into());
+        self.origins.push((long, Some(value.to_string()), line));
+        Ok(())
+    }
+
+    fn push_flag(&mut self, line: usize, key: &str, flag: bool) -> Result<(), ConfigError> {
+        let long = self.long(line, key)?;
+        if self.takes_values(key) {
+            return Err(self
+                .file
+                .error(line, format!("{} needs a value, not true or false", key)));
+        }
+        if flag {
+            self.args.push(format!("--{}", long).into());
+            self.origins.push((long, None, line));
+        }
+        Ok(())
+    }
+
+    /// Whether argument `id` was given on the command line itself.
+    pub fn command_line_provided(&self, id: &str) -> bool {
+        self.matches.value_source(id) == Some(ValueSource::CommandLine)
+    }
+
+    /// Parses the file's arguments ahead of `cli` (program name first), so values the file
+    /// sets are checked by the flags' own parsers; errors name the line they came from.
+    pub fn into_matches(
+        self,
+        cli: impl IntoIterator<Item = OsString>,
+    ) -> Result<ArgMatches, ConfigError> {
+        let mut cli = cli.into_iter();
+        let argv = cli
+            .next()
+            .into_iter()
+            .chain(self.args.iter().cloned())
+            .chain(cli);
+        self.command
+            .clone()
+            .try_get_matches_from(argv)
+            .map_err(|err| self.locate(&err))
+    }
+
+    fn locate(&self, err: &clap::Error) -> ConfigError {
+        let rendered = err.to_string();
+        let summary = rendered.lines().next().unwrap_or_default();
+        let summary = summary.strip_prefix("error: ").unwrap_or(summary);
+        let arg = match err.get(ContextKind::InvalidArg) {
+            Some(ContextValue::String(arg)) => arg.as_str(),
+            _ => "",
+        };
+        let value = match err.get(ContextKind::InvalidValue) {
+            Some(ContextValue::String(value)) => Some(value.as_str()),
+            _ => None,
+        };
+        let origin = self.origins.iter().find(|(long, origin_value, _)| {
+            let flag = format!("--{}", long);
+            (arg == flag || arg.starts_with(&format!("{} ", flag)))
+                && (value.is_none() || origin_value.as_deref() == value)
+        });
+        match origin {
+            Some((_, _, line)) => self.file.error(*line, summary),
+            None => ConfigError::new(format!("{}: {}", self.file.path, summary)),
+        }
+    }
+
+    /// Finds the flag spelled `key` with `_` for `-`.
+    fn arg(&self, key: &str) -> Option<&clap::Arg> {
+        if matches!(key, "config" | "help" | "version") {
+            return None;
+        }
+        self.command.get_arguments().find(|arg| {
+            arg.get_long()
+                .is_some_and(|long| long.replace('-', "_") == key)
+        })
+    }
+
+    fn long(&self, line: usize, key: &str) -> Result<String, ConfigError> {
+        self.arg(key)
+            .and_then(|arg| arg.get_long())
+            .map(str::to_string)
+            .ok_or_else(|| self.file.error(line, format!("Unknown option {}", key)))
+    }
+
+    fn takes_values(&self, key: &str) -> bool {
+        self.arg(key)
+            .is_some_and(|arg| arg.get_action().takes_values())
+    }
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    fn command() -> Command {
+        Command::new("test")
+            .arg(Arg::new("config").long("config"))
+            .arg(
+                Arg::new("tcp_listen_port")
+                    .long("tcp-listen-port")
+                    .value_parser(clap::value_parser!(u16))
+                    .default_value("5201"),
+            )
+            .arg(Arg::new("domains").long("domain").action(ArgAction::Append))
+            .arg(Arg::new("gso").long("gso").action(ArgAction::SetTrue))
+    }
+
+    fn parse(source: &str, cli: &[&str]) -> Result<ArgMatches, ConfigError> {
+        let file = ConfigFile::parse("test.toml", source)?;
+        let command = command();
+        let argv: Vec<OsString> = std::iter::once("test")
+            .chain(cli.iter().copied())
+            .map(OsString::from)
+            .collect();
+        let matches = command.clone().try_get_matches_from(&argv).unwrap();
+        let mut args = FileArgs::new(&file, &command, &matches);
+        for section in file.sections() {
+            args.push_section(section)?;
+        }
+        args.into_matches(argv)
+    }
+
+    #[test]
+    fn options_become_flags_below_the_command_line() {
+        let source = "[client]\ntcp_listen_port = 5300\ndomain = [\"a.example\", \"b.example\"]\ngso = true\n";
+        let matches = parse(source, &[]).unwrap();
+        assert_eq!(matches.get_one::<u16>("tcp_listen_port"), Some(&5300));
+        let domains: Vec<&String> = matches.get_many("domains").unwrap().collect();
+        assert_eq!(domains, ["a.example", "b.example"]);
+        assert!(matches.get_flag("gso"));
+
+        let matches = parse(
+            source,
+            &["--tcp-listen-port", "6000", "--domain", "c.example"],
+        )
+        .unwrap();
+        assert_eq!(matches.get_one::<u16>("tcp_listen_port"), Some(&6000));
+        let domains: Vec<&String> = matches.get_many("domains").unwrap().collect();
+        assert_eq!(domains, ["c.example"]);
+    }
+
+    #[test]
+    fn errors_carry_the_line() {
+        let err = parse("[client]\n\ntcp_listen_port = 70000\n", &[]).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("test.toml:3: invalid value '70000'"),
+            "{}",
+            err
+        );
+        let err = parse("[client]\nbogus = 1\n", &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "test.toml:2: Unknown option bogus in [client]"
+        );
+        let err = parse("[client]\ngso = \"yes\"\n", &[]).unwrap_err();
+        assert_eq!(err.to_string(), "test.toml:2: gso must be true or false");
+        let err = ConfigFile::parse("test.toml", "[client]\nport = \n").unwrap_err();
+        assert!(err.to_string().starts_with("test.toml:2: "), "{}", err);
+        let err = ConfigFile::parse("test.toml", "port = 1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "test.toml:1: port must be a [section] or [[section]]"
+        );
+    }
+
+    #[test]
+    fn arrays_of_tables_become_repeated_sections() {
+        let source = "[[resolver]]\nhost = \"192.0.2.1\"\n\n[[resolver]]\nhost = \"192.0.2.2\"\nport = 5353\n";
+        let file = ConfigFile::parse("test.toml", source).unwrap();
+        let hosts: Vec<_> = file
+            .sections()
+            .iter()
+            .map(|section| (section.line, section.get("host").unwrap().value.clone()))
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                (1, ConfigValue::Values(vec!["192.0.2.1".to_string()])),
+                (4, ConfigValue::Values(vec!["192.0.2.2".to_string()])),
+            ]
+        );
+        assert!(file.check_sections(&["resolver"]).is_ok());
+        assert!(file.check_sections(&["client"]).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+//! DNS tunnel settings that configs name without depending on the slipstream-dns codec.
+
+pub const EDNS_UDP_PAYLOAD: u16 = 1232;
+/// Smallest UDP payload size EDNS0 may advertise (RFC 6891).
+pub const MIN_EDNS_UDP_PAYLOAD: u16 = 512;
+
+/// Alphabet used to carry payload bytes in the QNAME subdomain.
+///
+/// Base64url packs 6 bits per character instead of 5 but is case-sensitive, so it only
+/// survives resolvers that preserve the QNAME case (no 0x20 randomization).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Encoding {
+    #[default]
+    Base32,
+    Base64Url,
+}
+
+/// Parses an encoding name (`base32` or `base64url`), case-insensitively.
+pub fn parse_encoding(name: &str) -> Option<Encoding> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "base32" => Some(Encoding::Base32),
+        "base64url" => Some(Encoding::Base64Url),
+        _ => None,
+    }
+}
+
+/// EDNS0 OPT record fields written into outbound messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EdnsOptions {
+    /// Advertised UDP payload size, at least [`MIN_EDNS_UDP_PAYLOAD`].
+    pub udp_payload: u16,
+    /// Sets the DO (DNSSEC OK) bit in the OPT TTL field.
+    pub dnssec_ok: bool,
+    /// Adds an EDNS Client Subnet option for `0.0.0.0/0`, asking resolvers not to attach or
+    /// route on the client's subnet (RFC 7871 section 7.1.2).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ecs_optout: bool,
+    /// Adds an EDNS Padding option (RFC 7830) that rounds the whole message up to a multiple
+    /// of this many bytes, so query sizes no longer track payload length; 0 leaves it out.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub padding_block: u16,
+}
+
+impl Default for EdnsOptions {
+    fn default() -> Self {
+        Self {
+            udp_payload: EDNS_UDP_PAYLOAD,
+            dnssec_ok: false,
+            ecs_optout: false,
+            padding_block: 0,
+        }
+    }
+}
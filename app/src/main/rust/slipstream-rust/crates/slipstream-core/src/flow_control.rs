@@ -1,3 +1,6 @@
+use crate::assert_invariant;
+use std::collections::VecDeque;
+use std::fmt;
 use std::sync::OnceLock;
 
 const DEFAULT_STREAM_QUEUE_MAX_BYTES: usize = 2 * 1024 * 1024;
@@ -13,6 +16,93 @@ pub struct FlowControlState {
     pub stop_sending_sent: bool,
 }
 
+impl FlowControlState {
+    pub fn snapshot(&self) -> FlowControlSnapshot {
+        FlowControlSnapshot {
+            queued_bytes: self.queued_bytes,
+            rx_bytes: self.rx_bytes,
+            consumed_offset: self.consumed_offset,
+            fin_offset: self.fin_offset,
+            discarding: self.discarding,
+            stop_sending_sent: self.stop_sending_sent,
+        }
+    }
+}
+
+/// Copy of a stream's [`FlowControlState`] for logs and diagnostic dumps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlowControlSnapshot {
+    pub queued_bytes: usize,
+    pub rx_bytes: u64,
+    pub consumed_offset: u64,
+    pub fin_offset: Option<u64>,
+    pub discarding: bool,
+    pub stop_sending_sent: bool,
+}
+
+/// Renders one line of `key=value` pairs, e.g.
+/// `queued=1024 rx=8192 consumed=7168 fin=none discarding=false stop_sending=false`.
+impl fmt::Display for FlowControlSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queued={} rx={} consumed={} fin=",
+            self.queued_bytes, self.rx_bytes, self.consumed_offset
+        )?;
+        match self.fin_offset {
+            Some(offset) => write!(f, "{}", offset)?,
+            None => f.write_str("none")?,
+        }
+        write!(
+            f,
+            " discarding={} stop_sending={}",
+            self.discarding, self.stop_sending_sent
+        )
+    }
+}
+
+/// Snapshots a [`FlowControlHistory`] keeps before dropping the oldest.
+pub const FLOW_CONTROL_HISTORY_LEN: usize = 16;
+
+/// One [`FlowControlHistory`] entry: a stream's snapshot and when it was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowControlSample {
+    pub at_ms: u64,
+    pub stream_id: u64,
+    pub snapshot: FlowControlSnapshot,
+}
+
+/// The last [`FLOW_CONTROL_HISTORY_LEN`] flow-control snapshots, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowControlHistory {
+    samples: VecDeque<FlowControlSample>,
+}
+
+impl FlowControlHistory {
+    pub fn record(&mut self, at_ms: u64, stream_id: u64, snapshot: FlowControlSnapshot) {
+        if self.samples.len() == FLOW_CONTROL_HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(FlowControlSample {
+            at_ms,
+            stream_id,
+            snapshot,
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FlowControlSample> {
+        self.samples.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
 pub trait HasFlowControlState {
     fn flow_control(&self) -> &FlowControlState;
     fn flow_control_mut(&mut self) -> &mut FlowControlState;
@@ -268,6 +358,12 @@ pub fn promote_consumed_offset<F, G>(
             on_error(ret, current);
         },
     );
+    assert_invariant!(
+        *consumed_offset <= rx_bytes,
+        "consumed_offset={} past rx_bytes={}",
+        *consumed_offset,
+        rx_bytes
+    );
 }
 
 pub fn promote_streams<'a, I, Consume, Log>(entries: I, mut consume_fn: Consume, mut on_error: Log)
@@ -385,6 +481,18 @@ where
         }
     }
 
+    assert_invariant!(
+        !config.multi_stream || queued_bytes <= config.max_queue,
+        "queued_bytes={} over max_queue={}",
+        queued_bytes,
+        config.max_queue
+    );
+    assert_invariant!(
+        consumed_offset <= rx_bytes,
+        "consumed_offset={} past rx_bytes={}",
+        consumed_offset,
+        rx_bytes
+    );
     stream.set_queued_bytes(queued_bytes);
     stream.set_consumed_offset(consumed_offset);
     stream.set_discarding(discarding);
@@ -392,3 +500,35 @@ where
 
     reset_stream
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_renders_one_line_and_history_keeps_the_newest() {
+        let state = FlowControlState {
+            queued_bytes: 1024,
+            rx_bytes: 8192,
+            consumed_offset: 7168,
+            ..FlowControlState::default()
+        };
+        let snapshot = state.snapshot();
+        assert_eq!(
+            snapshot.to_string(),
+            "queued=1024 rx=8192 consumed=7168 fin=none discarding=false stop_sending=false"
+        );
+        let finished = FlowControlSnapshot {
+            fin_offset: Some(8192),
+            ..snapshot
+        };
+        assert!(finished.to_string().contains(" fin=8192 "));
+
+        let mut history = FlowControlHistory::default();
+        for at_ms in 0..(FLOW_CONTROL_HISTORY_LEN as u64 + 2) {
+            history.record(at_ms, 4, snapshot);
+        }
+        assert_eq!(history.len(), FLOW_CONTROL_HISTORY_LEN);
+        assert_eq!(history.iter().next().map(|sample| sample.at_ms), Some(2));
+    }
+}
@@ -0,0 +1,68 @@
+//! Health probe carried on a reserved stream so supervisors can check the data path.
+//!
+//! The client opens a unidirectional stream and writes one frame: 4-byte magic `SLHC` then an
+//! 8-byte big-endian token, followed by FIN. The server answers on a unidirectional stream of
+//! its own with the same frame. The tunnel itself only uses bidirectional streams, so the
+//! stream type alone tells a probe apart and it never reaches a target.
+
+pub const HEALTH_FRAME_MAGIC: [u8; 4] = *b"SLHC";
+pub const HEALTH_FRAME_LEN: usize = HEALTH_FRAME_MAGIC.len() + 8;
+
+/// Whether `stream_id` is a unidirectional stream, which only health probes use.
+pub fn is_health_stream(stream_id: u64) -> bool {
+    stream_id & 0x2 != 0
+}
+
+pub fn encode_health_frame(token: u64) -> [u8; HEALTH_FRAME_LEN] {
+    let mut frame = [0u8; HEALTH_FRAME_LEN];
+    frame[..HEALTH_FRAME_MAGIC.len()].copy_from_slice(&HEALTH_FRAME_MAGIC);
+    frame[HEALTH_FRAME_MAGIC.len()..].copy_from_slice(&token.to_be_bytes());
+    frame
+}
+
+/// Decodes a complete frame; anything else, including trailing bytes, is `None`.
+pub fn decode_health_frame(buf: &[u8]) -> Option<u64> {
+    if buf.len() != HEALTH_FRAME_LEN || buf[..HEALTH_FRAME_MAGIC.len()] != HEALTH_FRAME_MAGIC {
+        return None;
+    }
+    let token: [u8; 8] = buf[HEALTH_FRAME_MAGIC.len()..].try_into().ok()?;
+    Some(u64::from_be_bytes(token))
+}
+
+/// Appends `data` to a partly received frame, returning false once it can no longer be one.
+pub fn extend_health_frame(buf: &mut Vec<u8>, data: &[u8]) -> bool {
+    if buf.len() + data.len() > HEALTH_FRAME_LEN {
+        return false;
+    }
+    buf.extend_from_slice(data);
+    let magic_len = buf.len().min(HEALTH_FRAME_MAGIC.len());
+    buf[..magic_len] == HEALTH_FRAME_MAGIC[..magic_len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_round_trips_across_chunks() {
+        let frame = encode_health_frame(0x0102_0304_0506_0708);
+        let mut buf = Vec::new();
+        for chunk in frame.chunks(5) {
+            assert!(extend_health_frame(&mut buf, chunk));
+        }
+        assert_eq!(decode_health_frame(&buf), Some(0x0102_0304_0506_0708));
+
+        assert!(!extend_health_frame(&mut buf, &[0]));
+        assert!(!extend_health_frame(&mut Vec::new(), b"SLPX"));
+        assert_eq!(decode_health_frame(&frame[..HEALTH_FRAME_LEN - 1]), None);
+    }
+
+    #[test]
+    fn only_unidirectional_streams_are_health_streams() {
+        // Client bidi, server bidi, client uni, server uni.
+        assert!(!is_health_stream(0));
+        assert!(!is_health_stream(5));
+        assert!(is_health_stream(2));
+        assert!(is_health_stream(7));
+    }
+}
@@ -0,0 +1,73 @@
+//! Minimal HTTP/1.1 for the plaintext status endpoints of the client and server: one request
+//! head per connection, answered with a complete response and `Connection: close`.
+
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Longest request head read before the request is refused.
+pub const MAX_REQUEST_HEAD_BYTES: usize = 8192;
+/// How long a client gets to send its request head.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads up to the blank line ending the request head; `None` if it never arrives.
+pub async fn read_request_head<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<String>> {
+    let mut buf = Vec::with_capacity(512);
+    let mut chunk = [0u8; 512];
+    loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..read]);
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            return Ok(String::from_utf8(buf[..end].to_vec()).ok());
+        }
+        if buf.len() > MAX_REQUEST_HEAD_BYTES {
+            return Ok(None);
+        }
+    }
+}
+
+/// Method and path of the request line, with any query string dropped.
+pub fn request_target(head: &str) -> Option<(&str, &str)> {
+    let request_line = head.lines().next()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?;
+    let target = parts.next()?;
+    Some((method, target.split('?').next().unwrap_or_default()))
+}
+
+pub fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+pub fn plain_response(status: &str, body: &str) -> String {
+    response(status, "text/plain", body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_request_line_and_frames_responses() {
+        assert_eq!(
+            request_target("GET /health?verbose=1 HTTP/1.1\r\nHost: localhost"),
+            Some(("GET", "/health"))
+        );
+        assert_eq!(request_target("GET"), None);
+        assert_eq!(request_target(""), None);
+        assert_eq!(
+            plain_response("404 Not Found", "not found\n"),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 10\r\n\
+             Connection: close\r\n\r\nnot found\n"
+        );
+    }
+}
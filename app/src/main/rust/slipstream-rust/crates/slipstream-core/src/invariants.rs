@@ -1,4 +1,54 @@
+//! Runtime invariant checks.
+//!
+//! `assert_invariant!` guards preconditions the flow-control and event-loop code relies on.
+//! Debug builds log a violation and keep going unless `SLIPSTREAM_STRICT_INVARIANTS=1` asks
+//! for an abort; release builds compile the check out. `InvariantReporter` rate-limits the
+//! stream-state checks that run in every build.
+
+use std::fmt;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// Checks `condition` in debug builds and reports a violation with the formatted message.
+#[macro_export]
+macro_rules! assert_invariant {
+    ($condition:expr, $($arg:tt)+) => {
+        if cfg!(debug_assertions) && !$condition {
+            $crate::invariants::violated(file!(), line!(), format_args!($($arg)+));
+        }
+    };
+}
+
+static VIOLATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// `assert_invariant!` failures so far; the watchdog reports it with each stall.
+pub fn violation_count() -> u64 {
+    VIOLATIONS.load(Ordering::Relaxed)
+}
+
+/// Whether a violation aborts the process (`SLIPSTREAM_STRICT_INVARIANTS=1`).
+pub fn strict() -> bool {
+    static STRICT: OnceLock<bool> = OnceLock::new();
+    *STRICT.get_or_init(|| {
+        std::env::var("SLIPSTREAM_STRICT_INVARIANTS").is_ok_and(|value| value.trim() == "1")
+    })
+}
+
+#[doc(hidden)]
+pub fn violated(file: &'static str, line: u32, message: fmt::Arguments<'_>) {
+    VIOLATIONS.fetch_add(1, Ordering::Relaxed);
+    let strict = strict();
+    #[cfg(feature = "tracing")]
+    tracing::error!(file, line, strict, "invariant violated: {}", message);
+    #[cfg(not(feature = "tracing"))]
+    eprintln!("invariant violated at {}:{}: {}", file, line, message);
+    if cfg!(any(test, feature = "invariant-panic")) {
+        panic!("invariant violated at {}:{}: {}", file, line, message);
+    }
+    if strict {
+        std::process::abort();
+    }
+}
 
 pub struct InvariantReporter {
     interval_us: u64,
@@ -47,3 +97,23 @@ impl InvariantReporter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holding_invariants_report_nothing() {
+        let before = violation_count();
+        assert_invariant!(1 + 1 == 2, "arithmetic broke: {}", 1 + 1);
+        assert_eq!(violation_count(), before);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "invariant violated")]
+    fn violations_panic_in_tests() {
+        let queued = 3;
+        assert_invariant!(queued <= 2, "queued={} over the cap", queued);
+    }
+}
@@ -1,12 +1,26 @@
 use std::fmt;
 
+#[cfg(feature = "config-file")]
+pub mod config_file;
+pub mod datagram;
+pub mod dns;
 pub mod flow_control;
+pub mod fs;
+pub mod health;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod invariants;
+pub mod logging;
 mod macros;
 pub mod net;
+pub mod proxy_frame;
+pub mod rate_limit;
 pub mod sip003;
 pub mod stream;
+pub mod stream_priority;
 pub mod tcp;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
 
 #[cfg(feature = "test-support")]
@@ -14,16 +28,59 @@ use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSoc
 pub mod test_support;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum AddressFamily {
     V4,
     V6,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HostPort {
     pub host: String,
     pub port: u16,
     pub family: AddressFamily,
+    /// IPv6 zone (`%eth0` or `%3`) scoping a link-local address to an interface.
+    pub zone: Option<String>,
+}
+
+/// Renders `host:port`, or `[host%zone]:port` for IPv6, which `parse_host_port` reads back.
+impl fmt::Display for HostPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.family, &self.zone) {
+            (AddressFamily::V4, _) => write!(f, "{}:{}", self.host, self.port),
+            (AddressFamily::V6, None) => write!(f, "[{}]:{}", self.host, self.port),
+            (AddressFamily::V6, Some(zone)) => write!(f, "[{}%{}]:{}", self.host, zone, self.port),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for HostPort {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Reads the `Display` form back; the port must be spelled out.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for HostPort {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let input = String::deserialize(deserializer)?;
+        let parsed = parse_host_port(&input, 0, AddressKind::Target).map_err(D::Error::custom)?;
+        if parsed.port == 0 {
+            return Err(D::Error::custom(format!(
+                "Missing port in address: {}",
+                input
+            )));
+        }
+        Ok(parsed)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -54,6 +111,55 @@ pub enum AddressKind {
     Fallback,
 }
 
+/// Which address families `--address-family` lets through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum AddressPolicy {
+    /// Literals of either family; hostnames resolve to IPv4, except server targets, which
+    /// race both families.
+    #[default]
+    Any,
+    Ipv4Only,
+    /// Hostnames resolve to IPv6 and never fall back to IPv4.
+    Ipv6Only,
+}
+
+impl AddressPolicy {
+    pub fn label(self) -> &'static str {
+        match self {
+            AddressPolicy::Any => "any",
+            AddressPolicy::Ipv4Only => "4",
+            AddressPolicy::Ipv6Only => "6",
+        }
+    }
+
+    /// Whether a resolved address belongs to a family this policy lets through.
+    pub fn allows(self, addr: &SocketAddr) -> bool {
+        match self {
+            AddressPolicy::Any => true,
+            AddressPolicy::Ipv4Only => addr.is_ipv4(),
+            AddressPolicy::Ipv6Only => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Parses `--address-family`: `4`, `6` or `any`.
+pub fn parse_address_policy(input: &str) -> Result<AddressPolicy, String> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "any" => Ok(AddressPolicy::Any),
+        "4" | "ipv4" => Ok(AddressPolicy::Ipv4Only),
+        "6" | "ipv6" => Ok(AddressPolicy::Ipv6Only),
+        _ => Err(format!(
+            "Invalid address family: {} (expected 4, 6 or any)",
+            input
+        )),
+    }
+}
+
 impl AddressKind {
     fn label(self) -> &'static str {
         match self {
@@ -64,6 +170,8 @@ impl AddressKind {
     }
 }
 
+/// Trims a domain and its trailing dot. A single leading `*.` label is kept as a wildcard;
+/// `*` anywhere else is rejected.
 pub fn normalize_domain(input: &str) -> Result<String, ConfigError> {
     let trimmed = input.trim();
     if trimmed.is_empty() {
@@ -73,20 +181,74 @@ pub fn normalize_domain(input: &str) -> Result<String, ConfigError> {
     if without_dot.is_empty() {
         return Err(ConfigError::new("Domain must not be empty"));
     }
+    let base = wildcard_base(without_dot).unwrap_or(without_dot);
+    if base.is_empty() || base.starts_with('.') || base.contains('*') {
+        return Err(ConfigError::new(format!(
+            "Invalid domain {}: only a single leading `*.` label may be a wildcard",
+            trimmed
+        )));
+    }
     Ok(without_dot.to_string())
 }
 
-pub fn parse_resolver_addresses(addrs: &[String]) -> Result<Vec<HostPort>, ConfigError> {
+/// Domain under a `*.` wildcard, or `None` for a plain domain.
+pub fn wildcard_base(domain: &str) -> Option<&str> {
+    domain.strip_prefix("*.")
+}
+
+pub fn parse_resolver_addresses(
+    addrs: &[String],
+    policy: AddressPolicy,
+) -> Result<Vec<HostPort>, ConfigError> {
     let mut parsed = Vec::with_capacity(addrs.len());
 
     for addr in addrs {
-        let parsed_addr = parse_host_port(addr, 53, AddressKind::Resolver)?;
+        let parsed_addr = parse_host_port_with_policy(addr, 53, AddressKind::Resolver, policy)?;
         parsed.push(parsed_addr);
     }
 
     Ok(parsed)
 }
 
+/// Like `parse_host_port`, but checks the address against `policy`.
+pub fn parse_host_port_with_policy(
+    input: &str,
+    default_port: u16,
+    kind: AddressKind,
+    policy: AddressPolicy,
+) -> Result<HostPort, ConfigError> {
+    parse_host_port(input, default_port, kind)
+        .and_then(|address| apply_address_policy(address, kind, policy))
+}
+
+/// Checks a parsed address against `policy`: literals of the other family are rejected and,
+/// under `Ipv6Only`, hostnames switch to IPv6 resolution.
+pub fn apply_address_policy(
+    mut address: HostPort,
+    kind: AddressKind,
+    policy: AddressPolicy,
+) -> Result<HostPort, ConfigError> {
+    let literal = address.host.parse::<std::net::IpAddr>().is_ok();
+    let rejected = match (policy, address.family) {
+        (AddressPolicy::Ipv4Only, AddressFamily::V6) => Some("IPv6"),
+        (AddressPolicy::Ipv6Only, AddressFamily::V4) if literal => Some("IPv4"),
+        _ => None,
+    };
+    if let Some(family) = rejected {
+        return Err(ConfigError::new(format!(
+            "{} {} address {} is not allowed with --address-family {}",
+            family,
+            kind.label(),
+            address,
+            policy.label()
+        )));
+    }
+    if policy == AddressPolicy::Ipv6Only {
+        address.family = AddressFamily::V6;
+    }
+    Ok(address)
+}
+
 pub fn parse_host_port(
     input: &str,
     default_port: u16,
@@ -100,7 +262,7 @@ pub fn parse_host_port(
             )));
         };
 
-        let host = &rest[..end];
+        let (host, zone) = split_zone(&rest[..end], input, kind)?;
         if host.is_empty() {
             return Err(ConfigError::new(format!(
                 "Invalid IPv6 address in {}: {}",
@@ -125,6 +287,7 @@ pub fn parse_host_port(
             host: host.to_string(),
             port,
             family: AddressFamily::V6,
+            zone,
         });
     }
 
@@ -157,11 +320,19 @@ pub fn parse_host_port(
             input
         )));
     }
+    if host.contains('%') {
+        return Err(ConfigError::new(format!(
+            "Zone identifiers need a bracketed IPv6 address in {}: {}",
+            kind.label(),
+            input
+        )));
+    }
 
     Ok(HostPort {
         host: host.to_string(),
         port,
         family: AddressFamily::V4,
+        zone: None,
     })
 }
 
@@ -170,7 +341,7 @@ pub fn parse_host_port_parts(
     port: u16,
     kind: AddressKind,
 ) -> Result<HostPort, ConfigError> {
-    let trimmed = host.trim();
+    let (trimmed, zone) = split_zone(host.trim(), host, kind)?;
     if trimmed.is_empty() {
         return Err(ConfigError::new(format!(
             "Invalid {} address: {}",
@@ -197,6 +368,7 @@ pub fn parse_host_port_parts(
         host: trimmed.to_string(),
         port,
         family,
+        zone,
     })
 }
 
@@ -209,16 +381,21 @@ pub fn resolve_host_port(address: &HostPort) -> Result<SocketAddr, ConfigError>
         }
         AddressFamily::V6 => {
             if let Ok(ip) = address.host.parse::<Ipv6Addr>() {
-                return Ok(SocketAddr::V6(SocketAddrV6::new(ip, address.port, 0, 0)));
+                let scope_id = match &address.zone {
+                    Some(zone) => zone_scope_id(zone)?,
+                    None => 0,
+                };
+                return Ok(SocketAddr::V6(SocketAddrV6::new(
+                    ip,
+                    address.port,
+                    0,
+                    scope_id,
+                )));
             }
         }
     }
 
-    let addr_str = match address.family {
-        AddressFamily::V4 => format!("{}:{}", address.host, address.port),
-        AddressFamily::V6 => format!("[{}]:{}", address.host, address.port),
-    };
-    let addrs = addr_str
+    let addrs = (address.host.as_str(), address.port)
         .to_socket_addrs()
         .map_err(|_| ConfigError::new(format!("Cannot resolve {}", address.host)))?;
 
@@ -249,6 +426,46 @@ pub fn normalize_dual_stack_addr(addr: SocketAddr) -> SocketAddr {
     }
 }
 
+/// Splits a `%zone` suffix off an IPv6 literal; zones on anything else are rejected.
+fn split_zone<'a>(
+    host: &'a str,
+    input: &str,
+    kind: AddressKind,
+) -> Result<(&'a str, Option<String>), ConfigError> {
+    let Some((address, zone)) = host.split_once('%') else {
+        return Ok((host, None));
+    };
+    if zone.is_empty() || address.parse::<Ipv6Addr>().is_err() {
+        return Err(ConfigError::new(format!(
+            "Zone identifiers need an IPv6 address in {}: {}",
+            kind.label(),
+            input
+        )));
+    }
+    Ok((address, Some(zone.to_string())))
+}
+
+/// Numeric zones are used as-is; names are looked up as network interfaces.
+fn zone_scope_id(zone: &str) -> Result<u32, ConfigError> {
+    if let Ok(scope_id) = zone.parse::<u32>() {
+        return Ok(scope_id);
+    }
+    #[cfg(unix)]
+    {
+        if let Ok(name) = std::ffi::CString::new(zone) {
+            // SAFETY: `name` is a valid NUL-terminated string for the duration of the call.
+            let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+            if index != 0 {
+                return Ok(index);
+            }
+        }
+    }
+    Err(ConfigError::new(format!(
+        "Unknown network interface: {}",
+        zone
+    )))
+}
+
 fn parse_port(port_str: &str, input: &str, kind: AddressKind) -> Result<u16, ConfigError> {
     let port: u16 = port_str.parse().map_err(|_| {
         ConfigError::new(format!(
@@ -266,3 +483,131 @@ fn parse_port(port_str: &str, input: &str, kind: AddressKind) -> Result<u16, Con
     }
     Ok(port)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_domain_accepts_one_leading_wildcard() {
+        assert_eq!(
+            normalize_domain(" *.Example.com. ").unwrap(),
+            "*.Example.com"
+        );
+        assert_eq!(wildcard_base("*.example.com"), Some("example.com"));
+        assert_eq!(wildcard_base("example.com"), None);
+        for input in [
+            "*",
+            "*.",
+            "**.example.com",
+            "*.*.example.com",
+            "a.*.example.com",
+            "*example.com",
+            "*..example.com",
+        ] {
+            assert!(normalize_domain(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn display_round_trips_through_parse() {
+        for input in [
+            "192.0.2.1:53",
+            "example.com:853",
+            "[2001:db8::1]:443",
+            "[fe80::1%eth0]:53",
+        ] {
+            let parsed = parse_host_port(input, 1, AddressKind::Resolver).unwrap();
+            assert_eq!(parsed.to_string(), input);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_uses_the_display_form() {
+        let parsed = parse_host_port("[2001:db8::1]:443", 53, AddressKind::Resolver).unwrap();
+        let json = serde_json::to_string(&parsed).unwrap();
+        assert_eq!(json, "\"[2001:db8::1]:443\"");
+        let back: HostPort = serde_json::from_str(&json).unwrap();
+        assert_eq!((back.host.as_str(), back.port), ("2001:db8::1", 443));
+        assert_eq!(back.family, AddressFamily::V6);
+        assert!(serde_json::from_str::<HostPort>("\"192.0.2.1\"").is_err());
+        assert_eq!(serde_json::to_string(&AddressFamily::V4).unwrap(), "\"v4\"");
+    }
+
+    #[test]
+    fn parses_named_zone() {
+        let parsed = parse_host_port("[fe80::1%eth0]:53", 53, AddressKind::Resolver).unwrap();
+        assert_eq!(parsed.host, "fe80::1");
+        assert_eq!(parsed.zone.as_deref(), Some("eth0"));
+        assert_eq!(parsed.family, AddressFamily::V6);
+        assert_eq!(parsed.port, 53);
+    }
+
+    #[test]
+    fn numeric_zone_becomes_scope_id() {
+        let parsed = parse_host_port("[fe80::1%3]", 5353, AddressKind::Resolver).unwrap();
+        assert_eq!(parsed.zone.as_deref(), Some("3"));
+        match resolve_host_port(&parsed).unwrap() {
+            SocketAddr::V6(addr) => {
+                assert_eq!(addr.scope_id(), 3);
+                assert_eq!(addr.port(), 5353);
+            }
+            other => panic!("expected an IPv6 address, got {}", other),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn interface_zone_resolves_to_its_index() {
+        let parsed = parse_host_port("[fe80::1%lo]:53", 53, AddressKind::Resolver).unwrap();
+        let expected = unsafe { libc::if_nametoindex(c"lo".as_ptr()) };
+        match resolve_host_port(&parsed).unwrap() {
+            SocketAddr::V6(addr) => assert_eq!(addr.scope_id(), expected),
+            other => panic!("expected an IPv6 address, got {}", other),
+        }
+        let unknown = parse_host_port("[fe80::1%nosuchif0]:53", 53, AddressKind::Resolver).unwrap();
+        assert!(resolve_host_port(&unknown).is_err());
+    }
+
+    #[test]
+    fn rejects_zone_on_ipv4() {
+        assert!(parse_host_port("192.0.2.1%eth0:53", 53, AddressKind::Resolver).is_err());
+        assert!(parse_host_port("[192.0.2.1%eth0]:53", 53, AddressKind::Resolver).is_err());
+        assert!(parse_host_port_parts("192.0.2.1%eth0", 53, AddressKind::Target).is_err());
+        assert!(parse_host_port("[fe80::1%]:53", 53, AddressKind::Resolver).is_err());
+    }
+
+    #[test]
+    fn parts_accept_zone() {
+        let parsed = parse_host_port_parts("fe80::1%eth0", 53, AddressKind::Target).unwrap();
+        assert_eq!(parsed.host, "fe80::1");
+        assert_eq!(parsed.zone.as_deref(), Some("eth0"));
+    }
+
+    #[test]
+    fn address_policy_filters_literals_and_pins_hostnames() {
+        let v6_only = AddressPolicy::Ipv6Only;
+        assert!(
+            parse_host_port_with_policy("192.0.2.1:53", 53, AddressKind::Resolver, v6_only)
+                .is_err()
+        );
+        let host =
+            parse_host_port_with_policy("example.com", 53, AddressKind::Resolver, v6_only).unwrap();
+        assert_eq!(host.family, AddressFamily::V6);
+        assert_eq!(host.to_string(), "[example.com]:53");
+
+        let v4_only = AddressPolicy::Ipv4Only;
+        assert!(
+            parse_host_port_with_policy("[2001:db8::1]", 53, AddressKind::Resolver, v4_only)
+                .is_err()
+        );
+        let host =
+            parse_host_port_with_policy("example.com", 53, AddressKind::Resolver, v4_only).unwrap();
+        assert_eq!(host.family, AddressFamily::V4);
+
+        assert_eq!(parse_address_policy("6"), Ok(AddressPolicy::Ipv6Only));
+        assert_eq!(parse_address_policy("any"), Ok(AddressPolicy::Any));
+        assert!(parse_address_policy("5").is_err());
+    }
+}
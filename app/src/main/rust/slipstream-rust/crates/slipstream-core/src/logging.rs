@@ -0,0 +1,61 @@
+/// Environment variable consulted when `--log-format` is not given.
+pub const LOG_FORMAT_ENV: &str = "SLIPSTREAM_LOG_FORMAT";
+
+/// Output format of the tracing subscriber installed by the binaries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+pub fn parse_log_format(input: &str) -> Result<LogFormat, String> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "text" => Ok(LogFormat::Text),
+        "json" => Ok(LogFormat::Json),
+        _ => Err(format!(
+            "Invalid log format '{}'; expected text or json",
+            input
+        )),
+    }
+}
+
+/// Picks the CLI value, then `SLIPSTREAM_LOG_FORMAT`, then `default`.
+///
+/// An unparsable environment value still yields `default` alongside the error, so callers can
+/// install a subscriber before reporting it.
+pub fn resolve_log_format(
+    cli: Option<LogFormat>,
+    default: LogFormat,
+) -> (LogFormat, Option<String>) {
+    if let Some(format) = cli {
+        return (format, None);
+    }
+    match std::env::var(LOG_FORMAT_ENV) {
+        Ok(value) => match parse_log_format(&value) {
+            Ok(format) => (format, None),
+            Err(err) => (default, Some(format!("{}: {}", LOG_FORMAT_ENV, err))),
+        },
+        Err(_) => (default, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_log_formats() {
+        assert_eq!(parse_log_format("text"), Ok(LogFormat::Text));
+        assert_eq!(parse_log_format("JSON"), Ok(LogFormat::Json));
+        assert!(parse_log_format("logfmt").is_err());
+        assert_eq!(
+            resolve_log_format(Some(LogFormat::Json), LogFormat::Text),
+            (LogFormat::Json, None)
+        );
+        assert_eq!(
+            resolve_log_format(Some(LogFormat::Text), LogFormat::Json),
+            (LogFormat::Text, None)
+        );
+    }
+}
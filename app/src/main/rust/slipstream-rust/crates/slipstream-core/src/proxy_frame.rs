@@ -0,0 +1,167 @@
+//! Header frame naming the dial target at the start of a connect-proxy stream.
+//!
+//! Layout: 4-byte magic `SLPX`, 2-byte big-endian host length, the host as UTF-8 (a DNS name
+//! or an IP literal without brackets), then a 2-byte big-endian port. Stream data follows the
+//! frame directly.
+
+use std::fmt;
+
+pub const PROXY_FRAME_MAGIC: [u8; 4] = *b"SLPX";
+/// Longest host a frame may carry; DNS names stop at 253 bytes.
+pub const MAX_PROXY_HOST_LEN: usize = 255;
+/// Largest encoded frame, so readers know when to stop waiting for more bytes.
+pub const MAX_PROXY_FRAME_LEN: usize = PROXY_FRAME_MAGIC.len() + 2 + MAX_PROXY_HOST_LEN + 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyTarget {
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyFrameError {
+    BadMagic,
+    EmptyHost,
+    HostTooLong(usize),
+    InvalidHost,
+    ZeroPort,
+}
+
+impl fmt::Display for ProxyFrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyFrameError::BadMagic => write!(f, "proxy frame has a bad magic"),
+            ProxyFrameError::EmptyHost => write!(f, "proxy frame host is empty"),
+            ProxyFrameError::HostTooLong(len) => write!(
+                f,
+                "proxy frame host is {} bytes; at most {} allowed",
+                len, MAX_PROXY_HOST_LEN
+            ),
+            ProxyFrameError::InvalidHost => write!(f, "proxy frame host is not valid"),
+            ProxyFrameError::ZeroPort => write!(f, "proxy frame port is 0"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyFrameError {}
+
+pub fn encode_proxy_frame(target: &ProxyTarget) -> Result<Vec<u8>, ProxyFrameError> {
+    validate_host(target.host.as_bytes())?;
+    if target.port == 0 {
+        return Err(ProxyFrameError::ZeroPort);
+    }
+    let host = target.host.as_bytes();
+    let mut frame = Vec::with_capacity(PROXY_FRAME_MAGIC.len() + 4 + host.len());
+    frame.extend_from_slice(&PROXY_FRAME_MAGIC);
+    frame.extend_from_slice(&(host.len() as u16).to_be_bytes());
+    frame.extend_from_slice(host);
+    frame.extend_from_slice(&target.port.to_be_bytes());
+    Ok(frame)
+}
+
+/// Decodes a frame from the start of `buf`.
+///
+/// Returns `Ok(None)` while `buf` may still be the prefix of a valid frame, and otherwise the
+/// target with the number of bytes the frame used.
+pub fn decode_proxy_frame(buf: &[u8]) -> Result<Option<(ProxyTarget, usize)>, ProxyFrameError> {
+    let magic_len = buf.len().min(PROXY_FRAME_MAGIC.len());
+    if buf[..magic_len] != PROXY_FRAME_MAGIC[..magic_len] {
+        return Err(ProxyFrameError::BadMagic);
+    }
+    let header_len = PROXY_FRAME_MAGIC.len() + 2;
+    if buf.len() < header_len {
+        return Ok(None);
+    }
+    let host_len = u16::from_be_bytes([buf[4], buf[5]]) as usize;
+    if host_len > MAX_PROXY_HOST_LEN {
+        return Err(ProxyFrameError::HostTooLong(host_len));
+    }
+    let frame_len = header_len + host_len + 2;
+    if buf.len() < frame_len {
+        return Ok(None);
+    }
+    let host = &buf[header_len..header_len + host_len];
+    validate_host(host)?;
+    let port = u16::from_be_bytes([buf[frame_len - 2], buf[frame_len - 1]]);
+    if port == 0 {
+        return Err(ProxyFrameError::ZeroPort);
+    }
+    let host = String::from_utf8(host.to_vec()).map_err(|_| ProxyFrameError::InvalidHost)?;
+    Ok(Some((ProxyTarget { host, port }, frame_len)))
+}
+
+fn validate_host(host: &[u8]) -> Result<(), ProxyFrameError> {
+    if host.is_empty() {
+        return Err(ProxyFrameError::EmptyHost);
+    }
+    if host.len() > MAX_PROXY_HOST_LEN {
+        return Err(ProxyFrameError::HostTooLong(host.len()));
+    }
+    if host
+        .iter()
+        .any(|byte| !byte.is_ascii_graphic() || matches!(byte, b'/' | b'[' | b']' | b'@'))
+    {
+        return Err(ProxyFrameError::InvalidHost);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(host: &str, port: u16) -> ProxyTarget {
+        ProxyTarget {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn round_trips_and_reports_frame_length() {
+        let frame = encode_proxy_frame(&target("example.com", 443)).unwrap();
+        assert_eq!(&frame[..6], b"SLPX\x00\x0b");
+        assert_eq!(&frame[frame.len() - 2..], &[0x01, 0xbb]);
+        let mut stream = frame.clone();
+        stream.extend_from_slice(b"GET /");
+        assert_eq!(
+            decode_proxy_frame(&stream).unwrap(),
+            Some((target("example.com", 443), frame.len()))
+        );
+        let frame = encode_proxy_frame(&target("2001:db8::1", 22)).unwrap();
+        assert_eq!(
+            decode_proxy_frame(&frame).unwrap().unwrap().0,
+            target("2001:db8::1", 22)
+        );
+    }
+
+    #[test]
+    fn waits_for_partial_frames() {
+        let frame = encode_proxy_frame(&target("example.com", 80)).unwrap();
+        for len in 0..frame.len() {
+            assert_eq!(decode_proxy_frame(&frame[..len]), Ok(None), "len={}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        assert_eq!(decode_proxy_frame(b"GET "), Err(ProxyFrameError::BadMagic));
+        assert_eq!(decode_proxy_frame(b"SL-"), Err(ProxyFrameError::BadMagic));
+        assert_eq!(
+            decode_proxy_frame(b"SLPX\x01\x00"),
+            Err(ProxyFrameError::HostTooLong(256))
+        );
+        assert_eq!(
+            decode_proxy_frame(b"SLPX\x00\x00\x00\x50"),
+            Err(ProxyFrameError::EmptyHost)
+        );
+        assert_eq!(
+            decode_proxy_frame(b"SLPX\x00\x01a\x00\x00"),
+            Err(ProxyFrameError::ZeroPort)
+        );
+        assert_eq!(
+            encode_proxy_frame(&target("a b", 80)),
+            Err(ProxyFrameError::InvalidHost)
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Byte-rate token bucket shared by the streams of one connection.
+
+/// Limits a byte stream to `rate` bytes per second with bursts of up to one second's worth.
+///
+/// A write larger than the tokens on hand is still admitted once the bucket is non-empty and
+/// leaves it in debt, so chunk sizes never need to fit the burst; the debt delays later writes
+/// and the average stays at `rate`. Times are in microseconds.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: u64,
+    tokens: i64,
+    last_refill_at: u64,
+}
+
+impl TokenBucket {
+    pub fn new(rate_bytes_per_sec: u64, now: u64) -> Self {
+        let rate = rate_bytes_per_sec.clamp(1, i64::MAX as u64);
+        Self {
+            rate,
+            tokens: rate as i64,
+            last_refill_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: u64) {
+        let elapsed_us = now.saturating_sub(self.last_refill_at);
+        if elapsed_us == 0 {
+            return;
+        }
+        let earned =
+            (self.rate as u128 * elapsed_us as u128 / 1_000_000).min(self.rate as u128) as i64;
+        if earned == 0 && self.tokens < self.rate as i64 {
+            // Leave the clock alone so sub-byte intervals keep accumulating.
+            return;
+        }
+        self.last_refill_at = now;
+        self.tokens = self.tokens.saturating_add(earned).min(self.rate as i64);
+    }
+
+    /// Takes `bytes` and returns 0, or leaves the bucket untouched and returns the
+    /// microseconds to wait before trying again.
+    pub fn try_take(&mut self, bytes: usize, now: u64) -> u64 {
+        self.refill(now);
+        if self.tokens > 0 {
+            self.tokens = self
+                .tokens
+                .saturating_sub(bytes.min(i64::MAX as usize) as i64);
+            return 0;
+        }
+        let missing = (1 - self.tokens) as u128;
+        (missing * 1_000_000).div_ceil(self.rate as u128) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admits_a_burst_then_paces_to_the_rate() {
+        let mut bucket = TokenBucket::new(1000, 0);
+        assert_eq!(bucket.try_take(600, 0), 0);
+        assert_eq!(bucket.try_take(600, 0), 0);
+        // 200 bytes of debt plus one byte to get back above zero.
+        assert_eq!(bucket.try_take(1, 0), 201_000);
+        assert_eq!(bucket.try_take(1, 200_000), 1_000);
+        assert_eq!(bucket.try_take(1, 201_000), 0);
+
+        // Idle time never banks more than one second's worth.
+        assert_eq!(bucket.try_take(0, 10_000_000), 0);
+        assert_eq!(bucket.tokens, 1000);
+    }
+
+    #[test]
+    fn keeps_fractional_refills() {
+        let mut bucket = TokenBucket::new(10, 0);
+        assert_eq!(bucket.try_take(10, 0), 0);
+        for now in (50_000..100_000).step_by(50_000) {
+            assert_ne!(bucket.try_take(1, now), 0);
+        }
+        assert_eq!(bucket.try_take(1, 100_000), 0);
+    }
+}
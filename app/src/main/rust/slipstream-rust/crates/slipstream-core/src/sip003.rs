@@ -1,4 +1,5 @@
 use crate::ConfigError;
+use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Sip003Option {
@@ -207,6 +208,105 @@ pub fn last_option_value(options: &[Sip003Option], key: &str) -> Option<String>
     last
 }
 
+/// Checks an option value; the error names what is wrong with it.
+pub type OptionValidator = Box<dyn Fn(&str) -> Result<(), String>>;
+
+/// One `SS_PLUGIN_OPTIONS` key a binary understands.
+pub struct SchemaDef {
+    pub key: &'static str,
+    pub required: bool,
+    pub validator: OptionValidator,
+}
+
+impl SchemaDef {
+    /// An optional key that takes any value.
+    pub fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            required: false,
+            validator: Box::new(|_| Ok(())),
+        }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// Accepts values `parse` accepts once trimmed.
+    pub fn parsed_by<T, E: fmt::Display>(
+        mut self,
+        parse: impl Fn(&str) -> Result<T, E> + 'static,
+    ) -> Self {
+        self.validator =
+            Box::new(move |value| parse(value.trim()).map(drop).map_err(|err| err.to_string()));
+        self
+    }
+
+    /// Accepts comma-separated lists whose every entry `parse` accepts.
+    pub fn list_parsed_by<T, E: fmt::Display>(
+        mut self,
+        parse: impl Fn(&str) -> Result<T, E> + 'static,
+    ) -> Self {
+        self.validator = Box::new(move |value| {
+            for entry in split_list(value).map_err(|err| err.to_string())? {
+                parse(&entry).map_err(|err| err.to_string())?;
+            }
+            Ok(())
+        });
+        self
+    }
+
+    pub fn validator(mut self, validator: impl Fn(&str) -> Result<(), String> + 'static) -> Self {
+        self.validator = Box::new(validator);
+        self
+    }
+}
+
+/// The `SS_PLUGIN_OPTIONS` keys a binary understands; see [`validate`].
+pub struct Sip003Schema {
+    pub defs: Vec<SchemaDef>,
+}
+
+impl Sip003Schema {
+    pub fn new(defs: Vec<SchemaDef>) -> Self {
+        Self { defs }
+    }
+
+    fn get(&self, key: &str) -> Option<&SchemaDef> {
+        self.defs.iter().find(|def| def.key == key)
+    }
+}
+
+/// Checks `options` against `schema`, reporting every unknown key, missing required key, and
+/// invalid value rather than stopping at the first.
+pub fn validate(options: &[Sip003Option], schema: &Sip003Schema) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    for option in options {
+        match schema.get(&option.key) {
+            Some(def) => {
+                if let Err(err) = (def.validator)(&option.value) {
+                    errors.push(format!("SS_PLUGIN_OPTIONS {}: {}", option.key, err));
+                }
+            }
+            None => errors.push(format!("Unknown SS_PLUGIN_OPTIONS key: {}", option.key)),
+        }
+    }
+    for def in schema.defs.iter().filter(|def| def.required) {
+        if !options.iter().any(|option| option.key == def.key) {
+            errors.push(format!(
+                "Missing required SS_PLUGIN_OPTIONS key: {}",
+                def.key
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
 fn read_env_value(name: &str) -> Option<String> {
     std::env::var(name)
         .ok()
@@ -254,7 +354,10 @@ fn allows_empty_value_key(key: &str) -> bool {
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_endpoint, parse_plugin_options, split_list, Sip003Option};
+    use super::{
+        parse_endpoint, parse_plugin_options, split_list, validate, SchemaDef, Sip003Option,
+        Sip003Schema,
+    };
 
     #[test]
     fn parses_plugin_options_with_escapes() {
@@ -318,4 +421,31 @@ mod tests {
     fn rejects_zero_port_in_endpoint() {
         assert!(parse_endpoint(Some("127.0.0.1"), Some("0"), "SS_LOCAL").is_err());
     }
+
+    #[test]
+    fn validate_reports_every_schema_error() {
+        let schema = Sip003Schema::new(vec![
+            SchemaDef::new("domain").required(),
+            SchemaDef::new("port").parsed_by(|value| value.parse::<u16>()),
+            SchemaDef::new("resolver").list_parsed_by(|value| value.parse::<u8>()),
+            SchemaDef::new("path"),
+        ]);
+        let valid = parse_plugin_options("domain=a.example;port= 53 ;resolver=1,2;path=x").unwrap();
+        assert_eq!(validate(&valid, &schema), Ok(()));
+        assert_eq!(validate(&[], &Sip003Schema::new(Vec::new())), Ok(()));
+
+        let invalid = parse_plugin_options("port=dns;resolver=1,,2;mode=tcp;resolver=300").unwrap();
+        let errors = validate(&invalid, &schema).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "SS_PLUGIN_OPTIONS port: invalid digit found in string".to_string(),
+                "SS_PLUGIN_OPTIONS resolver: Invalid SS_PLUGIN_OPTIONS list entry (empty value)"
+                    .to_string(),
+                "Unknown SS_PLUGIN_OPTIONS key: mode".to_string(),
+                "SS_PLUGIN_OPTIONS resolver: number too large to fit in target type".to_string(),
+                "Missing required SS_PLUGIN_OPTIONS key: domain".to_string(),
+            ]
+        );
+    }
 }
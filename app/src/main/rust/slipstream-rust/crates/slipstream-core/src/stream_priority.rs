@@ -0,0 +1,120 @@
+//! Per-stream QUIC priorities for `--stream-priority-mode`.
+//!
+//! picoquic sends from the stream with the lowest priority value first. Streams sharing an even
+//! value take turns round robin; streams sharing an odd value go out lowest stream id first.
+//! Without a mode every stream keeps [`DEFAULT_STREAM_PRIORITY`].
+
+/// Priority `configure_quic_common` gives every stream: one level, round robin.
+pub const DEFAULT_STREAM_PRIORITY: u8 = 2;
+
+/// One level served strictly in stream id order.
+const FIFO_PRIORITY: u8 = 3;
+const LATENCY_HEAD_PRIORITY: u8 = 0;
+const LATENCY_REST_PRIORITY: u8 = 2;
+const THROUGHPUT_LEVELS: u64 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum StreamPriorityMode {
+    /// Streams finish in the order they opened.
+    Fifo,
+    /// The oldest open stream of a connection goes first; the others share the next level.
+    Latency,
+    /// Streams spread over priorities 0-7 in the order they open.
+    Throughput,
+}
+
+impl StreamPriorityMode {
+    pub fn label(self) -> &'static str {
+        match self {
+            StreamPriorityMode::Fifo => "fifo",
+            StreamPriorityMode::Latency => "latency",
+            StreamPriorityMode::Throughput => "throughput",
+        }
+    }
+
+    /// Priority of a stream as it opens; `oldest` says no earlier stream of its connection is
+    /// still open.
+    pub fn initial_priority(self, stream_id: u64, oldest: bool) -> u8 {
+        match self {
+            StreamPriorityMode::Fifo => FIFO_PRIORITY,
+            StreamPriorityMode::Latency if oldest => LATENCY_HEAD_PRIORITY,
+            StreamPriorityMode::Latency => LATENCY_REST_PRIORITY,
+            // Stream ids of one type step by 4, so this counts streams in open order.
+            StreamPriorityMode::Throughput => ((stream_id >> 2) % THROUGHPUT_LEVELS) as u8,
+        }
+    }
+
+    /// Priority the oldest remaining stream moves to when the oldest one closes, if the mode
+    /// reorders streams at all.
+    pub fn promoted_priority(self) -> Option<u8> {
+        match self {
+            StreamPriorityMode::Latency => Some(LATENCY_HEAD_PRIORITY),
+            StreamPriorityMode::Fifo | StreamPriorityMode::Throughput => None,
+        }
+    }
+}
+
+/// Parses `--stream-priority-mode`: `fifo`, `latency` or `throughput`.
+pub fn parse_stream_priority_mode(input: &str) -> Result<StreamPriorityMode, String> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "fifo" => Ok(StreamPriorityMode::Fifo),
+        "latency" => Ok(StreamPriorityMode::Latency),
+        "throughput" => Ok(StreamPriorityMode::Throughput),
+        _ => Err(format!(
+            "Invalid stream priority mode: {} (expected fifo, latency or throughput)",
+            input
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo_keeps_every_stream_on_one_ordered_level() {
+        let mode = StreamPriorityMode::Fifo;
+        for stream_id in [0, 4, 8, 64] {
+            assert_eq!(mode.initial_priority(stream_id, stream_id == 0), 3);
+        }
+        assert_eq!(mode.promoted_priority(), None);
+    }
+
+    #[test]
+    fn latency_puts_only_the_oldest_stream_first() {
+        let mode = StreamPriorityMode::Latency;
+        assert_eq!(mode.initial_priority(0, true), 0);
+        assert_eq!(mode.initial_priority(4, false), 2);
+        assert_eq!(mode.initial_priority(8, false), 2);
+        assert_eq!(mode.initial_priority(12, true), 0);
+        assert_eq!(mode.promoted_priority(), Some(0));
+    }
+
+    #[test]
+    fn throughput_cycles_through_eight_levels() {
+        let mode = StreamPriorityMode::Throughput;
+        let priorities: Vec<u8> = (0..10)
+            .map(|index| mode.initial_priority(index * 4, index == 0))
+            .collect();
+        assert_eq!(priorities, [0, 1, 2, 3, 4, 5, 6, 7, 0, 1]);
+        assert_eq!(mode.promoted_priority(), None);
+    }
+
+    #[test]
+    fn parses_mode_names() {
+        assert_eq!(
+            parse_stream_priority_mode(" Latency "),
+            Ok(StreamPriorityMode::Latency)
+        );
+        assert_eq!(
+            parse_stream_priority_mode("throughput"),
+            Ok(StreamPriorityMode::Throughput)
+        );
+        assert!(parse_stream_priority_mode("lifo").is_err());
+    }
+}
@@ -53,3 +53,31 @@ impl<F: FnOnce()> Drop for ResetOnDrop<F> {
         }
     }
 }
+
+/// Deterministic byte source (SplitMix64) for fixtures that must be identical across runs.
+///
+/// Not cryptographically secure; never use it outside tests and test harnesses.
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Event-loop watchdog for the single-threaded Tokio runtimes of the client and server.
+//!
+//! An OS thread pings the runtime over a `watch` channel every interval and a task on the
+//! runtime echoes each ping back. A ping left unanswered for two intervals means something is
+//! blocking the loop (typically a QUIC callback that never returns), which a task on the same
+//! runtime could never notice itself.
+
+use crate::invariants;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Starts the watchdog thread and returns the responder that must run on the watched runtime.
+///
+/// A stall is logged once, with the `assert_invariant!` failures seen so far, when it crosses
+/// two intervals and again when the loop recovers.
+/// With `abort` set the process aborts instead, so a supervisor can restart it. The thread
+/// exits once the responder is dropped.
+pub fn start(interval: Duration, abort: bool) -> std::io::Result<impl Future<Output = ()>> {
+    let interval = interval.max(Duration::from_millis(1));
+    let (ping_tx, mut ping_rx) = watch::channel(0u64);
+    let acked = Arc::new(AtomicU64::new(0));
+    let thread_acked = Arc::clone(&acked);
+    thread::Builder::new()
+        .name("slipstream-watchdog".to_string())
+        .spawn(move || {
+            let mut detector = StallDetector::new(interval, Instant::now());
+            loop {
+                thread::sleep(interval);
+                match detector.check(thread_acked.load(Ordering::Acquire), Instant::now()) {
+                    Some(WatchdogEvent::Stalled(stalled_for)) => {
+                        tracing::error!(
+                            stall_ms = stalled_for.as_millis() as u64,
+                            invariant_violations = invariants::violation_count(),
+                            abort,
+                            "Event loop stalled: watchdog ping unanswered for {} ms",
+                            stalled_for.as_millis()
+                        );
+                        if abort {
+                            std::process::abort();
+                        }
+                    }
+                    Some(WatchdogEvent::Recovered(stalled_for)) => {
+                        tracing::warn!(
+                            stall_ms = stalled_for.as_millis() as u64,
+                            "Event loop recovered after a {} ms stall",
+                            stalled_for.as_millis()
+                        );
+                    }
+                    None => {}
+                }
+                if ping_tx.is_closed() {
+                    return;
+                }
+                let ping = detector.ping;
+                ping_tx.send_if_modified(|current| std::mem::replace(current, ping) != ping);
+            }
+        })?;
+    Ok(async move {
+        while ping_rx.changed().await.is_ok() {
+            let ping = *ping_rx.borrow_and_update();
+            acked.store(ping, Ordering::Release);
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchdogEvent {
+    Stalled(Duration),
+    Recovered(Duration),
+}
+
+/// Tracks the outstanding ping; `ping` is the value the thread should publish next.
+#[derive(Debug)]
+struct StallDetector {
+    interval: Duration,
+    ping: u64,
+    sent_at: Instant,
+    stalled: bool,
+}
+
+impl StallDetector {
+    fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            ping: 0,
+            sent_at: now,
+            stalled: false,
+        }
+    }
+
+    /// Issues the next ping once `acked` catches up, or reports a stall that has outlived two
+    /// intervals (only once per stall).
+    fn check(&mut self, acked: u64, now: Instant) -> Option<WatchdogEvent> {
+        let waited = now.saturating_duration_since(self.sent_at);
+        if acked == self.ping {
+            let recovered = std::mem::replace(&mut self.stalled, false);
+            self.ping += 1;
+            self.sent_at = now;
+            return recovered.then_some(WatchdogEvent::Recovered(waited));
+        }
+        if !self.stalled && waited >= self.interval * 2 {
+            self.stalled = true;
+            return Some(WatchdogEvent::Stalled(waited));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_stall_once_and_then_the_recovery() {
+        let interval = Duration::from_millis(100);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut detector = StallDetector::new(interval, start);
+
+        assert_eq!(detector.check(0, at(100)), None);
+        assert_eq!(detector.ping, 1);
+        assert_eq!(detector.check(1, at(200)), None);
+        assert_eq!(detector.ping, 2);
+
+        assert_eq!(detector.check(1, at(300)), None);
+        assert_eq!(
+            detector.check(1, at(400)),
+            Some(WatchdogEvent::Stalled(Duration::from_millis(200)))
+        );
+        assert_eq!(detector.check(1, at(500)), None);
+        assert_eq!(detector.ping, 2);
+
+        assert_eq!(
+            detector.check(2, at(900)),
+            Some(WatchdogEvent::Recovered(Duration::from_millis(700)))
+        );
+        assert_eq!(detector.ping, 3);
+        assert_eq!(detector.check(3, at(1000)), None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Criterion benchmarks for the per-packet DNS codec path.
+//!
+//! Run with `cargo bench -p slipstream-dns --bench codec` and compare against
+//! `benches/baseline.txt`. One query name carries at most 145 payload bytes under the domain
+//! used here, so the 512- and 1400-byte query cases measure that much payload split across as
+//! many queries as it takes.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use slipstream_dns::{
+    build_qname, decode_query, decode_response, encode_query, encode_response,
+    max_payload_len_for_domain, EdnsOptions, Encoding, QnameConfig, QueryParams, Question,
+    ResponseParams, CLASS_IN, RR_TXT,
+};
+
+const DOMAIN: &str = "test.example.com";
+const QUERY_SIZES: [usize; 3] = [128, 512, 1400];
+/// TXT character-strings carry at most 255 bytes each.
+const TXT_CHUNK_LEN: usize = 255;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + 7) as u8).collect()
+}
+
+/// Query names carrying `len` payload bytes, split at the per-query capacity.
+fn qnames(len: usize) -> Vec<String> {
+    let capacity =
+        max_payload_len_for_domain(DOMAIN, Encoding::Base32, QnameConfig::default()).unwrap();
+    payload(len)
+        .chunks(capacity)
+        .map(|chunk| build_qname(chunk, DOMAIN, Encoding::Base32, QnameConfig::default()).unwrap())
+        .collect()
+}
+
+fn query_params(qname: &str) -> QueryParams<'_> {
+    QueryParams {
+        id: 0x1234,
+        qname,
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+        edns: EdnsOptions::default(),
+    }
+}
+
+fn bench_encode_query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_query");
+    for len in QUERY_SIZES {
+        let names = qnames(len);
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &names, |b, names| {
+            b.iter(|| {
+                for name in names {
+                    black_box(encode_query(black_box(&query_params(name))).unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode_query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_query");
+    for len in QUERY_SIZES {
+        let packets: Vec<Vec<u8>> = qnames(len)
+            .iter()
+            .map(|name| encode_query(&query_params(name)).unwrap())
+            .collect();
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &packets, |b, packets| {
+            b.iter(|| {
+                for packet in packets {
+                    black_box(decode_query(black_box(packet), DOMAIN, Encoding::Base32).unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_build_qname(c: &mut Criterion) {
+    let capacity =
+        max_payload_len_for_domain(DOMAIN, Encoding::Base32, QnameConfig::default()).unwrap();
+    let mut group = c.benchmark_group("build_qname");
+    for len in [16, 64, capacity] {
+        let data = payload(len);
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &data, |b, data| {
+            b.iter(|| {
+                black_box(
+                    build_qname(
+                        black_box(data),
+                        DOMAIN,
+                        Encoding::Base32,
+                        QnameConfig::default(),
+                    )
+                    .unwrap(),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode_response(c: &mut Criterion) {
+    let question = Question {
+        name: qnames(1).remove(0),
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+    };
+    let mut group = c.benchmark_group("decode_response_txt");
+    for chunks in [1, 4, 16] {
+        let data = payload(chunks * TXT_CHUNK_LEN);
+        let response = encode_response(&ResponseParams {
+            id: 0x1234,
+            rd: true,
+            cd: false,
+            ad: false,
+            question: &question,
+            payload: Some(&data),
+            rcode: None,
+            encoding: Encoding::Base32,
+            ttl: 0,
+            compress_names: true,
+            edns: EdnsOptions {
+                udp_payload: u16::MAX,
+                ..EdnsOptions::default()
+            },
+        })
+        .unwrap();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("chunks", chunks),
+            &response,
+            |b, response| b.iter(|| black_box(decode_response(black_box(response)).unwrap())),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_encode_query,
+    bench_decode_query,
+    bench_build_qname,
+    bench_decode_response
+);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use slipstream_dns::{decode_query_with_domains, DecodeQueryError, Encoding};
+
+const DOMAINS: &[&str] = &["test.com", "tunnel.example.org"];
+
+fuzz_target!(|data: &[u8]| {
+    for encoding in [Encoding::Base32, Encoding::Base64Url] {
+        // Exhaustive on purpose: a new error variant must be looked at here.
+        match decode_query_with_domains(data, DOMAINS, encoding) {
+            Ok(_) | Err(DecodeQueryError::Drop(_)) | Err(DecodeQueryError::Reply { .. }) => {}
+        }
+    }
+
+    // The first two bytes pick where name parsing starts; the rest is the packet.
+    if data.len() >= 2 {
+        let start = u16::from_be_bytes([data[0], data[1]]) as usize;
+        let packet = &data[2..];
+        if let Some((name, end)) = slipstream_dns::fuzzing::parse_name(packet, start) {
+            assert!(end <= packet.len());
+            assert!(name.len() <= 255, "{} byte name", name.len());
+        }
+    }
+});
@@ -0,0 +1,71 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use slipstream_dns::{
+    build_qname, decode_query, decode_response, encode_query, encode_response,
+    max_response_payload_len, EdnsOptions, Encoding, QnameConfig, QueryParams, Question,
+    ResponseParams, CLASS_IN, RR_A, RR_AAAA, RR_CNAME, RR_NULL, RR_TXT,
+};
+
+const DOMAIN: &str = "test.com";
+const QTYPES: [u16; 5] = [RR_TXT, RR_A, RR_AAAA, RR_CNAME, RR_NULL];
+
+// The first byte picks the encoding, record type and name compression; the rest is the payload.
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, payload)) = data.split_first() else {
+        return;
+    };
+    let encoding = if selector & 1 == 0 {
+        Encoding::Base32
+    } else {
+        Encoding::Base64Url
+    };
+    let qtype = QTYPES[(selector >> 1) as usize % QTYPES.len()];
+
+    // Client to server: the payload rides in the query name.
+    if !payload.is_empty() {
+        if let Ok(qname) = build_qname(payload, DOMAIN, encoding, QnameConfig::default()) {
+            let query = encode_query(&QueryParams {
+                id: 1,
+                qname: &qname,
+                qtype,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("a built qname always encodes");
+            let decoded = decode_query(&query, DOMAIN, encoding).expect("own query decodes");
+            assert_eq!(decoded.payload, payload);
+        }
+    }
+
+    // Server to client: the payload rides in the answer records.
+    let fits = max_response_payload_len(qtype).is_none_or(|max| payload.len() <= max);
+    if payload.is_empty() || !fits {
+        return;
+    }
+    let question = Question {
+        name: format!("a.{}.", DOMAIN),
+        qtype,
+        qclass: CLASS_IN,
+    };
+    let Ok(response) = encode_response(&ResponseParams {
+        id: 1,
+        rd: true,
+        cd: false,
+        ad: false,
+        question: &question,
+        payload: Some(payload),
+        rcode: None,
+        encoding,
+        ttl: 0,
+        compress_names: selector & 0x80 != 0,
+        edns: EdnsOptions::default(),
+    }) else {
+        return;
+    };
+    assert_eq!(decode_response(&response).as_deref(), Some(payload));
+});
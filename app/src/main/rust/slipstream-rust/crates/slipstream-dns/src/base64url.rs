@@ -0,0 +1,117 @@
+use std::fmt;
+
+const ENCODE_TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base64UrlError {
+    InvalidLength,
+    InvalidChar,
+    InvalidPadding,
+}
+
+impl fmt::Display for Base64UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Base64UrlError::InvalidLength => "invalid base64url length",
+            Base64UrlError::InvalidChar => "invalid base64url character",
+            Base64UrlError::InvalidPadding => "invalid base64url padding",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for Base64UrlError {}
+
+/// Encodes with the RFC4648 URL-safe alphabet and no padding, since `=` is not label-safe.
+pub fn encode(input: &[u8]) -> String {
+    let mut out = String::with_capacity(encoded_len(input.len()));
+    for chunk in input.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = chunk.get(1).copied().unwrap_or(0) as u32;
+        let b2 = chunk.get(2).copied().unwrap_or(0) as u32;
+        let group = (b0 << 16) | (b1 << 8) | b2;
+        let chars = chunk.len() + 1;
+        for i in 0..chars {
+            let index = ((group >> (18 - 6 * i)) & 0x3f) as usize;
+            out.push(ENCODE_TABLE[index] as char);
+        }
+    }
+    out
+}
+
+/// Decodes unpadded base64url, skipping inline dots. Case is significant, so any
+/// character outside the URL-safe alphabet (including `+`, `/`, and `=`) is rejected.
+pub fn decode(input: &str) -> Result<Vec<u8>, Base64UrlError> {
+    let mut values = Vec::with_capacity(input.len());
+    for b in input.bytes() {
+        match b {
+            b'.' => continue,
+            b'=' => return Err(Base64UrlError::InvalidPadding),
+            _ => values.push(decode_value(b)?),
+        }
+    }
+    if values.len() % 4 == 1 {
+        return Err(Base64UrlError::InvalidLength);
+    }
+
+    let mut out = Vec::with_capacity(values.len() * 3 / 4);
+    for chunk in values.chunks(4) {
+        let mut group = 0u32;
+        for (i, value) in chunk.iter().enumerate() {
+            group |= (*value as u32) << (18 - 6 * i);
+        }
+        let bytes = chunk.len() - 1;
+        // Trailing bits of a partial group must be zero so each payload has one encoding.
+        let unused_bits = 6 * chunk.len() - 8 * bytes;
+        let unused_mask = ((1u32 << unused_bits) - 1) << (24 - 6 * chunk.len());
+        if group & unused_mask != 0 {
+            return Err(Base64UrlError::InvalidPadding);
+        }
+        for i in 0..bytes {
+            out.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(out)
+}
+
+pub(crate) fn encoded_len(payload_len: usize) -> usize {
+    (payload_len * 8).div_ceil(6)
+}
+
+fn decode_value(b: u8) -> Result<u8, Base64UrlError> {
+    match b {
+        b'A'..=b'Z' => Ok(b - b'A'),
+        b'a'..=b'z' => Ok(b - b'a' + 26),
+        b'0'..=b'9' => Ok(b - b'0' + 52),
+        b'-' => Ok(62),
+        b'_' => Ok(63),
+        _ => Err(Base64UrlError::InvalidChar),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Base64UrlError};
+
+    #[test]
+    fn round_trips_all_tail_lengths() {
+        let payload: Vec<u8> = (0..=255).collect();
+        for len in 0..16 {
+            let encoded = encode(&payload[..len]);
+            assert!(!encoded.contains('='));
+            assert_eq!(decode(&encoded).expect("decode"), &payload[..len]);
+        }
+        assert_eq!(encode(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn rejects_foreign_alphabet() {
+        assert_eq!(decode("ab+c"), Err(Base64UrlError::InvalidChar));
+        assert_eq!(decode("ab/c"), Err(Base64UrlError::InvalidChar));
+        assert_eq!(decode("aGk="), Err(Base64UrlError::InvalidPadding));
+        assert_eq!(decode("abcde"), Err(Base64UrlError::InvalidLength));
+        // "aGl" and "aGk" differ only in unused trailing bits.
+        assert_eq!(decode("aGk").expect("decode"), b"hi");
+        assert_eq!(decode("aGl"), Err(Base64UrlError::InvalidPadding));
+    }
+}
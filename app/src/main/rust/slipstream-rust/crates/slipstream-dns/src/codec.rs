@@ -1,38 +1,80 @@
-use crate::base32;
 use crate::dots;
-
-use crate::name::{encode_name, extract_subdomain_multi, parse_name};
+use crate::encoding;
+use crate::Encoding;
+use std::borrow::Cow;
+
+use crate::hmac::split_auth_label;
+use crate::name::{encode_name, extract_subdomain_multi, parse_name, CompressionTable};
+use crate::rdata::{decode_answers, encode_answers, is_tunnel_qtype};
+use crate::sequence::split_sequence_label;
 use crate::types::{
-    DecodeQueryError, DecodedQuery, DnsError, QueryParams, Rcode, ResponseParams, EDNS_UDP_PAYLOAD,
-    RR_OPT, RR_TXT,
+    DecodeQueryError, DecodedQuery, DnsError, EdnsOptions, QueryParams, Rcode, ResponseParams,
+    MIN_EDNS_UDP_PAYLOAD, RR_OPT,
 };
 use crate::wire::{
     parse_header, parse_question, parse_question_for_reply, read_u16, read_u32, write_u16,
     write_u32,
 };
 
-pub fn decode_query(packet: &[u8], domain: &str) -> Result<DecodedQuery, DecodeQueryError> {
-    decode_query_with_domains(packet, &[domain])
+const EDNS_FLAG_DO: u32 = 0x0000_8000;
+const EDNS_OPTION_ECS: u16 = 8;
+const EDNS_OPTION_PADDING: u16 = 12;
+/// Root name, type, class, TTL and RDLENGTH of an OPT record.
+const OPT_RECORD_FIXED_LEN: usize = 11;
+/// OPTION-CODE and OPTION-LENGTH ahead of every option's data.
+const EDNS_OPTION_HEADER_LEN: usize = 4;
+
+pub fn decode_query(
+    packet: &[u8],
+    domain: &str,
+    encoding: Encoding,
+) -> Result<DecodedQuery, DecodeQueryError> {
+    decode_query_with_domains(packet, &[domain], encoding)
 }
 
+/// Decodes a tunnel query whose subdomain must use `encoding`; a subdomain in the other
+/// alphabet is answered with SERVER_FAILURE.
 pub fn decode_query_with_domains(
     packet: &[u8],
     domains: &[&str],
+    encoding: Encoding,
+) -> Result<DecodedQuery, DecodeQueryError> {
+    decode_query_labels(packet, domains, encoding, false)
+}
+
+/// Like [`decode_query_with_domains`], for a server holding an auth secret: a trailing auth
+/// label is split off the payload into [`DecodedQuery::auth`].
+pub fn decode_authenticated_query(
+    packet: &[u8],
+    domains: &[&str],
+    encoding: Encoding,
+) -> Result<DecodedQuery, DecodeQueryError> {
+    decode_query_labels(packet, domains, encoding, true)
+}
+
+fn decode_query_labels(
+    packet: &[u8],
+    domains: &[&str],
+    encoding: Encoding,
+    authenticated: bool,
 ) -> Result<DecodedQuery, DecodeQueryError> {
     let header = match parse_header(packet) {
         Some(header) => header,
-        None => return Err(DecodeQueryError::Drop),
+        None => return Err(DecodeQueryError::Drop(None)),
     };
 
     let rd = header.rd;
     let cd = header.cd;
+    let ad = header.ad;
 
-    if header.is_response {
+    // Only standard queries carry tunnel payloads; IQUERY, STATUS, NOTIFY and UPDATE do not.
+    if header.is_response || header.opcode != 0 {
         let question = parse_question_for_reply(packet, header.qdcount, header.offset)?;
         return Err(DecodeQueryError::Reply {
             id: header.id,
             rd,
             cd,
+            ad,
             question,
             rcode: Rcode::FormatError,
         });
@@ -44,6 +86,7 @@ pub fn decode_query_with_domains(
             id: header.id,
             rd,
             cd,
+            ad,
             question,
             rcode: Rcode::FormatError,
         });
@@ -51,26 +94,28 @@ pub fn decode_query_with_domains(
 
     let question = match parse_question(packet, header.offset) {
         Ok((question, _)) => question,
-        Err(_) => return Err(DecodeQueryError::Drop),
+        Err(err) => return Err(DecodeQueryError::Drop(Some(err))),
     };
 
-    if question.qtype != RR_TXT {
+    if !is_tunnel_qtype(question.qtype) {
         return Err(DecodeQueryError::Reply {
             id: header.id,
             rd,
             cd,
+            ad,
             question: Some(question),
             rcode: Rcode::NameError,
         });
     }
 
-    let subdomain_raw = match extract_subdomain_multi(&question.name, domains) {
-        Ok(subdomain_raw) => subdomain_raw,
+    let (subdomain_raw, domain_index) = match extract_subdomain_multi(&question.name, domains) {
+        Ok(matched) => matched,
         Err(rcode) => {
             return Err(DecodeQueryError::Reply {
                 id: header.id,
                 rd,
                 cd,
+                ad,
                 question: Some(question),
                 rcode,
             })
@@ -83,18 +128,41 @@ pub fn decode_query_with_domains(
             id: header.id,
             rd,
             cd,
+            ad,
             question: Some(question),
             rcode: Rcode::NameError,
         });
     }
 
-    let payload = match base32::decode(&undotted) {
-        Ok(payload) => payload,
-        Err(_) => {
+    // The auth label is outermost; the labels before it decode like an unauthenticated
+    // subdomain, and a payload whose last label only looks like one decodes whole.
+    let authed = authenticated
+        .then(|| split_auth_label(&subdomain_raw))
+        .flatten()
+        .filter(|(payload_labels, _)| !payload_labels.is_empty())
+        .and_then(|(payload_labels, tag)| {
+            decode_payload_labels(payload_labels, encoding).map(|payload| (payload, tag))
+        });
+    let decoded = match authed {
+        Some((payload, tag)) => Some((encoding, payload, Some(tag))),
+        None => decode_payload_labels(&subdomain_raw, encoding)
+            .map(|payload| (encoding, payload, None))
+            .or_else(|| {
+                encoding::detect_and_decode(&undotted)
+                    .map(|(detected, payload)| (detected, payload, None))
+            }),
+    };
+
+    // A marker inside the subdomain selects the alphabet; labels mixing alphabets and
+    // queries from a client configured for the other encoding fail here.
+    let (payload, auth) = match decoded {
+        Some((detected, payload, auth)) if detected == encoding => (payload, auth),
+        _ => {
             return Err(DecodeQueryError::Reply {
                 id: header.id,
                 rd,
                 cd,
+                ad,
                 question: Some(question),
                 rcode: Rcode::ServerFailure,
             })
@@ -105,11 +173,29 @@ pub fn decode_query_with_domains(
         id: header.id,
         rd,
         cd,
+        ad,
         question,
         payload,
+        domain_index,
+        auth,
     })
 }
 
+/// Decodes dotted payload labels in `encoding`, dropping a trailing sequence label first; a
+/// base64url payload whose last label only looks like one still decodes whole.
+fn decode_payload_labels(labels: &str, encoding: Encoding) -> Option<Vec<u8>> {
+    let sequenced = split_sequence_label(labels)
+        .map(|(payload_labels, _)| dots::undotify(payload_labels))
+        .filter(|payload_labels| !payload_labels.is_empty());
+    sequenced
+        .as_deref()
+        .and_then(encoding::detect_and_decode)
+        .filter(|(detected, _)| *detected == encoding)
+        .or_else(|| encoding::detect_and_decode(&dots::undotify(labels)))
+        .filter(|(detected, _)| *detected == encoding)
+        .map(|(_, payload)| payload)
+}
+
 pub fn encode_query(params: &QueryParams<'_>) -> Result<Vec<u8>, DnsError> {
     let mut out = Vec::with_capacity(256);
     let mut flags = 0u16;
@@ -131,12 +217,12 @@ pub fn encode_query(params: &QueryParams<'_>) -> Result<Vec<u8>, DnsError> {
     write_u16(&mut out, 1);
 
     if params.qdcount > 0 {
-        encode_name(params.qname, &mut out)?;
+        encode_name(params.qname, &mut out, None)?;
         write_u16(&mut out, params.qtype);
         write_u16(&mut out, params.qclass);
     }
 
-    encode_opt_record(&mut out)?;
+    encode_opt_record(&mut out, params.edns)?;
 
     Ok(out)
 }
@@ -150,18 +236,25 @@ pub fn encode_response(params: &ResponseParams<'_>) -> Result<Vec<u8>, DnsError>
         Rcode::NameError
     });
 
-    let mut ancount = 0u16;
+    let mut answers = Vec::new();
     if payload_len > 0 && rcode == Rcode::Ok {
-        ancount = 1;
+        if let Some(payload) = params.payload {
+            answers = encode_answers(params.question.qtype, payload, params.encoding)?;
+        }
     } else if params.rcode.is_some() {
         rcode = params.rcode.unwrap_or(Rcode::Ok);
     }
+    let ancount = answers.len() as u16;
 
     let mut out = Vec::with_capacity(256);
+    // QR and AA.
     let mut flags = 0x8000 | 0x0400;
     if params.rd {
         flags |= 0x0100;
     }
+    if params.ad {
+        flags |= 0x0020;
+    }
     if params.cd {
         flags |= 0x0010;
     }
@@ -174,35 +267,22 @@ pub fn encode_response(params: &ResponseParams<'_>) -> Result<Vec<u8>, DnsError>
     write_u16(&mut out, 0);
     write_u16(&mut out, 1);
 
-    encode_name(&params.question.name, &mut out)?;
+    let mut names = CompressionTable::default();
+    let mut table = params.compress_names.then_some(&mut names);
+    encode_name(&params.question.name, &mut out, table.as_deref_mut())?;
     write_u16(&mut out, params.question.qtype);
     write_u16(&mut out, params.question.qclass);
 
-    if ancount == 1 {
-        out.extend_from_slice(&[0xC0, 0x0C]);
+    for rdata in &answers {
+        encode_name(&params.question.name, &mut out, table.as_deref_mut())?;
         write_u16(&mut out, params.question.qtype);
         write_u16(&mut out, params.question.qclass);
-        write_u32(&mut out, 60);
-        let chunk_count = payload_len.div_ceil(255);
-        let rdata_len = payload_len + chunk_count;
-        if rdata_len > u16::MAX as usize {
-            return Err(DnsError::new("payload too long"));
-        }
-        write_u16(&mut out, rdata_len as u16);
-        if let Some(payload) = params.payload {
-            let mut remaining = payload_len;
-            let mut cursor = 0;
-            while remaining > 0 {
-                let chunk_len = remaining.min(255);
-                out.push(chunk_len as u8);
-                out.extend_from_slice(&payload[cursor..cursor + chunk_len]);
-                cursor += chunk_len;
-                remaining -= chunk_len;
-            }
-        }
+        write_u32(&mut out, params.ttl);
+        write_u16(&mut out, rdata.len() as u16);
+        out.extend_from_slice(rdata);
     }
 
-    encode_opt_record(&mut out)?;
+    encode_opt_record(&mut out, params.edns)?;
 
     Ok(out)
 }
@@ -216,7 +296,7 @@ pub fn decode_response(packet: &[u8]) -> Option<Vec<u8>> {
     if rcode != Rcode::Ok {
         return None;
     }
-    if header.ancount != 1 {
+    if header.ancount == 0 {
         return None;
     }
 
@@ -230,65 +310,201 @@ pub fn decode_response(packet: &[u8]) -> Option<Vec<u8>> {
         offset += 4;
     }
 
-    let (_, new_offset) = parse_name(packet, offset).ok()?;
-    offset = new_offset;
-    if offset + 10 > packet.len() {
-        return None;
+    let mut answer_qtype = None;
+    let mut answers = Vec::with_capacity(header.ancount as usize);
+    for _ in 0..header.ancount {
+        let (_, new_offset) = parse_name(packet, offset).ok()?;
+        offset = new_offset;
+        if offset + 10 > packet.len() {
+            return None;
+        }
+        let qtype = read_u16(packet, offset)?;
+        offset += 2;
+        let _qclass = read_u16(packet, offset)?;
+        offset += 2;
+        let _ttl = read_u32(packet, offset)?;
+        offset += 4;
+        let rdlen = read_u16(packet, offset)? as usize;
+        offset += 2;
+        if offset + rdlen > packet.len() || rdlen < 1 {
+            return None;
+        }
+        // Every answer must share the first answer's type; mixed RRsets are not ours.
+        if *answer_qtype.get_or_insert(qtype) != qtype || !is_tunnel_qtype(qtype) {
+            return None;
+        }
+        answers.push((offset, rdlen));
+        offset += rdlen;
     }
-    let qtype = read_u16(packet, offset)?;
-    offset += 2;
-    let _qclass = read_u16(packet, offset)?;
-    offset += 2;
-    let _ttl = read_u32(packet, offset)?;
-    offset += 4;
-    let rdlen = read_u16(packet, offset)? as usize;
-    offset += 2;
-    if offset + rdlen > packet.len() || rdlen < 1 {
+
+    decode_answers(packet, answer_qtype?, &answers)
+}
+
+pub fn is_response(packet: &[u8]) -> bool {
+    parse_header(packet)
+        .map(|header| header.is_response)
+        .unwrap_or(false)
+}
+
+/// Returns the ID and RCODE of a DNS response; `None` for queries and truncated headers.
+/// Codes outside [`Rcode`] come back as `Some((id, None))`.
+pub fn response_id_and_rcode(packet: &[u8]) -> Option<(u16, Option<Rcode>)> {
+    let header = parse_header(packet)?;
+    if !header.is_response {
         return None;
     }
-    if qtype != RR_TXT {
+    Some((header.id, header.rcode))
+}
+
+/// Returns the first question name of a DNS response exactly as sent, so callers can check
+/// the case of an echoed query name.
+pub fn response_question_name(packet: &[u8]) -> Option<String> {
+    let header = parse_header(packet)?;
+    if !header.is_response || header.qdcount == 0 {
         return None;
     }
+    parse_name(packet, header.offset).ok().map(|(name, _)| name)
+}
 
-    let mut remaining = rdlen;
-    let mut cursor = offset;
-    let mut out = Vec::with_capacity(rdlen);
-    while remaining > 0 {
-        let txt_len = packet[cursor] as usize;
-        cursor += 1;
-        remaining -= 1;
-        if txt_len > remaining {
-            return None;
+/// Removes EDNS Client Subnet options (RFC 7871) from the OPT record of a query.
+///
+/// Recursive resolvers may attach the client's subnet before forwarding; stripping it keeps
+/// that address out of everything downstream. Packets without ECS, and packets too malformed
+/// to walk, are returned unchanged.
+pub fn strip_ecs(packet: &[u8]) -> Cow<'_, [u8]> {
+    let Some(header) = parse_header(packet) else {
+        return Cow::Borrowed(packet);
+    };
+    let mut offset = header.offset;
+    for _ in 0..header.qdcount {
+        match parse_question(packet, offset) {
+            Ok((_, next)) => offset = next,
+            Err(_) => return Cow::Borrowed(packet),
+        }
+    }
+    for _ in 0..header.ancount as u32 + header.nscount as u32 {
+        match parse_record(packet, offset) {
+            Some(record) => offset = record.end,
+            None => return Cow::Borrowed(packet),
         }
-        out.extend_from_slice(&packet[cursor..cursor + txt_len]);
-        cursor += txt_len;
-        remaining -= txt_len;
     }
-    if out.is_empty() {
+
+    let mut stripped: Option<Vec<u8>> = None;
+    let mut copied_to = 0;
+    for _ in 0..header.arcount {
+        let Some(record) = parse_record(packet, offset) else {
+            return Cow::Borrowed(packet);
+        };
+        if record.rtype == RR_OPT {
+            if let Some(kept) = options_without_ecs(&packet[record.rdata_start..record.end]) {
+                let out = stripped.get_or_insert_with(|| Vec::with_capacity(packet.len()));
+                // Everything up to the RDLENGTH field, which shrinks with the options.
+                out.extend_from_slice(&packet[copied_to..record.rdata_start - 2]);
+                write_u16(out, kept.len() as u16);
+                out.extend_from_slice(&kept);
+                copied_to = record.end;
+            }
+        }
+        offset = record.end;
+    }
+    match stripped {
+        Some(mut out) => {
+            out.extend_from_slice(&packet[copied_to..]);
+            Cow::Owned(out)
+        }
+        None => Cow::Borrowed(packet),
+    }
+}
+
+struct RecordBounds {
+    rtype: u16,
+    rdata_start: usize,
+    end: usize,
+}
+
+fn parse_record(packet: &[u8], offset: usize) -> Option<RecordBounds> {
+    let (_, offset) = parse_name(packet, offset).ok()?;
+    let rtype = read_u16(packet, offset)?;
+    // TYPE, CLASS and TTL precede RDLENGTH.
+    let rdlength = read_u16(packet, offset + 8)? as usize;
+    let rdata_start = offset + 10;
+    let end = rdata_start + rdlength;
+    if end > packet.len() {
         return None;
     }
-    Some(out)
+    Some(RecordBounds {
+        rtype,
+        rdata_start,
+        end,
+    })
 }
 
-pub fn is_response(packet: &[u8]) -> bool {
-    parse_header(packet)
-        .map(|header| header.is_response)
-        .unwrap_or(false)
+/// Returns the OPT RDATA minus its ECS options, or `None` when it holds none or is malformed.
+fn options_without_ecs(options: &[u8]) -> Option<Vec<u8>> {
+    let mut kept = Vec::with_capacity(options.len());
+    let mut found = false;
+    let mut offset = 0;
+    while offset < options.len() {
+        let code = read_u16(options, offset)?;
+        let len = read_u16(options, offset + 2)? as usize;
+        let end = offset + 4 + len;
+        if end > options.len() {
+            return None;
+        }
+        if code == EDNS_OPTION_ECS {
+            found = true;
+        } else {
+            kept.extend_from_slice(&options[offset..end]);
+        }
+        offset = end;
+    }
+    found.then_some(kept)
 }
 
-fn encode_opt_record(out: &mut Vec<u8>) -> Result<(), DnsError> {
+fn encode_opt_record(out: &mut Vec<u8>, edns: EdnsOptions) -> Result<(), DnsError> {
+    if edns.udp_payload < MIN_EDNS_UDP_PAYLOAD {
+        return Err(DnsError::new("EDNS UDP payload size below 512"));
+    }
+    let mut options = Vec::new();
+    if edns.ecs_optout {
+        // FAMILY 1 (IPv4) with SOURCE and SCOPE PREFIX-LENGTH 0, hence no address bytes.
+        write_u16(&mut options, EDNS_OPTION_ECS);
+        write_u16(&mut options, 4);
+        write_u16(&mut options, 1);
+        options.extend_from_slice(&[0, 0]);
+    }
+    if edns.padding_block > 0 {
+        // Padding goes last so it covers every byte before it (RFC 7830 section 3).
+        let block = edns.padding_block as usize;
+        let unpadded = out.len() + OPT_RECORD_FIXED_LEN + options.len() + EDNS_OPTION_HEADER_LEN;
+        let padding = (block - unpadded % block) % block;
+        write_u16(&mut options, EDNS_OPTION_PADDING);
+        write_u16(&mut options, padding as u16);
+        options.resize(options.len() + padding, 0);
+    }
+    // The OPT TTL carries the extended RCODE, the version, then the flags with DO on top.
+    let ttl = if edns.dnssec_ok { EDNS_FLAG_DO } else { 0 };
     out.push(0);
     write_u16(out, RR_OPT);
-    write_u16(out, EDNS_UDP_PAYLOAD);
-    write_u32(out, 0);
-    write_u16(out, 0);
+    write_u16(out, edns.udp_payload);
+    write_u32(out, ttl);
+    write_u16(out, options.len() as u16);
+    out.extend_from_slice(&options);
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::encode_response;
-    use crate::types::{Question, ResponseParams, CLASS_IN, RR_TXT};
+    use super::{
+        decode_authenticated_query, decode_query, decode_query_with_domains, decode_response,
+        encode_query, encode_response, response_question_name, strip_ecs,
+    };
+    use crate::types::{
+        DecodeQueryError, EdnsOptions, QnameConfig, QueryParams, Question, Rcode, ResponseParams,
+        CLASS_IN, RR_TXT,
+    };
+    use crate::Encoding;
+    use std::borrow::Cow;
 
     #[test]
     fn encode_response_rejects_large_payload() {
@@ -302,10 +518,488 @@ mod tests {
             id: 0x1234,
             rd: false,
             cd: false,
+            ad: false,
             question: &question,
             payload: Some(&payload),
             rcode: None,
+            encoding: Encoding::Base32,
+            ttl: 0,
+            compress_names: true,
+            edns: EdnsOptions::default(),
         };
         assert!(encode_response(&params).is_err());
     }
+
+    #[test]
+    fn response_echoes_question_case_and_uses_ttl() {
+        let question = Question {
+            name: "nbswy3dp.TeSt.cOm.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        let response = encode_response(&ResponseParams {
+            id: 0x1234,
+            rd: true,
+            cd: false,
+            ad: false,
+            question: &question,
+            payload: Some(b"hi"),
+            rcode: None,
+            encoding: Encoding::Base32,
+            ttl: 30,
+            compress_names: true,
+            edns: EdnsOptions::default(),
+        })
+        .expect("encode response");
+        assert_eq!(
+            response_question_name(&response).as_deref(),
+            Some("nbswy3dp.TeSt.cOm.")
+        );
+        // Header, question, then the answer's name pointer, type and class.
+        let ttl_at = 12 + 19 + 4 + 6;
+        assert_eq!(&response[ttl_at..ttl_at + 4], &30u32.to_be_bytes());
+        // The client reads the payload whatever TTL the answer carries.
+        let mut rewritten = response.clone();
+        rewritten[ttl_at..ttl_at + 4].copy_from_slice(&(i32::MAX as u32).to_be_bytes());
+        for packet in [&response, &rewritten] {
+            assert_eq!(super::decode_response(packet), Some(b"hi".to_vec()));
+        }
+    }
+
+    #[test]
+    fn uncompressed_response_decodes_like_compressed() {
+        let question = Question {
+            name: "nbswy3dp.TeSt.cOm.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        let encode = |compress_names| {
+            encode_response(&ResponseParams {
+                id: 0x1234,
+                rd: true,
+                cd: false,
+                ad: false,
+                question: &question,
+                payload: Some(b"hi"),
+                rcode: None,
+                encoding: Encoding::Base32,
+                ttl: 0,
+                compress_names,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode response")
+        };
+        let compressed = encode(true);
+        let plain = encode(false);
+        // The answer owner is a two-byte pointer instead of the 19-byte question name.
+        assert_eq!(plain.len() - compressed.len(), 17);
+        for response in [&compressed, &plain] {
+            assert_eq!(decode_response(response).as_deref(), Some(&b"hi"[..]));
+            assert_eq!(
+                response_question_name(response).as_deref(),
+                Some("nbswy3dp.TeSt.cOm.")
+            );
+        }
+    }
+
+    #[test]
+    fn response_sets_cd_and_ad_as_asked() {
+        let question = Question {
+            name: "nbswy3dp.test.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        for (cd, ad) in [(false, false), (true, false), (false, true), (true, true)] {
+            let response = encode_response(&ResponseParams {
+                id: 1,
+                rd: true,
+                cd,
+                ad,
+                question: &question,
+                payload: Some(b"hi"),
+                rcode: None,
+                encoding: Encoding::Base32,
+                ttl: 0,
+                compress_names: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode response");
+            // Low flags byte: RA, Z, AD, CD, then the RCODE.
+            assert_eq!(response[3] & 0x20 != 0, ad);
+            assert_eq!(response[3] & 0x10 != 0, cd);
+        }
+    }
+
+    #[test]
+    fn writes_edns_options_and_tolerates_opt_flags() {
+        let question = Question {
+            name: "nbswy3dp.test.com.".to_string(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        let edns = EdnsOptions {
+            udp_payload: 4096,
+            dnssec_ok: true,
+            ..EdnsOptions::default()
+        };
+        let response = encode_response(&ResponseParams {
+            id: 1,
+            rd: true,
+            cd: false,
+            ad: false,
+            question: &question,
+            payload: Some(b"hi"),
+            rcode: None,
+            encoding: Encoding::Base32,
+            ttl: 0,
+            compress_names: true,
+            edns,
+        })
+        .expect("encode response");
+        // The OPT record closes the message: root name, type, class (payload size), TTL, RDLEN.
+        let opt = &response[response.len() - 11..];
+        assert_eq!(&opt[..3], &[0, 0, 41]);
+        assert_eq!(&opt[3..5], &4096u16.to_be_bytes());
+        assert_eq!(&opt[5..9], &[0, 0, 0x80, 0]);
+        assert_eq!(super::decode_response(&response), Some(b"hi".to_vec()));
+
+        let query = QueryParams {
+            id: 1,
+            qname: "nbswy3dp.test.com.",
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns: EdnsOptions {
+                udp_payload: 511,
+                ..EdnsOptions::default()
+            },
+        };
+        assert!(encode_query(&query).is_err());
+    }
+
+    #[test]
+    fn decode_query_reports_the_ad_bit() {
+        let qname = crate::build_qname(b"hi", "test.com", Encoding::Base32, QnameConfig::default())
+            .expect("build qname");
+        let mut query = encode_query(&QueryParams {
+            id: 1,
+            qname: &qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns: EdnsOptions::default(),
+        })
+        .expect("encode query");
+        let decoded = decode_query(&query, "test.com", Encoding::Base32).expect("decode query");
+        assert!(!decoded.ad);
+        query[3] |= 0x20;
+        let decoded = decode_query(&query, "test.com", Encoding::Base32).expect("decode query");
+        assert!(decoded.ad);
+        assert!(!decoded.cd);
+    }
+
+    #[test]
+    fn decode_query_drops_trailing_sequence_label() {
+        let payload = b"hello tunnel";
+        for encoding in [Encoding::Base32, Encoding::Base64Url] {
+            let domain = format!("{}.test.com", crate::sequence_label(7));
+            let qname = crate::build_qname(payload, &domain, encoding, QnameConfig::default())
+                .expect("build qname");
+            let query = encode_query(&QueryParams {
+                id: 1,
+                qname: &qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode query");
+            let decoded = decode_query(&query, "test.com", encoding).expect("decode query");
+            assert_eq!(decoded.payload, payload, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn decode_query_strips_auth_label_after_sequence_label() {
+        let payload = b"hello tunnel";
+        let nonce = crate::hmac::new_nonce(1_700_000_000).expect("nonce");
+        let tag = crate::hmac::AuthTag {
+            nonce,
+            mac: crate::hmac::compute(b"secret", &nonce, payload, crate::hmac::DEFAULT_MAC_LEN)
+                .expect("mac"),
+        };
+        for encoding in [Encoding::Base32, Encoding::Base64Url] {
+            let domain = format!(
+                "{}.{}.test.com",
+                crate::sequence_label(7),
+                crate::hmac::auth_label(&tag)
+            );
+            let qname = crate::build_qname(payload, &domain, encoding, QnameConfig::default())
+                .expect("build qname");
+            let query = encode_query(&QueryParams {
+                id: 1,
+                qname: &qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode query");
+            let decoded =
+                decode_authenticated_query(&query, &["test.com"], encoding).expect("decode query");
+            assert_eq!(decoded.payload, payload, "{:?}", encoding);
+            assert_eq!(decoded.auth.as_ref(), Some(&tag), "{:?}", encoding);
+
+            // Without a secret the label is just more payload.
+            let unauthenticated = decode_query(&query, "test.com", encoding);
+            assert!(
+                !matches!(&unauthenticated, Ok(decoded) if decoded.payload == payload),
+                "{:?}",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn decode_query_rejects_mixed_alphabet_labels() {
+        for qname in [
+            "0aGk+aGk.test.com.",
+            "0aGk=.test.com.",
+            "NBSWY3DP.0aGk.test.com.",
+            "NBSW-Y3DP.test.com.",
+        ] {
+            let query = encode_query(&QueryParams {
+                id: 1,
+                qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode query");
+            for encoding in [Encoding::Base32, Encoding::Base64Url] {
+                match decode_query(&query, "test.com", encoding) {
+                    Err(DecodeQueryError::Reply { rcode, .. }) => {
+                        assert_eq!(rcode, Rcode::ServerFailure, "{}", qname)
+                    }
+                    other => panic!("{}: expected SERVER_FAILURE, got {:?}", qname, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_query_rejects_mismatched_encoding() {
+        let payload = b"hello tunnel";
+        for (sent, expected) in [
+            (Encoding::Base32, Encoding::Base64Url),
+            (Encoding::Base64Url, Encoding::Base32),
+        ] {
+            let qname = crate::build_qname(payload, "test.com", sent, QnameConfig::default())
+                .expect("build qname");
+            let query = encode_query(&QueryParams {
+                id: 1,
+                qname: &qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode query");
+            match decode_query(&query, "test.com", expected) {
+                Err(DecodeQueryError::Reply { rcode, .. }) => {
+                    assert_eq!(rcode, Rcode::ServerFailure)
+                }
+                other => panic!("expected SERVER_FAILURE, got {:?}", other),
+            }
+            let decoded = decode_query(&query, "test.com", sent).expect("decode query");
+            assert_eq!(decoded.payload, payload);
+        }
+    }
+
+    #[test]
+    fn strip_ecs_keeps_other_edns_options() {
+        let payload = b"hello tunnel";
+        let qname = crate::build_qname(
+            payload,
+            "test.com",
+            Encoding::Base32,
+            QnameConfig::default(),
+        )
+        .expect("build qname");
+        let query = encode_query(&QueryParams {
+            id: 7,
+            qname: &qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns: EdnsOptions::default(),
+        })
+        .expect("encode query");
+        assert!(matches!(strip_ecs(&query), Cow::Borrowed(_)));
+
+        let cookie = [0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8];
+        // ECS for 192.0.2.0/24: family 1, source prefix 24, scope 0, three address bytes.
+        let ecs = [0, 8, 0, 7, 0, 1, 24, 0, 192, 0, 2];
+        let padding = [0, 12, 0, 2, 0, 0];
+        let with_options = |options: &[&[u8]]| {
+            let rdata: Vec<u8> = options.concat();
+            let mut packet = query[..query.len() - 2].to_vec();
+            packet.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&rdata);
+            packet
+        };
+        let forwarded = with_options(&[&cookie, &ecs, &padding]);
+        let stripped = strip_ecs(&forwarded);
+        assert_eq!(
+            stripped.as_ref(),
+            with_options(&[&cookie, &padding]).as_slice()
+        );
+        let decoded = decode_query(&stripped, "test.com", Encoding::Base32).expect("decode query");
+        assert_eq!(decoded.payload, payload);
+
+        // A truncated option list is left for the decoder to judge.
+        let truncated = &forwarded[..forwarded.len() - 1];
+        assert!(matches!(strip_ecs(truncated), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn ecs_optout_adds_a_zero_prefix_option() {
+        let qname = crate::build_qname(
+            b"opt out",
+            "test.com",
+            Encoding::Base32,
+            QnameConfig::default(),
+        )
+        .expect("build qname");
+        let params = |ecs_optout| QueryParams {
+            id: 9,
+            qname: &qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns: EdnsOptions {
+                ecs_optout,
+                ..EdnsOptions::default()
+            },
+        };
+        let plain = encode_query(&params(false)).expect("encode query");
+        let query = encode_query(&params(true)).expect("encode query");
+        // RDLENGTH 8, then OPTION-CODE 8, OPTION-LENGTH 4, FAMILY 1, both prefix lengths 0.
+        assert_eq!(&query[query.len() - 10..], &[0, 8, 0, 8, 0, 4, 0, 1, 0, 0]);
+        assert_eq!(&query[..query.len() - 10], &plain[..plain.len() - 2]);
+        let decoded = decode_query(&query, "test.com", Encoding::Base32).expect("decode query");
+        assert_eq!(decoded.payload, b"opt out");
+        assert_eq!(strip_ecs(&query).as_ref(), plain.as_slice());
+
+        // Responses carrying unknown OPT options still decode.
+        let question = Question {
+            name: qname.clone(),
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+        };
+        let mut response = encode_response(&ResponseParams {
+            id: 9,
+            rd: true,
+            cd: false,
+            ad: false,
+            question: &question,
+            payload: Some(b"hi"),
+            rcode: None,
+            encoding: Encoding::Base32,
+            ttl: 0,
+            compress_names: true,
+            edns: EdnsOptions::default(),
+        })
+        .expect("encode response");
+        let len = response.len();
+        response[len - 2..].copy_from_slice(&[0, 12]);
+        response.extend_from_slice(&[0xfd, 0xe9, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(decode_response(&response), Some(b"hi".to_vec()));
+    }
+
+    #[test]
+    fn padded_queries_round_up_and_decode_like_unpadded() {
+        let encode = |payload: &[u8], padding_block, ecs_optout| {
+            let qname = crate::build_qname(
+                payload,
+                "test.com",
+                Encoding::Base32,
+                QnameConfig::default(),
+            )
+            .expect("build qname");
+            encode_query(&QueryParams {
+                id: 5,
+                qname: &qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions {
+                    ecs_optout,
+                    padding_block,
+                    ..EdnsOptions::default()
+                },
+            })
+            .expect("encode query")
+        };
+        let domains = ["test.com"];
+        for payload in [&b"a"[..], b"short payload", &[0x5a; 90]] {
+            let plain = encode(payload, 0, false);
+            for (block, ecs_optout) in [(128, false), (128, true), (1, false), (468, true)] {
+                let padded = encode(payload, block, ecs_optout);
+                assert_eq!(padded.len() % block as usize, 0);
+                assert!(padded.len() >= plain.len() + 4);
+                let unpadded = decode_query_with_domains(&plain, &domains, Encoding::Base32)
+                    .expect("decode query");
+                let decoded = decode_query_with_domains(&padded, &domains, Encoding::Base32)
+                    .expect("decode query");
+                assert_eq!(decoded.payload, unpadded.payload);
+                assert_eq!(decoded.payload, payload);
+                assert_eq!(decoded.question, unpadded.question);
+            }
+        }
+        // Short and long payloads land in the same bucket.
+        assert_eq!(
+            encode(b"a", 256, false).len(),
+            encode(&[0x5a; 90], 256, false).len()
+        );
+
+        // OPTION-CODE 12 closes the OPT RDATA, filled with zeros.
+        let padded = encode(b"a", 128, false);
+        let plain = encode(b"a", 0, false);
+        let padding = padded.len() - plain.len() - 4;
+        let rdlen = &padded[plain.len() - 2..plain.len()];
+        assert_eq!(rdlen, &(padding as u16 + 4).to_be_bytes());
+        let option = &padded[plain.len()..];
+        assert_eq!(&option[..2], &[0, 12]);
+        assert_eq!(&option[2..4], &(padding as u16).to_be_bytes());
+        assert!(option[4..].iter().all(|byte| *byte == 0));
+    }
 }
@@ -1,3 +1,6 @@
+/// Label length used by [`dotify`].
+pub(crate) const DEFAULT_LABEL_LEN: usize = 57;
+
 pub fn dotify(input: &str) -> String {
     if input.is_empty() {
         return String::new();
@@ -38,6 +41,22 @@ pub fn dotify(input: &str) -> String {
     String::from_utf8(buf).unwrap_or_default()
 }
 
+/// Splits `input` into labels of `label_len` characters with the remainder last, never adding
+/// a trailing dot. Unlike [`dotify`], no label ever exceeds `label_len`.
+pub fn dotify_with_label_len(input: &str, label_len: usize) -> String {
+    if label_len == 0 {
+        return input.to_string();
+    }
+    let mut out = String::with_capacity(input.len() + input.len() / label_len);
+    for (idx, label) in input.as_bytes().chunks(label_len).enumerate() {
+        if idx > 0 {
+            out.push('.');
+        }
+        out.push_str(std::str::from_utf8(label).unwrap_or_default());
+    }
+    out
+}
+
 pub fn undotify(input: &str) -> String {
     let mut out = Vec::with_capacity(input.len());
     for &b in input.as_bytes() {
@@ -50,7 +69,7 @@ pub fn undotify(input: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::dotify;
+    use super::{dotify, dotify_with_label_len};
 
     #[test]
     fn dotify_skips_trailing_dot_for_exact_segments() {
@@ -68,4 +87,13 @@ mod tests {
         assert_eq!(dotted, expected);
         assert!(!dotted.ends_with('.'));
     }
+
+    #[test]
+    fn dotify_with_label_len_caps_every_label() {
+        let dotted = dotify_with_label_len(&"A".repeat(70), 32);
+        assert_eq!(
+            dotted,
+            format!("{}.{}.{}", "A".repeat(32), "A".repeat(32), "A".repeat(6))
+        );
+    }
 }
@@ -0,0 +1,40 @@
+use crate::base32;
+use crate::base64url;
+use slipstream_core::dns::Encoding;
+
+// Base32 output never contains a digit outside 2-7, so a leading '0' marks base64url
+// unambiguously, even after resolvers change the case of the name.
+const BASE64URL_MARKER: char = '0';
+
+/// Encodes `payload` for the subdomain, including the alphabet marker if any.
+pub(crate) fn encode(encoding: Encoding, payload: &[u8]) -> String {
+    match encoding {
+        Encoding::Base32 => base32::encode(payload),
+        Encoding::Base64Url => {
+            let mut out = String::with_capacity(encoded_len(encoding, payload.len()));
+            out.push(BASE64URL_MARKER);
+            out.push_str(&base64url::encode(payload));
+            out
+        }
+    }
+}
+
+/// Length of [`encode`] output for a payload of `payload_len` bytes.
+pub(crate) fn encoded_len(encoding: Encoding, payload_len: usize) -> usize {
+    match encoding {
+        Encoding::Base32 => (payload_len * 8).div_ceil(5),
+        Encoding::Base64Url => 1 + base64url::encoded_len(payload_len),
+    }
+}
+
+/// Detects the alphabet of an undotted subdomain and decodes it.
+pub(crate) fn detect_and_decode(text: &str) -> Option<(Encoding, Vec<u8>)> {
+    match text.strip_prefix(BASE64URL_MARKER) {
+        Some(rest) => base64url::decode(rest)
+            .ok()
+            .map(|payload| (Encoding::Base64Url, payload)),
+        None => base32::decode(text)
+            .ok()
+            .map(|payload| (Encoding::Base32, payload)),
+    }
+}
@@ -0,0 +1,197 @@
+//! Per-query authentication label for servers that only answer clients holding a shared secret.
+//!
+//! The label sits between the payload (and any sequence label) and the domain: `8` followed by
+//! a per-query nonce and a truncated HMAC-SHA256 of the nonce and decoded payload, both in hex.
+//! `8` is outside the base32 alphabet, so base32 payloads never end in something that looks
+//! like an auth label.
+//!
+//! The nonce is the issue time in Unix seconds followed by random bytes; servers refuse
+//! nonces outside [`MAX_NONCE_AGE_SECS`] of their clock and ignore ones they have already
+//! seen, so a captured query cannot be replayed.
+
+use crate::types::DnsError;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+const AUTH_LABEL_PREFIX: u8 = b'8';
+
+/// Bytes of the nonce: a big-endian Unix time in seconds, then random bytes.
+pub const NONCE_LEN: usize = 8;
+/// How far a nonce's issue time may be from the server's clock, either way.
+pub const MAX_NONCE_AGE_SECS: u32 = 60;
+/// Bytes of the HMAC kept when no length is configured.
+pub const DEFAULT_MAC_LEN: usize = 8;
+/// Shortest MAC accepted; anything shorter is trivially guessable.
+pub const MIN_MAC_LEN: usize = 4;
+/// Longest MAC whose hex label, with the nonce, still fits in one 63-byte DNS label.
+pub const MAX_MAC_LEN: usize = 23;
+
+/// The nonce and truncated MAC an auth label carries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthTag {
+    pub nonce: [u8; NONCE_LEN],
+    pub mac: Vec<u8>,
+}
+
+impl AuthTag {
+    /// Unix time in seconds the client issued the nonce at.
+    pub fn issued_at(&self) -> u32 {
+        nonce_issued_at(&self.nonce)
+    }
+}
+
+/// Reads a shared secret from `path`, ignoring surrounding whitespace such as a trailing
+/// newline.
+pub fn load_secret(path: &str) -> std::io::Result<Vec<u8>> {
+    let contents = std::fs::read(path)?;
+    let secret = contents.trim_ascii();
+    if secret.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("auth secret file {} is empty", path),
+        ));
+    }
+    Ok(secret.to_vec())
+}
+
+/// A fresh nonce issued at `now_secs`, with its random half from the OpenSSL CSPRNG.
+pub fn new_nonce(now_secs: u32) -> Result<[u8; NONCE_LEN], DnsError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..4].copy_from_slice(&now_secs.to_be_bytes());
+    openssl::rand::rand_bytes(&mut nonce[4..])
+        .map_err(|err| DnsError::with_source("failed to draw an auth nonce", err))?;
+    Ok(nonce)
+}
+
+/// Unix time in seconds `nonce` was issued at.
+pub fn nonce_issued_at(nonce: &[u8; NONCE_LEN]) -> u32 {
+    u32::from_be_bytes([nonce[0], nonce[1], nonce[2], nonce[3]])
+}
+
+/// HMAC-SHA256 of `nonce` and `payload` under `secret`, truncated to `len` bytes (clamped to
+/// the supported range).
+pub fn compute(
+    secret: &[u8],
+    nonce: &[u8; NONCE_LEN],
+    payload: &[u8],
+    len: usize,
+) -> Result<Vec<u8>, DnsError> {
+    let len = len.clamp(MIN_MAC_LEN, MAX_MAC_LEN);
+    let mut mac = hmac_sha256(secret, nonce, payload)
+        .map_err(|err| DnsError::with_source("failed to compute the auth MAC", err))?;
+    mac.truncate(len);
+    Ok(mac)
+}
+
+/// Checks a truncated MAC in constant time; MACs of an unsupported length never verify.
+pub fn verify(secret: &[u8], tag: &AuthTag, payload: &[u8]) -> bool {
+    if !(MIN_MAC_LEN..=MAX_MAC_LEN).contains(&tag.mac.len()) {
+        return false;
+    }
+    match hmac_sha256(secret, &tag.nonce, payload) {
+        Ok(expected) => openssl::memcmp::eq(&expected[..tag.mac.len()], &tag.mac),
+        Err(_) => false,
+    }
+}
+
+fn hmac_sha256(
+    secret: &[u8],
+    nonce: &[u8; NONCE_LEN],
+    payload: &[u8],
+) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let key = PKey::hmac(secret)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(nonce)?;
+    signer.update(payload)?;
+    signer.sign_to_vec()
+}
+
+/// Renders `tag` as an auth label, without its separating dot.
+pub fn auth_label(tag: &AuthTag) -> String {
+    let mut label = String::with_capacity(1 + (NONCE_LEN + tag.mac.len()) * 2);
+    label.push(AUTH_LABEL_PREFIX as char);
+    for byte in tag.nonce.iter().chain(&tag.mac) {
+        label.push_str(&format!("{:02x}", byte));
+    }
+    label
+}
+
+/// Length of the auth label for a `len`-byte MAC, without its separating dot.
+pub fn auth_label_len(len: usize) -> usize {
+    1 + (NONCE_LEN + len.clamp(MIN_MAC_LEN, MAX_MAC_LEN)) * 2
+}
+
+/// Parses an auth label, ignoring the case resolvers may have changed.
+pub fn parse_auth_label(label: &str) -> Option<AuthTag> {
+    let digits = label.strip_prefix(AUTH_LABEL_PREFIX as char)?;
+    let len = digits.len() / 2;
+    if digits.len() % 2 != 0 || !(MIN_MAC_LEN..=MAX_MAC_LEN).contains(&len.checked_sub(NONCE_LEN)?)
+    {
+        return None;
+    }
+    let bytes = (0..len)
+        .map(|i| u8::from_str_radix(digits.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let (nonce, mac) = bytes.split_at(NONCE_LEN);
+    Some(AuthTag {
+        nonce: nonce.try_into().ok()?,
+        mac: mac.to_vec(),
+    })
+}
+
+/// Splits a trailing auth label off a dotted subdomain.
+pub(crate) fn split_auth_label(subdomain: &str) -> Option<(&str, AuthTag)> {
+    let (rest, label) = subdomain.rsplit_once('.')?;
+    let tag = parse_auth_label(label)?;
+    Some((rest, tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_truncated_macs_and_round_trips_labels() {
+        let nonce = new_nonce(1_700_000_000).expect("nonce");
+        assert_eq!(nonce_issued_at(&nonce), 1_700_000_000);
+        let mac = compute(b"secret", &nonce, b"payload", DEFAULT_MAC_LEN).expect("mac");
+        assert_eq!(mac.len(), DEFAULT_MAC_LEN);
+        let tag = AuthTag { nonce, mac };
+        assert!(verify(b"secret", &tag, b"payload"));
+        assert!(!verify(b"other", &tag, b"payload"));
+        assert!(!verify(b"secret", &tag, b"payloaD"));
+        let mut other_nonce = tag.clone();
+        other_nonce.nonce[7] ^= 1;
+        assert!(!verify(b"secret", &other_nonce, b"payload"));
+        let short = AuthTag {
+            nonce,
+            mac: tag.mac[..2].to_vec(),
+        };
+        assert!(!verify(b"secret", &short, b"payload"));
+
+        let label = auth_label(&tag);
+        assert_eq!(label.len(), auth_label_len(DEFAULT_MAC_LEN));
+        assert_eq!(
+            parse_auth_label(&label.to_ascii_uppercase()),
+            Some(tag.clone())
+        );
+        assert_eq!(parse_auth_label("8abc"), None);
+        assert_eq!(parse_auth_label(&format!("8{}", "0".repeat(16))), None);
+        assert_eq!(parse_auth_label(&format!("9{}", "0".repeat(24))), None);
+        assert_eq!(
+            split_auth_label(&format!("nbswy3dp.{}", label)),
+            Some(("nbswy3dp", tag))
+        );
+    }
+
+    #[test]
+    fn the_longest_mac_fits_in_one_label() {
+        assert_eq!(auth_label_len(MAX_MAC_LEN), 63);
+        assert_eq!(
+            auth_label_len(MAX_MAC_LEN + 1),
+            63,
+            "longer MACs are clamped"
+        );
+    }
+}
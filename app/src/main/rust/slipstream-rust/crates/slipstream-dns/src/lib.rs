@@ -1,36 +1,141 @@
 mod base32;
+mod base64url;
 mod codec;
 mod dots;
+mod encoding;
+pub mod hmac;
 mod name;
+mod rdata;
+mod sequence;
 mod types;
 mod wire;
 
 pub use base32::{decode as base32_decode, encode as base32_encode, Base32Error};
+pub use base64url::{decode as base64url_decode, encode as base64url_encode, Base64UrlError};
 pub use codec::{
-    decode_query, decode_query_with_domains, decode_response, encode_query, encode_response,
-    is_response,
+    decode_authenticated_query, decode_query, decode_query_with_domains, decode_response,
+    encode_query, encode_response, is_response, response_id_and_rcode, response_question_name,
+    strip_ecs,
 };
-pub use dots::{dotify, undotify};
+pub use dots::{dotify, dotify_with_label_len, undotify};
+pub use name::MAX_DNS_NAME_LEN;
+pub use rdata::{
+    is_tunnel_qtype, max_response_payload_len, max_udp_txt_payload_len, parse_qtype, txt_rdata_len,
+};
+pub use sequence::{parse_sequence_label, sequence_label, SEQUENCE_LABEL_LEN};
+pub use slipstream_core::dns::{parse_encoding, Encoding};
 pub use types::{
-    DecodeQueryError, DecodedQuery, DnsError, QueryParams, Question, Rcode, ResponseParams,
-    CLASS_IN, EDNS_UDP_PAYLOAD, RR_A, RR_OPT, RR_TXT,
+    DecodeQueryError, DecodedQuery, DnsError, EdnsOptions, QnameConfig, QueryParams, Question,
+    Rcode, ResponseParams, CLASS_IN, EDNS_UDP_PAYLOAD, MIN_EDNS_UDP_PAYLOAD, RR_A, RR_AAAA,
+    RR_CNAME, RR_NULL, RR_OPT, RR_TXT,
 };
 
-pub fn build_qname(payload: &[u8], domain: &str) -> Result<String, DnsError> {
+/// Internal entry points for the cargo-fuzz targets in `fuzz/`; built only with `--cfg fuzzing`.
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub mod fuzzing {
+    pub fn parse_name(packet: &[u8], start: usize) -> Option<(String, usize)> {
+        crate::name::parse_name(packet, start).ok()
+    }
+}
+
+pub fn build_qname(
+    payload: &[u8],
+    domain: &str,
+    encoding: Encoding,
+    config: QnameConfig,
+) -> Result<String, DnsError> {
     let domain = domain.trim_end_matches('.');
     if domain.is_empty() {
         return Err(DnsError::new("domain must not be empty"));
     }
-    let max_payload = max_payload_len_for_domain(domain)?;
+    let max_payload = max_payload_len_for_domain(domain, encoding, config)?;
     if payload.len() > max_payload {
         return Err(DnsError::new("payload too large for domain"));
     }
-    let base32 = base32_encode(payload);
-    let dotted = dotify(&base32);
-    Ok(format!("{}.{}.", dotted, domain))
+    let encoded = encoding::encode(encoding, payload);
+    Ok(format!("{}.{}.", dotify_for(&encoded, config), domain))
+}
+
+/// Applies DNS 0x20 case randomization to the `domain` suffix of `qname`.
+///
+/// Each ASCII letter of the suffix takes its case from the next bit of `entropy`, lowest bit
+/// first; letters past the end of `entropy` stay as they are. The encoded payload labels are
+/// left alone because base64url is case-sensitive.
+pub fn randomize_domain_case(qname: &str, domain: &str, entropy: &[u8]) -> String {
+    let domain = domain.trim_end_matches('.');
+    let trimmed = qname.trim_end_matches('.');
+    if trimmed.len() < domain.len()
+        || !trimmed.is_char_boundary(trimmed.len() - domain.len())
+        || !trimmed[trimmed.len() - domain.len()..].eq_ignore_ascii_case(domain)
+    {
+        return qname.to_string();
+    }
+    let start = trimmed.len() - domain.len();
+    let mut bytes = qname.as_bytes().to_vec();
+    let mut bits = entropy
+        .iter()
+        .flat_map(|byte| (0..8).map(move |bit| byte & (1 << bit) != 0));
+    for byte in &mut bytes[start..trimmed.len()] {
+        if !byte.is_ascii_alphabetic() {
+            continue;
+        }
+        match bits.next() {
+            Some(true) => byte.make_ascii_uppercase(),
+            Some(false) => byte.make_ascii_lowercase(),
+            None => break,
+        }
+    }
+    String::from_utf8(bytes).unwrap_or_else(|_| qname.to_string())
+}
+
+/// Builds a query name that fills `config` with characters neither encoding decodes, so it
+/// never reaches QUIC; `seed` varies the name to defeat resolver caches.
+pub fn build_probe_qname(domain: &str, config: QnameConfig, seed: u16) -> Result<String, DnsError> {
+    let domain = domain.trim_end_matches('.');
+    if domain.is_empty() {
+        return Err(DnsError::new("domain must not be empty"));
+    }
+    let len = max_encoded_len_for_domain(domain, config)?;
+    if len == 0 {
+        return Err(DnsError::new("domain leaves no room for payload"));
+    }
+    // '1', '8' and '9' are outside both alphabets, and '0' would mark base64url.
+    const FILLER: &[u8] = b"189";
+    let mut state = u32::from(seed);
+    let filler: String = (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            FILLER[(state >> 16) as usize % FILLER.len()] as char
+        })
+        .collect();
+    Ok(format!("{}.{}.", dotify_for(&filler, config), domain))
 }
 
-pub fn max_payload_len_for_domain(domain: &str) -> Result<usize, DnsError> {
+pub fn max_payload_len_for_domain(
+    domain: &str,
+    encoding: Encoding,
+    config: QnameConfig,
+) -> Result<usize, DnsError> {
+    let max_encoded_len = max_encoded_len_for_domain(domain, config)?;
+    let mut max_payload = max_encoded_len;
+    while max_payload > 0 && encoding::encoded_len(encoding, max_payload) > max_encoded_len {
+        max_payload -= 1;
+    }
+    Ok(max_payload)
+}
+
+// The default label length keeps the reference implementation's dot placement, whose
+// leading label can run a few characters past 57 on long names.
+fn dotify_for(encoded: &str, config: QnameConfig) -> String {
+    if config.max_label_len as usize == dots::DEFAULT_LABEL_LEN {
+        dotify(encoded)
+    } else {
+        dotify_with_label_len(encoded, config.max_label_len as usize)
+    }
+}
+
+fn max_encoded_len_for_domain(domain: &str, config: QnameConfig) -> Result<usize, DnsError> {
     let domain = domain.trim_end_matches('.');
     if domain.is_empty() {
         return Err(DnsError::new("domain must not be empty"));
@@ -38,50 +143,280 @@ pub fn max_payload_len_for_domain(domain: &str) -> Result<usize, DnsError> {
     if domain.len() > name::MAX_DNS_NAME_LEN {
         return Err(DnsError::new("domain too long"));
     }
-    let max_name_len = name::MAX_DNS_NAME_LEN;
-    let max_dotted_len = max_name_len.saturating_sub(domain.len() + 1);
+    let label_len = config.max_label_len as usize;
+    if label_len == 0 || label_len > name::MAX_LABEL_LEN || config.max_labels == 0 {
+        return Err(DnsError::new("invalid qname label limits"));
+    }
+    let max_name_len = config.max_name_len as usize;
+    if max_name_len == 0 || max_name_len > name::MAX_DNS_NAME_LEN {
+        return Err(DnsError::new("invalid qname length limit"));
+    }
+    let max_dotted_len = max_name_len
+        .saturating_sub(domain.len() + 1)
+        .min(config.max_labels as usize * (label_len + 1) - 1);
     if max_dotted_len == 0 {
         return Ok(0);
     }
-    let mut max_base32_len = 0usize;
+    let mut max_encoded_len = 0usize;
     for len in 1..=max_dotted_len {
-        let dots = (len - 1) / 57;
+        let dots = (len - 1) / label_len;
         if len + dots > max_dotted_len {
             break;
         }
-        max_base32_len = len;
+        max_encoded_len = len;
     }
-
-    let mut max_payload = (max_base32_len * 5) / 8;
-    while max_payload > 0 && base32_len(max_payload) > max_base32_len {
-        max_payload -= 1;
-    }
-    Ok(max_payload)
-}
-
-fn base32_len(payload_len: usize) -> usize {
-    if payload_len == 0 {
-        return 0;
-    }
-    (payload_len * 8).div_ceil(5)
+    Ok(max_encoded_len)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{build_qname, max_payload_len_for_domain};
+    use super::{
+        build_probe_qname, build_qname, decode_query, encode_query, max_payload_len_for_domain,
+        randomize_domain_case, Encoding, QnameConfig,
+    };
+    use crate::{DecodeQueryError, EdnsOptions, QueryParams, Rcode, CLASS_IN, RR_TXT};
 
     #[test]
     fn build_qname_rejects_payload_overflow() {
         let domain = "test.com";
-        let max_payload = max_payload_len_for_domain(domain).expect("max payload");
-        let payload = vec![0u8; max_payload + 1];
-        assert!(build_qname(&payload, domain).is_err());
+        for encoding in [Encoding::Base32, Encoding::Base64Url] {
+            let max_payload = max_payload_len_for_domain(domain, encoding, QnameConfig::default())
+                .expect("max payload");
+            let payload = vec![0u8; max_payload + 1];
+            assert!(build_qname(&payload, domain, encoding, QnameConfig::default()).is_err());
+        }
     }
 
     #[test]
     fn build_qname_rejects_long_domain() {
         let domain = format!("{}.com", "a".repeat(260));
         let payload = vec![0u8; 1];
-        assert!(build_qname(&payload, &domain).is_err());
+        assert!(build_qname(&payload, &domain, Encoding::Base32, QnameConfig::default()).is_err());
+    }
+
+    #[test]
+    fn base64url_qname_fits_more_payload_in_short_labels() {
+        let domain = "test.com";
+        let base32_max =
+            max_payload_len_for_domain(domain, Encoding::Base32, QnameConfig::default())
+                .expect("max payload");
+        let base64_max =
+            max_payload_len_for_domain(domain, Encoding::Base64Url, QnameConfig::default())
+                .expect("max payload");
+        assert!(base64_max > base32_max);
+
+        let payload: Vec<u8> = (0..base64_max).map(|i| i as u8).collect();
+        let qname = build_qname(
+            &payload,
+            domain,
+            Encoding::Base64Url,
+            QnameConfig::default(),
+        )
+        .expect("build qname");
+        assert!(qname.trim_end_matches('.').len() <= 253);
+        assert!(qname.split('.').all(|label| label.len() <= 63));
+
+        let query = encode_query(&QueryParams {
+            id: 7,
+            qname: &qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns: EdnsOptions::default(),
+        })
+        .expect("encode query");
+        let decoded = decode_query(&query, domain, Encoding::Base64Url).expect("decode query");
+        assert_eq!(decoded.payload, payload);
+    }
+
+    #[test]
+    fn build_qname_respects_label_limits() {
+        let domain = "test.com";
+        let config = QnameConfig {
+            max_label_len: 32,
+            max_labels: 2,
+            ..QnameConfig::default()
+        };
+        let max_payload =
+            max_payload_len_for_domain(domain, Encoding::Base32, config).expect("max payload");
+        assert_eq!(max_payload, 40);
+        assert!(
+            max_payload
+                < max_payload_len_for_domain(domain, Encoding::Base32, QnameConfig::default())
+                    .expect("max payload")
+        );
+
+        let payload = vec![7u8; max_payload];
+        let qname = build_qname(&payload, domain, Encoding::Base32, config).expect("build qname");
+        let labels: Vec<&str> = qname.trim_end_matches(".test.com.").split('.').collect();
+        assert_eq!(labels.len(), 2);
+        assert!(labels.iter().all(|label| label.len() <= 32));
+        assert!(build_qname(&[0u8; 41], domain, Encoding::Base32, config).is_err());
+
+        let query = encode_query(&QueryParams {
+            id: 3,
+            qname: &qname,
+            qtype: RR_TXT,
+            qclass: CLASS_IN,
+            rd: true,
+            cd: false,
+            qdcount: 1,
+            is_query: true,
+            edns: EdnsOptions::default(),
+        })
+        .expect("encode query");
+        let decoded = decode_query(&query, domain, Encoding::Base32).expect("decode query");
+        assert_eq!(decoded.payload, payload);
+    }
+
+    #[test]
+    fn build_qname_rejects_invalid_label_limits() {
+        for (max_label_len, max_labels) in [(0, 4), (64, 4), (57, 0)] {
+            let config = QnameConfig {
+                max_label_len,
+                max_labels,
+                ..QnameConfig::default()
+            };
+            assert!(build_qname(&[1], "test.com", Encoding::Base32, config).is_err());
+        }
+    }
+
+    #[test]
+    fn lower_name_cap_splits_payload_into_more_shorter_queries() {
+        let domain = "test.com";
+        let payload: Vec<u8> = (0..=255).collect();
+        let capped = QnameConfig {
+            max_name_len: 120,
+            ..QnameConfig::default()
+        };
+        let qnames = |config: QnameConfig| -> Vec<String> {
+            let chunk =
+                max_payload_len_for_domain(domain, Encoding::Base32, config).expect("max payload");
+            payload
+                .chunks(chunk)
+                .map(|part| {
+                    build_qname(part, domain, Encoding::Base32, config).expect("build qname")
+                })
+                .collect()
+        };
+        let full = qnames(QnameConfig::default());
+        let short = qnames(capped);
+        assert!(short.len() > full.len());
+        assert!(short.iter().all(|qname| qname.len() <= 120 + 1));
+        assert!(full.iter().any(|qname| qname.len() > 120 + 1));
+
+        // The decoder still takes names up to 253 regardless of what the sender capped.
+        let mut decoded = Vec::new();
+        for qname in &full {
+            let query = encode_query(&QueryParams {
+                id: 5,
+                qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode query");
+            decoded.extend(
+                decode_query(&query, domain, Encoding::Base32)
+                    .expect("decode query")
+                    .payload,
+            );
+        }
+        assert_eq!(decoded, payload);
+    }
+
+    #[test]
+    fn build_qname_rejects_name_cap_over_253() {
+        for max_name_len in [0, 254] {
+            let config = QnameConfig {
+                max_name_len,
+                ..QnameConfig::default()
+            };
+            assert!(build_qname(&[1], "test.com", Encoding::Base32, config).is_err());
+        }
+    }
+
+    #[test]
+    fn probe_qname_fills_config_and_gets_servfail() {
+        let domain = "test.com";
+        let config = QnameConfig {
+            max_label_len: 57,
+            max_labels: 3,
+            ..QnameConfig::default()
+        };
+        let qname = build_probe_qname(domain, config, 9).expect("probe qname");
+        assert_eq!(qname.len(), 3 * 58 + "test.com.".len());
+        assert_ne!(
+            qname,
+            build_probe_qname(domain, config, 10).expect("probe qname")
+        );
+
+        for encoding in [Encoding::Base32, Encoding::Base64Url] {
+            let query = encode_query(&QueryParams {
+                id: 9,
+                qname: &qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode query");
+            match decode_query(&query, domain, encoding) {
+                Err(DecodeQueryError::Reply { rcode, .. }) => {
+                    assert_eq!(rcode, Rcode::ServerFailure)
+                }
+                other => panic!("expected SERVFAIL reply, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn case_randomization_only_touches_the_domain() {
+        let qname = build_qname(
+            &[1, 2, 3],
+            "test.com",
+            Encoding::Base64Url,
+            QnameConfig::default(),
+        )
+        .expect("build qname");
+        let payload_labels = &qname[..qname.len() - "test.com.".len()];
+        let cased = randomize_domain_case(&qname, "test.com", &[0b0101_0101]);
+        assert_eq!(&cased[..payload_labels.len()], payload_labels);
+        assert!(cased.ends_with(".TeSt.CoM."), "{}", cased);
+        assert!(cased.eq_ignore_ascii_case(&qname));
+
+        let decoded = decode_query(
+            &encode_query(&QueryParams {
+                id: 1,
+                qname: &cased,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("encode query"),
+            "test.com",
+            Encoding::Base64Url,
+        )
+        .expect("decode query");
+        assert_eq!(decoded.payload, vec![1, 2, 3]);
+        assert_eq!(decoded.question.name, cased);
+        assert_eq!(
+            randomize_domain_case("a.other.org.", "test.com", &[0xff]),
+            "a.other.org."
+        );
     }
 }
@@ -1,6 +1,8 @@
 use crate::types::{DnsError, Rcode};
 
-pub(crate) const MAX_DNS_NAME_LEN: usize = 253;
+/// Longest name in presentation form without the trailing dot (RFC 1035 2.3.4).
+pub const MAX_DNS_NAME_LEN: usize = 253;
+pub(crate) const MAX_LABEL_LEN: usize = 63;
 
 fn extract_subdomain(qname: &str, domain: &str) -> Result<String, Rcode> {
     let domain = domain.trim_end_matches('.');
@@ -28,19 +30,54 @@ fn extract_subdomain(qname: &str, domain: &str) -> Result<String, Rcode> {
     Ok(subdomain.to_string())
 }
 
-pub(crate) fn extract_subdomain_multi(qname: &str, domains: &[&str]) -> Result<String, Rcode> {
+/// Name a `*.base` wildcard stands for in `qname`: `base` plus the single label before it.
+/// The apex `base` itself never matches.
+fn wildcard_domain<'a>(qname: &'a str, base: &str) -> Option<&'a str> {
+    let base_start = qname.len().checked_sub(base.len())?;
+    if base.is_empty()
+        || base_start < 2
+        || !qname.get(base_start..)?.eq_ignore_ascii_case(base)
+        || qname.as_bytes()[base_start - 1] != b'.'
+    {
+        return None;
+    }
+    let label_start = qname[..base_start - 1].rfind('.').map_or(0, |dot| dot + 1);
+    if label_start == base_start - 1 {
+        return None;
+    }
+    Some(&qname[label_start..])
+}
+
+/// Extracts the subdomain under the longest matching domain, along with that domain's index
+/// in `domains`.
+///
+/// A `*.base` entry matches as `base` plus whichever label precedes it in the query, so its
+/// payload starts one label further left. When it matches the same name as an exact entry, the
+/// exact entry wins.
+pub(crate) fn extract_subdomain_multi(
+    qname: &str,
+    domains: &[&str],
+) -> Result<(String, usize), Rcode> {
     let qname_trimmed = qname.trim_end_matches('.');
     if qname_trimmed.is_empty() {
         return Err(Rcode::NameError);
     }
     let qname_lower = qname_trimmed.to_ascii_lowercase();
 
-    let mut best_domain: Option<&str> = None;
+    let mut best_domain: Option<(&str, usize)> = None;
     let mut best_len = 0usize;
     let mut best_empty = false;
+    let mut best_wildcard = false;
 
-    for domain in domains {
+    for (index, domain) in domains.iter().enumerate() {
         let domain_trimmed = domain.trim_end_matches('.');
+        let (domain_trimmed, wildcard) = match domain_trimmed.strip_prefix("*.") {
+            Some(base) => match wildcard_domain(qname_trimmed, base) {
+                Some(matched) => (matched, true),
+                None => continue,
+            },
+            None => (domain_trimmed, false),
+        };
         if domain_trimmed.is_empty() {
             continue;
         }
@@ -57,21 +94,22 @@ pub(crate) fn extract_subdomain_multi(qname: &str, domains: &[&str]) -> Result<S
         }
 
         let domain_len = domain_trimmed.len();
-        if domain_len > best_len {
+        if domain_len > best_len || (domain_len == best_len && best_wildcard && !wildcard) {
             best_len = domain_len;
-            best_domain = Some(domain_trimmed);
+            best_domain = Some((domain_trimmed, index));
             best_empty = is_exact;
+            best_wildcard = wildcard;
         }
     }
 
-    let Some(best_domain) = best_domain else {
+    let Some((best_domain, best_index)) = best_domain else {
         return Err(Rcode::NameError);
     };
     if best_empty {
         return Err(Rcode::NameError);
     }
 
-    extract_subdomain(qname, best_domain)
+    extract_subdomain(qname, best_domain).map(|subdomain| (subdomain, best_index))
 }
 
 pub(crate) fn parse_name(packet: &[u8], start: usize) -> Result<(String, usize), DnsError> {
@@ -134,7 +172,7 @@ pub(crate) fn parse_name(packet: &[u8], start: usize) -> Result<(String, usize),
             return Err(DnsError::new("name too long"));
         }
         let label = std::str::from_utf8(&packet[offset..end])
-            .map_err(|_| DnsError::new("label not utf-8"))?;
+            .map_err(|err| DnsError::with_source("label not utf-8", err))?;
         labels.push(label.to_string());
         offset = end;
         if !jumped {
@@ -153,7 +191,33 @@ pub(crate) fn parse_name(packet: &[u8], start: usize) -> Result<(String, usize),
     Ok((name, end_offset))
 }
 
-pub(crate) fn encode_name(name: &str, out: &mut Vec<u8>) -> Result<(), DnsError> {
+/// Names already written to a packet, for emitting compression pointers (RFC 1035 4.1.4).
+///
+/// Suffixes match byte for byte, so a pointer never changes the case of the name it stands
+/// for. Only offsets a pointer can reach (below 0x4000) are remembered.
+#[derive(Debug, Default)]
+pub(crate) struct CompressionTable {
+    suffixes: Vec<(String, u16)>,
+}
+
+impl CompressionTable {
+    fn find(&self, suffix: &str) -> Option<u16> {
+        self.suffixes
+            .iter()
+            .find(|(known, _)| known == suffix)
+            .map(|(_, offset)| *offset)
+    }
+}
+
+/// Writes `name` at the end of `out`, which must hold the packet from its first byte.
+///
+/// With a `table`, the longest suffix written before is replaced by a pointer and the new
+/// suffixes are recorded; without one the name is always written in full.
+pub(crate) fn encode_name(
+    name: &str,
+    out: &mut Vec<u8>,
+    mut table: Option<&mut CompressionTable>,
+) -> Result<(), DnsError> {
     if name == "." {
         out.push(0);
         return Ok(());
@@ -161,24 +225,40 @@ pub(crate) fn encode_name(name: &str, out: &mut Vec<u8>) -> Result<(), DnsError>
 
     let trimmed = name.trim_end_matches('.');
     let mut name_len = 0usize;
-    let mut first = true;
+    let mut labels = Vec::new();
     for label in trimmed.split('.') {
         if label.is_empty() {
             return Err(DnsError::new("empty label"));
         }
-        if label.len() > 63 {
+        if label.len() > MAX_LABEL_LEN {
             return Err(DnsError::new("label too long"));
         }
-        if !first {
+        if !labels.is_empty() {
             name_len += 1;
         }
         name_len += label.len();
         if name_len > MAX_DNS_NAME_LEN {
             return Err(DnsError::new("name too long"));
         }
+        labels.push(label);
+    }
+
+    // Byte offset of each label within `trimmed`, so suffixes are plain slices.
+    let mut suffix_start = 0usize;
+    for label in labels {
+        let suffix = &trimmed[suffix_start..];
+        if let Some(table) = table.as_deref_mut() {
+            if let Some(pointer) = table.find(suffix) {
+                out.extend_from_slice(&(0xC000 | pointer).to_be_bytes());
+                return Ok(());
+            }
+            if out.len() < 0x4000 {
+                table.suffixes.push((suffix.to_string(), out.len() as u16));
+            }
+        }
         out.push(label.len() as u8);
         out.extend_from_slice(label.as_bytes());
-        first = false;
+        suffix_start += label.len() + 1;
     }
     out.push(0);
     Ok(())
@@ -187,7 +267,7 @@ pub(crate) fn encode_name(name: &str, out: &mut Vec<u8>) -> Result<(), DnsError>
 #[cfg(test)]
 mod tests {
     use super::MAX_DNS_NAME_LEN;
-    use super::{encode_name, parse_name};
+    use super::{encode_name, parse_name, CompressionTable};
 
     fn build_name(last_label_len: usize) -> String {
         format!(
@@ -204,11 +284,11 @@ mod tests {
         let mut out = Vec::new();
         let max_name = build_name(61);
         assert!(max_name.trim_end_matches('.').len() == MAX_DNS_NAME_LEN);
-        assert!(encode_name(&max_name, &mut out).is_ok());
+        assert!(encode_name(&max_name, &mut out, None).is_ok());
 
         let mut out = Vec::new();
         let too_long = build_name(62);
-        assert!(encode_name(&too_long, &mut out).is_err());
+        assert!(encode_name(&too_long, &mut out, None).is_err());
     }
 
     #[test]
@@ -231,4 +311,54 @@ mod tests {
         packet.push(0);
         assert!(parse_name(&packet, 0).is_err());
     }
+
+    #[test]
+    fn parse_errors_keep_their_cause() {
+        use std::error::Error;
+
+        let packet = [2, b'a', 0xff, 0];
+        let err = parse_name(&packet, 0).unwrap_err();
+        assert_eq!(err.to_string(), "label not utf-8");
+        assert!(err
+            .source()
+            .is_some_and(|cause| cause.is::<std::str::Utf8Error>()));
+
+        let err = crate::wire::parse_question(&packet, 0).unwrap_err();
+        assert_eq!(err.to_string(), "bad name");
+        let cause = err.source().expect("bad name has a cause");
+        assert_eq!(cause.to_string(), "label not utf-8");
+        assert!(cause.source().is_some());
+    }
+
+    #[test]
+    fn compressed_names_decode_to_the_same_names() {
+        let names = [
+            "a.Tunnel.example.com.",
+            "b.Tunnel.example.com.",
+            "Tunnel.example.com.",
+            "a.tunnel.example.com.",
+            "example.com.",
+            ".",
+        ];
+        let mut table = CompressionTable::default();
+        let mut compressed = Vec::new();
+        let mut starts = Vec::new();
+        for name in names {
+            starts.push(compressed.len());
+            encode_name(name, &mut compressed, Some(&mut table)).expect("encode");
+        }
+        let mut plain = Vec::new();
+        for name in names {
+            encode_name(name, &mut plain, None).expect("encode");
+        }
+        assert!(compressed.len() < plain.len());
+        for (name, start) in names.iter().zip(&starts) {
+            assert_eq!(parse_name(&compressed, *start).unwrap().0, *name);
+        }
+        // The second name reuses "Tunnel.example.com" and ends in a pointer.
+        assert_eq!(starts[2] - starts[1], 4);
+        // A suffix differing only in case is written again rather than pointed at.
+        let (_, end) = parse_name(&compressed, starts[3]).unwrap();
+        assert!(end - starts[3] > 4);
+    }
 }
@@ -0,0 +1,353 @@
+use crate::dots;
+use crate::encoding;
+use crate::name::{encode_name, parse_name, MAX_DNS_NAME_LEN};
+use crate::types::{DnsError, RR_A, RR_AAAA, RR_CNAME, RR_NULL, RR_TXT};
+use crate::Encoding;
+
+// Answer bytes we allow per response so it stays under the advertised EDNS payload
+// (1232) after the header, a maximum-length question, and the OPT record.
+const ANSWER_BUDGET: usize = 950;
+// Owner pointer, type, class, TTL, and RDLENGTH.
+const ANSWER_FIXED_LEN: usize = 12;
+const ADDRESS_LENGTH_PREFIX: usize = 2;
+// Character-strings per TXT answer; more than this is not a response we would ever send.
+const MAX_TXT_STRINGS: usize = 255;
+
+/// Returns true for query types that can carry tunnel payloads.
+pub fn is_tunnel_qtype(qtype: u16) -> bool {
+    matches!(qtype, RR_TXT | RR_A | RR_AAAA | RR_CNAME | RR_NULL)
+}
+
+/// Parses a CLI/config query type name (`txt`, `a`, `aaaa`, `cname`, `null`).
+pub fn parse_qtype(name: &str) -> Option<u16> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "txt" => Some(RR_TXT),
+        "a" => Some(RR_A),
+        "aaaa" => Some(RR_AAAA),
+        "cname" => Some(RR_CNAME),
+        "null" => Some(RR_NULL),
+        _ => None,
+    }
+}
+
+/// Largest payload a single response of `qtype` can carry, or `None` when the record
+/// shape does not impose a limit tighter than the QUIC MTU (TXT).
+pub fn max_response_payload_len(qtype: u16) -> Option<usize> {
+    match qtype {
+        RR_A | RR_AAAA => {
+            let chunk = address_chunk_len(qtype);
+            let records =
+                (ANSWER_BUDGET / (ANSWER_FIXED_LEN + chunk + 1)).min(max_address_records(qtype));
+            Some(records * chunk - ADDRESS_LENGTH_PREFIX)
+        }
+        RR_NULL => Some(max_payload_for_base32(ANSWER_BUDGET - ANSWER_FIXED_LEN)),
+        RR_CNAME => {
+            let mut max_base32_len = 0usize;
+            for len in 1..=MAX_DNS_NAME_LEN {
+                if len + (len - 1) / 57 > MAX_DNS_NAME_LEN {
+                    break;
+                }
+                max_base32_len = len;
+            }
+            Some(max_payload_for_base32(max_base32_len))
+        }
+        _ => None,
+    }
+}
+
+/// RDATA length of a TXT answer carrying `payload_len` bytes as 255-byte strings.
+pub fn txt_rdata_len(payload_len: usize) -> usize {
+    payload_len + payload_len.div_ceil(255)
+}
+
+/// Largest TXT payload whose response stays within the advertised EDNS payload size.
+pub fn max_udp_txt_payload_len() -> usize {
+    let budget = ANSWER_BUDGET - ANSWER_FIXED_LEN;
+    // Each started 255-byte string costs one length byte.
+    let mut payload_len = budget - budget.div_ceil(256);
+    while txt_rdata_len(payload_len + 1) <= budget {
+        payload_len += 1;
+    }
+    payload_len
+}
+
+/// Splits `payload` into the RDATA of one or more answers shaped for `qtype`.
+///
+/// Name-shaped records use `encoding`; limits stay at the base32 bound, which base64url
+/// always fits within.
+pub(crate) fn encode_answers(
+    qtype: u16,
+    payload: &[u8],
+    encoding: Encoding,
+) -> Result<Vec<Vec<u8>>, DnsError> {
+    if let Some(max_len) = max_response_payload_len(qtype) {
+        if payload.len() > max_len {
+            return Err(DnsError::new("payload too long for record type"));
+        }
+    }
+    match qtype {
+        RR_A | RR_AAAA => Ok(encode_address_records(qtype, payload)),
+        RR_CNAME => {
+            let name = dots::dotify(&encoding::encode(encoding, payload));
+            let mut rdata = Vec::with_capacity(name.len() + 2);
+            encode_name(&name, &mut rdata, None)?;
+            Ok(vec![rdata])
+        }
+        RR_NULL => Ok(vec![encoding::encode(encoding, payload).into_bytes()]),
+        _ => {
+            if payload.len().div_ceil(255) > MAX_TXT_STRINGS {
+                return Err(DnsError::new("payload needs more than 255 TXT strings"));
+            }
+            let rdata_len = txt_rdata_len(payload.len());
+            let mut rdata = Vec::with_capacity(rdata_len);
+            for chunk in payload.chunks(255) {
+                rdata.push(chunk.len() as u8);
+                rdata.extend_from_slice(chunk);
+            }
+            Ok(vec![rdata])
+        }
+    }
+}
+
+/// Reassembles the payload from answer RDATA (given as packet offsets) of type `qtype`.
+pub(crate) fn decode_answers(
+    packet: &[u8],
+    qtype: u16,
+    answers: &[(usize, usize)],
+) -> Option<Vec<u8>> {
+    match qtype {
+        RR_A | RR_AAAA => decode_address_records(packet, qtype, answers),
+        RR_CNAME => {
+            let [(offset, _)] = answers else {
+                return None;
+            };
+            let (name, _) = parse_name(packet, *offset).ok()?;
+            let undotted = dots::undotify(name.trim_end_matches('.'));
+            decode_name_payload(&undotted)
+        }
+        RR_NULL => {
+            let [(offset, len)] = answers else {
+                return None;
+            };
+            let text = std::str::from_utf8(&packet[*offset..*offset + *len]).ok()?;
+            decode_name_payload(text)
+        }
+        RR_TXT => {
+            let [(offset, len)] = answers else {
+                return None;
+            };
+            decode_txt(&packet[*offset..*offset + *len])
+        }
+        _ => None,
+    }
+}
+
+fn decode_name_payload(text: &str) -> Option<Vec<u8>> {
+    encoding::detect_and_decode(text)
+        .map(|(_, payload)| payload)
+        .filter(|out| !out.is_empty())
+}
+
+fn decode_txt(rdata: &[u8]) -> Option<Vec<u8>> {
+    let mut cursor = 0;
+    let mut out = Vec::with_capacity(rdata.len());
+    while cursor < rdata.len() {
+        let txt_len = rdata[cursor] as usize;
+        cursor += 1;
+        if txt_len > rdata.len() - cursor {
+            return None;
+        }
+        out.extend_from_slice(&rdata[cursor..cursor + txt_len]);
+        cursor += txt_len;
+    }
+    if out.is_empty() {
+        return None;
+    }
+    Some(out)
+}
+
+fn address_chunk_len(qtype: u16) -> usize {
+    // One byte of every address numbers the record, since resolvers may reorder RRsets.
+    if qtype == RR_A {
+        3
+    } else {
+        15
+    }
+}
+
+// The first byte of every address is its record index plus this base, so each answer lands
+// in globally routable space (A 11.0.0.0-99.255.255.255, AAAA 2100::/8-3e00::/8) that
+// resolvers with rebinding or bogon filtering pass through.
+fn address_index_base(qtype: u16) -> u8 {
+    if qtype == RR_A {
+        11
+    } else {
+        0x21
+    }
+}
+
+fn max_address_records(qtype: u16) -> usize {
+    if qtype == RR_A {
+        89
+    } else {
+        30
+    }
+}
+
+fn encode_address_records(qtype: u16, payload: &[u8]) -> Vec<Vec<u8>> {
+    let chunk = address_chunk_len(qtype);
+    let base = address_index_base(qtype);
+    let mut stream = Vec::with_capacity(payload.len() + ADDRESS_LENGTH_PREFIX);
+    stream.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    stream.extend_from_slice(payload);
+    stream
+        .chunks(chunk)
+        .enumerate()
+        .map(|(index, data)| {
+            let mut rdata = Vec::with_capacity(chunk + 1);
+            rdata.push(base + index as u8);
+            rdata.extend_from_slice(data);
+            rdata.resize(chunk + 1, 0);
+            rdata
+        })
+        .collect()
+}
+
+fn decode_address_records(
+    packet: &[u8],
+    qtype: u16,
+    answers: &[(usize, usize)],
+) -> Option<Vec<u8>> {
+    let chunk = address_chunk_len(qtype);
+    let mut records: Vec<&[u8]> = Vec::with_capacity(answers.len());
+    for (offset, len) in answers {
+        if *len != chunk + 1 {
+            return None;
+        }
+        records.push(&packet[*offset..*offset + *len]);
+    }
+    records.sort_by_key(|rdata| rdata[0]);
+    let base = address_index_base(qtype) as usize;
+    let mut stream = Vec::with_capacity(records.len() * chunk);
+    for (index, rdata) in records.iter().enumerate() {
+        if rdata[0] as usize != base + index {
+            return None;
+        }
+        stream.extend_from_slice(&rdata[1..]);
+    }
+    if stream.len() < ADDRESS_LENGTH_PREFIX {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([stream[0], stream[1]]) as usize;
+    let payload = &stream[ADDRESS_LENGTH_PREFIX..];
+    if payload_len == 0 || payload_len > payload.len() {
+        return None;
+    }
+    Some(payload[..payload_len].to_vec())
+}
+
+fn max_payload_for_base32(max_base32_len: usize) -> usize {
+    (max_base32_len * 5) / 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_answers, encode_answers, max_response_payload_len, max_udp_txt_payload_len,
+        txt_rdata_len, ANSWER_BUDGET, ANSWER_FIXED_LEN,
+    };
+    use crate::types::{RR_A, RR_AAAA, RR_CNAME, RR_NULL, RR_TXT};
+    use crate::Encoding;
+    use std::net::Ipv4Addr;
+
+    fn round_trip(qtype: u16, payload: &[u8]) -> Option<Vec<u8>> {
+        round_trip_with(qtype, payload, Encoding::Base32)
+    }
+
+    fn round_trip_with(qtype: u16, payload: &[u8], encoding: Encoding) -> Option<Vec<u8>> {
+        let answers = encode_answers(qtype, payload, encoding).expect("encode answers");
+        let mut packet = Vec::new();
+        let mut offsets = Vec::new();
+        for rdata in answers.iter().rev() {
+            offsets.push((packet.len(), rdata.len()));
+            packet.extend_from_slice(rdata);
+        }
+        decode_answers(&packet, qtype, &offsets)
+    }
+
+    #[test]
+    fn address_records_survive_reordering() {
+        let payload: Vec<u8> = (0..64).collect();
+        assert_eq!(round_trip(RR_A, &payload), Some(payload.clone()));
+        assert_eq!(round_trip(RR_AAAA, &payload), Some(payload));
+    }
+
+    #[test]
+    fn address_records_stay_out_of_filtered_ranges() {
+        for payload in [vec![0u8; 1], vec![0xFF; 64]] {
+            let records = encode_answers(RR_A, &payload, Encoding::Base32).expect("encode A");
+            for rdata in records {
+                let addr = Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]);
+                assert!(!matches!(rdata[0], 0 | 10 | 100 | 127), "{}", addr);
+                assert!(
+                    !(addr.is_private()
+                        || addr.is_loopback()
+                        || addr.is_link_local()
+                        || addr.is_multicast()
+                        || addr.is_documentation()
+                        || addr.is_broadcast()),
+                    "{}",
+                    addr
+                );
+            }
+        }
+        let max_len = max_response_payload_len(RR_A).expect("bounded A");
+        let records = encode_answers(RR_A, &vec![0u8; max_len], Encoding::Base32).unwrap();
+        assert!(records.iter().all(|rdata| (11..=99).contains(&rdata[0])));
+
+        // Global unicast, clear of 2001:db8::/32, 2002::/16 and 3fff::/20.
+        let max_len = max_response_payload_len(RR_AAAA).expect("bounded AAAA");
+        let records = encode_answers(RR_AAAA, &vec![0u8; max_len], Encoding::Base32).unwrap();
+        assert!(records
+            .iter()
+            .all(|rdata| (0x21..=0x3e).contains(&rdata[0])));
+    }
+
+    #[test]
+    fn name_shaped_records_round_trip() {
+        let payload = b"hello tunnel".to_vec();
+        assert_eq!(round_trip(RR_CNAME, &payload), Some(payload.clone()));
+        assert_eq!(round_trip(RR_NULL, &payload), Some(payload.clone()));
+        for qtype in [RR_CNAME, RR_NULL] {
+            assert_eq!(
+                round_trip_with(qtype, &payload, Encoding::Base64Url),
+                Some(payload.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_payload_over_record_capacity() {
+        for qtype in [RR_A, RR_AAAA, RR_CNAME, RR_NULL] {
+            let max_len = max_response_payload_len(qtype).expect("bounded qtype");
+            let payload = vec![0xAB; max_len];
+            for encoding in [Encoding::Base32, Encoding::Base64Url] {
+                assert_eq!(
+                    round_trip_with(qtype, &payload, encoding),
+                    Some(payload.clone())
+                );
+                assert!(encode_answers(qtype, &vec![0u8; max_len + 1], encoding).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn udp_txt_budget_is_the_largest_fitting_payload() {
+        let max_len = max_udp_txt_payload_len();
+        assert!(ANSWER_FIXED_LEN + txt_rdata_len(max_len) <= ANSWER_BUDGET);
+        assert!(ANSWER_FIXED_LEN + txt_rdata_len(max_len + 1) > ANSWER_BUDGET);
+        let answers =
+            encode_answers(RR_TXT, &vec![0u8; max_len], Encoding::Base32).expect("encode answers");
+        assert_eq!(answers[0].len(), txt_rdata_len(max_len));
+    }
+}
@@ -0,0 +1,55 @@
+//! Query sequence label used by clients that discard replayed responses.
+//!
+//! The label sits between the payload labels and the domain: `9` followed by the counter as
+//! eight hex digits. `9` is outside the base32 alphabet, so base32 payloads never end in
+//! something that looks like a sequence label.
+
+const SEQUENCE_LABEL_PREFIX: u8 = b'9';
+
+/// Length of a sequence label, without its separating dot.
+pub const SEQUENCE_LABEL_LEN: usize = 9;
+
+pub fn sequence_label(sequence: u32) -> String {
+    format!("{}{:08x}", SEQUENCE_LABEL_PREFIX as char, sequence)
+}
+
+/// Parses a sequence label, ignoring the case resolvers may have changed.
+pub fn parse_sequence_label(label: &str) -> Option<u32> {
+    let bytes = label.as_bytes();
+    if bytes.len() != SEQUENCE_LABEL_LEN || bytes[0] != SEQUENCE_LABEL_PREFIX {
+        return None;
+    }
+    let digits = &label[1..];
+    if !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// Splits a trailing sequence label off a dotted subdomain.
+pub(crate) fn split_sequence_label(subdomain: &str) -> Option<(&str, u32)> {
+    let (payload, label) = subdomain.rsplit_once('.')?;
+    let sequence = parse_sequence_label(label)?;
+    Some((payload, sequence))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_sequence_labels() {
+        let label = sequence_label(0x01ab_cdef);
+        assert_eq!(label, "901abcdef");
+        assert_eq!(parse_sequence_label(&label), Some(0x01ab_cdef));
+        assert_eq!(parse_sequence_label("901ABCDEF"), Some(0x01ab_cdef));
+        assert_eq!(parse_sequence_label("801abcdef"), None);
+        assert_eq!(parse_sequence_label("901abcdeg"), None);
+        assert_eq!(parse_sequence_label("9+1abcdef"), None);
+        assert_eq!(
+            split_sequence_label("nbswy3dp.9000000ff"),
+            Some(("nbswy3dp", 0xff))
+        );
+        assert_eq!(split_sequence_label("nbswy3dp"), None);
+    }
+}
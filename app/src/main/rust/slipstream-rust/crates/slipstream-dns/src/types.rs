@@ -1,10 +1,16 @@
+use crate::hmac::AuthTag;
+use crate::Encoding;
+pub use slipstream_core::dns::{EdnsOptions, EDNS_UDP_PAYLOAD, MIN_EDNS_UDP_PAYLOAD};
 use std::fmt;
+use std::sync::Arc;
 
 pub const RR_A: u16 = 1;
+pub const RR_CNAME: u16 = 5;
+pub const RR_NULL: u16 = 10;
 pub const RR_TXT: u16 = 16;
+pub const RR_AAAA: u16 = 28;
 pub const RR_OPT: u16 = 41;
 pub const CLASS_IN: u16 = 1;
-pub const EDNS_UDP_PAYLOAD: u16 = 1232;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Rcode {
@@ -12,6 +18,7 @@ pub enum Rcode {
     FormatError,
     ServerFailure,
     NameError,
+    Refused,
 }
 
 impl Rcode {
@@ -21,6 +28,7 @@ impl Rcode {
             Rcode::FormatError => 1,
             Rcode::ServerFailure => 2,
             Rcode::NameError => 3,
+            Rcode::Refused => 5,
         }
     }
 
@@ -30,6 +38,7 @@ impl Rcode {
             1 => Some(Rcode::FormatError),
             2 => Some(Rcode::ServerFailure),
             3 => Some(Rcode::NameError),
+            5 => Some(Rcode::Refused),
             _ => None,
         }
     }
@@ -47,22 +56,77 @@ pub struct DecodedQuery {
     pub id: u16,
     pub rd: bool,
     pub cd: bool,
+    /// The query's AD bit: the asker understands AD in the answer (RFC 6840 section 5.7).
+    pub ad: bool,
     pub question: Question,
     pub payload: Vec<u8>,
+    /// Index of the domain the query name matched in the list passed to the decoder.
+    pub domain_index: usize,
+    /// Nonce and truncated HMAC from the query's auth label; only
+    /// [`decode_authenticated_query`](crate::decode_authenticated_query) looks for one.
+    pub auth: Option<AuthTag>,
 }
 
 #[derive(Debug, Clone)]
 pub enum DecodeQueryError {
-    Drop,
+    /// Not answered at all; carries the parse error when the question did not parse.
+    Drop(Option<DnsError>),
     Reply {
         id: u16,
         rd: bool,
         cd: bool,
+        ad: bool,
         question: Option<Question>,
         rcode: Rcode,
     },
 }
 
+impl fmt::Display for DecodeQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Drop(None) => write!(f, "query dropped"),
+            Self::Drop(Some(_)) => write!(f, "malformed query dropped"),
+            Self::Reply { id, rcode, .. } => {
+                write!(f, "query {} answered with {:?}", id, rcode)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeQueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Drop(Some(err)) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Shape of the payload labels in a tunnel query name.
+///
+/// Restrictive resolvers may reject long labels or long names; a smaller config trades
+/// payload per query for compatibility.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QnameConfig {
+    /// Longest payload label, at most 63.
+    pub max_label_len: u8,
+    /// Most payload labels before the domain.
+    pub max_labels: u8,
+    /// Longest whole query name, at most 253; lower values split the same payload across
+    /// more, shorter queries.
+    pub max_name_len: u8,
+}
+
+impl Default for QnameConfig {
+    fn default() -> Self {
+        Self {
+            max_label_len: crate::dots::DEFAULT_LABEL_LEN as u8,
+            max_labels: u8::MAX,
+            max_name_len: crate::name::MAX_DNS_NAME_LEN as u8,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct QueryParams<'a> {
     pub id: u16,
@@ -73,6 +137,7 @@ pub struct QueryParams<'a> {
     pub cd: bool,
     pub qdcount: u16,
     pub is_query: bool,
+    pub edns: EdnsOptions,
 }
 
 #[derive(Debug, Clone)]
@@ -80,20 +145,47 @@ pub struct ResponseParams<'a> {
     pub id: u16,
     pub rd: bool,
     pub cd: bool,
+    /// Sets AD in the answer; only pass it on for an asker that set AD itself.
+    pub ad: bool,
     pub question: &'a Question,
     pub payload: Option<&'a [u8]>,
     pub rcode: Option<Rcode>,
+    /// Alphabet for name-shaped answers (CNAME, NULL); other record types ignore it.
+    pub encoding: Encoding,
+    /// TTL written into every answer record.
+    pub ttl: u32,
+    /// Writes answer owner names as pointers to earlier names; off spells every name in full,
+    /// which is easier to follow in a packet capture.
+    pub compress_names: bool,
+    pub edns: EdnsOptions,
 }
 
+/// A DNS message could not be built or parsed.
+///
+/// `Display` shows only this layer's message; the underlying cause, if any, is reachable
+/// through [`std::error::Error::source`].
 #[derive(Debug, Clone)]
 pub struct DnsError {
     message: String,
+    source: Option<Arc<dyn std::error::Error + Send + Sync>>,
 }
 
 impl DnsError {
     pub fn new(message: impl Into<String>) -> Self {
         Self {
             message: message.into(),
+            source: None,
+        }
+    }
+
+    /// An error caused by `source`, which stays reachable through `Error::source`.
+    pub fn with_source(
+        message: impl Into<String>,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        Self {
+            message: message.into(),
+            source: Some(Arc::from(source.into())),
         }
     }
 }
@@ -104,4 +196,10 @@ impl fmt::Display for DnsError {
     }
 }
 
-impl std::error::Error for DnsError {}
+impl std::error::Error for DnsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|err| err as &(dyn std::error::Error + 'static))
+    }
+}
@@ -5,10 +5,14 @@ use crate::types::{DecodeQueryError, DnsError, Question, Rcode};
 pub(crate) struct Header {
     pub(crate) id: u16,
     pub(crate) is_response: bool,
+    pub(crate) opcode: u8,
     pub(crate) rd: bool,
     pub(crate) cd: bool,
+    pub(crate) ad: bool,
     pub(crate) qdcount: u16,
     pub(crate) ancount: u16,
+    pub(crate) nscount: u16,
+    pub(crate) arcount: u16,
     pub(crate) rcode: Option<Rcode>,
     pub(crate) offset: usize,
 }
@@ -21,21 +25,27 @@ pub(crate) fn parse_header(packet: &[u8]) -> Option<Header> {
     let flags = read_u16(packet, 2)?;
     let qdcount = read_u16(packet, 4)?;
     let ancount = read_u16(packet, 6)?;
-    let _nscount = read_u16(packet, 8)?;
-    let _arcount = read_u16(packet, 10)?;
+    let nscount = read_u16(packet, 8)?;
+    let arcount = read_u16(packet, 10)?;
 
     let is_response = flags & 0x8000 != 0;
+    let opcode = ((flags >> 11) & 0x000f) as u8;
     let rd = flags & 0x0100 != 0;
     let cd = flags & 0x0010 != 0;
+    let ad = flags & 0x0020 != 0;
     let rcode = Rcode::from_u8((flags & 0x000f) as u8);
 
     Some(Header {
         id,
         is_response,
+        opcode,
         rd,
         cd,
+        ad,
         qdcount,
         ancount,
+        nscount,
+        arcount,
         rcode,
         offset: 12,
     })
@@ -44,7 +54,7 @@ pub(crate) fn parse_header(packet: &[u8]) -> Option<Header> {
 #[derive(Debug)]
 enum ParseError {
     NoQuestion,
-    Malformed,
+    Malformed(DnsError),
 }
 
 fn parse_first_question(
@@ -55,7 +65,7 @@ fn parse_first_question(
     if qdcount == 0 {
         return Err(ParseError::NoQuestion);
     }
-    let (question, _) = parse_question(packet, offset).map_err(|_| ParseError::Malformed)?;
+    let (question, _) = parse_question(packet, offset).map_err(ParseError::Malformed)?;
     Ok(Some(question))
 }
 
@@ -67,12 +77,13 @@ pub(crate) fn parse_question_for_reply(
     match parse_first_question(packet, qdcount, offset) {
         Ok(question) => Ok(question),
         Err(ParseError::NoQuestion) => Ok(None),
-        Err(ParseError::Malformed) => Err(DecodeQueryError::Drop),
+        Err(ParseError::Malformed(err)) => Err(DecodeQueryError::Drop(Some(err))),
     }
 }
 
 pub(crate) fn parse_question(packet: &[u8], offset: usize) -> Result<(Question, usize), DnsError> {
-    let (name, mut offset) = parse_name(packet, offset).map_err(|_| DnsError::new("bad name"))?;
+    let (name, mut offset) =
+        parse_name(packet, offset).map_err(|err| DnsError::with_source("bad name", err))?;
     if offset + 4 > packet.len() {
         return Err(DnsError::new("truncated question"));
     }
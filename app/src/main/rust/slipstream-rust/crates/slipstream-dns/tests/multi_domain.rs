@@ -1,12 +1,18 @@
 use slipstream_dns::{
-    build_qname, decode_query_with_domains, encode_query, DecodeQueryError, QueryParams, Rcode,
-    CLASS_IN, RR_TXT,
+    build_qname, decode_query_with_domains, encode_query, DecodeQueryError, EdnsOptions, Encoding,
+    QnameConfig, QueryParams, Rcode, CLASS_IN, RR_TXT,
 };
 
 #[test]
 fn decode_query_with_domains_accepts_any_match() {
     let payload = vec![1u8, 2, 3];
-    let qname = build_qname(&payload, "example.com").expect("build qname");
+    let qname = build_qname(
+        &payload,
+        "example.com",
+        Encoding::Base32,
+        QnameConfig::default(),
+    )
+    .expect("build qname");
     let query = encode_query(&QueryParams {
         id: 42,
         qname: &qname,
@@ -16,18 +22,30 @@ fn decode_query_with_domains_accepts_any_match() {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns: EdnsOptions::default(),
     })
     .expect("encode query");
 
-    let decoded = decode_query_with_domains(&query, &["alt.example.com", "example.com"])
-        .expect("decode query");
+    let decoded = decode_query_with_domains(
+        &query,
+        &["alt.example.com", "example.com"],
+        Encoding::Base32,
+    )
+    .expect("decode query");
     assert_eq!(decoded.payload, payload);
+    assert_eq!(decoded.domain_index, 1);
 }
 
 #[test]
 fn decode_query_with_domains_prefers_longest_suffix() {
     let payload = vec![9u8, 8, 7, 6, 5];
-    let qname = build_qname(&payload, "tunnel.example.com").expect("build qname");
+    let qname = build_qname(
+        &payload,
+        "tunnel.example.com",
+        Encoding::Base32,
+        QnameConfig::default(),
+    )
+    .expect("build qname");
     let query = encode_query(&QueryParams {
         id: 7,
         qname: &qname,
@@ -37,18 +55,30 @@ fn decode_query_with_domains_prefers_longest_suffix() {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns: EdnsOptions::default(),
     })
     .expect("encode query");
 
-    let decoded = decode_query_with_domains(&query, &["example.com", "tunnel.example.com"])
-        .expect("decode query");
+    let decoded = decode_query_with_domains(
+        &query,
+        &["example.com", "tunnel.example.com"],
+        Encoding::Base32,
+    )
+    .expect("decode query");
     assert_eq!(decoded.payload, payload);
+    assert_eq!(decoded.domain_index, 1);
 }
 
 #[test]
 fn decode_query_with_domains_rejects_unknown_domain() {
     let payload = vec![1u8, 2, 3];
-    let qname = build_qname(&payload, "example.com").expect("build qname");
+    let qname = build_qname(
+        &payload,
+        "example.com",
+        Encoding::Base32,
+        QnameConfig::default(),
+    )
+    .expect("build qname");
     let query = encode_query(&QueryParams {
         id: 99,
         qname: &qname,
@@ -58,10 +88,11 @@ fn decode_query_with_domains_rejects_unknown_domain() {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns: EdnsOptions::default(),
     })
     .expect("encode query");
 
-    match decode_query_with_domains(&query, &["other.com"]) {
+    match decode_query_with_domains(&query, &["other.com"], Encoding::Base32) {
         Err(DecodeQueryError::Reply { .. }) => {}
         other => panic!("expected reply error, got {:?}", other),
     }
@@ -79,13 +110,109 @@ fn decode_query_with_domains_rejects_empty_subdomain_on_overlap() {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns: EdnsOptions::default(),
     })
     .expect("encode query");
 
-    match decode_query_with_domains(&query, &["aa.example.com", "example.com"]) {
+    match decode_query_with_domains(&query, &["aa.example.com", "example.com"], Encoding::Base32) {
         Err(DecodeQueryError::Reply { rcode, .. }) => {
             assert_eq!(rcode, Rcode::NameError);
         }
         other => panic!("expected name error, got {:?}", other),
     }
 }
+
+fn query_for(payload: &[u8], domain: &str) -> Vec<u8> {
+    let qname = build_qname(payload, domain, Encoding::Base32, QnameConfig::default())
+        .expect("build qname");
+    encode_query(&QueryParams {
+        id: 5,
+        qname: &qname,
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+        edns: EdnsOptions::default(),
+    })
+    .expect("encode query")
+}
+
+#[test]
+fn decode_query_with_domains_matches_one_wildcard_label() {
+    let payload = vec![4u8, 5, 6];
+    let domains = ["other.com", "*.example.com"];
+    let decoded = decode_query_with_domains(
+        &query_for(&payload, "Tenant.EXAMPLE.com"),
+        &domains,
+        Encoding::Base32,
+    )
+    .expect("decode query");
+    assert_eq!(decoded.payload, payload);
+    assert_eq!(decoded.domain_index, 1);
+
+    // Each wildcard covers one label, so the deeper one matches the longer name.
+    let decoded = decode_query_with_domains(
+        &query_for(&payload, "a.b.example.com"),
+        &["*.example.com", "*.b.example.com"],
+        Encoding::Base32,
+    )
+    .expect("decode query");
+    assert_eq!(decoded.payload, payload);
+    assert_eq!(decoded.domain_index, 1);
+}
+
+#[test]
+fn decode_query_with_domains_wildcard_skips_the_apex_and_lookalikes() {
+    let payload = vec![4u8, 5, 6];
+    for domain in ["example.com", "badexample.com", "tenant.example.org"] {
+        match decode_query_with_domains(
+            &query_for(&payload, domain),
+            &["*.example.com"],
+            Encoding::Base32,
+        ) {
+            Err(DecodeQueryError::Reply { rcode, .. }) => assert_eq!(rcode, Rcode::NameError),
+            other => panic!("{}: expected name error, got {:?}", domain, other),
+        }
+    }
+
+    // A query naming just the wildcard's label carries no payload.
+    let query = encode_query(&QueryParams {
+        id: 6,
+        qname: "tenant.example.com.",
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+        edns: EdnsOptions::default(),
+    })
+    .expect("encode query");
+    match decode_query_with_domains(&query, &["*.example.com"], Encoding::Base32) {
+        Err(DecodeQueryError::Reply { rcode, .. }) => assert_eq!(rcode, Rcode::NameError),
+        other => panic!("expected name error, got {:?}", other),
+    }
+}
+
+#[test]
+fn decode_query_with_domains_prefers_exact_over_wildcard_of_same_length() {
+    let payload = vec![1u8, 1, 2, 3, 5];
+    let query = query_for(&payload, "tunnel.example.com");
+    for (domains, index) in [
+        (["*.example.com", "tunnel.example.com"], 1),
+        (["tunnel.example.com", "*.example.com"], 0),
+    ] {
+        let decoded =
+            decode_query_with_domains(&query, &domains, Encoding::Base32).expect("decode query");
+        assert_eq!(decoded.payload, payload);
+        assert_eq!(decoded.domain_index, index);
+    }
+    // Against the bare apex, the wildcard is the longer match.
+    let decoded =
+        decode_query_with_domains(&query, &["example.com", "*.example.com"], Encoding::Base32)
+            .expect("decode query");
+    assert_eq!(decoded.payload, payload);
+    assert_eq!(decoded.domain_index, 1);
+}
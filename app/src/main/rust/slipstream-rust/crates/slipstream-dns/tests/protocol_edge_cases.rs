@@ -0,0 +1,130 @@
+use slipstream_dns::{
+    build_qname, decode_query, decode_response, encode_query, encode_response, DecodeQueryError,
+    EdnsOptions, Encoding, QnameConfig, QueryParams, Question, Rcode, ResponseParams, CLASS_IN,
+    RR_TXT,
+};
+
+const DOMAIN: &str = "example.com";
+
+fn tunnel_query(id: u16) -> Vec<u8> {
+    let qname = build_qname(&[1, 2, 3], DOMAIN, Encoding::Base32, QnameConfig::default())
+        .expect("build qname");
+    encode_query(&QueryParams {
+        id,
+        qname: &qname,
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+        rd: true,
+        cd: false,
+        qdcount: 1,
+        is_query: true,
+        edns: EdnsOptions::default(),
+    })
+    .expect("encode query")
+}
+
+fn response_params<'a>(question: &'a Question, payload: &'a [u8]) -> ResponseParams<'a> {
+    ResponseParams {
+        id: 9,
+        rd: true,
+        cd: false,
+        ad: false,
+        question,
+        payload: Some(payload),
+        rcode: None,
+        encoding: Encoding::Base32,
+        ttl: 0,
+        compress_names: true,
+        edns: EdnsOptions::default(),
+    }
+}
+
+#[test]
+fn truncated_question_is_dropped() {
+    let query = tunnel_query(1);
+    // Keep the header and the first few bytes of the question name.
+    let truncated = &query[..16];
+    assert!(matches!(
+        decode_query(truncated, DOMAIN, Encoding::Base32),
+        Err(DecodeQueryError::Drop(Some(_)))
+    ));
+}
+
+#[test]
+fn non_standard_opcode_gets_format_error() {
+    let mut query = tunnel_query(2);
+    // OPCODE 2 (STATUS) in bits 11-14 of the flags.
+    query[2] |= 2 << 3;
+    match decode_query(&query, DOMAIN, Encoding::Base32) {
+        Err(DecodeQueryError::Reply {
+            id,
+            rcode,
+            question,
+            ..
+        }) => {
+            assert_eq!(id, 2);
+            assert_eq!(rcode, Rcode::FormatError);
+            assert!(question.is_some());
+        }
+        other => panic!("expected FormatError reply, got {:?}", other),
+    }
+}
+
+#[test]
+fn qname_pointer_loop_is_dropped() {
+    let mut query = vec![
+        0x00, 0x03, // ID
+        0x01, 0x00, // flags: RD
+        0x00, 0x01, // QDCOUNT
+        0x00, 0x00, // ANCOUNT
+        0x00, 0x00, // NSCOUNT
+        0x00, 0x00, // ARCOUNT
+    ];
+    // One label, then a pointer back to the start of the name.
+    query.extend_from_slice(&[1, b'a', 0xC0, 12]);
+    query.extend_from_slice(&RR_TXT.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    match decode_query(&query, DOMAIN, Encoding::Base32) {
+        Err(err @ DecodeQueryError::Drop(Some(_))) => {
+            use std::error::Error;
+            let cause = err.source().expect("malformed query has a cause");
+            assert_eq!(cause.to_string(), "bad name");
+            let root = cause.source().expect("bad name has a cause");
+            assert_eq!(root.to_string(), "pointer loop");
+        }
+        other => panic!("expected drop, got {:?}", other),
+    }
+}
+
+#[test]
+fn response_with_two_txt_answers_is_rejected() {
+    let question = Question {
+        name: format!("abc.{}.", DOMAIN),
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+    };
+    let payload = b"tunnel";
+    let mut response = encode_response(&response_params(&question, payload)).expect("encode");
+    assert_eq!(decode_response(&response).as_deref(), Some(&payload[..]));
+
+    // Duplicate the answer record, which sits between the question and the OPT record.
+    let question_end = 12 + question.name.len() + 1 + 4;
+    let answer_len = 2 + 10 + 1 + payload.len();
+    let answer = response[question_end..question_end + answer_len].to_vec();
+    response.splice(question_end..question_end, answer);
+    response[6..8].copy_from_slice(&2u16.to_be_bytes());
+    assert_eq!(decode_response(&response), None);
+}
+
+#[test]
+fn payload_over_255_txt_strings_is_rejected() {
+    let question = Question {
+        name: format!("abc.{}.", DOMAIN),
+        qtype: RR_TXT,
+        qclass: CLASS_IN,
+    };
+    let fits = vec![0u8; 255 * 255];
+    assert!(encode_response(&response_params(&question, &fits)).is_ok());
+    let too_long = vec![0u8; 255 * 255 + 1];
+    assert!(encode_response(&response_params(&question, &too_long)).is_err());
+}
@@ -3,8 +3,8 @@ use std::path::Path;
 
 use serde::Deserialize;
 use slipstream_dns::{
-    decode_query, decode_response, encode_query, encode_response, DecodeQueryError, QueryParams,
-    Question, Rcode, ResponseParams, CLASS_IN, RR_A, RR_TXT,
+    decode_query, decode_response, encode_query, encode_response, DecodeQueryError, EdnsOptions,
+    Encoding, QueryParams, Question, Rcode, ResponseParams, CLASS_IN, RR_TXT,
 };
 
 #[derive(Debug, Deserialize)]
@@ -61,8 +61,8 @@ fn vectors_match_codec() {
 
         let is_raw = vector.mode == "raw_query_hex" || vector.expected_action == "drop";
         if is_raw {
-            match decode_query(&query_bytes, &vector.domain) {
-                Err(DecodeQueryError::Drop) => {}
+            match decode_query(&query_bytes, &vector.domain, Encoding::Base32) {
+                Err(DecodeQueryError::Drop(_)) => {}
                 other => panic!("{}: expected drop, got {:?}", vector.name, other),
             }
             continue;
@@ -78,6 +78,7 @@ fn vectors_match_codec() {
             cd: false,
             qdcount,
             is_query,
+            edns: EdnsOptions::default(),
         })
         .expect("encode query");
         assert_eq!(
@@ -86,7 +87,7 @@ fn vectors_match_codec() {
             vector.name
         );
 
-        match decode_query(&query_bytes, &vector.domain) {
+        match decode_query(&query_bytes, &vector.domain, Encoding::Base32) {
             Ok(decoded) => {
                 assert_eq!(decoded.id, vector.id, "{}", vector.name);
                 assert_eq!(decoded.question.name, vector.qname, "{}", vector.name);
@@ -103,7 +104,7 @@ fn vectors_match_codec() {
                     .unwrap_or(Rcode::NameError);
                 assert_eq!(rcode, expected, "{}", vector.name);
             }
-            Err(DecodeQueryError::Drop) => {
+            Err(DecodeQueryError::Drop(_)) => {
                 panic!("{}: unexpected drop", vector.name);
             }
         }
@@ -120,9 +121,14 @@ fn vectors_match_codec() {
                 id: vector.id,
                 rd: true,
                 cd: false,
+                ad: false,
                 question: &question,
                 payload: Some(&payload),
                 rcode: None,
+                encoding: Encoding::Base32,
+                ttl: 60,
+                compress_names: true,
+                edns: EdnsOptions::default(),
             })
             .expect("encode response_ok");
             let expected = decode_hex(&resp.packet_hex);
@@ -137,9 +143,14 @@ fn vectors_match_codec() {
                 id: vector.id,
                 rd: true,
                 cd: false,
+                ad: false,
                 question: &question,
                 payload: None,
                 rcode: None,
+                encoding: Encoding::Base32,
+                ttl: 60,
+                compress_names: true,
+                edns: EdnsOptions::default(),
             })
             .expect("encode response_no_data");
             let expected = decode_hex(&resp.packet_hex);
@@ -162,9 +173,14 @@ fn vectors_match_codec() {
                 id: vector.id,
                 rd: true,
                 cd: false,
+                ad: false,
                 question: &question,
                 payload: None,
                 rcode: Some(rcode),
+                encoding: Encoding::Base32,
+                ttl: 60,
+                compress_names: true,
+                edns: EdnsOptions::default(),
             })
             .expect("encode response_error");
             let expected = decode_hex(&resp.packet_hex);
@@ -183,9 +199,12 @@ fn vectors_match_codec() {
     }
 }
 
+// MX is not one of the tunnel record types, so the server must reject it.
+const RR_MX: u16 = 15;
+
 fn query_mode(mode: &str) -> (u16, u16, bool) {
     match mode {
-        "non_txt" => (RR_A, 1, true),
+        "non_txt" => (RR_MX, 1, true),
         "qdcount_zero" => (RR_TXT, 0, true),
         "not_query" => (RR_TXT, 1, false),
         _ => (RR_TXT, 1, true),
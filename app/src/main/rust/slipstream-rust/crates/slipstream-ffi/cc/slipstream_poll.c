@@ -75,3 +75,30 @@ uint64_t slipstream_get_max_streams_bidir_remote(picoquic_cnx_t *cnx) {
     /* STREAM_RANK_FROM_ID is 1-based and returns stream count, not a zero-based index. */
     return STREAM_RANK_FROM_ID(cnx->max_stream_id_bidir_remote);
 }
+
+void slipstream_enable_datagrams(picoquic_quic_t *quic, uint32_t max_frame_size) {
+    if (quic == NULL) {
+        return;
+    }
+    picoquic_tp_t tp = *picoquic_get_default_tp(quic);
+    tp.max_datagram_frame_size = max_frame_size;
+    picoquic_set_default_tp(quic, &tp);
+}
+
+size_t slipstream_max_datagram_payload(picoquic_cnx_t *cnx) {
+    /* Short header with the longest connection ID, packet number, AEAD tag and the
+     * DATAGRAM frame's type and length. */
+    const size_t overhead = 1 + PICOQUIC_CONNECTION_ID_MAX_SIZE + 4 + 16 + 1 + 2;
+    if (cnx == NULL || cnx->remote_parameters_received == 0 ||
+        cnx->remote_parameters.max_datagram_frame_size == 0 || cnx->path[0] == NULL) {
+        return 0;
+    }
+    size_t space = cnx->path[0]->send_mtu > overhead ? cnx->path[0]->send_mtu - overhead : 0;
+    if (space > cnx->remote_parameters.max_datagram_frame_size) {
+        space = cnx->remote_parameters.max_datagram_frame_size;
+    }
+    if (space > PICOQUIC_DATAGRAM_QUEUE_MAX_LENGTH) {
+        space = PICOQUIC_DATAGRAM_QUEUE_MAX_LENGTH;
+    }
+    return space;
+}
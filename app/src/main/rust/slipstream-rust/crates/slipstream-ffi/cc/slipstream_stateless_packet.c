@@ -112,3 +112,32 @@ int slipstream_take_stateless_packet_for_cid(picoquic_quic_t* quic,
 
     return 0;
 }
+
+void slipstream_set_reset_seed(picoquic_quic_t* quic, const uint8_t* seed) {
+    if (quic == NULL || seed == NULL) {
+        return;
+    }
+    memcpy(quic->reset_seed, seed, PICOQUIC_RESET_SECRET_SIZE);
+}
+
+int slipstream_packet_opens_connection(picoquic_quic_t* quic,
+                                       const uint8_t* packet,
+                                       size_t packet_len,
+                                       const struct sockaddr* addr_from) {
+    if (quic == NULL || packet == NULL || packet_len == 0 || addr_from == NULL) {
+        return 0;
+    }
+    picoquic_packet_header ph;
+    picoquic_cnx_t* cnx = NULL;
+    if (picoquic_parse_packet_header(quic, packet, packet_len, addr_from, &ph, &cnx, 1) != 0) {
+        return 0;
+    }
+    return ph.ptype == picoquic_packet_initial && cnx == NULL;
+}
+
+void slipstream_set_server_busy(picoquic_quic_t* quic, int busy) {
+    if (quic == NULL) {
+        return;
+    }
+    quic->server_busy = busy ? 1 : 0;
+}
@@ -1,7 +1,8 @@
 #[cfg(feature = "openssl-vendored")]
 #[allow(unused_imports)]
 use openssl_sys as _;
-use slipstream_core::HostPort;
+use slipstream_core::stream_priority::StreamPriorityMode;
+use slipstream_core::{AddressPolicy, HostPort};
 
 pub mod picoquic;
 pub mod runtime;
@@ -10,35 +11,229 @@ pub use picoquic::get_pacing_rate;
 pub use picoquic::get_rtt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 #[repr(i32)]
 pub enum ResolverMode {
     Recursive = 1,
     Authoritative = 2,
+    Doh = 3,
+    Dot = 4,
+}
+
+/// How the client spreads polls across resolvers and picks the primary path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum ResolverStrategy {
+    /// First resolver is the primary path; every path answers its own polls.
+    #[default]
+    Ordered,
+    /// Rotate the primary across reconnects and hand out polls in turn.
+    RoundRobin,
+    /// Prefer the lowest-RTT resolver and weight polls by inverse RTT.
+    LatencyWeighted,
+}
+
+/// What the client expects on connections accepted by its TCP listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum ListenerMode {
+    /// Every connection is tunneled to the server's fixed target as-is.
+    #[default]
+    Passthrough,
+    /// Every connection starts with an HTTP CONNECT request naming its own target.
+    ConnectProxy,
+    /// Every connection is a SOCKS5 client issuing a CONNECT request for its own target.
+    Socks5,
+}
+
+/// How the client checks the server's certificate against `ClientConfig::cert`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum CertMode {
+    /// The leaf must be one of the configured certificates or match one of the SPKI pins.
+    #[default]
+    LeafPin,
+    /// The chain must lead to a configured CA and the leaf must name the TLS server name.
+    ChainVerify,
+}
+
+/// Lowest TLS version the client asks for; QUIC always negotiates TLS 1.3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TlsVersion {
+    #[cfg_attr(feature = "serde", serde(rename = "1.2"))]
+    Tls12,
+    #[default]
+    #[cfg_attr(feature = "serde", serde(rename = "1.3"))]
+    Tls13,
+}
+
+/// TLS 1.3 cipher suites QUIC can protect packets with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TlsCipher {
+    #[cfg_attr(feature = "serde", serde(rename = "TLS_AES_128_GCM_SHA256"))]
+    Aes128GcmSha256,
+    #[cfg_attr(feature = "serde", serde(rename = "TLS_AES_256_GCM_SHA384"))]
+    Aes256GcmSha384,
+    #[cfg_attr(feature = "serde", serde(rename = "TLS_CHACHA20_POLY1305_SHA256"))]
+    Chacha20Poly1305Sha256,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ResolverSpec {
     pub resolver: HostPort,
     pub mode: ResolverMode,
+    /// Request path for DoH resolvers (for example `/dns-query`); unused for other modes.
+    pub doh_path: Option<String>,
+}
+
+/// Tunnel lifecycle reported to embedders through `ClientConfig::on_state_change`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientState {
+    /// A QUIC handshake is under way, the first or one after `Reconnecting`.
+    Connecting,
+    /// The handshake completed and streams can be opened.
+    Ready,
+    /// The connection dropped; the client is backing off before the next handshake.
+    Reconnecting,
+    /// `run_client` is returning; no further states follow.
+    Closed,
+}
+
+/// Embedder callback for `ClientState` changes. It runs on the client's event loop, so it
+/// should only record the state or hand it off.
+#[derive(Clone, Copy)]
+pub struct StateCallback<'a>(pub &'a (dyn Fn(ClientState) + Sync));
+
+impl StateCallback<'_> {
+    pub fn notify(&self, state: ClientState) {
+        (self.0)(state)
+    }
+}
+
+impl std::fmt::Debug for StateCallback<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StateCallback(..)")
+    }
 }
 
+/// Serializable for dumping a resolved config; it borrows its strings and resolver list, so
+/// config files deserialize into owned types and build this from them.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ClientConfig<'a> {
     pub tcp_listen_host: &'a str,
     pub tcp_listen_port: u16,
+    pub listener_mode: ListenerMode,
+    /// TCP listeners sharing the listen address through `SO_REUSEPORT`, each with its own
+    /// accept task.
+    pub listener_workers: usize,
+    /// Also listen for UDP on the listen address and carry each source's datagrams to the
+    /// server in QUIC DATAGRAM frames.
+    pub datagram_mode: bool,
+    /// Per-stream picoquic priorities; `None` keeps every stream on the shared default.
+    pub stream_priority_mode: Option<StreamPriorityMode>,
     pub resolvers: &'a [ResolverSpec],
+    /// Address families resolvers may use; system resolvers of another family are skipped.
+    pub address_policy: AddressPolicy,
+    pub resolver_strategy: ResolverStrategy,
+    pub resolver_health_window_ms: u64,
     pub domain: &'a str,
+    pub qtype: u16,
+    pub encoding: slipstream_core::dns::Encoding,
+    /// Probe each UDP resolver for the largest query name it carries before connecting.
+    pub probe_qname_capacity: bool,
+    /// Longest query name the client builds, at most 253; shorter names carry less payload.
+    pub max_qname_len: u8,
+    /// Tag query names with a sequence label and drop responses echoing an older one.
+    pub ignore_cached_responses: bool,
+    /// EDNS0 UDP payload size and DO bit advertised in every query.
+    pub edns: slipstream_core::dns::EdnsOptions,
+    /// Shared secret for the per-query HMAC label; `None` sends unauthenticated queries.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub auth_secret: Option<&'a [u8]>,
+    /// Bytes of the truncated HMAC carried in each query name.
+    pub auth_mac_len: usize,
     pub cert: Option<&'a str>,
+    pub cert_mode: CertMode,
+    pub resolver_cert: Option<&'a str>,
+    /// TLS server name sent in the ClientHello; `None` keeps the built-in default.
+    pub sni: Option<&'a str>,
+    /// Lowest TLS version accepted; anything below 1.3 is only informational for QUIC.
+    pub tls_min_version: TlsVersion,
+    /// Cipher suites offered in the handshake; empty keeps picoquic's full set.
+    pub tls_ciphers: &'a [TlsCipher],
     pub congestion_control: Option<&'a str>,
     pub gso: bool,
     pub keep_alive_interval: usize,
+    /// Rescale the keep-alive interval to twice the measured RTT once polls are answered.
+    pub adaptive_keepalive: bool,
+    /// Bounds in milliseconds for the `adaptive_keepalive` interval.
+    pub min_keep_alive_ms: u64,
+    pub max_keep_alive_ms: u64,
+    /// Unix-domain socket path serving a JSON metrics snapshot per connection.
+    pub metrics_socket: Option<&'a str>,
+    /// HTTP address answering `GET /health` with a probe through the tunnel; None disables it.
+    pub health_listen: Option<std::net::SocketAddr>,
+    /// How long a path probed after a local address change may take to validate before the
+    /// client reconnects instead; 0 disables address monitoring.
+    pub migration_timeout_ms: u64,
+    /// Also migrate to a fresh local port when the primary resolver stops answering, instead
+    /// of waiting for the connection to time out and reconnecting.
+    pub enable_migration: bool,
+    /// Upper bound on DNS queries per second; `None` sends as fast as QUIC allows.
+    pub max_query_rate: Option<u32>,
+    /// Floor the self-tuning query rate never halves below.
+    pub min_query_rate: u32,
+    /// Upper bound on QUIC bytes per second sent to resolvers; `None` leaves pacing to the
+    /// congestion controller.
+    pub max_pacing_rate: Option<u64>,
+    /// Queries kept in flight to each recursive, DoH, or DoT resolver while streams are open,
+    /// topped up with polls so the server always has queries to answer. Also sizes the window
+    /// slot carried in query IDs and the per-resolver reorder buffer; 0 disables all three.
+    pub pipeline_window: usize,
+    /// First delay before reconnecting after the QUIC connection closes; doubles per attempt.
+    pub reconnect_backoff_ms: u64,
+    /// Cap on the reconnect delay.
+    pub reconnect_max_ms: u64,
+    /// Consecutive reconnects without reaching a ready connection before giving up; 0 retries
+    /// forever.
+    pub max_reconnect_attempts: u32,
+    /// Seconds a connection may go without tunneling stream payload before the client closes it
+    /// and reconnects; 0 disables.
+    pub idle_timeout_seconds: u64,
+    /// File holding TLS session tickets across reconnects and restarts, for resumption and 0-RTT.
+    pub session_ticket_path: Option<&'a str>,
+    /// Called on each `ClientState` transition; the binary leaves it `None` and only logs.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_state_change: Option<StateCallback<'a>>,
     pub debug_poll: bool,
     pub debug_streams: bool,
     pub idle_poll_interval_ms: u64,
 }
 
 pub use runtime::{
-    abort_stream_bidi, configure_quic, configure_quic_with_custom, sockaddr_storage_to_socket_addr,
-    socket_addr_to_storage, take_crypto_errors, take_stateless_packet_for_cid,
-    write_stream_or_reset, QuicGuard, SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR,
+    abort_stream_bidi, configure_quic, configure_quic_with_custom, prepare_ticket_file,
+    queue_datagram, save_session_tickets, sockaddr_storage_to_socket_addr, socket_addr_to_storage,
+    take_crypto_error_details, take_crypto_errors, take_stateless_packet_for_cid,
+    write_stream_or_reset, CryptoError, PicoquicStreams, QuicGuard, StreamControl,
+    SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR, SLIPSTREAM_STREAM_LIMIT_ERROR,
 };
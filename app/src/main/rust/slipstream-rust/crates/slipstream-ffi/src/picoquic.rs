@@ -1,12 +1,16 @@
 #![allow(non_camel_case_types, non_snake_case, non_upper_case_globals)]
 
 use libc::{c_char, c_int, c_uint, c_void, size_t, sockaddr, sockaddr_storage};
+use std::time::Duration;
 
 pub const PICOQUIC_CONNECTION_ID_MAX_SIZE: usize = 20;
 pub const PICOQUIC_MAX_PACKET_SIZE: usize = 1536;
 pub const PICOQUIC_RESET_SECRET_SIZE: usize = 16;
 pub const PICOQUIC_PACKET_LOOP_RECV_MAX: usize = 10;
 pub const PICOQUIC_PACKET_LOOP_SEND_MAX: usize = 10;
+pub const PICOQUIC_AES_128_GCM_SHA256: c_int = 0x1301;
+pub const PICOQUIC_AES_256_GCM_SHA384: c_int = 0x1302;
+pub const PICOQUIC_CHACHA20_POLY1305_SHA256: c_int = 0x1303;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -200,6 +204,10 @@ extern "C" {
     ) -> *mut picoquic_quic_t;
 
     pub fn picoquic_free(quic: *mut picoquic_quic_t);
+    pub fn picoquic_save_session_tickets(
+        quic: *mut picoquic_quic_t,
+        ticket_store_filename: *const c_char,
+    ) -> c_int;
 
     pub fn picoquic_set_cookie_mode(quic: *mut picoquic_quic_t, cookie_mode: c_int);
     pub fn picoquic_set_default_priority(quic: *mut picoquic_quic_t, default_stream_priority: u8);
@@ -237,6 +245,8 @@ extern "C" {
         initial_mtu_ipv6: u32,
     );
     pub fn picoquic_set_key_log_file_from_env(quic: *mut picoquic_quic_t);
+    /// Restricts the handshake to one IANA cipher suite id, or 0 for all; -1 if unsupported.
+    pub fn picoquic_set_cipher_suite(quic: *mut picoquic_quic_t, cipher_suite_id: c_int) -> c_int;
     pub fn picoquic_enable_path_callbacks_default(quic: *mut picoquic_quic_t, are_enabled: c_int);
 
     pub fn picoquic_explain_crypto_error(
@@ -262,6 +272,20 @@ extern "C" {
         out_capacity: size_t,
         out_len: *mut size_t,
     ) -> c_int;
+    /// Replaces the seed stateless reset tokens are derived from; `seed` holds
+    /// `PICOQUIC_RESET_SECRET_SIZE` bytes.
+    pub fn slipstream_set_reset_seed(quic: *mut picoquic_quic_t, seed: *const u8);
+    /// Whether `packet`, arriving from `addr_from`, is an Initial no connection claims, i.e.
+    /// one that would open a new connection.
+    pub fn slipstream_packet_opens_connection(
+        quic: *mut picoquic_quic_t,
+        packet: *const u8,
+        packet_len: size_t,
+        addr_from: *const sockaddr,
+    ) -> c_int;
+    /// While set, Initials that would open a connection are answered with a SERVER_BUSY close
+    /// and no connection is created.
+    pub fn slipstream_set_server_busy(quic: *mut picoquic_quic_t, busy: c_int);
 
     pub static mut slipstream_server_cc_algorithm: *mut picoquic_congestion_algorithm_t;
     pub static mut slipstream_mixed_cc_algorithm: *mut picoquic_congestion_algorithm_t;
@@ -325,10 +349,22 @@ extern "C" {
     pub fn slipstream_set_default_path_mode(mode: c_int);
     pub fn slipstream_set_path_mode(cnx: *mut picoquic_cnx_t, path_id: c_int, mode: c_int);
     pub fn slipstream_set_path_ack_delay(cnx: *mut picoquic_cnx_t, path_id: c_int, disable: c_int);
+    /// Advertises `max_datagram_frame_size` on every connection of `quic` created afterwards.
+    pub fn slipstream_enable_datagrams(quic: *mut picoquic_quic_t, max_frame_size: u32);
+    /// Largest DATAGRAM frame payload that fits an empty packet on the default path; 0 until
+    /// the peer allows datagrams.
+    pub fn slipstream_max_datagram_payload(cnx: *mut picoquic_cnx_t) -> size_t;
+    pub fn picoquic_queue_datagram_frame(
+        cnx: *mut picoquic_cnx_t,
+        length: size_t,
+        bytes: *const u8,
+    ) -> c_int;
 
+    pub fn picoquic_current_number_connections(quic: *mut picoquic_quic_t) -> u32;
     pub fn picoquic_get_first_cnx(quic: *mut picoquic_quic_t) -> *mut picoquic_cnx_t;
     pub fn picoquic_get_next_cnx(cnx: *mut picoquic_cnx_t) -> *mut picoquic_cnx_t;
     pub fn picoquic_get_cnx_state(cnx: *mut picoquic_cnx_t) -> picoquic_state_enum;
+    pub fn picoquic_tls_is_psk_handshake(cnx: *mut picoquic_cnx_t) -> c_int;
     pub fn picoquic_get_close_reasons(
         cnx: *mut picoquic_cnx_t,
         local_reason: *mut u64,
@@ -422,6 +458,11 @@ extern "C" {
         is_active: c_int,
         v_stream_ctx: *mut c_void,
     ) -> c_int;
+    pub fn picoquic_set_stream_priority(
+        cnx: *mut picoquic_cnx_t,
+        stream_id: u64,
+        stream_priority: u8,
+    ) -> c_int;
 
     pub fn picoquic_probe_new_path_ex(
         cnx: *mut picoquic_cnx_t,
@@ -490,3 +531,80 @@ pub unsafe fn get_bytes_in_transit(cnx: *mut picoquic_cnx_t) -> u64 {
         quality.bytes_in_transit
     }
 }
+
+/// Quality of a path as picoquic reports it, with times as `Duration`s.
+///
+/// Rates are in bytes per second; packet counts and losses accumulate from the path's
+/// creation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PathQuality {
+    pub receive_rate_bytes_per_sec: u64,
+    pub pacing_rate_bytes_per_sec: u64,
+    pub cwin_bytes: u64,
+    pub bytes_in_transit: u64,
+    /// Smoothed RTT.
+    pub rtt: Duration,
+    /// Most recent RTT sample.
+    pub rtt_sample: Duration,
+    pub rtt_variance: Duration,
+    pub rtt_min: Duration,
+    pub rtt_max: Duration,
+    pub packets_sent: u64,
+    pub packets_lost: u64,
+    /// Losses detected by a timer rather than by later acknowledgements.
+    pub timer_losses: u64,
+    /// Packets declared lost that were acknowledged afterwards.
+    pub spurious_losses: u64,
+    pub max_spurious_rtt: Duration,
+    pub max_reorder_delay: Duration,
+    /// Largest reordering seen, in packets.
+    pub max_reorder_gap: u64,
+}
+
+impl PathQuality {
+    /// Fraction of sent packets declared lost, or 0 before anything was sent.
+    pub fn loss_rate(&self) -> f64 {
+        if self.packets_sent == 0 {
+            return 0.0;
+        }
+        self.packets_lost as f64 / self.packets_sent as f64
+    }
+}
+
+impl From<&picoquic_path_quality_t> for PathQuality {
+    fn from(quality: &picoquic_path_quality_t) -> Self {
+        Self {
+            receive_rate_bytes_per_sec: quality.receive_rate_estimate,
+            pacing_rate_bytes_per_sec: quality.pacing_rate,
+            cwin_bytes: quality.cwin,
+            bytes_in_transit: quality.bytes_in_transit,
+            rtt: Duration::from_micros(quality.rtt),
+            rtt_sample: Duration::from_micros(quality.rtt_sample),
+            rtt_variance: Duration::from_micros(quality.rtt_variant),
+            rtt_min: Duration::from_micros(quality.rtt_min),
+            rtt_max: Duration::from_micros(quality.rtt_max),
+            packets_sent: quality.sent,
+            packets_lost: quality.lost,
+            timer_losses: quality.timer_losses,
+            spurious_losses: quality.spurious_losses,
+            max_spurious_rtt: Duration::from_micros(quality.max_spurious_rtt),
+            max_reorder_delay: Duration::from_micros(quality.max_reorder_delay),
+            max_reorder_gap: quality.max_reorder_gap,
+        }
+    }
+}
+
+/// Quality of the default path of `cnx`, or `None` when there is no connection.
+///
+/// # Safety
+/// `cnx` must be null or point to a valid picoquic connection for the duration
+/// of the call.
+pub unsafe fn get_path_quality(cnx: *mut picoquic_cnx_t) -> Option<PathQuality> {
+    if cnx.is_null() {
+        return None;
+    }
+    let mut quality = picoquic_path_quality_t::default();
+    // SAFETY: caller guarantees cnx is valid; quality is a properly initialized out parameter.
+    picoquic_get_default_path_quality(cnx, &mut quality as *mut _);
+    Some(PathQuality::from(&quality))
+}
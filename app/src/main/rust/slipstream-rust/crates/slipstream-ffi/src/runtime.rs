@@ -1,21 +1,31 @@
 use crate::picoquic::{
     picoquic_clear_crypto_errors, picoquic_cnx_t, picoquic_congestion_algorithm_t,
-    picoquic_disable_port_blocking, picoquic_explain_crypto_error, picoquic_free, picoquic_quic_t,
-    picoquic_reset_stream, picoquic_set_cookie_mode, picoquic_set_default_congestion_algorithm,
-    picoquic_set_default_congestion_algorithm_by_name, picoquic_set_default_multipath_option,
-    picoquic_set_default_priority, picoquic_set_initial_send_mtu,
-    picoquic_set_key_log_file_from_env, picoquic_set_max_data_control, picoquic_set_mtu_max,
-    picoquic_set_preemptive_repeat_policy, picoquic_set_stream_data_consumption_mode,
-    picoquic_stop_sending, slipstream_take_stateless_packet_for_cid, PICOQUIC_MAX_PACKET_SIZE,
+    picoquic_disable_port_blocking, picoquic_explain_crypto_error, picoquic_free,
+    picoquic_queue_datagram_frame, picoquic_quic_t, picoquic_reset_stream,
+    picoquic_save_session_tickets, picoquic_set_cookie_mode,
+    picoquic_set_default_congestion_algorithm, picoquic_set_default_congestion_algorithm_by_name,
+    picoquic_set_default_multipath_option, picoquic_set_default_priority,
+    picoquic_set_initial_send_mtu, picoquic_set_key_log_file_from_env,
+    picoquic_set_max_data_control, picoquic_set_mtu_max, picoquic_set_preemptive_repeat_policy,
+    picoquic_set_stream_data_consumption_mode, picoquic_set_stream_priority, picoquic_stop_sending,
+    slipstream_max_datagram_payload, slipstream_take_stateless_packet_for_cid,
+    PICOQUIC_MAX_PACKET_SIZE,
 };
 use libc::{c_char, c_int, c_ulong, size_t, sockaddr_storage};
+use slipstream_core::datagram::encode_datagram;
+use slipstream_core::fs::open_new_with_mode;
+use slipstream_core::stream_priority::DEFAULT_STREAM_PRIORITY;
 use slipstream_core::tcp::stream_write_buffer_bytes;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
+use std::fmt;
 use std::io::Write;
 use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6, TcpStream};
+use std::path::Path;
 
 pub const SLIPSTREAM_INTERNAL_ERROR: u64 = 0x101;
 pub const SLIPSTREAM_FILE_CANCEL_ERROR: u64 = 0x105;
+/// Server reset for streams opened past `--max-streams-per-connection`.
+pub const SLIPSTREAM_STREAM_LIMIT_ERROR: u64 = 0x106;
 
 extern "C" {
     fn ERR_error_string_n(e: c_ulong, buf: *mut c_char, len: size_t);
@@ -69,7 +79,7 @@ pub unsafe fn configure_quic_with_custom(
 /// `quic` must be a valid picoquic context and `mtu` must be non-zero.
 unsafe fn configure_quic_common(quic: *mut picoquic_quic_t, mtu: u32) {
     picoquic_set_cookie_mode(quic, 0);
-    picoquic_set_default_priority(quic, 2);
+    picoquic_set_default_priority(quic, DEFAULT_STREAM_PRIORITY);
     picoquic_set_default_multipath_option(quic, 1);
     picoquic_set_preemptive_repeat_policy(quic, 1);
     picoquic_disable_port_blocking(quic, 1);
@@ -80,7 +90,74 @@ unsafe fn configure_quic_common(quic: *mut picoquic_quic_t, mtu: u32) {
     picoquic_set_key_log_file_from_env(quic);
 }
 
-pub fn take_crypto_errors() -> Vec<String> {
+/// Makes sure the session ticket file at `path` is private before picoquic touches it.
+///
+/// picoquic rewrites the file with `fopen`, which keeps the mode of an existing file, so the
+/// file is created with mode 0600 when missing and tightened to 0600 otherwise. The returned
+/// string must outlive every QUIC context created with it; picoquic keeps the pointer.
+pub fn prepare_ticket_file(path: &str) -> Result<CString, String> {
+    let c_path = CString::new(path)
+        .map_err(|_| format!("Session ticket path contains a NUL byte: {}", path))?;
+    match open_new_with_mode(Path::new(path), 0o600) {
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).map_err(
+                    |err| format!("Failed to restrict session ticket file {}: {}", path, err),
+                )?;
+            }
+        }
+        Err(err) => {
+            return Err(format!(
+                "Failed to create session ticket file {}: {}",
+                path, err
+            ))
+        }
+    }
+    Ok(c_path)
+}
+
+/// Writes the tickets `quic` holds to `path`, replacing the file's contents.
+///
+/// # Safety
+/// `quic` must be a valid picoquic context.
+pub unsafe fn save_session_tickets(quic: *mut picoquic_quic_t, path: &CStr) -> Result<(), c_int> {
+    match picoquic_save_session_tickets(quic, path.as_ptr()) {
+        0 => Ok(()),
+        ret => Err(ret),
+    }
+}
+
+/// An OpenSSL error picoquic recorded, with the source location that raised it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CryptoError {
+    pub code: i32,
+    pub file: String,
+    pub line: i32,
+    pub description: Option<String>,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.description {
+            Some(description) => write!(
+                f,
+                "crypto error {} ({}) at {}:{}",
+                self.code, description, self.file, self.line
+            ),
+            None => write!(
+                f,
+                "crypto error {} at {}:{}",
+                self.code, self.file, self.line
+            ),
+        }
+    }
+}
+
+/// Drains and clears picoquic's recorded crypto errors.
+pub fn take_crypto_error_details() -> Vec<CryptoError> {
     let mut errors = Vec::new();
     loop {
         let mut file: *const c_char = std::ptr::null();
@@ -109,14 +186,12 @@ pub fn take_crypto_errors() -> Vec<String> {
                 .to_string_lossy()
                 .into_owned()
         };
-        if let Some(description) = description {
-            errors.push(format!(
-                "crypto error {} ({}) at {}:{}",
-                code, description, file, line
-            ));
-        } else {
-            errors.push(format!("crypto error {} at {}:{}", code, file, line));
-        }
+        errors.push(CryptoError {
+            code,
+            file,
+            line,
+            description,
+        });
     }
     unsafe {
         picoquic_clear_crypto_errors();
@@ -124,6 +199,13 @@ pub fn take_crypto_errors() -> Vec<String> {
     errors
 }
 
+pub fn take_crypto_errors() -> Vec<String> {
+    take_crypto_error_details()
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
 pub fn socket_addr_to_storage(addr: SocketAddr) -> sockaddr_storage {
     match addr {
         SocketAddr::V4(addr) => {
@@ -262,3 +344,56 @@ pub unsafe fn abort_stream_bidi(cnx: *mut picoquic_cnx_t, stream_id: u64, app_er
     let _ = picoquic_stop_sending(cnx, stream_id, app_error);
     let _ = picoquic_reset_stream(cnx, stream_id, app_error);
 }
+
+/// Stream operations the stream handlers issue on their connections, so tests can run the
+/// handlers without a live picoquic connection.
+pub trait StreamControl {
+    /// Stops both directions of `stream_id` with `app_error`.
+    ///
+    /// # Safety
+    /// Caller must ensure `cnx` points to a valid picoquic connection.
+    unsafe fn abort_stream(&mut self, cnx: *mut picoquic_cnx_t, stream_id: u64, app_error: u64);
+
+    /// Sets the picoquic priority of `stream_id`, returning picoquic's status.
+    ///
+    /// # Safety
+    /// Caller must ensure `cnx` points to a valid picoquic connection.
+    unsafe fn set_stream_priority(
+        &mut self,
+        cnx: *mut picoquic_cnx_t,
+        stream_id: u64,
+        priority: u8,
+    ) -> c_int;
+}
+
+/// Issues stream operations on picoquic.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PicoquicStreams;
+
+impl StreamControl for PicoquicStreams {
+    unsafe fn abort_stream(&mut self, cnx: *mut picoquic_cnx_t, stream_id: u64, app_error: u64) {
+        abort_stream_bidi(cnx, stream_id, app_error);
+    }
+
+    unsafe fn set_stream_priority(
+        &mut self,
+        cnx: *mut picoquic_cnx_t,
+        stream_id: u64,
+        priority: u8,
+    ) -> c_int {
+        picoquic_set_stream_priority(cnx, stream_id, priority)
+    }
+}
+
+/// Queues `payload` for `flow_id` as one DATAGRAM frame, returning `false` when the peer does
+/// not take datagrams or the frame would not fit an empty packet; the payload is dropped then.
+///
+/// # Safety
+/// Caller must ensure `cnx` points to a valid picoquic connection.
+pub unsafe fn queue_datagram(cnx: *mut picoquic_cnx_t, flow_id: u64, payload: &[u8]) -> bool {
+    let frame = encode_datagram(flow_id, payload);
+    if frame.len() > slipstream_max_datagram_payload(cnx) {
+        return false;
+    }
+    picoquic_queue_datagram_frame(cnx, frame.len(), frame.as_ptr()) == 0
+}
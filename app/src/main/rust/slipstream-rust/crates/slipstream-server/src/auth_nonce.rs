@@ -0,0 +1,82 @@
+use slipstream_dns::hmac::{nonce_issued_at, MAX_NONCE_AGE_SECS, NONCE_LEN};
+use std::collections::BTreeSet;
+
+/// Nonces remembered at most; past it the oldest are forgotten first, as they are the next
+/// to fall out of the window anyway.
+const MAX_TRACKED_NONCES: usize = 1 << 18;
+
+/// What `AuthNonces::check` made of an authenticated query's nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NonceCheck {
+    /// First sighting, within the window; the query goes on to QUIC.
+    Fresh,
+    /// Issued too far from the server's clock to be checked for replay; answer REFUSED.
+    Stale,
+    /// Already seen in the window: a resolver retry or a replay, which QUIC has had once.
+    Replayed,
+}
+
+/// The auth nonces seen within `MAX_NONCE_AGE_SECS` of now, ordered by issue time.
+#[derive(Debug, Default)]
+pub(crate) struct AuthNonces {
+    seen: BTreeSet<(u32, [u8; NONCE_LEN])>,
+}
+
+impl AuthNonces {
+    /// Records `nonce` if it is fresh at `now_secs` (Unix time) and reports what it was.
+    pub(crate) fn check(&mut self, nonce: [u8; NONCE_LEN], now_secs: u32) -> NonceCheck {
+        let issued_at = nonce_issued_at(&nonce);
+        if issued_at.abs_diff(now_secs) > MAX_NONCE_AGE_SECS {
+            return NonceCheck::Stale;
+        }
+        let cutoff = now_secs.saturating_sub(MAX_NONCE_AGE_SECS);
+        while self
+            .seen
+            .first()
+            .is_some_and(|(oldest, _)| *oldest < cutoff)
+        {
+            self.seen.pop_first();
+        }
+        if !self.seen.insert((issued_at, nonce)) {
+            return NonceCheck::Replayed;
+        }
+        if self.seen.len() > MAX_TRACKED_NONCES {
+            self.seen.pop_first();
+        }
+        NonceCheck::Fresh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nonce(issued_at: u32, tail: u32) -> [u8; NONCE_LEN] {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..4].copy_from_slice(&issued_at.to_be_bytes());
+        nonce[4..].copy_from_slice(&tail.to_be_bytes());
+        nonce
+    }
+
+    #[test]
+    fn refuses_stale_nonces_and_catches_replays_within_the_window() {
+        let now = 1_700_000_000;
+        let mut nonces = AuthNonces::default();
+        assert_eq!(nonces.check(nonce(now, 1), now), NonceCheck::Fresh);
+        assert_eq!(nonces.check(nonce(now, 1), now + 1), NonceCheck::Replayed);
+        assert_eq!(nonces.check(nonce(now, 2), now + 1), NonceCheck::Fresh);
+        // Clocks a little ahead of the server are fine.
+        assert_eq!(nonces.check(nonce(now + 30, 1), now), NonceCheck::Fresh);
+
+        let old = now - MAX_NONCE_AGE_SECS - 1;
+        assert_eq!(nonces.check(nonce(old, 1), now), NonceCheck::Stale);
+        let ahead = now + MAX_NONCE_AGE_SECS + 1;
+        assert_eq!(nonces.check(nonce(ahead, 1), now), NonceCheck::Stale);
+
+        // Once the window moves past a nonce it is forgotten, and refused as stale instead.
+        let later = now + MAX_NONCE_AGE_SECS + 1;
+        assert_eq!(nonces.check(nonce(later, 1), later), NonceCheck::Fresh);
+        assert!(!nonces.seen.contains(&(now, nonce(now, 1))));
+        assert_eq!(nonces.check(nonce(now, 1), later), NonceCheck::Stale);
+    }
+}
@@ -0,0 +1,379 @@
+use crate::config::{check_cert_key, load_reset_seed, load_ticket_key};
+use crate::connection_limit::ConnectionOverflowMode;
+use crate::server::{ServerConfig, TargetMode};
+use crate::source_filter::Cidr;
+use crate::target::TargetEndpoint;
+use crate::udp_fallback::FallbackMatch;
+use slipstream_core::stream_priority::StreamPriorityMode;
+use slipstream_core::{resolve_host_port, AddressPolicy, HostPort};
+use slipstream_dns::hmac;
+use std::fmt::Write;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+
+/// Validates a resolved config for `--check-config` and renders it as `key: value` lines.
+///
+/// Runs the address and cert/key checks `run_server` would, but never binds a socket,
+/// generates a certificate, or writes a reset seed.
+pub(crate) fn check_config(config: &ServerConfig) -> Result<String, String> {
+    let mut dns_listen = Vec::with_capacity(config.dns_listen_hosts.len());
+    for host in &config.dns_listen_hosts {
+        dns_listen
+            .push(resolve_listen(host, config.dns_listen_port, config.address_policy)?.to_string());
+    }
+    let target = describe_target(&config.target_address)?;
+    let mut domain_targets = Vec::with_capacity(config.domain_targets.len());
+    for route in &config.domain_targets {
+        let resolved = describe_target(&route.address)?;
+        domain_targets.push(format!("{}={}", route.domain, resolved));
+    }
+    let mut fallbacks = Vec::with_capacity(config.fallbacks.len());
+    for fallback in &config.fallbacks {
+        let resolved = describe(&fallback.address, resolve(&fallback.address)?);
+        fallbacks.push(match fallback.matcher {
+            FallbackMatch::Any => resolved,
+            ref matcher => format!("{}={}", matcher, resolved),
+        });
+    }
+    let cert_key_present = check_cert_key(Path::new(&config.cert), Path::new(&config.key))?;
+    let reset_seed_present = match &config.reset_seed_path {
+        Some(path) => match load_reset_seed(Path::new(path)) {
+            Ok(_) => Some(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Some(false),
+            Err(err) => return Err(format!("Invalid reset seed at {}: {}", path, err)),
+        },
+        None => None,
+    };
+
+    if let Some(path) = &config.auth_secret_path {
+        hmac::load_secret(path)
+            .map_err(|err| format!("Cannot read auth secret {}: {}", path, err))?;
+    }
+
+    let mut summary = String::new();
+    let _ = writeln!(summary, "dns_listen: {}", dns_listen.join(", "));
+    let _ = writeln!(summary, "address_family: {}", config.address_policy.label());
+    let _ = writeln!(summary, "target_address: {}", target);
+    if fallbacks.is_empty() {
+        let _ = writeln!(summary, "fallback: disabled");
+    } else {
+        let _ = writeln!(summary, "fallback: {}", fallbacks.join(", "));
+    }
+    let generated = if cert_key_present {
+        ""
+    } else {
+        " (generated on start)"
+    };
+    let _ = writeln!(summary, "cert: {}{}", config.cert, generated);
+    let _ = writeln!(summary, "key: {}{}", config.key, generated);
+    if !cert_key_present {
+        let _ = write!(
+            summary,
+            "cert_subject: {} CN={}",
+            config.cert_identity.key_type.label(),
+            config.cert_identity.common_name
+        );
+        for san in &config.cert_identity.sans {
+            let _ = write!(summary, " {}", san);
+        }
+        summary.push('\n');
+    }
+    match (&config.reset_seed_path, reset_seed_present) {
+        (Some(path), Some(true)) => {
+            let _ = writeln!(summary, "reset_seed: {}", path);
+        }
+        (Some(path), _) => {
+            let _ = writeln!(summary, "reset_seed: {} (created on start)", path);
+        }
+        (None, _) => {
+            let _ = writeln!(summary, "reset_seed: disabled");
+        }
+    }
+    if config.reset_seed_rotation_hours > 0 {
+        let _ = writeln!(
+            summary,
+            "reset_seed_rotation: every {}h",
+            config.reset_seed_rotation_hours
+        );
+    }
+    match &config.ticket_store_path {
+        Some(path) => match load_ticket_key(Path::new(path)) {
+            Ok(_) => {
+                let _ = writeln!(summary, "ticket_store: {}", path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let _ = writeln!(summary, "ticket_store: {} (created on start)", path);
+            }
+            Err(err) => return Err(format!("Invalid ticket key at {}: {}", path, err)),
+        },
+        None => {
+            let _ = writeln!(summary, "ticket_store: disabled");
+        }
+    }
+    let _ = writeln!(
+        summary,
+        "auth_secret: {}",
+        config.auth_secret_path.as_deref().unwrap_or("disabled")
+    );
+    let _ = writeln!(summary, "domains: {}", config.domains.join(", "));
+    if !domain_targets.is_empty() {
+        let _ = writeln!(summary, "domain_targets: {}", domain_targets.join(", "));
+    }
+    let _ = writeln!(summary, "encoding: {:?}", config.encoding);
+    let _ = writeln!(summary, "max_connections: {}", config.max_connections);
+    let overflow_mode = match config.connection_overflow_mode {
+        ConnectionOverflowMode::Drop => "drop",
+        ConnectionOverflowMode::Refuse => "refuse",
+        ConnectionOverflowMode::Queue => "queue",
+    };
+    let _ = writeln!(summary, "connection_overflow_mode: {}", overflow_mode);
+    let _ = writeln!(
+        summary,
+        "max_streams_per_connection: {}",
+        config.max_streams_per_connection
+    );
+    let _ = writeln!(
+        summary,
+        "idle_timeout_seconds: {}",
+        config.idle_timeout_seconds
+    );
+    let _ = writeln!(
+        summary,
+        "idle_drain_timeout_seconds: {}",
+        config.idle_drain_timeout_seconds
+    );
+    match config.metrics_listen {
+        Some(addr) => {
+            let _ = writeln!(summary, "metrics_listen: {}", addr);
+        }
+        None => {
+            let _ = writeln!(summary, "metrics_listen: disabled");
+        }
+    }
+    let _ = writeln!(
+        summary,
+        "connection_log_capacity: {}",
+        config.connection_log_capacity
+    );
+    let target_mode = match config.target_mode {
+        TargetMode::Fixed => "fixed",
+        TargetMode::ConnectProxy => "connect-proxy",
+    };
+    let _ = writeln!(summary, "target_mode: {}", target_mode);
+    let _ = writeln!(
+        summary,
+        "target_proxy_protocol: {}",
+        config.target_proxy_protocol
+    );
+    let _ = writeln!(summary, "datagram_mode: {}", config.datagram_mode);
+    let _ = writeln!(
+        summary,
+        "stream_priority_mode: {}",
+        config
+            .stream_priority_mode
+            .map_or("default", StreamPriorityMode::label)
+    );
+    let _ = writeln!(summary, "quic_mtu: {}", config.quic_mtu);
+    let _ = writeln!(summary, "response_ttl: {}", config.response_ttl);
+    match config.per_connection_rate_limit_kbps {
+        Some(kbps) => {
+            let _ = writeln!(summary, "per_connection_rate_limit_kbps: {}", kbps);
+        }
+        None => {
+            let _ = writeln!(summary, "per_connection_rate_limit_kbps: unlimited");
+        }
+    }
+    if config.target_write_timeout_ms > 0 {
+        let _ = writeln!(
+            summary,
+            "target_write_timeout_ms: {}",
+            config.target_write_timeout_ms
+        );
+    } else {
+        let _ = writeln!(summary, "target_write_timeout_ms: disabled");
+    }
+    if config.max_qps_per_source > 0 {
+        let _ = writeln!(summary, "max_qps_per_source: {}", config.max_qps_per_source);
+    } else {
+        let _ = writeln!(summary, "max_qps_per_source: unlimited");
+    }
+    if !config.source_filter.is_empty() {
+        let rules = |rules: &[Cidr]| {
+            rules
+                .iter()
+                .map(Cidr::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let _ = writeln!(
+            summary,
+            "allow_source: {}",
+            rules(config.source_filter.allow_rules())
+        );
+        let _ = writeln!(
+            summary,
+            "deny_source: {}",
+            rules(config.source_filter.deny_rules())
+        );
+    }
+    let _ = writeln!(
+        summary,
+        "congestion_control: {}",
+        config.congestion_control.as_deref().unwrap_or("slipstream")
+    );
+    let _ = writeln!(summary, "dnssec_passthrough: {}", config.dnssec_passthrough);
+    let _ = writeln!(summary, "compress_names: {}", config.compress_names);
+    let _ = writeln!(summary, "gso: {}", config.gso);
+    let _ = writeln!(summary, "recvmmsg: {}", config.recvmmsg);
+    Ok(summary)
+}
+
+/// Binds every DNS listen address and closes them again, for `--dry-run`.
+pub(crate) fn probe_listen(config: &ServerConfig) -> Result<(), String> {
+    for host in &config.dns_listen_hosts {
+        let addr = resolve_listen(host, config.dns_listen_port, config.address_policy)?;
+        UdpSocket::bind(addr)
+            .map(drop)
+            .map_err(|err| format!("Cannot bind DNS listen address {}: {}", addr, err))?;
+    }
+    Ok(())
+}
+
+fn resolve(address: &HostPort) -> Result<SocketAddr, String> {
+    resolve_host_port(address).map_err(|err| err.to_string())
+}
+
+fn resolve_listen(host: &str, port: u16, policy: AddressPolicy) -> Result<SocketAddr, String> {
+    (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.find(|addr| policy.allows(addr)))
+        .ok_or_else(|| format!("No addresses resolved for {}:{}", host, port))
+}
+
+/// Resolves a TCP target for display; a Unix socket is shown by path and not opened.
+fn describe_target(target: &TargetEndpoint) -> Result<String, String> {
+    match target {
+        TargetEndpoint::Tcp(address) => Ok(describe(address, resolve(address)?)),
+        unix => Ok(unix.to_string()),
+    }
+}
+
+fn describe(address: &HostPort, resolved: SocketAddr) -> String {
+    if address.host == resolved.ip().to_string() {
+        resolved.to_string()
+    } else {
+        format!("{}:{} ({})", address.host, address.port, resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CertIdentity;
+    use crate::source_filter::SourceFilter;
+    use slipstream_core::{parse_host_port, AddressKind};
+    use slipstream_dns::Encoding;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let suffix = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!(
+            "slipstream-check-{}-{}-{}",
+            name,
+            std::process::id(),
+            suffix
+        ));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    fn config(dir: &Path) -> ServerConfig {
+        ServerConfig {
+            dns_listen_hosts: vec!["127.0.0.1".to_string()],
+            dns_listen_port: 53,
+            address_policy: AddressPolicy::Any,
+            target_address: TargetEndpoint::Tcp(
+                parse_host_port("127.0.0.1:5201", 5201, AddressKind::Target).unwrap(),
+            ),
+            fallbacks: Vec::new(),
+            cert: dir.join("cert.pem").display().to_string(),
+            key: dir.join("key.pem").display().to_string(),
+            cert_identity: CertIdentity::default(),
+            reset_seed_path: None,
+            auth_secret_path: None,
+            reset_seed_rotation_hours: 0,
+            ticket_store_path: None,
+            domains: vec!["test.example.com".to_string()],
+            domain_targets: Vec::new(),
+            encoding: Encoding::Base32,
+            max_connections: 256,
+            connection_overflow_mode: ConnectionOverflowMode::Drop,
+            max_streams_per_connection: 256,
+            idle_timeout_seconds: 1200,
+            idle_drain_timeout_seconds: 30,
+            metrics_listen: None,
+            connection_log_capacity: 1000,
+            target_proxy_protocol: false,
+            target_mode: TargetMode::Fixed,
+            datagram_mode: false,
+            stream_priority_mode: None,
+            quic_mtu: 900,
+            response_ttl: 0,
+            per_connection_rate_limit_kbps: None,
+            target_write_timeout_ms: 0,
+            max_qps_per_source: 0,
+            source_filter: SourceFilter::default(),
+            congestion_control: None,
+            dnssec_passthrough: false,
+            compress_names: true,
+            gso: false,
+            recvmmsg: false,
+            debug_poll: false,
+            debug_streams: false,
+            debug_commands: false,
+        }
+    }
+
+    #[test]
+    fn summary_reports_missing_pair_without_creating_it() {
+        let dir = temp_dir("missing");
+        let config = config(&dir);
+        let summary = check_config(&config).expect("valid config");
+        assert!(summary.contains("dns_listen: 127.0.0.1:53\n"));
+        assert!(summary.contains("target_address: 127.0.0.1:5201\n"));
+        assert!(summary.contains("(generated on start)"));
+        assert!(!Path::new(&config.cert).exists());
+        let _ = fs::remove_dir(&dir);
+    }
+
+    #[test]
+    fn rejects_cert_without_key() {
+        let dir = temp_dir("half");
+        let config = config(&dir);
+        fs::write(&config.cert, b"cert").expect("write cert");
+        let err = check_config(&config).unwrap_err();
+        assert!(err.contains("Key file is missing"), "{}", err);
+        let _ = fs::remove_file(&config.cert);
+        let _ = fs::remove_dir(&dir);
+    }
+
+    #[test]
+    fn probe_reports_a_taken_listen_port() {
+        let dir = temp_dir("probe");
+        let taken = UdpSocket::bind("127.0.0.1:0").expect("bind");
+        let mut config = config(&dir);
+        config.dns_listen_port = taken.local_addr().expect("local addr").port();
+        let err = probe_listen(&config).unwrap_err();
+        assert!(err.contains("Cannot bind DNS listen address"), "{}", err);
+        drop(taken);
+        assert!(probe_listen(&config).is_ok());
+        let _ = fs::remove_dir(&dir);
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Connections `queue` mode holds at most; later ones are turned away.
+const CONNECTION_QUEUE_CAPACITY: usize = 64;
+/// Packets held per queued connection; later ones are dropped and left to retransmission.
+const QUEUED_PACKETS_PER_CONNECTION: usize = 8;
+/// A client whose Initial waited this long has given up on the handshake.
+const CONNECTION_QUEUE_TTL: Duration = Duration::from_secs(10);
+
+/// What happens to a connection opened while `--max-connections` are already open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionOverflowMode {
+    /// Close the connection at once with QUIC's SERVER_BUSY error, before any state is kept.
+    #[default]
+    Drop,
+    /// Discard the connection and answer its query with REFUSED, so the client backs off.
+    Refuse,
+    /// Hold the connection's packets until another connection closes, then admit it.
+    Queue,
+}
+
+/// The fate of a packet, decided by `ConnectionLimit::on_packet` before QUIC sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Overflow {
+    /// Hand the packet to QUIC as usual.
+    Admit,
+    /// Hand the packet to QUIC with the server marked busy, so it answers SERVER_BUSY.
+    Busy,
+    /// Answer the query with REFUSED and keep the packet from QUIC.
+    Refuse,
+    /// The packet was held for a queued connection.
+    Queued,
+}
+
+/// Where a packet came from: the query's source, listener and matched domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PacketOrigin {
+    pub(crate) peer: SocketAddr,
+    pub(crate) listener: usize,
+    pub(crate) domain: usize,
+}
+
+/// A connection waiting in `queue` mode, with what is needed to feed its packets to QUIC.
+#[derive(Debug)]
+pub(crate) struct QueuedConnection {
+    /// The connection's packets in arrival order, starting with the Initial that opened it.
+    pub(crate) packets: Vec<Vec<u8>>,
+    /// Origin of the opening Initial; the connection starts with its source and domain.
+    pub(crate) origin: PacketOrigin,
+    queued_at: Instant,
+}
+
+impl QueuedConnection {
+    fn dcid(&self) -> Option<&[u8]> {
+        initial_dcid(&self.packets[0])
+    }
+}
+
+pub(crate) struct ConnectionLimit {
+    max_connections: usize,
+    mode: ConnectionOverflowMode,
+    queue: VecDeque<QueuedConnection>,
+    /// Set from the first overflow until the count drops below the limit, so the warning
+    /// goes out once per episode.
+    limit_hit: bool,
+}
+
+impl ConnectionLimit {
+    pub(crate) fn new(max_connections: u32, mode: ConnectionOverflowMode) -> Self {
+        Self {
+            max_connections: max_connections as usize,
+            mode,
+            queue: VecDeque::new(),
+            limit_hit: false,
+        }
+    }
+
+    /// Decides on `payload` while `count` connections are open; `opens` tells whether it is
+    /// an Initial no connection claims, and is only asked at the limit. `Queued` has taken a
+    /// copy of `payload`.
+    pub(crate) fn on_packet(
+        &mut self,
+        count: usize,
+        opens: impl FnOnce() -> bool,
+        payload: &[u8],
+        origin: PacketOrigin,
+        now: Instant,
+    ) -> Overflow {
+        if self.hold(payload, now) {
+            return Overflow::Queued;
+        }
+        if count < self.max_connections {
+            self.limit_hit = false;
+            return Overflow::Admit;
+        }
+        if !opens() {
+            return Overflow::Admit;
+        }
+        if !std::mem::replace(&mut self.limit_hit, true) {
+            tracing::warn!(
+                connections = count,
+                max_connections = self.max_connections,
+                mode = ?self.mode,
+                "Connection limit reached; new connections are held back"
+            );
+        }
+        match self.mode {
+            ConnectionOverflowMode::Drop => Overflow::Busy,
+            ConnectionOverflowMode::Refuse => Overflow::Refuse,
+            ConnectionOverflowMode::Queue => {
+                if self.queue.len() >= CONNECTION_QUEUE_CAPACITY {
+                    return Overflow::Busy;
+                }
+                self.queue.push_back(QueuedConnection {
+                    packets: vec![payload.to_vec()],
+                    origin,
+                    queued_at: now,
+                });
+                Overflow::Queued
+            }
+        }
+    }
+
+    /// Appends `payload` to the queued connection it belongs to, if any. Retransmissions,
+    /// further Initials of a long ClientHello and 0-RTT packets all carry the same connection ID.
+    fn hold(&mut self, payload: &[u8], now: Instant) -> bool {
+        if self.queue.is_empty() {
+            return false;
+        }
+        self.expire(now);
+        let Some(dcid) = initial_dcid(payload) else {
+            return false;
+        };
+        let Some(queued) = self
+            .queue
+            .iter_mut()
+            .find(|queued| queued.dcid() == Some(dcid))
+        else {
+            return false;
+        };
+        if queued.packets.len() < QUEUED_PACKETS_PER_CONNECTION {
+            queued.packets.push(payload.to_vec());
+        }
+        true
+    }
+
+    /// Queued connections to replay, oldest first, now that `count` connections are open.
+    pub(crate) fn admit(&mut self, count: usize, now: Instant) -> Vec<QueuedConnection> {
+        if self.queue.is_empty() {
+            return Vec::new();
+        }
+        self.expire(now);
+        let free = self.max_connections.saturating_sub(count);
+        let take = free.min(self.queue.len());
+        self.queue.drain(..take).collect()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.queue.retain(|queued| {
+            now.saturating_duration_since(queued.queued_at) < CONNECTION_QUEUE_TTL
+        });
+    }
+}
+
+/// Destination connection ID of a QUIC long-header packet.
+fn initial_dcid(payload: &[u8]) -> Option<&[u8]> {
+    if payload.first()? & 0x80 == 0 {
+        return None;
+    }
+    let len = *payload.get(5)? as usize;
+    payload.get(6..6 + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn initial(dcid: u8, packet_number: u8) -> Vec<u8> {
+        vec![0xc0, 0, 0, 0, 1, 4, dcid, dcid, dcid, dcid, packet_number]
+    }
+
+    fn origin(listener: usize, domain: usize) -> PacketOrigin {
+        PacketOrigin {
+            peer: "192.0.2.1:53".parse().unwrap(),
+            listener,
+            domain,
+        }
+    }
+
+    #[test]
+    fn admits_up_to_the_limit() {
+        let now = Instant::now();
+        let mut limit = ConnectionLimit::new(2, ConnectionOverflowMode::Refuse);
+        assert_eq!(
+            limit.on_packet(1, || true, &initial(1, 0), origin(0, 0), now),
+            Overflow::Admit
+        );
+        assert_eq!(
+            limit.on_packet(2, || true, &initial(2, 0), origin(0, 0), now),
+            Overflow::Refuse
+        );
+        assert!(limit.limit_hit);
+        assert_eq!(
+            limit.on_packet(2, || false, &initial(1, 1), origin(0, 0), now),
+            Overflow::Admit,
+            "packets of open connections pass at the limit"
+        );
+        assert_eq!(
+            limit.on_packet(1, || true, &initial(3, 0), origin(0, 0), now),
+            Overflow::Admit
+        );
+        assert!(!limit.limit_hit);
+
+        let mut drop = ConnectionLimit::new(1, ConnectionOverflowMode::Drop);
+        assert_eq!(
+            drop.on_packet(1, || true, &initial(1, 0), origin(0, 0), now),
+            Overflow::Busy
+        );
+        assert!(drop.admit(0, now).is_empty());
+    }
+
+    #[test]
+    fn queue_holds_every_packet_of_a_connection_and_admits_in_order() {
+        let now = Instant::now();
+        let mut limit = ConnectionLimit::new(2, ConnectionOverflowMode::Queue);
+        for dcid in 1..=3 {
+            assert_eq!(
+                limit.on_packet(2, || true, &initial(dcid, 0), origin(1, 2), now),
+                Overflow::Queued
+            );
+        }
+        // Later packets of a queued connection join it, whatever QUIC would make of them.
+        assert_eq!(
+            limit.on_packet(2, || true, &initial(1, 1), origin(1, 2), now),
+            Overflow::Queued
+        );
+        assert_eq!(
+            limit.on_packet(2, || false, &initial(1, 2), origin(1, 2), now),
+            Overflow::Queued
+        );
+        assert!(limit.admit(2, now).is_empty());
+
+        let admitted = limit.admit(1, now);
+        assert_eq!(admitted.len(), 1);
+        assert_eq!(
+            admitted[0].packets,
+            vec![initial(1, 0), initial(1, 1), initial(1, 2)]
+        );
+        assert_eq!(admitted[0].origin, origin(1, 2));
+
+        let admitted = limit.admit(0, now);
+        assert_eq!(admitted.len(), 2);
+        assert_eq!(admitted[1].packets, vec![initial(3, 0)]);
+    }
+
+    #[test]
+    fn queue_is_bounded_and_forgets_stale_connections() {
+        let now = Instant::now();
+        let mut limit = ConnectionLimit::new(0, ConnectionOverflowMode::Queue);
+        for dcid in 0..CONNECTION_QUEUE_CAPACITY as u8 {
+            limit.on_packet(0, || true, &initial(dcid, 0), origin(0, 0), now);
+        }
+        assert_eq!(
+            limit.on_packet(0, || true, &initial(0xff, 0), origin(0, 0), now),
+            Overflow::Busy
+        );
+        for packet_number in 1..=QUEUED_PACKETS_PER_CONNECTION as u8 {
+            limit.on_packet(0, || false, &initial(0, packet_number), origin(0, 0), now);
+        }
+        assert_eq!(limit.queue[0].packets.len(), QUEUED_PACKETS_PER_CONNECTION);
+
+        let later = now + CONNECTION_QUEUE_TTL;
+        limit.max_connections = 1;
+        assert!(limit.admit(0, later).is_empty());
+        assert!(limit.queue.is_empty());
+    }
+}
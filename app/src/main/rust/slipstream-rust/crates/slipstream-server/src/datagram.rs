@@ -0,0 +1,307 @@
+//! `--datagram-mode`: UDP flows carried in QUIC DATAGRAM frames.
+//!
+//! Each flow id of a connection gets its own UDP socket connected to the connection's target,
+//! opened by the flow's first datagram and closed after [`DATAGRAM_FLOW_IDLE`] without traffic
+//! either way. Delivery is best effort end to end: datagrams are dropped while a flow's queue
+//! is full and replies that do not fit one QUIC packet never leave the server. A connection
+//! opens at most [`MAX_FLOWS_PER_CONNECTION`] flows and the server [`MAX_DATAGRAM_FLOWS`];
+//! datagrams that would open more are dropped.
+
+use crate::server::Command;
+use crate::target::{resolve_dial_addrs, TargetAddr};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, watch};
+use tokio::time::sleep;
+use tracing::debug;
+
+/// How long a flow's target socket stays open without a datagram in either direction.
+const DATAGRAM_FLOW_IDLE: Duration = Duration::from_secs(60);
+/// Datagrams buffered per flow while its socket is opening or busy.
+const DATAGRAM_FLOW_QUEUE: usize = 64;
+/// Flows, and so target sockets, one connection may hold open.
+pub(crate) const MAX_FLOWS_PER_CONNECTION: usize = 64;
+/// Flows the server holds open across all connections.
+pub(crate) const MAX_DATAGRAM_FLOWS: usize = 4096;
+const MAX_UDP_PAYLOAD: usize = 65_535;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct FlowKey {
+    pub(crate) cnx: usize,
+    pub(crate) flow_id: u64,
+}
+
+struct DatagramFlow {
+    tx: mpsc::Sender<Vec<u8>>,
+    shutdown_tx: watch::Sender<bool>,
+    generation: u64,
+}
+
+/// Open flows of every connection, owned by the server event loop.
+#[derive(Default)]
+pub(crate) struct DatagramFlows {
+    flows: HashMap<FlowKey, DatagramFlow>,
+    /// Open flows by connection.
+    connection_flows: HashMap<usize, usize>,
+    /// Tags each opened flow, so replies of a closed flow never reach a connection that
+    /// reuses its key.
+    next_generation: u64,
+}
+
+impl DatagramFlows {
+    pub(crate) fn contains(&self, key: FlowKey) -> bool {
+        self.flows.contains_key(&key)
+    }
+
+    /// Whether `key` is still the flow that was opened as `generation`.
+    pub(crate) fn is_current(&self, key: FlowKey, generation: u64) -> bool {
+        self.flows
+            .get(&key)
+            .is_some_and(|flow| flow.generation == generation)
+    }
+
+    /// Opens a socket to `target` for `key`; its replies come back as
+    /// `Command::DatagramReceived`. Returns `false`, opening nothing, when the connection or
+    /// the server is at its flow limit.
+    pub(crate) fn open(
+        &mut self,
+        key: FlowKey,
+        target: TargetAddr,
+        command_tx: mpsc::UnboundedSender<Command>,
+        debug_streams: bool,
+    ) -> bool {
+        let open_flows = self.connection_flows.get(&key.cnx).copied().unwrap_or(0);
+        if open_flows >= MAX_FLOWS_PER_CONNECTION || self.flows.len() >= MAX_DATAGRAM_FLOWS {
+            return false;
+        }
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        let (tx, rx) = mpsc::channel(DATAGRAM_FLOW_QUEUE);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        if debug_streams {
+            debug!(
+                "cnx {} flow {}: opening datagram flow",
+                key.cnx, key.flow_id
+            );
+        }
+        spawn_datagram_flow(
+            key,
+            generation,
+            target,
+            rx,
+            command_tx,
+            debug_streams,
+            shutdown_rx,
+        );
+        self.flows.insert(
+            key,
+            DatagramFlow {
+                tx,
+                shutdown_tx,
+                generation,
+            },
+        );
+        *self.connection_flows.entry(key.cnx).or_default() += 1;
+        true
+    }
+
+    /// Hands `payload` to the flow's socket; dropped when the flow is unknown or backed up.
+    pub(crate) fn forward(&self, key: FlowKey, payload: &[u8]) {
+        if let Some(flow) = self.flows.get(&key) {
+            let _ = flow.tx.try_send(payload.to_vec());
+        }
+    }
+
+    /// Forgets a flow whose task has exited; a flow reopened under the same key is kept.
+    pub(crate) fn remove_closed(&mut self, key: FlowKey) {
+        if self.flows.get(&key).is_some_and(|flow| flow.tx.is_closed()) {
+            self.flows.remove(&key);
+            if let Some(open_flows) = self.connection_flows.get_mut(&key.cnx) {
+                *open_flows -= 1;
+                if *open_flows == 0 {
+                    self.connection_flows.remove(&key.cnx);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn remove_connection(&mut self, cnx: usize) {
+        if self.connection_flows.remove(&cnx).is_none() {
+            return;
+        }
+        self.flows.retain(|key, flow| {
+            if key.cnx != cnx {
+                return true;
+            }
+            let _ = flow.shutdown_tx.send(true);
+            false
+        });
+    }
+}
+
+fn spawn_datagram_flow(
+    key: FlowKey,
+    generation: u64,
+    target: TargetAddr,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    command_tx: mpsc::UnboundedSender<Command>,
+    debug_streams: bool,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        let socket = tokio::select! {
+            _ = shutdown_rx.changed() => return,
+            result = connect_udp_target(&target) => result,
+        };
+        match socket {
+            Ok(socket) => {
+                let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+                loop {
+                    tokio::select! {
+                        _ = shutdown_rx.changed() => return,
+                        payload = rx.recv() => {
+                            let Some(payload) = payload else {
+                                break;
+                            };
+                            if let Err(err) = socket.send(&payload).await {
+                                if debug_streams {
+                                    debug!(
+                                        "cnx {} flow {}: target send failed: {}",
+                                        key.cnx, key.flow_id, err
+                                    );
+                                }
+                            }
+                        }
+                        result = socket.recv(&mut buf) => match result {
+                            Ok(read) => {
+                                let command = Command::DatagramReceived {
+                                    cnx_id: key.cnx,
+                                    flow_id: key.flow_id,
+                                    generation,
+                                    data: buf[..read].to_vec(),
+                                };
+                                if command_tx.send(command).is_err() {
+                                    return;
+                                }
+                            }
+                            // Connected UDP sockets surface ICMP errors here; the flow stays open.
+                            Err(err) => {
+                                if debug_streams {
+                                    debug!(
+                                        "cnx {} flow {}: target recv failed: {}",
+                                        key.cnx, key.flow_id, err
+                                    );
+                                }
+                            }
+                        },
+                        _ = sleep(DATAGRAM_FLOW_IDLE) => break,
+                    }
+                }
+            }
+            Err(err) => {
+                debug!(
+                    "cnx {} flow {}: datagram target unavailable: {}",
+                    key.cnx, key.flow_id, err
+                );
+            }
+        }
+        drop(rx);
+        let _ = command_tx.send(Command::DatagramFlowClosed {
+            cnx_id: key.cnx,
+            flow_id: key.flow_id,
+        });
+    });
+}
+
+async fn connect_udp_target(target: &TargetAddr) -> io::Result<UdpSocket> {
+    let addr = match target {
+        TargetAddr::Fixed(addr) => *addr,
+        TargetAddr::Dial(target, policy) => {
+            resolve_dial_addrs(&target.host, target.port, *policy).await?[0]
+        }
+        TargetAddr::UnixSocket(path) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("no datagrams to Unix socket target {}", path.display()),
+            ))
+        }
+    };
+    let local: SocketAddr = if addr.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(addr).await?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn flows_relay_both_ways_and_close_with_their_connection() {
+        let target = UdpSocket::bind("127.0.0.1:0").await.expect("bind target");
+        let target_addr = target.local_addr().expect("target addr");
+        let (command_tx, mut command_rx) = mpsc::unbounded_channel();
+        let mut flows = DatagramFlows::default();
+        let key = FlowKey { cnx: 1, flow_id: 7 };
+
+        flows.open(key, TargetAddr::Fixed(target_addr), command_tx, false);
+        flows.forward(key, b"ping");
+        let mut buf = [0u8; 16];
+        let (read, peer) = target.recv_from(&mut buf).await.expect("target recv");
+        assert_eq!(&buf[..read], b"ping");
+
+        target.send_to(b"pong", peer).await.expect("target send");
+        match command_rx.recv().await {
+            Some(Command::DatagramReceived {
+                cnx_id,
+                flow_id,
+                generation,
+                data,
+            }) => {
+                assert_eq!((cnx_id, flow_id), (1, 7));
+                assert!(flows.is_current(key, generation));
+                assert_eq!(data, b"pong");
+            }
+            _ => panic!("expected a datagram"),
+        }
+
+        flows.remove_connection(1);
+        assert!(!flows.contains(key));
+    }
+
+    #[tokio::test]
+    async fn flows_are_capped_per_connection_and_reopened_under_a_new_generation() {
+        let (command_tx, _command_rx) = mpsc::unbounded_channel();
+        let mut flows = DatagramFlows::default();
+        let target = || TargetAddr::Fixed(SocketAddr::from(([127, 0, 0, 1], 9)));
+        let open = |flows: &mut DatagramFlows, cnx, flow_id| {
+            flows.open(
+                FlowKey { cnx, flow_id },
+                target(),
+                command_tx.clone(),
+                false,
+            )
+        };
+
+        for flow_id in 0..MAX_FLOWS_PER_CONNECTION as u64 {
+            assert!(open(&mut flows, 1, flow_id));
+        }
+        assert!(!open(&mut flows, 1, MAX_FLOWS_PER_CONNECTION as u64));
+        assert!(open(&mut flows, 2, 0), "the cap is per connection");
+
+        let key = FlowKey { cnx: 1, flow_id: 0 };
+        let first = flows.flows[&key].generation;
+        flows.remove_connection(1);
+        assert!(!flows.connection_flows.contains_key(&1));
+        assert!(open(&mut flows, 1, 0));
+        assert!(!flows.is_current(key, first), "a reused key is a new flow");
+        assert!(flows.is_current(key, flows.flows[&key].generation));
+    }
+}
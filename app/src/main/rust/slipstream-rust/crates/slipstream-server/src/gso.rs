@@ -0,0 +1,283 @@
+use slipstream_core::net::is_transient_udp_error;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::{debug, warn};
+
+/// Linux rejects GSO sends with more segments than this (`UDP_MAX_SEGMENTS`).
+const MAX_SEGMENTS: usize = 64;
+/// Largest UDP payload one coalesced send may carry.
+const MAX_BATCH_BYTES: usize = 65_507;
+
+/// An encoded answer waiting to leave through `listener`.
+pub(crate) struct Outgoing {
+    pub(crate) listener: usize,
+    pub(crate) peer: SocketAddr,
+    pub(crate) response: Vec<u8>,
+}
+
+/// Splits `packets` into runs one GSO send can carry: consecutive answers through the same
+/// listener to the same peer, each the size of the first except for a shorter last one.
+///
+/// Anything else ends the run, so heterogeneous answers go out in runs of one.
+pub(crate) fn plan_batches(packets: &[Outgoing]) -> Vec<Range<usize>> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    while start < packets.len() {
+        let first = &packets[start];
+        let segment_len = first.response.len();
+        let mut total = segment_len;
+        let mut end = start + 1;
+        while end < packets.len() && end - start < MAX_SEGMENTS {
+            let next = &packets[end];
+            let len = next.response.len();
+            if next.listener != first.listener
+                || next.peer != first.peer
+                || len > segment_len
+                || total + len > MAX_BATCH_BYTES
+            {
+                break;
+            }
+            total += len;
+            end += 1;
+            if len < segment_len {
+                break;
+            }
+        }
+        batches.push(start..end);
+        start = end;
+    }
+    batches
+}
+
+/// Sends planned batches with `UDP_SEGMENT` when enabled, one `send_to` per answer otherwise.
+pub(crate) struct BatchSender {
+    gso: bool,
+    debug_poll: bool,
+    datagrams: u64,
+    sends: u64,
+    last_report: Instant,
+}
+
+impl BatchSender {
+    pub(crate) fn new(gso: bool, debug_poll: bool) -> Self {
+        let gso = if gso && !cfg!(target_os = "linux") {
+            warn!("--gso needs Linux UDP_SEGMENT; sending one datagram per answer");
+            false
+        } else {
+            gso
+        };
+        Self {
+            gso,
+            debug_poll,
+            datagrams: 0,
+            sends: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Sends one batch from `plan_batches`; every packet shares its listener and peer.
+    pub(crate) async fn send(
+        &mut self,
+        socket: &UdpSocket,
+        packets: &[Outgoing],
+    ) -> io::Result<()> {
+        let Some(first) = packets.first() else {
+            return Ok(());
+        };
+        if self.gso && packets.len() > 1 {
+            match send_segmented(socket, first.peer, packets).await {
+                Ok(()) => {
+                    self.datagrams += packets.len() as u64;
+                    self.sends += 1;
+                    return Ok(());
+                }
+                Err(err) if is_gso_unsupported(&err) => {
+                    warn!(
+                        error = %err,
+                        "GSO send failed; falling back to one datagram per answer"
+                    );
+                    self.gso = false;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        for packet in packets {
+            self.datagrams += 1;
+            self.sends += 1;
+            if let Err(err) = socket.send_to(&packet.response, packet.peer).await {
+                if !is_transient_udp_error(&err) {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Logs datagrams per send once per second under `--debug-poll`.
+    pub(crate) fn maybe_report(&mut self) {
+        if !self.debug_poll {
+            return;
+        }
+        let now = Instant::now();
+        if now.duration_since(self.last_report) < Duration::from_secs(1) {
+            return;
+        }
+        if self.sends > 0 {
+            debug!(
+                "debug: send batching datagrams={} sends={} ratio={:.2} gso={}",
+                self.datagrams,
+                self.sends,
+                self.datagrams as f64 / self.sends as f64,
+                self.gso
+            );
+        }
+        self.datagrams = 0;
+        self.sends = 0;
+        self.last_report = now;
+    }
+}
+
+/// Errors that mean the kernel or NIC cannot segment, as opposed to a failed send.
+fn is_gso_unsupported(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EIO | libc::EINVAL | libc::EOPNOTSUPP | libc::ENOPROTOOPT)
+    ) || err.kind() == io::ErrorKind::Unsupported
+}
+
+#[cfg(target_os = "linux")]
+async fn send_segmented(
+    socket: &UdpSocket,
+    peer: SocketAddr,
+    packets: &[Outgoing],
+) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    let segment_len = packets[0].response.len() as u16;
+    let buf: Vec<u8> = packets
+        .iter()
+        .flat_map(|packet| packet.response.iter().copied())
+        .collect();
+    let addr = socket2::SockAddr::from(peer);
+    socket
+        .async_io(Interest::WRITABLE, || {
+            sendmsg_segmented(socket.as_raw_fd(), &addr, &buf, segment_len)
+        })
+        .await
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn send_segmented(
+    _socket: &UdpSocket,
+    _peer: SocketAddr,
+    _packets: &[Outgoing],
+) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(target_os = "linux")]
+fn sendmsg_segmented(
+    fd: libc::c_int,
+    addr: &socket2::SockAddr,
+    buf: &[u8],
+    segment_len: u16,
+) -> io::Result<()> {
+    // Room for one cmsghdr carrying a u16, 8-byte aligned like the kernel expects.
+    let mut control = [0u64; 4];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // SAFETY: msghdr is plain-old-data; every pointer set below outlives the sendmsg call.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = addr.as_ptr() as *mut libc::c_void;
+    msg.msg_namelen = addr.len();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    // SAFETY: CMSG_* only compute sizes and offsets within `control`, which is large enough.
+    unsafe {
+        msg.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<u16>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_UDP;
+        (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<u16>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_len);
+    }
+    // SAFETY: `msg` describes valid buffers for the duration of the call.
+    if unsafe { libc::sendmsg(fd, &msg, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outgoing(listener: usize, port: u16, len: usize) -> Outgoing {
+        Outgoing {
+            listener,
+            peer: SocketAddr::from(([127, 0, 0, 1], port)),
+            response: vec![0; len],
+        }
+    }
+
+    #[test]
+    fn batches_same_peer_runs_with_a_shorter_tail() {
+        let packets = vec![
+            outgoing(0, 1000, 100),
+            outgoing(0, 1000, 100),
+            outgoing(0, 1000, 60),
+            outgoing(0, 1000, 100),
+            outgoing(0, 2000, 100),
+            outgoing(1, 2000, 100),
+            outgoing(1, 2000, 120),
+        ];
+        assert_eq!(plan_batches(&packets), vec![0..3, 3..4, 4..5, 5..6, 6..7]);
+    }
+
+    #[test]
+    fn batches_respect_segment_and_size_caps() {
+        let packets: Vec<_> = (0..MAX_SEGMENTS + 1)
+            .map(|_| outgoing(0, 1000, 10))
+            .collect();
+        assert_eq!(
+            plan_batches(&packets),
+            vec![0..MAX_SEGMENTS, MAX_SEGMENTS..MAX_SEGMENTS + 1]
+        );
+        let packets: Vec<_> = (0..3).map(|_| outgoing(0, 1000, 30_000)).collect();
+        assert_eq!(plan_batches(&packets), vec![0..2, 2..3]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn segmented_sends_arrive_as_separate_datagrams() {
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let peer = receiver.local_addr().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let packets: Vec<_> = [b'a', b'b', b'c']
+            .into_iter()
+            .zip([40, 40, 25])
+            .map(|(fill, len)| Outgoing {
+                listener: 0,
+                peer,
+                response: vec![fill; len],
+            })
+            .collect();
+        let mut sender = BatchSender::new(true, false);
+        sender.send(&socket, &packets).await.unwrap();
+        let mut buf = [0u8; 128];
+        for packet in &packets {
+            let (len, _) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], &packet.response[..]);
+        }
+    }
+}
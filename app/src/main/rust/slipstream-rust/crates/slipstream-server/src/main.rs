@@ -1,16 +1,43 @@
+mod auth_nonce;
+mod check;
 mod config;
+mod connection_limit;
+mod datagram;
+mod gso;
+mod metrics;
+mod proxy_protocol;
+mod recvmmsg;
 mod server;
+mod source_filter;
 mod streams;
 mod target;
 mod udp_fallback;
 
-use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser};
-use server::{run_server, ServerConfig};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use config::{
+    is_inline_pem, parse_cert_key_type, parse_cert_san, read_pem_value, rotate_reset_seed,
+    validate_quic_mtu, CertIdentity, CertKeyType, CertSan, InlineCertKey, DEFAULT_CERT_CN,
+};
+use connection_limit::ConnectionOverflowMode;
+use server::{run_server, DomainTarget, FallbackSpec, ServerConfig, TargetMode};
+use slipstream_core::config_file::{ConfigFile, FileArgs};
+use slipstream_core::logging::{parse_log_format, resolve_log_format, LogFormat};
+use slipstream_core::stream_priority::{parse_stream_priority_mode, StreamPriorityMode};
+use slipstream_core::watchdog;
 use slipstream_core::{
-    normalize_domain, parse_host_port, parse_host_port_parts, sip003, AddressKind, HostPort,
+    apply_address_policy, normalize_domain, parse_address_policy, parse_host_port,
+    parse_host_port_parts, sip003, AddressKind, AddressPolicy, ConfigError, HostPort,
 };
+use slipstream_dns::Encoding;
+use source_filter::{parse_cidr, Cidr, SourceFilter};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use streams::DEFAULT_CONNECTION_LOG_CAPACITY;
+use target::TargetEndpoint;
 use tokio::runtime::Builder;
 use tracing_subscriber::EnvFilter;
+use udp_fallback::FallbackMatch;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -19,30 +46,194 @@ use tracing_subscriber::EnvFilter;
 )]
 struct Args {
     #[arg(long = "dns-listen-host", default_value = "::")]
-    dns_listen_host: String,
+    dns_listen_host: Vec<String>,
     #[arg(long = "dns-listen-port", short = 'l', default_value_t = 53)]
     dns_listen_port: u16,
+    #[arg(
+        long = "address-family",
+        value_name = "4|6|any",
+        default_value = "any",
+        value_parser = parse_address_policy
+    )]
+    address_family: AddressPolicy,
     #[arg(
         long = "target-address",
         short = 'a',
         default_value = "127.0.0.1:5201",
         value_parser = parse_target_address
     )]
-    target_address: HostPort,
-    #[arg(long = "fallback", value_name = "HOST:PORT", value_parser = parse_fallback_address)]
-    fallback: Option<HostPort>,
+    target_address: TargetEndpoint,
+    #[arg(long = "fallback", value_name = "[MATCH=]HOST:PORT", value_parser = parse_fallback)]
+    fallback: Vec<FallbackSpec>,
     #[arg(long = "cert", short = 'c', value_name = "PATH")]
     cert: Option<String>,
     #[arg(long = "key", short = 'k', value_name = "PATH")]
     key: Option<String>,
+    #[arg(
+        long = "cert-pem",
+        value_name = "PEM|-|env:NAME|file:PATH",
+        conflicts_with = "cert",
+        requires = "key_pem"
+    )]
+    cert_pem: Option<String>,
+    #[arg(
+        long = "key-pem",
+        value_name = "PEM|-|env:NAME|file:PATH",
+        conflicts_with = "key",
+        requires = "cert_pem"
+    )]
+    key_pem: Option<String>,
+    #[arg(long = "cert-cn", value_name = "NAME", default_value = DEFAULT_CERT_CN)]
+    cert_cn: String,
+    #[arg(long = "cert-san", value_name = "DNS|IP", value_parser = parse_cert_san)]
+    cert_san: Vec<CertSan>,
+    #[arg(
+        long = "cert-key-type",
+        value_name = "TYPE",
+        default_value = "ecdsa-p256",
+        value_parser = parse_cert_key_type
+    )]
+    cert_key_type: CertKeyType,
     #[arg(long = "reset-seed", value_name = "PATH")]
     reset_seed: Option<String>,
-    #[arg(long = "domain", short = 'd', value_parser = parse_domain)]
-    domains: Vec<String>,
+    #[arg(long = "rotate-reset-seed", requires = "reset_seed")]
+    rotate_reset_seed: bool,
+    #[arg(long = "ticket-store", value_name = "PATH")]
+    ticket_store: Option<String>,
+    #[arg(long = "auth-secret", value_name = "FILE")]
+    auth_secret: Option<String>,
+    #[arg(
+        long = "reset-seed-rotation-interval",
+        value_name = "HOURS",
+        default_value_t = 0
+    )]
+    reset_seed_rotation_interval: u64,
+    #[arg(
+        long = "domain",
+        short = 'd',
+        value_name = "DOMAIN[=HOST:PORT]",
+        value_parser = parse_domain
+    )]
+    domains: Vec<DomainArg>,
+    #[arg(long = "encoding", value_name = "ENCODING", default_value = "base32", value_parser = parse_encoding)]
+    encoding: Encoding,
     #[arg(long = "max-connections", default_value_t = 256, value_parser = parse_max_connections)]
     max_connections: u32,
+    #[arg(
+        long = "connection-overflow-mode",
+        value_name = "drop|refuse|queue",
+        default_value = "drop",
+        value_parser = parse_connection_overflow_mode
+    )]
+    connection_overflow_mode: ConnectionOverflowMode,
+    #[arg(
+        long = "max-streams-per-connection",
+        default_value_t = 256,
+        value_parser = parse_max_streams_per_connection
+    )]
+    max_streams_per_connection: u32,
     #[arg(long = "idle-timeout-seconds", default_value_t = 1200)]
     idle_timeout_seconds: u64,
+    #[arg(
+        long = "idle-drain-timeout-seconds",
+        alias = "shutdown-drain-seconds",
+        default_value_t = 30
+    )]
+    idle_drain_timeout_seconds: u64,
+    #[arg(
+        long = "metrics-listen",
+        alias = "metrics-addr",
+        value_name = "HOST:PORT",
+        value_parser = parse_metrics_listen
+    )]
+    metrics_listen: Option<SocketAddr>,
+    #[arg(
+        long = "connection-log-capacity",
+        value_name = "N",
+        default_value_t = DEFAULT_CONNECTION_LOG_CAPACITY,
+        value_parser = parse_connection_log_capacity
+    )]
+    connection_log_capacity: usize,
+    #[arg(
+        long = "target-mode",
+        value_name = "MODE",
+        default_value = "fixed",
+        value_parser = parse_target_mode
+    )]
+    target_mode: TargetMode,
+    #[arg(long = "dynamic-target", conflicts_with = "target_mode")]
+    dynamic_target: bool,
+    #[arg(long = "target-proxy-protocol")]
+    target_proxy_protocol: bool,
+    #[arg(long = "datagram-mode")]
+    datagram_mode: bool,
+    #[arg(
+        long = "stream-priority-mode",
+        value_name = "MODE",
+        value_parser = parse_stream_priority_mode
+    )]
+    stream_priority_mode: Option<StreamPriorityMode>,
+    #[arg(long = "dnssec-passthrough")]
+    dnssec_passthrough: bool,
+    #[arg(long = "no-name-compression")]
+    no_name_compression: bool,
+    #[arg(long = "gso")]
+    gso: bool,
+    #[arg(long = "recvmmsg")]
+    recvmmsg: bool,
+    #[arg(long = "quic-mtu", value_name = "BYTES", default_value_t = 900, value_parser = parse_quic_mtu)]
+    quic_mtu: u32,
+    #[arg(
+        long = "response-ttl",
+        visible_alias = "answer-ttl",
+        value_name = "SECONDS",
+        default_value_t = 0,
+        value_parser = parse_response_ttl
+    )]
+    response_ttl: u32,
+    #[arg(
+        long = "per-connection-rate-limit-kbps",
+        value_name = "KBPS",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    per_connection_rate_limit_kbps: Option<u32>,
+    #[arg(
+        long = "target-write-timeout-ms",
+        value_name = "MS",
+        default_value_t = 0
+    )]
+    target_write_timeout_ms: u64,
+    #[arg(
+        long = "max-qps-per-source",
+        value_name = "QPS",
+        default_value_t = 0,
+        value_parser = parse_max_qps_per_source
+    )]
+    max_qps_per_source: u32,
+    #[arg(long = "allow-source", value_name = "CIDR", value_parser = parse_cidr)]
+    allow_source: Vec<Cidr>,
+    #[arg(long = "deny-source", value_name = "CIDR", value_parser = parse_cidr)]
+    deny_source: Vec<Cidr>,
+    #[arg(
+        long = "congestion-control",
+        value_name = "bbr|dcubic",
+        value_parser = parse_congestion_control
+    )]
+    congestion_control: Option<String>,
+    #[arg(long = "config", value_name = "FILE")]
+    config: Option<String>,
+    #[arg(long = "check-config")]
+    check_config: bool,
+    #[arg(long = "dry-run", conflicts_with = "check_config")]
+    dry_run: bool,
+    #[arg(long = "watchdog-interval-ms", value_name = "MS", default_value_t = 0)]
+    watchdog_interval_ms: u64,
+    #[arg(long = "watchdog-abort")]
+    watchdog_abort: bool,
+    #[arg(long = "log-format", value_name = "text|json", value_parser = parse_log_format)]
+    log_format: Option<LogFormat>,
+    #[arg(long = "debug-poll")]
+    debug_poll: bool,
     #[arg(long = "debug-streams")]
     debug_streams: bool,
     #[arg(long = "debug-commands")]
@@ -50,9 +241,30 @@ struct Args {
 }
 
 fn main() {
-    init_logging();
-    let matches = Args::command().get_matches();
+    let (matches, config_file_err) = apply_config_file(Args::command().get_matches());
     let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let (log_format, log_format_err) = resolve_log_format(args.log_format, DEFAULT_LOG_FORMAT);
+    init_logging(log_format);
+    if let Some(err) = log_format_err {
+        tracing::warn!("Ignoring {}", err);
+    }
+    if let Some(err) = config_file_err {
+        tracing::error!("Config file error: {}", err);
+        std::process::exit(2);
+    }
+    if let (true, Some(path)) = (args.rotate_reset_seed, &args.reset_seed) {
+        match rotate_reset_seed(Path::new(path)) {
+            Ok(_) => {
+                tracing::info!("Wrote a new reset seed to {}", path);
+                tracing::warn!("Running servers keep their current reset seed until they restart");
+                std::process::exit(0);
+            }
+            Err(err) => {
+                tracing::error!("Reset seed error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
     let sip003_env = sip003::read_sip003_env().unwrap_or_else(|err| {
         tracing::error!("SIP003 env error: {}", err);
         std::process::exit(2);
@@ -60,11 +272,18 @@ fn main() {
     if sip003_env.is_present() {
         tracing::info!("SIP003 env detected; applying SS_* overrides with CLI precedence");
     }
+    if let Err(errors) = sip003::validate(&sip003_env.plugin_options, &plugin_option_schema()) {
+        for err in errors {
+            tracing::error!("SIP003 env error: {}", err);
+        }
+        std::process::exit(2);
+    }
 
     let dns_listen_host_provided = cli_provided(&matches, "dns_listen_host");
     let dns_listen_port_provided = cli_provided(&matches, "dns_listen_port");
+    // Repeated flags and comma-separated lists combine into one list.
     let (dns_listen_host, dns_listen_port) = sip003::select_host_port(
-        &args.dns_listen_host,
+        &args.dns_listen_host.join(","),
         args.dns_listen_port,
         dns_listen_host_provided,
         dns_listen_port_provided,
@@ -76,6 +295,30 @@ fn main() {
         tracing::error!("SIP003 env error: {}", err);
         std::process::exit(2);
     });
+    let mut dns_listen_hosts = parse_listen_hosts(&dns_listen_host).unwrap_or_else(|err| {
+        tracing::error!("DNS listen host error: {}", err);
+        std::process::exit(2);
+    });
+    let address_policy = if cli_provided(&matches, "address_family") {
+        args.address_family
+    } else if let Some(value) =
+        sip003::last_option_value(&sip003_env.plugin_options, "address-family")
+    {
+        parse_address_policy(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
+    } else {
+        args.address_family
+    };
+    if address_policy == AddressPolicy::Ipv4Only {
+        // The default `::` wildcard would only bind IPv6 without dual-stack.
+        for host in &mut dns_listen_hosts {
+            if host == "::" {
+                *host = "0.0.0.0".to_string();
+            }
+        }
+    }
 
     let sip003_local = if cli_provided(&matches, "target_address") {
         None
@@ -91,27 +334,52 @@ fn main() {
         })
     };
     let target_address = if let Some(endpoint) = &sip003_local {
-        parse_host_port_parts(&endpoint.host, endpoint.port, AddressKind::Target).unwrap_or_else(
-            |err| {
+        parse_host_port_parts(&endpoint.host, endpoint.port, AddressKind::Target)
+            .map(TargetEndpoint::Tcp)
+            .unwrap_or_else(|err| {
                 tracing::error!("SIP003 env error: {}", err);
                 std::process::exit(2);
-            },
-        )
+            })
     } else {
         args.target_address.clone()
     };
-    let fallback_address = if cli_provided(&matches, "fallback") {
+    let fallbacks = if cli_provided(&matches, "fallback") {
         args.fallback.clone()
+    } else if let Some(value) = sip003::last_option_value(&sip003_env.plugin_options, "fallback") {
+        parse_fallback_list(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
     } else {
-        sip003::last_option_value(&sip003_env.plugin_options, "fallback").map(|value| {
-            parse_fallback_address(&value).unwrap_or_else(|err| {
-                tracing::error!("SIP003 env error: {}", err);
-                std::process::exit(2);
+        Vec::new()
+    };
+    if let Err(err) = validate_fallback_order(&fallbacks) {
+        tracing::error!("Fallback error: {}", err);
+        std::process::exit(2);
+    }
+    let target_address = target_address
+        .apply_address_policy(address_policy)
+        .unwrap_or_else(|err| {
+            tracing::error!("Target address error: {}", err);
+            std::process::exit(2);
+        });
+    let fallbacks: Vec<FallbackSpec> = fallbacks
+        .into_iter()
+        .map(|fallback| {
+            let address =
+                apply_address_policy(fallback.address, AddressKind::Fallback, address_policy)?;
+            Ok(FallbackSpec {
+                matcher: fallback.matcher,
+                address,
             })
         })
-    };
+        .collect::<Result<_, ConfigError>>()
+        .unwrap_or_else(|err| {
+            tracing::error!("Fallback error: {}", err);
+            std::process::exit(2);
+        });
 
-    let domains = if !args.domains.is_empty() {
+    let domain_args = if !args.domains.is_empty() {
         args.domains.clone()
     } else {
         let option_domains =
@@ -125,29 +393,75 @@ fn main() {
         }
         option_domains
     };
+    let domains: Vec<String> = domain_args.iter().map(|arg| arg.domain.clone()).collect();
+    let domain_targets: Vec<DomainTarget> = domain_args
+        .into_iter()
+        .filter_map(|arg| Some((arg.domain, arg.target?)))
+        .map(|(domain, address)| {
+            let address = address.apply_address_policy(address_policy)?;
+            Ok(DomainTarget { domain, address })
+        })
+        .collect::<Result<_, ConfigError>>()
+        .unwrap_or_else(|err| {
+            tracing::error!("Target address error: {}", err);
+            std::process::exit(2);
+        });
 
-    let cert = if let Some(cert) = args.cert.clone() {
-        cert
-    } else if let Some(cert) = sip003::last_option_value(&sip003_env.plugin_options, "cert") {
-        cert
+    let encoding = if cli_provided(&matches, "encoding") {
+        args.encoding
+    } else if let Some(value) = sip003::last_option_value(&sip003_env.plugin_options, "encoding") {
+        parse_encoding(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
     } else {
-        tracing::error!("A certificate path is required");
-        std::process::exit(2);
+        args.encoding
     };
 
-    let key = if let Some(key) = args.key.clone() {
-        key
-    } else if let Some(key) = sip003::last_option_value(&sip003_env.plugin_options, "key") {
-        key
+    let inline_cert_key = resolve_inline_cert_key(&args, &sip003_env.plugin_options)
+        .unwrap_or_else(|err| {
+            tracing::error!("Inline cert/key error: {}", err);
+            std::process::exit(2);
+        });
+    let (cert, key) = if let Some(inline) = &inline_cert_key {
+        (
+            inline.cert_path().display().to_string(),
+            inline.key_path().display().to_string(),
+        )
     } else {
-        tracing::error!("A key path is required");
-        std::process::exit(2);
+        let cert = if let Some(cert) = args.cert.clone() {
+            cert
+        } else if let Some(cert) = sip003::last_option_value(&sip003_env.plugin_options, "cert") {
+            cert
+        } else {
+            tracing::error!("A certificate path is required");
+            std::process::exit(2);
+        };
+
+        let key = if let Some(key) = args.key.clone() {
+            key
+        } else if let Some(key) = sip003::last_option_value(&sip003_env.plugin_options, "key") {
+            key
+        } else {
+            tracing::error!("A key path is required");
+            std::process::exit(2);
+        };
+        (cert, key)
     };
     let reset_seed_path = if let Some(path) = args.reset_seed.clone() {
         Some(path)
     } else {
         sip003::last_option_value(&sip003_env.plugin_options, "reset-seed")
     };
+    if args.reset_seed_rotation_interval > 0 && reset_seed_path.is_none() {
+        tracing::error!("--reset-seed-rotation-interval needs a --reset-seed path");
+        std::process::exit(2);
+    }
+    let auth_secret_path = if let Some(path) = args.auth_secret.clone() {
+        Some(path)
+    } else {
+        sip003::last_option_value(&sip003_env.plugin_options, "auth-secret")
+    };
     let max_connections = if cli_provided(&matches, "max_connections") {
         args.max_connections
     } else if let Some(value) =
@@ -161,27 +475,206 @@ fn main() {
         args.max_connections
     };
 
+    let max_streams_per_connection = if cli_provided(&matches, "max_streams_per_connection") {
+        args.max_streams_per_connection
+    } else if let Some(value) =
+        sip003::last_option_value(&sip003_env.plugin_options, "max-streams-per-connection")
+    {
+        parse_max_streams_per_connection(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
+    } else {
+        args.max_streams_per_connection
+    };
+
+    let metrics_listen = if cli_provided(&matches, "metrics_listen") {
+        args.metrics_listen
+    } else {
+        sip003::last_option_value(&sip003_env.plugin_options, "metrics-listen").map(|value| {
+            parse_metrics_listen(&value).unwrap_or_else(|err| {
+                tracing::error!("SIP003 env error: {}", err);
+                std::process::exit(2);
+            })
+        })
+    };
+
+    let connection_log_capacity = if cli_provided(&matches, "connection_log_capacity") {
+        args.connection_log_capacity
+    } else if let Some(value) =
+        sip003::last_option_value(&sip003_env.plugin_options, "connection-log-capacity")
+    {
+        parse_connection_log_capacity(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
+    } else {
+        args.connection_log_capacity
+    };
+
+    let target_mode = if args.dynamic_target {
+        TargetMode::ConnectProxy
+    } else if cli_provided(&matches, "target_mode") {
+        args.target_mode
+    } else if let Some(value) = sip003::last_option_value(&sip003_env.plugin_options, "target-mode")
+    {
+        parse_target_mode(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
+    } else {
+        args.target_mode
+    };
+
+    let quic_mtu = if cli_provided(&matches, "quic_mtu") {
+        args.quic_mtu
+    } else if let Some(value) = sip003::last_option_value(&sip003_env.plugin_options, "quic-mtu") {
+        parse_quic_mtu(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
+    } else {
+        args.quic_mtu
+    };
+
+    let response_ttl = if cli_provided(&matches, "response_ttl") {
+        args.response_ttl
+    } else if let Some(value) =
+        sip003::last_option_value(&sip003_env.plugin_options, "response-ttl")
+    {
+        parse_response_ttl(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
+    } else {
+        args.response_ttl
+    };
+
+    let max_qps_per_source = if cli_provided(&matches, "max_qps_per_source") {
+        args.max_qps_per_source
+    } else if let Some(value) =
+        sip003::last_option_value(&sip003_env.plugin_options, "max-qps-per-source")
+    {
+        parse_max_qps_per_source(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        })
+    } else {
+        args.max_qps_per_source
+    };
+
+    let congestion_control = if cli_provided(&matches, "congestion_control") {
+        args.congestion_control.clone()
+    } else if let Some(value) =
+        sip003::last_option_value(&sip003_env.plugin_options, "congestion-control")
+    {
+        Some(parse_congestion_control(&value).unwrap_or_else(|err| {
+            tracing::error!("SIP003 env error: {}", err);
+            std::process::exit(2);
+        }))
+    } else {
+        args.congestion_control.clone()
+    };
+
     let config = ServerConfig {
-        dns_listen_host,
+        dns_listen_hosts,
         dns_listen_port,
+        address_policy,
         target_address,
-        fallback_address,
+        fallbacks,
         cert,
         key,
+        cert_identity: CertIdentity {
+            common_name: args.cert_cn.clone(),
+            sans: args.cert_san.clone(),
+            key_type: args.cert_key_type,
+        },
         reset_seed_path,
+        auth_secret_path,
+        reset_seed_rotation_hours: args.reset_seed_rotation_interval,
+        ticket_store_path: args.ticket_store.clone(),
         domains,
+        domain_targets,
+        encoding,
         max_connections,
+        connection_overflow_mode: args.connection_overflow_mode,
+        max_streams_per_connection,
         idle_timeout_seconds: args.idle_timeout_seconds,
+        idle_drain_timeout_seconds: args.idle_drain_timeout_seconds,
+        metrics_listen,
+        connection_log_capacity,
+        target_proxy_protocol: args.target_proxy_protocol,
+        target_mode,
+        datagram_mode: args.datagram_mode,
+        stream_priority_mode: args.stream_priority_mode,
+        quic_mtu,
+        response_ttl,
+        per_connection_rate_limit_kbps: args.per_connection_rate_limit_kbps,
+        target_write_timeout_ms: args.target_write_timeout_ms,
+        max_qps_per_source,
+        source_filter: SourceFilter::new(args.allow_source.clone(), args.deny_source.clone()),
+        congestion_control,
+        dnssec_passthrough: args.dnssec_passthrough,
+        compress_names: !args.no_name_compression,
+        gso: args.gso,
+        recvmmsg: args.recvmmsg,
+        debug_poll: args.debug_poll,
         debug_streams: args.debug_streams,
         debug_commands: args.debug_commands,
     };
 
+    if args.dry_run {
+        let result = check::check_config(&config).and_then(|_| check::probe_listen(&config));
+        drop(inline_cert_key);
+        match result {
+            Ok(()) => {
+                tracing::info!("Configuration OK");
+                std::process::exit(0);
+            }
+            Err(err) => {
+                tracing::error!("Config error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+    if args.check_config {
+        let result = check::check_config(&config);
+        // process::exit skips destructors; remove the inline cert/key files first.
+        drop(inline_cert_key);
+        match result {
+            Ok(summary) => {
+                print!("{}", summary);
+                std::process::exit(0);
+            }
+            Err(err) => {
+                tracing::error!("Config error: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+
     let runtime = Builder::new_current_thread()
         .enable_io()
         .enable_time()
         .build()
         .expect("Failed to build Tokio runtime");
-    match runtime.block_on(run_server(&config)) {
+    if args.watchdog_interval_ms > 0 {
+        let interval = Duration::from_millis(args.watchdog_interval_ms);
+        match watchdog::start(interval, args.watchdog_abort) {
+            Ok(responder) => {
+                runtime.spawn(responder);
+            }
+            Err(err) => {
+                tracing::error!("Failed to start the watchdog: {}", err);
+                std::process::exit(1);
+            }
+        }
+    } else if args.watchdog_abort {
+        tracing::warn!("Ignoring --watchdog-abort without --watchdog-interval-ms");
+    }
+    let result = runtime.block_on(run_server(&config));
+    drop(inline_cert_key);
+    match result {
         Ok(code) => std::process::exit(code),
         Err(err) => {
             tracing::error!("Server error: {}", err);
@@ -190,21 +683,154 @@ fn main() {
     }
 }
 
-fn init_logging() {
+/// Log format used when neither `--log-format` nor `SLIPSTREAM_LOG_FORMAT` is set.
+const DEFAULT_LOG_FORMAT: LogFormat = if cfg!(feature = "json-logs") {
+    LogFormat::Json
+} else {
+    LogFormat::Text
+};
+
+fn init_logging(format: LogFormat) {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .without_time()
-        .try_init();
+    let _ = match format {
+        LogFormat::Text => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_target(false)
+            .without_time()
+            .try_init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_env_filter(filter)
+            .try_init(),
+    };
+}
+
+/// Reads `--cert-pem`/`--key-pem` (or SIP003 `cert-pem`/`key-pem`) and stages them on disk.
+///
+/// A path given with `--cert`/`--key` on the CLI takes precedence over the SIP003 options. See
+/// `read_pem_value` for where each value is read from.
+fn resolve_inline_cert_key(
+    args: &Args,
+    options: &[sip003::Sip003Option],
+) -> Result<Option<InlineCertKey>, String> {
+    let cert_pem = args.cert_pem.clone().or_else(|| {
+        args.cert
+            .is_none()
+            .then(|| sip003::last_option_value(options, "cert-pem"))
+            .flatten()
+    });
+    let key_pem = args.key_pem.clone().or_else(|| {
+        args.key
+            .is_none()
+            .then(|| sip003::last_option_value(options, "key-pem"))
+            .flatten()
+    });
+    let (cert_pem, key_pem) = match (cert_pem, key_pem) {
+        (None, None) => return Ok(None),
+        (Some(cert_pem), Some(key_pem)) => (cert_pem, key_pem),
+        _ => return Err("cert-pem and key-pem must be given together".to_string()),
+    };
+    if args.key_pem.as_deref().is_some_and(is_inline_pem) {
+        tracing::warn!(
+            "--key-pem on the command line is visible to other local users; \
+             pass env:NAME, file:PATH, or - instead"
+        );
+    }
+    let mut stdin = None;
+    let cert_pem = read_pem_value(&cert_pem, &mut stdin)?;
+    let key_pem = read_pem_value(&key_pem, &mut stdin)?;
+    InlineCertKey::write(cert_pem.as_bytes(), key_pem.as_bytes()).map(Some)
+}
+
+/// A `--domain` value: the domain and, with `DOMAIN=TARGET`, the target its connections use.
+#[derive(Debug, Clone)]
+struct DomainArg {
+    domain: String,
+    target: Option<TargetEndpoint>,
+}
+
+fn parse_domain(input: &str) -> Result<DomainArg, String> {
+    let (domain, target) = match input.split_once('=') {
+        Some((domain, target)) => (domain, Some(parse_target_address(target)?)),
+        None => (input, None),
+    };
+    Ok(DomainArg {
+        domain: normalize_domain(domain).map_err(|err| err.to_string())?,
+        target,
+    })
+}
+
+fn parse_encoding(input: &str) -> Result<Encoding, String> {
+    slipstream_dns::parse_encoding(input).ok_or_else(|| {
+        format!(
+            "Unsupported encoding (expected base32 or base64url): {}",
+            input
+        )
+    })
+}
+
+fn parse_listen_hosts(input: &str) -> Result<Vec<String>, String> {
+    let mut hosts: Vec<String> = Vec::new();
+    for entry in input.split(',').map(str::trim) {
+        if entry.is_empty() {
+            return Err(format!("Empty entry in listen host list {}", input));
+        }
+        if hosts.iter().any(|host| host == entry) {
+            return Err(format!("Listen host {} is repeated", entry));
+        }
+        hosts.push(entry.to_string());
+    }
+    Ok(hosts)
+}
+
+/// Parses `HOST:PORT`, or a Unix socket path given as `/path` or `unix:path`.
+fn parse_target_address(input: &str) -> Result<TargetEndpoint, String> {
+    let path = match input.strip_prefix("unix:") {
+        Some(path) => Some(path),
+        None => input.starts_with('/').then_some(input),
+    };
+    if let Some(path) = path {
+        if path.is_empty() {
+            return Err(format!("Empty Unix socket path: {}", input));
+        }
+        return Ok(TargetEndpoint::UnixSocket(PathBuf::from(path)));
+    }
+    parse_host_port(input, 5201, AddressKind::Target)
+        .map(TargetEndpoint::Tcp)
+        .map_err(|err| err.to_string())
+}
+
+fn parse_fallback(input: &str) -> Result<FallbackSpec, String> {
+    let (matcher, address) = match input.split_once('=') {
+        Some((matcher, address)) => (matcher.parse()?, address),
+        None => (FallbackMatch::Any, input),
+    };
+    Ok(FallbackSpec {
+        matcher,
+        address: parse_fallback_address(address)?,
+    })
 }
 
-fn parse_domain(input: &str) -> Result<String, String> {
-    normalize_domain(input).map_err(|err| err.to_string())
+fn parse_fallback_list(input: &str) -> Result<Vec<FallbackSpec>, String> {
+    sip003::split_list(input)
+        .map_err(|err| err.to_string())?
+        .iter()
+        .map(|entry| parse_fallback(entry))
+        .collect()
 }
 
-fn parse_target_address(input: &str) -> Result<HostPort, String> {
-    parse_host_port(input, 5201, AddressKind::Target).map_err(|err| err.to_string())
+/// Rejects routes that can never be picked because a catch-all comes before them.
+fn validate_fallback_order(fallbacks: &[FallbackSpec]) -> Result<(), String> {
+    let catch_all = fallbacks
+        .iter()
+        .position(|fallback| fallback.matcher == FallbackMatch::Any);
+    match catch_all {
+        Some(index) if index + 1 < fallbacks.len() => {
+            Err("a fallback without a match expression must be the last one".to_string())
+        }
+        _ => Ok(()),
+    }
 }
 
 fn parse_fallback_address(input: &str) -> Result<HostPort, String> {
@@ -226,11 +852,155 @@ fn parse_max_connections(input: &str) -> Result<u32, String> {
     Ok(value)
 }
 
+fn parse_max_streams_per_connection(input: &str) -> Result<u32, String> {
+    let trimmed = input.trim();
+    let value = trimmed
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid max-streams-per-connection value: {}", trimmed))?;
+    if value == 0 {
+        return Err("max-streams-per-connection must be at least 1".to_string());
+    }
+    Ok(value)
+}
+
+fn parse_target_mode(input: &str) -> Result<TargetMode, String> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "fixed" => Ok(TargetMode::Fixed),
+        "connect-proxy" => Ok(TargetMode::ConnectProxy),
+        _ => Err(format!(
+            "Unsupported target mode (expected fixed or connect-proxy): {}",
+            input
+        )),
+    }
+}
+
+fn parse_connection_overflow_mode(input: &str) -> Result<ConnectionOverflowMode, String> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "drop" => Ok(ConnectionOverflowMode::Drop),
+        "refuse" => Ok(ConnectionOverflowMode::Refuse),
+        "queue" => Ok(ConnectionOverflowMode::Queue),
+        _ => Err(format!(
+            "Unsupported connection overflow mode (expected drop, refuse, or queue): {}",
+            input
+        )),
+    }
+}
+
+fn parse_connection_log_capacity(input: &str) -> Result<usize, String> {
+    let trimmed = input.trim();
+    trimmed
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid connection-log-capacity value: {}", trimmed))
+}
+
+fn parse_quic_mtu(input: &str) -> Result<u32, String> {
+    let trimmed = input.trim();
+    let value = trimmed
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid quic-mtu value: {}", trimmed))?;
+    validate_quic_mtu(value)
+}
+
+fn parse_max_qps_per_source(input: &str) -> Result<u32, String> {
+    let trimmed = input.trim();
+    trimmed
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid max-qps-per-source value: {}", trimmed))
+}
+
+fn parse_congestion_control(input: &str) -> Result<String, String> {
+    let trimmed = input.trim();
+    match trimmed {
+        "bbr" | "dcubic" => Ok(trimmed.to_string()),
+        _ => Err(format!(
+            "Invalid congestion-control value: {} (expected bbr or dcubic)",
+            trimmed
+        )),
+    }
+}
+
+fn parse_response_ttl(input: &str) -> Result<u32, String> {
+    let trimmed = input.trim();
+    let value = trimmed
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid response-ttl value: {}", trimmed))?;
+    // RFC 2181 limits TTLs to 31 bits.
+    if value > i32::MAX as u32 {
+        return Err(format!(
+            "response-ttl must be at most {}: {}",
+            i32::MAX,
+            trimmed
+        ));
+    }
+    Ok(value)
+}
+
+fn parse_metrics_listen(input: &str) -> Result<SocketAddr, String> {
+    let trimmed = input.trim();
+    trimmed.parse::<SocketAddr>().map_err(|_| {
+        format!(
+            "Invalid metrics-listen address (expected IP:PORT): {}",
+            trimmed
+        )
+    })
+}
+
+/// Parses the command line again with the `--config` file's options under it.
+///
+/// On error the command line alone is returned so logging can start before reporting it.
+fn apply_config_file(matches: ArgMatches) -> (ArgMatches, Option<ConfigError>) {
+    let Some(path) = matches.get_one::<String>("config") else {
+        return (matches, None);
+    };
+    match load_config_file(path, &matches) {
+        Ok(merged) => (merged, None),
+        Err(err) => (matches, Some(err)),
+    }
+}
+
+fn load_config_file(path: &str, matches: &ArgMatches) -> Result<ArgMatches, ConfigError> {
+    let file = ConfigFile::load(Path::new(path))?;
+    file.check_sections(&["server"])?;
+    let command = Args::command();
+    let mut args = FileArgs::new(&file, &command, matches);
+    for section in file.sections() {
+        args.push_section(section)?;
+    }
+    args.into_matches(std::env::args_os())
+}
+
 fn cli_provided(matches: &clap::ArgMatches, id: &str) -> bool {
     matches.value_source(id) == Some(ValueSource::CommandLine)
 }
 
-fn parse_domains_from_options(options: &[sip003::Sip003Option]) -> Result<Vec<String>, String> {
+/// `SS_PLUGIN_OPTIONS` keys the server consumes; anything else is rejected up front.
+fn plugin_option_schema() -> sip003::Sip003Schema {
+    use sip003::SchemaDef;
+
+    sip003::Sip003Schema::new(vec![
+        SchemaDef::new("domain").list_parsed_by(parse_domain),
+        SchemaDef::new("encoding").parsed_by(parse_encoding),
+        SchemaDef::new("cert"),
+        SchemaDef::new("key"),
+        SchemaDef::new("cert-pem"),
+        SchemaDef::new("key-pem"),
+        SchemaDef::new("reset-seed"),
+        SchemaDef::new("auth-secret"),
+        SchemaDef::new("fallback").parsed_by(parse_fallback_list),
+        SchemaDef::new("max-connections").parsed_by(parse_max_connections),
+        SchemaDef::new("max-streams-per-connection").parsed_by(parse_max_streams_per_connection),
+        SchemaDef::new("metrics-listen").parsed_by(parse_metrics_listen),
+        SchemaDef::new("connection-log-capacity").parsed_by(parse_connection_log_capacity),
+        SchemaDef::new("target-mode").parsed_by(parse_target_mode),
+        SchemaDef::new("quic-mtu").parsed_by(parse_quic_mtu),
+        SchemaDef::new("response-ttl").parsed_by(parse_response_ttl),
+        SchemaDef::new("max-qps-per-source").parsed_by(parse_max_qps_per_source),
+        SchemaDef::new("congestion-control").parsed_by(parse_congestion_control),
+        SchemaDef::new("address-family").parsed_by(parse_address_policy),
+    ])
+}
+
+fn parse_domains_from_options(options: &[sip003::Sip003Option]) -> Result<Vec<DomainArg>, String> {
     let mut domains = None;
     for option in options {
         if option.key == "domain" {
@@ -240,8 +1010,7 @@ fn parse_domains_from_options(options: &[sip003::Sip003Option]) -> Result<Vec<St
             let entries = sip003::split_list(&option.value).map_err(|err| err.to_string())?;
             let mut parsed = Vec::new();
             for entry in entries {
-                let normalized = normalize_domain(&entry).map_err(|err| err.to_string())?;
-                parsed.push(normalized);
+                parsed.push(parse_domain(&entry)?);
             }
             domains = Some(parsed);
         }
@@ -0,0 +1,403 @@
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use crate::server::{map_io, ServerError};
+use crate::streams::{ConnectionLog, ServerStreamMetrics};
+use slipstream_core::http::{
+    plain_response, read_request_head, request_target, response, REQUEST_TIMEOUT,
+};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// How a received UDP datagram was handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DnsOutcome {
+    /// Decoded as a tunnel query and handed to QUIC.
+    Quic,
+    /// Answered with an error RCODE.
+    Error,
+    /// Forwarded to the `--fallback` peer.
+    Fallback,
+    /// Discarded.
+    Drop,
+    /// Discarded by `--max-qps-per-source`.
+    RateLimited,
+}
+
+/// Whether a received UDP datagram parsed as a DNS query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PacketKind {
+    Dns,
+    NonDns,
+}
+
+/// Counters and gauges served by `--metrics-listen`.
+///
+/// The event loop and the picoquic callbacks update these with relaxed atomics; the HTTP task
+/// only reads them, so no locking is involved.
+#[derive(Default)]
+pub(crate) struct ServerMetrics {
+    connections_total: AtomicU64,
+    active_connections: AtomicU64,
+    bytes_rx_total: AtomicU64,
+    bytes_tx_total: AtomicU64,
+    dns_queries_quic: AtomicU64,
+    dns_queries_error: AtomicU64,
+    dns_queries_fallback: AtomicU64,
+    dns_queries_drop: AtomicU64,
+    dns_queries_rate_limited: AtomicU64,
+    idle_gc_evictions_total: AtomicU64,
+    fallback_sessions_active: AtomicU64,
+    streams_total: AtomicU64,
+    active_streams: AtomicU64,
+    stream_queued_bytes_total: AtomicU64,
+    stream_queued_bytes_max: AtomicU64,
+    packets_dns: AtomicU64,
+    packets_non_dns: AtomicU64,
+}
+
+impl ServerMetrics {
+    pub(crate) fn record_connection_ready(&self) {
+        self.connections_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_active_connections(&self, count: usize) {
+        self.active_connections
+            .store(count as u64, Ordering::Relaxed);
+    }
+
+    /// Counts stream bytes received from clients.
+    pub(crate) fn add_bytes_rx(&self, bytes: usize) {
+        self.bytes_rx_total
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts stream bytes sent to clients.
+    pub(crate) fn add_bytes_tx(&self, bytes: usize) {
+        self.bytes_tx_total
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dns_query(&self, outcome: DnsOutcome) {
+        let counter = match outcome {
+            DnsOutcome::Quic => &self.dns_queries_quic,
+            DnsOutcome::Error => &self.dns_queries_error,
+            DnsOutcome::Fallback => &self.dns_queries_fallback,
+            DnsOutcome::Drop => &self.dns_queries_drop,
+            DnsOutcome::RateLimited => &self.dns_queries_rate_limited,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_idle_gc_eviction(&self) {
+        self.idle_gc_evictions_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_fallback_sessions_active(&self, count: usize) {
+        self.fallback_sessions_active
+            .store(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_stream_opened(&self) {
+        self.streams_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_stream_gauges(&self, streams: &ServerStreamMetrics) {
+        self.active_streams
+            .store(streams.streams_total as u64, Ordering::Relaxed);
+        self.stream_queued_bytes_total
+            .store(streams.queued_bytes_total, Ordering::Relaxed);
+        self.stream_queued_bytes_max
+            .store(streams.queued_bytes_max, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_packet(&self, kind: PacketKind) {
+        let counter = match kind {
+            PacketKind::Dns => &self.packets_dns,
+            PacketKind::NonDns => &self.packets_non_dns,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut out = String::new();
+        write_metric(
+            &mut out,
+            "slipstream_connections_total",
+            "counter",
+            "QUIC connections that completed the handshake.",
+            &[("", load(&self.connections_total))],
+        );
+        write_metric(
+            &mut out,
+            "slipstream_active_connections",
+            "gauge",
+            "QUIC connections currently open.",
+            &[("", load(&self.active_connections))],
+        );
+        write_metric(
+            &mut out,
+            "slipstream_bytes_tunneled_total",
+            "counter",
+            "Stream bytes carried through the tunnel.",
+            &[
+                ("direction=\"rx\"", load(&self.bytes_rx_total)),
+                ("direction=\"tx\"", load(&self.bytes_tx_total)),
+            ],
+        );
+        write_metric(
+            &mut out,
+            "slipstream_dns_queries_total",
+            "counter",
+            "UDP datagrams received on the DNS listener, by outcome.",
+            &[
+                ("outcome=\"quic\"", load(&self.dns_queries_quic)),
+                ("outcome=\"error\"", load(&self.dns_queries_error)),
+                ("outcome=\"fallback\"", load(&self.dns_queries_fallback)),
+                ("outcome=\"drop\"", load(&self.dns_queries_drop)),
+                (
+                    "outcome=\"rate_limited\"",
+                    load(&self.dns_queries_rate_limited),
+                ),
+            ],
+        );
+        write_metric(
+            &mut out,
+            "slipstream_idle_gc_evictions_total",
+            "counter",
+            "Connections closed by the idle timeout.",
+            &[("", load(&self.idle_gc_evictions_total))],
+        );
+        write_metric(
+            &mut out,
+            "slipstream_fallback_sessions_active",
+            "gauge",
+            "Peers currently forwarded to the fallback address.",
+            &[("", load(&self.fallback_sessions_active))],
+        );
+        write_metric(
+            &mut out,
+            "slipstream_streams_total",
+            "counter",
+            "Streams opened by clients.",
+            &[("", load(&self.streams_total))],
+        );
+        write_metric(
+            &mut out,
+            "slipstream_active_streams",
+            "gauge",
+            "Streams currently open across all connections.",
+            &[("", load(&self.active_streams))],
+        );
+        write_metric(
+            &mut out,
+            "slipstream_stream_queued_bytes",
+            "gauge",
+            "Bytes received from clients and not yet written to the target.",
+            &[
+                ("stat=\"total\"", load(&self.stream_queued_bytes_total)),
+                ("stat=\"max\"", load(&self.stream_queued_bytes_max)),
+            ],
+        );
+        write_metric(
+            &mut out,
+            "slipstream_packets_total",
+            "counter",
+            "UDP datagrams received on the DNS listener, by whether they parsed as DNS.",
+            &[
+                ("kind=\"dns\"", load(&self.packets_dns)),
+                ("kind=\"non_dns\"", load(&self.packets_non_dns)),
+            ],
+        );
+        out
+    }
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, u64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+/// Binds `addr` and serves `GET /metrics` and `GET /connections` from a separate task.
+pub(crate) async fn spawn_metrics_server(
+    addr: SocketAddr,
+    metrics: Arc<ServerMetrics>,
+    connection_log: Arc<ConnectionLog>,
+) -> Result<SocketAddr, ServerError> {
+    let listener = TcpListener::bind(addr).await.map_err(|err| {
+        ServerError::new(format!("Failed to bind metrics address {}: {}", addr, err))
+    })?;
+    let local_addr = listener.local_addr().map_err(map_io)?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let metrics = metrics.clone();
+                    let connection_log = connection_log.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = serve_connection(stream, &metrics, &connection_log).await
+                        {
+                            tracing::debug!("metrics request failed: {}", err);
+                        }
+                    });
+                }
+                Err(err) => tracing::warn!("metrics accept failed: {}", err),
+            }
+        }
+    });
+    Ok(local_addr)
+}
+
+async fn serve_connection(
+    mut stream: TcpStream,
+    metrics: &ServerMetrics,
+    connection_log: &ConnectionLog,
+) -> std::io::Result<()> {
+    let Ok(head) = timeout(REQUEST_TIMEOUT, read_request_head(&mut stream)).await else {
+        return Ok(());
+    };
+    let response = match head? {
+        Some(head) => respond(&head, metrics, connection_log),
+        None => plain_response("400 Bad Request", "bad request\n"),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn respond(head: &str, metrics: &ServerMetrics, connection_log: &ConnectionLog) -> String {
+    let Some((method, path)) = request_target(head) else {
+        return plain_response("400 Bad Request", "bad request\n");
+    };
+    match (method, path) {
+        ("GET", "/metrics") => response("200 OK", CONTENT_TYPE, &metrics.render()),
+        ("GET", "/connections") => response("200 OK", JSON_CONTENT_TYPE, &connection_log.to_json()),
+        (_, "/metrics" | "/connections") => {
+            plain_response("405 Method Not Allowed", "method not allowed\n")
+        }
+        _ => plain_response("404 Not Found", "not found\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streams::{CloseReason, ConnectionRecord};
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn render_uses_prometheus_text_format() {
+        let metrics = ServerMetrics::default();
+        metrics.record_connection_ready();
+        metrics.set_active_connections(1);
+        metrics.add_bytes_rx(100);
+        metrics.add_bytes_tx(250);
+        metrics.record_dns_query(DnsOutcome::Quic);
+        metrics.record_dns_query(DnsOutcome::Quic);
+        metrics.record_dns_query(DnsOutcome::Drop);
+        metrics.record_idle_gc_eviction();
+        metrics.set_fallback_sessions_active(2);
+        metrics.record_stream_opened();
+        metrics.set_stream_gauges(&ServerStreamMetrics {
+            streams_total: 1,
+            queued_bytes_total: 4096,
+            queued_bytes_max: 4096,
+            ..ServerStreamMetrics::default()
+        });
+        metrics.record_packet(PacketKind::Dns);
+        metrics.record_packet(PacketKind::NonDns);
+        metrics.record_packet(PacketKind::NonDns);
+
+        let body = metrics.render();
+        assert!(body.contains("# TYPE slipstream_connections_total counter\n"));
+        assert!(body.contains("\nslipstream_connections_total 1\n"));
+        assert!(body.contains("\nslipstream_active_connections 1\n"));
+        assert!(body.contains("\nslipstream_bytes_tunneled_total{direction=\"rx\"} 100\n"));
+        assert!(body.contains("\nslipstream_bytes_tunneled_total{direction=\"tx\"} 250\n"));
+        assert!(body.contains("\nslipstream_dns_queries_total{outcome=\"quic\"} 2\n"));
+        assert!(body.contains("\nslipstream_dns_queries_total{outcome=\"error\"} 0\n"));
+        assert!(body.contains("\nslipstream_dns_queries_total{outcome=\"drop\"} 1\n"));
+        assert!(body.contains("\nslipstream_idle_gc_evictions_total 1\n"));
+        assert!(body.contains("\nslipstream_fallback_sessions_active 2\n"));
+        assert!(body.contains("\nslipstream_streams_total 1\n"));
+        assert!(body.contains("\nslipstream_active_streams 1\n"));
+        assert!(body.contains("\nslipstream_stream_queued_bytes{stat=\"max\"} 4096\n"));
+        assert!(body.contains("\nslipstream_packets_total{kind=\"dns\"} 1\n"));
+        assert!(body.contains("\nslipstream_packets_total{kind=\"non_dns\"} 2\n"));
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_over_http() {
+        let metrics = Arc::new(ServerMetrics::default());
+        metrics.record_connection_ready();
+        let connection_log = Arc::new(ConnectionLog::default());
+        connection_log.push(ConnectionRecord {
+            cnx_id: 7,
+            client_addr: Some("192.0.2.1:53".parse().unwrap()),
+            connected_at_ms: 1_000,
+            disconnected_at_ms: 2_000,
+            close_reason: CloseReason::Application,
+            bytes_sent: 10,
+            bytes_received: 20,
+            streams: 1,
+            flow_history: Default::default(),
+        });
+        let addr = spawn_metrics_server("127.0.0.1:0".parse().unwrap(), metrics, connection_log)
+            .await
+            .expect("bind metrics");
+
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .expect("write request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("read response");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("\nslipstream_connections_total 1\n"));
+
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        stream
+            .write_all(b"GET /connections HTTP/1.1\r\n\r\n")
+            .await
+            .expect("write request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("read response");
+        assert!(response.contains("Content-Type: application/json\r\n"));
+        assert!(response.ends_with(
+            "\r\n\r\n[{\"cnx_id\":7,\"client_addr\":\"192.0.2.1:53\",\"connected_at_ms\":1000,\"disconnected_at_ms\":2000,\"close_reason\":\"application\",\"bytes_sent\":10,\"bytes_received\":20,\"streams\":1,\"flow_history\":[]}]"
+        ), "{}", response);
+
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        stream
+            .write_all(b"GET / HTTP/1.1\r\n\r\n")
+            .await
+            .expect("write request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("read response");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
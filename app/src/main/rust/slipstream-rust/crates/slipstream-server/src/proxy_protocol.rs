@@ -0,0 +1,116 @@
+use std::net::{IpAddr, SocketAddr};
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const VERSION_2_LOCAL: u8 = 0x20;
+const VERSION_2_PROXY: u8 = 0x21;
+const AF_UNSPEC: u8 = 0x00;
+const TCP_OVER_IPV4: u8 = 0x11;
+const TCP_OVER_IPV6: u8 = 0x21;
+
+/// Encodes a PROXY protocol v2 header announcing a TCP connection from `src` to `dst`.
+///
+/// IPv4-mapped IPv6 addresses are unwrapped first; if the families still differ, the
+/// IPv4 side is mapped so both addresses share the IPv6 block.
+pub(crate) fn encode_v2(src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+    let src_ip = canonical_ip(src.ip());
+    let dst_ip = canonical_ip(dst.ip());
+    let mut header = Vec::with_capacity(SIGNATURE.len() + 4 + 36);
+    header.extend_from_slice(&SIGNATURE);
+    header.push(VERSION_2_PROXY);
+    match (src_ip, dst_ip) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            header.push(TCP_OVER_IPV4);
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&src_ip.octets());
+            header.extend_from_slice(&dst_ip.octets());
+        }
+        (src_ip, dst_ip) => {
+            header.push(TCP_OVER_IPV6);
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&ipv6_octets(src_ip));
+            header.extend_from_slice(&ipv6_octets(dst_ip));
+        }
+    }
+    header.extend_from_slice(&src.port().to_be_bytes());
+    header.extend_from_slice(&dst.port().to_be_bytes());
+    header
+}
+
+/// Encodes a PROXY protocol v2 `LOCAL` header, used when the client address is unknown.
+///
+/// Receivers keep the real connection endpoints for `LOCAL` headers.
+pub(crate) fn encode_v2_local() -> Vec<u8> {
+    let mut header = Vec::with_capacity(SIGNATURE.len() + 4);
+    header.extend_from_slice(&SIGNATURE);
+    header.push(VERSION_2_LOCAL);
+    header.push(AF_UNSPEC);
+    header.extend_from_slice(&0u16.to_be_bytes());
+    header
+}
+
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        ip => ip,
+    }
+}
+
+fn ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+        IpAddr::V6(v6) => v6.octets(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn encodes_ipv4_header() {
+        let src = SocketAddr::from(([192, 0, 2, 1], 40000));
+        let dst = SocketAddr::from(([127, 0, 0, 1], 5201));
+        let header = encode_v2(src, dst);
+        assert_eq!(&header[..12], &SIGNATURE);
+        assert_eq!(&header[12..16], &[0x21, 0x11, 0x00, 0x0c]);
+        assert_eq!(&header[16..20], &[192, 0, 2, 1]);
+        assert_eq!(&header[20..24], &[127, 0, 0, 1]);
+        assert_eq!(&header[24..], &[0x9c, 0x40, 0x14, 0x51]);
+    }
+
+    #[test]
+    fn encodes_ipv6_header_and_maps_mixed_families() {
+        let src_ip = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let src = SocketAddr::from((src_ip, 53));
+        let dst = SocketAddr::from((Ipv4Addr::new(127, 0, 0, 1), 5201));
+        let header = encode_v2(src, dst);
+        assert_eq!(header.len(), 16 + 36);
+        assert_eq!(&header[12..16], &[0x21, 0x21, 0x00, 0x24]);
+        assert_eq!(&header[16..32], &src_ip.octets());
+        assert_eq!(
+            &header[32..48],
+            &Ipv4Addr::new(127, 0, 0, 1).to_ipv6_mapped().octets()
+        );
+        assert_eq!(&header[48..], &[0x00, 0x35, 0x14, 0x51]);
+    }
+
+    #[test]
+    fn unwraps_ipv4_mapped_sources() {
+        let src = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 7).to_ipv6_mapped(), 1234));
+        let dst = SocketAddr::from(([10, 0, 0, 1], 80));
+        let header = encode_v2(src, dst);
+        assert_eq!(header[13], TCP_OVER_IPV4);
+        assert_eq!(&header[16..20], &[192, 0, 2, 7]);
+    }
+
+    #[test]
+    fn local_header_has_no_addresses() {
+        let header = encode_v2_local();
+        assert_eq!(header.len(), 16);
+        assert_eq!(&header[12..], &[0x20, 0x00, 0x00, 0x00]);
+    }
+}
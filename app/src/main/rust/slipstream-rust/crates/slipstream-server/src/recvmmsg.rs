@@ -0,0 +1,142 @@
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+/// Datagrams pulled per `recvmmsg` call.
+pub(crate) const RECVMMSG_BATCH: usize = 32;
+
+/// Reusable buffers for receiving several datagrams with one `recvmmsg` call.
+pub(crate) struct RecvBatch {
+    bufs: Vec<Vec<u8>>,
+    addrs: Vec<libc::sockaddr_storage>,
+    lens: Vec<usize>,
+    peers: Vec<Option<SocketAddr>>,
+}
+
+impl RecvBatch {
+    /// `None` off Linux, where the caller keeps the `try_recv_from` loop.
+    pub(crate) fn new(batch: usize, buf_len: usize) -> Option<Self> {
+        if !cfg!(target_os = "linux") {
+            tracing::warn!("--recvmmsg needs Linux; receiving one datagram per call");
+            return None;
+        }
+        Some(Self {
+            bufs: vec![vec![0u8; buf_len]; batch],
+            // SAFETY: sockaddr_storage is plain-old-data; zeroing is valid.
+            addrs: vec![unsafe { std::mem::zeroed() }; batch],
+            lens: vec![0; batch],
+            peers: vec![None; batch],
+        })
+    }
+
+    /// Receives up to the batch size without waiting, returning how many datagrams arrived.
+    ///
+    /// `WouldBlock` means nothing was queued, like `try_recv_from`.
+    pub(crate) fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        socket.try_io(tokio::io::Interest::READABLE, || self.recv_raw(socket))
+    }
+
+    /// The first `count` datagrams of the last `recv`, in arrival order with their senders.
+    ///
+    /// Datagrams from an address family other than IPv4/IPv6 are skipped.
+    pub(crate) fn packets(&self, count: usize) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        (0..count).filter_map(move |i| Some((&self.bufs[i][..self.lens[i]], self.peers[i]?)))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn recv_raw(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        use std::os::fd::AsRawFd;
+
+        let mut iovs: Vec<libc::iovec> = self
+            .bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = iovs
+            .iter_mut()
+            .zip(self.addrs.iter_mut())
+            .map(|(iov, addr)| {
+                // SAFETY: mmsghdr is plain-old-data; zeroing is valid.
+                let mut msg: libc::mmsghdr = unsafe { std::mem::zeroed() };
+                msg.msg_hdr.msg_name = addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+                msg.msg_hdr.msg_namelen =
+                    std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                msg.msg_hdr.msg_iov = iov;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+        // SAFETY: every mmsghdr points at a live buffer and address slot owned by `self`.
+        let count = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                msgs.as_mut_ptr(),
+                msgs.len() as _,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            )
+        };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let count = count as usize;
+        for (i, msg) in msgs[..count].iter().enumerate() {
+            self.lens[i] = msg.msg_len as usize;
+            let mut storage = socket2::SockAddrStorage::zeroed();
+            // SAFETY: the kernel filled `addrs[i]` with `msg_namelen` bytes of a sockaddr.
+            let addr = unsafe {
+                *storage.view_as::<libc::sockaddr_storage>() = self.addrs[i];
+                socket2::SockAddr::new(storage, msg.msg_hdr.msg_namelen)
+            };
+            self.peers[i] = addr.as_socket();
+        }
+        Ok(count)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn recv_raw(&mut self, _socket: &UdpSocket) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn receives_a_burst_in_order_with_senders() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let first = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let second = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        first.send_to(b"one", addr).unwrap();
+        second.send_to(b"two", addr).unwrap();
+        first.send_to(b"three", addr).unwrap();
+
+        let mut batch = RecvBatch::new(2, 16).unwrap();
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            socket.readable().await.unwrap();
+            match batch.recv(&socket) {
+                Ok(count) => received.extend(
+                    batch
+                        .packets(count)
+                        .map(|(packet, peer)| (packet.to_vec(), peer)),
+                ),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => panic!("recvmmsg failed: {}", err),
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                (b"one".to_vec(), first.local_addr().unwrap()),
+                (b"two".to_vec(), second.local_addr().unwrap()),
+                (b"three".to_vec(), first.local_addr().unwrap()),
+            ]
+        );
+    }
+}
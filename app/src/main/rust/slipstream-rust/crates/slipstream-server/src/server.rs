@@ -1,86 +1,228 @@
-use crate::config::{ensure_cert_key, load_or_create_reset_seed, ResetSeed};
-use crate::udp_fallback::{handle_packet, FallbackManager, PacketContext, MAX_UDP_PACKET_SIZE};
+use crate::config::{
+    ensure_cert_key, load_or_create_reset_seed, load_or_create_ticket_key, rotate_reset_seed,
+    CertIdentity, ResetSeed, TicketKey,
+};
+use crate::connection_limit::ConnectionOverflowMode;
+use crate::gso::{plan_batches, BatchSender, Outgoing};
+use crate::metrics::{spawn_metrics_server, DnsOutcome, ServerMetrics};
+use crate::recvmmsg::{RecvBatch, RECVMMSG_BATCH};
+use crate::source_filter::SourceFilter;
+use crate::udp_fallback::{
+    admit_queued_connection, handle_packet, FallbackManager, FallbackMatch, FallbackRoute,
+    PacketContext, SourceRateLimiter, MAX_UDP_PACKET_SIZE,
+};
+use slipstream_core::proxy_frame::ProxyTarget;
+use slipstream_core::stream_priority::StreamPriorityMode;
 use slipstream_core::{
-    net::is_transient_udp_error, normalize_dual_stack_addr, resolve_host_port, HostPort,
+    assert_invariant, net::is_transient_udp_error, normalize_dual_stack_addr, resolve_host_port,
+    wildcard_base, AddressPolicy, HostPort,
+};
+use slipstream_dns::{
+    encode_response, hmac, max_response_payload_len, max_udp_txt_payload_len, DnsError,
+    EdnsOptions, Encoding, Question, Rcode, ResponseParams,
 };
-use slipstream_dns::{encode_response, Question, Rcode, ResponseParams};
 use slipstream_ffi::picoquic::{
-    picoquic_cnx_t, picoquic_create, picoquic_current_time, picoquic_delete_cnx,
-    picoquic_get_first_cnx, picoquic_get_next_cnx, picoquic_prepare_packet_ex, picoquic_quic_t,
-    slipstream_has_ready_stream, slipstream_is_flow_blocked, slipstream_server_cc_algorithm,
-    PICOQUIC_MAX_PACKET_SIZE, PICOQUIC_PACKET_LOOP_RECV_MAX,
+    picoquic_cnx_t, picoquic_create, picoquic_current_number_connections, picoquic_current_time,
+    picoquic_delete_cnx, picoquic_get_first_cnx, picoquic_get_next_cnx, picoquic_prepare_packet_ex,
+    picoquic_quic_t, slipstream_enable_datagrams, slipstream_has_ready_stream,
+    slipstream_is_flow_blocked, slipstream_server_cc_algorithm, slipstream_set_reset_seed,
+    PICOQUIC_MAX_PACKET_SIZE, PICOQUIC_PACKET_LOOP_RECV_MAX, PICOQUIC_RESET_SECRET_SIZE,
 };
 use slipstream_ffi::{
-    configure_quic_with_custom, socket_addr_to_storage, take_crypto_errors, QuicGuard,
+    configure_quic, configure_quic_with_custom, socket_addr_to_storage, take_crypto_errors,
+    QuicGuard,
 };
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 use std::collections::HashMap;
 use std::ffi::CString;
 use std::fmt;
-use std::net::SocketAddr;
-use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::task::Poll;
 use std::time::{Duration, Instant};
 use tokio::net::{lookup_host, UdpSocket as TokioUdpSocket};
 use tokio::sync::mpsc;
-use tokio::time::sleep;
+use tokio::time::{sleep, sleep_until};
 
 use crate::streams::{
-    drain_commands, handle_command, handle_shutdown, maybe_report_command_stats,
-    remove_connection_streams, server_callback, ServerState,
+    cancel_connections, drain_commands, handle_command, handle_shutdown,
+    maybe_report_command_stats, remove_connection_streams, server_callback, CloseReason,
+    ConnectionLog, ServerState,
 };
+use crate::target::{resolve_dial_addrs, TargetAddr, TargetEndpoint};
 
 // Protocol defaults; see docs/config.md for details.
 const SLIPSTREAM_ALPN: &str = "picoquic_sample";
 const DNS_MAX_QUERY_SIZE: usize = 512;
 const IDLE_SLEEP_MS: u64 = 10;
 const IDLE_GC_INTERVAL: Duration = Duration::from_secs(1);
-// Default QUIC MTU for server packets; see docs/config.md for details.
-const QUIC_MTU: u32 = 900;
+const STREAM_METRICS_INTERVAL: Duration = Duration::from_secs(1);
 pub(crate) const STREAM_READ_CHUNK_BYTES: usize = 4096;
 pub(crate) const DEFAULT_TCP_RCVBUF_BYTES: usize = 256 * 1024;
 pub(crate) const TARGET_WRITE_COALESCE_DEFAULT_BYTES: usize = 256 * 1024;
 const FLOW_BLOCKED_LOG_INTERVAL_US: u64 = 1_000_000;
+// How long connections closed at the drain deadline get to deliver the close to their peers.
+const CLOSE_GRACE: Duration = Duration::from_secs(2);
 
-static SHOULD_SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static SIGTERM_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 extern "C" fn handle_sigterm(_signum: libc::c_int) {
-    SHOULD_SHUTDOWN.store(true, Ordering::Relaxed);
+    SIGTERM_COUNT.fetch_add(1, Ordering::Relaxed);
 }
 
 #[derive(Debug)]
 pub struct ServerError {
     message: String,
+    /// picoquic crypto errors behind the failure, oldest first.
+    crypto_errors: Vec<String>,
+    /// The DNS error this one surfaces, reachable through `Error::source`.
+    dns: Option<DnsError>,
 }
 
 impl ServerError {
     pub(crate) fn new(message: impl Into<String>) -> Self {
         Self {
             message: message.into(),
+            crypto_errors: Vec::new(),
+            dns: None,
+        }
+    }
+
+    pub(crate) fn crypto(message: impl Into<String>, crypto_errors: Vec<String>) -> Self {
+        Self {
+            message: message.into(),
+            crypto_errors,
+            dns: None,
         }
     }
 }
 
 impl fmt::Display for ServerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message)
+        if self.crypto_errors.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(
+                f,
+                "{} (TLS errors: {})",
+                self.message,
+                self.crypto_errors.join("; ")
+            )
+        }
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.dns
+            .as_ref()
+            .map(|err| err as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<DnsError> for ServerError {
+    fn from(err: DnsError) -> Self {
+        Self {
+            message: err.to_string(),
+            crypto_errors: Vec::new(),
+            dns: Some(err),
+        }
     }
 }
 
-impl std::error::Error for ServerError {}
+/// Where stream data goes once a client opens a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TargetMode {
+    /// Every stream connects to `target_address`.
+    #[default]
+    Fixed,
+    /// Every stream starts with a connect-proxy frame naming the host and port to dial.
+    ConnectProxy,
+}
+
+/// A `--fallback` backend and the first packets it claims.
+#[derive(Debug, Clone)]
+pub struct FallbackSpec {
+    pub matcher: FallbackMatch,
+    pub address: HostPort,
+}
+
+/// A `--domain DOMAIN=TARGET` route: streams of connections under `domain` go to `address`.
+#[derive(Debug, Clone)]
+pub struct DomainTarget {
+    pub domain: String,
+    pub address: TargetEndpoint,
+}
 
 pub struct ServerConfig {
-    pub dns_listen_host: String,
+    /// Each host gets its own UDP socket on `dns_listen_port`, all feeding one QUIC context.
+    pub dns_listen_hosts: Vec<String>,
     pub dns_listen_port: u16,
-    pub target_address: HostPort,
-    pub fallback_address: Option<HostPort>,
+    /// Address families the listeners, target, and fallbacks may use; only `Any` binds
+    /// dual-stack sockets.
+    pub address_policy: AddressPolicy,
+    pub target_address: TargetEndpoint,
+    /// Tried in order for the packet that opens a fallback session; the first match wins.
+    pub fallbacks: Vec<FallbackSpec>,
     pub cert: String,
     pub key: String,
+    /// Subject CN and SubjectAltNames for a self-signed cert generated at `cert`/`key`.
+    pub cert_identity: CertIdentity,
     pub reset_seed_path: Option<String>,
+    /// File holding the shared secret queries must carry an HMAC label for; queries without
+    /// a valid one are refused.
+    pub auth_secret_path: Option<String>,
+    /// Hours between regenerating the reset seed file and applying it in place; 0 disables.
+    pub reset_seed_rotation_hours: u64,
+    /// File holding the session ticket key, so tickets (and 0-RTT) outlive a restart.
+    pub ticket_store_path: Option<String>,
     pub domains: Vec<String>,
+    /// Per-domain targets; domains without one use `target_address`.
+    pub domain_targets: Vec<DomainTarget>,
+    pub encoding: Encoding,
     pub max_connections: u32,
+    /// What happens to connections opened past `max_connections`.
+    pub connection_overflow_mode: ConnectionOverflowMode,
+    pub max_streams_per_connection: u32,
     pub idle_timeout_seconds: u64,
+    pub idle_drain_timeout_seconds: u64,
+    pub metrics_listen: Option<SocketAddr>,
+    /// Closed connections kept for `/connections` on the metrics endpoint.
+    pub connection_log_capacity: usize,
+    /// Prefix target connections with a PROXY protocol v2 header naming the client.
+    pub target_proxy_protocol: bool,
+    pub target_mode: TargetMode,
+    /// Forward the UDP flows clients send in QUIC DATAGRAM frames to the target.
+    pub datagram_mode: bool,
+    /// Per-stream picoquic priorities; `None` keeps every stream on the shared default.
+    pub stream_priority_mode: Option<StreamPriorityMode>,
+    pub quic_mtu: u32,
+    /// TTL in seconds written into answer records; 0 asks resolvers not to cache them.
+    pub response_ttl: u32,
+    /// Aggregate kilobits per second each connection may write to its targets.
+    pub per_connection_rate_limit_kbps: Option<u32>,
+    /// Milliseconds a target connect or write may block before the stream is reset; 0 waits
+    /// forever.
+    pub target_write_timeout_ms: u64,
+    /// Datagrams per second accepted from each source address; 0 is unlimited.
+    pub max_qps_per_source: u32,
+    /// `--allow-source`/`--deny-source` rules; datagrams from refused sources are dropped.
+    pub source_filter: SourceFilter,
+    /// picoquic algorithm name (`bbr` or `dcubic`); `None` keeps the slipstream server algorithm.
+    pub congestion_control: Option<String>,
+    /// Never set AD in answers, even for a query that set it; CD is echoed either way.
+    pub dnssec_passthrough: bool,
+    /// Write answer owner names as pointers to earlier names; `--no-name-compression` spells
+    /// them out in full for packet captures.
+    pub compress_names: bool,
+    /// Coalesce answers to the same peer into one `UDP_SEGMENT` send.
+    pub gso: bool,
+    /// Receive queries in batches with `recvmmsg` on Linux.
+    pub recvmmsg: bool,
+    /// Report send batching once per second.
+    pub debug_poll: bool,
     pub debug_streams: bool,
     pub debug_commands: bool,
 }
@@ -130,13 +272,29 @@ pub(crate) enum Command {
         stream_id: u64,
         bytes: usize,
     },
+    /// A reply from the target of a `--datagram-mode` flow.
+    DatagramReceived {
+        cnx_id: usize,
+        flow_id: u64,
+        /// Generation of the flow that read the reply; a reply of a closed flow is dropped.
+        generation: u64,
+        data: Vec<u8>,
+    },
+    DatagramFlowClosed {
+        cnx_id: usize,
+        flow_id: u64,
+    },
 }
 
 pub(crate) struct Slot {
     pub(crate) peer: SocketAddr,
+    /// Index of the listener the query arrived on; the answer leaves through it.
+    pub(crate) listener: usize,
     pub(crate) id: u16,
     pub(crate) rd: bool,
     pub(crate) cd: bool,
+    /// The query's AD bit; see [`answer_sets_ad`].
+    pub(crate) ad: bool,
     pub(crate) question: Question,
     pub(crate) rcode: Option<Rcode>,
     pub(crate) cnx: *mut picoquic_cnx_t,
@@ -147,12 +305,14 @@ pub(crate) struct Slot {
 pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
     let cert_path = Path::new(&config.cert);
     let key_path = Path::new(&config.key);
-    let generated = ensure_cert_key(cert_path, key_path).map_err(ServerError::new)?;
+    let generated =
+        ensure_cert_key(cert_path, key_path, &config.cert_identity).map_err(ServerError::new)?;
     if generated {
         tracing::warn!(
-            "Generated self-signed TLS cert/key at {} and {} (ECDSA P-256, 1000y validity); replace for production use",
-            cert_path.display(),
-            key_path.display()
+            cert = %cert_path.display(),
+            key = %key_path.display(),
+            key_type = config.cert_identity.key_type.label(),
+            "Generated self-signed TLS cert/key (1000y validity); replace for production use"
         );
     }
 
@@ -160,8 +320,8 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
         let seed = load_or_create_reset_seed(Path::new(path)).map_err(ServerError::new)?;
         if seed.created {
             tracing::warn!(
-                "Reset seed created at {}; stateless resets will now survive restarts",
-                path
+                path = %path,
+                "Reset seed created; stateless resets will now survive restarts"
             );
         } else {
             tracing::debug!("Loaded reset seed from {}", path);
@@ -174,15 +334,56 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
         None
     };
 
-    let target_addr = resolve_host_port(&config.target_address)
-        .map_err(|err| ServerError::new(err.to_string()))?;
-    let fallback_addr = match &config.fallback_address {
-        Some(address) => {
-            Some(resolve_host_port(address).map_err(|err| ServerError::new(err.to_string()))?)
+    let ticket_key: Option<TicketKey> = match &config.ticket_store_path {
+        Some(path) => {
+            let key = load_or_create_ticket_key(Path::new(path)).map_err(ServerError::new)?;
+            if key.created {
+                tracing::info!(path = %path, "Session ticket key created");
+            }
+            tracing::warn!(
+                path = %path,
+                "Session tickets now survive restarts; 0-RTT data on resumed connections can be \
+                 replayed by anyone who captured it, so only tunnel idempotent traffic"
+            );
+            Some(key)
         }
         None => None,
     };
 
+    let auth_secret = config
+        .auth_secret_path
+        .as_deref()
+        .map(|path| {
+            hmac::load_secret(path).map_err(|err| {
+                ServerError::new(format!("Cannot read auth secret {}: {}", path, err))
+            })
+        })
+        .transpose()?;
+
+    let target_addr = resolve_target(&config.target_address, config.address_policy).await?;
+    check_domain_targets(&config.domain_targets)?;
+    let mut domain_targets = Vec::new();
+    if !config.domain_targets.is_empty() {
+        for domain in &config.domains {
+            let route = config
+                .domain_targets
+                .iter()
+                .find(|route| route.domain == *domain);
+            domain_targets.push(match route {
+                Some(route) => resolve_target(&route.address, config.address_policy).await?,
+                None => target_addr.clone(),
+            });
+        }
+    }
+    let mut fallback_routes = Vec::with_capacity(config.fallbacks.len());
+    for fallback in &config.fallbacks {
+        fallback_routes.push(FallbackRoute {
+            matcher: fallback.matcher.clone(),
+            addr: resolve_host_port(&fallback.address)
+                .map_err(|err| ServerError::new(err.to_string()))?,
+        });
+    }
+
     let alpn = CString::new(SLIPSTREAM_ALPN)
         .map_err(|_| ServerError::new("ALPN contains an unexpected null byte"))?;
     let cert = CString::new(config.cert.clone())
@@ -193,12 +394,27 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
     let debug_streams = config.debug_streams;
     let debug_commands = config.debug_commands;
     let idle_timeout = Duration::from_secs(config.idle_timeout_seconds);
+    let idle_drain_timeout = Duration::from_secs(config.idle_drain_timeout_seconds);
+    let metrics = Arc::new(ServerMetrics::default());
+    let connection_log = Arc::new(ConnectionLog::new(config.connection_log_capacity));
     let mut state = Box::new(ServerState::new(
         target_addr,
         command_tx,
+        config.max_streams_per_connection,
         debug_streams,
         debug_commands,
+        metrics.clone(),
+        connection_log.clone(),
     ));
+    state.set_target_proxy_protocol(config.target_proxy_protocol);
+    state.set_target_mode(config.target_mode);
+    state.set_address_policy(config.address_policy);
+    state.set_domain_targets(domain_targets);
+    state.set_target_rate_limit(config.per_connection_rate_limit_kbps);
+    state.set_connection_limit(config.max_connections, config.connection_overflow_mode);
+    state.set_target_write_timeout(config.target_write_timeout_ms);
+    state.set_datagram_mode(config.datagram_mode);
+    state.set_stream_priority_mode(config.stream_priority_mode);
     let state_ptr: *mut ServerState = &mut *state;
     let _state = state;
 
@@ -207,6 +423,12 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
         .as_ref()
         .map(|seed| seed.bytes.as_ptr())
         .unwrap_or(std::ptr::null());
+    // The server stores no tickets of its own; the key lets it decrypt the ones it issued
+    // before a restart.
+    let (ticket_key_ptr, ticket_key_len) =
+        ticket_key.as_ref().map_or((std::ptr::null(), 0), |key| {
+            (key.bytes.as_ptr(), key.bytes.len())
+        });
     let quic = unsafe {
         picoquic_create(
             config.max_connections,
@@ -222,46 +444,94 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
             current_time,
             std::ptr::null_mut(),
             std::ptr::null(),
-            std::ptr::null(),
-            0,
+            ticket_key_ptr,
+            ticket_key_len,
         )
     };
     if quic.is_null() {
-        let crypto_errors = take_crypto_errors();
-        if crypto_errors.is_empty() {
-            return Err(ServerError::new("Could not create QUIC context"));
-        }
-        return Err(ServerError::new(format!(
-            "Could not create QUIC context (TLS errors: {})",
-            crypto_errors.join("; ")
-        )));
+        return Err(ServerError::crypto(
+            "Could not create QUIC context",
+            take_crypto_errors(),
+        ));
     }
     let _quic_guard = QuicGuard::new(quic);
-    unsafe {
-        if slipstream_server_cc_algorithm.is_null() {
-            return Err(ServerError::new(
-                "Slipstream server congestion algorithm is unavailable",
-            ));
+    match config.congestion_control.as_deref() {
+        Some(name) => {
+            let name = CString::new(name).map_err(|_| {
+                ServerError::new("Congestion control contains an unexpected null byte")
+            })?;
+            tracing::info!("Using {} congestion control", name.to_string_lossy());
+            unsafe {
+                configure_quic(quic, name.as_ptr(), config.quic_mtu);
+            }
         }
-        configure_quic_with_custom(quic, slipstream_server_cc_algorithm, QUIC_MTU);
+        None => unsafe {
+            if slipstream_server_cc_algorithm.is_null() {
+                return Err(ServerError::new(
+                    "Slipstream server congestion algorithm is unavailable",
+                ));
+            }
+            configure_quic_with_custom(quic, slipstream_server_cc_algorithm, config.quic_mtu);
+        },
+    }
+    if config.datagram_mode {
+        unsafe { slipstream_enable_datagrams(quic, PICOQUIC_MAX_PACKET_SIZE as u32) };
     }
 
-    let udp = Arc::new(bind_udp_socket(&config.dns_listen_host, config.dns_listen_port).await?);
-    let udp_local_addr = udp.local_addr().map_err(map_io)?;
-    let map_ipv4_peers = matches!(udp_local_addr, SocketAddr::V6(_));
-    let local_addr_storage = socket_addr_to_storage(udp_local_addr);
-    if let Some(addr) = fallback_addr {
-        if addr == udp_local_addr {
-            tracing::warn!(
-                "Fallback address matches DNS listen address ({}); non-DNS packets will loop. \
-                 Configure a different fallback address.",
-                addr
-            );
+    let mut listeners = Vec::with_capacity(config.dns_listen_hosts.len());
+    for host in &config.dns_listen_hosts {
+        let socket =
+            Arc::new(bind_udp_socket(host, config.dns_listen_port, config.address_policy).await?);
+        let local_addr = socket.local_addr().map_err(map_io)?;
+        let map_ipv4_peers =
+            matches!(local_addr, SocketAddr::V6(_)) && config.address_policy == AddressPolicy::Any;
+        for route in &fallback_routes {
+            if route.addr == local_addr {
+                tracing::warn!(
+                    fallback = %route.addr,
+                    "Fallback address matches DNS listen address; non-DNS packets will loop. \
+                     Configure a different fallback address."
+                );
+            }
         }
+        let fallback_mgr = (!fallback_routes.is_empty())
+            .then(|| FallbackManager::new(socket.clone(), fallback_routes.clone(), map_ipv4_peers));
+        listeners.push(Listener {
+            socket,
+            local_addr,
+            local_addr_storage: socket_addr_to_storage(local_addr),
+            map_ipv4_peers,
+            fallback_mgr,
+        });
+    }
+    if listeners.is_empty() {
+        return Err(ServerError::new(
+            "At least one DNS listen host must be configured",
+        ));
+    }
+    if listeners.len() > 1 {
+        let addrs: Vec<String> = listeners
+            .iter()
+            .map(|listener| listener.local_addr.to_string())
+            .collect();
+        tracing::info!("Serving DNS on {}", addrs.join(", "));
+    }
+    if let Some(addr) = config.metrics_listen {
+        let local_addr =
+            spawn_metrics_server(addr, metrics.clone(), connection_log.clone()).await?;
+        tracing::info!(
+            "Serving Prometheus metrics on http://{}/metrics",
+            local_addr
+        );
     }
-    let mut fallback_mgr =
-        fallback_addr.map(|addr| FallbackManager::new(udp.clone(), addr, map_ipv4_peers));
     warn_overlapping_domains(&config.domains);
+    if config.quic_mtu as usize > max_udp_txt_payload_len() {
+        tracing::warn!(
+            quic_mtu = config.quic_mtu,
+            max_fit = max_udp_txt_payload_len(),
+            "--quic-mtu makes TXT responses larger than the advertised EDNS payload; resolvers may truncate them"
+        );
+    }
     let domains: Vec<&str> = config.domains.iter().map(String::as_str).collect();
     if domains.is_empty() {
         return Err(ServerError::new("At least one domain must be configured"));
@@ -272,30 +542,116 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
         libc::signal(libc::SIGTERM, handler);
     }
 
-    let recv_buf_len = if fallback_mgr.is_some() {
+    let recv_buf_len = if !fallback_routes.is_empty() {
         MAX_UDP_PACKET_SIZE
     } else {
         DNS_MAX_QUERY_SIZE
     };
     let mut recv_buf = vec![0u8; recv_buf_len];
+    let mut recv_batch = config
+        .recvmmsg
+        .then(|| RecvBatch::new(RECVMMSG_BATCH, recv_buf_len))
+        .flatten();
     let mut send_buf = vec![0u8; PICOQUIC_MAX_PACKET_SIZE];
+    let mut outgoing = Vec::new();
+    let mut sender = BatchSender::new(config.gso, config.debug_poll);
     let mut last_seen = HashMap::new();
     let mut last_idle_gc = Instant::now();
+    let mut last_stream_metrics = Instant::now();
     let mut last_flow_block_log_at: u64 = 0;
+    let mut drain: Option<Drain> = None;
+    let mut reset_seed_rx = match (&config.reset_seed_path, config.reset_seed_rotation_hours) {
+        (Some(path), hours) if hours > 0 => spawn_reset_seed_rotation(
+            PathBuf::from(path),
+            Duration::from_secs(hours.saturating_mul(3600)),
+        ),
+        _ => None,
+    };
+    let mut next_listener = 0usize;
+    // A recvmmsg batch can carry the loop past the per-wakeup receive limit.
+    let max_slots = if recv_batch.is_some() {
+        PICOQUIC_PACKET_LOOP_RECV_MAX + RECVMMSG_BATCH - 1
+    } else {
+        PICOQUIC_PACKET_LOOP_RECV_MAX
+    };
+    let mut source_limiter = (config.max_qps_per_source > 0).then(|| {
+        SourceRateLimiter::new(config.max_qps_per_source, unsafe {
+            picoquic_current_time()
+        })
+    });
 
     loop {
         drain_commands(state_ptr, &mut command_rx);
 
-        if SHOULD_SHUTDOWN.load(Ordering::Relaxed) {
+        let sigterms = SIGTERM_COUNT.load(Ordering::Relaxed);
+        if sigterms > 0 {
             let state = unsafe { &mut *state_ptr };
-            if handle_shutdown(quic, state) {
-                break;
+            let connections = unsafe { picoquic_current_number_connections(quic) } as usize;
+            let now = Instant::now();
+            let phase = match drain.as_ref() {
+                Some(drain) => match drain.closing_until {
+                    Some(until) => closing_phase(sigterms, until, connections > 0, now),
+                    None => drain_phase(sigterms, Some(drain.deadline), state.has_streams(), now),
+                },
+                None if idle_drain_timeout.is_zero() => ShutdownPhase::Close,
+                None => drain_phase(sigterms, None, state.has_streams(), now),
+            };
+            match phase {
+                ShutdownPhase::StartDrain => {
+                    tracing::info!(
+                        "SIGTERM received; draining {} connections for up to {}s \
+                         (send SIGTERM again to close immediately)",
+                        connections,
+                        idle_drain_timeout.as_secs()
+                    );
+                    state.start_drain(collect_active_connections(quic).into_keys().collect());
+                    drain = Some(Drain {
+                        deadline: now + idle_drain_timeout,
+                        closing_until: None,
+                    });
+                }
+                ShutdownPhase::Draining | ShutdownPhase::Closing => {}
+                ShutdownPhase::Close => {
+                    let forced = handle_shutdown(quic, state);
+                    if drain.is_some() && sigterms > 1 {
+                        tracing::info!(
+                            "Second SIGTERM received; closed {} connections and {} streams",
+                            connections,
+                            forced
+                        );
+                    } else if forced > 0 {
+                        tracing::info!("Forcibly closed {} streams", forced);
+                    }
+                    break;
+                }
+                ShutdownPhase::Drained => {
+                    tracing::info!("Drain complete; closing {} idle connections", connections);
+                    handle_shutdown(quic, state);
+                    break;
+                }
+                ShutdownPhase::DeadlineReached => {
+                    let forced = cancel_connections(quic, state);
+                    tracing::warn!(
+                        streams = forced,
+                        connections,
+                        "Drain deadline reached; forcibly closed remaining streams"
+                    );
+                    if let Some(drain) = drain.as_mut() {
+                        drain.closing_until = Some(now + CLOSE_GRACE);
+                    }
+                }
             }
         }
+        let drain_wake_at = drain.as_ref().map(Drain::wake_at);
 
         let mut slots = Vec::new();
-        if let Some(manager) = fallback_mgr.as_mut() {
-            manager.cleanup();
+        for listener in listeners.iter_mut() {
+            if let Some(manager) = listener.fallback_mgr.as_mut() {
+                manager.cleanup();
+            }
+        }
+        if let Some(limiter) = source_limiter.as_mut() {
+            limiter.cleanup(unsafe { picoquic_current_time() });
         }
 
         tokio::select! {
@@ -304,33 +660,78 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
                     handle_command(state_ptr, command);
                 }
             }
-            recv = udp.recv_from(&mut recv_buf) => {
-                match recv {
-                    Ok((size, peer)) => {
-                        let loop_time = unsafe { picoquic_current_time() };
-                        let context = PacketContext {
-                            domains: &domains,
-                            quic,
-                            current_time: loop_time,
-                            local_addr_storage: &local_addr_storage,
-                        };
-                        handle_packet(
-                            &mut slots,
-                            &recv_buf[..size],
-                            peer,
-                            &context,
-                            &mut fallback_mgr,
-                        )
-                        .await?;
-                        for _ in 1..PICOQUIC_PACKET_LOOP_RECV_MAX {
-                            match udp.try_recv_from(&mut recv_buf) {
+            (index, ready) = wait_readable(&listeners, next_listener) => {
+                next_listener = (index + 1) % listeners.len();
+                if let Err(err) = ready {
+                    if !is_transient_udp_error(&err) {
+                        return Err(map_io(err));
+                    }
+                } else {
+                    let loop_time = unsafe { picoquic_current_time() };
+                    let listener = &mut listeners[index];
+                    let context = PacketContext {
+                        domains: &domains,
+                        encoding: config.encoding,
+                        quic,
+                        current_time: loop_time,
+                        listener: index,
+                        local_addr_storage: &listener.local_addr_storage,
+                        metrics: &metrics,
+                        state: state_ptr,
+                        auth_secret: auth_secret.as_deref(),
+                        source_filter: (!config.source_filter.is_empty())
+                            .then_some(&config.source_filter),
+                    };
+                    if let Some(batch) = recv_batch.as_mut() {
+                        let mut received = 0;
+                        while received < PICOQUIC_PACKET_LOOP_RECV_MAX {
+                            match batch.recv(&listener.socket) {
+                                Ok(count) => {
+                                    received += count;
+                                    // Rate limit, then classify, as the loop below does.
+                                    for (packet, peer) in batch.packets(count) {
+                                        if let Some(limiter) = source_limiter.as_mut() {
+                                            if !limiter.allow(peer, loop_time) {
+                                                metrics.record_dns_query(DnsOutcome::RateLimited);
+                                                continue;
+                                            }
+                                        }
+                                        handle_packet(
+                                            &mut slots,
+                                            packet,
+                                            peer,
+                                            &context,
+                                            &mut listener.fallback_mgr,
+                                        )
+                                        .await?;
+                                    }
+                                }
+                                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                                Err(err) => {
+                                    if is_transient_udp_error(&err) {
+                                        break;
+                                    }
+                                    return Err(map_io(err));
+                                }
+                            }
+                        }
+                    } else {
+                        for _ in 0..PICOQUIC_PACKET_LOOP_RECV_MAX {
+                            match listener.socket.try_recv_from(&mut recv_buf) {
                                 Ok((size, peer)) => {
+                                    if let Some(limiter) = source_limiter.as_mut() {
+                                        if !limiter.allow(peer, loop_time) {
+                                            metrics.record_dns_query(DnsOutcome::RateLimited);
+                                            continue;
+                                        }
+                                    }
                                     handle_packet(
                                         &mut slots,
                                         &recv_buf[..size],
                                         peer,
                                         &context,
-                                        &mut fallback_mgr,
+                                        &mut listener.fallback_mgr,
                                     )
                                     .await?;
                                 }
@@ -345,16 +746,37 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
                             }
                         }
                     }
-                    Err(err) => {
-                        if !is_transient_udp_error(&err) {
-                            return Err(map_io(err));
-                        }
-                    }
                 }
             }
             _ = sleep(Duration::from_millis(IDLE_SLEEP_MS)) => {}
+            _ = sleep_until_drain_deadline(drain_wake_at) => {}
+            Some(seed) = next_reset_seed(&mut reset_seed_rx) => {
+                unsafe { slipstream_set_reset_seed(quic, seed.as_ptr()) };
+                tracing::info!(
+                    path = config.reset_seed_path.as_deref().unwrap_or_default(),
+                    "Rotated the stateless reset seed"
+                );
+            }
         }
 
+        // Connections held by `--connection-overflow-mode queue` take the slots freed since.
+        let connections = unsafe { picoquic_current_number_connections(quic) } as usize;
+        let admitted =
+            unsafe { (&mut *state_ptr).connection_limit_mut() }.admit(connections, Instant::now());
+        for queued in admitted {
+            let local_addr_storage = &listeners[queued.origin.listener].local_addr_storage;
+            admit_queued_connection(quic, state_ptr, local_addr_storage, queued, unsafe {
+                picoquic_current_time()
+            })?;
+        }
+
+        assert_invariant!(
+            slots.len() <= max_slots,
+            "{} slots from one wakeup, over {}",
+            slots.len(),
+            max_slots
+        );
+
         let now = Instant::now();
         if idle_timeout != Duration::ZERO {
             note_active_connections(&mut last_seen, &slots, now);
@@ -365,8 +787,28 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
                 idle_timeout,
                 &mut last_idle_gc,
                 now,
+                &metrics,
+            );
+        }
+        metrics
+            .set_active_connections(unsafe { picoquic_current_number_connections(quic) } as usize);
+        if !fallback_routes.is_empty() {
+            metrics.set_fallback_sessions_active(
+                listeners
+                    .iter()
+                    .filter_map(|listener| listener.fallback_mgr.as_ref())
+                    .map(FallbackManager::session_count)
+                    .sum(),
             );
         }
+        // Walking every stream is only worth it when someone can scrape the result, and only
+        // once a second; gauges that old are fresh enough for a scrape.
+        if config.metrics_listen.is_some()
+            && now.duration_since(last_stream_metrics) >= STREAM_METRICS_INTERVAL
+        {
+            last_stream_metrics = now;
+            metrics.set_stream_gauges(unsafe { &(&*state_ptr).stream_metrics() });
+        }
 
         drain_commands(state_ptr, &mut command_rx);
         maybe_report_command_stats(state_ptr);
@@ -377,6 +819,7 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
 
         let loop_time = unsafe { picoquic_current_time() };
 
+        outgoing.clear();
         for slot in slots.iter_mut() {
             let mut send_length = 0usize;
             let mut addr_to: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
@@ -384,13 +827,16 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
             let mut if_index: libc::c_int = 0;
 
             if slot.payload_override.is_none() && slot.rcode.is_none() && !slot.cnx.is_null() {
+                // Non-TXT answers hold less than a full QUIC packet; cap the packet to fit.
+                let send_capacity = max_response_payload_len(slot.question.qtype)
+                    .map_or(send_buf.len(), |max_len| max_len.min(send_buf.len()));
                 let ret = unsafe {
                     picoquic_prepare_packet_ex(
                         slot.cnx,
                         slot.path_id,
                         loop_time,
                         send_buf.as_mut_ptr(),
-                        send_buf.len(),
+                        send_capacity,
                         &mut send_length,
                         &mut addr_to,
                         &mut addr_from,
@@ -415,28 +861,36 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
                             unsafe { slipstream_has_ready_stream(slot.cnx) != 0 };
                         let send_backlog =
                             unsafe { (&*state_ptr).stream_send_backlog_summaries(cnx_id, 8) };
+                        let stream_flow = unsafe { (&*state_ptr).stream_flow_snapshots(cnx_id) }
+                            .iter()
+                            .map(|(stream_id, snapshot)| {
+                                format!("stream {}: {}", stream_id, snapshot)
+                            })
+                            .collect::<Vec<_>>()
+                            .join("; ");
                         tracing::warn!(
-                            "server connection stalled: cnx={} streams={} streams_with_write_tx={} streams_with_data_rx={} queued_bytes_total={} streams_with_pending_data={} pending_chunks_total={} pending_bytes_total={} streams_with_pending_fin={} streams_with_fin_enqueued={} streams_with_target_fin_pending={} streams_with_send_pending={} streams_with_send_stash={} send_stash_bytes_total={} streams_discarding={} streams_close_after_flush={} multi_stream={} flow_blocked={} has_ready_stream={} send_backlog={:?}",
-                            cnx_id,
-                            metrics.streams_total,
-                            metrics.streams_with_write_tx,
-                            metrics.streams_with_data_rx,
-                            metrics.queued_bytes_total,
-                            metrics.streams_with_pending_data,
-                            metrics.pending_chunks_total,
-                            metrics.pending_bytes_total,
-                            metrics.streams_with_pending_fin,
-                            metrics.streams_with_fin_enqueued,
-                            metrics.streams_with_target_fin_pending,
-                            metrics.streams_with_send_pending,
-                            metrics.streams_with_send_stash,
-                            metrics.send_stash_bytes_total,
-                            metrics.streams_discarding,
-                            metrics.streams_close_after_flush,
-                            metrics.multi_stream,
+                            cnx = cnx_id,
+                            streams = metrics.streams_total,
+                            streams_with_write_tx = metrics.streams_with_write_tx,
+                            streams_with_data_rx = metrics.streams_with_data_rx,
+                            queued_bytes_total = metrics.queued_bytes_total,
+                            streams_with_pending_data = metrics.streams_with_pending_data,
+                            pending_chunks_total = metrics.pending_chunks_total,
+                            pending_bytes_total = metrics.pending_bytes_total,
+                            streams_with_pending_fin = metrics.streams_with_pending_fin,
+                            streams_with_fin_enqueued = metrics.streams_with_fin_enqueued,
+                            streams_with_target_fin_pending = metrics.streams_with_target_fin_pending,
+                            streams_with_send_pending = metrics.streams_with_send_pending,
+                            streams_with_send_stash = metrics.streams_with_send_stash,
+                            send_stash_bytes_total = metrics.send_stash_bytes_total,
+                            streams_discarding = metrics.streams_discarding,
+                            streams_close_after_flush = metrics.streams_close_after_flush,
+                            multi_stream = metrics.multi_stream,
                             flow_blocked,
                             has_ready_stream,
-                            send_backlog
+                            send_backlog = ?send_backlog,
+                            stream_flow = %stream_flow,
+                            "server connection stalled"
                         );
                         last_flow_block_log_at = loop_time;
                     }
@@ -458,38 +912,110 @@ pub async fn run_server(config: &ServerConfig) -> Result<i32, ServerError> {
                 id: slot.id,
                 rd: slot.rd,
                 cd: slot.cd,
+                ad: answer_sets_ad(slot, config.dnssec_passthrough),
                 question: &slot.question,
                 payload,
                 rcode,
-            })
-            .map_err(|err| ServerError::new(err.to_string()))?;
-            let peer = if map_ipv4_peers {
+                encoding: config.encoding,
+                ttl: config.response_ttl,
+                compress_names: config.compress_names,
+                edns: EdnsOptions::default(),
+            })?;
+            // The resolver expects the answer from the address it queried, whatever local
+            // address picoquic has on record for the path.
+            let listener_index = slot.listener;
+            let peer = if listeners[listener_index].map_ipv4_peers {
                 normalize_dual_stack_addr(slot.peer)
             } else {
                 slot.peer
             };
-            if let Err(err) = udp.send_to(&response, peer).await {
+            outgoing.push(Outgoing {
+                listener: listener_index,
+                peer,
+                response,
+            });
+        }
+
+        for batch in plan_batches(&outgoing) {
+            let packets = &outgoing[batch];
+            let socket = &listeners[packets[0].listener].socket;
+            if let Err(err) = sender.send(socket, packets).await {
                 if !is_transient_udp_error(&err) {
                     return Err(map_io(err));
                 }
             }
         }
+        sender.maybe_report();
     }
 
     Ok(0)
 }
 
-async fn bind_udp_socket(host: &str, port: u16) -> Result<TokioUdpSocket, ServerError> {
-    let addrs: Vec<SocketAddr> = lookup_host((host, port)).await.map_err(map_io)?.collect();
+/// Rejects a domain routed to more than one target, since only one of them could be used.
+fn check_domain_targets(routes: &[DomainTarget]) -> Result<(), ServerError> {
+    for (index, route) in routes.iter().enumerate() {
+        if routes[..index]
+            .iter()
+            .any(|earlier| earlier.domain == route.domain)
+        {
+            return Err(ServerError::new(format!(
+                "Domain {} is given more than one target",
+                route.domain
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Resolves a literal target once; host names are only checked here and resolved again on
+/// every connect, so streams can race both families with happy eyeballs. A Unix socket is
+/// connected to by path, so it may appear after the server starts.
+async fn resolve_target(
+    target: &TargetEndpoint,
+    policy: AddressPolicy,
+) -> Result<TargetAddr, ServerError> {
+    let address = match target {
+        TargetEndpoint::Tcp(address) => address,
+        TargetEndpoint::UnixSocket(path) => return Ok(TargetAddr::UnixSocket(path.clone())),
+    };
+    if address.host.parse::<IpAddr>().is_ok() {
+        return resolve_host_port(address)
+            .map(TargetAddr::Fixed)
+            .map_err(|err| ServerError::new(err.to_string()));
+    }
+    resolve_dial_addrs(&address.host, address.port, policy)
+        .await
+        .map_err(|err| ServerError::new(format!("Cannot resolve {}: {}", address.host, err)))?;
+    Ok(TargetAddr::Dial(
+        ProxyTarget {
+            host: address.host.clone(),
+            port: address.port,
+        },
+        policy,
+    ))
+}
+
+async fn bind_udp_socket(
+    host: &str,
+    port: u16,
+    policy: AddressPolicy,
+) -> Result<TokioUdpSocket, ServerError> {
+    let addrs: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .map_err(map_io)?
+        .filter(|addr| policy.allows(addr))
+        .collect();
     if addrs.is_empty() {
         return Err(ServerError::new(format!(
-            "No addresses resolved for {}:{}",
-            host, port
+            "No addresses resolved for {}:{} (address family {})",
+            host,
+            port,
+            policy.label()
         )));
     }
     let mut last_err = None;
     for addr in addrs {
-        match bind_udp_socket_addr(addr) {
+        match bind_udp_socket_addr(addr, policy) {
             Ok(socket) => return Ok(socket),
             Err(err) => last_err = Some(err),
         }
@@ -499,19 +1025,26 @@ async fn bind_udp_socket(host: &str, port: u16) -> Result<TokioUdpSocket, Server
     }))
 }
 
-fn bind_udp_socket_addr(addr: SocketAddr) -> Result<TokioUdpSocket, ServerError> {
+fn bind_udp_socket_addr(
+    addr: SocketAddr,
+    policy: AddressPolicy,
+) -> Result<TokioUdpSocket, ServerError> {
     let domain = match addr {
         SocketAddr::V4(_) => Domain::IPV4,
         SocketAddr::V6(_) => Domain::IPV6,
     };
     let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP)).map_err(map_io)?;
     if let SocketAddr::V6(_) = addr {
-        if let Err(err) = socket.set_only_v6(false) {
-            tracing::warn!(
-                "Failed to enable dual-stack UDP listener on {}: {}",
-                addr,
-                err
-            );
+        if policy == AddressPolicy::Any {
+            if let Err(err) = socket.set_only_v6(false) {
+                tracing::warn!(
+                    addr = %addr,
+                    error = %err,
+                    "Failed to enable dual-stack UDP listener"
+                );
+            }
+        } else {
+            socket.set_only_v6(true).map_err(map_io)?;
         }
     }
     let sock_addr = SockAddr::from(addr);
@@ -521,10 +1054,155 @@ fn bind_udp_socket_addr(addr: SocketAddr) -> Result<TokioUdpSocket, ServerError>
     TokioUdpSocket::from_std(std_socket).map_err(map_io)
 }
 
+/// One bound DNS socket; every listener feeds the same QUIC context.
+struct Listener {
+    socket: Arc<TokioUdpSocket>,
+    local_addr: SocketAddr,
+    local_addr_storage: libc::sockaddr_storage,
+    map_ipv4_peers: bool,
+    fallback_mgr: Option<FallbackManager>,
+}
+
+/// Waits until a listener has a datagram queued, scanning from `start` so one busy
+/// socket cannot starve the others.
+async fn wait_readable(listeners: &[Listener], start: usize) -> (usize, std::io::Result<()>) {
+    std::future::poll_fn(|cx| {
+        for offset in 0..listeners.len() {
+            let index = (start + offset) % listeners.len();
+            if let Poll::Ready(result) = listeners[index].socket.poll_recv_ready(cx) {
+                return Poll::Ready((index, result));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
 pub(crate) fn map_io(err: std::io::Error) -> ServerError {
     ServerError::new(err.to_string())
 }
 
+/// Shutdown progress after SIGTERM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShutdownPhase {
+    StartDrain,
+    Draining,
+    Drained,
+    DeadlineReached,
+    /// Connections were closed with an application error; waiting for peers to pick it up.
+    Closing,
+    Close,
+}
+
+/// Connections being drained after the first SIGTERM.
+struct Drain {
+    deadline: Instant,
+    // Set once the deadline closes the remaining connections.
+    closing_until: Option<Instant>,
+}
+
+impl Drain {
+    fn wake_at(&self) -> Instant {
+        self.closing_until.unwrap_or(self.deadline)
+    }
+}
+
+/// Regenerates the reset seed file every `interval` and hands each new seed to the event loop,
+/// which owns the QUIC context.
+///
+/// An interval too long for the clock to represent never comes due, so it rotates nothing.
+fn spawn_reset_seed_rotation(
+    path: PathBuf,
+    interval: Duration,
+) -> Option<mpsc::UnboundedReceiver<[u8; PICOQUIC_RESET_SECRET_SIZE]>> {
+    let Some(first_at) = Instant::now().checked_add(interval) else {
+        tracing::warn!(
+            hours = interval.as_secs() / 3600,
+            "Reset seed rotation interval is too long; the seed will not be rotated"
+        );
+        return None;
+    };
+    let (seed_tx, seed_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval_at(first_at.into(), interval);
+        loop {
+            ticker.tick().await;
+            match rotate_reset_seed(&path) {
+                Ok(seed) => {
+                    if seed_tx.send(seed).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(error = %err, "Reset seed rotation failed; keeping the current seed");
+                }
+            }
+        }
+    });
+    Some(seed_rx)
+}
+
+async fn next_reset_seed(
+    seed_rx: &mut Option<mpsc::UnboundedReceiver<[u8; PICOQUIC_RESET_SECRET_SIZE]>>,
+) -> Option<[u8; PICOQUIC_RESET_SECRET_SIZE]> {
+    match seed_rx {
+        Some(seed_rx) => seed_rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves at the drain (or closing) deadline so the loop reacts to it without waiting on
+/// traffic; never resolves outside a drain.
+async fn sleep_until_drain_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn drain_phase(
+    sigterms: usize,
+    deadline: Option<Instant>,
+    has_streams: bool,
+    now: Instant,
+) -> ShutdownPhase {
+    let Some(deadline) = deadline else {
+        return if sigterms > 1 {
+            ShutdownPhase::Close
+        } else {
+            ShutdownPhase::StartDrain
+        };
+    };
+    if sigterms > 1 {
+        ShutdownPhase::Close
+    } else if !has_streams {
+        ShutdownPhase::Drained
+    } else if now >= deadline {
+        ShutdownPhase::DeadlineReached
+    } else {
+        ShutdownPhase::Draining
+    }
+}
+
+fn closing_phase(
+    sigterms: usize,
+    closing_until: Instant,
+    has_connections: bool,
+    now: Instant,
+) -> ShutdownPhase {
+    if sigterms > 1 || !has_connections || now >= closing_until {
+        ShutdownPhase::Close
+    } else {
+        ShutdownPhase::Closing
+    }
+}
+
+/// AD is passed back to an asker that set it unless `--dnssec-passthrough` keeps it clear, so a
+/// validating resolver never treats the unsigned tunnel answer as authenticated.
+fn answer_sets_ad(slot: &Slot, dnssec_passthrough: bool) -> bool {
+    slot.ad && !dnssec_passthrough
+}
+
 fn note_active_connections(last_seen: &mut HashMap<usize, Instant>, slots: &[Slot], now: Instant) {
     for slot in slots {
         if !slot.cnx.is_null() {
@@ -566,6 +1244,7 @@ fn maybe_gc_idle_connections(
     idle_timeout: Duration,
     last_gc: &mut Instant,
     now: Instant,
+    metrics: &ServerMetrics,
 ) {
     if last_seen.is_empty() {
         return;
@@ -596,6 +1275,7 @@ fn maybe_gc_idle_connections(
     let state = unsafe { &mut *state_ptr };
     for cnx_id in idle {
         if let Some(&cnx) = active.get(&cnx_id) {
+            state.record_connection_closed(cnx_id, CloseReason::IdleTimeout);
             remove_connection_streams(state, cnx_id);
             if let Some(last) = last_seen.get(&cnx_id) {
                 tracing::debug!(
@@ -607,6 +1287,7 @@ fn maybe_gc_idle_connections(
             unsafe {
                 picoquic_delete_cnx(cnx);
             }
+            metrics.record_idle_gc_eviction();
             last_seen.remove(&cnx_id);
         }
     }
@@ -630,24 +1311,44 @@ fn warn_overlapping_domains(domains: &[String]) {
 
             if left == right {
                 tracing::warn!(
-                    "Duplicate domain configured: '{}' and '{}'",
-                    domains[i],
-                    domains[j]
+                    first = %domains[i],
+                    second = %domains[j],
+                    "Duplicate domain configured"
                 );
                 continue;
             }
 
-            if is_label_suffix(left, right) || is_label_suffix(right, left) {
+            if !domains_overlap(left, right) {
+                continue;
+            }
+            if wildcard_base(left).is_some() || wildcard_base(right).is_some() {
                 tracing::warn!(
-                    "Configured domains overlap; longest suffix wins: '{}' and '{}'",
-                    domains[i],
-                    domains[j]
+                    first = %domains[i],
+                    second = %domains[j],
+                    "Configured domains overlap; the longest match wins, exact domains over wildcards of equal length"
+                );
+            } else {
+                tracing::warn!(
+                    first = %domains[i],
+                    second = %domains[j],
+                    "Configured domains overlap; longest suffix wins"
                 );
             }
         }
     }
 }
 
+/// Whether some query name could match both domains. A `*.base` wildcard covers the names
+/// strictly under `base`, so it overlaps anything that is `base`, under it, or above it.
+fn domains_overlap(left: &str, right: &str) -> bool {
+    let left_base = wildcard_base(left).unwrap_or(left);
+    let right_base = wildcard_base(right).unwrap_or(right);
+    let either_wildcard = left_base.len() != left.len() || right_base.len() != right.len();
+    (either_wildcard && left_base == right_base)
+        || is_label_suffix(left_base, right_base)
+        || is_label_suffix(right_base, left_base)
+}
+
 fn is_label_suffix(domain: &str, suffix: &str) -> bool {
     if domain.len() <= suffix.len() {
         return false;
@@ -661,6 +1362,100 @@ fn is_label_suffix(domain: &str, suffix: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::load_reset_seed;
+
+    #[test]
+    fn wildcard_domains_overlap_their_base_and_its_relatives() {
+        assert!(domains_overlap("*.example.com", "example.com"));
+        assert!(domains_overlap("tunnel.example.com", "*.example.com"));
+        assert!(domains_overlap("*.example.com", "*.tunnel.example.com"));
+        assert!(domains_overlap("*.tunnel.example.com", "example.com"));
+        assert!(!domains_overlap("*.example.com", "*.example.org"));
+        assert!(!domains_overlap("*.example.com", "badexample.com"));
+        assert!(!domains_overlap("a.example.com", "b.example.com"));
+    }
+
+    #[test]
+    fn domains_take_at_most_one_target() {
+        let route = |domain: &str, path: &str| DomainTarget {
+            domain: domain.to_string(),
+            address: TargetEndpoint::UnixSocket(path.into()),
+        };
+        let routes = [route("a.example", "/a"), route("b.example", "/b")];
+        assert!(check_domain_targets(&routes).is_ok());
+
+        let routes = [route("a.example", "/a"), route("a.example", "/b")];
+        let err = check_domain_targets(&routes).unwrap_err();
+        assert!(err.to_string().contains("a.example"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn rotation_replaces_the_seed_file_each_interval() {
+        let path = std::env::temp_dir().join(format!(
+            "slipstream-reset-seed-rotation-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut seeds = spawn_reset_seed_rotation(path.clone(), Duration::from_millis(20))
+            .expect("rotation scheduled");
+        let first = seeds.recv().await.expect("first seed");
+        assert_eq!(load_reset_seed(&path).expect("seed file"), first);
+        let second = seeds.recv().await.expect("second seed");
+        assert_ne!(first, second);
+        assert_eq!(load_reset_seed(&path).expect("seed file"), second);
+        let _ = std::fs::remove_file(&path);
+
+        assert!(spawn_reset_seed_rotation(path, Duration::MAX).is_none());
+    }
+
+    #[test]
+    fn answers_echo_cd_and_clear_ad_only_with_dnssec_passthrough() {
+        let slot = |cd: bool, ad: bool| Slot {
+            peer: "127.0.0.1:53".parse().unwrap(),
+            listener: 0,
+            id: 7,
+            rd: true,
+            cd,
+            ad,
+            question: Question {
+                name: "nbswy3dp.test.com.".to_string(),
+                qtype: slipstream_dns::RR_TXT,
+                qclass: slipstream_dns::CLASS_IN,
+            },
+            rcode: None,
+            cnx: std::ptr::null_mut(),
+            path_id: -1,
+            payload_override: None,
+        };
+        for passthrough in [false, true] {
+            for (cd, ad) in [(false, false), (true, false), (false, true), (true, true)] {
+                let slot = slot(cd, ad);
+                let response = encode_response(&ResponseParams {
+                    id: slot.id,
+                    rd: slot.rd,
+                    cd: slot.cd,
+                    ad: answer_sets_ad(&slot, passthrough),
+                    question: &slot.question,
+                    payload: Some(b"hi"),
+                    rcode: None,
+                    encoding: Encoding::Base32,
+                    ttl: 0,
+                    compress_names: true,
+                    edns: EdnsOptions::default(),
+                })
+                .expect("encode response");
+                // Low flags byte: RA, Z, AD, CD, then the RCODE.
+                assert_eq!(response[3] & 0x10 != 0, cd, "cd={} ad={}", cd, ad);
+                assert_eq!(
+                    response[3] & 0x20 != 0,
+                    ad && !passthrough,
+                    "passthrough={} ad={}",
+                    passthrough,
+                    ad
+                );
+            }
+        }
+    }
 
     #[test]
     fn prune_and_collect_idle_prunes_and_collects() {
@@ -683,4 +1478,26 @@ mod tests {
         assert!(last_seen.contains_key(&2));
         assert!(!last_seen.contains_key(&3));
     }
+
+    #[test]
+    fn drain_phase_follows_signals_streams_and_deadline() {
+        let now = Instant::now();
+        let deadline = Some(now + Duration::from_secs(30));
+
+        assert_eq!(drain_phase(1, None, true, now), ShutdownPhase::StartDrain);
+        assert_eq!(drain_phase(2, None, true, now), ShutdownPhase::Close);
+        assert_eq!(drain_phase(1, deadline, true, now), ShutdownPhase::Draining);
+        assert_eq!(drain_phase(1, deadline, false, now), ShutdownPhase::Drained);
+        assert_eq!(drain_phase(2, deadline, true, now), ShutdownPhase::Close);
+        assert_eq!(
+            drain_phase(1, deadline, true, now + Duration::from_secs(30)),
+            ShutdownPhase::DeadlineReached
+        );
+
+        let until = now + CLOSE_GRACE;
+        assert_eq!(closing_phase(1, until, true, now), ShutdownPhase::Closing);
+        assert_eq!(closing_phase(1, until, false, now), ShutdownPhase::Close);
+        assert_eq!(closing_phase(2, until, true, now), ShutdownPhase::Close);
+        assert_eq!(closing_phase(1, until, true, until), ShutdownPhase::Close);
+    }
 }
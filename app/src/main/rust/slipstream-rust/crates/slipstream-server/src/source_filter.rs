@@ -0,0 +1,195 @@
+use slipstream_core::normalize_dual_stack_addr;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+/// Bits an IPv4 address sits behind in its IPv4-mapped IPv6 form.
+const V4_MAPPED_PREFIX_LEN: u8 = 96;
+
+/// A `--allow-source`/`--deny-source` range, kept in IPv6 form so an IPv4 rule also matches
+/// IPv4-mapped peers of dual-stack listeners.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: u128,
+    prefix_len: u8,
+    /// Written as IPv4, so it displays that way.
+    v4: bool,
+}
+
+impl Cidr {
+    fn contains(&self, addr: u128) -> bool {
+        (addr ^ self.network) & mask(self.prefix_len) == 0
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addr = Ipv6Addr::from(self.network);
+        match addr.to_ipv4_mapped() {
+            Some(v4) if self.v4 => write!(f, "{}/{}", v4, self.prefix_len - V4_MAPPED_PREFIX_LEN),
+            _ => write!(f, "{}/{}", addr, self.prefix_len),
+        }
+    }
+}
+
+/// Source addresses the DNS listeners take queries from. A deny rule wins over an allow rule;
+/// with allow rules, sources matching none of them are refused too.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl SourceFilter {
+    pub fn new(allow: Vec<Cidr>, deny: Vec<Cidr>) -> Self {
+        Self { allow, deny }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn allow_rules(&self) -> &[Cidr] {
+        &self.allow
+    }
+
+    pub fn deny_rules(&self) -> &[Cidr] {
+        &self.deny
+    }
+
+    /// Whether a datagram from `peer` may be handled; false means drop it without a reply.
+    pub fn allows(&self, peer: SocketAddr) -> bool {
+        let addr = match normalize_dual_stack_addr(peer).ip() {
+            IpAddr::V6(v6) => u128::from(v6),
+            IpAddr::V4(v4) => u128::from(v4.to_ipv6_mapped()),
+        };
+        if self.deny.iter().any(|cidr| cidr.contains(addr)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(addr))
+    }
+}
+
+/// Parses `ADDR[/LEN]`; a bare address is a single host. Host bits must be zero.
+pub fn parse_cidr(input: &str) -> Result<Cidr, String> {
+    let trimmed = input.trim();
+    let (addr, len) = match trimmed.split_once('/') {
+        Some((addr, len)) => (addr, Some(len)),
+        None => (trimmed, None),
+    };
+    let addr = addr
+        .parse::<IpAddr>()
+        .map_err(|_| format!("Invalid CIDR address: {}", trimmed))?;
+    let (network, max_len, offset, v4) = match addr {
+        IpAddr::V4(v4) => (
+            u128::from(v4.to_ipv6_mapped()),
+            32,
+            V4_MAPPED_PREFIX_LEN,
+            true,
+        ),
+        IpAddr::V6(v6) => (u128::from(v6), 128, 0, false),
+    };
+    let len = match len {
+        Some(len) => len
+            .parse::<u8>()
+            .ok()
+            .filter(|len| *len <= max_len)
+            .ok_or_else(|| format!("Invalid CIDR prefix length: {}", trimmed))?,
+        None => max_len,
+    };
+    let prefix_len = offset + len;
+    if network & !mask(prefix_len) != 0 {
+        return Err(format!("CIDR has host bits set: {}", trimmed));
+    }
+    Ok(Cidr {
+        network,
+        prefix_len,
+        v4,
+    })
+}
+
+fn mask(prefix_len: u8) -> u128 {
+    match prefix_len {
+        0 => 0,
+        len => u128::MAX << (128 - u32::from(len)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(addr: &str) -> SocketAddr {
+        SocketAddr::new(addr.parse().unwrap(), 53)
+    }
+
+    fn source_filter(allow: &[&str], deny: &[&str]) -> SourceFilter {
+        let parse = |rules: &[&str]| rules.iter().map(|rule| parse_cidr(rule).unwrap()).collect();
+        SourceFilter::new(parse(allow), parse(deny))
+    }
+
+    #[test]
+    fn parses_and_displays_cidrs() {
+        assert_eq!(parse_cidr("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(parse_cidr("192.0.2.7").unwrap().to_string(), "192.0.2.7/32");
+        assert_eq!(
+            parse_cidr("2001:db8::/32").unwrap().to_string(),
+            "2001:db8::/32"
+        );
+        assert_eq!(parse_cidr("::/0").unwrap().to_string(), "::/0");
+        assert!(parse_cidr("10.0.0.1/8").is_err());
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+        assert!(parse_cidr("2001:db8::/129").is_err());
+        assert!(parse_cidr("example.com/8").is_err());
+    }
+
+    #[test]
+    fn empty_filter_allows_everything() {
+        let filter = source_filter(&[], &[]);
+        assert!(filter.is_empty());
+        assert!(filter.allows(peer("198.51.100.1")));
+        assert!(filter.allows(peer("2001:db8::1")));
+    }
+
+    #[test]
+    fn matches_ipv4_prefixes() {
+        let filter = source_filter(&["10.1.0.0/16", "192.0.2.7"], &[]);
+        assert!(filter.allows(peer("10.1.255.3")));
+        assert!(filter.allows(peer("192.0.2.7")));
+        assert!(!filter.allows(peer("10.2.0.1")));
+        assert!(!filter.allows(peer("192.0.2.8")));
+    }
+
+    #[test]
+    fn matches_ipv6_prefixes() {
+        let filter = source_filter(&["2001:db8:1::/48"], &[]);
+        assert!(filter.allows(peer("2001:db8:1:ffff::1")));
+        assert!(!filter.allows(peer("2001:db8:2::1")));
+        assert!(!filter.allows(peer("10.0.0.1")));
+    }
+
+    #[test]
+    fn ipv4_rules_match_mapped_peers() {
+        let filter = source_filter(&["10.0.0.0/8", "2001:db8::/32"], &["10.9.0.0/16"]);
+        assert!(filter.allows(peer("::ffff:10.1.2.3")));
+        assert!(!filter.allows(peer("::ffff:10.9.2.3")));
+        assert!(!filter.allows(peer("::ffff:11.0.0.1")));
+        assert!(filter.allows(peer("2001:db8::5")));
+
+        let mapped = source_filter(&["::ffff:10.0.0.0/104"], &[]);
+        assert!(mapped.allows(peer("10.4.4.4")));
+        assert!(!mapped.allows(peer("11.4.4.4")));
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        let filter = source_filter(&["0.0.0.0/0", "::/0"], &["203.0.113.0/24", "2001:db8::/32"]);
+        assert!(!filter.allows(peer("203.0.113.9")));
+        assert!(!filter.allows(peer("2001:db8::9")));
+        assert!(filter.allows(peer("198.51.100.1")));
+        assert!(filter.allows(peer("2001:db9::1")));
+
+        let deny_only = source_filter(&[], &["198.51.100.0/24"]);
+        assert!(!deny_only.allows(peer("198.51.100.1")));
+        assert!(deny_only.allows(peer("198.51.101.1")));
+    }
+}
@@ -1,40 +1,302 @@
-use crate::server::{Command, StreamKey, StreamWrite};
-use crate::target::spawn_target_connector;
+use crate::auth_nonce::AuthNonces;
+use crate::connection_limit::{ConnectionLimit, ConnectionOverflowMode};
+use crate::datagram::{DatagramFlows, FlowKey};
+use crate::metrics::ServerMetrics;
+use crate::server::{Command, StreamKey, StreamWrite, TargetMode};
+use crate::target::{
+    spawn_target_connector, ProxyHeader, TargetAddr, TargetRateLimiter, TargetRelay,
+};
+use slipstream_core::datagram::decode_datagram;
 use slipstream_core::flow_control::{
     conn_reserve_bytes, consume_error_log_message, consume_stream_data, handle_stream_receive,
     overflow_log_message, promote_error_log_message, promote_streams, reserve_target_offset,
-    FlowControlState, HasFlowControlState, PromoteEntry, StreamReceiveConfig, StreamReceiveOps,
+    FlowControlHistory, FlowControlSnapshot, FlowControlState, HasFlowControlState, PromoteEntry,
+    StreamReceiveConfig, StreamReceiveOps,
+};
+use slipstream_core::health::{
+    decode_health_frame, encode_health_frame, extend_health_frame, is_health_stream,
 };
 use slipstream_core::invariants::InvariantReporter;
+use slipstream_core::proxy_frame::{
+    decode_proxy_frame, ProxyFrameError, ProxyTarget, MAX_PROXY_FRAME_LEN,
+};
+use slipstream_core::stream_priority::StreamPriorityMode;
 #[cfg(test)]
 use slipstream_core::test_support::FailureCounter;
+use slipstream_core::AddressPolicy;
 use slipstream_ffi::picoquic::{
-    picoquic_call_back_event_t, picoquic_close, picoquic_close_immediate, picoquic_cnx_t,
-    picoquic_current_time, picoquic_get_first_cnx, picoquic_get_next_cnx,
-    picoquic_mark_active_stream, picoquic_provide_stream_data_buffer, picoquic_quic_t,
-    picoquic_reset_stream, picoquic_stop_sending, picoquic_stream_data_consumed,
+    picoquic_add_to_stream, picoquic_call_back_event_t, picoquic_close, picoquic_close_immediate,
+    picoquic_cnx_t, picoquic_current_time, picoquic_get_cnx_state, picoquic_get_first_cnx,
+    picoquic_get_next_cnx, picoquic_get_next_local_stream_id, picoquic_mark_active_stream,
+    picoquic_provide_stream_data_buffer, picoquic_quic_t, picoquic_reset_stream,
+    picoquic_state_enum, picoquic_stop_sending, picoquic_stream_data_consumed,
+};
+use slipstream_ffi::{
+    abort_stream_bidi, get_pacing_rate, get_rtt, queue_datagram, take_crypto_errors,
+    PicoquicStreams, StreamControl, SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR,
+    SLIPSTREAM_STREAM_LIMIT_ERROR,
 };
-use slipstream_ffi::{abort_stream_bidi, SLIPSTREAM_FILE_CANCEL_ERROR, SLIPSTREAM_INTERNAL_ERROR};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::Write as _;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::{mpsc, watch};
 use tracing::{debug, error, warn};
 
 static INVARIANT_REPORTER: InvariantReporter = InvariantReporter::new(1_000_000);
+pub(crate) const DEFAULT_CONNECTION_LOG_CAPACITY: usize = 1000;
+
+/// Why a connection left the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CloseReason {
+    /// The peer closed the connection with an application error code.
+    Application,
+    /// The connection closed at the transport layer (including picoquic's idle timeout).
+    Transport,
+    /// The peer sent a stateless reset.
+    StatelessReset,
+    /// The server's own idle GC evicted the connection.
+    IdleTimeout,
+}
+
+impl CloseReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::Application => "application",
+            CloseReason::Transport => "transport",
+            CloseReason::StatelessReset => "stateless_reset",
+            CloseReason::IdleTimeout => "idle_timeout",
+        }
+    }
+}
+
+/// One closed connection in the log served at `/connections`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ConnectionRecord {
+    pub(crate) cnx_id: usize,
+    /// Source of the first query for the connection (usually a recursive resolver).
+    pub(crate) client_addr: Option<SocketAddr>,
+    pub(crate) connected_at_ms: u64,
+    pub(crate) disconnected_at_ms: u64,
+    pub(crate) close_reason: CloseReason,
+    pub(crate) bytes_sent: u64,
+    pub(crate) bytes_received: u64,
+    pub(crate) streams: u64,
+    /// Final flow-control snapshots of the connection's last streams.
+    pub(crate) flow_history: FlowControlHistory,
+}
+
+impl ConnectionRecord {
+    fn write_json(&self, out: &mut String) {
+        let _ = write!(out, "{{\"cnx_id\":{},\"client_addr\":", self.cnx_id);
+        match self.client_addr {
+            Some(addr) => {
+                let _ = write!(out, "\"{}\"", addr);
+            }
+            None => out.push_str("null"),
+        }
+        let _ = write!(
+            out,
+            ",\"connected_at_ms\":{},\"disconnected_at_ms\":{},\"close_reason\":\"{}\",\"bytes_sent\":{},\"bytes_received\":{},\"streams\":{}",
+            self.connected_at_ms,
+            self.disconnected_at_ms,
+            self.close_reason.as_str(),
+            self.bytes_sent,
+            self.bytes_received,
+            self.streams
+        );
+        out.push_str(",\"flow_history\":[");
+        for (index, sample) in self.flow_history.iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"at_ms\":{},\"stream_id\":{},\"flow\":\"{}\"}}",
+                sample.at_ms, sample.stream_id, sample.snapshot
+            );
+        }
+        out.push_str("]}");
+    }
+}
+
+/// The last `capacity` closed connections, oldest first.
+///
+/// The event loop appends while the metrics task renders, hence the mutex.
+pub(crate) struct ConnectionLog {
+    capacity: usize,
+    records: Mutex<VecDeque<ConnectionRecord>>,
+}
+
+impl Default for ConnectionLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONNECTION_LOG_CAPACITY)
+    }
+}
+
+impl ConnectionLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity.min(1024))),
+        }
+    }
+
+    pub(crate) fn push(&self, record: ConnectionRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut records = self
+            .records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Renders the log as a JSON array.
+    pub(crate) fn to_json(&self) -> String {
+        let records = self
+            .records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut out = String::from("[");
+        for (index, record) in records.iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            record.write_json(&mut out);
+        }
+        out.push(']');
+        out
+    }
+}
+
+/// Bytes moved between the server and the target, seen from the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ByteTotals {
+    /// Bytes the target reader handed to the QUIC stream.
+    pub(crate) rx: u64,
+    /// Bytes the target writer flushed to the target socket.
+    pub(crate) tx: u64,
+}
+
+/// Adds target bytes to an open stream and to its connection.
+///
+/// The connection keeps counting writes that drain after their stream is gone.
+fn count_target_bytes(
+    stream_target_bytes: &mut HashMap<StreamKey, ByteTotals>,
+    connections: &mut HashMap<usize, ConnectionStats>,
+    key: StreamKey,
+    bump: impl Fn(&mut ByteTotals),
+) {
+    if let Some(totals) = stream_target_bytes.get_mut(&key) {
+        bump(totals);
+    }
+    if let Some(stats) = connections.get_mut(&key.cnx) {
+        bump(&mut stats.target_bytes);
+    }
+}
+
+/// Running totals for a connection that is still open.
+struct ConnectionStats {
+    client_addr: Option<SocketAddr>,
+    /// Index of the domain the connection's first query matched; picks its target.
+    domain: usize,
+    connected_at_ms: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    streams: u64,
+    /// Bytes moved to and from the targets of the connection's streams.
+    target_bytes: ByteTotals,
+    /// Streams the target closed cleanly.
+    streams_closed: u64,
+    /// Streams reset by the peer, or by the server after a target error.
+    streams_reset: u64,
+    flow_history: FlowControlHistory,
+}
+
+/// Stream ids of one connection, for its stream limit and priorities.
+#[derive(Default)]
+struct ConnectionStreams {
+    /// Open streams, oldest first.
+    open: BTreeSet<u64>,
+    /// Streams refused over the limit; their later frames are dropped without another reset.
+    rejected: HashSet<u64>,
+}
+
+/// Totals for one connection, logged by `--debug-commands` when the connection closes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ConnectionDebugStats {
+    pub(crate) bytes_sent_to_target: u64,
+    pub(crate) bytes_received_from_target: u64,
+    pub(crate) stream_open_count: u64,
+    pub(crate) stream_close_count: u64,
+    pub(crate) stream_reset_count: u64,
+}
+
+fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Peer and domain of the query whose payload QUIC is processing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IncomingQuery {
+    pub(crate) peer: SocketAddr,
+    pub(crate) domain: usize,
+}
 
 pub(crate) struct ServerState {
-    target_addr: SocketAddr,
+    target_addr: TargetAddr,
+    /// Targets indexed like the configured domains; empty sends every stream to `target_addr`.
+    domain_targets: Vec<TargetAddr>,
+    /// Set while QUIC processes a query, so the connection it opens starts with its source.
+    incoming: Option<IncomingQuery>,
+    target_proxy_protocol: bool,
+    target_mode: TargetMode,
+    /// Address families connect-proxy targets may be dialled over.
+    address_policy: AddressPolicy,
     streams: HashMap<StreamKey, ServerStream>,
+    /// Open and rejected stream ids of each connection with streams.
+    connection_streams: HashMap<usize, ConnectionStreams>,
+    /// Issues stream resets and priorities; tests swap in a recorder.
+    stream_control: Box<dyn StreamControl>,
+    /// Health probe frames still arriving, by the client's unidirectional stream.
+    health_probes: HashMap<StreamKey, Vec<u8>>,
     multi_streams: HashSet<usize>,
     command_tx: mpsc::UnboundedSender<Command>,
+    max_streams_per_connection: u32,
     debug_streams: bool,
     debug_commands: bool,
     command_counts: CommandCounts,
     last_command_report: Instant,
     last_mark_active_fail_log_at: u64,
+    metrics: Arc<ServerMetrics>,
+    connections: HashMap<usize, ConnectionStats>,
+    connection_log: Arc<ConnectionLog>,
+    /// Target bytes of each open stream.
+    stream_target_bytes: HashMap<StreamKey, ByteTotals>,
+    /// Bytes per second each connection may write to its targets; `None` is unlimited.
+    target_rate_limit: Option<u64>,
+    target_rate_limiters: HashMap<usize, Arc<TargetRateLimiter>>,
+    /// How long a target connect or write may block before the stream is reset.
+    target_write_timeout: Option<Duration>,
+    /// Whether DATAGRAM frames are forwarded as UDP flows; they are ignored otherwise.
+    datagram_mode: bool,
+    datagram_flows: DatagramFlows,
+    /// How streams are ordered against each other; `None` leaves picoquic's shared default.
+    stream_priority_mode: Option<StreamPriorityMode>,
+    connection_limit: ConnectionLimit,
+    /// Set while draining: the connections open when it started and not closed since. Packets
+    /// opening any other connection are refused.
+    draining: Option<HashSet<usize>>,
+    auth_nonces: AuthNonces,
     #[cfg(test)]
     mark_active_stream_failures: FailureCounter,
 }
@@ -48,6 +310,7 @@ pub(crate) struct ServerStreamMetrics {
     pub(crate) pending_chunks_total: usize,
     pub(crate) pending_bytes_total: u64,
     pub(crate) queued_bytes_total: u64,
+    pub(crate) queued_bytes_max: u64,
     pub(crate) streams_with_pending_fin: usize,
     pub(crate) streams_with_fin_enqueued: usize,
     pub(crate) streams_with_target_fin_pending: usize,
@@ -83,33 +346,269 @@ impl ServerStreamMetrics {
 
 impl ServerState {
     pub(crate) fn new(
-        target_addr: SocketAddr,
+        target_addr: TargetAddr,
         command_tx: mpsc::UnboundedSender<Command>,
+        max_streams_per_connection: u32,
         debug_streams: bool,
         debug_commands: bool,
+        metrics: Arc<ServerMetrics>,
+        connection_log: Arc<ConnectionLog>,
     ) -> Self {
         Self {
             target_addr,
+            domain_targets: Vec::new(),
+            incoming: None,
+            target_proxy_protocol: false,
+            target_mode: TargetMode::Fixed,
+            address_policy: AddressPolicy::Any,
             streams: HashMap::new(),
+            connection_streams: HashMap::new(),
+            stream_control: Box::new(PicoquicStreams),
+            health_probes: HashMap::new(),
             multi_streams: HashSet::new(),
             command_tx,
+            max_streams_per_connection,
             debug_streams,
             debug_commands,
             command_counts: CommandCounts::default(),
             last_command_report: Instant::now(),
             last_mark_active_fail_log_at: 0,
+            metrics,
+            connections: HashMap::new(),
+            connection_log,
+            stream_target_bytes: HashMap::new(),
+            target_rate_limit: None,
+            target_rate_limiters: HashMap::new(),
+            target_write_timeout: None,
+            datagram_mode: false,
+            datagram_flows: DatagramFlows::default(),
+            stream_priority_mode: None,
+            connection_limit: ConnectionLimit::new(u32::MAX, ConnectionOverflowMode::Drop),
+            draining: None,
+            auth_nonces: AuthNonces::default(),
             #[cfg(test)]
             mark_active_stream_failures: FailureCounter::new(),
         }
     }
 
-    pub(crate) fn stream_debug_metrics(&self, cnx_id: usize) -> ServerStreamMetrics {
-        let mut metrics = ServerStreamMetrics {
-            multi_stream: self.multi_streams.contains(&cnx_id),
-            ..ServerStreamMetrics::default()
+    /// Sends a PROXY protocol v2 header ahead of the first byte on each target connection.
+    pub(crate) fn set_target_proxy_protocol(&mut self, enabled: bool) {
+        self.target_proxy_protocol = enabled;
+    }
+
+    /// Routes the streams of each connection to the target of the domain its first query
+    /// matched, indexed like the configured domains.
+    pub(crate) fn set_domain_targets(&mut self, targets: Vec<TargetAddr>) {
+        self.domain_targets = targets;
+    }
+
+    /// Target for new streams of `cnx_id`, per the domain the connection opened with.
+    fn connection_target(&self, cnx_id: usize) -> TargetAddr {
+        let domain = self
+            .connections
+            .get(&cnx_id)
+            .map(|stats| stats.domain)
+            .or(self.incoming.map(|query| query.domain))
+            .unwrap_or(0);
+        self.domain_targets
+            .get(domain)
+            .unwrap_or(&self.target_addr)
+            .clone()
+    }
+
+    /// Dials the host named by each stream's connect-proxy frame instead of the fixed target.
+    pub(crate) fn set_target_mode(&mut self, mode: TargetMode) {
+        self.target_mode = mode;
+    }
+
+    /// Limits the hosts connect-proxy frames name to the `--address-family` in force.
+    pub(crate) fn set_address_policy(&mut self, policy: AddressPolicy) {
+        self.address_policy = policy;
+    }
+
+    /// Caps the aggregate rate at which each connection's streams write to their targets.
+    pub(crate) fn set_target_rate_limit(&mut self, kbps: Option<u32>) {
+        self.target_rate_limit = kbps.map(|kbps| u64::from(kbps) * 1000 / 8);
+    }
+
+    /// Decides on connections opened while `max_connections` are open, per `mode`.
+    pub(crate) fn set_connection_limit(
+        &mut self,
+        max_connections: u32,
+        mode: ConnectionOverflowMode,
+    ) {
+        self.connection_limit = ConnectionLimit::new(max_connections, mode);
+    }
+
+    pub(crate) fn connection_limit_mut(&mut self) -> &mut ConnectionLimit {
+        &mut self.connection_limit
+    }
+
+    /// Serves only the `existing` connections from now on; each leaves the set as it closes.
+    pub(crate) fn start_drain(&mut self, existing: HashSet<usize>) {
+        self.draining = Some(existing);
+    }
+
+    /// Whether draining refuses `cnx_id` as a connection opened since it started.
+    pub(crate) fn refuses_connection(&self, cnx_id: usize) -> bool {
+        self.draining
+            .as_ref()
+            .is_some_and(|existing| !existing.contains(&cnx_id))
+    }
+
+    pub(crate) fn auth_nonces_mut(&mut self) -> &mut AuthNonces {
+        &mut self.auth_nonces
+    }
+
+    /// Resets streams whose target connect or write has not finished within `ms`; 0 waits
+    /// forever.
+    pub(crate) fn set_target_write_timeout(&mut self, ms: u64) {
+        self.target_write_timeout = (ms > 0).then(|| Duration::from_millis(ms));
+    }
+
+    /// Forwards the flows of DATAGRAM frames to each connection's target over UDP.
+    pub(crate) fn set_datagram_mode(&mut self, enabled: bool) {
+        self.datagram_mode = enabled;
+    }
+
+    /// Gives each new stream a picoquic priority according to `mode`.
+    pub(crate) fn set_stream_priority_mode(&mut self, mode: Option<StreamPriorityMode>) {
+        self.stream_priority_mode = mode;
+    }
+
+    /// Returns the shared limiter for `cnx_id`, creating it with the connection's first stream.
+    fn target_rate_limiter(&mut self, cnx_id: usize) -> Option<Arc<TargetRateLimiter>> {
+        let rate = self.target_rate_limit?;
+        Some(
+            self.target_rate_limiters
+                .entry(cnx_id)
+                .or_insert_with(|| Arc::new(TargetRateLimiter::new(rate)))
+                .clone(),
+        )
+    }
+
+    pub(crate) fn has_streams(&self) -> bool {
+        !self.streams.is_empty()
+    }
+
+    /// Starts the totals of a connection opened by `query`; a connection already open keeps
+    /// the source and domain of its first query.
+    ///
+    /// Only the packet that creates a connection opens it, so once the close callback has
+    /// moved the totals to the log they are never started again.
+    pub(crate) fn open_connection(&mut self, cnx_id: usize, query: IncomingQuery) {
+        self.connections
+            .entry(cnx_id)
+            .or_insert_with(|| ConnectionStats {
+                client_addr: Some(query.peer),
+                domain: query.domain,
+                connected_at_ms: unix_time_ms(),
+                bytes_sent: 0,
+                bytes_received: 0,
+                streams: 0,
+                target_bytes: ByteTotals::default(),
+                streams_closed: 0,
+                streams_reset: 0,
+                flow_history: FlowControlHistory::default(),
+            });
+    }
+
+    /// Moves the totals of a closed connection into the connection log.
+    pub(crate) fn record_connection_closed(&mut self, cnx_id: usize, reason: CloseReason) {
+        if let Some(existing) = self.draining.as_mut() {
+            existing.remove(&cnx_id);
+        }
+        if self.debug_commands && self.connections.contains_key(&cnx_id) {
+            let stats = self.connection_debug_stats(cnx_id);
+            let cnx = cnx_id as *mut picoquic_cnx_t;
+            // The connection is closing but picoquic has not freed it yet.
+            let (rtt_us, pacing_rate) = unsafe { (get_rtt(cnx), get_pacing_rate(cnx)) };
+            debug!(
+                "debug: cnx {} closed reason={} bytes_sent_to_target={} bytes_received_from_target={} stream_open_count={} stream_close_count={} stream_reset_count={} rtt_us={} pacing_rate={}",
+                cnx_id,
+                reason.as_str(),
+                stats.bytes_sent_to_target,
+                stats.bytes_received_from_target,
+                stats.stream_open_count,
+                stats.stream_close_count,
+                stats.stream_reset_count,
+                rtt_us,
+                pacing_rate
+            );
+        }
+        let Some(mut stats) = self.connections.remove(&cnx_id) else {
+            return;
         };
+        if self.debug_streams {
+            debug!(
+                "cnx {}: removed target_rx_bytes={} target_tx_bytes={}",
+                cnx_id, stats.target_bytes.rx, stats.target_bytes.tx
+            );
+        }
+        let now_ms = unix_time_ms();
+        for (stream_id, snapshot) in self.stream_flow_snapshots(cnx_id) {
+            stats.flow_history.record(now_ms, stream_id, snapshot);
+        }
+        self.connection_log.push(ConnectionRecord {
+            cnx_id,
+            client_addr: stats.client_addr,
+            connected_at_ms: stats.connected_at_ms,
+            disconnected_at_ms: now_ms,
+            close_reason: reason,
+            bytes_sent: stats.bytes_sent,
+            bytes_received: stats.bytes_received,
+            streams: stats.streams,
+            flow_history: stats.flow_history,
+        });
+    }
+
+    /// Target bytes and stream outcomes for `cnx_id` so far; zero once the connection is gone.
+    pub(crate) fn connection_debug_stats(&self, cnx_id: usize) -> ConnectionDebugStats {
+        let totals = self.connection_byte_totals(cnx_id);
+        let mut stats = ConnectionDebugStats {
+            bytes_sent_to_target: totals.tx,
+            bytes_received_from_target: totals.rx,
+            ..ConnectionDebugStats::default()
+        };
+        if let Some(connection) = self.connections.get(&cnx_id) {
+            stats.stream_open_count = connection.streams;
+            stats.stream_close_count = connection.streams_closed;
+            stats.stream_reset_count = connection.streams_reset;
+        }
+        stats
+    }
+
+    /// Target bytes for `cnx_id` so far; zero once the connection is gone.
+    pub(crate) fn connection_byte_totals(&self, cnx_id: usize) -> ByteTotals {
+        self.connections
+            .get(&cnx_id)
+            .map(|stats| stats.target_bytes)
+            .unwrap_or_default()
+    }
+
+    /// Target bytes for an open stream; zero once the stream is gone.
+    pub(crate) fn stream_byte_totals(&self, key: StreamKey) -> ByteTotals {
+        self.stream_target_bytes
+            .get(&key)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn stream_debug_metrics(&self, cnx_id: usize) -> ServerStreamMetrics {
+        let mut metrics = self.collect_stream_metrics(|key| key.cnx == cnx_id);
+        metrics.multi_stream = self.multi_streams.contains(&cnx_id);
+        metrics
+    }
+
+    /// The same totals across every connection, for `--metrics-listen`.
+    pub(crate) fn stream_metrics(&self) -> ServerStreamMetrics {
+        self.collect_stream_metrics(|_| true)
+    }
+
+    fn collect_stream_metrics(&self, include: impl Fn(&StreamKey) -> bool) -> ServerStreamMetrics {
+        let mut metrics = ServerStreamMetrics::default();
         for (key, stream) in self.streams.iter() {
-            if key.cnx != cnx_id {
+            if !include(key) {
                 continue;
             }
             metrics.streams_total = metrics.streams_total.saturating_add(1);
@@ -121,6 +620,7 @@ impl ServerState {
             }
             let queued = stream.flow.queued_bytes as u64;
             metrics.queued_bytes_total = metrics.queued_bytes_total.saturating_add(queued);
+            metrics.queued_bytes_max = metrics.queued_bytes_max.max(queued);
             if !stream.pending_data.is_empty() {
                 metrics.streams_with_pending_data =
                     metrics.streams_with_pending_data.saturating_add(1);
@@ -173,6 +673,18 @@ impl ServerState {
         metrics
     }
 
+    /// Flow-control snapshots of every open stream on `cnx_id`, by stream ID.
+    pub(crate) fn stream_flow_snapshots(&self, cnx_id: usize) -> Vec<(u64, FlowControlSnapshot)> {
+        let mut snapshots: Vec<_> = self
+            .streams
+            .iter()
+            .filter(|(key, _)| key.cnx == cnx_id)
+            .map(|(key, stream)| (key.stream_id, stream.flow.snapshot()))
+            .collect();
+        snapshots.sort_unstable_by_key(|(stream_id, _)| *stream_id);
+        snapshots
+    }
+
     pub(crate) fn stream_send_backlog_summaries(
         &self,
         cnx_id: usize,
@@ -289,6 +801,8 @@ struct CommandCounts {
     stream_read_error: u64,
     stream_write_error: u64,
     stream_write_drained: u64,
+    datagram_received: u64,
+    datagram_flow_closed: u64,
 }
 
 impl CommandCounts {
@@ -301,6 +815,8 @@ impl CommandCounts {
             Command::StreamReadError { .. } => self.stream_read_error += 1,
             Command::StreamWriteError { .. } => self.stream_write_error += 1,
             Command::StreamWriteDrained { .. } => self.stream_write_drained += 1,
+            Command::DatagramReceived { .. } => self.datagram_received += 1,
+            Command::DatagramFlowClosed { .. } => self.datagram_flow_closed += 1,
         }
     }
 
@@ -312,6 +828,8 @@ impl CommandCounts {
             + self.stream_read_error
             + self.stream_write_error
             + self.stream_write_drained
+            + self.datagram_received
+            + self.datagram_flow_closed
     }
 
     fn reset(&mut self) {
@@ -331,9 +849,31 @@ struct ServerStream {
     pending_data: VecDeque<Vec<u8>>,
     pending_fin: bool,
     fin_enqueued: bool,
+    /// Connect-proxy streams hold their data until the leading proxy frame names a target.
+    awaiting_proxy_frame: bool,
     flow: FlowControlState,
 }
 
+impl ServerStream {
+    fn new(shutdown_tx: watch::Sender<bool>, awaiting_proxy_frame: bool) -> Self {
+        Self {
+            write_tx: None,
+            data_rx: None,
+            send_pending: None,
+            send_stash: None,
+            shutdown_tx,
+            tx_bytes: 0,
+            target_fin_pending: false,
+            close_after_flush: false,
+            pending_data: VecDeque::new(),
+            pending_fin: false,
+            fin_enqueued: false,
+            awaiting_proxy_frame,
+            flow: FlowControlState::default(),
+        }
+    }
+}
+
 impl HasFlowControlState for ServerStream {
     fn flow_control(&self) -> &FlowControlState {
         &self.flow
@@ -344,12 +884,18 @@ impl HasFlowControlState for ServerStream {
     }
 }
 
+fn open_stream_count(state: &ServerState, cnx_id: usize) -> usize {
+    state
+        .connection_streams
+        .get(&cnx_id)
+        .map_or(0, |streams| streams.open.len())
+}
+
 fn mark_multi_stream(state: &mut ServerState, cnx_id: usize) -> bool {
     if state.multi_streams.contains(&cnx_id) {
         return false;
     }
-    let count = state.streams.keys().filter(|key| key.cnx == cnx_id).count();
-    if count > 1 {
+    if open_stream_count(state, cnx_id) > 1 {
         state.multi_streams.insert(cnx_id);
         true
     } else {
@@ -357,6 +903,70 @@ fn mark_multi_stream(state: &mut ServerState, cnx_id: usize) -> bool {
     }
 }
 
+fn stream_limit_reached(state: &ServerState, cnx_id: usize) -> bool {
+    open_stream_count(state, cnx_id) >= state.max_streams_per_connection as usize
+}
+
+fn stream_rejected(state: &ServerState, key: StreamKey) -> bool {
+    state
+        .connection_streams
+        .get(&key.cnx)
+        .is_some_and(|streams| streams.rejected.contains(&key.stream_id))
+}
+
+/// Resets a stream opened over the limit, once; its later frames are ignored.
+fn reject_stream_over_limit(cnx: *mut picoquic_cnx_t, state: &mut ServerState, key: StreamKey) {
+    warn!(
+        "cnx {} stream {:?}: rejecting stream over the limit of {} per connection",
+        key.cnx, key.stream_id, state.max_streams_per_connection
+    );
+    state
+        .connection_streams
+        .entry(key.cnx)
+        .or_default()
+        .rejected
+        .insert(key.stream_id);
+    unsafe {
+        state
+            .stream_control
+            .abort_stream(cnx, key.stream_id, SLIPSTREAM_STREAM_LIMIT_ERROR)
+    };
+}
+
+fn apply_stream_priority(state: &mut ServerState, key: StreamKey, priority: u8) {
+    let cnx = key.cnx as *mut picoquic_cnx_t;
+    let ret = unsafe {
+        state
+            .stream_control
+            .set_stream_priority(cnx, key.stream_id, priority)
+    };
+    if ret != 0 && state.debug_streams {
+        debug!(
+            "stream {:?}: set_stream_priority({}) failed ret={}",
+            key.stream_id, priority, ret
+        );
+    }
+}
+
+/// Moves the oldest stream left on the connection up once every stream older than it closed.
+fn promote_oldest_stream(state: &mut ServerState, closed: StreamKey, priority: u8) {
+    let oldest = oldest_open_stream(state, closed.cnx);
+    if let Some(stream_id) = oldest.filter(|stream_id| *stream_id > closed.stream_id) {
+        let oldest = StreamKey {
+            cnx: closed.cnx,
+            stream_id,
+        };
+        apply_stream_priority(state, oldest, priority);
+    }
+}
+
+fn oldest_open_stream(state: &ServerState, cnx_id: usize) -> Option<u64> {
+    state
+        .connection_streams
+        .get(&cnx_id)
+        .and_then(|streams| streams.open.first().copied())
+}
+
 pub(crate) unsafe extern "C" fn server_callback(
     cnx: *mut picoquic_cnx_t,
     stream_id: u64,
@@ -370,6 +980,17 @@ pub(crate) unsafe extern "C" fn server_callback(
         return 0;
     }
     let state = &mut *(callback_ctx as *mut ServerState);
+    // 0-RTT data can open streams while the connection's first packet is still being decoded.
+    if !matches!(
+        fin_or_event,
+        picoquic_call_back_event_t::picoquic_callback_close
+            | picoquic_call_back_event_t::picoquic_callback_application_close
+            | picoquic_call_back_event_t::picoquic_callback_stateless_reset
+    ) {
+        if let Some(query) = state.incoming {
+            state.open_connection(cnx as usize, query);
+        }
+    }
 
     match fin_or_event {
         picoquic_call_back_event_t::picoquic_callback_stream_data
@@ -383,7 +1004,11 @@ pub(crate) unsafe extern "C" fn server_callback(
             } else {
                 &[]
             };
-            handle_stream_data(cnx, state, stream_id, fin, data);
+            if is_health_stream(stream_id) {
+                handle_health_probe(cnx, state, stream_id, fin, data);
+            } else {
+                handle_stream_data(cnx, state, stream_id, fin, data);
+            }
         }
         picoquic_call_back_event_t::picoquic_callback_stream_reset
         | picoquic_call_back_event_t::picoquic_callback_stop_sending => {
@@ -396,7 +1021,12 @@ pub(crate) unsafe extern "C" fn server_callback(
                 cnx: cnx as usize,
                 stream_id,
             };
+            if is_health_stream(stream_id) {
+                state.health_probes.remove(&key);
+                return 0;
+            }
             if let Some(stream) = shutdown_stream(state, key) {
+                note_stream_reset(state, key.cnx);
                 warn!(
                     "stream {:?}: reset event={} tx_bytes={} rx_bytes={} consumed_offset={} queued={} pending_chunks={} pending_fin={} fin_enqueued={} fin_offset={:?} target_fin_pending={} close_after_flush={}",
                     key.stream_id,
@@ -420,9 +1050,29 @@ pub(crate) unsafe extern "C" fn server_callback(
             }
             let _ = picoquic_reset_stream(cnx, stream_id, SLIPSTREAM_FILE_CANCEL_ERROR);
         }
+        picoquic_call_back_event_t::picoquic_callback_ready => {
+            state.metrics.record_connection_ready();
+        }
+        picoquic_call_back_event_t::picoquic_callback_datagram
+            if state.datagram_mode && length > 0 && !bytes.is_null() =>
+        {
+            let frame = unsafe { std::slice::from_raw_parts(bytes as *const u8, length) };
+            handle_datagram(cnx, state, frame);
+        }
         picoquic_call_back_event_t::picoquic_callback_close
         | picoquic_call_back_event_t::picoquic_callback_application_close
         | picoquic_call_back_event_t::picoquic_callback_stateless_reset => {
+            let reason = match fin_or_event {
+                picoquic_call_back_event_t::picoquic_callback_application_close => {
+                    CloseReason::Application
+                }
+                picoquic_call_back_event_t::picoquic_callback_stateless_reset => {
+                    CloseReason::StatelessReset
+                }
+                _ => CloseReason::Transport,
+            };
+            report_handshake_failure(state, cnx);
+            state.record_connection_closed(cnx as usize, reason);
             remove_connection_streams(state, cnx as usize);
             let _ = picoquic_close(cnx, 0);
         }
@@ -511,6 +1161,12 @@ pub(crate) unsafe extern "C" fn server_callback(
                 } else if let Some(rx) = stream.data_rx.as_mut() {
                     match rx.try_recv() {
                         Ok(mut data) => {
+                            count_target_bytes(
+                                &mut state.stream_target_bytes,
+                                &mut state.connections,
+                                key,
+                                |totals| totals.rx += data.len() as u64,
+                            );
                             if data.len() > length {
                                 let remainder = data.split_off(length);
                                 stream.send_stash = Some(remainder);
@@ -553,6 +1209,10 @@ pub(crate) unsafe extern "C" fn server_callback(
                         std::ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
                     }
                     stream.tx_bytes = stream.tx_bytes.saturating_add(data.len() as u64);
+                    state.metrics.add_bytes_tx(data.len());
+                    if let Some(stats) = state.connections.get_mut(&key.cnx) {
+                        stats.bytes_sent += data.len() as u64;
+                    }
                 } else if stream.target_fin_pending {
                     stream.target_fin_pending = false;
                     if stream.close_after_flush {
@@ -582,6 +1242,37 @@ pub(crate) unsafe extern "C" fn server_callback(
     0
 }
 
+fn handle_datagram(cnx: *mut picoquic_cnx_t, state: &mut ServerState, frame: &[u8]) {
+    let Some((flow_id, payload)) = decode_datagram(frame) else {
+        return;
+    };
+    let key = FlowKey {
+        cnx: cnx as usize,
+        flow_id,
+    };
+    state.metrics.add_bytes_rx(payload.len());
+    if let Some(stats) = state.connections.get_mut(&key.cnx) {
+        stats.bytes_received += payload.len() as u64;
+    }
+    if !state.datagram_flows.contains(key) {
+        let target = state.connection_target(key.cnx);
+        let opened =
+            state
+                .datagram_flows
+                .open(key, target, state.command_tx.clone(), state.debug_streams);
+        if !opened {
+            if state.debug_streams {
+                debug!(
+                    "cnx {} flow {}: dropped a datagram over the flow limit",
+                    key.cnx, key.flow_id
+                );
+            }
+            return;
+        }
+    }
+    state.datagram_flows.forward(key, payload);
+}
+
 fn handle_stream_data(
     cnx: *mut picoquic_cnx_t,
     state: &mut ServerState,
@@ -595,36 +1286,46 @@ fn handle_stream_data(
     };
     let debug_streams = state.debug_streams;
     let mut reset_stream = false;
+    state.metrics.add_bytes_rx(data.len());
+    if let Some(stats) = state.connections.get_mut(&key.cnx) {
+        stats.bytes_received += data.len() as u64;
+    }
     let mut remove_stream = false;
 
     if !state.streams.contains_key(&key) {
+        if stream_rejected(state, key) {
+            return;
+        }
+        if stream_limit_reached(state, key.cnx) {
+            reject_stream_over_limit(cnx, state, key);
+            return;
+        }
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
         if debug_streams {
             debug!("stream {:?}: connecting", key.stream_id);
         }
-        spawn_target_connector(
-            key,
-            state.target_addr,
-            state.command_tx.clone(),
-            debug_streams,
-            shutdown_rx,
-        );
-        state.streams.insert(
+        if let Some(mode) = state.stream_priority_mode {
+            let oldest =
+                oldest_open_stream(state, key.cnx).is_none_or(|oldest| oldest > key.stream_id);
+            apply_stream_priority(state, key, mode.initial_priority(key.stream_id, oldest));
+        }
+        let awaiting_proxy_frame = state.target_mode == TargetMode::ConnectProxy;
+        if !awaiting_proxy_frame {
+            spawn_target_connector(
+                target_relay(state, key, shutdown_rx),
+                state.connection_target(key.cnx),
+                proxy_header(state, key.cnx),
+            );
+        }
+        state.metrics.record_stream_opened();
+        if let Some(stats) = state.connections.get_mut(&key.cnx) {
+            stats.streams += 1;
+        }
+        state.stream_target_bytes.insert(key, ByteTotals::default());
+        insert_stream(
+            state,
             key,
-            ServerStream {
-                write_tx: None,
-                data_rx: None,
-                send_pending: None,
-                send_stash: None,
-                shutdown_tx,
-                tx_bytes: 0,
-                target_fin_pending: false,
-                close_after_flush: false,
-                pending_data: VecDeque::new(),
-                pending_fin: false,
-                fin_enqueued: false,
-                flow: FlowControlState::default(),
-            },
+            ServerStream::new(shutdown_tx, awaiting_proxy_frame),
         );
     }
 
@@ -744,6 +1445,13 @@ fn handle_stream_data(
         return;
     }
 
+    if !reset_stream {
+        if let Err(err) = open_proxy_target(cnx, state, key) {
+            warn!("stream {:?}: {}", stream_id, err);
+            reset_stream = true;
+        }
+    }
+
     if reset_stream {
         if debug_streams {
             debug!("stream {:?}: resetting", stream_id);
@@ -762,27 +1470,263 @@ fn handle_stream_data(
     check_stream_invariants(state, key, "handle_stream_data");
 }
 
+/// Echoes a client health probe on a unidirectional stream of the server's own.
+fn handle_health_probe(
+    cnx: *mut picoquic_cnx_t,
+    state: &mut ServerState,
+    stream_id: u64,
+    fin: bool,
+    data: &[u8],
+) {
+    let key = StreamKey {
+        cnx: cnx as usize,
+        stream_id,
+    };
+    let frame = state.health_probes.entry(key).or_default();
+    if !extend_health_frame(frame, data) {
+        state.health_probes.remove(&key);
+        warn!("stream {}: malformed health probe", stream_id);
+        let _ = unsafe { picoquic_stop_sending(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
+        return;
+    }
+    let _ = unsafe { picoquic_stream_data_consumed(cnx, stream_id, frame.len() as u64) };
+    if !fin {
+        return;
+    }
+    let Some(token) = state
+        .health_probes
+        .remove(&key)
+        .and_then(|frame| decode_health_frame(&frame))
+    else {
+        warn!("stream {}: health probe ended early", stream_id);
+        return;
+    };
+    let reply = encode_health_frame(token);
+    let reply_id = unsafe { picoquic_get_next_local_stream_id(cnx, 1) };
+    let ret = unsafe { picoquic_add_to_stream(cnx, reply_id, reply.as_ptr(), reply.len(), 1) };
+    if ret != 0 {
+        warn!(
+            "stream {}: health reply on stream {} failed ret={}",
+            stream_id, reply_id, ret
+        );
+    } else if state.debug_streams {
+        debug!(
+            "stream {}: answered health probe on stream {}",
+            stream_id, reply_id
+        );
+    }
+}
+
+/// Logs the TLS errors behind a connection closing in a failed handshake. One client's failure
+/// does not stop the server, so this is where a bad certificate or ALPN shows up.
+fn report_handshake_failure(state: &ServerState, cnx: *mut picoquic_cnx_t) {
+    let handshake_failed = matches!(
+        unsafe { picoquic_get_cnx_state(cnx) },
+        picoquic_state_enum::picoquic_state_handshake_failure
+            | picoquic_state_enum::picoquic_state_handshake_failure_resend
+    );
+    if !handshake_failed {
+        return;
+    }
+    let client = state
+        .connections
+        .get(&(cnx as usize))
+        .and_then(|stats| stats.client_addr)
+        .map_or_else(|| "unknown client".to_string(), |addr| addr.to_string());
+    let errors = take_crypto_errors();
+    if errors.is_empty() {
+        warn!("TLS handshake with {} failed", client);
+    } else {
+        warn!(
+            "TLS handshake with {} failed: {}",
+            client,
+            errors.join("; ")
+        );
+    }
+}
+
+/// The shared settings of a stream's target tasks.
+fn target_relay(
+    state: &mut ServerState,
+    key: StreamKey,
+    shutdown_rx: watch::Receiver<bool>,
+) -> TargetRelay {
+    TargetRelay {
+        key,
+        command_tx: state.command_tx.clone(),
+        rate_limiter: state.target_rate_limiter(key.cnx),
+        write_timeout: state.target_write_timeout,
+        debug_streams: state.debug_streams,
+        shutdown_rx,
+    }
+}
+
+fn proxy_header(state: &ServerState, cnx_id: usize) -> ProxyHeader {
+    if !state.target_proxy_protocol {
+        return ProxyHeader::Off;
+    }
+    state
+        .connections
+        .get(&cnx_id)
+        .and_then(|stats| stats.client_addr)
+        .map_or(ProxyHeader::Local, ProxyHeader::Client)
+}
+
+/// Starts the target connection once a connect-proxy stream has its whole frame.
+fn open_proxy_target(
+    cnx: *mut picoquic_cnx_t,
+    state: &mut ServerState,
+    key: StreamKey,
+) -> Result<(), String> {
+    let Some(stream) = state.streams.get_mut(&key) else {
+        return Ok(());
+    };
+    if !stream.awaiting_proxy_frame || stream.flow.discarding {
+        return Ok(());
+    }
+    let Some((target, frame_len)) =
+        take_proxy_frame(&mut stream.pending_data).map_err(|err| err.to_string())?
+    else {
+        if stream.flow.fin_offset.is_some() {
+            return Err("stream finished before its proxy frame".to_string());
+        }
+        return Ok(());
+    };
+    stream.awaiting_proxy_frame = false;
+    let shutdown_rx = stream.shutdown_tx.subscribe();
+    if state.debug_streams {
+        debug!(
+            "stream {:?}: connect-proxy to {}:{}",
+            key.stream_id, target.host, target.port
+        );
+    }
+    spawn_target_connector(
+        target_relay(state, key, shutdown_rx),
+        TargetAddr::Dial(target, state.address_policy),
+        proxy_header(state, key.cnx),
+    );
+    // The frame never reaches the target, so credit it back as if it had drained.
+    if release_queued_bytes(cnx, state, key, frame_len) {
+        return Err("failed to consume the proxy frame".to_string());
+    }
+    Ok(())
+}
+
+/// Removes a complete proxy frame from the front of `pending`.
+fn take_proxy_frame(
+    pending: &mut VecDeque<Vec<u8>>,
+) -> Result<Option<(ProxyTarget, usize)>, ProxyFrameError> {
+    let mut head = Vec::with_capacity(MAX_PROXY_FRAME_LEN);
+    for chunk in pending.iter() {
+        let take = chunk.len().min(MAX_PROXY_FRAME_LEN - head.len());
+        head.extend_from_slice(&chunk[..take]);
+        if head.len() == MAX_PROXY_FRAME_LEN {
+            break;
+        }
+    }
+    let Some((target, frame_len)) = decode_proxy_frame(&head)? else {
+        return Ok(None);
+    };
+    let mut remaining = frame_len;
+    while remaining > 0 {
+        let Some(chunk) = pending.front_mut() else {
+            break;
+        };
+        if chunk.len() <= remaining {
+            remaining -= chunk.len();
+            pending.pop_front();
+        } else {
+            chunk.drain(..remaining);
+            remaining = 0;
+        }
+    }
+    Ok(Some((target, frame_len)))
+}
+
 pub(crate) fn remove_connection_streams(state: &mut ServerState, cnx: usize) {
-    let keys: Vec<StreamKey> = state
+    let mut keys: Vec<StreamKey> = state
         .streams
         .keys()
         .filter(|key| key.cnx == cnx)
         .cloned()
         .collect();
+    // Newest first, so closing streams never promotes one that is about to close too.
+    keys.sort_unstable_by_key(|key| std::cmp::Reverse(key.stream_id));
     for key in keys {
         shutdown_stream(state, key);
     }
     state.multi_streams.remove(&cnx);
+    state.connection_streams.remove(&cnx);
+    state.health_probes.retain(|key, _| key.cnx != cnx);
+    state.datagram_flows.remove_connection(cnx);
+    state.target_rate_limiters.remove(&cnx);
+}
+
+fn insert_stream(state: &mut ServerState, key: StreamKey, stream: ServerStream) {
+    state
+        .connection_streams
+        .entry(key.cnx)
+        .or_default()
+        .open
+        .insert(key.stream_id);
+    state.streams.insert(key, stream);
 }
 
 fn shutdown_stream(state: &mut ServerState, key: StreamKey) -> Option<ServerStream> {
     if let Some(stream) = state.streams.remove(&key) {
+        if let Some(streams) = state.connection_streams.get_mut(&key.cnx) {
+            streams.open.remove(&key.stream_id);
+        }
+        let totals = state.stream_byte_totals(key);
+        state.stream_target_bytes.remove(&key);
+        if let Some(stats) = state.connections.get_mut(&key.cnx) {
+            stats
+                .flow_history
+                .record(unix_time_ms(), key.stream_id, stream.flow.snapshot());
+        }
+        if state.debug_streams {
+            debug!(
+                "stream {:?}: removed target_rx_bytes={} target_tx_bytes={}",
+                key.stream_id, totals.rx, totals.tx
+            );
+        }
         let _ = stream.shutdown_tx.send(true);
+        if let Some(priority) = state
+            .stream_priority_mode
+            .and_then(StreamPriorityMode::promoted_priority)
+        {
+            promote_oldest_stream(state, key, priority);
+        }
         return Some(stream);
     }
     None
 }
 
+/// Counts a stream of `cnx_id` that ended in a reset rather than a clean close.
+fn note_stream_reset(state: &mut ServerState, cnx_id: usize) {
+    if let Some(stats) = state.connections.get_mut(&cnx_id) {
+        stats.streams_reset += 1;
+    }
+}
+
+/// Runs `feed`, which hands `query`'s payload to QUIC, with the query visible to the callbacks
+/// it triggers; no query is visible outside such a call.
+pub(crate) fn feed_query<R>(
+    state_ptr: *mut ServerState,
+    query: IncomingQuery,
+    feed: impl FnOnce() -> R,
+) -> R {
+    // SAFETY: the server loop owns the state and holds no other borrow of it here.
+    if let Some(state) = unsafe { state_ptr.as_mut() } {
+        state.incoming = Some(query);
+    }
+    let result = feed();
+    if let Some(state) = unsafe { state_ptr.as_mut() } {
+        state.incoming = None;
+    }
+    result
+}
+
 pub(crate) fn drain_commands(
     state_ptr: *mut ServerState,
     command_rx: &mut mpsc::UnboundedReceiver<Command>,
@@ -872,6 +1816,7 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 stream_id,
             };
             if shutdown_stream(state, key).is_some() {
+                note_stream_reset(state, cnx_id);
                 unsafe { abort_stream_bidi(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
                 warn!("stream {:?}: target connect failed", stream_id);
             }
@@ -953,10 +1898,13 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                         unsafe { abort_stream_bidi(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
                     }
                     remove_stream = true;
+                } else if let Some(stats) = state.connections.get_mut(&cnx_id) {
+                    stats.streams_closed += 1;
                 }
             }
             if remove_stream {
                 shutdown_stream(state, key);
+                note_stream_reset(state, cnx_id);
             }
             check_stream_invariants(state, key, "StreamClosed");
         }
@@ -988,6 +1936,7 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 unsafe { picoquic_mark_active_stream(cnx, stream_id, 1, std::ptr::null_mut()) };
             if ret != 0 {
                 if let Some(stream) = shutdown_stream(state, key) {
+                    note_stream_reset(state, cnx_id);
                     warn!(
                         "stream {:?}: mark_active_stream readable failed ret={} tx_bytes={} rx_bytes={} consumed_offset={} queued={} fin_offset={:?}",
                         stream_id,
@@ -1016,6 +1965,7 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 stream_id,
             };
             if let Some(stream) = shutdown_stream(state, key) {
+                note_stream_reset(state, cnx_id);
                 warn!(
                     "stream {:?}: target read error tx_bytes={} rx_bytes={} consumed_offset={} queued={} fin_offset={:?}",
                     stream_id,
@@ -1035,6 +1985,7 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 stream_id,
             };
             if let Some(stream) = shutdown_stream(state, key) {
+                note_stream_reset(state, cnx_id);
                 warn!(
                     "stream {:?}: target write failed tx_bytes={} rx_bytes={} consumed_offset={} queued={} fin_offset={:?}",
                     stream_id,
@@ -1056,55 +2007,100 @@ pub(crate) fn handle_command(state_ptr: *mut ServerState, command: Command) {
                 cnx: cnx_id,
                 stream_id,
             };
-            let mut reset_stream = false;
-            if let Some(stream) = state.streams.get_mut(&key) {
-                if stream.flow.discarding {
-                    return;
-                }
-                stream.flow.queued_bytes = stream.flow.queued_bytes.saturating_sub(bytes);
-                if !state.multi_streams.contains(&cnx_id) {
-                    let new_offset = reserve_target_offset(
-                        stream.flow.rx_bytes,
-                        stream.flow.queued_bytes,
-                        stream.flow.fin_offset,
-                        conn_reserve_bytes(),
-                    );
-                    if !consume_stream_data(
-                        &mut stream.flow.consumed_offset,
-                        new_offset,
-                        |new_offset| unsafe {
-                            picoquic_stream_data_consumed(
-                                cnx_id as *mut picoquic_cnx_t,
-                                stream_id,
-                                new_offset,
-                            )
-                        },
-                        |ret, current, target| {
-                            warn!(
-                                "{}",
-                                consume_error_log_message(stream_id, "", ret, current, target)
-                            );
-                        },
-                    ) {
-                        reset_stream = true;
-                    }
-                }
+            count_target_bytes(
+                &mut state.stream_target_bytes,
+                &mut state.connections,
+                key,
+                |totals| totals.tx += bytes as u64,
+            );
+            if state
+                .streams
+                .get(&key)
+                .is_some_and(|stream| stream.flow.discarding)
+            {
+                return;
             }
-            if reset_stream {
+            let cnx = cnx_id as *mut picoquic_cnx_t;
+            if release_queued_bytes(cnx, state, key, bytes) {
                 shutdown_stream(state, key);
-                unsafe {
-                    abort_stream_bidi(
-                        cnx_id as *mut picoquic_cnx_t,
-                        stream_id,
-                        SLIPSTREAM_INTERNAL_ERROR,
-                    )
-                };
+                note_stream_reset(state, cnx_id);
+                unsafe { abort_stream_bidi(cnx, stream_id, SLIPSTREAM_INTERNAL_ERROR) };
             }
             check_stream_invariants(state, key, "StreamWriteDrained");
         }
+        Command::DatagramReceived {
+            cnx_id,
+            flow_id,
+            generation,
+            data,
+        } => {
+            // Flows go away with their connection, so the flow that read the reply still
+            // being open means `cnx_id` is the connection it was read for.
+            let key = FlowKey {
+                cnx: cnx_id,
+                flow_id,
+            };
+            if !state.datagram_flows.is_current(key, generation) {
+                return;
+            }
+            let cnx = cnx_id as *mut picoquic_cnx_t;
+            if unsafe { queue_datagram(cnx, flow_id, &data) } {
+                state.metrics.add_bytes_tx(data.len());
+                if let Some(stats) = state.connections.get_mut(&cnx_id) {
+                    stats.bytes_sent += data.len() as u64;
+                }
+            } else if state.debug_streams {
+                debug!(
+                    "cnx {} flow {}: dropped a {} byte datagram that does not fit a packet",
+                    cnx_id,
+                    flow_id,
+                    data.len()
+                );
+            }
+        }
+        Command::DatagramFlowClosed { cnx_id, flow_id } => {
+            state.datagram_flows.remove_closed(FlowKey {
+                cnx: cnx_id,
+                flow_id,
+            });
+        }
     }
 }
 
+/// Takes `bytes` off a stream's target queue and, in single-stream mode, moves the consumed
+/// offset up behind them. Returns true when the stream must be reset.
+fn release_queued_bytes(
+    cnx: *mut picoquic_cnx_t,
+    state: &mut ServerState,
+    key: StreamKey,
+    bytes: usize,
+) -> bool {
+    let Some(stream) = state.streams.get_mut(&key) else {
+        return false;
+    };
+    stream.flow.queued_bytes = stream.flow.queued_bytes.saturating_sub(bytes);
+    if state.multi_streams.contains(&key.cnx) {
+        return false;
+    }
+    let new_offset = reserve_target_offset(
+        stream.flow.rx_bytes,
+        stream.flow.queued_bytes,
+        stream.flow.fin_offset,
+        conn_reserve_bytes(),
+    );
+    !consume_stream_data(
+        &mut stream.flow.consumed_offset,
+        new_offset,
+        |new_offset| unsafe { picoquic_stream_data_consumed(cnx, key.stream_id, new_offset) },
+        |ret, current, target| {
+            warn!(
+                "{}",
+                consume_error_log_message(key.stream_id, "", ret, current, target)
+            );
+        },
+    )
+}
+
 pub(crate) fn maybe_report_command_stats(state_ptr: *mut ServerState) {
     let state = unsafe { &mut *state_ptr };
     if !state.debug_commands {
@@ -1117,7 +2113,7 @@ pub(crate) fn maybe_report_command_stats(state_ptr: *mut ServerState) {
     let total = state.command_counts.total();
     if total > 0 {
         debug!(
-            "debug: commands total={} connected={} connect_err={} closed={} readable={} read_err={} write_err={} write_drained={}",
+            "debug: commands total={} connected={} connect_err={} closed={} readable={} read_err={} write_err={} write_drained={} datagrams={} datagram_flows_closed={}",
             total,
             state.command_counts.stream_connected,
             state.command_counts.stream_connect_error,
@@ -1125,14 +2121,34 @@ pub(crate) fn maybe_report_command_stats(state_ptr: *mut ServerState) {
             state.command_counts.stream_readable,
             state.command_counts.stream_read_error,
             state.command_counts.stream_write_error,
-            state.command_counts.stream_write_drained
+            state.command_counts.stream_write_drained,
+            state.command_counts.datagram_received,
+            state.command_counts.datagram_flow_closed
         );
     }
     state.command_counts.reset();
     state.last_command_report = now;
 }
 
-pub(crate) fn handle_shutdown(quic: *mut picoquic_quic_t, state: &mut ServerState) -> bool {
+/// Closes every connection with `SLIPSTREAM_FILE_CANCEL_ERROR` when draining runs out of time,
+/// returning how many streams were still open.
+///
+/// The connections stay in picoquic so the close can ride the answers to their next queries.
+pub(crate) fn cancel_connections(quic: *mut picoquic_quic_t, state: &mut ServerState) -> usize {
+    let forced = state.streams.len();
+    let mut cnx = unsafe { picoquic_get_first_cnx(quic) };
+    while !cnx.is_null() {
+        let next = unsafe { picoquic_get_next_cnx(cnx) };
+        let _ = unsafe { picoquic_close(cnx, SLIPSTREAM_FILE_CANCEL_ERROR) };
+        remove_connection_streams(state, cnx as usize);
+        cnx = next;
+    }
+    forced
+}
+
+/// Drops every connection without notifying peers, returning how many streams were still open.
+pub(crate) fn handle_shutdown(quic: *mut picoquic_quic_t, state: &mut ServerState) -> usize {
+    let forced = state.streams.len();
     let mut cnx = unsafe { picoquic_get_first_cnx(quic) };
     while !cnx.is_null() {
         let next = unsafe { picoquic_get_next_cnx(cnx) };
@@ -1142,7 +2158,7 @@ pub(crate) fn handle_shutdown(quic: *mut picoquic_quic_t, state: &mut ServerStat
     }
     state.streams.clear();
     state.multi_streams.clear();
-    true
+    forced
 }
 
 #[cfg(test)]
@@ -1159,32 +2175,228 @@ mod tests {
     use std::sync::Arc;
     use tokio::sync::{mpsc, watch};
 
+    /// A state with the defaults `run_server` starts from; tests adjust its fields.
+    fn test_state() -> (ServerState, mpsc::UnboundedReceiver<Command>) {
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let target_addr = TargetAddr::Fixed(SocketAddr::from(([127, 0, 0, 1], 0)));
+        let state = ServerState::new(
+            target_addr,
+            command_tx,
+            256,
+            false,
+            false,
+            Arc::default(),
+            Arc::default(),
+        );
+        (state, command_rx)
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum StreamCall {
+        Abort { stream_id: u64, app_error: u64 },
+        Priority { stream_id: u64, priority: u8 },
+    }
+
+    /// Stands in for picoquic, recording the stream operations a handler issues.
+    #[derive(Clone, Default)]
+    struct RecordedStreams(Arc<Mutex<Vec<StreamCall>>>);
+
+    impl RecordedStreams {
+        fn install(state: &mut ServerState) -> Self {
+            let recorded = Self::default();
+            state.stream_control = Box::new(recorded.clone());
+            recorded
+        }
+
+        fn calls(&self) -> Vec<StreamCall> {
+            self.0.lock().unwrap().clone()
+        }
+
+        fn priorities(&self) -> Vec<(u64, u8)> {
+            self.calls()
+                .into_iter()
+                .filter_map(|call| match call {
+                    StreamCall::Priority {
+                        stream_id,
+                        priority,
+                    } => Some((stream_id, priority)),
+                    StreamCall::Abort { .. } => None,
+                })
+                .collect()
+        }
+    }
+
+    impl StreamControl for RecordedStreams {
+        unsafe fn abort_stream(
+            &mut self,
+            _cnx: *mut picoquic_cnx_t,
+            stream_id: u64,
+            app_error: u64,
+        ) {
+            self.0.lock().unwrap().push(StreamCall::Abort {
+                stream_id,
+                app_error,
+            });
+        }
+
+        unsafe fn set_stream_priority(
+            &mut self,
+            _cnx: *mut picoquic_cnx_t,
+            stream_id: u64,
+            priority: u8,
+        ) -> libc::c_int {
+            self.0.lock().unwrap().push(StreamCall::Priority {
+                stream_id,
+                priority,
+            });
+            0
+        }
+    }
+
+    fn record(cnx_id: usize) -> ConnectionRecord {
+        ConnectionRecord {
+            cnx_id,
+            client_addr: None,
+            connected_at_ms: 0,
+            disconnected_at_ms: 0,
+            close_reason: CloseReason::Transport,
+            bytes_sent: 0,
+            bytes_received: 0,
+            streams: 0,
+            flow_history: FlowControlHistory::default(),
+        }
+    }
+
+    #[test]
+    fn connection_record_lists_flow_history() {
+        let mut record = record(1);
+        let flow = FlowControlState {
+            rx_bytes: 10,
+            consumed_offset: 10,
+            fin_offset: Some(10),
+            ..FlowControlState::default()
+        };
+        record.flow_history.record(7, 4, flow.snapshot());
+        let log = ConnectionLog::new(1);
+        log.push(record);
+        assert!(log.to_json().ends_with(
+            ",\"streams\":0,\"flow_history\":[{\"at_ms\":7,\"stream_id\":4,\"flow\":\"queued=0 rx=10 consumed=10 fin=10 discarding=false stop_sending=false\"}]}]"
+        ));
+    }
+
+    #[test]
+    fn connection_log_keeps_the_newest_records() {
+        let log = ConnectionLog::new(2);
+        log.push(record(1));
+        log.push(record(2));
+        log.push(record(3));
+        let json = log.to_json();
+        assert!(!json.contains("\"cnx_id\":1,"));
+        assert!(json.starts_with("[{\"cnx_id\":2,\"client_addr\":null,"));
+        assert!(json.contains("},{\"cnx_id\":3,"));
+
+        let disabled = ConnectionLog::new(0);
+        disabled.push(record(1));
+        assert_eq!(disabled.to_json(), "[]");
+    }
+
+    #[test]
+    fn streams_follow_the_domain_of_the_first_query() {
+        let (mut state, _command_rx) = test_state();
+        let default_target = state.target_addr.clone();
+        assert_eq!(state.connection_target(0x1), default_target);
+
+        let a = TargetAddr::Fixed("127.0.0.1:2".parse().unwrap());
+        let b = TargetAddr::Dial(
+            ProxyTarget {
+                host: "target.example".to_string(),
+                port: 3,
+            },
+            AddressPolicy::Any,
+        );
+        state.set_domain_targets(vec![a.clone(), b.clone()]);
+        let query = |domain| IncomingQuery {
+            peer: "192.0.2.1:53".parse().unwrap(),
+            domain,
+        };
+        let state_ptr: *mut ServerState = &mut state;
+        // A stream opened by 0-RTT data before the connection's first packet is decoded.
+        let target = feed_query(state_ptr, query(1), || unsafe {
+            (*state_ptr).connection_target(0x2)
+        });
+        assert_eq!(target, b);
+        state.open_connection(0x2, query(1));
+        let state_ptr: *mut ServerState = &mut state;
+        let target = feed_query(state_ptr, query(0), || unsafe {
+            (*state_ptr).connection_target(0x2)
+        });
+        assert_eq!(target, b);
+
+        // A closed connection's address may come back for another client and domain.
+        state.record_connection_closed(0x2, CloseReason::Transport);
+        state.open_connection(0x2, query(0));
+        assert_eq!(state.connection_target(0x2), a);
+    }
+
+    #[test]
+    fn draining_serves_only_connections_still_open_from_before() {
+        let (mut state, _command_rx) = test_state();
+        assert!(!state.refuses_connection(0x3));
+        state.start_drain(HashSet::from([0x1, 0x2]));
+        assert!(!state.refuses_connection(0x1));
+        assert!(state.refuses_connection(0x3));
+
+        // A closed connection's address may come back, but only as a new connection.
+        state.record_connection_closed(0x1, CloseReason::Transport);
+        assert!(state.refuses_connection(0x1));
+        assert!(!state.refuses_connection(0x2));
+    }
+
+    #[test]
+    fn closed_connection_totals_move_to_the_log() {
+        let log = Arc::new(ConnectionLog::new(4));
+        let (mut state, _command_rx) = test_state();
+        state.connection_log = log.clone();
+        let query = |peer: &str| IncomingQuery {
+            peer: peer.parse().unwrap(),
+            domain: 0,
+        };
+        state.open_connection(0x1, query("192.0.2.1:53"));
+        state.open_connection(0x1, query("192.0.2.2:53"));
+        state.connections.get_mut(&0x1).unwrap().bytes_sent += 5;
+        state.record_connection_closed(0x1, CloseReason::IdleTimeout);
+        state.record_connection_closed(0x1, CloseReason::Transport);
+
+        let json = log.to_json();
+        assert!(
+            json.contains("\"client_addr\":\"192.0.2.1:53\""),
+            "{}",
+            json
+        );
+        assert!(
+            json.contains("\"close_reason\":\"idle_timeout\""),
+            "{}",
+            json
+        );
+        assert!(json.contains("\"bytes_sent\":5,"), "{}", json);
+        assert_eq!(json.matches("cnx_id").count(), 1);
+    }
+
     #[test]
     fn mark_active_stream_failure_should_remove_stream() {
-        let (command_tx, _command_rx) = mpsc::unbounded_channel();
-        let target_addr = SocketAddr::from(([127, 0, 0, 1], 0));
-        let mut state = ServerState::new(target_addr, command_tx, false, false);
+        let (mut state, _command_rx) = test_state();
         let key = StreamKey {
             cnx: 0x1,
             stream_id: 4,
         };
         let (shutdown_tx, _shutdown_rx) = watch::channel(false);
 
-        state.streams.insert(
+        insert_stream(
+            &mut state,
             key,
             ServerStream {
-                write_tx: None,
-                data_rx: None,
                 send_pending: Some(Arc::new(AtomicBool::new(false))),
-                send_stash: None,
-                shutdown_tx,
-                tx_bytes: 0,
-                target_fin_pending: false,
-                close_after_flush: false,
-                pending_data: VecDeque::new(),
-                pending_fin: false,
-                fin_enqueued: false,
-                flow: FlowControlState::default(),
+                ..ServerStream::new(shutdown_tx, false)
             },
         );
 
@@ -1206,9 +2418,7 @@ mod tests {
 
     #[test]
     fn mark_active_stream_readable_failure_should_not_leave_send_pending_stuck() {
-        let (command_tx, _command_rx) = mpsc::unbounded_channel();
-        let target_addr = SocketAddr::from(([127, 0, 0, 1], 0));
-        let mut state = ServerState::new(target_addr, command_tx, false, false);
+        let (mut state, _command_rx) = test_state();
         let key = StreamKey {
             cnx: 0x1,
             stream_id: 4,
@@ -1217,21 +2427,12 @@ mod tests {
         let send_pending = Arc::new(AtomicBool::new(true));
         let send_pending_handle = Arc::clone(&send_pending);
 
-        state.streams.insert(
+        insert_stream(
+            &mut state,
             key,
             ServerStream {
-                write_tx: None,
-                data_rx: None,
                 send_pending: Some(send_pending_handle),
-                send_stash: None,
-                shutdown_tx,
-                tx_bytes: 0,
-                target_fin_pending: false,
-                close_after_flush: false,
-                pending_data: VecDeque::new(),
-                pending_fin: false,
-                fin_enqueued: false,
-                flow: FlowControlState::default(),
+                ..ServerStream::new(shutdown_tx, false)
             },
         );
 
@@ -1255,4 +2456,245 @@ mod tests {
             "send_pending should be dropped when the stream is removed"
         );
     }
+
+    #[test]
+    fn write_drained_commands_accumulate_target_bytes() {
+        let (mut state, _command_rx) = test_state();
+        let key = StreamKey {
+            cnx: 0x1,
+            stream_id: 4,
+        };
+        let (shutdown_tx, _shutdown_rx) = watch::channel(false);
+        state.open_connection(
+            key.cnx,
+            IncomingQuery {
+                peer: "192.0.2.1:53".parse().unwrap(),
+                domain: 0,
+            },
+        );
+        state.stream_target_bytes.insert(key, ByteTotals::default());
+        state
+            .streams
+            .insert(key, ServerStream::new(shutdown_tx, false));
+        let drained = |bytes| Command::StreamWriteDrained {
+            cnx_id: key.cnx,
+            stream_id: key.stream_id,
+            bytes,
+        };
+
+        handle_command(&mut state as *mut _, drained(5));
+        handle_command(&mut state as *mut _, drained(7));
+        count_target_bytes(
+            &mut state.stream_target_bytes,
+            &mut state.connections,
+            key,
+            |totals| totals.rx += 3,
+        );
+        assert_eq!(state.stream_byte_totals(key), ByteTotals { rx: 3, tx: 12 });
+        assert_eq!(
+            state.connection_byte_totals(key.cnx),
+            ByteTotals { rx: 3, tx: 12 }
+        );
+
+        // Writes that drain after the stream is gone still count for the connection.
+        shutdown_stream(&mut state, key);
+        handle_command(&mut state as *mut _, drained(4));
+        assert_eq!(state.stream_byte_totals(key), ByteTotals::default());
+        assert_eq!(state.connection_byte_totals(key.cnx).tx, 16);
+
+        state.record_connection_closed(key.cnx, CloseReason::Transport);
+        handle_command(&mut state as *mut _, drained(1));
+        assert_eq!(state.connection_byte_totals(key.cnx), ByteTotals::default());
+    }
+
+    #[test]
+    fn debug_stats_count_stream_outcomes_per_connection() {
+        let (mut state, _command_rx) = test_state();
+        state.debug_commands = true;
+        let key = StreamKey {
+            cnx: 0x1,
+            stream_id: 4,
+        };
+        let (shutdown_tx, _shutdown_rx) = watch::channel(false);
+        state.open_connection(
+            key.cnx,
+            IncomingQuery {
+                peer: "192.0.2.1:53".parse().unwrap(),
+                domain: 0,
+            },
+        );
+        state.connections.get_mut(&key.cnx).unwrap().streams += 1;
+        state.stream_target_bytes.insert(key, ByteTotals::default());
+        state
+            .streams
+            .insert(key, ServerStream::new(shutdown_tx, false));
+        handle_command(
+            &mut state as *mut _,
+            Command::StreamWriteDrained {
+                cnx_id: key.cnx,
+                stream_id: key.stream_id,
+                bytes: 9,
+            },
+        );
+        count_target_bytes(
+            &mut state.stream_target_bytes,
+            &mut state.connections,
+            key,
+            |totals| totals.rx += 2,
+        );
+
+        // The target closed the stream, then the FIN could not be scheduled.
+        test_helpers::set_mark_active_stream_failures(&mut state, 1);
+        handle_command(
+            &mut state as *mut _,
+            Command::StreamClosed {
+                cnx_id: key.cnx,
+                stream_id: key.stream_id,
+            },
+        );
+        assert_eq!(
+            state.connection_debug_stats(key.cnx),
+            ConnectionDebugStats {
+                bytes_sent_to_target: 9,
+                bytes_received_from_target: 2,
+                stream_open_count: 1,
+                stream_close_count: 0,
+                stream_reset_count: 1,
+            }
+        );
+        assert_eq!(
+            state.connection_debug_stats(0x2),
+            ConnectionDebugStats::default()
+        );
+    }
+
+    #[test]
+    fn proxy_frame_is_taken_across_chunks() {
+        let frame = slipstream_core::proxy_frame::encode_proxy_frame(&ProxyTarget {
+            host: "example.com".to_string(),
+            port: 443,
+        })
+        .unwrap();
+        let mut pending = VecDeque::new();
+        pending.push_back(frame[..3].to_vec());
+        assert_eq!(take_proxy_frame(&mut pending), Ok(None));
+        let mut rest = frame[3..].to_vec();
+        rest.extend_from_slice(b"data");
+        pending.push_back(rest);
+        let (target, frame_len) = take_proxy_frame(&mut pending).unwrap().unwrap();
+        assert_eq!((target.host.as_str(), target.port), ("example.com", 443));
+        assert_eq!(frame_len, frame.len());
+        assert_eq!(pending, VecDeque::from([b"data".to_vec()]));
+
+        let mut pending = VecDeque::from([b"GET / HTTP/1.1\r\n".to_vec()]);
+        assert_eq!(
+            take_proxy_frame(&mut pending),
+            Err(ProxyFrameError::BadMagic)
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_over_per_connection_limit_are_reset() {
+        let max_streams = 3u32;
+        let (mut state, _command_rx) = test_state();
+        state.max_streams_per_connection = max_streams;
+        let recorded = RecordedStreams::install(&mut state);
+        let cnx = std::ptr::dangling_mut::<picoquic_cnx_t>();
+        let cnx_id = cnx as usize;
+
+        for index in 0..=u64::from(max_streams) {
+            handle_stream_data(cnx, &mut state, index * 4, false, &[]);
+        }
+
+        let last = StreamKey {
+            cnx: cnx_id,
+            stream_id: u64::from(max_streams) * 4,
+        };
+        let reset = StreamCall::Abort {
+            stream_id: last.stream_id,
+            app_error: SLIPSTREAM_STREAM_LIMIT_ERROR,
+        };
+        assert_eq!(recorded.calls(), vec![reset]);
+        assert!(!state.streams.contains_key(&last));
+        assert_eq!(state.streams.len(), max_streams as usize);
+        assert!(
+            !stream_limit_reached(&state, 0x2),
+            "the limit applies per connection"
+        );
+
+        handle_stream_data(cnx, &mut state, last.stream_id, true, b"late");
+        assert_eq!(
+            recorded.calls(),
+            vec![reset],
+            "a rejected stream is reset once"
+        );
+
+        shutdown_stream(
+            &mut state,
+            StreamKey {
+                cnx: cnx_id,
+                stream_id: 0,
+            },
+        );
+        handle_stream_data(cnx, &mut state, last.stream_id, false, &[]);
+        assert!(
+            !state.streams.contains_key(&last),
+            "a rejected stream stays closed once there is room"
+        );
+        handle_stream_data(cnx, &mut state, last.stream_id + 4, false, &[]);
+        assert_eq!(state.streams.len(), max_streams as usize);
+
+        remove_connection_streams(&mut state, cnx_id);
+        assert!(state.connection_streams.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_priorities_follow_the_configured_mode() {
+        let cnx = std::ptr::dangling_mut::<picoquic_cnx_t>();
+        let key = |stream_id| StreamKey {
+            cnx: cnx as usize,
+            stream_id,
+        };
+        let new_state = |mode| {
+            let (mut state, _command_rx) = test_state();
+            state.set_stream_priority_mode(mode);
+            let recorded = RecordedStreams::install(&mut state);
+            (state, recorded)
+        };
+
+        let (mut state, recorded) = new_state(None);
+        handle_stream_data(cnx, &mut state, 0, false, &[]);
+        assert!(recorded.calls().is_empty());
+
+        let (mut state, recorded) = new_state(Some(StreamPriorityMode::Fifo));
+        for stream_id in [0, 4] {
+            handle_stream_data(cnx, &mut state, stream_id, false, &[]);
+        }
+        assert_eq!(recorded.priorities(), vec![(0, 3), (4, 3)]);
+
+        let (mut state, recorded) = new_state(Some(StreamPriorityMode::Throughput));
+        for index in 0..9 {
+            handle_stream_data(cnx, &mut state, index * 4, false, &[]);
+        }
+        let priorities: Vec<u8> = recorded.priorities().iter().map(|(_, p)| *p).collect();
+        assert_eq!(priorities, [0, 1, 2, 3, 4, 5, 6, 7, 0]);
+
+        let (mut state, recorded) = new_state(Some(StreamPriorityMode::Latency));
+        for stream_id in [0, 4, 8] {
+            handle_stream_data(cnx, &mut state, stream_id, false, &[]);
+        }
+        assert_eq!(recorded.priorities(), vec![(0, 0), (4, 2), (8, 2)]);
+        shutdown_stream(&mut state, key(4));
+        assert_eq!(recorded.priorities().len(), 3, "a newer stream closed");
+        shutdown_stream(&mut state, key(0));
+        assert_eq!(recorded.priorities()[3], (8, 0));
+        handle_stream_data(cnx, &mut state, 12, false, &[]);
+        assert_eq!(
+            recorded.priorities()[4],
+            (12, 2),
+            "a stream opened behind the promoted one waits"
+        );
+        remove_connection_streams(&mut state, cnx as usize);
+        assert_eq!(recorded.priorities().len(), 5);
+    }
 }
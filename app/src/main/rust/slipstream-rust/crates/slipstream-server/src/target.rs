@@ -1,76 +1,219 @@
+use crate::proxy_protocol::{encode_v2, encode_v2_local};
 use crate::server::{
     Command, StreamKey, StreamWrite, DEFAULT_TCP_RCVBUF_BYTES, STREAM_READ_CHUNK_BYTES,
     TARGET_WRITE_COALESCE_DEFAULT_BYTES,
 };
+use slipstream_core::proxy_frame::ProxyTarget;
+use slipstream_core::rate_limit::TokenBucket;
 use slipstream_core::tcp::{stream_read_limit_chunks, tcp_send_buffer_bytes};
-use std::net::SocketAddr;
+use slipstream_core::{apply_address_policy, AddressKind, AddressPolicy, ConfigError, HostPort};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream as TokioTcpStream;
-use tokio::sync::{mpsc, watch};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream as TokioTcpStream, UnixStream};
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
 use tracing::{debug, warn};
 
+/// A `--target-address` (or per-domain target) as configured, before any resolving.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetEndpoint {
+    Tcp(HostPort),
+    /// A path given as `/path` or `unix:path`; streams connect to the Unix socket there.
+    UnixSocket(PathBuf),
+}
+
+impl TargetEndpoint {
+    /// Checks a TCP target against `--address-family`; Unix sockets have no family.
+    pub fn apply_address_policy(self, policy: AddressPolicy) -> Result<Self, ConfigError> {
+        match self {
+            TargetEndpoint::Tcp(address) => Ok(TargetEndpoint::Tcp(apply_address_policy(
+                address,
+                AddressKind::Target,
+                policy,
+            )?)),
+            unix => Ok(unix),
+        }
+    }
+}
+
+impl fmt::Display for TargetEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetEndpoint::Tcp(address) => write!(f, "{}", address),
+            TargetEndpoint::UnixSocket(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Where a stream's target connection goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TargetAddr {
+    /// A literal `--target-address` (or per-domain target), connected to directly.
+    Fixed(SocketAddr),
+    /// A host name target or the host named by a stream's connect-proxy frame, resolved on
+    /// every connect and dialled with happy eyeballs over the families `policy` allows.
+    Dial(ProxyTarget, AddressPolicy),
+    /// A Unix-domain socket, for targets listening on a local path.
+    UnixSocket(PathBuf),
+}
+
+/// Whether a target connection opens with a PROXY protocol v2 header, and for whom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProxyHeader {
+    /// `--target-proxy-protocol` is off; stream data goes out as is.
+    Off,
+    /// The client address is not known yet, so a LOCAL header goes out.
+    Local,
+    /// A header naming the client at this address.
+    Client(SocketAddr),
+}
+
+/// A connected target, over TCP or a Unix-domain socket.
+enum TargetStream {
+    Tcp(TokioTcpStream),
+    Unix(UnixStream),
+}
+
+impl TargetStream {
+    /// The PROXY protocol v2 bytes for `header`. A Unix socket has no address of its own,
+    /// so the header names the unspecified address of the client's family.
+    fn proxy_header(&self, header: ProxyHeader) -> Vec<u8> {
+        let ProxyHeader::Client(src) = header else {
+            return encode_v2_local();
+        };
+        match self {
+            TargetStream::Tcp(stream) => match stream.peer_addr() {
+                Ok(dst) => encode_v2(src, dst),
+                Err(_) => encode_v2_local(),
+            },
+            TargetStream::Unix(_) => {
+                let ip: IpAddr = if src.ip().to_canonical().is_ipv4() {
+                    Ipv4Addr::UNSPECIFIED.into()
+                } else {
+                    Ipv6Addr::UNSPECIFIED.into()
+                };
+                encode_v2(src, SocketAddr::new(ip, 0))
+            }
+        }
+    }
+
+    async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            TargetStream::Tcp(stream) => stream.write_all(buf).await,
+            TargetStream::Unix(stream) => stream.write_all(buf).await,
+        }
+    }
+}
+
+/// RFC 8305 "Connection Attempt Delay": how long an attempt runs alone before the next
+/// address is tried alongside it.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Writes larger than this are split so one big coalesced write cannot drain a slow bucket.
+const RATE_LIMITED_WRITE_BYTES: usize = STREAM_READ_CHUNK_BYTES;
+
+/// `--per-connection-rate-limit-kbps` budget for bytes written to the target, shared by every
+/// stream of one QUIC connection.
+///
+/// A stream over budget stops pulling from its write channel, so its data stays queued there and
+/// keeps counting against QUIC flow control until the bucket refills.
+pub(crate) struct TargetRateLimiter {
+    bucket: Mutex<TokenBucket>,
+    started_at: Instant,
+    /// Set while a refill wake-up is scheduled. Like a stream's `send_pending`, only the writer
+    /// that sets it arms the timer; every writer over budget waits on `refilled`.
+    refill_pending: AtomicBool,
+    refilled: Notify,
+}
+
+impl TargetRateLimiter {
+    pub(crate) fn new(rate_bytes_per_sec: u64) -> Self {
+        Self {
+            bucket: Mutex::new(TokenBucket::new(rate_bytes_per_sec, 0)),
+            started_at: Instant::now(),
+            refill_pending: AtomicBool::new(false),
+            refilled: Notify::new(),
+        }
+    }
+
+    async fn acquire(self: &Arc<Self>, bytes: usize) {
+        loop {
+            // Registered before taking, so a wake-up between the two is not missed.
+            let refilled = self.refilled.notified();
+            tokio::pin!(refilled);
+            refilled.as_mut().enable();
+            let now = self.started_at.elapsed().as_micros() as u64;
+            let wait_us = self
+                .bucket
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .try_take(bytes, now);
+            if wait_us == 0 {
+                return;
+            }
+            if !self.refill_pending.swap(true, Ordering::SeqCst) {
+                let limiter = Arc::clone(self);
+                tokio::spawn(async move {
+                    sleep(Duration::from_micros(wait_us)).await;
+                    limiter.refill_pending.store(false, Ordering::SeqCst);
+                    limiter.refilled.notify_waiters();
+                });
+            }
+            refilled.await;
+        }
+    }
+}
+
+/// Connects a new stream to the target.
+///
+/// `proxy_header` says what PROXY protocol v2 header, if any, goes out before the stream
+/// data. The relay's `write_timeout` bounds the connect as well as the writes.
 pub(crate) fn spawn_target_connector(
-    key: StreamKey,
-    target_addr: SocketAddr,
-    command_tx: mpsc::UnboundedSender<Command>,
-    debug_streams: bool,
-    mut shutdown_rx: watch::Receiver<bool>,
+    mut relay: TargetRelay,
+    target: TargetAddr,
+    proxy_header: ProxyHeader,
 ) {
     tokio::spawn(async move {
-        if *shutdown_rx.borrow() {
+        if *relay.shutdown_rx.borrow() {
             return;
         }
-        let connect = TokioTcpStream::connect(target_addr);
+        let connect = with_timeout(relay.write_timeout, connect_target(&target));
         let stream = tokio::select! {
-            _ = shutdown_rx.changed() => {
+            _ = relay.shutdown_rx.changed() => {
                 return;
             }
             result = connect => result,
         };
-        if *shutdown_rx.borrow() {
+        if *relay.shutdown_rx.borrow() {
             return;
         }
+        let key = relay.key;
+        let stream = match stream {
+            Ok(mut stream) if proxy_header != ProxyHeader::Off => {
+                let header = stream.proxy_header(proxy_header);
+                stream.write_all(&header).await.map(|_| stream)
+            }
+            stream => stream,
+        };
         match stream {
-            Ok(stream) => {
+            Ok(TargetStream::Tcp(stream)) => {
                 let _ = stream.set_nodelay(true);
-                let read_limit = stream_read_limit_chunks(
-                    &stream,
-                    DEFAULT_TCP_RCVBUF_BYTES,
-                    STREAM_READ_CHUNK_BYTES,
-                );
-                let (data_tx, data_rx) = mpsc::channel(read_limit);
-                let send_buffer_bytes = tcp_send_buffer_bytes(&stream)
-                    .filter(|bytes| *bytes > 0)
-                    .unwrap_or(TARGET_WRITE_COALESCE_DEFAULT_BYTES);
+                let (read_limit, send_buffer_bytes) = buffer_limits(&stream);
                 let (read_half, write_half) = stream.into_split();
-                let (write_tx, write_rx) = mpsc::unbounded_channel();
-                let send_pending = Arc::new(AtomicBool::new(false));
-                spawn_target_reader(
-                    key,
-                    read_half,
-                    data_tx,
-                    command_tx.clone(),
-                    send_pending.clone(),
-                    debug_streams,
-                    shutdown_rx.clone(),
-                );
-                spawn_target_writer(
-                    key,
-                    write_half,
-                    write_rx,
-                    command_tx.clone(),
-                    shutdown_rx,
-                    send_buffer_bytes,
-                );
-                let _ = command_tx.send(Command::StreamConnected {
-                    cnx_id: key.cnx,
-                    stream_id: key.stream_id,
-                    write_tx,
-                    data_rx,
-                    send_pending,
-                });
+                relay.start(read_half, write_half, read_limit, send_buffer_bytes);
+            }
+            Ok(TargetStream::Unix(stream)) => {
+                let (read_limit, send_buffer_bytes) = buffer_limits(&stream);
+                let (read_half, write_half) = stream.into_split();
+                relay.start(read_half, write_half, read_limit, send_buffer_bytes);
             }
             Err(err) => {
                 warn!(
@@ -79,7 +222,7 @@ pub(crate) fn spawn_target_connector(
                     err,
                     err.kind()
                 );
-                let _ = command_tx.send(Command::StreamConnectError {
+                let _ = relay.command_tx.send(Command::StreamConnectError {
                     cnx_id: key.cnx,
                     stream_id: key.stream_id,
                 });
@@ -88,9 +231,71 @@ pub(crate) fn spawn_target_connector(
     });
 }
 
+async fn connect_target(target: &TargetAddr) -> io::Result<TargetStream> {
+    match target {
+        TargetAddr::Fixed(addr) => TokioTcpStream::connect(*addr).await.map(TargetStream::Tcp),
+        TargetAddr::Dial(target, policy) => dial(&target.host, target.port, *policy)
+            .await
+            .map(TargetStream::Tcp),
+        TargetAddr::UnixSocket(path) => UnixStream::connect(path).await.map(TargetStream::Unix),
+    }
+}
+
+/// Read channel depth in chunks and write coalescing limit, sized from the socket buffers.
+fn buffer_limits<T: AsRawFd>(stream: &T) -> (usize, usize) {
+    let read_limit =
+        stream_read_limit_chunks(stream, DEFAULT_TCP_RCVBUF_BYTES, STREAM_READ_CHUNK_BYTES);
+    let send_buffer_bytes = tcp_send_buffer_bytes(stream)
+        .filter(|bytes| *bytes > 0)
+        .unwrap_or(TARGET_WRITE_COALESCE_DEFAULT_BYTES);
+    (read_limit, send_buffer_bytes)
+}
+
+/// What a target's connector, reader and writer tasks share.
+pub(crate) struct TargetRelay {
+    pub(crate) key: StreamKey,
+    pub(crate) command_tx: mpsc::UnboundedSender<Command>,
+    pub(crate) rate_limiter: Option<Arc<TargetRateLimiter>>,
+    /// How long the connect, or a write the target takes none of, may block.
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) debug_streams: bool,
+    pub(crate) shutdown_rx: watch::Receiver<bool>,
+}
+
+impl TargetRelay {
+    fn start<R, W>(self, read_half: R, write_half: W, read_limit: usize, send_buffer_bytes: usize)
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (data_tx, data_rx) = mpsc::channel(read_limit);
+        let (write_tx, write_rx) = mpsc::unbounded_channel();
+        let send_pending = Arc::new(AtomicBool::new(false));
+        spawn_target_reader(
+            self.key,
+            read_half,
+            data_tx,
+            self.command_tx.clone(),
+            send_pending.clone(),
+            self.debug_streams,
+            self.shutdown_rx.clone(),
+        );
+        let key = self.key;
+        let command_tx = self.command_tx.clone();
+        spawn_target_writer(self, write_half, write_rx, send_buffer_bytes);
+        let _ = command_tx.send(Command::StreamConnected {
+            cnx_id: key.cnx,
+            stream_id: key.stream_id,
+            write_tx,
+            data_rx,
+            send_pending,
+        });
+    }
+}
+
 pub(crate) fn spawn_target_reader(
     key: StreamKey,
-    mut read_half: tokio::net::tcp::OwnedReadHalf,
+    mut read_half: impl AsyncRead + Unpin + Send + 'static,
     data_tx: mpsc::Sender<Vec<u8>>,
     command_tx: mpsc::UnboundedSender<Command>,
     send_pending: Arc<AtomicBool>,
@@ -163,13 +368,19 @@ pub(crate) fn spawn_target_reader(
 }
 
 pub(crate) fn spawn_target_writer(
-    key: StreamKey,
-    mut write_half: tokio::net::tcp::OwnedWriteHalf,
+    relay: TargetRelay,
+    mut write_half: impl AsyncWrite + Unpin + Send + 'static,
     mut write_rx: mpsc::UnboundedReceiver<StreamWrite>,
-    command_tx: mpsc::UnboundedSender<Command>,
-    mut shutdown_rx: watch::Receiver<bool>,
     coalesce_max_bytes: usize,
 ) {
+    let TargetRelay {
+        key,
+        command_tx,
+        rate_limiter,
+        write_timeout,
+        mut shutdown_rx,
+        ..
+    } = relay;
     tokio::spawn(async move {
         let coalesce_max_bytes = coalesce_max_bytes.max(1);
         loop {
@@ -180,8 +391,11 @@ pub(crate) fn spawn_target_writer(
                     }
                 }
                 msg = write_rx.recv() => {
+                    // recv only reports a closed channel once every queued write is taken,
+                    // so a dropped sender is a FIN after the last of them.
                     let Some(msg) = msg else {
-                        break;
+                        finish_target_write(key, &mut write_half, &command_tx, write_timeout).await;
+                        return;
                     };
                     match msg {
                         StreamWrite::Data(data) => {
@@ -207,7 +421,29 @@ pub(crate) fn spawn_target_writer(
                                 }
                             }
                             let len = buffer.len();
-                            if write_half.write_all(&buffer).await.is_err() {
+                            let written = match rate_limiter.as_ref() {
+                                Some(limiter) => {
+                                    write_rate_limited(
+                                        &mut write_half,
+                                        &buffer,
+                                        limiter,
+                                        write_timeout,
+                                    )
+                                    .await
+                                }
+                                None => {
+                                    write_all_until_stalled(&mut write_half, &buffer, write_timeout)
+                                        .await
+                                }
+                            };
+                            if let Err(err) = written {
+                                if err.kind() == std::io::ErrorKind::TimedOut {
+                                    warn!(
+                                        "stream {:?}: target write timed out after {}ms without progress",
+                                        key.stream_id,
+                                        write_timeout.unwrap_or_default().as_millis()
+                                    );
+                                }
                                 let _ = command_tx.send(Command::StreamWriteError {
                                     cnx_id: key.cnx,
                                     stream_id: key.stream_id,
@@ -219,13 +455,17 @@ pub(crate) fn spawn_target_writer(
                                 stream_id: key.stream_id,
                                 bytes: len,
                             });
+                            // The coalesced buffer holds every write queued ahead of the FIN,
+                            // and it was written in full above.
                             if saw_fin {
-                                let _ = write_half.shutdown().await;
+                                finish_target_write(key, &mut write_half, &command_tx, write_timeout)
+                                    .await;
                                 return;
                             }
                         }
                         StreamWrite::Fin => {
-                            let _ = write_half.shutdown().await;
+                            finish_target_write(key, &mut write_half, &command_tx, write_timeout)
+                                .await;
                             return;
                         }
                     }
@@ -235,3 +475,338 @@ pub(crate) fn spawn_target_writer(
         let _ = write_half.shutdown().await;
     });
 }
+
+/// Connects to `host`, racing its addresses with happy eyeballs unless it is an IP literal.
+async fn dial(host: &str, port: u16, policy: AddressPolicy) -> io::Result<TokioTcpStream> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return TokioTcpStream::connect((ip, port)).await;
+    }
+    let addrs = resolve_dial_addrs(host, port, policy).await?;
+    connect_staggered(&addrs, CONNECTION_ATTEMPT_DELAY).await
+}
+
+/// Resolves every address of `host` that `policy` allows, in happy-eyeballs order.
+pub(crate) async fn resolve_dial_addrs(
+    host: &str,
+    port: u16,
+    policy: AddressPolicy,
+) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<_> = lookup_host((host, port))
+        .await?
+        .filter(|addr| policy.allows(addr))
+        .collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no address for {} allowed by --address-family {}",
+                host,
+                policy.label()
+            ),
+        ));
+    }
+    Ok(interleave_families(addrs))
+}
+
+/// Alternates IPv6 and IPv4 addresses, IPv6 first, keeping the resolver's order within each
+/// family (RFC 8305 section 4).
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Starts a connect to each address in turn, `delay` apart or as soon as the previous attempt
+/// fails, and returns the first that succeeds. The attempts still running are aborted.
+async fn connect_staggered(addrs: &[SocketAddr], delay: Duration) -> io::Result<TokioTcpStream> {
+    let mut pending = addrs.iter().copied();
+    let mut attempts = JoinSet::new();
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to");
+    if let Some(addr) = pending.next() {
+        attempts.spawn(TokioTcpStream::connect(addr));
+    }
+    while !attempts.is_empty() {
+        let finished = tokio::select! {
+            joined = attempts.join_next() => joined,
+            _ = sleep(delay), if pending.len() > 0 => None,
+        };
+        match finished {
+            Some(Ok(Ok(stream))) => return Ok(stream),
+            Some(Ok(Err(err))) => last_err = err,
+            Some(Err(err)) => last_err = io::Error::other(err),
+            None => {}
+        }
+        if let Some(addr) = pending.next() {
+            attempts.spawn(TokioTcpStream::connect(addr));
+        }
+    }
+    Err(last_err)
+}
+
+/// Runs `io`, failing with `TimedOut` once `limit` passes; `None` waits forever.
+async fn with_timeout<T>(
+    limit: Option<Duration>,
+    io: impl std::future::Future<Output = std::io::Result<T>>,
+) -> std::io::Result<T> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, io)
+            .await
+            .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into())),
+        None => io.await,
+    }
+}
+
+/// Writes all of `buffer`, failing with `TimedOut` once `idle` passes without the target
+/// taking any of it; `None` waits forever. A slow target that keeps reading never times out.
+async fn write_all_until_stalled(
+    write_half: &mut (impl AsyncWrite + Unpin),
+    buffer: &[u8],
+    idle: Option<Duration>,
+) -> std::io::Result<()> {
+    if idle.is_none() {
+        return write_half.write_all(buffer).await;
+    }
+    let mut written = 0;
+    while written < buffer.len() {
+        match with_timeout(idle, write_half.write(&buffer[written..])).await? {
+            0 => return Err(std::io::ErrorKind::WriteZero.into()),
+            n => written += n,
+        }
+    }
+    Ok(())
+}
+
+/// Flushes what was written before a FIN, then half-closes the target.
+///
+/// A failed flush may have lost bytes sent ahead of the FIN, so it is reported as a write
+/// error and the stream is reset rather than closed cleanly.
+async fn finish_target_write(
+    key: StreamKey,
+    write_half: &mut (impl AsyncWrite + Unpin),
+    command_tx: &mpsc::UnboundedSender<Command>,
+    write_timeout: Option<Duration>,
+) {
+    if let Err(err) = with_timeout(write_timeout, write_half.flush()).await {
+        debug!(
+            "stream {:?}: target flush before FIN failed: {}",
+            key.stream_id, err
+        );
+        let _ = command_tx.send(Command::StreamWriteError {
+            cnx_id: key.cnx,
+            stream_id: key.stream_id,
+        });
+        return;
+    }
+    let _ = write_half.shutdown().await;
+}
+
+async fn write_rate_limited(
+    write_half: &mut (impl AsyncWrite + Unpin),
+    buffer: &[u8],
+    limiter: &Arc<TargetRateLimiter>,
+    write_timeout: Option<Duration>,
+) -> std::io::Result<()> {
+    for chunk in buffer.chunks(RATE_LIMITED_WRITE_BYTES) {
+        limiter.acquire(chunk.len()).await;
+        // Waiting for tokens is not the target's fault, so only the write itself is timed.
+        write_all_until_stalled(write_half, chunk, write_timeout).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn interleaves_families_starting_with_ipv6() {
+        let addrs: Vec<SocketAddr> = [
+            "10.0.0.1:80",
+            "10.0.0.2:80",
+            "[2001:db8::1]:80",
+            "10.0.0.3:80",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+        let ordered: Vec<String> = interleave_families(addrs)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            ordered,
+            [
+                "[2001:db8::1]:80",
+                "10.0.0.1:80",
+                "10.0.0.2:80",
+                "10.0.0.3:80"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn target_gets_every_byte_queued_before_fin() {
+        let payload: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        // End the stream with an explicit FIN, then with the sender dropped.
+        for explicit_fin in [true, false] {
+            let (target, mut peer) = tokio::io::duplex(4096);
+            let (write_tx, write_rx) = mpsc::unbounded_channel();
+            let (command_tx, mut command_rx) = mpsc::unbounded_channel();
+            let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+            // Queue everything up front so the writer coalesces straight into the FIN.
+            write_tx
+                .send(StreamWrite::Data(payload[..payload.len() - 10].to_vec()))
+                .unwrap();
+            write_tx
+                .send(StreamWrite::Data(payload[payload.len() - 10..].to_vec()))
+                .unwrap();
+            if explicit_fin {
+                write_tx.send(StreamWrite::Fin).unwrap();
+            } else {
+                drop(write_tx);
+            }
+            let relay = TargetRelay {
+                key: StreamKey {
+                    cnx: 1,
+                    stream_id: 4,
+                },
+                command_tx,
+                rate_limiter: None,
+                write_timeout: Some(Duration::from_secs(5)),
+                debug_streams: false,
+                shutdown_rx,
+            };
+            spawn_target_writer(relay, target, write_rx, 64 * 1024);
+
+            let mut received = Vec::new();
+            tokio::time::timeout(Duration::from_secs(5), peer.read_to_end(&mut received))
+                .await
+                .expect("target sees EOF")
+                .expect("target read");
+            assert_eq!(received.len(), payload.len());
+            assert!(received == payload);
+
+            let mut drained = 0;
+            while let Ok(command) = command_rx.try_recv() {
+                match command {
+                    Command::StreamWriteDrained { bytes, .. } => drained += bytes,
+                    _ => panic!("unexpected command after a clean FIN"),
+                }
+            }
+            assert_eq!(drained, payload.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limited_writers_share_the_connection_budget() {
+        const RATE: usize = 64 * 1024;
+        let limiter = Arc::new(TargetRateLimiter::new(RATE as u64));
+        let (command_tx, _command_rx) = mpsc::unbounded_channel();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let started = Instant::now();
+        let mut peers = Vec::new();
+        // Two streams of one connection queue a second's worth each; the first second's worth
+        // is the burst, the rest has to wait for the bucket to refill.
+        for stream_id in [0, 4] {
+            let (target, peer) = tokio::io::duplex(4096);
+            let (write_tx, write_rx) = mpsc::unbounded_channel();
+            for _ in 0..RATE / STREAM_READ_CHUNK_BYTES {
+                write_tx
+                    .send(StreamWrite::Data(vec![0u8; STREAM_READ_CHUNK_BYTES]))
+                    .unwrap();
+            }
+            write_tx.send(StreamWrite::Fin).unwrap();
+            let relay = TargetRelay {
+                key: StreamKey { cnx: 1, stream_id },
+                command_tx: command_tx.clone(),
+                rate_limiter: Some(Arc::clone(&limiter)),
+                write_timeout: Some(Duration::from_secs(5)),
+                debug_streams: false,
+                shutdown_rx: shutdown_rx.clone(),
+            };
+            spawn_target_writer(relay, target, write_rx, 16 * 1024);
+            peers.push(peer);
+        }
+
+        for mut peer in peers {
+            let mut received = Vec::new();
+            tokio::time::timeout(Duration::from_secs(5), peer.read_to_end(&mut received))
+                .await
+                .expect("writer resumes once the bucket refills")
+                .expect("target read");
+            assert_eq!(received.len(), RATE);
+        }
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(900),
+            "two seconds' worth went out in {elapsed:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn staggered_connect_moves_on_when_an_attempt_fails() {
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused_addr = refused.local_addr().unwrap();
+        drop(refused);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // The failure starts the next attempt at once rather than after the long delay.
+        let stream = tokio::time::timeout(
+            Duration::from_secs(5),
+            connect_staggered(&[refused_addr, addr], Duration::from_secs(60)),
+        )
+        .await
+        .expect("second attempt started early")
+        .expect("connects to the listener");
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+
+        let err = connect_staggered(&[refused_addr], Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn connects_to_unix_socket_targets() {
+        let path = std::env::temp_dir().join(format!(
+            "slipstream-target-{}-{:?}.sock",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let target = TargetAddr::UnixSocket(path.clone());
+
+        let mut stream = connect_target(&target)
+            .await
+            .expect("connects to the socket");
+        assert!(matches!(stream, TargetStream::Unix(_)));
+        let client: SocketAddr = "[::ffff:192.0.2.1]:4000".parse().unwrap();
+        let header = stream.proxy_header(ProxyHeader::Client(client));
+        assert_eq!(
+            header,
+            encode_v2(client, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+        );
+        stream.write_all(b"hello").await.unwrap();
+        let (mut accepted, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 5];
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+        assert!(connect_target(&target).await.is_err());
+        assert_eq!(
+            TargetEndpoint::UnixSocket(path.clone()).to_string(),
+            format!("unix:{}", path.display())
+        );
+    }
+}
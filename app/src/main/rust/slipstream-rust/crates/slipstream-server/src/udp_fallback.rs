@@ -1,23 +1,40 @@
+use slipstream_core::rate_limit::TokenBucket;
 use slipstream_core::{net::is_transient_udp_error, normalize_dual_stack_addr};
-use slipstream_dns::{decode_query_with_domains, DecodeQueryError};
+use slipstream_dns::{
+    decode_authenticated_query, decode_query_with_domains, hmac, strip_ecs, DecodeQueryError,
+    DecodedQuery, Encoding, Rcode,
+};
 use slipstream_ffi::picoquic::{
-    picoquic_cnx_t, picoquic_incoming_packet_ex, picoquic_quic_t, slipstream_disable_ack_delay,
+    picoquic_cnx_t, picoquic_current_number_connections, picoquic_delete_cnx,
+    picoquic_get_cnx_state, picoquic_incoming_packet_ex, picoquic_quic_t, picoquic_state_enum,
+    slipstream_disable_ack_delay, slipstream_packet_opens_connection, slipstream_set_server_busy,
 };
 use slipstream_ffi::{socket_addr_to_storage, take_stateless_packet_for_cid};
 use std::collections::HashMap;
+use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::net::UdpSocket as TokioUdpSocket;
 use tokio::sync::watch;
 use tokio::task::JoinHandle;
 
+use crate::auth_nonce::NonceCheck;
+use crate::connection_limit::{Overflow, PacketOrigin, QueuedConnection};
+use crate::metrics::{DnsOutcome, PacketKind, ServerMetrics};
 use crate::server::{map_io, ServerError, Slot};
+use crate::source_filter::SourceFilter;
+use crate::streams::{feed_query, IncomingQuery, ServerState};
 
 pub(crate) const MAX_UDP_PACKET_SIZE: usize = 65535;
 const FALLBACK_IDLE_TIMEOUT: Duration = Duration::from_secs(180);
 const FALLBACK_CLEANUP_INTERVAL: Duration = Duration::from_secs(30);
 const NON_DNS_STREAK_THRESHOLD: usize = 16;
+/// A source bucket idle this long has refilled, so forgetting it changes nothing.
+const SOURCE_BUCKET_IDLE_US: u64 = 1_000_000;
+/// Source addresses `--max-qps-per-source` keeps buckets for; past it the least recently seen
+/// eighth is forgotten, so a spoofed flood cannot grow the map without bound.
+const MAX_SOURCE_BUCKETS: usize = 65_536;
 
 enum DecodeSlotOutcome {
     Slot(Slot),
@@ -27,6 +44,8 @@ enum DecodeSlotOutcome {
 
 struct FallbackSession {
     socket: Arc<TokioUdpSocket>,
+    /// Backend picked by the session's first packet.
+    target: SocketAddr,
     last_seen: Arc<Mutex<Instant>>,
     shutdown_tx: watch::Sender<bool>,
     reply_task: JoinHandle<()>,
@@ -39,9 +58,94 @@ struct DnsPeerState {
 
 pub(crate) struct PacketContext<'a> {
     pub(crate) domains: &'a [&'a str],
+    pub(crate) encoding: Encoding,
     pub(crate) quic: *mut picoquic_quic_t,
     pub(crate) current_time: u64,
+    /// Index of the listener the packets arrived on.
+    pub(crate) listener: usize,
     pub(crate) local_addr_storage: &'a libc::sockaddr_storage,
+    pub(crate) metrics: &'a ServerMetrics,
+    /// Told each query's source and matched domain while QUIC processes its payload, so the
+    /// connection it opens starts with them; null in tests.
+    pub(crate) state: *mut ServerState,
+    /// Queries must carry a valid HMAC label under this secret or are refused.
+    pub(crate) auth_secret: Option<&'a [u8]>,
+    /// Sources outside it are dropped before anything else looks at their packets.
+    pub(crate) source_filter: Option<&'a SourceFilter>,
+}
+
+/// Which first packets a `--fallback` backend claims.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackMatch {
+    /// Every packet; a route without a match expression.
+    Any,
+    /// QUIC long-header packets (header form and fixed bits set), such as an Initial.
+    Quic,
+    /// Packets starting with these bytes.
+    Prefix(Vec<u8>),
+}
+
+impl FallbackMatch {
+    fn matches(&self, packet: &[u8]) -> bool {
+        match self {
+            FallbackMatch::Any => true,
+            FallbackMatch::Quic => packet.first().is_some_and(|byte| byte & 0xc0 == 0xc0),
+            FallbackMatch::Prefix(prefix) => packet.starts_with(prefix),
+        }
+    }
+}
+
+impl std::str::FromStr for FallbackMatch {
+    type Err = String;
+
+    /// Parses `quic` or `prefix:<hex>`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        if input.eq_ignore_ascii_case("quic") {
+            return Ok(FallbackMatch::Quic);
+        }
+        let Some(hex) = input.strip_prefix("prefix:") else {
+            return Err(format!(
+                "Unsupported fallback match (expected quic or prefix:<hex>): {}",
+                input
+            ));
+        };
+        if hex.is_empty() || hex.len() % 2 != 0 {
+            return Err(format!(
+                "Fallback prefix must be a non-empty even number of hex digits: {}",
+                hex
+            ));
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| format!("Invalid hex in fallback prefix: {}", hex))?;
+        Ok(FallbackMatch::Prefix(bytes))
+    }
+}
+
+impl fmt::Display for FallbackMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FallbackMatch::Any => write!(f, "any"),
+            FallbackMatch::Quic => write!(f, "quic"),
+            FallbackMatch::Prefix(prefix) => {
+                write!(f, "prefix:")?;
+                for byte in prefix {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A resolved fallback backend.
+#[derive(Debug, Clone)]
+pub(crate) struct FallbackRoute {
+    pub(crate) matcher: FallbackMatch,
+    pub(crate) addr: SocketAddr,
 }
 
 /// Tracks per-peer routing for UDP fallback based on DNS decoding outcomes.
@@ -53,8 +157,12 @@ pub(crate) struct PacketContext<'a> {
 /// For DNS-only peers, a streak of non-DNS packets can switch the peer to fallback once it
 /// reaches the non-DNS streak threshold. Classification is per source address and expires after
 /// idle timeout.
+///
+/// The packet that opens a fallback session also picks its backend: the first route whose
+/// matcher accepts it. The session keeps that backend for its lifetime; a packet no route
+/// matches is dropped without opening a session.
 pub(crate) struct FallbackManager {
-    fallback_addr: SocketAddr,
+    routes: Vec<FallbackRoute>,
     main_socket: Arc<TokioUdpSocket>,
     map_ipv4_peers: bool,
     dns_peers: HashMap<SocketAddr, DnsPeerState>,
@@ -65,12 +173,18 @@ pub(crate) struct FallbackManager {
 impl FallbackManager {
     pub(crate) fn new(
         main_socket: Arc<TokioUdpSocket>,
-        fallback_addr: SocketAddr,
+        routes: Vec<FallbackRoute>,
         map_ipv4_peers: bool,
     ) -> Self {
-        tracing::info!("non-DNS packets will be forwarded to {}", fallback_addr);
+        for route in &routes {
+            tracing::info!(
+                "non-DNS packets matching {} will be forwarded to {}",
+                route.matcher,
+                route.addr
+            );
+        }
         Self {
-            fallback_addr,
+            routes,
             main_socket,
             map_ipv4_peers,
             dns_peers: HashMap::new(),
@@ -95,8 +209,8 @@ impl FallbackManager {
                 Ok(last_seen) => *last_seen,
                 Err(_) => {
                     tracing::warn!(
-                        "fallback session for {} has poisoned mutex, marking for cleanup",
-                        peer
+                        peer = %peer,
+                        "fallback session has poisoned mutex, marking for cleanup"
                     );
                     expired.push(*peer);
                     continue;
@@ -112,6 +226,10 @@ impl FallbackManager {
         }
     }
 
+    pub(crate) fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
     fn end_session(&mut self, peer: SocketAddr) {
         if let Some(session) = self.sessions.remove(&peer) {
             let _ = session.shutdown_tx.send(true);
@@ -140,8 +258,8 @@ impl FallbackManager {
                 Ok(last_seen) => *last_seen,
                 Err(_) => {
                     tracing::warn!(
-                        "fallback session for {} has poisoned mutex, marking for cleanup",
-                        peer
+                        peer = %peer,
+                        "fallback session has poisoned mutex, marking for cleanup"
                     );
                     should_end = true;
                     Instant::now()
@@ -165,10 +283,11 @@ impl FallbackManager {
     }
 
     async fn forward_existing(&mut self, packet: &[u8], peer: SocketAddr) {
-        self.forward_packet(packet, peer).await;
+        let _ = self.forward_packet(packet, peer).await;
     }
 
-    async fn handle_non_dns(&mut self, packet: &[u8], peer: SocketAddr) {
+    /// Returns whether the packet was forwarded to the fallback address.
+    async fn handle_non_dns(&mut self, packet: &[u8], peer: SocketAddr) -> bool {
         let mut should_forward = true;
         let mut should_remove = false;
         if let Some(state) = self.dns_peers.get_mut(&peer) {
@@ -183,29 +302,42 @@ impl FallbackManager {
             self.dns_peers.remove(&peer);
         }
         if !should_forward {
-            return;
+            return false;
         }
-        self.forward_packet(packet, peer).await;
+        self.forward_packet(packet, peer).await
     }
 
-    async fn forward_packet(&mut self, packet: &[u8], peer: SocketAddr) {
-        let socket = match self.ensure_session(peer).await {
-            Some(socket) => socket,
-            None => return,
+    /// Returns false when no session exists and no route accepts `packet` to open one.
+    async fn forward_packet(&mut self, packet: &[u8], peer: SocketAddr) -> bool {
+        let (socket, target) = match self.ensure_session(peer, packet).await {
+            Some(session) => session,
+            None => return false,
         };
         if let Err(err) = socket.send(packet).await {
             if !is_transient_udp_error(&err) {
                 tracing::warn!(
-                    "fallback write to {} for client {} failed: {}",
-                    self.fallback_addr,
-                    peer,
-                    err
+                    target_addr = %target,
+                    peer = %peer,
+                    error = %err,
+                    "fallback write failed"
                 );
             }
         }
+        true
     }
 
-    async fn ensure_session(&mut self, peer: SocketAddr) -> Option<Arc<TokioUdpSocket>> {
+    fn route_for(&self, packet: &[u8]) -> Option<SocketAddr> {
+        self.routes
+            .iter()
+            .find(|route| route.matcher.matches(packet))
+            .map(|route| route.addr)
+    }
+
+    async fn ensure_session(
+        &mut self,
+        peer: SocketAddr,
+        packet: &[u8],
+    ) -> Option<(Arc<TokioUdpSocket>, SocketAddr)> {
         let reset_session = self
             .sessions
             .get(&peer)
@@ -216,28 +348,36 @@ impl FallbackManager {
             tracing::debug!("fallback reply loop ended for {}; recreating session", peer);
         }
         if !self.sessions.contains_key(&peer) {
-            if let Err(err) = self.create_session(peer).await {
-                tracing::warn!("failed to create fallback session for {}: {}", peer, err);
+            let Some(target) = self.route_for(packet) else {
+                tracing::debug!("no fallback route matches the first packet from {}", peer);
+                return None;
+            };
+            if let Err(err) = self.create_session(peer, target).await {
+                tracing::warn!(peer = %peer, error = %err, "failed to create fallback session");
                 return None;
             }
         }
 
-        let socket = if let Some(session) = self.sessions.get_mut(&peer) {
+        let route = if let Some(session) = self.sessions.get_mut(&peer) {
             if let Ok(mut last_seen) = session.last_seen.lock() {
                 *last_seen = Instant::now();
             }
-            session.socket.clone()
+            (session.socket.clone(), session.target)
         } else {
             return None;
         };
 
-        Some(socket)
+        Some(route)
     }
 
-    async fn create_session(&mut self, peer: SocketAddr) -> Result<(), ServerError> {
-        let bind_addr = fallback_bind_addr(self.fallback_addr);
+    async fn create_session(
+        &mut self,
+        peer: SocketAddr,
+        target: SocketAddr,
+    ) -> Result<(), ServerError> {
+        let bind_addr = fallback_bind_addr(target);
         let socket = TokioUdpSocket::bind(bind_addr).await.map_err(map_io)?;
-        socket.connect(self.fallback_addr).await.map_err(map_io)?;
+        socket.connect(target).await.map_err(map_io)?;
         let socket = Arc::new(socket);
         let last_seen = Arc::new(Mutex::new(Instant::now()));
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
@@ -260,16 +400,98 @@ impl FallbackManager {
             peer,
             FallbackSession {
                 socket,
+                target,
                 last_seen,
                 shutdown_tx,
                 reply_task,
             },
         );
-        tracing::debug!("created fallback session for {}", peer);
+        tracing::debug!("created fallback session for {} to {}", peer, target);
         Ok(())
     }
 }
 
+struct SourceBucket {
+    bucket: TokenBucket,
+    last_seen: u64,
+}
+
+/// Per-source packet budget for `--max-qps-per-source`.
+///
+/// Each source IP gets a token bucket of `max_qps` packets per second with a one-second burst,
+/// shared by all its ports so a client cannot multiply its budget by rotating them. Packets
+/// over budget are dropped before they reach picoquic or a fallback, without a reply, so a
+/// flood cannot be reflected. Idle buckets are forgotten on the fallback cleanup cadence, and
+/// at most `MAX_SOURCE_BUCKETS` are kept. Times are picoquic microseconds.
+pub(crate) struct SourceRateLimiter {
+    max_qps: u32,
+    buckets: HashMap<IpAddr, SourceBucket>,
+    max_sources: usize,
+    last_cleanup: u64,
+}
+
+impl SourceRateLimiter {
+    pub(crate) fn new(max_qps: u32, now: u64) -> Self {
+        Self {
+            max_qps,
+            buckets: HashMap::new(),
+            max_sources: MAX_SOURCE_BUCKETS,
+            last_cleanup: now,
+        }
+    }
+
+    /// Takes one packet from `peer`'s budget; false means drop it.
+    pub(crate) fn allow(&mut self, peer: SocketAddr, now: u64) -> bool {
+        let source = peer.ip().to_canonical();
+        if !self.buckets.contains_key(&source) && self.buckets.len() >= self.max_sources {
+            self.evict_least_recent(now);
+        }
+        let max_qps = self.max_qps;
+        let source = self.buckets.entry(source).or_insert_with(|| SourceBucket {
+            bucket: TokenBucket::new(max_qps as u64, now),
+            last_seen: now,
+        });
+        source.last_seen = now;
+        source.bucket.try_take(1, now) == 0
+    }
+
+    // Forgets idle sources, or if none are, the least recently seen eighth of them at once so
+    // a stream of new sources pays for the scan only every so often.
+    fn evict_least_recent(&mut self, now: u64) {
+        self.buckets
+            .retain(|_, source| now.saturating_sub(source.last_seen) < SOURCE_BUCKET_IDLE_US);
+        if self.buckets.len() < self.max_sources {
+            return;
+        }
+        let mut last_seen: Vec<u64> = self
+            .buckets
+            .values()
+            .map(|source| source.last_seen)
+            .collect();
+        let evict = (self.max_sources / 8).max(1);
+        let (_, cutoff, _) = last_seen.select_nth_unstable(evict - 1);
+        let cutoff = *cutoff;
+        let mut evicted = 0;
+        self.buckets.retain(|_, source| {
+            if evicted < evict && source.last_seen <= cutoff {
+                evicted += 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    pub(crate) fn cleanup(&mut self, now: u64) {
+        if now.saturating_sub(self.last_cleanup) < FALLBACK_CLEANUP_INTERVAL.as_micros() as u64 {
+            return;
+        }
+        self.last_cleanup = now;
+        self.buckets
+            .retain(|_, source| now.saturating_sub(source.last_seen) < SOURCE_BUCKET_IDLE_US);
+    }
+}
+
 pub(crate) async fn handle_packet(
     slots: &mut Vec<Slot>,
     packet: &[u8],
@@ -277,36 +499,54 @@ pub(crate) async fn handle_packet(
     context: &PacketContext<'_>,
     fallback_mgr: &mut Option<FallbackManager>,
 ) -> Result<(), ServerError> {
+    if context
+        .source_filter
+        .is_some_and(|filter| !filter.allows(peer))
+    {
+        context.metrics.record_dns_query(DnsOutcome::Drop);
+        return Ok(());
+    }
+
     if let Some(manager) = fallback_mgr.as_mut() {
         if manager.is_active_fallback_peer(peer) {
             manager.forward_existing(packet, peer).await;
+            context.metrics.record_packet(PacketKind::NonDns);
+            context.metrics.record_dns_query(DnsOutcome::Fallback);
             return Ok(());
         }
     }
 
-    match decode_slot(
-        packet,
-        peer,
-        context.domains,
-        context.quic,
-        context.current_time,
-        context.local_addr_storage,
-    )? {
+    match decode_slot(packet, peer, context)? {
         DecodeSlotOutcome::Slot(slot) => {
             if let Some(manager) = fallback_mgr.as_mut() {
                 manager.mark_dns(peer);
             }
+            context.metrics.record_packet(PacketKind::Dns);
+            context.metrics.record_dns_query(if slot.rcode.is_some() {
+                DnsOutcome::Error
+            } else {
+                DnsOutcome::Quic
+            });
             slots.push(slot);
         }
         DecodeSlotOutcome::DnsOnly => {
             if let Some(manager) = fallback_mgr.as_mut() {
                 manager.mark_dns(peer);
             }
+            context.metrics.record_packet(PacketKind::Dns);
+            context.metrics.record_dns_query(DnsOutcome::Quic);
         }
         DecodeSlotOutcome::Drop => {
-            if let Some(manager) = fallback_mgr.as_mut() {
-                manager.handle_non_dns(packet, peer).await;
-            }
+            let forwarded = match fallback_mgr.as_mut() {
+                Some(manager) => manager.handle_non_dns(packet, peer).await,
+                None => false,
+            };
+            context.metrics.record_packet(PacketKind::NonDns);
+            context.metrics.record_dns_query(if forwarded {
+                DnsOutcome::Fallback
+            } else {
+                DnsOutcome::Drop
+            });
         }
     }
 
@@ -316,18 +556,89 @@ pub(crate) async fn handle_packet(
 fn decode_slot(
     packet: &[u8],
     peer: SocketAddr,
-    domains: &[&str],
-    quic: *mut picoquic_quic_t,
-    current_time: u64,
-    local_addr_storage: &libc::sockaddr_storage,
+    context: &PacketContext<'_>,
 ) -> Result<DecodeSlotOutcome, ServerError> {
-    match decode_query_with_domains(packet, domains) {
+    let quic = context.quic;
+    // Client subnets a recursive resolver attached never reach the decoder or the logs.
+    let packet = strip_ecs(packet);
+    // Only a server holding a secret looks for an auth label; elsewhere it is payload.
+    let decoded = match context.auth_secret {
+        Some(_) => decode_authenticated_query(&packet, context.domains, context.encoding),
+        None => decode_query_with_domains(&packet, context.domains, context.encoding),
+    };
+    match decoded {
         Ok(query) => {
+            if let Some(secret) = context.auth_secret {
+                let Some(tag) = query
+                    .auth
+                    .as_ref()
+                    .filter(|tag| hmac::verify(secret, tag, &query.payload))
+                else {
+                    tracing::debug!("Refusing query from {} without a valid auth label", peer);
+                    return Ok(refused_slot(query, peer, context));
+                };
+                // SAFETY: the server loop owns the state and holds no other borrow of it here.
+                let nonce = match unsafe { context.state.as_mut() } {
+                    Some(state) => state.auth_nonces_mut().check(tag.nonce, unix_now_secs()),
+                    None => NonceCheck::Fresh,
+                };
+                match nonce {
+                    NonceCheck::Fresh => {}
+                    NonceCheck::Stale => {
+                        tracing::debug!("Refusing query from {} with a stale auth nonce", peer);
+                        return Ok(refused_slot(query, peer, context));
+                    }
+                    NonceCheck::Replayed => {
+                        tracing::debug!("Ignoring query from {} with a reused auth nonce", peer);
+                        return Ok(DecodeSlotOutcome::DnsOnly);
+                    }
+                }
+            }
+            let incoming = IncomingQuery {
+                peer,
+                domain: query.domain_index,
+            };
             let mut peer_storage = dummy_sockaddr_storage();
-            let mut local_storage = unsafe { std::ptr::read(local_addr_storage) };
+            let mut local_storage = unsafe { std::ptr::read(context.local_addr_storage) };
             let mut first_cnx: *mut picoquic_cnx_t = std::ptr::null_mut();
             let mut first_path: libc::c_int = -1;
-            let ret = unsafe {
+            let connections_before = unsafe { picoquic_current_number_connections(quic) };
+            let origin = PacketOrigin {
+                peer,
+                listener: context.listener,
+                domain: query.domain_index,
+            };
+            let opens = || unsafe {
+                slipstream_packet_opens_connection(
+                    quic,
+                    query.payload.as_ptr(),
+                    query.payload.len(),
+                    &peer_storage as *const _ as *const libc::sockaddr,
+                ) != 0
+            };
+            // Decided before QUIC sees the packet, so no connection is created only to go.
+            // SAFETY: the server loop owns the state and holds no other borrow of it here.
+            let overflow = match unsafe { context.state.as_mut() } {
+                Some(state) => state.connection_limit_mut().on_packet(
+                    connections_before as usize,
+                    opens,
+                    &query.payload,
+                    origin,
+                    Instant::now(),
+                ),
+                None => Overflow::Admit,
+            };
+            match overflow {
+                Overflow::Admit | Overflow::Busy => {}
+                Overflow::Queued => return Ok(DecodeSlotOutcome::DnsOnly),
+                Overflow::Refuse => return Ok(refused_slot(query, peer, context)),
+            }
+            let busy = overflow == Overflow::Busy;
+            if busy {
+                // picoquic answers the Initial with a SERVER_BUSY close and keeps no state.
+                unsafe { slipstream_set_server_busy(quic, 1) };
+            }
+            let ret = feed_query(context.state, incoming, || unsafe {
                 picoquic_incoming_packet_ex(
                     quic,
                     query.payload.as_ptr() as *mut u8,
@@ -338,9 +649,12 @@ fn decode_slot(
                     0,
                     &mut first_cnx,
                     &mut first_path,
-                    current_time,
+                    context.current_time,
                 )
-            };
+            });
+            if busy {
+                unsafe { slipstream_set_server_busy(quic, 0) };
+            }
             if ret < 0 {
                 return Err(ServerError::new("Failed to process QUIC packet"));
             }
@@ -351,9 +665,11 @@ fn decode_slot(
                     if !payload.is_empty() {
                         return Ok(DecodeSlotOutcome::Slot(Slot {
                             peer,
+                            listener: context.listener,
                             id: query.id,
                             rd: query.rd,
                             cd: query.cd,
+                            ad: query.ad,
                             question: query.question,
                             rcode: None,
                             cnx: std::ptr::null_mut(),
@@ -364,14 +680,40 @@ fn decode_slot(
                 }
                 return Ok(DecodeSlotOutcome::DnsOnly);
             }
+            if !context.state.is_null()
+                && unsafe { (*context.state).refuses_connection(first_cnx as usize) }
+            {
+                tracing::debug!("Draining; refusing new connection from {}", peer);
+                unsafe {
+                    picoquic_delete_cnx(first_cnx);
+                }
+                return Ok(DecodeSlotOutcome::Slot(Slot {
+                    peer,
+                    listener: context.listener,
+                    id: query.id,
+                    rd: query.rd,
+                    cd: query.cd,
+                    ad: query.ad,
+                    question: query.question,
+                    rcode: Some(Rcode::ServerFailure),
+                    cnx: std::ptr::null_mut(),
+                    path_id: -1,
+                    payload_override: None,
+                }));
+            }
+            if unsafe { picoquic_current_number_connections(quic) } > connections_before {
+                open_connection(context.state, first_cnx, incoming);
+            }
             unsafe {
                 slipstream_disable_ack_delay(first_cnx);
             }
             Ok(DecodeSlotOutcome::Slot(Slot {
                 peer,
+                listener: context.listener,
                 id: query.id,
                 rd: query.rd,
                 cd: query.cd,
+                ad: query.ad,
                 question: query.question,
                 rcode: None,
                 cnx: first_cnx,
@@ -379,11 +721,12 @@ fn decode_slot(
                 payload_override: None,
             }))
         }
-        Err(DecodeQueryError::Drop) => Ok(DecodeSlotOutcome::Drop),
+        Err(DecodeQueryError::Drop(_)) => Ok(DecodeSlotOutcome::Drop),
         Err(DecodeQueryError::Reply {
             id,
             rd,
             cd,
+            ad,
             question,
             rcode,
         }) => {
@@ -393,9 +736,11 @@ fn decode_slot(
             };
             Ok(DecodeSlotOutcome::Slot(Slot {
                 peer,
+                listener: context.listener,
                 id,
                 rd,
                 cd,
+                ad,
                 question,
                 rcode: Some(rcode),
                 cnx: std::ptr::null_mut(),
@@ -406,6 +751,32 @@ fn decode_slot(
     }
 }
 
+fn refused_slot(
+    query: DecodedQuery,
+    peer: SocketAddr,
+    context: &PacketContext<'_>,
+) -> DecodeSlotOutcome {
+    DecodeSlotOutcome::Slot(Slot {
+        peer,
+        listener: context.listener,
+        id: query.id,
+        rd: query.rd,
+        cd: query.cd,
+        ad: query.ad,
+        question: query.question,
+        rcode: Some(Rcode::Refused),
+        cnx: std::ptr::null_mut(),
+        path_id: -1,
+        payload_override: None,
+    })
+}
+
+fn unix_now_secs() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as u32)
+}
+
 fn fallback_bind_addr(fallback_addr: SocketAddr) -> SocketAddr {
     match fallback_addr {
         SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
@@ -438,9 +809,9 @@ async fn forward_fallback_replies(
                         if let Err(err) = main_socket.send_to(&buf[..size], client_send_addr).await {
                             if !is_transient_udp_error(&err) {
                                 tracing::warn!(
-                                    "fallback write to client {} failed: {}",
-                                    client_addr,
-                                    err
+                                    peer = %client_addr,
+                                    error = %err,
+                                    "fallback write to client failed"
                                 );
                             }
                         }
@@ -450,9 +821,9 @@ async fn forward_fallback_replies(
                             continue;
                         }
                         tracing::warn!(
-                            "fallback read for client {} failed: {}",
-                            client_addr,
-                            err
+                            peer = %client_addr,
+                            error = %err,
+                            "fallback read for client failed"
                         );
                         break;
                     }
@@ -467,6 +838,72 @@ async fn forward_fallback_replies(
     }
 }
 
+/// Feeds the packets of a connection held by `--connection-overflow-mode queue` to QUIC, in
+/// arrival order, once a connection has closed; the handshake reply goes out with the
+/// client's next poll.
+pub(crate) fn admit_queued_connection(
+    quic: *mut picoquic_quic_t,
+    state: *mut ServerState,
+    local_addr_storage: &libc::sockaddr_storage,
+    queued: QueuedConnection,
+    current_time: u64,
+) -> Result<(), ServerError> {
+    let incoming = IncomingQuery {
+        peer: queued.origin.peer,
+        domain: queued.origin.domain,
+    };
+    let mut admitted = false;
+    for mut payload in queued.packets {
+        let connections_before = unsafe { picoquic_current_number_connections(quic) };
+        let mut peer_storage = dummy_sockaddr_storage();
+        let mut local_storage = unsafe { std::ptr::read(local_addr_storage) };
+        let mut first_cnx: *mut picoquic_cnx_t = std::ptr::null_mut();
+        let mut first_path: libc::c_int = -1;
+        let ret = feed_query(state, incoming, || unsafe {
+            picoquic_incoming_packet_ex(
+                quic,
+                payload.as_mut_ptr(),
+                payload.len(),
+                &mut peer_storage as *mut _ as *mut libc::sockaddr,
+                &mut local_storage as *mut _ as *mut libc::sockaddr,
+                0,
+                0,
+                &mut first_cnx,
+                &mut first_path,
+                current_time,
+            )
+        });
+        if ret < 0 {
+            return Err(ServerError::new("Failed to process QUIC packet"));
+        }
+        if first_cnx.is_null() {
+            continue;
+        }
+        if !std::mem::replace(&mut admitted, true) {
+            tracing::debug!("Admitted queued connection from {}", queued.origin.peer);
+            unsafe {
+                slipstream_disable_ack_delay(first_cnx);
+            }
+        }
+        if unsafe { picoquic_current_number_connections(quic) } > connections_before {
+            open_connection(state, first_cnx, incoming);
+        }
+    }
+    Ok(())
+}
+
+/// Starts the totals of a connection `incoming` just created, unless QUIC already closed it.
+fn open_connection(state: *mut ServerState, cnx: *mut picoquic_cnx_t, incoming: IncomingQuery) {
+    let closed = (unsafe { picoquic_get_cnx_state(cnx) } as u32)
+        >= picoquic_state_enum::picoquic_state_disconnecting as u32;
+    // SAFETY: the server loop owns the state and holds no other borrow of it here.
+    if let Some(state) = unsafe { state.as_mut() } {
+        if !closed {
+            state.open_connection(cnx as usize, incoming);
+        }
+    }
+}
+
 fn dummy_sockaddr_storage() -> libc::sockaddr_storage {
     socket_addr_to_storage(SocketAddr::new(
         IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
@@ -477,7 +914,7 @@ fn dummy_sockaddr_storage() -> libc::sockaddr_storage {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use slipstream_dns::{encode_query, QueryParams, CLASS_IN, RR_A};
+    use slipstream_dns::{encode_query, EdnsOptions, QueryParams, CLASS_IN, RR_A, RR_TXT};
     use tokio::sync::mpsc;
     use tokio::time::{timeout, Duration};
 
@@ -491,6 +928,7 @@ mod tests {
             cd: false,
             qdcount: 1,
             is_query: true,
+            edns: EdnsOptions::default(),
         })
         .expect("dns query")
     }
@@ -540,16 +978,26 @@ mod tests {
 
         let mut fallback_mgr = Some(FallbackManager::new(
             main_socket.clone(),
-            fallback_addr,
+            vec![FallbackRoute {
+                matcher: FallbackMatch::Any,
+                addr: fallback_addr,
+            }],
             false,
         ));
         let domains = vec!["example.com"];
         let local_addr_storage = dummy_sockaddr_storage();
+        let metrics = ServerMetrics::default();
         let context = PacketContext {
             domains: &domains,
+            encoding: Encoding::Base32,
             quic: std::ptr::null_mut(),
             current_time: 0,
+            listener: 0,
             local_addr_storage: &local_addr_storage,
+            metrics: &metrics,
+            state: std::ptr::null_mut(),
+            auth_secret: None,
+            source_filter: None,
         };
 
         let non_dns = b"nope";
@@ -617,16 +1065,26 @@ mod tests {
 
         let mut fallback_mgr = Some(FallbackManager::new(
             main_socket.clone(),
-            fallback_addr,
+            vec![FallbackRoute {
+                matcher: FallbackMatch::Any,
+                addr: fallback_addr,
+            }],
             false,
         ));
         let domains = vec!["example.com"];
         let local_addr_storage = dummy_sockaddr_storage();
+        let metrics = ServerMetrics::default();
         let context = PacketContext {
             domains: &domains,
+            encoding: Encoding::Base32,
             quic: std::ptr::null_mut(),
             current_time: 0,
+            listener: 0,
             local_addr_storage: &local_addr_storage,
+            metrics: &metrics,
+            state: std::ptr::null_mut(),
+            auth_secret: None,
+            source_filter: None,
         };
 
         let qdcount_zero = build_empty_question_query();
@@ -675,16 +1133,26 @@ mod tests {
 
         let mut fallback_mgr = Some(FallbackManager::new(
             main_socket.clone(),
-            fallback_addr,
+            vec![FallbackRoute {
+                matcher: FallbackMatch::Any,
+                addr: fallback_addr,
+            }],
             false,
         ));
         let domains = vec!["example.com"];
         let local_addr_storage = dummy_sockaddr_storage();
+        let metrics = ServerMetrics::default();
         let context = PacketContext {
             domains: &domains,
+            encoding: Encoding::Base32,
             quic: std::ptr::null_mut(),
             current_time: 0,
+            listener: 0,
             local_addr_storage: &local_addr_storage,
+            metrics: &metrics,
+            state: std::ptr::null_mut(),
+            auth_secret: None,
+            source_filter: None,
         };
 
         let dns_packet = build_dns_query("example.com");
@@ -762,16 +1230,26 @@ mod tests {
 
         let mut fallback_mgr = Some(FallbackManager::new(
             main_socket.clone(),
-            fallback_addr,
+            vec![FallbackRoute {
+                matcher: FallbackMatch::Any,
+                addr: fallback_addr,
+            }],
             false,
         ));
         let domains = vec!["example.com"];
         let local_addr_storage = dummy_sockaddr_storage();
+        let metrics = ServerMetrics::default();
         let context = PacketContext {
             domains: &domains,
+            encoding: Encoding::Base32,
             quic: std::ptr::null_mut(),
             current_time: 0,
+            listener: 0,
             local_addr_storage: &local_addr_storage,
+            metrics: &metrics,
+            state: std::ptr::null_mut(),
+            auth_secret: None,
+            source_filter: None,
         };
 
         let non_dns = b"nope";
@@ -830,4 +1308,212 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn decode_slot_refuses_queries_without_a_valid_mac() {
+        let domains = vec!["example.com"];
+        let local_addr_storage = dummy_sockaddr_storage();
+        let metrics = ServerMetrics::default();
+        let context = PacketContext {
+            domains: &domains,
+            encoding: Encoding::Base32,
+            quic: std::ptr::null_mut(),
+            current_time: 0,
+            listener: 0,
+            local_addr_storage: &local_addr_storage,
+            metrics: &metrics,
+            state: std::ptr::null_mut(),
+            auth_secret: Some(b"secret"),
+            source_filter: None,
+        };
+        let peer: SocketAddr = "127.0.0.1:5300".parse().unwrap();
+        let nonce = hmac::new_nonce(unix_now_secs()).expect("nonce");
+        let forged = hmac::AuthTag {
+            nonce,
+            mac: hmac::compute(b"other", &nonce, b"payload", hmac::DEFAULT_MAC_LEN).expect("mac"),
+        };
+        for domain in [
+            "example.com".to_string(),
+            format!("{}.example.com", hmac::auth_label(&forged)),
+        ] {
+            let qname = slipstream_dns::build_qname(
+                b"payload",
+                &domain,
+                Encoding::Base32,
+                Default::default(),
+            )
+            .expect("qname");
+            let query = encode_query(&QueryParams {
+                id: 1,
+                qname: &qname,
+                qtype: RR_TXT,
+                qclass: CLASS_IN,
+                rd: true,
+                cd: false,
+                qdcount: 1,
+                is_query: true,
+                edns: EdnsOptions::default(),
+            })
+            .expect("dns query");
+            match decode_slot(&query, peer, &context).expect("decode slot") {
+                DecodeSlotOutcome::Slot(slot) => {
+                    assert_eq!(slot.rcode, Some(Rcode::Refused), "{}", domain)
+                }
+                _ => panic!("{}: expected a refused slot", domain),
+            }
+        }
+    }
+
+    #[test]
+    fn source_limiter_drops_beyond_the_rate() {
+        let peer: SocketAddr = "192.0.2.1:5353".parse().unwrap();
+        let other: SocketAddr = "192.0.2.2:5353".parse().unwrap();
+        let mut limiter = SourceRateLimiter::new(10, 0);
+        let allowed = (0..25).filter(|_| limiter.allow(peer, 0)).count();
+        assert_eq!(allowed, 10);
+        assert!(limiter.allow(other, 0), "budgets are per source");
+        assert!(!limiter.allow(peer, 50_000));
+        assert!(limiter.allow(peer, 100_000));
+
+        limiter.cleanup(1_000_000);
+        assert_eq!(limiter.buckets.len(), 2, "cleanup waits for its interval");
+        let later = FALLBACK_CLEANUP_INTERVAL.as_micros() as u64;
+        assert!(limiter.allow(peer, later));
+        limiter.cleanup(later);
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn source_limiter_shares_a_budget_across_ports_and_caps_its_sources() {
+        let mut limiter = SourceRateLimiter::new(10, 0);
+        let allowed = (0..25)
+            .filter(|port| limiter.allow(SocketAddr::from(([192, 0, 2, 1], 1000 + port)), 0))
+            .count();
+        assert_eq!(allowed, 10, "ports of one address share its budget");
+        let mapped: SocketAddr = "[::ffff:192.0.2.1]:53".parse().unwrap();
+        assert!(
+            !limiter.allow(mapped, 0),
+            "IPv4-mapped peers are the same source"
+        );
+
+        let mut limiter = SourceRateLimiter::new(10, 0);
+        limiter.max_sources = 16;
+        for host in 0..16u8 {
+            assert!(limiter.allow(SocketAddr::from(([198, 51, 100, host], 53)), host as u64));
+        }
+        assert!(limiter.allow(SocketAddr::from(([203, 0, 113, 1], 53)), 100));
+        assert!(limiter.buckets.len() <= 16);
+        let oldest = IpAddr::from([198, 51, 100, 0]);
+        assert!(
+            !limiter.buckets.contains_key(&oldest),
+            "least recent goes first"
+        );
+        assert!(limiter
+            .buckets
+            .contains_key(&IpAddr::from([198, 51, 100, 15])));
+    }
+
+    #[test]
+    fn parses_and_applies_fallback_matches() {
+        assert_eq!("quic".parse::<FallbackMatch>(), Ok(FallbackMatch::Quic));
+        let prefix = "prefix:16030a".parse::<FallbackMatch>().unwrap();
+        assert_eq!(prefix, FallbackMatch::Prefix(vec![0x16, 0x03, 0x0a]));
+        assert_eq!(prefix.to_string(), "prefix:16030a");
+        assert!("prefix:".parse::<FallbackMatch>().is_err());
+        assert!("prefix:160".parse::<FallbackMatch>().is_err());
+        assert!("prefix:zz".parse::<FallbackMatch>().is_err());
+        assert!("tls".parse::<FallbackMatch>().is_err());
+
+        assert!(prefix.matches(&[0x16, 0x03, 0x0a, 0x00]));
+        assert!(!prefix.matches(&[0x16, 0x03]));
+        assert!(FallbackMatch::Quic.matches(&[0xc3, 0x00]));
+        assert!(!FallbackMatch::Quic.matches(&[0x43, 0x00]));
+        assert!(!FallbackMatch::Quic.matches(&[]));
+    }
+
+    #[tokio::test]
+    async fn fallback_routes_by_first_packet_and_sticks() {
+        let main_socket = Arc::new(TokioUdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let main_addr = main_socket.local_addr().unwrap();
+        let quic_socket = Arc::new(TokioUdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let quic_addr = quic_socket.local_addr().unwrap();
+        let other_socket = Arc::new(TokioUdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let other_addr = other_socket.local_addr().unwrap();
+        let (quic_tx, mut quic_rx) = mpsc::unbounded_channel();
+        let (other_tx, mut other_rx) = mpsc::unbounded_channel();
+        spawn_fallback_echo(quic_socket, quic_tx);
+        spawn_fallback_echo(other_socket, other_tx);
+
+        let mut fallback_mgr = Some(FallbackManager::new(
+            main_socket.clone(),
+            vec![
+                FallbackRoute {
+                    matcher: FallbackMatch::Quic,
+                    addr: quic_addr,
+                },
+                FallbackRoute {
+                    matcher: FallbackMatch::Any,
+                    addr: other_addr,
+                },
+            ],
+            false,
+        ));
+        let domains = vec!["example.com"];
+        let local_addr_storage = dummy_sockaddr_storage();
+        let metrics = ServerMetrics::default();
+        let context = PacketContext {
+            domains: &domains,
+            encoding: Encoding::Base32,
+            quic: std::ptr::null_mut(),
+            current_time: 0,
+            listener: 0,
+            local_addr_storage: &local_addr_storage,
+            metrics: &metrics,
+            state: std::ptr::null_mut(),
+            auth_secret: None,
+            source_filter: None,
+        };
+
+        let quic_client = TokioUdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let plain_client = TokioUdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut recv_buf = [0u8; 64];
+        for (client, packet) in [
+            (&quic_client, &b"\xc3quic"[..]),
+            (&quic_client, &b"nope"[..]),
+            (&plain_client, &b"nope"[..]),
+        ] {
+            client.send_to(packet, main_addr).await.unwrap();
+            let (size, peer) = recv_with_timeout(&main_socket, &mut recv_buf).await;
+            let mut slots = Vec::new();
+            handle_packet(
+                &mut slots,
+                &recv_buf[..size],
+                peer,
+                &context,
+                &mut fallback_mgr,
+            )
+            .await
+            .unwrap();
+        }
+
+        for expected in [&b"\xc3quic"[..], &b"nope"[..]] {
+            let received = timeout(Duration::from_secs(1), quic_rx.recv())
+                .await
+                .expect("quic backend receive timeout")
+                .expect("quic backend receive");
+            assert_eq!(received, expected);
+        }
+        let received = timeout(Duration::from_secs(1), other_rx.recv())
+            .await
+            .expect("other backend receive timeout")
+            .expect("other backend receive");
+        assert_eq!(received, b"nope");
+        assert!(quic_rx.try_recv().is_err());
+
+        if let Some(manager) = fallback_mgr.as_mut() {
+            for session in manager.sessions.values() {
+                let _ = session.shutdown_tx.send(true);
+            }
+        }
+    }
 }
@@ -58,6 +58,7 @@ fn cert_pinning_e2e() {
         envs: &[],
         rust_log: "info",
         capture_logs: false,
+        ..ServerArgs::default()
     });
     thread::sleep(Duration::from_millis(200));
     if server.has_exited() {
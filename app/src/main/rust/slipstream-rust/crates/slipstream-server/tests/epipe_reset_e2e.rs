@@ -126,6 +126,7 @@ fn epipe_triggers_quic_reset() {
             envs: &[],
             rust_log: "info",
             capture_logs: true,
+            ..ServerArgs::default()
         },
         ClientArgs {
             client_bin: &client_bin,
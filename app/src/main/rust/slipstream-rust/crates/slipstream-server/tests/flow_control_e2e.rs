@@ -48,6 +48,13 @@ struct FlowControlHarness {
 }
 
 fn setup_flow_control(envs: &[(&str, &str)]) -> Option<FlowControlHarness> {
+    setup_flow_control_with_write_timeout(envs, None)
+}
+
+fn setup_flow_control_with_write_timeout(
+    envs: &[(&str, &str)],
+    target_write_timeout_ms: Option<u64>,
+) -> Option<FlowControlHarness> {
     if std::env::var(ENV_ENABLE).is_err() {
         eprintln!(
             "skipping flow control e2e test; set {}=1 to enable",
@@ -142,6 +149,7 @@ fn setup_flow_control(envs: &[(&str, &str)]) -> Option<FlowControlHarness> {
             reset_seed_path: None,
             fallback_addr: None,
             idle_timeout_seconds: None,
+            target_write_timeout_ms,
             envs,
             rust_log: "info",
             capture_logs: true,
@@ -314,3 +322,35 @@ fn single_stream_slow_transfer_should_not_abort() {
     assert_log_absent(server_logs, "queued_bytes", Duration::from_secs(1));
     assert_log_absent(client_logs, "reset event", Duration::from_secs(1));
 }
+
+#[test]
+fn wedged_target_write_resets_stream_after_timeout() {
+    let Some(harness) = setup_flow_control_with_write_timeout(
+        &[
+            ("SLIPSTREAM_STREAM_QUEUE_MAX_BYTES", "8388608"),
+            ("SLIPSTREAM_CONN_RESERVE_BYTES", "65536"),
+            ("SLIPSTREAM_STREAM_WRITE_BUFFER_BYTES", "8388608"),
+        ],
+        Some(500),
+    ) else {
+        return;
+    };
+
+    let server_logs = &harness.server_logs;
+    let client_logs = &harness.client_logs;
+    // Enough to fill the blackhole's receive buffer so the server's write blocks.
+    let _blocked = open_blackhole_stream(&harness, 0, Duration::from_secs(8), 64 * 1024);
+
+    if !wait_for_log(
+        server_logs,
+        "target write timed out",
+        Duration::from_secs(10),
+    ) {
+        let snapshot = log_snapshot(server_logs);
+        panic!("server did not time out the wedged write\n{}", snapshot);
+    }
+    if !wait_for_log(client_logs, "reset event", Duration::from_secs(10)) {
+        let snapshot = log_snapshot(client_logs);
+        panic!("client did not see the stream reset\n{}", snapshot);
+    }
+}
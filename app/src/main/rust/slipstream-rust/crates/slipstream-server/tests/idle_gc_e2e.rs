@@ -65,6 +65,7 @@ fn idle_gc_closes_connection() {
         envs: &[],
         rust_log: "debug",
         capture_logs: true,
+        ..ServerArgs::default()
     });
     let server_logs = server_logs.expect("server logs");
     thread::sleep(Duration::from_millis(200));
@@ -49,6 +49,7 @@ fn restart_reconnects_idle_client() {
         envs: &[],
         rust_log: "info",
         capture_logs: false,
+        ..ServerArgs::default()
     });
     thread::sleep(Duration::from_millis(200));
     if server.has_exited() {
@@ -96,6 +97,7 @@ fn restart_reconnects_idle_client() {
         envs: &[],
         rust_log: "info",
         capture_logs: false,
+        ..ServerArgs::default()
     });
     thread::sleep(Duration::from_millis(200));
     if server.has_exited() {
@@ -99,6 +99,7 @@ fn stream_limit_reuse_allows_next_stream() {
             envs: &[],
             rust_log: "info",
             capture_logs: true,
+            ..ServerArgs::default()
         },
         ClientArgs {
             client_bin: &client_bin,
@@ -212,6 +213,7 @@ fn stream_limit_server_close_allows_next_stream() {
             envs: &[],
             rust_log: "info",
             capture_logs: true,
+            ..ServerArgs::default()
         },
         ClientArgs {
             client_bin: &client_bin,
@@ -105,11 +105,33 @@ pub struct ServerArgs<'a> {
     pub reset_seed_path: Option<&'a Path>,
     pub fallback_addr: Option<SocketAddr>,
     pub idle_timeout_seconds: Option<u64>,
+    pub target_write_timeout_ms: Option<u64>,
     pub envs: &'a [(&'a str, &'a str)],
     pub rust_log: &'a str,
     pub capture_logs: bool,
 }
 
+impl Default for ServerArgs<'_> {
+    fn default() -> Self {
+        Self {
+            server_bin: Path::new(""),
+            dns_listen_host: None,
+            dns_port: 0,
+            target_address: "",
+            domains: &[],
+            cert: Path::new(""),
+            key: Path::new(""),
+            reset_seed_path: None,
+            fallback_addr: None,
+            idle_timeout_seconds: None,
+            target_write_timeout_ms: None,
+            envs: &[],
+            rust_log: "info",
+            capture_logs: false,
+        }
+    }
+}
+
 pub struct ClientArgs<'a> {
     pub client_bin: &'a Path,
     pub dns_port: u16,
@@ -198,6 +220,10 @@ pub fn spawn_server(args: ServerArgs<'_>) -> (ChildGuard, Option<LogCapture>) {
         cmd.arg("--idle-timeout-seconds")
             .arg(idle_timeout.to_string());
     }
+    if let Some(timeout_ms) = args.target_write_timeout_ms {
+        cmd.arg("--target-write-timeout-ms")
+            .arg(timeout_ms.to_string());
+    }
     for (key, value) in args.envs {
         cmd.env(key, value);
     }
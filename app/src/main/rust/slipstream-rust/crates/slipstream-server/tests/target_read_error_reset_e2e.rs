@@ -100,6 +100,7 @@ fn target_read_error_triggers_client_reset() {
             envs: &[],
             rust_log: "info",
             capture_logs: true,
+            ..ServerArgs::default()
         },
         ClientArgs {
             client_bin: &client_bin,
@@ -8,7 +8,7 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use slipstream_dns::{encode_query, is_response, QueryParams, CLASS_IN, RR_A};
+use slipstream_dns::{encode_query, is_response, EdnsOptions, QueryParams, CLASS_IN, RR_A};
 
 use support::{
     pick_udp_port, server_bin_path, spawn_server, test_cert_and_key, workspace_root, ServerArgs,
@@ -84,6 +84,7 @@ fn build_dns_query(qname: &str) -> Vec<u8> {
         cd: false,
         qdcount: 1,
         is_query: true,
+        edns: EdnsOptions::default(),
     })
     .expect("encode DNS query")
 }
@@ -125,6 +126,7 @@ fn udp_fallback_e2e() {
         envs: &[],
         rust_log: "info",
         capture_logs: false,
+        ..ServerArgs::default()
     });
     thread::sleep(Duration::from_millis(200));
     if server.has_exited() {
@@ -312,7 +312,7 @@ static int emit_vector(FILE *out, const char *name, uint16_t id, const char *dom
 
     if (!raw_mode) {
         if (streq_icase(mode_name, "non_txt")) {
-            qtype = RR_A;
+            qtype = 15; /* MX: not a tunnel record type */
         } else if (streq_icase(mode_name, "qdcount_zero")) {
             qdcount = 0;
         } else if (streq_icase(mode_name, "not_query")) {